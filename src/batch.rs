@@ -45,7 +45,18 @@ pub const HEADER_SIZE: usize = 12;
 ///  | key type | key len(var) | key data | value len(var) | value data |
 ///  +----------+--------------+----------+----------------+------------+
 ///
+/// The key type is the `ValueType` of the record:
+///
+///  0: Deletion, only the key is present
+///  1: Value
+///  2: RangeDeletion, the key is the start and the value is the end of the range
+///  3: Merge, the value is the operand
+///
 /// ```
+/// The key type also serves as the version of a record: a reader that doesn't
+/// know about a key type rejects the whole batch as corrupted instead of
+/// misinterpreting the following records.
+///
 /// The updates are applied in the order in which they are added
 /// to the `WriteBatch`.
 ///
@@ -57,12 +68,17 @@ pub const HEADER_SIZE: usize = 12;
 #[derive(Clone, Default)]
 pub struct WriteBatch {
     pub(super) contents: Vec<u8>,
+    // The stack of the (contents size, count) marked by `set_savepoint`
+    save_points: Vec<(usize, u32)>,
 }
 
 impl WriteBatch {
     pub fn new() -> Self {
         let contents = vec![0; HEADER_SIZE];
-        Self { contents }
+        Self {
+            contents,
+            save_points: vec![],
+        }
     }
 
     #[inline]
@@ -72,7 +88,7 @@ impl WriteBatch {
 
     /// Stores the mapping "key -> value" in the database
     pub fn put(&mut self, key: &[u8], value: &[u8]) {
-        self.set_count(self.get_count() + 1);
+        self.set_count(self.count() + 1);
         self.contents.push(ValueType::Value as u8);
         VarintU32::put_varint(&mut self.contents, key.len() as u32);
         self.contents.extend_from_slice(key);
@@ -82,39 +98,123 @@ impl WriteBatch {
 
     /// If the database contains a mapping for "key", erase it. Else do nothing
     pub fn delete(&mut self, key: &[u8]) {
-        self.set_count(self.get_count() + 1);
+        self.set_count(self.count() + 1);
         self.contents.push(ValueType::Deletion as u8);
         VarintU32::put_varint(&mut self.contents, key.len() as u32);
         self.contents.extend_from_slice(key);
     }
 
+    /// Merges the operand `value` into the existing value of `key`.
+    /// The semantics of the merge is defined by `Options::merge_operator`.
+    pub fn merge(&mut self, key: &[u8], value: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.contents.push(ValueType::Merge as u8);
+        VarintU32::put_varint(&mut self.contents, key.len() as u32);
+        self.contents.extend_from_slice(key);
+        VarintU32::put_varint(&mut self.contents, value.len() as u32);
+        self.contents.extend_from_slice(value);
+    }
+
+    /// Erases all the keys in `[start, end)` in the database.
+    /// Keys written after this range deletion are not affected.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.contents.push(ValueType::RangeDeletion as u8);
+        VarintU32::put_varint(&mut self.contents, start.len() as u32);
+        self.contents.extend_from_slice(start);
+        VarintU32::put_varint(&mut self.contents, end.len() as u32);
+        self.contents.extend_from_slice(end);
+    }
+
     /// The size of the database changes caused by this batch.
     #[inline]
     pub fn approximate_size(&self) -> usize {
         self.contents.len()
     }
 
-    /// Copies the operations in "source" to this batch.
-    pub fn append(&mut self, mut src: WriteBatch) {
-        assert!(
-            src.contents.len() >= HEADER_SIZE,
-            "[batch] malformed WriteBatch (too small) to append"
-        );
-        self.set_count(self.get_count() + src.get_count());
-        src.contents.drain(0..HEADER_SIZE);
-        self.contents.append(&mut src.contents)
+    /// Copies the operations in `src` to the end of this batch.
+    /// The sequence number of `src` is ignored: the records of `src` take the
+    /// sequence numbers right after the ones of this batch when it's written.
+    pub fn append(&mut self, src: &WriteBatch) {
+        if src.is_empty() {
+            return;
+        }
+        if self.contents.len() < HEADER_SIZE {
+            self.contents.resize(HEADER_SIZE, 0);
+        }
+        self.set_count(self.count() + src.count());
+        self.contents
+            .extend_from_slice(&src.contents.as_slice()[HEADER_SIZE..]);
     }
 
-    /// Clears all updates buffered in this batch
+    /// Splits the batch into the sub-batches whose `data_size` are no larger than
+    /// `max_bytes`, keeping the order of the records. The batch is only split at
+    /// the boundaries of the records, so a record larger than `max_bytes` makes a
+    /// sub-batch by itself. The sub-batches could be written one by one, but they
+    /// are not atomic as a whole. Returns no sub-batch for an empty batch.
+    ///
+    /// Returns a `Status::Corruption` error if the batch is malformed.
+    pub fn split_by_size(&self, max_bytes: usize) -> Result<Vec<WriteBatch>> {
+        let mut splitter = BatchSplitter {
+            max_bytes,
+            batches: vec![],
+            current: WriteBatch::new(),
+        };
+        self.iterate(&mut splitter)?;
+        if !splitter.current.is_empty() {
+            splitter.batches.push(splitter.current);
+        }
+        Ok(splitter.batches)
+    }
+
+    /// Clears all updates buffered in this batch and all the savepoints
     #[inline]
     pub fn clear(&mut self) {
         self.contents.clear();
         self.contents.resize(HEADER_SIZE, 0);
         self.set_count(0);
+        self.save_points.clear();
+    }
+
+    /// Marks the current end of the batch. The savepoints form a stack so
+    /// `rollback_to_savepoint` discards the records added after the latest one.
+    pub fn set_savepoint(&mut self) {
+        if self.contents.len() < HEADER_SIZE {
+            self.contents.resize(HEADER_SIZE, 0);
+        }
+        self.save_points.push((self.contents.len(), self.count()));
+    }
+
+    /// Removes all the records added since the latest savepoint and pops the savepoint.
+    /// Returns a `NotFound` error if there is no savepoint.
+    pub fn rollback_to_savepoint(&mut self) -> Result<()> {
+        match self.save_points.pop() {
+            Some((size, count)) => {
+                self.contents.truncate(size);
+                self.set_count(count);
+                Ok(())
+            }
+            None => Err(WickErr::new(
+                Status::NotFound,
+                Some("[batch] no savepoint to roll back to"),
+            )),
+        }
     }
 
     /// Insert all the records in the batch into the given `MemTable`
     pub fn insert_into(&self, mem: &MemTable) -> Result<()> {
+        let mut inserter = MemTableInserter {
+            mem,
+            seq: self.get_sequence(),
+        };
+        self.iterate(&mut inserter)
+    }
+
+    /// Invokes the callbacks of `handler` on every record in the batch in the
+    /// order they were added. Returns a `Status::Corruption` error if the batch
+    /// is malformed, in which case the records before the malformed one have
+    /// already been delivered.
+    pub fn iterate(&self, handler: &mut dyn BatchHandler) -> Result<()> {
         if self.contents.len() < HEADER_SIZE {
             return Err(WickErr::new(
                 Status::Corruption,
@@ -123,7 +223,6 @@ impl WriteBatch {
         }
         let mut s = Slice::from(&self.contents.as_slice()[HEADER_SIZE..]);
         let mut found = 0;
-        let mut seq = self.get_sequence();
         while !s.is_empty() {
             found += 1;
             let tag = s[0];
@@ -132,8 +231,7 @@ impl WriteBatch {
                 ValueType::Value => {
                     if let Some(key) = VarintU32::get_varint_prefixed_slice(&mut s) {
                         if let Some(value) = VarintU32::get_varint_prefixed_slice(&mut s) {
-                            mem.add(seq, ValueType::Value, key.as_slice(), value.as_slice());
-                            seq += 1;
+                            handler.put(key.as_slice(), value.as_slice());
                             continue;
                         }
                     }
@@ -144,8 +242,7 @@ impl WriteBatch {
                 }
                 ValueType::Deletion => {
                     if let Some(key) = VarintU32::get_varint_prefixed_slice(&mut s) {
-                        mem.add(seq, ValueType::Deletion, key.as_slice(), b"");
-                        seq += 1;
+                        handler.delete(key.as_slice());
                         continue;
                     }
                     return Err(WickErr::new(
@@ -153,6 +250,30 @@ impl WriteBatch {
                         Some("[batch] bad WriteBatch delete"),
                     ));
                 }
+                ValueType::RangeDeletion => {
+                    if let Some(start) = VarintU32::get_varint_prefixed_slice(&mut s) {
+                        if let Some(end) = VarintU32::get_varint_prefixed_slice(&mut s) {
+                            handler.delete_range(start.as_slice(), end.as_slice());
+                            continue;
+                        }
+                    }
+                    return Err(WickErr::new(
+                        Status::Corruption,
+                        Some("[batch] bad WriteBatch delete range"),
+                    ));
+                }
+                ValueType::Merge => {
+                    if let Some(key) = VarintU32::get_varint_prefixed_slice(&mut s) {
+                        if let Some(value) = VarintU32::get_varint_prefixed_slice(&mut s) {
+                            handler.merge(key.as_slice(), value.as_slice());
+                            continue;
+                        }
+                    }
+                    return Err(WickErr::new(
+                        Status::Corruption,
+                        Some("[batch] bad WriteBatch merge"),
+                    ));
+                }
                 ValueType::Unknown => {
                     return Err(WickErr::new(
                         Status::Corruption,
@@ -161,7 +282,7 @@ impl WriteBatch {
                 }
             }
         }
-        if found != self.get_count() {
+        if found != self.count() {
             return Err(WickErr::new(
                 Status::Corruption,
                 Some("[batch] WriteBatch has wrong count"),
@@ -174,12 +295,21 @@ impl WriteBatch {
     pub(crate) fn set_contents(&mut self, src: &mut Vec<u8>) {
         self.contents.clear();
         self.contents.append(src);
+        self.save_points.clear();
     }
+
+    /// Returns the number of records in the batch
     #[inline]
-    pub fn get_count(&self) -> u32 {
+    pub fn count(&self) -> u32 {
         decode_fixed_32(&self.contents.as_slice()[8..])
     }
 
+    /// Returns the size of the serialized batch including the header
+    #[inline]
+    pub fn data_size(&self) -> usize {
+        self.contents.len()
+    }
+
     #[inline]
     pub(crate) fn set_count(&mut self, count: u32) {
         let s = self.contents.as_mut_slice();
@@ -196,18 +326,110 @@ impl WriteBatch {
         decode_fixed_64(self.contents.as_slice())
     }
 
+    /// Returns true if there is no record in the batch
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.contents.is_empty()
+        self.contents.len() <= HEADER_SIZE
+    }
+}
+
+/// The callbacks invoked by `WriteBatch::iterate` for every record in a batch
+pub trait BatchHandler {
+    /// Called for a record added by `WriteBatch::put`
+    fn put(&mut self, key: &[u8], value: &[u8]);
+
+    /// Called for a record added by `WriteBatch::delete`
+    fn delete(&mut self, key: &[u8]);
+
+    /// Called for a record added by `WriteBatch::merge`
+    fn merge(&mut self, key: &[u8], value: &[u8]);
+
+    /// Called for a record added by `WriteBatch::delete_range`
+    fn delete_range(&mut self, start: &[u8], end: &[u8]);
+}
+
+// Inserts the records into a `MemTable` with increasing sequence numbers
+struct MemTableInserter<'a> {
+    mem: &'a MemTable,
+    seq: u64,
+}
+
+impl MemTableInserter<'_> {
+    fn add(&mut self, value_type: ValueType, key: &[u8], value: &[u8]) {
+        self.mem.add(self.seq, value_type, key, value);
+        self.seq += 1;
+    }
+}
+
+impl BatchHandler for MemTableInserter<'_> {
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.add(ValueType::Value, key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.add(ValueType::Deletion, key, b"")
+    }
+
+    fn merge(&mut self, key: &[u8], value: &[u8]) {
+        self.add(ValueType::Merge, key, value)
+    }
+
+    fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.add(ValueType::RangeDeletion, start, end)
+    }
+}
+
+// Copies the records into the sub-batches limited by `max_bytes`
+struct BatchSplitter {
+    max_bytes: usize,
+    batches: Vec<WriteBatch>,
+    current: WriteBatch,
+}
+
+impl BatchSplitter {
+    // Adds a record by `add` into the current sub-batch, or into a new one if the
+    // current one becomes too large
+    fn add(&mut self, add: impl Fn(&mut WriteBatch)) {
+        self.current.set_savepoint();
+        add(&mut self.current);
+        if self.current.data_size() > self.max_bytes && self.current.count() > 1 {
+            self.current.rollback_to_savepoint().unwrap();
+            let mut batch = std::mem::replace(&mut self.current, WriteBatch::new());
+            batch.save_points.clear();
+            self.batches.push(batch);
+            add(&mut self.current);
+        } else {
+            self.current.save_points.pop();
+        }
+    }
+}
+
+impl BatchHandler for BatchSplitter {
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.add(|b| b.put(key, value))
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.add(|b| b.delete(key))
+    }
+
+    fn merge(&mut self, key: &[u8], value: &[u8]) {
+        self.add(|b| b.merge(key, value))
+    }
+
+    fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.add(|b| b.delete_range(start, end))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::batch::WriteBatch;
+    use crate::batch::{BatchHandler, WriteBatch, HEADER_SIZE};
     use crate::db::format::{InternalKeyComparator, ParsedInternalKey, ValueType};
     use crate::mem::{MemTable, MemoryTable};
+    use crate::util::coding::encode_fixed_32;
     use crate::util::comparator::BytewiseComparator;
+    use crate::util::status::Status;
     use std::sync::Arc;
 
     fn print_contents(batch: &WriteBatch) -> String {
@@ -233,6 +455,24 @@ mod tests {
                         s.push_str(tmp.as_str());
                         count += 1
                     }
+                    ValueType::Merge => {
+                        let tmp = format!(
+                            "Merge({}, {})",
+                            ikey.user_key.as_str(),
+                            iter.value().as_str()
+                        );
+                        s.push_str(tmp.as_str());
+                        count += 1
+                    }
+                    ValueType::RangeDeletion => {
+                        let tmp = format!(
+                            "DeleteRange({}, {})",
+                            ikey.user_key.as_str(),
+                            iter.value().as_str()
+                        );
+                        s.push_str(tmp.as_str());
+                        count += 1
+                    }
                     _ => {}
                 }
                 s.push('@');
@@ -243,7 +483,7 @@ mod tests {
         }
         if result.is_err() {
             s.push_str("ParseError()")
-        } else if count != batch.get_count() {
+        } else if count != batch.count() {
             s.push_str("CountMisMatch")
         }
         s
@@ -253,7 +493,7 @@ mod tests {
     fn test_empty_batch() {
         let b = WriteBatch::new();
         assert_eq!("", print_contents(&b).as_str());
-        assert_eq!(0, b.get_count());
+        assert_eq!(0, b.count());
     }
 
     #[test]
@@ -264,13 +504,56 @@ mod tests {
         b.put("baz".as_bytes(), "boo".as_bytes());
         b.set_sequence(100);
         assert_eq!(100, b.get_sequence());
-        assert_eq!(3, b.get_count());
+        assert_eq!(3, b.count());
         assert_eq!(
             "Put(baz, boo)@102|Delete(box)@101|Put(foo, bar)@100|",
             print_contents(&b).as_str()
         );
     }
 
+    #[test]
+    fn test_delete_range() {
+        let mut b = WriteBatch::new();
+        b.put("foo".as_bytes(), "bar".as_bytes());
+        b.delete_range("a".as_bytes(), "g".as_bytes());
+        b.put("box".as_bytes(), "boo".as_bytes());
+        b.set_sequence(100);
+        assert_eq!(3, b.count());
+        assert_eq!(
+            "DeleteRange(a, g)@101|Put(box, boo)@102|Put(foo, bar)@100|",
+            print_contents(&b).as_str()
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut b = WriteBatch::new();
+        b.put("foo".as_bytes(), "1".as_bytes());
+        b.merge("foo".as_bytes(), "2".as_bytes());
+        b.merge("foo".as_bytes(), "3".as_bytes());
+        b.set_sequence(100);
+        assert_eq!(3, b.count());
+        assert_eq!(
+            "Merge(foo, 3)@102|Merge(foo, 2)@101|Put(foo, 1)@100|",
+            print_contents(&b).as_str()
+        );
+    }
+
+    #[test]
+    fn test_unknown_record_type() {
+        let mut b = WriteBatch::new();
+        b.put("foo".as_bytes(), "bar".as_bytes());
+        b.delete_range("a".as_bytes(), "g".as_bytes());
+        b.set_sequence(100);
+        // pretend the range deletion is a record type from a newer version
+        let range_deletion_offset = HEADER_SIZE + 1 + 1 + 3 + 1 + 3;
+        b.contents[range_deletion_offset] = 0x7f;
+        assert_eq!(
+            "Put(foo, bar)@100|ParseError()",
+            print_contents(&b).as_str()
+        );
+    }
+
     #[test]
     fn test_corrupted_batch() {
         let mut b = WriteBatch::new();
@@ -290,21 +573,224 @@ mod tests {
         let mut b2 = WriteBatch::new();
         b1.set_sequence(200);
         b2.set_sequence(300);
-        b1.append(b2.clone());
+        b1.append(&b2);
         assert_eq!("", print_contents(&b1));
+        // appending an empty batch is a no-op
+        b1.append(&WriteBatch::default());
+        assert_eq!(b1.data_size(), HEADER_SIZE);
+        assert!(b1.is_empty());
         b2.put("a".as_bytes(), "va".as_bytes());
-        b1.append(b2.clone());
+        b1.append(&b2);
         assert_eq!("Put(a, va)@200|", print_contents(&b1));
         b2.clear();
         b2.put("b".as_bytes(), "vb".as_bytes());
-        b1.append(b2.clone());
+        b1.append(&b2);
         assert_eq!("Put(a, va)@200|Put(b, vb)@201|", print_contents(&b1));
         b2.delete("foo".as_bytes());
-        b1.append(b2.clone());
+        b1.append(&b2);
         assert_eq!(
             "Put(a, va)@200|Put(b, vb)@202|Put(b, vb)@201|Delete(foo)@203|",
             print_contents(&b1)
         );
+        assert_eq!(b1.count(), 4);
+        // appending to an empty batch
+        let mut b3 = WriteBatch::default();
+        b3.append(&b1);
+        b3.set_sequence(10);
+        assert_eq!(
+            "Put(a, va)@10|Put(b, vb)@12|Put(b, vb)@11|Delete(foo)@13|",
+            print_contents(&b3)
+        );
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut b = WriteBatch::new();
+        assert_eq!(
+            b.rollback_to_savepoint().unwrap_err().status(),
+            Status::NotFound
+        );
+        b.put(b"a", b"va");
+        b.set_savepoint();
+        b.delete(b"a");
+        b.put(b"b", b"vb");
+        b.set_savepoint();
+        b.put(b"c", b"vc");
+        b.delete_range(b"a", b"z");
+        assert_eq!(b.count(), 5);
+        // nested savepoints are rolled back one by one
+        b.rollback_to_savepoint().unwrap();
+        assert_eq!(b.count(), 3);
+        b.rollback_to_savepoint().unwrap();
+        assert_eq!(b.count(), 1);
+        assert_eq!(
+            b.rollback_to_savepoint().unwrap_err().status(),
+            Status::NotFound
+        );
+        // new records could be added after a rollback
+        b.merge(b"d", b"vd");
+        b.set_sequence(100);
+        assert_eq!("Put(a, va)@100|Merge(d, vd)@101|", print_contents(&b));
+
+        // rolling back to a savepoint set on an empty batch
+        let mut b = WriteBatch::default();
+        b.set_savepoint();
+        b.put(b"a", b"va");
+        b.rollback_to_savepoint().unwrap();
+        assert!(b.is_empty());
+        assert_eq!(b.count(), 0);
+        // `clear` removes the savepoints
+        b.set_savepoint();
+        b.clear();
+        assert!(b.rollback_to_savepoint().is_err());
+    }
+
+    // Records the callbacks invoked by `WriteBatch::iterate`
+    #[derive(Default)]
+    struct Recorder {
+        records: Vec<String>,
+    }
+
+    impl BatchHandler for Recorder {
+        fn put(&mut self, key: &[u8], value: &[u8]) {
+            self.records.push(format!(
+                "Put({}, {})",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(value)
+            ))
+        }
+
+        fn delete(&mut self, key: &[u8]) {
+            self.records
+                .push(format!("Delete({})", String::from_utf8_lossy(key)))
+        }
+
+        fn merge(&mut self, key: &[u8], value: &[u8]) {
+            self.records.push(format!(
+                "Merge({}, {})",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(value)
+            ))
+        }
+
+        fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+            self.records.push(format!(
+                "DeleteRange({}, {})",
+                String::from_utf8_lossy(start),
+                String::from_utf8_lossy(end)
+            ))
+        }
+    }
+
+    #[test]
+    fn test_iterate() {
+        let mut b = WriteBatch::new();
+        assert_eq!(b.data_size(), HEADER_SIZE);
+        b.put("foo".as_bytes(), "bar".as_bytes());
+        b.delete("box".as_bytes());
+        b.merge("baz".as_bytes(), "1".as_bytes());
+        b.delete_range("a".as_bytes(), "c".as_bytes());
+        b.put("foo".as_bytes(), "".as_bytes());
+        assert_eq!(b.count(), 5);
+        assert_eq!(b.data_size(), b.data().len());
+        let mut r = Recorder::default();
+        b.iterate(&mut r).unwrap();
+        // in the order of the records instead of the keys
+        assert_eq!(
+            r.records,
+            vec![
+                "Put(foo, bar)",
+                "Delete(box)",
+                "Merge(baz, 1)",
+                "DeleteRange(a, c)",
+                "Put(foo, )",
+            ]
+        );
+
+        // replays the batch from the serialized data
+        let mut replayed = WriteBatch::new();
+        replayed.set_contents(&mut Vec::from(b.data()));
+        let mut r2 = Recorder::default();
+        replayed.iterate(&mut r2).unwrap();
+        assert_eq!(r.records, r2.records);
+    }
+
+    #[test]
+    fn test_iterate_malformed_batch() {
+        let mut b = WriteBatch::new();
+        b.put("foo".as_bytes(), "bar".as_bytes());
+        b.delete("box".as_bytes());
+        let data = Vec::from(b.data());
+        let check = |mut contents: Vec<u8>, expected: &[&str]| {
+            let mut b = WriteBatch::new();
+            b.set_contents(&mut contents);
+            let mut r = Recorder::default();
+            let err = b.iterate(&mut r).unwrap_err();
+            assert_eq!(err.status(), Status::Corruption);
+            assert_eq!(r.records, expected);
+        };
+        // too small
+        check(vec![0; HEADER_SIZE - 1], &[]);
+        // truncated record
+        check(Vec::from(&data[..data.len() - 1]), &["Put(foo, bar)"]);
+        // the key length exceeds the payload
+        let mut bad_len = data.clone();
+        bad_len[HEADER_SIZE + 1] = 100;
+        check(bad_len, &[]);
+        // unknown record type
+        let mut bad_type = data.clone();
+        bad_type[HEADER_SIZE] = 0x7f;
+        check(bad_type, &[]);
+        // wrong count
+        let mut bad_count = data;
+        encode_fixed_32(&mut bad_count[8..], 3);
+        check(bad_count, &["Put(foo, bar)", "Delete(box)"]);
+    }
+
+    #[test]
+    fn test_split_by_size() {
+        assert!(WriteBatch::new().split_by_size(100).unwrap().is_empty());
+        let mut b = WriteBatch::new();
+        let value = vec![b'v'; 2000];
+        for i in 0..10_000 {
+            let key = format!("key{:05}", i);
+            match i % 4 {
+                0 => b.delete(key.as_bytes()),
+                1 => b.merge(key.as_bytes(), value.as_slice()),
+                2 => b.delete_range(key.as_bytes(), b"zzz"),
+                _ => b.put(key.as_bytes(), value.as_slice()),
+            }
+        }
+        // a record larger than the limit
+        b.put(b"large", vec![b'v'; 200 * 1024].as_slice());
+        for i in 0..10 {
+            b.put(format!("{}", i).as_bytes(), value.as_slice());
+        }
+        assert!(b.data_size() > 10_000_000);
+        b.set_sequence(100);
+        let max_bytes = 64 * 1024;
+        let batches = b.split_by_size(max_bytes).unwrap();
+        let mut reassembled = WriteBatch::new();
+        for batch in batches.iter() {
+            assert!(!batch.is_empty());
+            if batch.data_size() > max_bytes {
+                assert_eq!(batch.count(), 1);
+                assert_eq!(
+                    print_contents(batch),
+                    "Put(large, ".to_owned() + &"v".repeat(200 * 1024) + ")@0|"
+                );
+            }
+            reassembled.append(batch);
+        }
+        assert_eq!(reassembled.count(), b.count());
+        reassembled.set_sequence(100);
+        assert_eq!(reassembled.data(), b.data());
+        assert_eq!(
+            b.split_by_size(usize::MAX).unwrap()[0].data()[HEADER_SIZE..],
+            b.data()[HEADER_SIZE..]
+        );
+        // every record is in its own sub-batch
+        assert_eq!(b.split_by_size(0).unwrap().len(), b.count() as usize);
     }
 
     #[test]
@@ -0,0 +1,451 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::cache::lru::DEFAULT_NUM_SHARD_BITS;
+use crate::cache::{Cache, CacheStats, Handle as CacheHandle, HandleRef};
+use crate::util::hash::hash;
+use hashbrown::hash_map::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+// The max number of shards is `1 << MAX_NUM_SHARD_BITS`
+const MAX_NUM_SHARD_BITS: usize = 16;
+
+/// A `ClockCache` approximates the LRU eviction by the CLOCK algorithm: every
+/// entry has a reference bit set by `look_up`, and a hand sweeping the entries
+/// evicts the first one which is neither in use nor referenced since the last
+/// sweep, clearing the reference bits on its way.
+///
+/// Unlike `SharedLRUCache`, a `look_up` only sets the reference bit without
+/// updating any list, so the lookups of a shard take a shared lock and don't
+/// contend with each other. Only the insertions and the evictions take the
+/// exclusive lock.
+pub struct ClockCache<T: 'static + Clone> {
+    shards: Vec<ClockShard<T>>,
+    num_shard_bits: usize,
+    last_id: AtomicU64,
+}
+
+impl<T: 'static + Clone> ClockCache<T> {
+    pub fn new(cap: usize) -> Self {
+        Self::with_shards(cap, DEFAULT_NUM_SHARD_BITS)
+    }
+
+    /// Create a `ClockCache` with `1 << num_shard_bits` shards.
+    /// The capacity `cap` is divided across the shards like `SharedLRUCache`.
+    ///
+    /// # Panic
+    ///
+    /// `num_shard_bits` is larger than 16
+    pub fn with_shards(cap: usize, num_shard_bits: usize) -> Self {
+        assert!(
+            num_shard_bits <= MAX_NUM_SHARD_BITS,
+            "[clock cache] num_shard_bits should be no larger than {}, but got {}",
+            MAX_NUM_SHARD_BITS,
+            num_shard_bits
+        );
+        let num_shards = 1 << num_shard_bits;
+        let shards = (0..num_shards)
+            .map(|i| ClockShard::new(cap / num_shards + if i < cap % num_shards { 1 } else { 0 }))
+            .collect();
+        Self {
+            shards,
+            num_shard_bits,
+            last_id: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    fn shard_by_hash(&self, hash: u32) -> &ClockShard<T> {
+        if self.num_shard_bits == 0 {
+            &self.shards[0]
+        } else {
+            &self.shards[(hash >> (32 - self.num_shard_bits)) as usize]
+        }
+    }
+}
+
+impl<T: 'static + Clone + Send + Sync> Cache<T> for ClockCache<T> {
+    fn insert(
+        &self,
+        key: Vec<u8>,
+        value: T,
+        charge: usize,
+        deleter: Option<Box<dyn FnMut(&[u8], T)>>,
+    ) -> HandleRef<T> {
+        let h = hash(key.as_slice(), 0);
+        self.shard_by_hash(h).insert(key, h, value, charge, deleter)
+    }
+
+    fn look_up(&self, key: &[u8]) -> Option<HandleRef<T>> {
+        self.shard_by_hash(hash(key, 0)).look_up(key)
+    }
+
+    fn release(&self, handle: HandleRef<T>) {
+        // The handles given out by a `ClockCache` are always `ClockHandle`s
+        let p = Rc::into_raw(handle) as *const ClockHandle<T>;
+        let handle = unsafe { Rc::from_raw(p) };
+        self.shard_by_hash(handle.entry.hash).release(handle);
+    }
+
+    fn erase(&self, key: &[u8]) {
+        self.shard_by_hash(hash(key, 0)).erase(key)
+    }
+
+    fn new_id(&self) -> u64 {
+        self.last_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn prune(&self) {
+        for s in self.shards.iter() {
+            s.prune();
+        }
+    }
+
+    fn total_charge(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.usage.load(Ordering::Acquire))
+            .sum()
+    }
+
+    fn capacity(&self) -> usize {
+        self.shards.iter().map(|s| s.capacity).sum()
+    }
+
+    fn stats(&self) -> CacheStats {
+        let load = |f: fn(&ClockShard<T>) -> &AtomicU64| {
+            self.shards
+                .iter()
+                .map(|s| f(s).load(Ordering::Relaxed))
+                .sum()
+        };
+        CacheStats {
+            hits: load(|s| &s.hits),
+            misses: load(|s| &s.misses),
+            insertions: load(|s| &s.insertions),
+            evictions: load(|s| &s.evictions),
+        }
+    }
+
+    fn reset_stats(&self) {
+        for s in self.shards.iter() {
+            s.hits.store(0, Ordering::Relaxed);
+            s.misses.store(0, Ordering::Relaxed);
+            s.insertions.store(0, Ordering::Relaxed);
+            s.evictions.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// An entry in the `ClockCache`. It's shared by the cache and the handles given
+// out, and the deleter is called when the last of them is dropped.
+struct ClockEntry<T: Clone> {
+    key: Box<[u8]>,
+    value: T,
+    hash: u32,
+    charge: usize,
+    deleter: Option<Box<dyn FnMut(&[u8], T)>>,
+    // set by `look_up` and cleared by the hand
+    referenced: AtomicBool,
+    // the number of the handles of the entry not released yet
+    refs: AtomicUsize,
+}
+
+// The deleter is only called by the last of the cache and the handles dropping the
+// entry, and the others only read the value.
+unsafe impl<T: Clone + Send + Sync> Send for ClockEntry<T> {}
+unsafe impl<T: Clone + Send + Sync> Sync for ClockEntry<T> {}
+
+impl<T: Clone> Drop for ClockEntry<T> {
+    fn drop(&mut self) {
+        if let Some(deleter) = self.deleter.as_mut() {
+            (deleter)(&self.key, self.value.clone());
+        }
+    }
+}
+
+struct ClockHandle<T: Clone> {
+    entry: Arc<ClockEntry<T>>,
+}
+
+impl<T: Clone> CacheHandle<T> for ClockHandle<T> {
+    fn get_value(&self) -> Option<T> {
+        Some(self.entry.value.clone())
+    }
+}
+
+struct ClockShard<T: Clone> {
+    capacity: usize,
+    inner: RwLock<ClockShardInner<T>>,
+    // the total charge of the entries in `inner`
+    usage: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+}
+
+struct ClockShardInner<T: Clone> {
+    // The circle swept by the hand. An evicted entry leaves an empty slot which
+    // is reused by the next insertion.
+    slots: Vec<Option<Arc<ClockEntry<T>>>>,
+    free_slots: Vec<usize>,
+    hand: usize,
+    // the slots of the entries by their keys
+    table: HashMap<Vec<u8>, usize>,
+}
+
+impl<T: Clone> ClockShardInner<T> {
+    // Removes the entry in the `slot` from the shard and returns it
+    fn remove(&mut self, slot: usize) -> Arc<ClockEntry<T>> {
+        let entry = self.slots[slot].take().unwrap();
+        self.table.remove(entry.key.as_ref());
+        self.free_slots.push(slot);
+        entry
+    }
+}
+
+impl<T: 'static + Clone> ClockShard<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: RwLock::new(ClockShardInner {
+                slots: vec![],
+                free_slots: vec![],
+                hand: 0,
+                table: HashMap::new(),
+            }),
+            usage: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            insertions: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn insert(
+        &self,
+        key: Vec<u8>,
+        hash: u32,
+        value: T,
+        charge: usize,
+        deleter: Option<Box<dyn FnMut(&[u8], T)>>,
+    ) -> HandleRef<T> {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(ClockEntry {
+            key: key.clone().into_boxed_slice(),
+            value,
+            hash,
+            charge,
+            deleter,
+            referenced: AtomicBool::new(false),
+            refs: AtomicUsize::new(1),
+        });
+        if self.capacity > 0 {
+            let mut inner = self.inner.write().unwrap();
+            if let Some(old) = inner.table.get(&key).copied() {
+                let old = inner.remove(old);
+                self.usage.fetch_sub(old.charge, Ordering::SeqCst);
+            }
+            let slot = match inner.free_slots.pop() {
+                Some(slot) => {
+                    inner.slots[slot] = Some(entry.clone());
+                    slot
+                }
+                None => {
+                    inner.slots.push(Some(entry.clone()));
+                    inner.slots.len() - 1
+                }
+            };
+            inner.table.insert(key, slot);
+            self.usage.fetch_add(charge, Ordering::SeqCst);
+            self.evict(&mut inner);
+        }
+        Rc::new(ClockHandle { entry })
+    }
+
+    fn look_up(&self, key: &[u8]) -> Option<HandleRef<T>> {
+        let inner = self.inner.read().unwrap();
+        match inner.table.get(key) {
+            Some(slot) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                let entry = inner.slots[*slot].as_ref().unwrap().clone();
+                entry.referenced.store(true, Ordering::Relaxed);
+                // taken under the shared lock so that the hand never evicts it
+                entry.refs.fetch_add(1, Ordering::SeqCst);
+                Some(Rc::new(ClockHandle { entry }))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn release(&self, handle: Rc<ClockHandle<T>>) {
+        handle.entry.refs.fetch_sub(1, Ordering::SeqCst);
+        // the released entry might be the one exceeding the capacity
+        if self.usage.load(Ordering::Acquire) > self.capacity {
+            self.evict(&mut self.inner.write().unwrap());
+        }
+    }
+
+    fn erase(&self, key: &[u8]) {
+        let mut inner = self.inner.write().unwrap();
+        if let Some(slot) = inner.table.get(key).copied() {
+            let entry = inner.remove(slot);
+            self.usage.fetch_sub(entry.charge, Ordering::SeqCst);
+        }
+    }
+
+    fn prune(&self) {
+        let mut inner = self.inner.write().unwrap();
+        for slot in 0..inner.slots.len() {
+            if Self::is_unused(&inner.slots[slot]) {
+                let entry = inner.remove(slot);
+                self.usage.fetch_sub(entry.charge, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[inline]
+    fn is_unused(slot: &Option<Arc<ClockEntry<T>>>) -> bool {
+        matches!(slot, Some(e) if e.refs.load(Ordering::SeqCst) == 0)
+    }
+
+    // Sweeps the hand to evict the unused entries until the usage is no larger than
+    // the capacity. An entry referenced since the last sweep gets a second chance.
+    // Gives up after two rounds in case all the entries are in use.
+    fn evict(&self, inner: &mut ClockShardInner<T>) {
+        let mut steps = 2 * inner.slots.len();
+        while self.usage.load(Ordering::Acquire) > self.capacity && steps > 0 {
+            steps -= 1;
+            let slot = inner.hand;
+            inner.hand = (inner.hand + 1) % inner.slots.len();
+            if !Self::is_unused(&inner.slots[slot]) {
+                continue;
+            }
+            let entry = inner.slots[slot].as_ref().unwrap();
+            if entry.referenced.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+            let entry = inner.remove(slot);
+            self.usage.fetch_sub(entry.charge, Ordering::SeqCst);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn key(k: u32) -> Vec<u8> {
+        k.to_le_bytes().to_vec()
+    }
+
+    fn look_up(cache: &dyn Cache<u32>, k: u32) -> Option<u32> {
+        cache.look_up(key(k).as_slice()).map(|h| {
+            let v = h.get_value().unwrap();
+            cache.release(h);
+            v
+        })
+    }
+
+    fn insert(cache: &dyn Cache<u32>, k: u32, v: u32) {
+        let h = cache.insert(key(k), v, 1, None);
+        cache.release(h);
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = ClockCache::with_shards(100, 0);
+        assert_eq!(look_up(&cache, 100), None);
+        insert(&cache, 100, 101);
+        assert_eq!(look_up(&cache, 100), Some(101));
+        assert_eq!(look_up(&cache, 200), None);
+        insert(&cache, 200, 201);
+        insert(&cache, 100, 102);
+        assert_eq!(look_up(&cache, 100), Some(102));
+        assert_eq!(look_up(&cache, 200), Some(201));
+        assert_eq!(cache.total_charge(), 2);
+        cache.erase(key(100).as_slice());
+        assert_eq!(look_up(&cache, 100), None);
+        assert_eq!(cache.total_charge(), 1);
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.insertions, 3);
+    }
+
+    #[test]
+    fn test_second_chance() {
+        let cache = ClockCache::with_shards(4, 0);
+        for i in 0..4 {
+            insert(&cache, i, i);
+        }
+        // 0 and 2 are referenced so 1 and 3 are evicted first
+        look_up(&cache, 0);
+        look_up(&cache, 2);
+        insert(&cache, 4, 4);
+        insert(&cache, 5, 5);
+        assert_eq!(look_up(&cache, 1), None);
+        assert_eq!(look_up(&cache, 3), None);
+        for i in [0, 2, 4, 5].iter() {
+            assert_eq!(look_up(&cache, *i), Some(*i));
+        }
+        assert_eq!(cache.stats().evictions, 2);
+        assert_eq!(cache.total_charge(), 4);
+    }
+
+    #[test]
+    fn test_entries_in_use_are_kept() {
+        let deleted = Rc::new(RefCell::new(vec![]));
+        let deleter = |deleted: &Rc<RefCell<Vec<u32>>>| {
+            let deleted = deleted.clone();
+            Some(
+                Box::new(move |_: &[u8], v: u32| deleted.borrow_mut().push(v))
+                    as Box<dyn FnMut(&[u8], u32)>,
+            )
+        };
+        let cache = ClockCache::with_shards(2, 0);
+        let h1 = cache.insert(key(1), 1, 1, deleter(&deleted));
+        let h2 = cache.insert(key(2), 2, 1, deleter(&deleted));
+        // exceeds the capacity since all the entries are in use
+        let h3 = cache.insert(key(3), 3, 1, deleter(&deleted));
+        assert_eq!(cache.total_charge(), 3);
+        cache.release(h3);
+        assert_eq!(look_up(&cache, 3), None);
+        assert_eq!(*deleted.borrow(), vec![3]);
+        // an erased entry is deleted after its handle is released
+        cache.erase(key(1).as_slice());
+        assert_eq!(h1.get_value(), Some(1));
+        assert_eq!(*deleted.borrow(), vec![3]);
+        cache.release(h1);
+        assert_eq!(*deleted.borrow(), vec![3, 1]);
+        cache.release(h2);
+        cache.prune();
+        assert_eq!(cache.total_charge(), 0);
+        assert_eq!(*deleted.borrow(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_zero_size_cache() {
+        let cache = ClockCache::with_shards(0, 0);
+        insert(&cache, 1, 100);
+        assert_eq!(look_up(&cache, 1), None);
+    }
+}
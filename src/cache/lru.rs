@@ -15,7 +15,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use crate::cache::{Cache, Handle as CacheHandle, HandleRef};
+use crate::cache::{Cache, CacheStats, Handle as CacheHandle, HandleRef};
 use hashbrown::hash_map::HashMap;
 
 use std::mem;
@@ -23,42 +23,90 @@ use std::ptr;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::util::hash::hash;
 
-const NUM_SHARD_BITS: usize = 4;
-const NUM_SHARD: usize = 1 << NUM_SHARD_BITS;
+/// The default number of shards in `SharedLRUCache` is `1 << DEFAULT_NUM_SHARD_BITS`
+pub const DEFAULT_NUM_SHARD_BITS: usize = 4;
+// The max number of shards is `1 << MAX_NUM_SHARD_BITS`
+const MAX_NUM_SHARD_BITS: usize = 16;
 
 // TODO: add benchmark for lru
 
 // TODO: Use Rc::into_raw and Rc::from_raw could be extremely unsafe so we may need a
 //       better implementation.
 
-/// A LRUCache that can be accessed safely in multiple threads
+/// A LRUCache that can be accessed safely in multiple threads.
+/// The keys are hashed into `1 << num_shard_bits` `LRUCache` shards and each of them has
+/// its own lock, so the accesses to different shards don't contend with each other.
 pub struct SharedLRUCache<T: 'static + Clone> {
     shards: Vec<LRUCache<T>>,
+    num_shard_bits: usize,
     last_id: AtomicU64,
 }
 
 impl<T: 'static + Clone> SharedLRUCache<T> {
     pub fn new(cap: usize) -> Self {
-        let per_shard = (cap + NUM_SHARD - 1) / NUM_SHARD;
-        let mut shards = vec![];
-        for _ in 0..NUM_SHARD {
-            shards.push(LRUCache::new(per_shard));
+        Self::with_config(cap, DEFAULT_NUM_SHARD_BITS, None)
+    }
+
+    /// Create a `SharedLRUCache` whose entries expire after `ttl` since they're inserted.
+    /// `None` means the entries never expire.
+    pub fn with_ttl(cap: usize, ttl: Option<Duration>) -> Self {
+        Self::with_config(cap, DEFAULT_NUM_SHARD_BITS, ttl)
+    }
+
+    /// Create a `SharedLRUCache` with `1 << num_shard_bits` shards.
+    /// The capacity `cap` is divided across the shards so the total charge of the
+    /// entries not in use never exceeds `cap`.
+    ///
+    /// # Panic
+    ///
+    /// `num_shard_bits` is larger than 16
+    pub fn with_config(cap: usize, num_shard_bits: usize, ttl: Option<Duration>) -> Self {
+        assert!(
+            num_shard_bits <= MAX_NUM_SHARD_BITS,
+            "[lru cache] num_shard_bits should be no larger than {}, but got {}",
+            MAX_NUM_SHARD_BITS,
+            num_shard_bits
+        );
+        let num_shards = 1 << num_shard_bits;
+        let mut shards = Vec::with_capacity(num_shards);
+        for i in 0..num_shards {
+            // the remainder is spread over the first shards
+            let per_shard = cap / num_shards + if i < cap % num_shards { 1 } else { 0 };
+            shards.push(LRUCache::with_ttl(per_shard, ttl));
         }
         Self {
             shards,
+            num_shard_bits,
             last_id: AtomicU64::new(0),
         }
     }
 
+    /// Returns the number of the shards
+    #[inline]
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline]
     fn shard(&self, key: &[u8]) -> usize {
-        (hash(key, 0) >> (32 - NUM_SHARD_BITS)) as usize
+        self.shard_by_hash(hash(key, 0))
+    }
+
+    #[inline]
+    fn shard_by_hash(&self, hash: u32) -> usize {
+        if self.num_shard_bits == 0 {
+            0
+        } else {
+            (hash >> (32 - self.num_shard_bits)) as usize
+        }
     }
 }
 
-impl<T: 'static + Clone> Cache<T> for SharedLRUCache<T> {
+impl<T: 'static + Clone + Send + Sync> Cache<T> for SharedLRUCache<T> {
     fn insert(
         &self,
         key: Vec<u8>,
@@ -78,7 +126,7 @@ impl<T: 'static + Clone> Cache<T> for SharedLRUCache<T> {
     fn release(&self, handle: HandleRef<T>) {
         let p = Rc::into_raw(handle) as *mut LRUHandle<T>;
         let hash = unsafe { (*p).hash };
-        self.shards[(hash >> (32 - NUM_SHARD_BITS)) as usize].release(unsafe { Rc::from_raw(p) });
+        self.shards[self.shard_by_hash(hash)].release(unsafe { Rc::from_raw(p) });
     }
 
     fn erase(&self, key: &[u8]) {
@@ -102,6 +150,28 @@ impl<T: 'static + Clone> Cache<T> for SharedLRUCache<T> {
             .iter()
             .fold(0, |sum, lru| sum + lru.total_charge())
     }
+
+    fn capacity(&self) -> usize {
+        self.shards.iter().fold(0, |sum, lru| sum + lru.capacity)
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.shards.iter().fold(CacheStats::default(), |sum, lru| {
+            let s = lru.stats();
+            CacheStats {
+                hits: sum.hits + s.hits,
+                misses: sum.misses + s.misses,
+                insertions: sum.insertions + s.insertions,
+                evictions: sum.evictions + s.evictions,
+            }
+        })
+    }
+
+    fn reset_stats(&self) {
+        for lru in self.shards.iter() {
+            lru.reset_stats();
+        }
+    }
 }
 
 /// Exact node in the `LRUCache`
@@ -113,6 +183,11 @@ pub struct LRUHandle<T: Clone> {
     hash: u32, // Hash of key; used for fast sharding and comparisons
     charge: usize,
     key: Box<[u8]>,
+    // The time when the handle is inserted into the cache
+    inserted_at: Instant,
+    // Whether the handle is in the cache's table. An erased handle still held by
+    // clients is in neither list.
+    in_cache: bool,
 }
 
 impl<T: Clone> Drop for LRUHandle<T> {
@@ -150,6 +225,8 @@ impl<T: Clone> LRUHandle<T> {
             hash,
             next: ptr::null_mut(),
             prev: ptr::null_mut(),
+            inserted_at: Instant::now(),
+            in_cache: false,
         }
     }
 
@@ -162,6 +239,8 @@ impl<T: Clone> LRUHandle<T> {
             charge: 0,
             hash: 0,
             key: Vec::new().into_boxed_slice(),
+            inserted_at: Instant::now(),
+            in_cache: false,
         }
     }
 }
@@ -209,6 +288,14 @@ pub struct LRUCache<T: Clone> {
     mutex: Mutex<MutexFields<T>>,
     /// The size of space which have been allocated
     usage: AtomicUsize,
+    /// The entries older than `ttl` are treated as misses in `look_up` and evicted lazily
+    ttl: Option<Duration>,
+    /// The statistics counters. They're only updated with relaxed ordering since
+    /// they're not used for synchronization.
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
 }
 
 struct MutexFields<T: Clone> {
@@ -224,8 +311,18 @@ struct MutexFields<T: Clone> {
     table: HashMap<Vec<u8>, Rc<LRUHandle<T>>>,
 }
 
+// The handles are only linked and counted with the mutex held, and the clients
+// only read the values of them.
+unsafe impl<T: Clone + Send + Sync> Send for MutexFields<T> {}
+
 impl<T: 'static + Clone> LRUCache<T> {
     pub fn new(cap: usize) -> Self {
+        Self::with_ttl(cap, None)
+    }
+
+    /// Create a `LRUCache` whose entries expire after `ttl` since they're inserted.
+    /// `None` means the entries never expire.
+    pub fn with_ttl(cap: usize, ttl: Option<Duration>) -> Self {
         let mutex = MutexFields {
             lru: Self::create_dummy_node(),
             in_use: Self::create_dummy_node(),
@@ -235,6 +332,11 @@ impl<T: 'static + Clone> LRUCache<T> {
             usage: AtomicUsize::new(0),
             capacity: cap,
             mutex: Mutex::new(mutex),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            insertions: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         }
     }
     pub fn set_capacity(&mut self, cap: usize) {
@@ -278,9 +380,11 @@ impl<T: 'static + Clone> LRUCache<T> {
         // dec from 2 to 1 because the given n will be dropped
         if Rc::strong_count(&n) == 2 {
             let p = Rc::into_raw(n) as *mut LRUHandle<T>;
-            // move to 'lru' from 'in_use'
-            Self::lru_remove(p);
-            Self::lru_append(lru, p);
+            // move to 'lru' from 'in_use' unless it's been erased
+            if unsafe { (*p).in_cache } {
+                Self::lru_remove(p);
+                Self::lru_append(lru, p);
+            }
             mem::drop(unsafe { Rc::from_raw(p) }); // manually drop
         }
         // refs is 1 , n is dropped so nothing left
@@ -289,10 +393,39 @@ impl<T: 'static + Clone> LRUCache<T> {
     fn finish_erase(data: &mut MutexFields<T>, n: HandleRef<T>) {
         let p = Rc::into_raw(n) as *mut LRUHandle<T>;
         Self::lru_remove(p);
+        unsafe { (*p).in_cache = false };
         let h = unsafe { Rc::from_raw(p) };
         Self::dec_ref(data.lru, h);
     }
 
+    // Evict the unused lru entries until the usage is no larger than the capacity
+    fn evict(&self, mutex_data: &mut MutexFields<T>) {
+        unsafe {
+            while self.usage.load(Ordering::Acquire) > self.capacity
+                && (*mutex_data.lru).next != mutex_data.lru
+            {
+                let old = (*mutex_data.lru).next;
+                if let Some(n) = mutex_data.table.remove(&(*old).key[..]) {
+                    assert_eq!(
+                        Rc::strong_count(&n),
+                        1,
+                        "[lru cache] refs is {}, expect 1 when evicted",
+                        Rc::strong_count(&n)
+                    );
+                    self.usage.fetch_sub(n.charge, Ordering::SeqCst);
+                    Self::finish_erase(mutex_data, n);
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn is_expired(&self, handle: &LRUHandle<T>) -> bool {
+        self.ttl
+            .is_some_and(|ttl| handle.inserted_at.elapsed() >= ttl)
+    }
+
     // Create a dummy node whose 'next' and 'prev' are both itself
     fn create_dummy_node() -> *mut LRUHandle<T> {
         let node = Box::into_raw(Box::new(LRUHandle::new_empty()));
@@ -304,7 +437,7 @@ impl<T: 'static + Clone> LRUCache<T> {
     }
 }
 
-impl<T: 'static + Clone> Cache<T> for LRUCache<T> {
+impl<T: 'static + Clone + Send + Sync> Cache<T> for LRUCache<T> {
     fn insert(
         &self,
         key: Vec<u8>,
@@ -312,11 +445,13 @@ impl<T: 'static + Clone> Cache<T> for LRUCache<T> {
         charge: usize,
         deleter: Option<Box<FnMut(&[u8], T)>>,
     ) -> HandleRef<T> {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
         let mut mutex_data = self.mutex.lock().unwrap();
         let handle = LRUHandle::new(key.clone().into_boxed_slice(), value, deleter, charge);
         let r = Rc::new(handle);
         if self.capacity > 0 {
             let p = Rc::into_raw(r.clone()) as *mut LRUHandle<T>;
+            unsafe { (*p).in_cache = true };
             Self::lru_append(mutex_data.in_use, p);
             mem::drop(unsafe { Rc::from_raw(p) });
             self.usage.fetch_add(charge, Ordering::SeqCst);
@@ -332,42 +467,42 @@ impl<T: 'static + Clone> Cache<T> for LRUCache<T> {
                 Rc::strong_count(&r)
             );
         }
-        // evict unused lru entries
-        unsafe {
-            while self.usage.load(Ordering::Acquire) > self.capacity
-                && (*(*mutex_data).lru).next != mutex_data.lru
-            {
-                let old = (*mutex_data.lru).next;
-                if let Some(n) = mutex_data.table.remove(&(*old).key[..]) {
-                    assert_eq!(
-                        Rc::strong_count(&n),
-                        1,
-                        "[lru cache] refs is {}, expect 1 when evicted",
-                        Rc::strong_count(&n)
-                    );
-                    self.usage.fetch_sub(n.charge, Ordering::SeqCst);
-                    Self::finish_erase(&mut mutex_data, n);
-                }
-            }
-        }
+        self.evict(&mut mutex_data);
         r
     }
 
     fn look_up(&self, key: &[u8]) -> Option<HandleRef<T>> {
-        let mutex = self.mutex.lock().unwrap();
+        let mut mutex = self.mutex.lock().unwrap();
         match mutex.table.get(key) {
             Some(handle) => {
+                if self.is_expired(handle) {
+                    // evict the expired entry just like `erase`. The handles of it
+                    // that are still held by clients are kept valid until released.
+                    if let Some(n) = mutex.table.remove(key) {
+                        self.usage.fetch_sub(n.charge, Ordering::SeqCst);
+                        Self::finish_erase(&mut mutex, n);
+                    }
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 // ref added here
                 let h = Self::inc_ref(mutex.in_use, handle);
                 Some(h)
             }
-            None => None,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
         }
     }
 
     fn release(&self, handle: HandleRef<T>) {
-        let mutex = self.mutex.lock().unwrap();
+        let mut mutex = self.mutex.lock().unwrap();
         Self::dec_ref(mutex.lru, handle);
+        // the released entry might be the one exceeding the capacity
+        self.evict(&mut mutex);
     }
 
     fn erase(&self, key: &[u8]) {
@@ -402,6 +537,27 @@ impl<T: 'static + Clone> Cache<T> for LRUCache<T> {
     fn total_charge(&self) -> usize {
         self.usage.load(Ordering::Acquire)
     }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset_stats(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.insertions.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+    }
 }
 
 #[cfg(test)]
@@ -575,6 +731,25 @@ mod tests {
         cache.assert_deleted_keys_and_values(1, (100, 102));
     }
 
+    #[test]
+    fn test_erased_entries_in_use_are_never_evicted() {
+        let cache = CacheTest::new(CACHE_SIZE);
+        cache.insert(100, 101);
+        let h1 = cache.assert_inside_handle(100, 101);
+        cache.erase(100);
+        let h2 = cache.insert_and_return(100, 102);
+        // only the unused entries are evicted, not the erased (100, 101)
+        for i in 0..(CACHE_SIZE + 100) as u32 {
+            cache.insert(1000 + i, 2000 + i);
+        }
+        assert_eq!(101, h1.get_value().unwrap());
+        assert_eq!(102, h2.get_value().unwrap());
+        cache.cache.release(h1);
+        cache.cache.release(h2);
+        assert_eq!(Some(102), cache.look_up(100));
+        assert_eq!(CACHE_SIZE, cache.cache.total_charge());
+    }
+
     #[test]
     fn test_eviction_policy() {
         let cache = CacheTest::new(CACHE_SIZE);
@@ -669,10 +844,89 @@ mod tests {
         assert_ne!(b, c);
     }
 
+    #[test]
+    fn test_ttl_expiration() {
+        let mut cache = CacheTest::new(CACHE_SIZE);
+        cache.cache = Box::new(SharedLRUCache::<u32>::with_ttl(
+            CACHE_SIZE,
+            Some(Duration::from_millis(50)),
+        ));
+        cache.insert(100, 101);
+        cache.insert(200, 201);
+        let h = cache.assert_inside_handle(200, 201);
+        assert_eq!(Some(101), cache.look_up(100));
+        std::thread::sleep(Duration::from_millis(60));
+
+        // expired entries are treated as misses and evicted
+        assert_eq!(None, cache.look_up(100));
+        assert_eq!(1, cache.deleted_keys.borrow().len());
+        cache.assert_deleted_keys_and_values(0, (100, 101));
+        // the holding handle is still valid but not handed out again
+        assert_eq!(None, cache.look_up(200));
+        assert_eq!(201, h.get_value().unwrap());
+        assert_eq!(1, cache.deleted_keys.borrow().len());
+        cache.cache.release(h);
+        assert_eq!(2, cache.deleted_keys.borrow().len());
+        cache.assert_deleted_keys_and_values(1, (200, 201));
+        assert_eq!(0, cache.cache.total_charge());
+
+        // a re-inserted entry is fresh
+        cache.insert(100, 102);
+        assert_eq!(Some(102), cache.look_up(100));
+    }
+
     #[test]
     fn test_zero_size_cache() {
         let cache = CacheTest::new(0);
         cache.insert(100, 101);
         assert_eq!(None, cache.look_up(100));
     }
+
+    #[test]
+    fn test_total_charge_never_exceeds_capacity() {
+        for num_shard_bits in [0, 1, 4, 6].iter() {
+            for cap in [1, 7, 100, 1000].iter() {
+                let cache = SharedLRUCache::<u32>::with_config(*cap, *num_shard_bits, None);
+                assert_eq!(1 << *num_shard_bits, cache.num_shards());
+                for i in 0..5000u32 {
+                    let h = cache.insert(encoded_u32(i), i, (i % 3 + 1) as usize, None);
+                    cache.release(h);
+                    assert!(
+                        cache.total_charge() <= *cap,
+                        "total charge {} exceeds the capacity {} with {} shard bits",
+                        cache.total_charge(),
+                        cap,
+                        num_shard_bits
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache = CacheTest::new(CACHE_SIZE);
+        assert_eq!(CacheStats::default(), cache.cache.stats());
+        assert_eq!(0.0, cache.cache.stats().hit_ratio());
+        for i in 0..(CACHE_SIZE + 10) as u32 {
+            cache.insert(i, i);
+        }
+        assert_eq!(
+            Some(CACHE_SIZE as u32 + 9),
+            cache.look_up(CACHE_SIZE as u32 + 9)
+        );
+        assert_eq!(None, cache.look_up(CACHE_SIZE as u32 + 10));
+        let stats = cache.cache.stats();
+        assert_eq!(1, stats.hits);
+        assert_eq!(1, stats.misses);
+        assert_eq!(CACHE_SIZE as u64 + 10, stats.insertions);
+        assert_eq!(cache.deleted_keys.borrow().len() as u64, stats.evictions);
+        assert!(stats.evictions >= 10);
+        assert_eq!(0.5, stats.hit_ratio());
+
+        cache.cache.reset_stats();
+        assert_eq!(CacheStats::default(), cache.cache.stats());
+        cache.look_up(CACHE_SIZE as u32 + 9);
+        assert_eq!(1, cache.cache.stats().hits);
+    }
 }
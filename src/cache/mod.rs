@@ -17,6 +17,7 @@
 
 use std::rc::Rc;
 
+pub mod clock;
 pub mod lru;
 
 /// The `Handle` is a simple trait for the value in Cache
@@ -41,7 +42,7 @@ pub trait Handle<T> {
 /// Clients may use their own implementations if
 /// they want something more sophisticated (like scan-resistance, a
 /// custom eviction policy, variable cache sizing, etc.)
-pub trait Cache<T> {
+pub trait Cache<T>: Send + Sync {
     /// Insert a mapping from key->value into the cache and assign it
     /// the specified charge against the total cache capacity.
     ///
@@ -89,6 +90,42 @@ pub trait Cache<T> {
     /// Return an estimate of the combined charges of all elements stored in the
     /// cache.
     fn total_charge(&self) -> usize;
+
+    /// Returns the capacity of the combined charges of the cache.
+    fn capacity(&self) -> usize;
+
+    /// Returns the statistics of the cache since it's created or the last `reset_stats()`.
+    fn stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+
+    /// Reset all the statistics counters to zero.
+    fn reset_stats(&self) {}
+}
+
+/// The statistics of a `Cache`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of `look_up` calls that found an entry
+    pub hits: u64,
+    /// The number of `look_up` calls that found nothing
+    pub misses: u64,
+    /// The number of `insert` calls
+    pub insertions: u64,
+    /// The number of entries evicted to make room for new entries or because they're expired
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Returns the ratio of hits to all the lookups. Returns 0 when there is no lookup.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
 }
 
 pub type HandleRef<T> = Rc<dyn Handle<T>>;
@@ -15,27 +15,79 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use crate::db::format::{InternalKey, InternalKeyComparator};
+use crate::db::event_listener::{CompactionJobInfo, TableFileInfo};
+use crate::db::format::{extract_user_key, InternalKey, InternalKeyComparator};
+use crate::db::range_del::RangeTombstone;
 use crate::iterator::{ConcatenateIterator, Iterator, MergingIterator};
-use crate::options::{Options, ReadOptions};
+use crate::options::{CompactRangeOptions, Options, ReadOptions};
 use crate::sstable::table::TableBuilder;
 use crate::table_cache::TableCache;
 use crate::util::comparator::Comparator;
 use crate::util::slice::Slice;
+use crate::util::status::Result;
 use crate::version::version_edit::{FileMetaData, VersionEdit};
 use crate::version::version_set::{FileIterFactory, VersionSet};
 use crate::version::{LevelFileNumIterator, Version};
 use std::cell::RefCell;
 use std::cmp::Ordering as CmpOrdering;
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
 /// Information for a manual compaction
 pub struct ManualCompaction {
     pub level: usize,
+    // Whether the files are rewritten in `level` instead of being compacted into `level + 1`
+    pub in_place: bool,
     pub done: bool,
-    pub begin: Option<Rc<InternalKey>>, // None means beginning of key range
-    pub end: Option<Rc<InternalKey>>,   // None means end of key range
+    pub begin: Option<Arc<InternalKey>>, // None means beginning of key range
+    pub end: Option<Arc<InternalKey>>,   // None means end of key range
+    pub options: CompactRangeOptions,
+    // Whether the manual compaction is stopped by the cancellation
+    pub aborted: bool,
+}
+
+impl ManualCompaction {
+    /// Returns whether the cancellation is requested by `CompactRangeOptions::canceled`
+    #[inline]
+    pub fn is_canceled(&self) -> bool {
+        is_canceled(&self.options.canceled)
+    }
+}
+
+/// The progress of a `WickDB::compact_range_with_options`. The counters are
+/// increased every time a compaction of the range finishes and its outputs are
+/// installed, so the canceled compactions are never counted.
+#[derive(Default, Debug)]
+pub struct CompactionProgress {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl CompactionProgress {
+    /// Returns the total size of the input files compacted so far
+    #[inline]
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Acquire)
+    }
+
+    /// Returns the total size of the output files written so far
+    #[inline]
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn add(&self, bytes_read: u64, bytes_written: u64) {
+        self.bytes_read.fetch_add(bytes_read, Ordering::AcqRel);
+        self.bytes_written
+            .fetch_add(bytes_written, Ordering::AcqRel);
+    }
+}
+
+#[inline]
+fn is_canceled(canceled: &Option<Arc<AtomicBool>>) -> bool {
+    canceled.as_ref().is_some_and(|c| c.load(Ordering::Acquire))
 }
 
 /// A helper enum describing relations between the indexes of `inputs` in `Compaction`
@@ -80,6 +132,14 @@ pub struct Compaction {
     // we can drop all entries for the same key with sequence numbers < S
     pub oldest_snapshot_alive: u64,
 
+    // The sequence number of the newest live snapshot. The entries with greater
+    // sequence numbers are invisible to all the snapshots so the compaction
+    // filter is able to be applied on them.
+    pub newest_snapshot: Option<u64>,
+
+    // Whether any entry is removed or modified by the compaction filter
+    pub filtered: bool,
+
     // all output files information
     pub outputs: Vec<FileMetaData>,
 
@@ -90,8 +150,32 @@ pub struct Compaction {
 
     // total bytes has been written
     pub total_bytes: u64,
+
+    // The user key range `[start, end)` of a subcompaction. `None` means the
+    // range is unbounded on that side.
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+
+    // The level where the output files are placed. It's `level + 1` except for
+    // the universal compaction.
+    pub output_level: usize,
+    // The input files in the levels deeper than `level` picked by the universal
+    // compaction. Each level is a whole sorted run.
+    pub other_inputs: Vec<(usize, Vec<Arc<FileMetaData>>)>,
+
+    // Whether the compaction is picked for a file older than `Options::ttl`.
+    // The inputs are always rewritten so the compaction filter is applied on them.
+    pub expired: bool,
+
+    // The cancellation flag of the manual compaction. The compaction stops at the
+    // next entry once it's set and none of the outputs is installed.
+    pub canceled: Option<Arc<AtomicBool>>,
 }
 
+// The subcompactions are run on separate threads. A subcompaction only shares the
+// immutable input files with the others and owns the rest of its states.
+unsafe impl Send for Compaction {}
+
 impl Compaction {
     pub fn new(options: Arc<Options>, level: usize) -> Self {
         let max_levels = options.max_levels as usize;
@@ -109,16 +193,91 @@ impl Compaction {
             grand_parent_index: 0,
             seen_key: false,
             overlapped_bytes: 0,
-            level_ptrs: vec![],
+            level_ptrs,
             oldest_snapshot_alive: 0,
+            newest_snapshot: None,
+            filtered: false,
             outputs: vec![],
             builder: None,
             total_bytes: 0,
+            start: None,
+            end: None,
+            output_level: level + 1,
+            other_inputs: vec![],
+            expired: false,
+            canceled: None,
+        }
+    }
+
+    /// Returns the user keys splitting the compaction into at most
+    /// `max_subcompactions` subcompactions with about the same input bytes.
+    /// The keys are picked from the smallest keys of the input files so every
+    /// subcompaction reads a disjoint key span. A key in `(start, end]` of any of
+    /// `range_tombstones` is never picked so that a tombstone and the keys it covers
+    /// are written by the same subcompaction. No key is returned if the compaction
+    /// should not be split.
+    pub fn subcompaction_boundaries(
+        &self,
+        ucmp: &dyn Comparator,
+        range_tombstones: &[RangeTombstone],
+    ) -> Vec<Vec<u8>> {
+        let max = self.options.max_subcompactions;
+        let mut files: Vec<&FileMetaData> = self
+            .inputs
+            .iter()
+            .chain(self.other_inputs.iter().map(|(_, files)| files))
+            .flatten()
+            .map(|f| f.as_ref())
+            .collect();
+        if max <= 1 || files.len() <= 1 {
+            return vec![];
+        }
+        files.sort_by(|a, b| ucmp.compare(a.smallest.user_key(), b.smallest.user_key()));
+        let total = self.bytes_read();
+        let mut boundaries: Vec<Vec<u8>> = vec![];
+        let mut accumulated = 0;
+        for (i, f) in files.iter().enumerate().skip(1) {
+            accumulated += files[i - 1].file_size;
+            let key = f.smallest.user_key();
+            let last = boundaries
+                .last()
+                .map_or(files[0].smallest.user_key(), |b| b.as_slice());
+            let in_tombstone = range_tombstones.iter().any(|t| {
+                ucmp.compare(t.start.as_slice(), key) == CmpOrdering::Less
+                    && ucmp.compare(key, t.end.as_slice()) != CmpOrdering::Greater
+            });
+            if accumulated * max as u64 >= total * (boundaries.len() as u64 + 1)
+                && ucmp.compare(key, last) == CmpOrdering::Greater
+                && !in_tombstone
+            {
+                boundaries.push(Vec::from(key));
+                if boundaries.len() + 1 == max {
+                    break;
+                }
+            }
         }
+        boundaries
+    }
+
+    /// Creates a subcompaction compacting the same inputs into the user key
+    /// range `[start, end)`
+    pub fn new_subcompaction(&self, start: Option<Vec<u8>>, end: Option<Vec<u8>>) -> Self {
+        let mut sub = Self::new(self.options.clone(), self.level);
+        sub.input_version = self.input_version.clone();
+        sub.inputs = self.inputs.clone();
+        sub.grand_parents = self.grand_parents.clone();
+        sub.oldest_snapshot_alive = self.oldest_snapshot_alive;
+        sub.newest_snapshot = self.newest_snapshot;
+        sub.canceled = self.canceled.clone();
+        sub.output_level = self.output_level;
+        sub.other_inputs = self.other_inputs.clone();
+        sub.start = start;
+        sub.end = end;
+        sub
     }
 
     /// Returns the minimal range that covers all entries in `self.inputs[0]`
-    pub fn base_range(&self, icmp: &InternalKeyComparator) -> (Rc<InternalKey>, Rc<InternalKey>) {
+    pub fn base_range(&self, icmp: &InternalKeyComparator) -> (Arc<InternalKey>, Arc<InternalKey>) {
         let files = &self.inputs[CompactionInputsRelation::Source as usize];
         assert!(
             !files.is_empty(),
@@ -147,7 +306,10 @@ impl Compaction {
     }
 
     /// Returns the minimal range that covers all entries in `self.inputs`
-    pub fn total_range(&self, icmp: &InternalKeyComparator) -> (Rc<InternalKey>, Rc<InternalKey>) {
+    pub fn total_range(
+        &self,
+        icmp: &InternalKeyComparator,
+    ) -> (Arc<InternalKey>, Arc<InternalKey>) {
         let (mut smallest, mut largest) = self.base_range(icmp);
         let files = &self.inputs[CompactionInputsRelation::Parent as usize];
         if !files.is_empty() {
@@ -165,11 +327,22 @@ impl Compaction {
 
     /// Is this a trivial compaction that can be implemented by just
     /// moving a single input file to the next level (no merging or splitting)
+    /// Returns whether the compaction is canceled
+    #[inline]
+    pub fn is_canceled(&self) -> bool {
+        is_canceled(&self.canceled)
+    }
+
     pub fn is_trivial_move(&self) -> bool {
         self.inputs[CompactionInputsRelation::Source as usize].len() == 1
             && self.inputs[CompactionInputsRelation::Parent as usize].is_empty()
+            && self.other_inputs.is_empty()
+            && self.output_level == self.level + 1
+            && !self.expired
             && VersionSet::total_file_size(self.grand_parents.as_slice())
-                <= self.options.max_grandparent_overlap_bytes()
+                <= self
+                    .options
+                    .max_grandparent_overlap_bytes(self.output_level)
     }
 
     /// Create an iterator that reads over all the compaction input tables with merged order.
@@ -189,7 +362,8 @@ impl Compaction {
         let read_options = Rc::new(ReadOptions {
             verify_checksums: self.options.paranoid_checks,
             fill_cache: false,
-            snapshot: None,
+            readahead_size: self.options.compaction_readahead_size,
+            ..Default::default()
         });
         // Level-0 files have to be merged together so we generate a merging iterator includes iterators for each level 0 file.
         // For other levels, we will make a concatenating iterator per level.
@@ -207,13 +381,14 @@ impl Compaction {
                         // all the level0 tables are guaranteed being added into the table_cache via minor compaction
                         iter_list.push(Rc::new(RefCell::new(table_cache.clone().new_iter(
                             read_options.clone(),
+                            0,
                             file.number,
                             file.file_size,
                         ))));
                     }
                 } else {
                     let origin = LevelFileNumIterator::new(icmp.clone(), self.inputs[i].clone());
-                    let factory = FileIterFactory::new(table_cache.clone());
+                    let factory = FileIterFactory::new(table_cache.clone(), self.level + i);
                     iter_list.push(Rc::new(RefCell::new(Box::new(ConcatenateIterator::new(
                         read_options.clone(),
                         Box::new(origin),
@@ -222,9 +397,53 @@ impl Compaction {
                 }
             }
         }
+        for (level, files) in self.other_inputs.iter() {
+            let origin = LevelFileNumIterator::new(icmp.clone(), files.clone());
+            let factory = FileIterFactory::new(table_cache.clone(), *level);
+            iter_list.push(Rc::new(RefCell::new(Box::new(ConcatenateIterator::new(
+                read_options.clone(),
+                Box::new(origin),
+                Box::new(factory),
+            )))));
+        }
         MergingIterator::new(icmp, iter_list)
     }
 
+    /// Returns the range tombstones in all the input files
+    pub fn input_range_tombstones(&self, table_cache: &TableCache) -> Result<Vec<RangeTombstone>> {
+        let mut tombstones = vec![];
+        let inputs = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, files)| (self.level + i, files))
+            .chain(
+                self.other_inputs
+                    .iter()
+                    .map(|(level, files)| (*level, files)),
+            );
+        for (level, files) in inputs {
+            for f in files.iter().filter(|f| f.has_range_tombstones) {
+                let t = table_cache.range_tombstones(level, f.number, f.file_size)?;
+                tombstones.extend(t.iter().cloned());
+            }
+        }
+        Ok(tombstones)
+    }
+
+    /// Returns true if the current output file is able to be finished before the entry
+    /// of internal key `ikey`. A user key is never split into two files, and nor is the
+    /// range of a range tombstone, so that the output files don't overlap each other.
+    pub fn can_finish_output_before(&self, ucmp: &dyn Comparator, ikey: &[u8]) -> bool {
+        match self.outputs.last() {
+            Some(output) => {
+                ucmp.compare(extract_user_key(ikey).as_slice(), output.largest.user_key())
+                    == CmpOrdering::Greater
+            }
+            None => true,
+        }
+    }
+
     /// Returns true iff we should stop building the current output
     /// before processing `ikey` for too much overlapping with grand parents
     pub fn should_stop_before(&mut self, ikey: &Slice, icmp: Arc<InternalKeyComparator>) -> bool {
@@ -242,7 +461,11 @@ impl Compaction {
             self.grand_parent_index += 1;
         }
         self.seen_key = true;
-        if self.overlapped_bytes > self.options.max_grandparent_overlap_bytes() {
+        if self.overlapped_bytes
+            > self
+                .options
+                .max_grandparent_overlap_bytes(self.output_level)
+        {
             // Too much overlap for current output, start new output
             self.overlapped_bytes = 0;
             return true;
@@ -251,15 +474,19 @@ impl Compaction {
     }
 
     /// Returns false if the information we have available guarantees that
-    /// the compaction is producing data in "level+1" for which no relative key exists
-    /// in levels greater than "level+1".
+    /// the compaction is producing data in "output_level" for which no relative key exists
+    /// in levels greater than "output_level".
     pub fn key_exist_in_deeper_level(&mut self, ukey: &Slice) -> bool {
+        if self.output_level == 0 {
+            // The older level 0 files not in the compaction might contain the key
+            return true;
+        }
         let v = self.input_version.as_ref().unwrap().clone();
         let icmp = v.get_comparator().clone();
         let ucmp = icmp.user_comparator.as_ref();
         let max_levels = self.options.max_levels as usize;
-        if self.level + 2 < max_levels {
-            for level in self.level + 2..max_levels {
+        if self.output_level + 1 < max_levels {
+            for level in self.output_level + 1..max_levels {
                 let files = v.get_level_files(level);
                 while self.level_ptrs[level] < files.len() {
                     let f = files[self.level_ptrs[level]].clone();
@@ -278,6 +505,19 @@ impl Compaction {
         false
     }
 
+    /// Returns false if no file in the levels greater than "output_level" overlaps the
+    /// user keys `[start, end)`, so a range tombstone of the range covers no key
+    /// outside the compaction.
+    pub fn range_exist_in_deeper_level(&self, start: &[u8], end: &[u8]) -> bool {
+        if self.output_level == 0 {
+            // The older level 0 files not in the compaction might overlap the range
+            return true;
+        }
+        let v = self.input_version.as_ref().unwrap();
+        (self.output_level + 1..self.options.max_levels as usize)
+            .any(|level| v.overlap_in_level(level, &Slice::from(start), &Slice::from(end)))
+    }
+
     /// Apply deletion for current inputs and current output files to the edit
     pub fn apply_to_edit(&mut self) {
         for (delta, files) in self.inputs.iter().enumerate() {
@@ -285,26 +525,126 @@ impl Compaction {
                 self.edit.delete_file(self.level + delta, file.number)
             }
         }
-        for output in self.outputs.drain(..) {
-            self.edit.new_files.push((self.level + 1, Rc::new(output)))
+        for (level, files) in self.other_inputs.iter() {
+            for file in files.iter() {
+                self.edit.delete_file(*level, file.number)
+            }
+        }
+        for output in self.outputs.iter() {
+            self.edit.add_file(self.output_level, output)
+        }
+    }
+
+    /// Returns the numbers of all the input files
+    pub fn input_numbers(&self) -> Vec<u64> {
+        self.inputs
+            .iter()
+            .chain(self.other_inputs.iter().map(|(_, files)| files))
+            .flat_map(|files| files.iter().map(|f| f.number))
+            .collect()
+    }
+
+    /// Returns the information of the compaction reported to the `EventListener`s.
+    /// The only source file is the output file of a trivial move.
+    pub fn job_info(&self, db_name: &str, is_trivial_move: bool) -> CompactionJobInfo {
+        let mut input_files = vec![];
+        for (delta, files) in self.inputs.iter().enumerate() {
+            for file in files.iter() {
+                input_files.push(TableFileInfo::new(db_name, self.level + delta, file));
+            }
+        }
+        for (level, files) in self.other_inputs.iter() {
+            for file in files.iter() {
+                input_files.push(TableFileInfo::new(db_name, *level, file));
+            }
+        }
+        let output_files = if is_trivial_move {
+            input_files
+                .iter()
+                .map(|f| TableFileInfo {
+                    level: self.output_level,
+                    ..f.clone()
+                })
+                .collect()
+        } else {
+            self.outputs
+                .iter()
+                .map(|f| TableFileInfo::new(db_name, self.output_level, f))
+                .collect()
+        };
+        CompactionJobInfo {
+            level: self.level,
+            output_level: self.output_level,
+            input_files,
+            output_files,
+            is_trivial_move,
         }
     }
 
     /// Calculate the read bytes
     #[inline]
     pub fn bytes_read(&self) -> u64 {
-        self.inputs.iter().fold(0, |accumulate, files| {
-            accumulate + files.iter().fold(0, |sum, file| sum + file.number)
-        })
+        self.inputs
+            .iter()
+            .chain(self.other_inputs.iter().map(|(_, files)| files))
+            .fold(0, |accumulate, files| {
+                accumulate + files.iter().fold(0, |sum, file| sum + file.file_size)
+            })
     }
 
     /// Calculate the written bytes
     #[inline]
     pub fn bytes_written(&self) -> u64 {
-        self.outputs.iter().fold(0, |sum, file| sum + file.number)
+        self.outputs
+            .iter()
+            .fold(0, |sum, file| sum + file.file_size)
     }
 }
 
+/// A sorted run in the universal compaction, which is either a level 0 file
+/// or all the files in a deeper level
+pub struct SortedRun {
+    pub level: usize,
+    pub files: Vec<Arc<FileMetaData>>,
+    pub size: u64,
+}
+
+/// Picks the sorted runs to be merged by the universal compaction from `runs`
+/// sorted from the newest to the oldest. See `UniversalCompactionOptions` for
+/// how the runs are picked.
+///
+/// Returns the range of the picked runs in `runs`. The output of the merged runs
+/// is placed right above the next older run, so the picked runs never include
+/// a level 0 file unless they start from the newest run.
+pub fn pick_sorted_runs(runs: &[SortedRun], options: &Options) -> Option<Range<usize>> {
+    let opts = &options.universal_compaction_options;
+    if runs.len() < options.level0_file_num_compaction_trigger.max(2) {
+        return None;
+    }
+    for start in 0..runs.len() {
+        if start > 0 && runs[start].level == 0 {
+            continue;
+        }
+        let mut total = runs[start].size;
+        let mut end = start + 1;
+        while end < runs.len()
+            && end - start < opts.max_merge_width
+            && runs[end].size * 100 <= total * (100 + opts.size_ratio)
+        {
+            total += runs[end].size;
+            end += 1;
+        }
+        if end - start >= opts.min_merge_width {
+            return Some(start..end);
+        }
+    }
+    if runs.len() > opts.max_sorted_runs {
+        // Merge the newest runs into one to bound the number of the sorted runs
+        return Some(0..runs.len() - opts.max_sorted_runs + 1);
+    }
+    None
+}
+
 /// A helper struct for recording the statistics in compactions
 pub struct CompactionStats {
     micros: u64,
@@ -329,3 +669,54 @@ impl CompactionStats {
         self.bytes_written += bytes_written;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::UniversalCompactionOptions;
+
+    #[test]
+    fn test_pick_sorted_runs() {
+        let runs = |runs: &[(usize, u64)]| -> Vec<SortedRun> {
+            runs.iter()
+                .map(|(level, size)| SortedRun {
+                    level: *level,
+                    files: vec![],
+                    size: *size,
+                })
+                .collect()
+        };
+        let options = Options {
+            level0_file_num_compaction_trigger: 3,
+            universal_compaction_options: UniversalCompactionOptions {
+                max_sorted_runs: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let tests: Vec<(Vec<(usize, u64)>, Option<Range<usize>>)> = vec![
+            // too few runs
+            (vec![(0, 1), (0, 1)], None),
+            (vec![(0, 1), (0, 1), (0, 1)], Some(0..3)),
+            // the runs much bigger than the newer ones are left
+            (vec![(0, 1), (0, 1), (0, 2), (0, 10)], Some(0..3)),
+            (vec![(0, 1), (0, 10), (5, 100)], None),
+            // the level 0 files are only picked from the newest one
+            (vec![(0, 1), (0, 10), (4, 100), (5, 100)], Some(2..4)),
+            (vec![(0, 1), (0, 10), (0, 10), (5, 100)], None),
+            // the newest runs are merged to keep at most 4 sorted runs
+            (
+                vec![(0, 1), (0, 10), (0, 100), (4, 1000), (5, 10000)],
+                Some(0..2),
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(
+                pick_sorted_runs(&runs(&input), &options),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+}
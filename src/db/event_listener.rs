@@ -0,0 +1,78 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::db::filename::{generate_filename, FileType};
+use crate::version::version_edit::FileMetaData;
+
+/// An `EventListener` is notified by the background thread of a DB when a flush
+/// or a compaction finishes successfully.
+///
+/// The callbacks are invoked without holding any lock of the DB, so they are able
+/// to call back into the DB. But they block the following background work until
+/// they return, so they should be quick.
+pub trait EventListener: Send + Sync {
+    /// Called after an immutable memtable is flushed into a new table.
+    /// Not called if the memtable produces no table.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called after the result of a compaction is installed: the `input_files`
+    /// are removed from the DB and the `output_files` are added.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+}
+
+/// A table file in a DB
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableFileInfo {
+    /// The path of the file
+    pub path: String,
+    /// The file number
+    pub number: u64,
+    /// The level where the file is
+    pub level: usize,
+    /// The size of the file in bytes
+    pub file_size: u64,
+}
+
+impl TableFileInfo {
+    pub(crate) fn new(db_name: &str, level: usize, meta: &FileMetaData) -> Self {
+        Self {
+            path: generate_filename(db_name, FileType::Table, meta.number),
+            number: meta.number,
+            level,
+            file_size: meta.file_size,
+        }
+    }
+}
+
+/// The result of a flush
+#[derive(Clone, Debug)]
+pub struct FlushJobInfo {
+    /// The table generated by the flush
+    pub file: TableFileInfo,
+}
+
+/// The result of a compaction
+#[derive(Clone, Debug)]
+pub struct CompactionJobInfo {
+    /// The level being compacted
+    pub level: usize,
+    /// The level where the output files are placed
+    pub output_level: usize,
+    /// The files removed by the compaction, from `level` and `output_level`
+    pub input_files: Vec<TableFileInfo>,
+    /// The files added by the compaction
+    pub output_files: Vec<TableFileInfo>,
+    /// Whether the only input file is moved to `output_level` without being
+    /// rewritten. The only output file is the same file then.
+    pub is_trivial_move: bool,
+}
@@ -15,26 +15,49 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+//! The format of the internal keys stored in the sstables and the memtables.
+//!
+//! An internal key is a user key followed by an 8-byte tag, which is the
+//! little-endian fixed64 of `(sequence << 8) | type`. The type byte is one of the
+//! `TYPE_*` constants, which are also the tags of the records in a `WriteBatch`
+//! (and so in the WAL). This format is persisted and stays stable across
+//! versions, so tools reading the files directly could rely on it.
+
 use crate::filter::FilterPolicy;
 use crate::util::coding::{decode_fixed_64, put_fixed_64};
 use crate::util::comparator::Comparator;
 use crate::util::slice::Slice;
+use crate::util::status::{Result as WickResult, Status, WickErr};
 use crate::util::varint::VarintU32;
 use std::cmp::Ordering;
 use std::fmt::{Debug, Error, Formatter};
-use std::rc::Rc;
 use std::sync::Arc;
 
 /// The max key sequence number. The value is 2^56 - 1 because the seq number
 /// only takes 56 bits when is serialized to `InternalKey`
 pub const MAX_KEY_SEQUENCE: u64 = (1u64 << 56) - 1;
 
+/// The type byte of a deletion
+pub const TYPE_DELETION: u8 = 0;
+/// The type byte of a normal value
+pub const TYPE_VALUE: u8 = 1;
+/// The type byte of a range tombstone
+pub const TYPE_RANGE_DELETION: u8 = 2;
+/// The type byte of a merge operand
+pub const TYPE_MERGE: u8 = 3;
+
+/// The type of an entry, which is stored as the lowest byte of the tag of an
+/// internal key
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ValueType {
     /// A value indicates that the key is deleted
-    Deletion = 0,
+    Deletion = TYPE_DELETION as isize,
     /// A normal value
-    Value = 1,
+    Value = TYPE_VALUE as isize,
+    /// A range tombstone deleting the user keys in `[key, value)`
+    RangeDeletion = TYPE_RANGE_DELETION as isize,
+    /// A merge operand to be applied on the older value
+    Merge = TYPE_MERGE as isize,
 
     /// Unknown type
     Unknown,
@@ -46,13 +69,18 @@ pub enum ValueType {
 /// and the value type is embedded as the low 8 bits in the sequence
 /// number in internal keys, we need to use the highest-numbered
 /// ValueType, not the lowest).
-pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Merge;
 
 impl From<u64> for ValueType {
     fn from(v: u64) -> Self {
-        match v {
-            1 => ValueType::Value,
-            0 => ValueType::Deletion,
+        if v > u64::from(u8::MAX) {
+            return ValueType::Unknown;
+        }
+        match v as u8 {
+            TYPE_MERGE => ValueType::Merge,
+            TYPE_RANGE_DELETION => ValueType::RangeDeletion,
+            TYPE_VALUE => ValueType::Value,
+            TYPE_DELETION => ValueType::Deletion,
             _ => ValueType::Unknown,
         }
     }
@@ -164,7 +192,21 @@ impl InternalKey {
     #[inline]
     pub fn user_key(&self) -> &[u8] {
         let length = self.data.len();
-        &self.data.as_slice()[length - 8..]
+        &self.data.as_slice()[..length - 8]
+    }
+
+    /// Returns the sequence number in the tag
+    #[inline]
+    pub fn sequence(&self) -> u64 {
+        extract_seq_number(self.data.as_slice())
+    }
+
+    /// Returns the value type in the tag, which is `ValueType::Unknown` for an
+    /// unknown type byte
+    #[inline]
+    pub fn value_type(&self) -> ValueType {
+        let size = self.data.len();
+        ValueType::from(decode_fixed_64(&self.data.as_slice()[size - 8..]) & 0xff)
     }
 
     /// Returns a `ParsedInternalKey`
@@ -249,6 +291,11 @@ impl LookupKey {
         let len = self.data.len();
         Slice::from(&self.data.as_slice()[self.ukey_start..len - 8])
     }
+
+    /// Returns the sequence number to read at
+    pub fn sequence(&self) -> u64 {
+        decode_fixed_64(&self.data.as_slice()[self.data.len() - 8..]) >> 8
+    }
 }
 
 /// `InternalKeyComparator` is used for comparing the `InternalKey`
@@ -274,7 +321,7 @@ impl Comparator for InternalKeyComparator {
         let ua = extract_user_key(a);
         let ub = extract_user_key(b);
         // compare user key first
-        match ua.compare(&ub) {
+        match self.user_comparator.compare(ua.as_slice(), ub.as_slice()) {
             Ordering::Greater => Ordering::Greater,
             Ordering::Less => Ordering::Less,
             Ordering::Equal => {
@@ -298,18 +345,46 @@ impl Comparator for InternalKeyComparator {
         "leveldb.InternalKeyComparator"
     }
 
-    fn separator(&self, _a: &[u8], _b: &[u8]) -> Vec<u8> {
-        unimplemented!()
+    fn separator(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let ua = extract_user_key(a);
+        let ub = extract_user_key(b);
+        let mut sep = self.user_comparator.separator(ua.as_slice(), ub.as_slice());
+        if sep.len() < ua.size()
+            && self.user_comparator.compare(ua.as_slice(), sep.as_slice()) == Ordering::Less
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            put_fixed_64(
+                &mut sep,
+                pack_seq_and_type(MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK),
+            );
+            return sep;
+        }
+        Vec::from(a)
     }
 
-    fn successor(&self, _s: &[u8]) -> Vec<u8> {
-        unimplemented!()
+    fn successor(&self, s: &[u8]) -> Vec<u8> {
+        let ukey = extract_user_key(s);
+        let mut succ = self.user_comparator.successor(ukey.as_slice());
+        if succ.len() < ukey.size()
+            && self
+                .user_comparator
+                .compare(ukey.as_slice(), succ.as_slice())
+                == Ordering::Less
+        {
+            put_fixed_64(
+                &mut succ,
+                pack_seq_and_type(MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK),
+            );
+            return succ;
+        }
+        Vec::from(s)
     }
 }
 
 /// A wrapper for the internal key filter policy
 pub struct InternalFilterPolicy {
-    user_policy: Rc<dyn FilterPolicy>,
+    user_policy: Arc<dyn FilterPolicy>,
 }
 
 impl FilterPolicy for InternalFilterPolicy {
@@ -333,6 +408,40 @@ impl FilterPolicy for InternalFilterPolicy {
     }
 }
 
+/// Parses the internal key `data`. Returns a `Status::Corruption` error if it's
+/// shorter than the tag or has an unknown type byte.
+pub fn parse_internal_key(data: &[u8]) -> WickResult<InternalKey> {
+    if data.len() < 8 {
+        return Err(WickErr::new(
+            Status::Corruption,
+            Some("[internal key] too short for the tag"),
+        ));
+    }
+    let key = InternalKey::decoded_from(data);
+    if key.value_type() == ValueType::Unknown {
+        return Err(WickErr::new(
+            Status::Corruption,
+            Some("[internal key] unknown value type"),
+        ));
+    }
+    Ok(key)
+}
+
+/// Encodes the internal key of `user_key` with the sequence number `seq` and the
+/// value type `t`.
+///
+/// # Panics
+///
+/// * If `seq` is larger than `MAX_KEY_SEQUENCE`
+/// * If `t` is `ValueType::Unknown`
+pub fn encode_internal_key(user_key: &[u8], seq: u64, t: ValueType) -> Vec<u8> {
+    assert!(t != ValueType::Unknown, "[internal key] unknown value type");
+    let mut v = Vec::with_capacity(user_key.len() + 8);
+    v.extend_from_slice(user_key);
+    put_fixed_64(&mut v, pack_seq_and_type(seq, t));
+    v
+}
+
 // use a `Slice` to represent only the user key in a internal key slice
 #[inline]
 pub fn extract_user_key(key: &[u8]) -> Slice {
@@ -372,6 +481,8 @@ fn pack_seq_and_type(seq: u64, v_type: ValueType) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::comparator::BytewiseComparator;
+    use rand::Rng;
 
     #[test]
     fn test_pack_seq_and_type() {
@@ -390,6 +501,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_and_encode_internal_key() {
+        for (t, b) in [
+            (ValueType::Deletion, TYPE_DELETION),
+            (ValueType::Value, TYPE_VALUE),
+            (ValueType::RangeDeletion, TYPE_RANGE_DELETION),
+            (ValueType::Merge, TYPE_MERGE),
+        ]
+        .iter()
+        {
+            let encoded = encode_internal_key(b"key", 100, *t);
+            assert_eq!(
+                encoded,
+                InternalKey::new(&Slice::from("key"), 100, *t).data()
+            );
+            assert_eq!(encoded[3], *b);
+            assert_eq!(decode_fixed_64(&encoded[3..]), 100 << 8 | u64::from(*b));
+            let key = parse_internal_key(encoded.as_slice()).unwrap();
+            assert_eq!(key.user_key(), b"key");
+            assert_eq!(key.sequence(), 100);
+            assert_eq!(key.value_type(), *t);
+        }
+        let key = parse_internal_key(&encode_internal_key(
+            b"",
+            MAX_KEY_SEQUENCE,
+            ValueType::Value,
+        ))
+        .unwrap();
+        assert!(key.user_key().is_empty());
+        assert_eq!(key.sequence(), MAX_KEY_SEQUENCE);
+        // too short or unknown type
+        for data in [&b"1234567"[..], &[4, 0, 0, 0, 0, 0, 0, 0][..]].iter() {
+            assert_eq!(
+                parse_internal_key(data).unwrap_err().status(),
+                Status::Corruption
+            );
+        }
+        assert_eq!(ValueType::from(256 + 1), ValueType::Unknown);
+    }
+
     #[test]
     #[should_panic]
     fn test_pack_seq_and_type_panic() {
@@ -428,4 +579,93 @@ mod tests {
             }
         }
     }
+
+    fn ikey(key: &str, seq: u64, vt: ValueType) -> Vec<u8> {
+        Vec::from(InternalKey::new(&Slice::from(key), seq, vt).data())
+    }
+
+    #[test]
+    fn test_internal_key_short_separator() {
+        let icmp = InternalKeyComparator::new(Arc::new(BytewiseComparator::new()));
+        let tests = vec![
+            // when user keys are same
+            (
+                ikey("foo", 100, ValueType::Value),
+                ikey("foo", 99, ValueType::Value),
+                ikey("foo", 100, ValueType::Value),
+            ),
+            (
+                ikey("foo", 100, ValueType::Value),
+                ikey("foo", 101, ValueType::Value),
+                ikey("foo", 100, ValueType::Value),
+            ),
+            // when user keys are misordered
+            (
+                ikey("foo", 100, ValueType::Value),
+                ikey("bar", 99, ValueType::Value),
+                ikey("foo", 100, ValueType::Value),
+            ),
+            // when user keys are different, but correctly ordered
+            (
+                ikey("foo", 100, ValueType::Value),
+                ikey("hello", 200, ValueType::Value),
+                ikey("g", MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK),
+            ),
+            // when start user key is prefix of limit user key
+            (
+                ikey("foo", 100, ValueType::Value),
+                ikey("foobar", 200, ValueType::Value),
+                ikey("foo", 100, ValueType::Value),
+            ),
+            // when limit user key is prefix of start user key
+            (
+                ikey("foobar", 100, ValueType::Value),
+                ikey("foo", 200, ValueType::Value),
+                ikey("foobar", 100, ValueType::Value),
+            ),
+        ];
+        for (a, b, expect) in tests {
+            assert_eq!(icmp.separator(a.as_slice(), b.as_slice()), expect);
+        }
+    }
+
+    #[test]
+    fn test_internal_key_shortening_invariants() {
+        let icmp = InternalKeyComparator::new(Arc::new(BytewiseComparator::new()));
+        let mut rng = rand::thread_rng();
+        let mut random_ikey = || {
+            const ALPHABET: [u8; 4] = [0x00, b'a', 0xfe, 0xff];
+            let mut ukey = vec![0xff; rng.gen_range(0, 8)];
+            for _ in 0..rng.gen_range(0, 4) {
+                ukey.push(ALPHABET[rng.gen_range(0, ALPHABET.len())]);
+            }
+            let seq = rng.gen_range(0, 4);
+            InternalKey::new(&Slice::from(ukey.as_slice()), seq, ValueType::Value)
+        };
+        for _ in 0..100_000 {
+            let (mut a, mut b) = (random_ikey(), random_ikey());
+            match icmp.compare(a.data(), b.data()) {
+                Ordering::Equal => continue,
+                Ordering::Greater => std::mem::swap(&mut a, &mut b),
+                Ordering::Less => {}
+            }
+            let sep = icmp.separator(a.data(), b.data());
+            assert_ne!(icmp.compare(a.data(), &sep), Ordering::Greater);
+            assert_eq!(icmp.compare(&sep, b.data()), Ordering::Less);
+            let succ = icmp.successor(a.data());
+            assert_ne!(icmp.compare(a.data(), &succ), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn test_internal_key_short_successor() {
+        let icmp = InternalKeyComparator::new(Arc::new(BytewiseComparator::new()));
+        assert_eq!(
+            icmp.successor(ikey("foo", 100, ValueType::Value).as_slice()),
+            ikey("g", MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK)
+        );
+        let k = InternalKey::new(&Slice::from(&b"\xff\xff"[..]), 100, ValueType::Value);
+        let k = k.data();
+        assert_eq!(icmp.successor(k), k);
+    }
 }
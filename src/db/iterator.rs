@@ -12,12 +12,17 @@
 // limitations under the License.
 
 use crate::db::format::ValueType;
-use crate::db::format::{extract_user_key, ParsedInternalKey, VALUE_TYPE_FOR_SEEK};
+use crate::db::format::{
+    extract_user_key, ParsedInternalKey, MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK,
+};
+use crate::db::range_del::FragmentedRangeTombstones;
 use crate::db::DBImpl;
 use crate::iterator::Iterator;
+use crate::snapshot::Snapshot;
 use crate::util::comparator::Comparator;
 use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
+use crate::version::Version;
 use rand::Rng;
 use std::cmp::Ordering;
 use std::sync::Arc;
@@ -47,18 +52,38 @@ pub struct DBIterator {
     err: Option<WickErr>,
     inner: Box<dyn Iterator>,
     direction: Direction,
+    // The inclusive lower bound and the exclusive upper bound of user keys.
+    // See `ReadOptions::iterate_lower_bound` and `ReadOptions::iterate_upper_bound`
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
     // used for randomly picking a yielded key to record read stats
     bytes_util_read_sampling: u64,
+    // The range tombstones visible at `sequence`. A value covered by a newer
+    // tombstone is skipped like a deletion, which is found by a binary search on
+    // the fragments instead of checking every tombstone.
+    //
+    // The covered entries are still stepped over one by one, since seeking the
+    // inner iterator to the end of a tombstone would also skip the newer values
+    // in the range.
+    range_tombstones: FragmentedRangeTombstones,
 
-    // If we guarantee that the inner iterator's lifetime is not shorter than DBIterator, we
-    // could use Slice instead Vec<u8> for saved_key and saved_value here.
-    // (This seems to be sure because we owns the Arc<DBImpl> and inner iter's lifecycle
-    // is depending on DBImpl)
+    // The saved key and value are copied since the inner iterator might reuse
+    // its buffers (e.g. a block iterator) after being moved.
 
-    // Current key when direction is Reverse
-    saved_key: Slice,
-    // Current value when direction is Reverse
-    saved_value: Slice,
+    // Current key when direction is Reverse or the current entry is merged
+    saved_key: Vec<u8>,
+    // Current value when direction is Reverse or the current entry is merged
+    saved_value: Vec<u8>,
+    // Whether the current entry is folded from the merge operands when direction
+    // is Forward. The inner iterator is positioned after the folded entries then.
+    merged: bool,
+
+    // The version whose files are read by `inner`, which keeps the files from
+    // being deleted by the compactions
+    version: Option<Arc<Version>>,
+    // The snapshot of `sequence` acquired by the iterator itself, which keeps the
+    // entries visible to the iterator from being dropped by the compactions
+    snapshot: Option<Arc<Snapshot>>,
 }
 
 impl Iterator for DBIterator {
@@ -67,6 +92,11 @@ impl Iterator for DBIterator {
     }
 
     fn seek_to_first(&mut self) {
+        if let Some(lower) = self.lower_bound.clone() {
+            // seek to the bound directly so that the blocks before it are never loaded
+            self.seek(&Slice::from(lower.as_slice()));
+            return;
+        }
         self.direction = Direction::Forward;
         self.saved_value.clear();
         self.inner.seek_to_first();
@@ -80,7 +110,24 @@ impl Iterator for DBIterator {
     fn seek_to_last(&mut self) {
         self.direction = Direction::Reverse;
         self.saved_value.clear();
-        self.inner.seek_to_last();
+        match self.upper_bound.clone() {
+            Some(upper) => {
+                // position the inner iter at the last entry before the upper bound
+                let ikey = ParsedInternalKey::new(
+                    Slice::from(upper.as_slice()),
+                    MAX_KEY_SEQUENCE,
+                    VALUE_TYPE_FOR_SEEK,
+                )
+                .encode();
+                self.inner.seek(&Slice::from(ikey.data()));
+                if self.inner.valid() {
+                    self.inner.prev();
+                } else {
+                    self.inner.seek_to_last();
+                }
+            }
+            None => self.inner.seek_to_last(),
+        }
         self.find_prev_user_key();
     }
 
@@ -88,23 +135,38 @@ impl Iterator for DBIterator {
         self.direction = Direction::Forward;
         self.saved_value.clear();
         self.saved_key.clear();
-        let ikey =
-            ParsedInternalKey::new(target.clone(), self.sequence, VALUE_TYPE_FOR_SEEK).encode();
-        self.saved_key = Slice::from(ikey.data());
-        self.inner.seek(&self.saved_key);
+        let target = match &self.lower_bound {
+            Some(lower)
+                if self.ucmp.compare(target.as_slice(), lower.as_slice()) == Ordering::Less =>
+            {
+                Slice::from(lower.as_slice())
+            }
+            _ => target.clone(),
+        };
+        let ikey = ParsedInternalKey::new(target, self.sequence, VALUE_TYPE_FOR_SEEK).encode();
+        self.inner.seek(&Slice::from(ikey.data()));
         if self.inner.valid() {
             self.find_next_user_entry(false)
         } else {
             self.valid = false;
         }
-        self.saved_key.clear(); // avoid dangling ptr
     }
 
     fn next(&mut self) {
         self.valid_or_panic();
         match self.direction {
+            Direction::Forward if self.merged => {
+                // the inner iterator is already after the entries of the saved key
+                self.merged = false;
+                if !self.inner.valid() {
+                    self.valid = false;
+                    self.saved_key.clear();
+                    return;
+                }
+            }
             Direction::Forward => {
-                self.saved_key = extract_user_key(self.inner.key().as_slice());
+                self.saved_key =
+                    Vec::from(extract_user_key(self.inner.key().as_slice()).as_slice());
                 self.inner.next();
                 if !self.inner.valid() {
                     self.valid = false;
@@ -126,7 +188,6 @@ impl Iterator for DBIterator {
                     self.valid = false;
                     self.saved_key.clear();
                 } else {
-
                 }
             }
         }
@@ -138,7 +199,16 @@ impl Iterator for DBIterator {
         // inner iter is pointing at the current entry.  Scan backwards until
         // the key changes so we can use the normal reverse scanning code.
         if self.direction == Direction::Forward {
-            self.saved_key = extract_user_key(self.inner.key().as_slice());
+            if self.merged {
+                // the saved key is kept and the inner iterator might be already exhausted
+                self.merged = false;
+                if !self.inner.valid() {
+                    self.inner.seek_to_last();
+                }
+            } else {
+                self.saved_key =
+                    Vec::from(extract_user_key(self.inner.key().as_slice()).as_slice());
+            }
             loop {
                 self.inner.prev();
                 if !self.inner.valid() {
@@ -163,16 +233,16 @@ impl Iterator for DBIterator {
     fn key(&self) -> Slice {
         self.valid_or_panic();
         match self.direction {
-            Direction::Forward => extract_user_key(self.inner.key().as_slice()),
-            Direction::Reverse => self.saved_key.clone(),
+            Direction::Forward if !self.merged => extract_user_key(self.inner.key().as_slice()),
+            _ => Slice::from(self.saved_key.as_slice()),
         }
     }
 
     fn value(&self) -> Slice {
         self.valid_or_panic();
         match self.direction {
-            Direction::Forward => self.inner.value(),
-            Direction::Reverse => self.saved_value.clone(),
+            Direction::Forward if !self.merged => self.inner.value(),
+            _ => Slice::from(self.saved_value.as_slice()),
         }
     }
 
@@ -191,21 +261,43 @@ impl DBIterator {
         db: Arc<DBImpl>,
         sequence: u64,
         ucmp: Arc<dyn Comparator>,
+        lower_bound: Option<Vec<u8>>,
+        upper_bound: Option<Vec<u8>>,
     ) -> Self {
         Self {
             valid: false,
             db: db.clone(),
-            ucmp,
+            ucmp: ucmp.clone(),
             sequence,
             err: None,
             inner: iter,
             direction: Direction::Forward,
+            lower_bound,
+            upper_bound,
             bytes_util_read_sampling: Self::random_compaction_period(db.options.read_bytes_period),
+            range_tombstones: FragmentedRangeTombstones::new(ucmp.clone()),
             saved_key: Default::default(),
             saved_value: Default::default(),
+            merged: false,
+            version: None,
+            snapshot: None,
         }
     }
 
+    /// Sets the range tombstones visible at the sequence of the iterator, which
+    /// are collected from all the memtables and tables read by the iterator
+    #[inline]
+    pub fn set_range_tombstones(&mut self, range_tombstones: FragmentedRangeTombstones) {
+        self.range_tombstones = range_tombstones;
+    }
+
+    /// Keeps `version` and `snapshot` alive until the iterator is dropped
+    #[inline]
+    pub fn pin(&mut self, version: Arc<Version>, snapshot: Option<Arc<Snapshot>>) {
+        self.version = Some(version);
+        self.snapshot = snapshot;
+    }
+
     #[inline]
     fn valid_or_panic(&self) {
         assert!(self.valid(), "invalid iterator")
@@ -235,17 +327,27 @@ impl DBIterator {
     // user key with sequence limitation. We only need to find the first entry that has a different
     // user key.
     fn find_next_user_entry(&mut self, mut skipping: bool) {
+        self.merged = false;
         loop {
             if let Some(pkey) = self.parse_key() {
+                if !self.within_upper_bound(pkey.user_key.as_slice()) {
+                    // all the following keys are out of the range
+                    break;
+                }
                 if pkey.seq <= self.sequence {
+                    let hidden = skipping
+                        && self
+                            .ucmp
+                            .compare(pkey.user_key.as_slice(), self.saved_key.as_slice())
+                            != Ordering::Greater;
                     match pkey.value_type {
+                        ValueType::Value | ValueType::Merge if self.is_covered(&pkey) => {
+                            // hidden by a range tombstone, the same as a deletion
+                            self.saved_key = Vec::from(pkey.user_key.as_slice());
+                            skipping = true;
+                        }
                         ValueType::Value => {
-                            if skipping
-                                && self
-                                    .ucmp
-                                    .compare(pkey.user_key.as_slice(), self.saved_key.as_slice())
-                                    != Ordering::Greater
-                            {
+                            if hidden {
                                 // not greater than saved_key, so the key is skipped
                             } else {
                                 // Found the next user key
@@ -256,12 +358,25 @@ impl DBIterator {
                                 return;
                             }
                         }
+                        ValueType::Merge => {
+                            if hidden {
+                                // not greater than saved_key, so the key is skipped
+                            } else {
+                                // Found the next user key, whose value is merged from the
+                                // operands and the older entries
+                                self.merge_forward();
+                                return;
+                            }
+                        }
                         ValueType::Deletion => {
                             // Arrange to skip all upcoming entries for this key since
                             // they are hidden by this deletion.
-                            self.saved_key = pkey.user_key.clone();
+                            self.saved_key = Vec::from(pkey.user_key.as_slice());
                             skipping = true;
                         }
+                        ValueType::RangeDeletion => {
+                            // applied by `range_tombstones` collected up front
+                        }
                         _ => { /* ignore the unknown value type */ }
                     }
                 }
@@ -283,11 +398,24 @@ impl DBIterator {
     // iter has to be pointed to the first entry whose user key is less than the current one.
     fn find_prev_user_key(&mut self) {
         let mut value_type = ValueType::Deletion;
+        // The merge operands of `saved_key` from the oldest to the newest, which are
+        // merged on `saved_value` if a value older than them is found
+        let mut operands = vec![];
+        let mut has_base = false;
         if self.inner.valid() {
             loop {
                 if let Some(pkey) = self.parse_key() {
-                    if pkey.seq <= self.sequence {
-                        if value_type == ValueType::Value
+                    if !self.within_lower_bound(pkey.user_key.as_slice()) {
+                        // all the previous keys are out of the range
+                        break;
+                    }
+                    // only values, deletions and merge operands are taken into account here
+                    if pkey.seq <= self.sequence
+                        && (pkey.value_type == ValueType::Value
+                            || pkey.value_type == ValueType::Deletion
+                            || pkey.value_type == ValueType::Merge)
+                    {
+                        if value_type != ValueType::Deletion
                             && self
                                 .ucmp
                                 .compare(pkey.user_key.as_slice(), self.saved_key.as_slice())
@@ -296,17 +424,40 @@ impl DBIterator {
                             // found the key that less than
                             break;
                         }
-                        value_type = pkey.value_type;
+                        let prev_type = value_type;
+                        value_type =
+                            if pkey.value_type != ValueType::Deletion && self.is_covered(&pkey) {
+                                // hidden by a range tombstone, the same as a deletion
+                                ValueType::Deletion
+                            } else {
+                                pkey.value_type
+                            };
                         match value_type {
                             ValueType::Deletion => {
                                 self.saved_key.clear();
                                 self.saved_value.clear();
+                                operands.clear();
+                                has_base = false;
                             }
                             ValueType::Value => {
                                 // record the current key for later comparing
-                                self.saved_key = extract_user_key(self.inner.key().as_slice());
+                                self.saved_key = Vec::from(
+                                    extract_user_key(self.inner.key().as_slice()).as_slice(),
+                                );
                                 // record the current value for later yielding
-                                self.saved_value = self.inner.value();
+                                self.saved_value = Vec::from(self.inner.value().as_slice());
+                                operands.clear();
+                                has_base = true;
+                            }
+                            ValueType::Merge => {
+                                if prev_type == ValueType::Deletion {
+                                    // the first entry of the key, which has no base value
+                                    self.saved_key = Vec::from(
+                                        extract_user_key(self.inner.key().as_slice()).as_slice(),
+                                    );
+                                    self.saved_value.clear();
+                                }
+                                operands.push(Vec::from(self.inner.value().as_slice()));
                             }
                             _ => { /* ignore the unknown value type */ }
                         }
@@ -318,14 +469,105 @@ impl DBIterator {
                 }
             }
         }
-        if value_type != ValueType::Value {
+        match value_type {
+            ValueType::Value => self.valid = true,
+            ValueType::Merge => {
+                operands.reverse();
+                let base = if has_base {
+                    Some(std::mem::take(&mut self.saved_value))
+                } else {
+                    None
+                };
+                self.set_merged(base, operands);
+            }
+            _ => self.valid = false,
+        }
+        if !self.valid {
             // We reach the end of inner iter but didn't find a valid user key
-            self.valid = false;
             self.saved_key.clear();
             self.saved_value.clear();
             self.direction = Direction::Forward;
-        } else {
-            self.valid = true;
+        }
+    }
+
+    // Folds the merge operand at the current position of the inner iterator with the
+    // older entries of the same user key until a value or a deletion is found. The
+    // merged entry is saved and the inner iterator is left after the folded entries.
+    fn merge_forward(&mut self) {
+        self.saved_key = Vec::from(extract_user_key(self.inner.key().as_slice()).as_slice());
+        // the merge operands from the newest to the oldest
+        let mut operands = vec![Vec::from(self.inner.value().as_slice())];
+        let mut base = None;
+        self.inner.next();
+        while self.inner.valid() {
+            let pkey = match self.parse_key() {
+                Some(pkey) => pkey,
+                None => break,
+            };
+            if self
+                .ucmp
+                .compare(pkey.user_key.as_slice(), self.saved_key.as_slice())
+                != Ordering::Equal
+            {
+                break;
+            }
+            match pkey.value_type {
+                ValueType::Value | ValueType::Merge if self.is_covered(&pkey) => break,
+                ValueType::Value => {
+                    base = Some(Vec::from(self.inner.value().as_slice()));
+                    break;
+                }
+                ValueType::Deletion => break,
+                ValueType::Merge => operands.push(Vec::from(self.inner.value().as_slice())),
+                _ => { /* ignore the unknown value type */ }
+            }
+            self.inner.next();
+        }
+        self.set_merged(base, operands);
+        self.merged = self.valid;
+    }
+
+    // Saves the value merged from `operands` (from the newest to the oldest) on `base`
+    // as the current entry of `saved_key`, or records the error of merging them
+    fn set_merged(&mut self, base: Option<Vec<u8>>, operands: Vec<Vec<u8>>) {
+        match self
+            .db
+            .apply_merge_operands(self.saved_key.as_slice(), base.as_deref(), operands)
+        {
+            Ok(value) => {
+                self.saved_value = value.unwrap_or_default();
+                self.valid = true;
+            }
+            Err(e) => {
+                self.err = Some(e);
+                self.valid = false;
+                self.saved_key.clear();
+                self.saved_value.clear();
+            }
+        }
+    }
+
+    // Returns true if the entry is deleted by a newer range tombstone
+    #[inline]
+    fn is_covered(&self, pkey: &ParsedInternalKey) -> bool {
+        self.range_tombstones
+            .covering(pkey.user_key.as_slice())
+            .is_some_and(|t| t.seq > pkey.seq)
+    }
+
+    #[inline]
+    fn within_lower_bound(&self, ukey: &[u8]) -> bool {
+        match &self.lower_bound {
+            Some(lower) => self.ucmp.compare(ukey, lower.as_slice()) != Ordering::Less,
+            None => true,
+        }
+    }
+
+    #[inline]
+    fn within_upper_bound(&self, ukey: &[u8]) -> bool {
+        match &self.upper_bound {
+            Some(upper) => self.ucmp.compare(ukey, upper.as_slice()) == Ordering::Less,
+            None => true,
         }
     }
 
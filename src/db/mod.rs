@@ -11,43 +11,66 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod event_listener;
 pub mod filename;
 pub mod format;
 pub mod iterator;
+pub mod namespace;
+pub mod range_del;
+pub mod repair;
+pub mod statistics;
+pub mod subscription;
 
 use crate::batch::{WriteBatch, HEADER_SIZE};
-use crate::compaction::{Compaction, CompactionInputsRelation};
+use crate::compaction::{Compaction, CompactionInputsRelation, ManualCompaction};
+use crate::db::event_listener::{FlushJobInfo, TableFileInfo};
 use crate::db::filename::{generate_filename, parse_filename, update_current, FileType};
 use crate::db::format::{
-    InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey, ValueType,
+    extract_user_key, InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey, ValueType,
+    MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK,
 };
 use crate::db::iterator::DBIterator;
-use crate::iterator::{Iterator, MergingIterator};
+use crate::db::namespace::Namespace;
+use crate::db::range_del::{
+    max_covering_tombstone_seq, tombstone_end, tombstone_largest_key, FragmentedRangeTombstones,
+    RangeTombstone,
+};
+use crate::db::repair::{RepairReport, Repairer};
+use crate::db::statistics::{Statistics, StatisticsCounters};
+use crate::db::subscription::{Subscribers, Subscription};
+use crate::iterator::{Iterator, MergingIterator, PrefixIterator};
 use crate::mem::{MemTable, MemoryTable};
-use crate::options::{Options, ReadOptions, WriteOptions};
+use crate::options::{
+    CompactRangeOptions, CompressionType, IngestExternalFileOptions, Options, ReadOptions,
+    WALRecoveryMode, WriteOptions,
+};
 use crate::record::reader::Reader;
 use crate::record::writer::Writer;
-use crate::snapshot::Snapshot;
-use crate::sstable::table::TableBuilder;
+use crate::record::BLOCK_SIZE;
+use crate::snapshot::{Snapshot, SnapshotHandle};
+use crate::sstable::table::{Table, TableBuilder};
 use crate::storage::{File, Storage};
 use crate::table_cache::TableCache;
+use crate::util::compaction_filter::Decision;
+use crate::util::comparator::Comparator;
 use crate::util::reporter::LogReporter;
 use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
-use crate::version::version_edit::{FileMetaData, VersionEdit};
+use crate::version::version_edit::{unix_time_secs, FileMetaData, VersionEdit};
 use crate::version::version_set::VersionSet;
-use crossbeam_channel::{Receiver, Sender};
+use crate::version::Version;
+use crossbeam_channel::{Receiver, SendError, Sender};
 use crossbeam_utils::sync::ShardedLock;
 use std::cell::RefCell;
 use std::cmp::Ordering as CmpOrdering;
 use std::collections::vec_deque::VecDeque;
 use std::mem;
-use std::path::MAIN_SEPARATOR;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 /// A `DB` is a persistent ordered map from keys to values.
 /// A `DB` is safe for concurrent access from multiple threads without
@@ -58,8 +81,17 @@ pub trait DB {
     fn put(&self, write_opt: WriteOptions, key: Slice, value: Slice) -> Result<()>;
 
     /// `get` gets the value for the given key. It returns `None` if the DB
-    /// does not contain the key.
-    fn get(&self, read_opt: ReadOptions, key: Slice) -> Result<Option<Slice>>;
+    /// does not contain the key, and returns `Status::NotSupported` if there are
+    /// merge operands of the key but no `merge_operator` is set in `Options`.
+    fn get(&self, read_opt: ReadOptions, key: Slice) -> Result<Option<Vec<u8>>>;
+
+    /// `multi_get` gets the values for the given keys from a same snapshot of the DB.
+    /// The results are in the same order as `keys`.
+    fn multi_get(&self, read_opt: ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>>;
+
+    /// `merge` merges the operand `value` into the existing value of the given key.
+    /// It returns `Status::NotSupported` if no `merge_operator` is set in `Options`.
+    fn merge(&self, write_opt: WriteOptions, key: Slice, value: Slice) -> Result<()>;
 
     /// Return an iterator over the contents of the database.
     fn iter(&self, read_opt: ReadOptions) -> Box<dyn Iterator>;
@@ -79,8 +111,24 @@ pub trait DB {
     /// `destroy` shuts down the current WickDB and delete all relative files and the db directory.
     fn destroy(&mut self) -> Result<()>;
 
-    /// Acquire a `Snapshot` for reading DB
-    fn get_snapshot(&self) -> Arc<Snapshot>;
+    /// Acquire a `Snapshot` for reading DB. The snapshot is released when the
+    /// returned handle is dropped.
+    fn get_snapshot(&self) -> SnapshotHandle;
+
+    /// Releases the snapshot acquired by `get_snapshot` explicitly
+    fn release_snapshot(&self, snapshot: SnapshotHandle);
+}
+
+/// The result of `WickDB::get_detailed`
+#[derive(Clone, Debug, PartialEq)]
+pub enum GetResult {
+    /// The value of the key, which is merged from the merge operands if any
+    Found(Vec<u8>),
+    /// The newest entry of the key visible to the read is a deletion or a range
+    /// tombstone covering the key
+    Deleted,
+    /// There is no entry of the key visible to the read
+    NotPresent,
 }
 
 /// The wrapper of `DBImpl` for concurrency control.
@@ -96,38 +144,102 @@ impl DB for WickDB {
         self.write(options, batch)
     }
 
-    fn get(&self, options: ReadOptions, key: Slice) -> Result<Option<Slice>> {
+    fn get(&self, options: ReadOptions, key: Slice) -> Result<Option<Vec<u8>>> {
         self.inner.get(options, key)
     }
 
+    fn multi_get(&self, options: ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        self.inner.multi_get(options, keys)
+    }
+
+    fn merge(&self, options: WriteOptions, key: Slice, value: Slice) -> Result<()> {
+        if self.inner.options.merge_operator.is_none() {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("merge operator is not set"),
+            ));
+        }
+        let mut batch = WriteBatch::new();
+        batch.merge(key.as_slice(), value.as_slice());
+        self.write(options, batch)
+    }
+
     fn iter(&self, read_opt: ReadOptions) -> Box<dyn Iterator> {
         let ucmp = self.inner.internal_comparator.user_comparator.clone();
-        let sequence = if let Some(snapshot) = &read_opt.snapshot {
-            snapshot.sequence()
-        } else {
-            self.inner.versions.lock().unwrap().get_last_sequence()
+        // The sequence is acquired before the memtables so that the writes into a
+        // new memtable are never visible. Without a given snapshot, a snapshot is
+        // held by the iterator to protect the visible entries from the compactions.
+        let (sequence, snapshot) = match &read_opt.snapshot {
+            Some(snapshot) => (snapshot.sequence_number(), None),
+            None => {
+                let snapshot = self.inner.get_snapshot();
+                (snapshot.sequence_number(), Some(snapshot))
+            }
         };
+        let lower_bound = read_opt.iterate_lower_bound.clone();
+        let upper_bound = read_opt.iterate_upper_bound.clone();
+        let total_order_seek = read_opt.total_order_seek;
         let mut children = vec![];
-        children.push(Rc::new(RefCell::new(self.inner.mem.read().unwrap().iter())));
-        if let Some(im_mem) = self.inner.im_mem.read().unwrap().as_ref() {
-            children.push(Rc::new(RefCell::new(im_mem.iter())));
+        let mut tombstones = vec![];
+        {
+            let mem = self.inner.mem.read().unwrap();
+            children.push(Rc::new(RefCell::new(mem.iter())));
+            tombstones.append(&mut mem.range_tombstones());
         }
-        let mut table_iters = self
-            .inner
-            .versions
-            .lock()
-            .unwrap()
-            .current_iters(Rc::new(read_opt), self.inner.table_cache.clone());
+        for im_mem in self.inner.im_mem.read().unwrap().iter().rev() {
+            children.push(Rc::new(RefCell::new(im_mem.mem.iter())));
+            tombstones.append(&mut im_mem.mem.range_tombstones());
+        }
+        // The version is acquired after the memtables so that the entries of a
+        // memtable flushed in the meantime are never missed
+        let (version, mut table_iters) = {
+            let versions = self.inner.versions.lock().unwrap();
+            (
+                versions.current(),
+                versions.current_iters(Rc::new(read_opt), self.inner.table_cache.clone()),
+            )
+        };
         for iter in table_iters.drain(..) {
             children.push(Rc::new(RefCell::new(iter)));
         }
+        for level in 0..self.inner.options.max_levels as usize {
+            for f in version
+                .get_level_files(level)
+                .iter()
+                .filter(|f| f.has_range_tombstones)
+            {
+                // the errors are reported by the table iterators
+                if let Ok(ts) =
+                    self.inner
+                        .table_cache
+                        .range_tombstones(level, f.number, f.file_size)
+                {
+                    tombstones.extend_from_slice(ts.as_slice());
+                }
+            }
+        }
+        let mut range_tombstones = FragmentedRangeTombstones::new(ucmp.clone());
+        for t in tombstones.drain(..).filter(|t| t.seq <= sequence) {
+            range_tombstones.add(t);
+        }
         let iter = MergingIterator::new(self.inner.internal_comparator.clone(), children);
-        Box::new(DBIterator::new(
+        let mut db_iter = DBIterator::new(
             Box::new(iter),
             self.inner.clone(),
             sequence,
             ucmp,
-        ))
+            lower_bound,
+            upper_bound,
+        );
+        db_iter.set_range_tombstones(range_tombstones);
+        db_iter.pin(version, snapshot);
+        let db_iter = Box::new(db_iter);
+        match &self.inner.options.prefix_extractor {
+            Some(extractor) if !total_order_seek => {
+                Box::new(PrefixIterator::new(db_iter, extractor.clone()))
+            }
+            _ => db_iter,
+        }
     }
 
     fn delete(&self, options: WriteOptions, key: Slice) -> Result<()> {
@@ -154,24 +266,45 @@ impl DB for WickDB {
         db.options.env.remove_dir(&db.db_name, true)
     }
 
-    fn get_snapshot(&self) -> Arc<Snapshot> {
-        self.inner.get_snapshot()
+    fn get_snapshot(&self) -> SnapshotHandle {
+        SnapshotHandle::new(self.inner.get_snapshot())
+    }
+
+    fn release_snapshot(&self, mut snapshot: SnapshotHandle) {
+        snapshot.release();
+        self.inner.versions.lock().unwrap().snapshots.gc();
     }
 }
 
 impl WickDB {
     /// Create a new WickDB
     pub fn open_db(mut options: Options, db_name: String) -> Result<Self> {
-        let env = options.env.clone();
-        options.initialize(db_name.clone());
+        options.validate()?;
+        options.initialize(db_name.clone(), false);
         let mut db = DBImpl::new(options, db_name.clone());
-        let (mut edit, should_save_manifest) = db.recover()?;
+        let (mut edit, mut should_save_manifest) = db.recover()?;
         let mut versions = db.versions.lock().unwrap();
+        let initial_sequence = db.options.initial_sequence;
+        if initial_sequence > 0 {
+            let last_sequence = versions.get_last_sequence();
+            if initial_sequence < last_sequence {
+                return Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some("initial_sequence is less than the last sequence of the DB"),
+                )
+                .with_context(format!(
+                    "initial_sequence {}, last sequence {}",
+                    initial_sequence, last_sequence
+                )));
+            }
+            if initial_sequence > last_sequence {
+                versions.set_last_sequence(initial_sequence);
+                should_save_manifest = true;
+            }
+        }
         if versions.record_writer.is_none() {
             let new_log_number = versions.inc_next_file_number();
-            let log_file =
-                env.create(generate_filename(&db_name, FileType::Log, new_log_number).as_str())?;
-            versions.record_writer = Some(Writer::new(log_file));
+            versions.record_writer = Some(db.new_log_writer(&mut versions, new_log_number)?);
             edit.set_log_number(new_log_number);
             versions.set_log_number(new_log_number);
         }
@@ -191,15 +324,296 @@ impl WickDB {
         Ok(wick_db)
     }
 
+    /// Opens an existing DB in read-only mode. The records in the log files are
+    /// replayed into the memtable, and no file in the DB is created or modified,
+    /// so that multiple processes are able to open the same DB at the same time.
+    /// No compaction runs in a read-only DB, and all the writes are rejected with
+    /// `Status::NotSupported`.
+    pub fn open_read_only(options: Options, db_name: String) -> Result<Self> {
+        Self::open_read_only_db(options, db_name, false)
+    }
+
+    /// Opens an existing DB as a secondary instance, which is a read-only DB that
+    /// is able to catch up with the primary DB opened by another process with
+    /// `try_catch_up_with_primary`.
+    pub fn open_as_secondary(options: Options, db_name: String) -> Result<Self> {
+        Self::open_read_only_db(options, db_name, true)
+    }
+
+    /// Rebuilds the manifest of the DB from the tables in `db_name` when the manifest
+    /// is lost or corrupted. The logs are converted into new tables, and the tables
+    /// are placed in the bottommost level unless they overlap with any other one,
+    /// in which case they're placed in level 0.
+    ///
+    /// Nothing is deleted: the unreadable tables, the original ones of the tables
+    /// rewritten without their unreadable entries, the logs and the old manifests are
+    /// moved into the `lost` directory of the DB. The DB must not be opened during
+    /// the repair.
+    pub fn repair(mut options: Options, db_name: String) -> Result<RepairReport> {
+        options.validate()?;
+        options.initialize(db_name.clone(), false);
+        Repairer::new(Arc::new(options), db_name).run()
+    }
+
+    /// Catches up with the primary DB: the new tables in the MANIFEST and the
+    /// records in the log files written by the primary since the last catch-up
+    /// become visible to the reads started after this call. The iterators created
+    /// before keep reading the old data, but a table deleted by the primary since
+    /// then fails the reading if it's not opened yet.
+    ///
+    /// Returns `Status::NotSupported` if the DB is not opened by `open_as_secondary`.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        self.inner.try_catch_up_with_primary()
+    }
+
+    fn open_read_only_db(mut options: Options, db_name: String, secondary: bool) -> Result<Self> {
+        options.validate()?;
+        // the logs and the manifest are never reused to be written
+        options.reuse_logs = false;
+        options.initialize(db_name.clone(), true);
+        let mut db = DBImpl::new(options, db_name);
+        db.read_only = true;
+        db.secondary = secondary;
+        db.recover()?;
+        Ok(WickDB {
+            inner: Arc::new(db),
+        })
+    }
+
+    /// Gets the value for the given key with the sequence number at which it was
+    /// written. For a merged value, the sequence number is the one of the newest
+    /// merge operand. Returns `None` if the key is not found or deleted, and
+    /// `get_latest_seq` tells them apart.
+    pub fn get_with_seq(
+        &self,
+        read_opt: ReadOptions,
+        key: Slice,
+    ) -> Result<Option<(Vec<u8>, u64)>> {
+        let (value, seq) = self.inner.get_with_seq(read_opt, key)?;
+        Ok(value.map(|v| (v, seq.unwrap())))
+    }
+
+    /// Returns the sequence number of the newest entry of the given key visible to
+    /// the read, including the deletions and the range tombstones covering the key.
+    /// Returns `None` if the key has never been written or all its entries have been
+    /// dropped by compactions.
+    pub fn get_latest_seq(&self, read_opt: ReadOptions, key: Slice) -> Result<Option<u64>> {
+        Ok(self.inner.get_with_seq(read_opt, key)?.1)
+    }
+
+    /// Same as `get` except that a key which is deleted is told apart from a key
+    /// which is not present. A key is deleted as long as its deletion or a range
+    /// tombstone covering it is kept, and is not present once they have been dropped
+    /// by compactions.
+    pub fn get_detailed(&self, read_opt: ReadOptions, key: Slice) -> Result<GetResult> {
+        Ok(match self.inner.get_with_seq(read_opt, key)? {
+            (Some(value), _) => GetResult::Found(value),
+            (None, Some(_)) => GetResult::Deleted,
+            (None, None) => GetResult::NotPresent,
+        })
+    }
+
+    /// Returns the base value and the merge operands of the given key visible to the
+    /// read without merging them. The operands are ordered from the oldest to the
+    /// newest, and the base value is `None` if the key is deleted or never put before
+    /// the operands. The operands already merged by compactions are merged into the
+    /// base value then. Returns a `NotSupported` error if no `merge_operator` is set.
+    pub fn get_merge_operands(
+        &self,
+        read_opt: ReadOptions,
+        key: Slice,
+    ) -> Result<(Option<Vec<u8>>, Vec<Vec<u8>>)> {
+        if self.inner.options.merge_operator.is_none() {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("merge operator is not set"),
+            ));
+        }
+        let (base, mut operands, _) = self.inner.get_unmerged(read_opt, key.as_slice())?;
+        operands.reverse();
+        Ok((base, operands))
+    }
+
+    /// Returns false only if the given key definitely does not exist, which is
+    /// told by the memtables and the filters of the sstables without reading any
+    /// data block. Returns true if the key may exist.
+    pub fn key_may_exist(&self, read_opt: ReadOptions, key: Slice) -> bool {
+        self.inner.key_may_exist(read_opt, key.as_slice())
+    }
+
+    /// Returns the approximate sizes of the data in the given user key ranges
+    /// `[start, limit)`. The sizes of the sstables are computed by the block handles
+    /// in their index blocks, and the entries in the memtables are added as a rough
+    /// approximation. The results are in the same order as `ranges`.
+    pub fn get_approximate_sizes(&self, ranges: &[(&[u8], &[u8])]) -> Vec<u64> {
+        self.inner.get_approximate_sizes(ranges)
+    }
+
+    /// Returns the value of a DB property, or `None` if the property is unknown.
+    /// The valid property names include:
+    ///
+    /// * `wickdb.num-files-at-level<N>`: the number of files at level `<N>`
+    /// * `wickdb.total-sst-files-size`: the total size in bytes of all the live sstables
+    /// * `wickdb.mem-table-size`: the approximate memory in bytes used by the memtable
+    ///   and the immutable memtables waiting for being flushed
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        self.inner.get_property(name)
+    }
+
+    /// Compacts the underlying storage for the user key range `[start, end]`. `None`
+    /// is treated as a key before (or after) all the keys in the DB. In particular,
+    /// the entries deleted or overwritten are discarded and the data is rearranged
+    /// to reduce the cost of accessing it. This is useful to reclaim the space after
+    /// a large `delete_range`.
+    ///
+    /// The memtable is flushed first and the overlapping files are compacted down
+    /// through the levels, then the files of the bottommost level overlapping the
+    /// range are rewritten in place. Blocks until the compaction of the range completes.
+    pub fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        self.inner
+            .compact_range(start, end, &CompactRangeOptions::default())
+    }
+
+    /// Same as `compact_range` except that the progress is reported and the
+    /// compaction is able to be canceled by the given `CompactRangeOptions`.
+    /// Returns a `Status::Aborted` error if it's canceled.
+    pub fn compact_range_with_options(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: CompactRangeOptions,
+    ) -> Result<()> {
+        self.inner.compact_range(start, end, &options)
+    }
+
+    /// Switches the memtable to an immutable one and flushes it into a level-0 table.
+    /// If `wait` is true, blocks until all the immutable memtables are flushed.
+    /// Otherwise returns once the memtable is switched, while the flush goes on in
+    /// the background.
+    ///
+    /// It's a no-op if the memtable is empty, and the memtables waiting for being
+    /// flushed are not flushed again.
+    pub fn flush(&self, wait: bool) -> Result<()> {
+        self.inner.flush(wait)
+    }
+
+    /// Reads all the live sstables and validates the checksums of all their blocks
+    /// as well as the footer magic. Returns the first error found, which is usually
+    /// a `Status::Corruption` with the name of the offending file.
+    pub fn verify_checksums(&self) -> Result<()> {
+        self.inner.verify_checksums()
+    }
+
+    /// Creates a checkpoint of the DB in the directory `dest`, which is able to be
+    /// opened as an independent DB. The memtable is flushed first so that the
+    /// checkpoint contains all the writes finished before this call. The tables
+    /// are hard linked by `Storage::link` if possible.
+    ///
+    /// Returns `Status::InvalidArgument` if `dest` already exists.
+    pub fn create_checkpoint(&self, dest: &Path) -> Result<()> {
+        match dest.to_str() {
+            Some(dest) => self.inner.create_checkpoint(dest),
+            None => Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("checkpoint directory is not valid unicode"),
+            )),
+        }
+    }
+
+    /// Ingests the sstables built by `ExternalTableBuilder` into the DB without
+    /// going through the WAL and the memtable. Every file gets a new sequence number
+    /// so that its entries override the existing ones, and is placed at the deepest
+    /// level whose upper levels don't overlap its key range. The files overlapping
+    /// the existing level-0 files are placed at level 0.
+    ///
+    /// The tables are rewritten into new files of the DB and the external files are
+    /// left untouched. Either all the files are ingested or none of them if it fails.
+    pub fn ingest_external_files(
+        &self,
+        files: &[PathBuf],
+        opts: IngestExternalFileOptions,
+    ) -> Result<()> {
+        self.inner.ingest_external_files(files, opts)
+    }
+
+    /// Removes the table files whose user keys are all in `[start, end)` at every
+    /// level without rewriting anything, e.g. after the range is migrated to another
+    /// DB. The files partially overlapping the range and the keys in the memtables
+    /// are left untouched, so some keys in the range may remain. The input files of
+    /// a running compaction are skipped as well.
+    ///
+    /// The removals are committed by a single manifest record, and the files are
+    /// deleted after that once they are not used by any iterator.
+    ///
+    /// NOTICE: The deletions and the range tombstones in the removed files don't
+    /// cover the older keys any longer, so the keys deleted by a range tombstone
+    /// starting in `[start, end)` but ending after `end` may reappear.
+    pub fn delete_files_in_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_files_in_range(start, end)
+    }
+
+    /// Warms up the block cache by reading the data blocks of the tables overlapping
+    /// the user keys `[start, end)`, e.g. for a known hot range after a failover, and
+    /// returns the number of the blocks read from the files. The tables are read from
+    /// the newest level to the oldest, and the memtables are not touched.
+    ///
+    /// The blocks are read by the block reader of the table iterators with `read_opt`,
+    /// and kept in the cache only if `read_opt.fill_cache` is set. The blocks kept by the call,
+    /// including the ones already cached, take at most `max_bytes` and never more than
+    /// the capacity of the block cache, so the blocks loaded first are not evicted by
+    /// the later ones. The warming stops once the next block doesn't fit.
+    ///
+    /// If `canceled` is non-null, the warming stops once the flag is set and fails
+    /// with `Status::Aborted`. The blocks loaded before are kept in the cache.
+    pub fn warm_cache(
+        &self,
+        read_opt: ReadOptions,
+        start: &[u8],
+        end: &[u8],
+        max_bytes: usize,
+        canceled: Option<Arc<AtomicBool>>,
+    ) -> Result<usize> {
+        self.inner
+            .warm_cache(read_opt, (start, end), max_bytes, canceled.as_deref())
+    }
+
+    /// Returns the `Namespace` of the given name in this DB. See `Namespace` for how
+    /// its keys are prefixed.
+    pub fn namespace(&self, name: &[u8]) -> Namespace {
+        Namespace::new(self.clone(), name)
+    }
+
+    /// Subscribes the batches committed from now on. At most `capacity` batches
+    /// are buffered for the subscription, and the following ones are dropped until
+    /// the buffered ones are received. See `Subscription` for details.
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        self.inner.subscribers.subscribe(capacity)
+    }
+
+    /// Clears the background error which stops the writes and the background work,
+    /// e.g. after the disk space is freed, and then flushes the memtables. Returns
+    /// the error of the flush if it fails again. Does nothing if there is no
+    /// background error.
+    pub fn resume(&self) -> Result<()> {
+        self.inner.resume()
+    }
+
+    /// Returns a snapshot of the counters of the flushes, the compactions and the
+    /// write stalls since the DB is opened
+    pub fn statistics(&self) -> Statistics {
+        self.inner.statistics.snapshot()
+    }
+
     // The thread take batches from the queue and apples them into memtable and WAL.
     //
     // Steps:
     // 1. Grouping the batches in the queue into a big enough batch
     // 2. Make sure there is enough space in the memtable. This might trigger a minor compaction
     //    or even several major compaction.
-    // 3. Write into WAL (.log file)
+    // 3. Write into WAL (.log file) unless `disable_wal` is set
     // 4. Write into Memtable
-    // 5. Update sequence of version set
+    // 5. Publish the batch to the subscribers
+    // 6. Update sequence of version set
     fn process_batch(&self) {
         let db = self.inner.clone();
         thread::spawn(move || {
@@ -224,22 +638,33 @@ impl WickDB {
                 let mut signals = vec![];
                 signals.push(first.signal.clone());
                 let mut grouped = first;
+                // The batches in the group are kept to be inserted into the memtable
+                // concurrently
+                let concurrent = db.options.allow_concurrent_memtable_write
+                    && db.mem.read().unwrap().allow_concurrent_insert();
+                let mut batches = vec![];
 
-                // Group several batches from queue
+                // Group several batches from queue into one WAL record
                 while !queue.is_empty() {
                     let current = queue.pop_front().unwrap();
-                    if current.options.sync && !grouped.options.sync {
-                        // Do not include a sync write into a batch handled by a non-sync write.
+                    size += current.batch.approximate_size();
+                    // Do not make batch too big, or mix the writes with and without
+                    // the WAL. The batch is left to the next group.
+                    if size > max_size || current.options.disable_wal != grouped.options.disable_wal
+                    {
                         queue.push_front(current);
                         break;
                     }
-                    size += current.batch.approximate_size();
-                    if size > max_size {
-                        // Do not make batch too big
-                        break;
+                    // The whole group is synced if any of the writes requires it
+                    grouped.options.sync |= current.options.sync;
+                    if concurrent && batches.is_empty() {
+                        batches.push(grouped.batch.clone());
                     }
-                    grouped.batch.append(current.batch);
+                    grouped.batch.append(&current.batch);
                     signals.push(current.signal.clone());
+                    if concurrent {
+                        batches.push(current.batch);
+                    }
                 }
                 // Release the queue lock
                 mem::drop(queue);
@@ -247,10 +672,14 @@ impl WickDB {
                     Ok(mut versions) => {
                         let mut last_seq = versions.get_last_sequence();
                         grouped.batch.set_sequence(last_seq + 1);
-                        last_seq += u64::from(grouped.batch.get_count());
+                        last_seq += u64::from(grouped.batch.count());
                         // must initialize the WAL writer after `make_room_for_write`
                         let writer = versions.record_writer.as_mut().unwrap();
-                        let mut status = writer.add_record(&Slice::from(grouped.batch.data()));
+                        let mut status = if grouped.options.disable_wal {
+                            Ok(())
+                        } else {
+                            writer.add_record(&Slice::from(grouped.batch.data()))
+                        };
                         let mut sync_err = false;
                         if status.is_ok() && grouped.options.sync {
                             status = writer.sync();
@@ -259,12 +688,23 @@ impl WickDB {
                             }
                         }
                         if status.is_ok() {
-                            let memtable = db.mem.read().unwrap();
-                            status = grouped.batch.insert_into(&*memtable);
+                            status = if batches.is_empty() {
+                                let memtable = db.mem.read().unwrap();
+                                grouped.batch.insert_into(&memtable)
+                            } else {
+                                db.insert_concurrently(
+                                    batches,
+                                    signals.as_slice(),
+                                    grouped.batch.get_sequence(),
+                                )
+                            };
+                        }
+                        if status.is_ok() {
+                            db.subscribers.publish(&grouped.batch);
                         }
 
                         for signal in signals.iter() {
-                            if let Err(e) = signal.send(status.clone()) {
+                            if let Err(e) = signal.send(BatchSignal::Done(status.clone())) {
                                 error!(
                                     "[process batch] Fail sending finshing signal to waiting batch: {}", e
                                 )
@@ -282,7 +722,7 @@ impl WickDB {
                     }
                     Err(e) => {
                         for signal in signals.iter() {
-                            if let Err(e) = signal.send(Err(e.clone())) {
+                            if let Err(e) = signal.send(BatchSignal::Done(Err(e.clone()))) {
                                 error!(
                                     "[process batch] Fail sending finishing signal to waiting batch: {}", e
                                 )
@@ -307,6 +747,7 @@ impl WickDB {
                     // Non more background work after a background error
                 } else {
                     db.background_compaction();
+                    db.maybe_auto_resume();
                 }
                 db.background_compaction_scheduled
                     .store(false, Ordering::Release);
@@ -335,6 +776,10 @@ pub struct DBImpl {
     // The physical path of wickdb
     db_name: String,
     db_lock: Option<Box<dyn File>>,
+    // Whether the DB is opened by `WickDB::open_read_only` or `WickDB::open_as_secondary`
+    read_only: bool,
+    // Whether the DB is opened by `WickDB::open_as_secondary`
+    secondary: bool,
 
     /*
      * Fields for write batch scheduling
@@ -358,11 +803,18 @@ pub struct DBImpl {
     // all relative methods are using immutable borrowing,
     // we still need to mutate the field `mem` and `im_mem` in few situations.
     mem: ShardedLock<MemTable>,
-    im_mem: ShardedLock<Option<MemTable>>, // iff the memtable is compacted
+    // the immutable memtables waiting for being flushed from the oldest to the newest
+    im_mem: ShardedLock<VecDeque<ImmutableMemTable>>,
     // Have we encountered a background error in paranoid mode
     bg_error: RwLock<Option<WickErr>>,
+    // The number of automatic retries of the failed background work in a row
+    bg_error_resume_count: AtomicUsize,
     // Whether the db is closing
     is_shutting_down: AtomicBool,
+    // The subscribers of the committed batches
+    subscribers: Subscribers,
+    // The counters of the flushes, the compactions and the write stalls
+    statistics: StatisticsCounters,
 }
 
 unsafe impl Sync for DBImpl {}
@@ -388,6 +840,8 @@ impl DBImpl {
             options: o.clone(),
             db_name: db_name.clone(),
             db_lock: None,
+            read_only: false,
+            secondary: false,
             batch_queue: Mutex::new(VecDeque::new()),
             process_batch_sem: Condvar::new(),
             table_cache: Arc::new(TableCache::new(
@@ -399,17 +853,44 @@ impl DBImpl {
             background_work_finished_signal: Condvar::new(),
             background_compaction_scheduled: AtomicBool::new(false),
             do_compaction: crossbeam_channel::unbounded(),
-            mem: ShardedLock::new(MemTable::new(icmp)),
-            im_mem: ShardedLock::new(None),
+            mem: ShardedLock::new(MemTable::with_factory(icmp, o.memtable_factory.as_ref())),
+            im_mem: ShardedLock::new(VecDeque::new()),
             bg_error: RwLock::new(None),
+            bg_error_resume_count: AtomicUsize::new(0),
             is_shutting_down: AtomicBool::new(false),
+            subscribers: Subscribers::default(),
+            statistics: StatisticsCounters::new(o.max_levels as usize),
         }
     }
     fn get_snapshot(&self) -> Arc<Snapshot> {
         self.versions.lock().unwrap().new_snapshot()
     }
 
-    fn get(&self, options: ReadOptions, key: Slice) -> Result<Option<Slice>> {
+    fn get(&self, options: ReadOptions, key: Slice) -> Result<Option<Vec<u8>>> {
+        self.get_with_seq(options, key).map(|(value, _)| value)
+    }
+
+    // Returns the value of `key` and the sequence number of the newest entry of `key`
+    // visible to the read, which is `None` if there is no such entry
+    fn get_with_seq(
+        &self,
+        options: ReadOptions,
+        key: Slice,
+    ) -> Result<(Option<Vec<u8>>, Option<u64>)> {
+        let (base, operands, newest_seq) = self.get_unmerged(options, key.as_slice())?;
+        let value = self.apply_merge_operands(key.as_slice(), base.as_deref(), operands)?;
+        Ok((value, newest_seq))
+    }
+
+    // Returns the base value, the merge operands from the newest to the oldest and the
+    // sequence number of the newest entry of `key` visible to the read. The base value
+    // is `None` if the oldest visible entry is a deletion or there is no such entry.
+    #[allow(clippy::type_complexity)]
+    fn get_unmerged(
+        &self,
+        options: ReadOptions,
+        key: &[u8],
+    ) -> Result<(Option<Vec<u8>>, Vec<Vec<u8>>, Option<u64>)> {
         if self.is_shutting_down.load(Ordering::Acquire) {
             return Err(WickErr::new(
                 Status::NotSupported,
@@ -417,822 +898,6621 @@ impl DBImpl {
             ));
         }
         let snapshot = match &options.snapshot {
-            Some(snapshot) => snapshot.sequence(),
+            Some(snapshot) => snapshot.sequence_number(),
             None => self.versions.lock().unwrap().get_last_sequence(),
         };
-        let lookup_key = LookupKey::new(key.as_slice(), snapshot);
+        let lookup_key = LookupKey::new(key, snapshot);
+        // the merge operands found from the newest to the oldest
+        let mut operands = vec![];
+        let mut newest_seq = None;
         // search the memtable
-        if let Some(result) = self.mem.read().unwrap().get(&lookup_key) {
-            match result {
-                Ok(value) => return Ok(Some(value)),
-                // mem.get only returns Err() when it get a Deletion of the key
-                Err(_) => return Ok(None),
-            }
-        }
-        // search the immutable memtable
-        if let Some(im_mem) = self.im_mem.read().unwrap().as_ref() {
-            if let Some(result) = im_mem.get(&lookup_key) {
-                match result {
-                    Ok(value) => return Ok(Some(value)),
-                    Err(_) => return Ok(None),
-                }
+        if let Some(result) = self.mem.read().unwrap().get_with_merge_operands(
+            &lookup_key,
+            &mut operands,
+            &mut newest_seq,
+        ) {
+            // mem.get only returns Err() when it get a Deletion of the key
+            return Ok((
+                result.ok().map(|v| v.as_slice().to_vec()),
+                operands,
+                newest_seq,
+            ));
+        }
+        // search the immutable memtables from the newest one
+        for im_mem in self.im_mem.read().unwrap().iter().rev() {
+            if let Some(result) =
+                im_mem
+                    .mem
+                    .get_with_merge_operands(&lookup_key, &mut operands, &mut newest_seq)
+            {
+                return Ok((
+                    result.ok().map(|v| v.as_slice().to_vec()),
+                    operands,
+                    newest_seq,
+                ));
             }
         }
         let current = self.versions.lock().unwrap().current();
-        let (value, seek_stats) = current.get(options, lookup_key, self.table_cache.clone())?;
+        let (value, seek_stats) = current.get(
+            Rc::new(options),
+            &lookup_key,
+            self.table_cache.clone(),
+            &mut operands,
+            &mut newest_seq,
+        )?;
         if current.update_stats(seek_stats) {
             self.maybe_schedule_compaction()
         }
-        Ok(value)
+        Ok((value, operands, newest_seq))
     }
 
-    // Record a sample of bytes read at the specified internal key
-    // Might schedule a background compaction.
-    fn record_read_sample(&self, key: Slice) {
-        if self
-            .versions
-            .lock()
-            .unwrap()
-            .current()
-            .record_read_sample(key)
-        {
-            self.maybe_schedule_compaction()
+    fn key_may_exist(&self, options: ReadOptions, key: &[u8]) -> bool {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return true;
+        }
+        let snapshot = match &options.snapshot {
+            Some(snapshot) => snapshot.sequence_number(),
+            None => self.versions.lock().unwrap().get_last_sequence(),
+        };
+        let lookup_key = LookupKey::new(key, snapshot);
+        let mut operands = vec![];
+        let mut newest_seq = None;
+        // A value found in the memtables means the key exists, and a deletion means
+        // the key is absent unless there are merge operands on it
+        if let Some(result) = self.mem.read().unwrap().get_with_merge_operands(
+            &lookup_key,
+            &mut operands,
+            &mut newest_seq,
+        ) {
+            return result.is_ok() || !operands.is_empty();
+        }
+        for im_mem in self.im_mem.read().unwrap().iter().rev() {
+            if let Some(result) =
+                im_mem
+                    .mem
+                    .get_with_merge_operands(&lookup_key, &mut operands, &mut newest_seq)
+            {
+                return result.is_ok() || !operands.is_empty();
+            }
+        }
+        if !operands.is_empty() {
+            return true;
         }
+        let current = self.versions.lock().unwrap().current();
+        current.key_may_exist(&lookup_key, self.table_cache.clone())
     }
 
-    // Recover DB from `db_name`.
-    // Returns the newest VersionEdit and whether we need to persistent VersionEdit to Manifest
-    fn recover(&mut self) -> Result<(VersionEdit, bool)> {
-        let env = self.options.env.clone();
-
-        // Ignore error from `mkdir_all` since the creation of the DB is
-        // committed only when the descriptor is created, and this directory
-        // may already exist from a previous failed creation attempt.
-        let _ = env.mkdir_all(self.db_name.as_str());
-
-        // Try acquire file lock
-        let lock_file =
-            env.create(generate_filename(self.db_name.as_str(), FileType::Lock, 0).as_str())?;
-        lock_file.lock()?;
-        self.db_lock = Some(lock_file);
-        if !env.exists(generate_filename(self.db_name.as_str(), FileType::Current, 0).as_str()) {
-            if self.options.create_if_missing {
-                // Create new necessary files for DB
-                let mut new_db = VersionEdit::new(self.options.max_levels);
-                new_db.set_comparator_name(self.options.comparator.name().to_owned());
-                new_db.set_log_number(0);
-                new_db.set_next_file(2);
-                new_db.set_last_sequence(0);
-                let manifest_filenum = 1;
-                let manifest_filename =
-                    generate_filename(self.db_name.as_str(), FileType::Manifest, manifest_filenum);
-                let manifest = env.create(manifest_filename.as_str())?;
-                let mut manifest_writer = Writer::new(manifest);
-                let mut record = vec![];
-                new_db.encode_to(&mut record);
-                match manifest_writer.add_record(&Slice::from(&record)) {
-                    Ok(()) => update_current(env.clone(), self.db_name.as_str(), manifest_filenum)?,
-                    Err(e) => {
-                        env.remove(manifest_filename.as_str())?;
-                        return Err(e);
+    fn multi_get(&self, options: ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return keys
+                .iter()
+                .map(|_| {
+                    Err(WickErr::new(
+                        Status::NotSupported,
+                        Some("Try to operate a closed db"),
+                    ))
+                })
+                .collect();
+        }
+        // Pin a snapshot for the whole batch so that the entries visible to it
+        // won't be dropped by compactions in the middle of the lookups
+        let (snapshot, _pinned) = match &options.snapshot {
+            Some(snapshot) => (snapshot.sequence_number(), None),
+            None => {
+                let pinned = self.get_snapshot();
+                (pinned.sequence_number(), Some(pinned))
+            }
+        };
+        let lookup_keys: Vec<LookupKey> =
+            keys.iter().map(|k| LookupKey::new(k, snapshot)).collect();
+        let mut operands: Vec<Vec<Vec<u8>>> = keys.iter().map(|_| vec![]).collect();
+        let mut results: Vec<Option<Result<Option<Vec<u8>>>>> = keys.iter().map(|_| None).collect();
+        // search the memtable and then the immutable memtables with the lock acquired only once
+        {
+            let mem = self.mem.read().unwrap();
+            let im_mem = self.im_mem.read().unwrap();
+            for table in std::iter::once(&*mem).chain(im_mem.iter().rev().map(|m| &m.mem)) {
+                for (i, lookup_key) in lookup_keys.iter().enumerate() {
+                    if results[i].is_some() {
+                        continue;
+                    }
+                    if let Some(result) =
+                        table.get_with_merge_operands(lookup_key, &mut operands[i], &mut None)
+                    {
+                        let base = result.ok();
+                        results[i] = Some(self.apply_merge_operands(
+                            keys[i],
+                            base.as_ref().map(|v| v.as_slice()),
+                            mem::take(&mut operands[i]),
+                        ));
                     }
                 }
-            } else {
-                return Err(WickErr::new(
-                    Status::InvalidArgument,
-                    Some(Box::leak(
-                        (self.db_name.clone() + " does not exist (create_if_missing is false)")
-                            .into_boxed_str(),
-                    )),
-                ));
             }
-        } else if self.options.error_if_exists {
-            return Err(WickErr::new(
-                Status::InvalidArgument,
-                Some(Box::leak(
-                    (self.db_name.clone() + " exists (error_if_exists is true)").into_boxed_str(),
-                )),
-            ));
         }
-        let mut versions = self.versions.lock().unwrap();
-        let mut should_save_manifest = versions.recover()?;
-
-        // Recover from all newer log files than the ones named in the
-        // MANIFEST (new log files may have been added by the previous
-        // incarnation without registering them in the MANIFEST).
-        //
-        // Note that PrevLogNumber() is no longer used, but we pay
-        // attention to it in case we are recovering a database
-        // produced by an older version of leveldb.
-        let min_log = versions.get_log_number();
-        let prev_log = versions.get_prev_log_number();
-        let all_files = env.list(self.db_name.as_str())?;
-        let mut logs_to_recover = vec![];
-        for filename in all_files.iter() {
-            if let Some((file_type, file_number)) = parse_filename(filename) {
-                if file_type == FileType::Log && (file_number >= min_log || file_number == prev_log)
-                {
-                    logs_to_recover.push(file_number);
-                }
+        // Search the sstables in the order of the user key so that the lookups in a
+        // same sstable are adjacent and hit the table cache one after another
+        let mut pending: Vec<usize> = (0..keys.len()).filter(|i| results[*i].is_none()).collect();
+        if !pending.is_empty() {
+            let ucmp = self.internal_comparator.user_comparator.clone();
+            pending.sort_by(|a, b| ucmp.compare(keys[*a], keys[*b]));
+            let current = self.versions.lock().unwrap().current();
+            let opt = Rc::new(options);
+            let mut need_compaction = false;
+            for i in pending {
+                let result = current
+                    .get(
+                        opt.clone(),
+                        &lookup_keys[i],
+                        self.table_cache.clone(),
+                        &mut operands[i],
+                        &mut None,
+                    )
+                    .and_then(|(value, seek_stats)| {
+                        need_compaction |= current.update_stats(seek_stats);
+                        self.apply_merge_operands(
+                            keys[i],
+                            value.as_deref(),
+                            mem::take(&mut operands[i]),
+                        )
+                    });
+                results[i] = Some(result);
+            }
+            if need_compaction {
+                self.maybe_schedule_compaction()
             }
         }
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
 
-        // Recover in the order in which the logs were generated
-        logs_to_recover.sort();
-        let mut max_sequence = 0;
-        let mut edit = VersionEdit::new(self.options.max_levels);
-        for (i, log_number) in logs_to_recover.iter().enumerate() {
-            let last_seq = self.replay_log_file(
-                &mut versions,
-                *log_number,
-                i == logs_to_recover.len() - 1,
-                &mut should_save_manifest,
-                &mut edit,
-            )?;
-            if max_sequence < last_seq {
-                max_sequence = last_seq
+    fn get_property(&self, name: &str) -> Option<String> {
+        let name = name.strip_prefix("wickdb.")?;
+        if let Some(level) = name.strip_prefix("num-files-at-level") {
+            let level = level.parse::<usize>().ok()?;
+            if level >= self.options.max_levels as usize {
+                return None;
             }
-
-            // The previous incarnation may not have written any MANIFEST
-            // records after allocating this log number.  So we manually
-            // update the file number allocation counter in VersionSet.
-            versions.mark_file_number_used(*log_number);
+            let current = self.versions.lock().unwrap().current();
+            return Some(current.get_level_files(level).len().to_string());
         }
-        if versions.get_last_sequence() < max_sequence {
-            versions.set_last_sequence(max_sequence)
+        match name {
+            "total-sst-files-size" => {
+                let current = self.versions.lock().unwrap().current();
+                let size: u64 = (0..self.options.max_levels as usize)
+                    .map(|level| VersionSet::total_file_size(current.get_level_files(level)))
+                    .sum();
+                Some(size.to_string())
+            }
+            "mem-table-size" => {
+                let size = self.mem.read().unwrap().approximate_memory_usage()
+                    + self
+                        .im_mem
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .map(|im| im.mem.approximate_memory_usage())
+                        .sum::<usize>();
+                Some(size.to_string())
+            }
+            _ => None,
         }
-
-        Ok((edit, should_save_manifest))
     }
 
-    // Replays the edits in the named log file and returns the last sequence of insertions
-    fn replay_log_file(
-        &self,
-        versions: &mut MutexGuard<VersionSet>,
-        log_number: u64,
-        last_log: bool,
-        save_manifest: &mut bool,
-        edit: &mut VersionEdit,
-    ) -> Result<u64> {
-        let file_name = generate_filename(self.db_name.as_str(), FileType::Log, log_number);
+    fn get_approximate_sizes(&self, ranges: &[(&[u8], &[u8])]) -> Vec<u64> {
+        let current = self.versions.lock().unwrap().current();
+        let ucmp = self.internal_comparator.user_comparator.clone();
+        let mem = self.mem.read().unwrap();
+        let im_mem = self.im_mem.read().unwrap();
+        ranges
+            .iter()
+            .map(|(start, limit)| {
+                let k1 =
+                    InternalKey::new(&Slice::from(*start), MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+                let k2 =
+                    InternalKey::new(&Slice::from(*limit), MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+                let offset1 = current.approximate_offset_of(k1.data(), &self.table_cache);
+                let offset2 = current.approximate_offset_of(k2.data(), &self.table_cache);
+                let mut size = offset2.saturating_sub(offset1);
+                // the encoded entries in the memtables within the range
+                for table in std::iter::once(&*mem).chain(im_mem.iter().map(|m| &m.mem)) {
+                    let mut iter = table.iter();
+                    iter.seek(&Slice::from(k1.data()));
+                    while iter.valid() {
+                        let key = iter.key();
+                        if ucmp.compare(extract_user_key(key.as_slice()).as_slice(), limit)
+                            != CmpOrdering::Less
+                        {
+                            break;
+                        }
+                        size += (key.size() + iter.value().size()) as u64;
+                        iter.next();
+                    }
+                }
+                size
+            })
+            .collect()
+    }
 
-        // Open the log file
-        let log_file = match self.env.open(file_name.as_str()) {
-            Ok(f) => f,
-            Err(e) => {
-                if self.options.paranoid_checks {
-                    return Err(e);
-                } else {
-                    info!("ignore errors when replaying log file : {:?}", e);
-                    return Ok(0);
-                }
-            }
+    fn create_checkpoint(&self, dest: &str) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("create a checkpoint of a read-only db"),
+            ));
+        }
+        if self.env.exists(dest) {
+            return Err(
+                WickErr::new(Status::InvalidArgument, Some("checkpoint directory exists"))
+                    .with_context(format!("directory {}", dest)),
+            );
+        }
+        self.force_compact_mem_table()?;
+        let (version, edit, manifest_number) = {
+            let mut versions = self.versions.lock().unwrap();
+            // The files of `version` are never deleted when it's alive
+            let version = versions.current();
+            let mut edit = versions.snapshot_edit(&version);
+            let manifest_number = versions.inc_next_file_number();
+            // There is no log file in the checkpoint
+            edit.set_log_number(0);
+            edit.set_prev_log_number(0);
+            edit.set_next_file(versions.get_next_file_number());
+            edit.set_last_sequence(versions.get_last_sequence());
+            (version, edit, manifest_number)
         };
+        let result = self.write_checkpoint(dest, &version, &edit, manifest_number);
+        if result.is_err() {
+            let _ = self.env.remove_dir(dest, true);
+        }
+        result
+    }
 
-        // We intentionally make Reader do checksumming even if
-        // paranoid_checks is false so that corruptions cause entire commits
-        // to be skipped instead of propagating bad information (like overly
-        // large sequence numbers).
-        let reporter = LogReporter::new();
-        let mut reader = Reader::new(log_file, Some(Box::new(reporter.clone())), true, 0);
-        info!("Recovering log #{}", log_number);
-
-        // Read all the records and add to a memtable
-        let mut mem = None;
-        let mut record_buf = vec![];
-        let mut batch = WriteBatch::new();
-        let mut max_sequence = 0;
-        let mut have_compacted = false; // indicates that maybe we need
-        while reader.read_record(&mut record_buf) {
-            if let Err(e) = reporter.result() {
-                return Err(e);
+    // Links the tables of `version` into `dest` and makes a MANIFEST with `edit`
+    fn write_checkpoint(
+        &self,
+        dest: &str,
+        version: &Version,
+        edit: &VersionEdit,
+        manifest_number: u64,
+    ) -> Result<()> {
+        self.env.mkdir_all(dest)?;
+        for level in 0..self.options.max_levels as usize {
+            for file in version.get_level_files(level) {
+                self.env.link(
+                    generate_filename(self.db_name.as_str(), FileType::Table, file.number).as_str(),
+                    generate_filename(dest, FileType::Table, file.number).as_str(),
+                )?;
             }
-            if record_buf.len() < HEADER_SIZE {
-                return Err(WickErr::new(
-                    Status::Corruption,
-                    Some("log record too small"),
-                ));
+        }
+        let manifest = self
+            .env
+            .create(generate_filename(dest, FileType::Manifest, manifest_number).as_str())?;
+        let mut writer = Writer::new(manifest);
+        let mut record = vec![];
+        edit.encode_to(&mut record);
+        writer.add_record(&Slice::from(record.as_slice()))?;
+        writer.sync()?;
+        update_current(self.env.clone(), dest, manifest_number)
+    }
+
+    fn ingest_external_files(
+        &self,
+        files: &[PathBuf],
+        opts: IngestExternalFileOptions,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("ingest external files into a read-only db"),
+            ));
+        }
+        let mut externals = Vec::with_capacity(files.len());
+        for path in files {
+            let path = match path.to_str() {
+                Some(p) => p,
+                None => {
+                    return Err(WickErr::new(
+                        Status::InvalidArgument,
+                        Some("external file path is not valid unicode"),
+                    ))
+                }
+            };
+            let external = self
+                .open_external_file(path, &opts)
+                .map_err(|e| e.with_context(path.to_owned()))?;
+            externals.push(external);
+        }
+        if externals.is_empty() {
+            return Ok(());
+        }
+        // The keys in the memtable must be older than the ingested ones, so the
+        // overlapping memtable is flushed first. The writes are blocked since
+        // the `VersionSet` is locked until the files are ingested.
+        let mut versions = loop {
+            if self.memtable_overlaps(&externals) {
+                if !opts.allow_blocking_flush {
+                    return Err(WickErr::new(
+                        Status::InvalidArgument,
+                        Some("external files overlap the memtable"),
+                    ));
+                }
+                self.force_compact_mem_table()?;
             }
-            if mem.is_none() {
-                mem = Some(MemTable::new(self.internal_comparator.clone()))
+            let versions = self.versions.lock().unwrap();
+            if !self.memtable_overlaps(&externals) {
+                break versions;
             }
-            let mem_ref = mem.as_ref().unwrap();
-            batch.set_contents(&mut record_buf);
-            let last_seq = batch.get_sequence() + u64::from(batch.get_count()) - 1;
-            if let Err(e) = batch.insert_into(&mem_ref) {
-                if self.options.paranoid_checks {
-                    return Err(e);
-                } else {
-                    info!("ignore errors when replaying log file : {:?}", e);
+        };
+        if let Some(e) = self.bg_error.read().unwrap().as_ref() {
+            return Err(e.clone());
+        }
+        let current = versions.current();
+        let ucmp = self.internal_comparator.user_comparator.clone();
+        let mut last_seq = versions.get_last_sequence();
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        // the levels of the files ingested so far
+        let mut ingested: Vec<(usize, &ExternalFile)> = vec![];
+        let mut created = vec![];
+        let mut result = Ok(());
+        for external in externals.iter() {
+            last_seq += 1;
+            let smallest = Slice::from(external.smallest.as_slice());
+            let largest = Slice::from(external.largest.as_slice());
+            let mut level = current.pick_level_for_external_file(&smallest, &largest);
+            // A file ingested by this call overlapping the range is older
+            for (l, f) in ingested.iter() {
+                if ucmp.compare(&f.smallest, &external.largest) != CmpOrdering::Greater
+                    && ucmp.compare(&external.smallest, &f.largest) != CmpOrdering::Greater
+                {
+                    level = level.min(l.saturating_sub(1));
                 }
             }
-            if last_seq > max_sequence {
-                max_sequence = last_seq
-            }
-            if mem_ref.approximate_memory_usage() > self.options.write_buffer_size {
-                have_compacted = true;
-                *save_manifest = true;
-                let iter = mem_ref.iter();
-                versions.write_level0_files(
-                    self.db_name.as_str(),
-                    self.table_cache.clone(),
-                    iter,
-                    edit,
-                )?;
-                mem = None;
+            let mut meta = FileMetaData {
+                number: versions.inc_next_file_number(),
+                ..Default::default()
+            };
+            created.push(meta.number);
+            result = self.copy_external_file(
+                external,
+                last_seq,
+                &mut meta,
+                self.options.compression_for_level(level),
+            );
+            if result.is_err() {
+                break;
             }
+            info!(
+                "Ingest {} as table #{} at level-{}: {} bytes",
+                external.path, meta.number, level, meta.file_size
+            );
+            edit.add_file(level, &meta);
+            ingested.push((level, external));
         }
-        // See if we should keep reusing the last log file.
-        if self.options.reuse_logs && last_log && !have_compacted {
-            let log_file = reader.into_file();
-            info!("Reusing old log file : {}", file_name);
-            versions.record_writer = Some(Writer::new(log_file));
-            versions.set_log_number(log_number);
-            if let Some(m) = mem {
-                *self.mem.write().unwrap() = m;
-                mem = None;
-            } else {
-                *self.mem.write().unwrap() = MemTable::new(self.internal_comparator.clone());
+        if result.is_ok() {
+            let prev_seq = versions.get_last_sequence();
+            versions.set_last_sequence(last_seq);
+            result = versions.log_and_apply(&mut edit);
+            if result.is_err() {
+                versions.set_last_sequence(prev_seq);
             }
         }
-        if let Some(m) = &mem {
-            *save_manifest = true;
-            versions.write_level0_files(
-                self.db_name.as_str(),
-                self.table_cache.clone(),
-                m.iter(),
-                edit,
-            )?;
+        if result.is_err() {
+            for number in created {
+                let _ = self.env.remove(
+                    generate_filename(self.db_name.as_str(), FileType::Table, number).as_str(),
+                );
+            }
+            return result;
         }
-        Ok(max_sequence)
+        mem::drop(versions);
+        self.maybe_schedule_compaction();
+        Ok(())
     }
 
-    // Delete any unneeded files and stale in-memory entries.
-    #[allow(unused_must_use)]
-    fn delete_obsolete_files(&self, mut versions: MutexGuard<VersionSet>) {
-        if self.bg_error.read().is_err() {
-            // After a background error, we don't know whether a new version may
-            // or may not have been committed, so we cannot safely garbage collect
-            return;
+    fn delete_files_in_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("delete files in a read-only db"),
+            ));
         }
-        versions.lock_live_files();
-        // ignore IO error on purpose
-        if let Ok(files) = self.env.list(self.db_name.as_str()) {
-            for file in files.iter() {
-                if let Some((file_type, number)) = parse_filename(file) {
-                    let mut keep = true;
-                    match file_type {
-                        FileType::Log => {
-                            keep = number >= versions.get_log_number()
-                                || number == versions.get_prev_log_number()
-                        }
-                        FileType::Manifest => keep = number >= versions.get_manifest_number(),
-                        FileType::Table => keep = versions.pending_outputs.contains(&number),
-                        // Any temp files that are currently being written to must
-                        // be recorded in pending_outputs
-                        FileType::Temp => keep = versions.pending_outputs.contains(&number),
-                        _ => {}
-                    }
-                    if !keep {
-                        if file_type == FileType::Table {
-                            self.table_cache.evict(number)
-                        }
-                        info!("Delete type={:?} #{}", file_type, number);
-                        // ignore the IO error here
-                        self.env.remove(
-                            format!("{}{}{:?}", self.db_name.as_str(), MAIN_SEPARATOR, file)
-                                .as_str(),
-                        );
-                    }
+        let ucmp = self.internal_comparator.user_comparator.clone();
+        if ucmp.compare(start, end) != CmpOrdering::Less {
+            return Ok(());
+        }
+        let mut versions = self.versions.lock().unwrap();
+        if let Some(e) = self.bg_error.read().unwrap().as_ref() {
+            return Err(e.clone());
+        }
+        let current = versions.current();
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        let mut deleted = vec![];
+        for level in 0..self.options.max_levels as usize {
+            for f in current.get_level_files(level) {
+                if !versions.compacting_files.contains(&f.number)
+                    && ucmp.compare(f.smallest.user_key(), start) != CmpOrdering::Less
+                    && ucmp.compare(f.largest.user_key(), end) == CmpOrdering::Less
+                {
+                    edit.delete_file(level, f.number);
+                    deleted.push(f.number);
                 }
             }
         }
+        if deleted.is_empty() {
+            return Ok(());
+        }
+        // the files in the old version are deleted once it's not referenced
+        mem::drop(current);
+        versions.log_and_apply(&mut edit)?;
+        info!(
+            "Deleted {} files in range: {:?}, current level summary: {}",
+            deleted.len(),
+            deleted,
+            versions.current().level_summary()
+        );
+        self.delete_obsolete_files(versions);
+        Ok(())
     }
 
-    // Schedule the WriteBatch and wait for the result from the receiver.
-    // This function wakes up the thread in `process_batch`.
-    fn schedule_batch_and_wait(&self, options: WriteOptions, batch: WriteBatch) -> Result<()> {
-        if self.is_shutting_down.load(Ordering::Acquire) {
-            return Err(WickErr::new(
-                Status::NotSupported,
-                Some("Try to operate a closed db"),
-            ));
+    // Opens the table at `path` and finds its key range. All the user keys in the
+    // table must be unique and sorted by the comparator.
+    fn open_external_file(
+        &self,
+        path: &str,
+        opts: &IngestExternalFileOptions,
+    ) -> Result<ExternalFile> {
+        let file = self.env.open(path)?;
+        let size = file.len()?;
+        let table = Arc::new(Table::open(file, size, self.options.clone())?);
+        let mut iter = table.clone().iter(ReadOptions {
+            verify_checksums: opts.verify_checksums,
+            fill_cache: false,
+            ..Default::default()
+        });
+        let ucmp = self.internal_comparator.user_comparator.clone();
+        let mut smallest = None;
+        let mut largest = vec![];
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key()?;
+            let user_key = key.user_key.as_slice();
+            if smallest.is_none() {
+                smallest = Some(Vec::from(user_key));
+            } else if ucmp.compare(user_key, largest.as_slice()) != CmpOrdering::Greater {
+                return Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some("keys are not unique and sorted by the comparator"),
+                ));
+            }
+            largest.clear();
+            largest.extend_from_slice(user_key);
+            iter.next();
         }
-        if batch.is_empty() {
-            return Ok(());
+        iter.status()?;
+        match smallest {
+            Some(smallest) => Ok(ExternalFile {
+                path: path.to_owned(),
+                table,
+                verify_checksums: opts.verify_checksums,
+                smallest,
+                largest,
+            }),
+            None => Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("external file is empty"),
+            )),
         }
-        let (send, recv) = crossbeam_channel::bounded(0);
-        let task = BatchTask::new(batch, send, options);
-        self.batch_queue.lock().unwrap().push_back(task);
-        self.process_batch_sem.notify_all();
-        match recv.recv() {
-            Ok(m) => m,
-            Err(e) => Err(WickErr::new_from_raw(Status::Unexpected, None, Box::new(e))),
+    }
+
+    // Returns true iff the memtable or the immutable memtable overlaps some of the files
+    fn memtable_overlaps(&self, files: &[ExternalFile]) -> bool {
+        let overlaps = |mem: &dyn MemoryTable| {
+            let mut iter = mem.iter();
+            files.iter().any(|f| {
+                let target = InternalKey::new(
+                    &Slice::from(f.smallest.as_slice()),
+                    MAX_KEY_SEQUENCE,
+                    VALUE_TYPE_FOR_SEEK,
+                );
+                iter.seek(&Slice::from(target.data()));
+                iter.valid()
+                    && self.internal_comparator.user_comparator.compare(
+                        extract_user_key(iter.key().as_slice()).as_slice(),
+                        f.largest.as_slice(),
+                    ) != CmpOrdering::Greater
+            })
+        };
+        if overlaps(&*self.mem.read().unwrap()) {
+            return true;
         }
+        self.im_mem
+            .read()
+            .unwrap()
+            .iter()
+            .any(|im_mem| overlaps(&im_mem.mem))
     }
 
-    // Make sure there is enough space in memtable.
-    // This method acquires the mutex of VersionSet and deliver it to the caller.
-    fn make_room_for_write(&self, mut force: bool) -> Result<MutexGuard<VersionSet>> {
-        let mut allow_delay = !force;
-        let mut versions = self.versions.lock().unwrap();
-        loop {
-            if let Some(e) = { self.bg_error.write().unwrap().take() } {
-                return Err(e);
-            } else if allow_delay
-                && versions.level_files_count(0) >= self.options.l0_slowdown_writes_threshold
-            {
-                // We are getting close to hitting a hard limit on the number of
-                // L0 files.  Rather than delaying a single write by several
-                // seconds when we hit the hard limit, start delaying each
-                // individual write by 1ms to reduce latency variance.  Also,
-                // this delay hands over some CPU to the compaction thread in
-                // case it is sharing the same core as the writer.
-                thread::sleep(Duration::from_micros(1000));
-                allow_delay = false; // do not delay a single write more than once
-            } else if !force
-                && self.mem.read().unwrap().approximate_memory_usage()
-                    <= self.options.write_buffer_size
-            {
-                // There is room in current memtable
-                break;
-            } else if self.im_mem.read().unwrap().is_some() {
-                info!("Current memtable full; waiting...");
-                versions = self.background_work_finished_signal.wait(versions).unwrap();
-            } else if versions.level_files_count(0) >= self.options.l0_stop_writes_threshold {
-                info!("Too many L0 files; waiting...");
-                versions = self.background_work_finished_signal.wait(versions).unwrap();
-            } else {
-                // there must be no prev log
-                let new_log_num = versions.get_next_file_number();
-                let log_file = self.env.create(
-                    generate_filename(self.db_name.as_str(), FileType::Log, new_log_num).as_str(),
-                )?;
-                versions.set_next_file_number(new_log_num + 1);
-                versions.record_writer = Some(Writer::new(log_file));
-                // rotate the mem to immutable mem
-                let mut mem = self.mem.write().unwrap();
-                let memtable =
-                    mem::replace(&mut *mem, MemTable::new(self.internal_comparator.clone()));
-                let mut im_mem = self.im_mem.write().unwrap();
-                *im_mem = Some(memtable);
-                force = false; // do not force another compaction if have room
-                self.maybe_schedule_compaction();
+    // Rewrites the entries of the external file into the table `meta.number` with
+    // the sequence number `seq`
+    fn copy_external_file(
+        &self,
+        external: &ExternalFile,
+        seq: u64,
+        meta: &mut FileMetaData,
+        compression: CompressionType,
+    ) -> Result<()> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Table, meta.number);
+        let file = self.env.create(file_name.as_str())?;
+        let mut builder = TableBuilder::new(file, compression, self.options.clone());
+        let mut iter = external.table.clone().iter(ReadOptions {
+            verify_checksums: external.verify_checksums,
+            fill_cache: false,
+            ..Default::default()
+        });
+        let mut smallest = None;
+        let mut largest = None;
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key()?;
+            let ikey = InternalKey::new(&key.user_key, seq, key.value_type);
+            builder.add(ikey.data(), iter.value())?;
+            if smallest.is_none() {
+                smallest = Some(InternalKey::decoded_from(ikey.data()));
             }
+            largest = Some(ikey);
+            iter.next();
         }
-        Ok(versions)
+        iter.status()?;
+        builder.finish(true)?;
+        meta.file_size = builder.file_size();
+        meta.smallest = Arc::new(smallest.unwrap());
+        meta.largest = Arc::new(largest.unwrap());
+        meta.creation_time = unix_time_secs();
+        meta.has_range_tombstones = builder.has_range_tombstones();
+        Ok(())
     }
 
-    // Compact immutable memory table to level0 files
-    fn compact_mem_table(&self) {
-        let mut versions = self.versions.lock().unwrap();
-        let mut edit = VersionEdit::new(self.options.max_levels);
-        let mut im_mem = self.im_mem.write().unwrap();
-        match versions.write_level0_files(
-            self.db_name.as_str(),
-            self.table_cache.clone(),
-            im_mem.as_ref().unwrap().iter(),
-            &mut edit,
-        ) {
-            Ok(()) => {
-                if self.is_shutting_down.load(Ordering::Acquire) {
-                    self.record_bg_error(WickErr::new(
-                        Status::IOError,
-                        Some("Deleting DB during memtable compaction"),
-                    ))
-                } else {
-                    edit.prev_log_number = Some(0);
-                    edit.log_number = Some(versions.get_log_number());
-                    match versions.log_and_apply(&mut edit) {
-                        Ok(()) => {
-                            *im_mem = None;
-                            self.delete_obsolete_files(versions);
-                        }
-                        Err(e) => {
-                            self.record_bg_error(e);
-                        }
-                    }
+    fn warm_cache(
+        &self,
+        read_opt: ReadOptions,
+        (start, end): (&[u8], &[u8]),
+        max_bytes: usize,
+        canceled: Option<&AtomicBool>,
+    ) -> Result<usize> {
+        let mut budget = match &self.options.block_cache {
+            Some(cache) => max_bytes.min(cache.capacity()),
+            None => return Ok(0),
+        };
+        let ucmp = self.internal_comparator.user_comparator.clone();
+        let current = self.versions.lock().unwrap().current();
+        let read_opt = Rc::new(read_opt);
+        let mut loaded = 0;
+        for level in 0..self.options.max_levels as usize {
+            let mut files: Vec<_> = current
+                .get_level_files(level)
+                .iter()
+                .filter(|f| {
+                    ucmp.compare(f.smallest.user_key(), end) == CmpOrdering::Less
+                        && ucmp.compare(f.largest.user_key(), start) != CmpOrdering::Less
+                })
+                .collect();
+            if level == 0 {
+                // the newer files are hotter
+                files.sort_by_key(|f| std::cmp::Reverse(f.number));
+            }
+            for f in files {
+                loaded += self.table_cache.warm_cache(
+                    read_opt.clone(),
+                    level,
+                    f,
+                    (start, end),
+                    &mut budget,
+                    canceled,
+                )?;
+                if budget == 0 {
+                    return Ok(loaded);
                 }
             }
-            Err(e) => {
-                self.record_bg_error(e);
+        }
+        Ok(loaded)
+    }
+
+    fn verify_checksums(&self) -> Result<()> {
+        let current = self.versions.lock().unwrap().current();
+        for level in 0..self.options.max_levels as usize {
+            for file in current.get_level_files(level) {
+                let file_name =
+                    generate_filename(self.db_name.as_str(), FileType::Table, file.number);
+                let result = self.env.open(file_name.as_str()).and_then(|f| {
+                    Table::open(f, file.file_size, self.options.clone())?.verify_checksums()
+                });
+                if let Err(e) = result {
+                    return Err(e.with_context(file_name));
+                }
             }
         }
+        Ok(())
     }
 
-    // The complete compaction process
-    fn background_compaction(&self) {
-        if self.im_mem.read().unwrap().is_some() {
-            // minor compaction
-            self.compact_mem_table();
-        } else {
-            let mut is_manual = false;
+    fn try_catch_up_with_primary(&self) -> Result<()> {
+        if !self.secondary {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("catch up with the primary by a non-secondary db"),
+            ));
+        }
+        let mut versions = self.versions.lock().unwrap();
+        versions.catch_up()?;
+        // The memtable is rebuilt from the logs which are not flushed by the primary
+        // yet, since the records in the old memtable might be obsolete now.
+        let mem = self.new_memtable();
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        let mut should_save_manifest = false;
+        self.replay_logs(
+            &mut versions,
+            Some(&mem),
+            &mut should_save_manifest,
+            &mut edit,
+        )?;
+        *self.mem.write().unwrap() = mem;
+        Ok(())
+    }
+
+    fn compact_range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("compact a read-only db"),
+            ));
+        }
+        let max_level_with_files = {
+            let current = self.versions.lock().unwrap().current();
+            let smallest = start.map_or(Slice::default(), Slice::from);
+            let largest = end.map_or(Slice::default(), Slice::from);
+            let mut max_level = 1;
+            for level in 1..self.options.max_levels as usize {
+                if current.overlap_in_level(level, &smallest, &largest) {
+                    max_level = level;
+                }
+            }
+            max_level
+        };
+        self.force_compact_mem_table()?;
+        for level in 0..max_level_with_files {
+            self.manual_compact_range(level, false, start, end, options)?;
+        }
+        // The bottommost level holds the obsolete entries which are never compacted
+        // into a deeper level
+        self.manual_compact_range(max_level_with_files, true, start, end, options)
+    }
+
+    // Flushes the memtable into a level-0 table. If `wait` is false, returns once
+    // the memtable is switched to an immutable one.
+    fn flush(&self, wait: bool) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("flush a read-only db"),
+            ));
+        }
+        if wait {
+            self.force_compact_mem_table()
+        } else if !self.mem.read().unwrap().is_empty() {
+            self.make_room_for_write(true).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+
+    // Rotates the memtable if it's not empty and waits until the immutable memtable is flushed
+    fn force_compact_mem_table(&self) -> Result<()> {
+        let mut versions = if !self.mem.read().unwrap().is_empty() {
+            self.make_room_for_write(true)?
+        } else {
+            self.versions.lock().unwrap()
+        };
+        while !self.im_mem.read().unwrap().is_empty() {
+            if let Some(e) = self.bg_error.read().unwrap().as_ref() {
+                return Err(e.clone());
+            }
+            versions = self.background_work_finished_signal.wait(versions).unwrap();
+        }
+        Ok(())
+    }
+
+    // Compacts the files in `level` overlapping `[start, end]` into `level + 1`, or
+    // rewrites them in `level` if `in_place` is true, by the background compaction
+    // thread and waits until it's done.
+    fn manual_compact_range(
+        &self,
+        level: usize,
+        in_place: bool,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<()> {
+        let begin = start.map(|k| {
+            Arc::new(InternalKey::new(
+                &Slice::from(k),
+                MAX_KEY_SEQUENCE,
+                VALUE_TYPE_FOR_SEEK,
+            ))
+        });
+        let end = end.map(|k| Arc::new(InternalKey::new(&Slice::from(k), 0, ValueType::Deletion)));
+        let mut versions = self.versions.lock().unwrap();
+        // Only one manual compaction is allowed at a time
+        while versions.manual_compaction.is_some() {
+            versions = self.background_work_finished_signal.wait(versions).unwrap();
+        }
+        versions.manual_compaction = Some(ManualCompaction {
+            level,
+            in_place,
+            done: false,
+            begin,
+            end,
+            options: options.clone(),
+            aborted: false,
+        });
+        // `maybe_schedule_compaction` locks the `VersionSet` as well
+        mem::drop(versions);
+        self.maybe_schedule_compaction();
+        let mut versions = self.versions.lock().unwrap();
+        let result = loop {
+            let m = versions.manual_compaction.as_ref().unwrap();
+            if m.done {
+                if m.aborted {
+                    break Err(WickErr::new(
+                        Status::Aborted,
+                        Some("Manual compaction is canceled"),
+                    ));
+                }
+                break Ok(());
+            }
+            if self.is_shutting_down.load(Ordering::Acquire) {
+                break Err(WickErr::new(
+                    Status::IOError,
+                    Some("Deleting DB during manual compaction"),
+                ));
+            }
+            if let Some(e) = self.bg_error.read().unwrap().as_ref() {
+                break Err(e.clone());
+            }
+            versions = self.background_work_finished_signal.wait(versions).unwrap();
+        };
+        versions.manual_compaction = None;
+        // wake up the other waiting manual compactions
+        self.background_work_finished_signal.notify_all();
+        result
+    }
+
+    // Applies the merge `operands` (from the newest to the oldest) of `key` on the `base` value
+    fn apply_merge_operands(
+        &self,
+        key: &[u8],
+        base: Option<&[u8]>,
+        mut operands: Vec<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if operands.is_empty() {
+            return Ok(base.map(Vec::from));
+        }
+        match &self.options.merge_operator {
+            Some(merge_operator) => {
+                operands.reverse();
+                match merge_operator.full_merge(key, base, operands.as_slice()) {
+                    Some(value) => Ok(Some(value)),
+                    None => Err(WickErr::new(
+                        Status::Corruption,
+                        Some("merge operator failed to merge the operands"),
+                    )),
+                }
+            }
+            None => Err(WickErr::new(
+                Status::NotSupported,
+                Some("merge operands found but merge operator is not set"),
+            )),
+        }
+    }
+
+    // Record a sample of bytes read at the specified internal key
+    // Might schedule a background compaction.
+    fn record_read_sample(&self, key: Slice) {
+        if self
+            .versions
+            .lock()
+            .unwrap()
+            .current()
+            .record_read_sample(key)
+        {
+            self.maybe_schedule_compaction()
+        }
+    }
+
+    // Recover DB from `db_name`.
+    // Returns the newest VersionEdit and whether we need to persistent VersionEdit to Manifest.
+    // A read-only DB is recovered without touching any file.
+    fn recover(&mut self) -> Result<(VersionEdit, bool)> {
+        let env = self.options.env.clone();
+
+        if !self.read_only {
+            // Ignore error from `mkdir_all` since the creation of the DB is
+            // committed only when the descriptor is created, and this directory
+            // may already exist from a previous failed creation attempt.
+            let _ = env.mkdir_all(self.db_name.as_str());
+
+            // Try acquire file lock
+            let lock_file =
+                env.create(generate_filename(self.db_name.as_str(), FileType::Lock, 0).as_str())?;
+            lock_file.lock()?;
+            self.db_lock = Some(lock_file);
+        }
+        if !env.exists(generate_filename(self.db_name.as_str(), FileType::Current, 0).as_str()) {
+            if self.read_only {
+                return Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some("db does not exist (read-only mode)"),
+                )
+                .with_context(format!("db {}", self.db_name)));
+            }
+            if self.options.create_if_missing {
+                // Create new necessary files for DB
+                let mut new_db = VersionEdit::new(self.options.max_levels);
+                new_db.set_comparator_name(self.options.comparator.name().to_owned());
+                if self.options.log_block_size != BLOCK_SIZE {
+                    new_db.set_log_block_size(self.options.log_block_size as u64);
+                }
+                new_db.set_log_number(0);
+                new_db.set_next_file(2);
+                new_db.set_last_sequence(0);
+                let manifest_filenum = 1;
+                let manifest_filename =
+                    generate_filename(self.db_name.as_str(), FileType::Manifest, manifest_filenum);
+                let manifest = env.create(manifest_filename.as_str())?;
+                let mut manifest_writer = Writer::new(manifest);
+                let mut record = vec![];
+                new_db.encode_to(&mut record);
+                match manifest_writer.add_record(&Slice::from(&record)) {
+                    Ok(()) => update_current(env.clone(), self.db_name.as_str(), manifest_filenum)?,
+                    Err(e) => {
+                        env.remove(manifest_filename.as_str())?;
+                        return Err(e);
+                    }
+                }
+            } else {
+                return Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some(Box::leak(
+                        (self.db_name.clone() + " does not exist (create_if_missing is false)")
+                            .into_boxed_str(),
+                    )),
+                ));
+            }
+        } else if self.options.error_if_exists {
+            return Err(WickErr::new(
+                Status::InvalidArgument,
+                Some(Box::leak(
+                    (self.db_name.clone() + " exists (error_if_exists is true)").into_boxed_str(),
+                )),
+            ));
+        }
+        let mut versions = self.versions.lock().unwrap();
+        let mut should_save_manifest = versions.recover()?;
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        if self.read_only {
+            let mem = self.new_memtable();
+            self.replay_logs(
+                &mut versions,
+                Some(&mem),
+                &mut should_save_manifest,
+                &mut edit,
+            )?;
+            *self.mem.write().unwrap() = mem;
+        } else {
+            self.replay_logs(&mut versions, None, &mut should_save_manifest, &mut edit)?;
+        }
+        Ok((edit, should_save_manifest))
+    }
+
+    // Recover from all newer log files than the ones named in the
+    // MANIFEST (new log files may have been added by the previous
+    // incarnation without registering them in the MANIFEST).
+    //
+    // Note that PrevLogNumber() is no longer used, but we pay
+    // attention to it in case we are recovering a database
+    // produced by an older version of leveldb.
+    //
+    // See `replay_log_file` for `replay_into`.
+    fn replay_logs(
+        &self,
+        versions: &mut MutexGuard<VersionSet>,
+        replay_into: Option<&MemTable>,
+        should_save_manifest: &mut bool,
+        edit: &mut VersionEdit,
+    ) -> Result<()> {
+        let env = self.options.env.clone();
+        let min_log = versions.get_log_number();
+        let prev_log = versions.get_prev_log_number();
+        let all_files = env.list(self.db_name.as_str())?;
+        let mut logs_to_recover = vec![];
+        for filename in all_files.iter() {
+            if let Some((file_type, file_number)) = parse_filename(filename) {
+                if file_type == FileType::Log && (file_number >= min_log || file_number == prev_log)
+                {
+                    logs_to_recover.push(file_number);
+                }
+            }
+        }
+
+        // Recover in the order in which the logs were generated
+        logs_to_recover.sort();
+        let mut max_sequence = 0;
+        for (i, log_number) in logs_to_recover.iter().enumerate() {
+            let last_seq = self.replay_log_file(
+                versions,
+                *log_number,
+                i == logs_to_recover.len() - 1,
+                replay_into,
+                should_save_manifest,
+                edit,
+            )?;
+            if max_sequence < last_seq {
+                max_sequence = last_seq
+            }
+
+            // The previous incarnation may not have written any MANIFEST
+            // records after allocating this log number.  So we manually
+            // update the file number allocation counter in VersionSet.
+            versions.mark_file_number_used(*log_number);
+        }
+        if versions.get_last_sequence() < max_sequence {
+            versions.set_last_sequence(max_sequence)
+        }
+        Ok(())
+    }
+
+    // Replays the edits in the named log file and returns the last sequence of insertions.
+    // If `replay_into` is given, the records are inserted into it instead of being
+    // written into level 0 tables, which is used by the read-only DB.
+    fn replay_log_file(
+        &self,
+        versions: &mut MutexGuard<VersionSet>,
+        log_number: u64,
+        last_log: bool,
+        replay_into: Option<&MemTable>,
+        save_manifest: &mut bool,
+        edit: &mut VersionEdit,
+    ) -> Result<u64> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Log, log_number);
+
+        // Open the log file
+        let log_file = match self.env.open(file_name.as_str()) {
+            Ok(f) => f,
+            Err(e) => {
+                if self.options.paranoid_checks {
+                    return Err(e);
+                } else {
+                    info!("ignore errors when replaying log file : {:?}", e);
+                    return Ok(0);
+                }
+            }
+        };
+
+        // We intentionally make Reader do checksumming even if
+        // paranoid_checks is false so that corruptions cause entire commits
+        // to be skipped instead of propagating bad information (like overly
+        // large sequence numbers).
+        let reporter = LogReporter::new();
+        let mut reader = Reader::new(
+            log_file,
+            Some(Box::new(reporter.clone())),
+            true,
+            0,
+            self.options.wal_recovery_mode,
+            self.options.log_block_size,
+            log_number,
+        );
+        info!("Recovering log #{}", log_number);
+
+        // Read all the records and add to a memtable
+        let mut mem = None;
+        let mut record_buf = vec![];
+        let mut batch = WriteBatch::new();
+        let mut max_sequence = 0;
+        let mut have_compacted = false; // indicates that maybe we need
+        while reader.read_record(&mut record_buf) {
+            if let Err(e) = reporter.result() {
+                return Err(e);
+            }
+            if record_buf.len() < HEADER_SIZE {
+                return Err(WickErr::new(
+                    Status::Corruption,
+                    Some("log record too small"),
+                ));
+            }
+            if mem.is_none() && replay_into.is_none() {
+                mem = Some(self.new_memtable())
+            }
+            let mem_ref = replay_into.unwrap_or_else(|| mem.as_ref().unwrap());
+            batch.set_contents(&mut record_buf);
+            let last_seq = batch.get_sequence() + u64::from(batch.count()) - 1;
+            if let Err(e) = batch.insert_into(&mem_ref) {
+                if self.options.paranoid_checks {
+                    return Err(e);
+                } else {
+                    info!("ignore errors when replaying log file : {:?}", e);
+                }
+            }
+            if last_seq > max_sequence {
+                max_sequence = last_seq
+            }
+            if replay_into.is_none()
+                && mem_ref.approximate_memory_usage() > self.options.write_buffer_size
+            {
+                have_compacted = true;
+                *save_manifest = true;
+                let iter = mem_ref.iter();
+                versions.write_level0_files(
+                    self.db_name.as_str(),
+                    self.table_cache.clone(),
+                    iter,
+                    mem_ref.range_tombstones().as_slice(),
+                    edit,
+                )?;
+                mem = None;
+            }
+        }
+        // The corruptions found after the last record are in the tail of the log.
+        // A torn tail is only reported in `AbsoluteConsistency` and other corruptions
+        // are not tolerated in paranoid mode.
+        if self.options.paranoid_checks
+            || self.options.wal_recovery_mode == WALRecoveryMode::AbsoluteConsistency
+        {
+            reporter.result()?;
+        }
+        if replay_into.is_some() {
+            return Ok(max_sequence);
+        }
+        // See if we should keep reusing the last log file. A log with a dropped
+        // tail is never reused since the new records would follow the broken one.
+        if self.options.reuse_logs
+            && last_log
+            && !have_compacted
+            && !reader.tail_dropped()
+            && reporter.result().is_ok()
+        {
+            let log_file = reader.into_file();
+            info!("Reusing old log file : {}", file_name);
+            let length = log_file.len()?;
+            let recyclable = self.options.recycle_log_file_num > 0;
+            let mut writer = Writer::with_config(
+                log_file,
+                self.options.log_block_size,
+                if recyclable { Some(log_number) } else { None },
+            );
+            writer.set_dest_length(length);
+            versions.record_writer = Some(writer);
+            versions.set_log_number(log_number);
+            if let Some(m) = mem {
+                *self.mem.write().unwrap() = m;
+                mem = None;
+            } else {
+                *self.mem.write().unwrap() = self.new_memtable();
+            }
+        }
+        if let Some(m) = &mem {
+            *save_manifest = true;
+            versions.write_level0_files(
+                self.db_name.as_str(),
+                self.table_cache.clone(),
+                m.iter(),
+                m.range_tombstones().as_slice(),
+                edit,
+            )?;
+        }
+        Ok(max_sequence)
+    }
+
+    // Creates the writer of the new log `log_number`, which overwrites a recycled
+    // log file if there is any.
+    fn new_log_writer(&self, versions: &mut VersionSet, log_number: u64) -> Result<Writer> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Log, log_number);
+        let block_size = self.options.log_block_size;
+        if self.options.recycle_log_file_num == 0 {
+            let log_file = self.env.create(file_name.as_str())?;
+            return Ok(Writer::with_config(log_file, block_size, None));
+        }
+        if versions.min_recyclable_log == u64::MAX {
+            versions.min_recyclable_log = log_number;
+        }
+        let log_file = match versions.recycled_logs.pop_front() {
+            Some(old) => {
+                info!("Reuse log #{} as #{}", old, log_number);
+                let old_name = generate_filename(self.db_name.as_str(), FileType::Log, old);
+                self.env.reuse(old_name.as_str(), file_name.as_str())?
+            }
+            None => self.env.create(file_name.as_str())?,
+        };
+        Ok(Writer::with_config(log_file, block_size, Some(log_number)))
+    }
+
+    // Delete any unneeded files and stale in-memory entries.
+    #[allow(unused_must_use)]
+    fn delete_obsolete_files(&self, mut versions: MutexGuard<VersionSet>) {
+        if self.bg_error.read().unwrap().is_some() {
+            // After a background error, we don't know whether a new version may
+            // or may not have been committed, so we cannot safely garbage collect
+            return;
+        }
+        let live = versions.live_files();
+        // ignore IO error on purpose
+        if let Ok(files) = self.env.list(self.db_name.as_str()) {
+            for file in files.iter() {
+                if let Some((file_type, number)) = parse_filename(file) {
+                    let mut keep = true;
+                    match file_type {
+                        FileType::Log => {
+                            keep = number >= versions.get_log_number()
+                                || number == versions.get_prev_log_number()
+                                || versions.recycled_logs.contains(&number);
+                            // Keep the obsolete log for recycling if it's written in
+                            // the recyclable format by this DB
+                            if !keep
+                                && number >= versions.min_recyclable_log
+                                && versions.recycled_logs.len() < self.options.recycle_log_file_num
+                            {
+                                info!("Recycle log #{}", number);
+                                versions.recycled_logs.push_back(number);
+                                keep = true;
+                            }
+                        }
+                        FileType::Manifest => keep = number >= versions.get_manifest_number(),
+                        FileType::Table => keep = live.contains(&number),
+                        // Any temp files that are currently being written to must
+                        // be recorded in pending_outputs
+                        FileType::Temp => keep = live.contains(&number),
+                        _ => {}
+                    }
+                    if !keep {
+                        if file_type == FileType::Table {
+                            self.table_cache.evict(number)
+                        }
+                        info!("Delete type={:?} #{}", file_type, number);
+                        // ignore the IO error here
+                        let _ = self.env.remove(file.to_str().unwrap());
+                    }
+                }
+            }
+        }
+    }
+
+    // Schedule the WriteBatch and wait for the result from the receiver.
+    // This function wakes up the thread in `process_batch`.
+    fn schedule_batch_and_wait(&self, options: WriteOptions, batch: WriteBatch) -> Result<()> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("Try to operate a closed db"),
+            ));
+        }
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("Try to write a read-only db"),
+            ));
+        }
+        if options.sync && options.disable_wal {
+            return Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("sync writes must enable the WAL"),
+            ));
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let (send, recv) = crossbeam_channel::bounded(0);
+        let task = BatchTask::new(batch, send, options);
+        self.batch_queue.lock().unwrap().push_back(task);
+        self.process_batch_sem.notify_all();
+        loop {
+            match recv.recv() {
+                Ok(BatchSignal::Insert(batch, done)) => {
+                    let status = batch.insert_into(&self.mem.read().unwrap());
+                    if let Err(e) = done.send(status) {
+                        error!("[schedule batch] Fail sending the insertion result: {}", e)
+                    }
+                }
+                Ok(BatchSignal::Done(m)) => return m,
+                Err(e) => return Err(WickErr::new_from_raw(Status::Unexpected, None, Box::new(e))),
+            }
+        }
+    }
+
+    // Inserts the batches of a group into the memtable in parallel. The first batch
+    // is inserted by the current thread and the others are inserted by their own
+    // writers waiting in `schedule_batch_and_wait`.
+    // `signals` are the signals of the writers in the same order as `batches`.
+    fn insert_concurrently(
+        &self,
+        batches: Vec<WriteBatch>,
+        signals: &[Sender<BatchSignal>],
+        mut seq: u64,
+    ) -> Result<()> {
+        let (send, recv) = crossbeam_channel::bounded(batches.len());
+        let mut batches = batches.into_iter();
+        let mut first = batches.next().unwrap();
+        first.set_sequence(seq);
+        seq += u64::from(first.count());
+        let mut status = Ok(());
+        let mut waiting = 0;
+        for (mut batch, signal) in batches.zip(signals.iter().skip(1)) {
+            batch.set_sequence(seq);
+            seq += u64::from(batch.count());
+            match signal.send(BatchSignal::Insert(batch, send.clone())) {
+                Ok(()) => waiting += 1,
+                Err(SendError(BatchSignal::Insert(batch, _))) => {
+                    // the writer is gone so insert its batch here
+                    if let Err(e) = batch.insert_into(&self.mem.read().unwrap()) {
+                        status = Err(e);
+                    }
+                }
+                Err(_) => unreachable!(),
+            }
+        }
+        if let Err(e) = first.insert_into(&self.mem.read().unwrap()) {
+            status = Err(e);
+        }
+        for _ in 0..waiting {
+            match recv.recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => status = Err(e),
+                Err(e) => {
+                    status = Err(WickErr::new_from_raw(Status::Unexpected, None, Box::new(e)))
+                }
+            }
+        }
+        status
+    }
+
+    // Make sure there is enough space in memtable.
+    // This method acquires the mutex of VersionSet and deliver it to the caller.
+    fn make_room_for_write(&self, mut force: bool) -> Result<MutexGuard<VersionSet>> {
+        let forced = force;
+        let mut allow_delay = !force;
+        // The time spent on waiting, which is recorded as a write stall unless forced
+        let mut stall = Duration::default();
+        let mut versions = self.versions.lock().unwrap();
+        loop {
+            // The guard in the condition of `if let` lives until the end of the whole
+            // `if` chain so take the error out first
+            let bg_error = self.bg_error.read().unwrap().clone();
+            if let Some(e) = bg_error {
+                if !forced {
+                    self.statistics.record_write_stall(stall);
+                }
+                return Err(e);
+            } else if allow_delay
+                && versions.level_files_count(0) >= self.options.level0_slowdown_writes_trigger
+            {
+                // We are getting close to hitting a hard limit on the number of
+                // L0 files.  Rather than delaying a single write by several
+                // seconds when we hit the hard limit, start delaying each
+                // individual write by 1ms to reduce latency variance.  Also,
+                // this delay hands over some CPU to the compaction thread in
+                // case it is sharing the same core as the writer.
+                let start = Instant::now();
+                thread::sleep(Duration::from_micros(1000));
+                stall += start.elapsed();
+                allow_delay = false; // do not delay a single write more than once
+            } else if !forced
+                && versions.level_files_count(0) >= self.options.level0_stop_writes_trigger
+            {
+                // Block the writes until the L0 compaction drains the files even
+                // if there is room in current memtable
+                info!("Too many L0 files; waiting...");
+                let start = Instant::now();
+                versions = self.background_work_finished_signal.wait(versions).unwrap();
+                stall += start.elapsed();
+            } else if !force
+                && self.mem.read().unwrap().approximate_memory_usage()
+                    <= self.options.write_buffer_size
+            {
+                // There is room in current memtable
+                break;
+            } else if force && self.mem.read().unwrap().is_empty() {
+                // Nothing to be flushed in current memtable
+                break;
+            } else if self.im_mem.read().unwrap().len() >= self.options.max_write_buffer_number {
+                // There are too many immutable memtables waiting for being flushed
+                info!("Current memtable full; waiting...");
+                let start = Instant::now();
+                versions = self.background_work_finished_signal.wait(versions).unwrap();
+                stall += start.elapsed();
+            } else if versions.level_files_count(0) >= self.options.level0_stop_writes_trigger {
+                info!("Too many L0 files; waiting...");
+                let start = Instant::now();
+                versions = self.background_work_finished_signal.wait(versions).unwrap();
+                stall += start.elapsed();
+            } else {
+                // there must be no prev log
+                let new_log_num = versions.get_next_file_number();
+                let writer = self.new_log_writer(&mut versions, new_log_num)?;
+                versions.set_next_file_number(new_log_num + 1);
+                versions.record_writer = Some(writer);
+                // rotate the mem to immutable mem
+                {
+                    let mut mem = self.mem.write().unwrap();
+                    let memtable = mem::replace(&mut *mem, self.new_memtable());
+                    self.im_mem.write().unwrap().push_back(ImmutableMemTable {
+                        mem: memtable,
+                        log_number: new_log_num,
+                    });
+                }
+                force = false; // do not force another compaction if have room
+                self.maybe_schedule_compaction();
+            }
+        }
+        if !forced {
+            self.statistics.record_write_stall(stall);
+        }
+        Ok(versions)
+    }
+
+    // Compact the oldest immutable memory table to level0 files.
+    // The table is built without holding the lock of `VersionSet` so that
+    // the writes are able to go on into the memtable.
+    fn compact_mem_table(&self) {
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        let (iter, range_tombstones, log_number) = match self.im_mem.read().unwrap().front() {
+            Some(im_mem) => (
+                im_mem.mem.iter(),
+                im_mem.mem.range_tombstones(),
+                im_mem.log_number,
+            ),
+            None => return,
+        };
+        let (base, mut meta) = {
+            let mut versions = self.versions.lock().unwrap();
+            let number = versions.inc_next_file_number();
+            versions.pending_outputs.insert(number);
+            let meta = FileMetaData {
+                number,
+                ..Default::default()
+            };
+            (versions.current(), meta)
+        };
+        let now = SystemTime::now();
+        let (level, build_result) = VersionSet::build_level0_file(
+            self.options.clone(),
+            self.db_name.as_str(),
+            self.table_cache.clone(),
+            &base,
+            iter,
+            range_tombstones.as_slice(),
+            &mut meta,
+        );
+        let mut versions = self.versions.lock().unwrap();
+        versions.pending_outputs.remove(&meta.number);
+        versions.record_level0_file(
+            level,
+            &meta,
+            now.elapsed().unwrap().as_micros() as u64,
+            build_result.is_ok(),
+            &mut edit,
+        );
+        match build_result {
+            Ok(()) => {
+                if self.is_shutting_down.load(Ordering::Acquire) {
+                    self.record_bg_error(WickErr::new(
+                        Status::IOError,
+                        Some("Deleting DB during memtable compaction"),
+                    ))
+                } else {
+                    // The logs older than the one created when the memtable was
+                    // rotated are obsolete after the memtable is flushed
+                    edit.prev_log_number = Some(0);
+                    edit.log_number = Some(log_number);
+                    match versions.log_and_apply(&mut edit) {
+                        Ok(()) => {
+                            self.im_mem.write().unwrap().pop_front();
+                            self.delete_obsolete_files(versions);
+                            if meta.file_size > 0 {
+                                self.statistics.record_flush(meta.file_size);
+                                let info = FlushJobInfo {
+                                    file: TableFileInfo::new(&self.db_name, level, &meta),
+                                };
+                                for listener in self.options.listeners.iter() {
+                                    listener.on_flush_completed(&info);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            self.record_bg_error(e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                self.record_bg_error(e);
+            }
+        }
+    }
+
+    // The complete compaction process
+    fn background_compaction(&self) {
+        if !self.im_mem.read().unwrap().is_empty() {
+            // minor compaction
+            self.compact_mem_table();
+            return;
+        }
+        let mut versions = self.versions.lock().unwrap();
+        if let Some(m) = versions.manual_compaction.as_mut() {
+            if !m.done && m.is_canceled() {
+                // Canceled before the next compaction of the range
+                m.done = true;
+                m.aborted = true;
+            }
+        }
+        // The manual compaction is left in the `VersionSet` so that the waiting
+        // `compact_range` is able to see whether it's done
+        let manual = versions
+            .manual_compaction
+            .as_ref()
+            .filter(|m| !m.done)
+            .map(|m| {
+                (
+                    m.level,
+                    m.in_place,
+                    m.begin.clone(),
+                    m.end.clone(),
+                    m.options.clone(),
+                )
+            });
+        let is_manual = manual.is_some();
+        let mut manual_end = None;
+        let mut manual_progress = None;
+        let compaction = match manual {
+            Some((level, in_place, begin, end, options)) => {
+                let mut compaction =
+                    versions.compact_range(level, begin.clone(), end.clone(), in_place);
+                if let Some(c) = compaction.as_mut() {
+                    c.canceled = options.canceled;
+                }
+                manual_progress = options.progress;
+                let begin = if let Some(begin) = &begin {
+                    format!("{:?}", begin)
+                } else {
+                    "(begin)".to_owned()
+                };
+                let end = if let Some(end) = &end {
+                    format!("{:?}", end)
+                } else {
+                    "(end)".to_owned()
+                };
+                let stop = if let Some(c) = &compaction {
+                    let largest = c.inputs[CompactionInputsRelation::Source as usize]
+                        .last()
+                        .unwrap()
+                        .largest
+                        .clone();
+                    let stop = format!("{:?}", largest);
+                    manual_end = Some(largest);
+                    stop
+                } else {
+                    "(end)".to_owned()
+                };
+                info!(
+                    "Manual compaction at level-{} from {} .. {}; will stop at {}",
+                    level, begin, end, stop
+                );
+                versions.manual_compaction.as_mut().unwrap().done = compaction.is_none();
+                compaction
+            }
+            None => versions.pick_compaction(),
+        };
+        // The compaction reported to the listeners after the lock is released
+        let mut completed = None;
+        if let Some(mut compaction) = compaction {
+            if !is_manual && compaction.is_trivial_move() {
+                // just move file to next level
+                let f = compaction.inputs[CompactionInputsRelation::Source as usize]
+                    .first()
+                    .unwrap();
+                compaction.edit.delete_file(compaction.level, f.number);
+                compaction.edit.add_file(compaction.level + 1, f);
+                match versions.log_and_apply(&mut compaction.edit) {
+                    Ok(()) => {
+                        self.statistics.record_trivial_move();
+                        completed = Some(compaction.job_info(&self.db_name, true))
+                    }
+                    Err(e) => {
+                        debug!("Error in compaction: {:?}", &e);
+                        self.record_bg_error(e);
+                    }
+                }
+                let current_summary = versions.current().level_summary();
+                info!(
+                    "Moved #{} to level-{} {} bytes, current level summary: {}",
+                    f.number,
+                    compaction.level + 1,
+                    f.file_size,
+                    current_summary
+                )
+            } else {
+                let level = compaction.level;
+                info!(
+                    "Compacting {}@{} + {}@{} files into level {}",
+                    compaction.inputs[CompactionInputsRelation::Source as usize].len(),
+                    level,
+                    compaction.inputs[CompactionInputsRelation::Parent as usize].len(),
+                    level + 1,
+                    compaction.output_level,
+                );
+                set_compaction_snapshots(&mut compaction, &mut versions);
+                versions.compacting_files.extend(compaction.input_numbers());
+                // The `VersionSet` is locked again by the compaction when needed
+                mem::drop(versions);
+                let (v, status) = self.do_compaction(&mut compaction);
+                versions = v;
+                versions.compacting_files.clear();
+                match &status {
+                    Ok(()) => {
+                        self.statistics.record_compaction(
+                            compaction.output_level,
+                            compaction.bytes_read(),
+                            compaction.bytes_written(),
+                        );
+                        completed = Some(compaction.job_info(&self.db_name, false));
+                        if let Some(progress) = &manual_progress {
+                            progress.add(compaction.bytes_read(), compaction.bytes_written());
+                        }
+                        if let Some(m) = versions.manual_compaction.as_mut().filter(|_| is_manual) {
+                            // The whole range is rewritten in one shot
+                            m.done |= m.in_place;
+                        }
+                    }
+                    Err(e) if e.status() == Status::Aborted => {
+                        info!("Manual compaction at level-{} is canceled", level);
+                        if let Some(m) = versions.manual_compaction.as_mut() {
+                            m.done = true;
+                            m.aborted = true;
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+            if !self.is_shutting_down.load(Ordering::Acquire) {
+                if let Some(e) = self.bg_error.read().unwrap().as_ref() {
+                    info!("Compaction error: {:?}", e)
+                }
+            }
+        }
+        if is_manual {
+            if let Some(m) = versions.manual_compaction.as_mut() {
+                if !m.done {
+                    // Only part of the range is compacted so we start from the
+                    // end of it next time
+                    m.begin = manual_end;
+                }
+            }
+        }
+        self.delete_obsolete_files(versions);
+        if let Some(info) = completed {
+            for listener in self.options.listeners.iter() {
+                listener.on_compaction_completed(&info);
+            }
+        }
+    }
+
+    // Merging files in level n into file in level n + 1 and
+    // keep the still-in-use files.
+    // Returns the lock of `VersionSet` with the result of the compaction.
+    fn do_compaction(&self, c: &mut Compaction) -> (MutexGuard<VersionSet>, Result<()>) {
+        let now = SystemTime::now();
+        // The compaction is not split if the range tombstones are unable to be read,
+        // and the error is reported by the compaction reading the same files
+        let boundaries = match c.input_range_tombstones(&self.table_cache) {
+            Ok(tombstones) => c.subcompaction_boundaries(
+                self.internal_comparator.user_comparator.as_ref(),
+                tombstones.as_slice(),
+            ),
+            Err(_) => vec![],
+        };
+        // The memtables flushed during a compaction into level 0 would be older
+        // than the outputs by the file numbers
+        let flush_memtable = c.output_level > 0;
+        let (mut versions, mut status) = loop {
+            let (status, mem_compaction_duration) = if boundaries.is_empty() {
+                self.run_compaction(c, flush_memtable)
+            } else {
+                self.run_subcompactions(c, boundaries.clone(), flush_memtable)
+            };
+            // Calculate the stats of this compaction
             let mut versions = self.versions.lock().unwrap();
-            if let Some(mut compaction) = {
-                match versions.manual_compaction.take() {
-                    // manul compaction
-                    Some(mut manual) => {
-                        if manual.done {
-                            versions.pick_compaction()
-                        } else {
-                            let compaction = versions.compact_range(
-                                manual.level,
-                                manual.begin.clone(),
-                                manual.end.clone(),
+            versions.compaction_stats[c.output_level].accumulate(
+                now.elapsed().unwrap().as_micros() as u64 - mem_compaction_duration,
+                c.bytes_read(),
+                c.bytes_written(),
+            );
+            if status.is_ok()
+                && c.filtered
+                && versions.snapshots.last_seq() > c.newest_snapshot.unwrap_or(0)
+            {
+                // A snapshot taken during the compaction sees the entries filtered
+                // out, so the outputs are discarded and the compaction runs again.
+                info!(
+                    "Compaction at level {} is rerun for the new snapshots",
+                    c.level
+                );
+                for output in c.outputs.iter() {
+                    versions.pending_outputs.remove(&output.number);
+                }
+                *c = c.new_subcompaction(c.start.clone(), c.end.clone());
+                set_compaction_snapshots(c, &mut versions);
+                continue;
+            }
+            break (versions, status);
+        };
+        if status.is_ok() {
+            info!(
+                "Compacted {}@{} + {}@{} files => {} bytes at level {}",
+                c.inputs[CompactionInputsRelation::Source as usize].len(),
+                c.level,
+                c.inputs[CompactionInputsRelation::Parent as usize].len(),
+                c.level + 1,
+                c.total_bytes,
+                c.output_level,
+            );
+            c.apply_to_edit();
+            status = versions.log_and_apply(&mut c.edit);
+        }
+        if let Err(e) = &status {
+            // The outputs of a canceled compaction are simply discarded
+            if e.status() != Status::Aborted {
+                self.record_bg_error(e.clone())
+            }
+        }
+
+        let summary = versions.current().level_summary();
+        info!("compacted to : {}", summary);
+
+        // Close unclosed table builder and remove files in `pending_outputs`
+        if let Some(builder) = c.builder.as_mut() {
+            builder.close()
+        }
+        for output in c.outputs.iter() {
+            versions.pending_outputs.remove(&output.number);
+        }
+        (versions, status)
+    }
+
+    // Splits the compaction at `boundaries` and runs the subcompactions in parallel.
+    // The first subcompaction runs on the current thread, which also flushes the
+    // immutable memtables in the meantime if `flush_memtable` is set. The outputs
+    // of the subcompactions are collected into `c` in the key order.
+    //
+    // Returns the result with the time spent on flushing the memtables.
+    fn run_subcompactions(
+        &self,
+        c: &mut Compaction,
+        boundaries: Vec<Vec<u8>>,
+        flush_memtable: bool,
+    ) -> (Result<()>, u64) {
+        let mut subs = Vec::with_capacity(boundaries.len() + 1);
+        let mut start = None;
+        for boundary in boundaries {
+            subs.push(c.new_subcompaction(start, Some(boundary.clone())));
+            start = Some(boundary);
+        }
+        subs.push(c.new_subcompaction(start, None));
+        info!(
+            "Compacting level {} in {} subcompactions",
+            c.level,
+            subs.len()
+        );
+        let (first, rest) = subs.split_first_mut().unwrap();
+        let (mut status, mem_compaction_duration, results) = thread::scope(|scope| {
+            let handles: Vec<_> = rest
+                .iter_mut()
+                .map(|sub| scope.spawn(move || self.run_compaction(sub, false)))
+                .collect();
+            let (status, duration) = self.run_compaction(first, flush_memtable);
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            (status, duration, results)
+        });
+        for (result, _) in results {
+            if status.is_ok() {
+                status = result;
+            }
+        }
+        for sub in subs {
+            c.outputs.extend(sub.outputs);
+            c.total_bytes += sub.total_bytes;
+            c.filtered |= sub.filtered;
+        }
+        (status, mem_compaction_duration)
+    }
+
+    // Compacts the inputs of `c` in the user key range of `c` into the output files.
+    // The immutable memtables are flushed in the meantime if `flush_memtable` is set.
+    //
+    // Returns the result with the time spent on flushing the memtables.
+    fn run_compaction(&self, c: &mut Compaction, flush_memtable: bool) -> (Result<()>, u64) {
+        let icmp = self.internal_comparator.clone();
+        let ucmp = icmp.user_comparator.as_ref();
+        let mut input_iter = c.new_input_iterator(icmp.clone(), self.table_cache.clone());
+        let mut mem_compaction_duration = 0;
+
+        // the current user key to be compacted
+        let mut current_ukey = vec![];
+        let mut has_current_ukey = false;
+        let mut last_sequence_for_key = u64::max_value();
+        // the range tombstones which might cover the remaining keys
+        let mut range_tombstones: Vec<RangeTombstone> = vec![];
+
+        match c.start.clone() {
+            Some(start) => {
+                // The tombstones before the range might cover the keys in the range
+                match self.range_tombstones_before(c, start.as_slice()) {
+                    Ok(tombstones) => range_tombstones = tombstones,
+                    Err(e) => return (Err(e), 0),
+                }
+                let seek_key = InternalKey::new(
+                    &Slice::from(start.as_slice()),
+                    MAX_KEY_SEQUENCE,
+                    VALUE_TYPE_FOR_SEEK,
+                );
+                input_iter.seek(&Slice::from(seek_key.data()));
+            }
+            None => input_iter.seek_to_first(),
+        }
+        let mut status = Ok(());
+        // Iterate every key
+        while input_iter.valid() && !self.is_shutting_down.load(Ordering::Acquire) {
+            if c.is_canceled() {
+                status = Err(WickErr::new(
+                    Status::Aborted,
+                    Some("Compaction is canceled"),
+                ));
+                break;
+            }
+            // Prioritize immutable compaction work
+            if flush_memtable && !self.im_mem.read().unwrap().is_empty() {
+                let imm_start = SystemTime::now();
+                self.compact_mem_table();
+                mem_compaction_duration = imm_start.elapsed().unwrap().as_micros() as u64;
+            }
+            let ikey = input_iter.key();
+            if let Some(end) = &c.end {
+                if ucmp.compare(extract_user_key(ikey.as_slice()).as_slice(), end.as_slice())
+                    != CmpOrdering::Less
+                {
+                    break;
+                }
+            }
+            // Checkout whether we need rotate a new output file
+            if c.should_stop_before(&ikey, icmp.clone())
+                && c.builder.is_some()
+                && c.can_finish_output_before(ucmp, ikey.as_slice())
+            {
+                status = self.finish_output_file(c, true);
+                if status.is_err() {
+                    break;
+                }
+            }
+            let mut drop = false;
+            match ParsedInternalKey::decode_from(ikey.clone()) {
+                Some(key) => {
+                    if key.value_type == ValueType::RangeDeletion {
+                        let end = input_iter.value();
+                        // A range tombstone is kept while the keys it covers might live in
+                        // the files which are not involved in this compaction or be seen
+                        // by a snapshot. Otherwise the keys it covers in the inputs are
+                        // dropped below and so is the tombstone.
+                        drop = key.seq <= c.oldest_snapshot_alive
+                            && !c.range_exist_in_deeper_level(
+                                key.user_key.as_slice(),
+                                end.as_slice(),
                             );
-                            manual.done = compaction.is_none();
-                            let begin = if let Some(begin) = &manual.begin {
-                                format!("{:?}", begin)
-                            } else {
-                                "(begin)".to_owned()
-                            };
-                            let end = if let Some(end) = &manual.end {
-                                format!("{:?}", end)
-                            } else {
-                                "(end)".to_owned()
-                            };
-                            let stop = if let Some(c) = &compaction {
-                                format!(
-                                    "{:?}",
-                                    c.inputs[CompactionInputsRelation::Source as usize]
-                                        .last()
-                                        .unwrap()
-                                        .largest
-                                        .clone()
+                        range_tombstones.push(RangeTombstone::new(
+                            key.user_key.as_slice(),
+                            end.as_slice(),
+                            key.seq,
+                        ));
+                    } else {
+                        if !has_current_ukey
+                            || ucmp.compare(key.user_key.as_slice(), current_ukey.as_slice())
+                                != CmpOrdering::Equal
+                        {
+                            // First occurrence of this user key
+                            current_ukey = Vec::from(key.user_key.as_slice());
+                            has_current_ukey = true;
+                            last_sequence_for_key = u64::MAX;
+                            // Tombstones are sorted by their start keys so the ones
+                            // ending before the current key cover nothing from now on
+                            range_tombstones.retain(|t| {
+                                ucmp.compare(key.user_key.as_slice(), t.end.as_slice())
+                                    == CmpOrdering::Less
+                            });
+                        }
+                        // Keep the still-in-use old key or not
+                        if last_sequence_for_key <= c.oldest_snapshot_alive
+                            || (key.value_type == ValueType::Deletion
+                                && key.seq <= c.oldest_snapshot_alive
+                                && !c.key_exist_in_deeper_level(&key.user_key))
+                        {
+                            // For this user key:
+                            // (1) there is no data in higher levels
+                            // (2) data in lower levels will have larger sequence numbers
+                            // (3) data in layers that are being compacted here and have
+                            //     smaller sequence numbers will be dropped in the next
+                            //     few iterations of this loop
+                            //     (by last_sequence_for_key <= c.smallest_snapshot above).
+                            // Therefore this deletion marker is obsolete and can be dropped.
+                            drop = true
+                        } else if let Some(seq) = max_covering_tombstone_seq(
+                            range_tombstones.as_slice(),
+                            ucmp,
+                            key.user_key.as_slice(),
+                            c.oldest_snapshot_alive,
+                        ) {
+                            // The key is deleted by a range tombstone visible to all the snapshots
+                            drop = seq > key.seq
+                        }
+                        last_sequence_for_key = key.seq;
+                    }
+                    if !drop {
+                        if key.value_type == ValueType::Merge && key.seq <= c.oldest_snapshot_alive
+                        {
+                            // The operands visible to all the snapshots can be folded.
+                            // `input_iter` is advanced to the first entry not folded.
+                            status = self
+                                .merge_compaction_entries(
+                                    c,
+                                    &mut input_iter,
+                                    range_tombstones.as_slice(),
                                 )
-                            } else {
-                                "(end)".to_owned()
-                            };
-                            info!(
-                                "Manual compaction at level-{} from {} .. {}; will stop at {}",
-                                manual.level, begin, end, stop
-                            );
-                            is_manual = true;
-                            versions.manual_compaction = Some(manual);
-                            compaction
+                                .and_then(|entries| {
+                                    for (k, v) in entries.iter() {
+                                        self.add_compaction_output(c, k.as_slice(), v.as_slice())?;
+                                    }
+                                    Ok(())
+                                });
+                            if status.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        status = match self.filter_compaction_entry(
+                            c,
+                            &key,
+                            input_iter.value().as_slice(),
+                        ) {
+                            Decision::Keep => self.add_compaction_output(
+                                c,
+                                ikey.as_slice(),
+                                input_iter.value().as_slice(),
+                            ),
+                            Decision::Remove => {
+                                if c.newest_snapshot.is_none()
+                                    && !c.key_exist_in_deeper_level(&key.user_key)
+                                {
+                                    // No live snapshot and no deeper level can see an
+                                    // older version of the key, so it's safe to drop.
+                                    Ok(())
+                                } else {
+                                    // The older versions are shadowed by a deletion.
+                                    let deletion = InternalKey::new(
+                                        &key.user_key,
+                                        key.seq,
+                                        ValueType::Deletion,
+                                    );
+                                    self.add_compaction_output(c, deletion.data(), &[])
+                                }
+                            }
+                            Decision::ChangeValue(value) => {
+                                self.add_compaction_output(c, ikey.as_slice(), value.as_slice())
+                            }
+                        };
+                        if status.is_err() {
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    current_ukey.clear();
+                    has_current_ukey = false;
+                    last_sequence_for_key = u64::max_value();
+                }
+            }
+            input_iter.next();
+        }
+        // TODO: simplify the implementation
+        if status.is_ok() && self.is_shutting_down.load(Ordering::Acquire) {
+            status = Err(WickErr::new(
+                Status::IOError,
+                Some("Deleting DB during compaction"),
+            ))
+        }
+        if status.is_ok() {
+            status = input_iter.status()
+        }
+        if c.builder.is_some() {
+            let input_ok = status.is_ok();
+            let finished = self.finish_output_file(c, input_ok);
+            if status.is_ok() {
+                status = finished;
+            }
+        }
+        (status, mem_compaction_duration)
+    }
+
+    // Applies the compaction filter on the given entry if it's a value invisible
+    // to all the live snapshots
+    fn filter_compaction_entry(
+        &self,
+        c: &mut Compaction,
+        key: &ParsedInternalKey,
+        value: &[u8],
+    ) -> Decision {
+        match &self.options.compaction_filter {
+            Some(filter)
+                if key.value_type == ValueType::Value
+                    && !matches!(c.newest_snapshot, Some(seq) if key.seq <= seq) =>
+            {
+                let decision = filter.filter(c.level, key.user_key.as_slice(), value);
+                if decision != Decision::Keep {
+                    c.filtered = true;
+                }
+                decision
+            }
+            _ => Decision::Keep,
+        }
+    }
+
+    // Returns the range tombstones in the inputs of `c` which start before `key`
+    // and end after it
+    fn range_tombstones_before(&self, c: &Compaction, key: &[u8]) -> Result<Vec<RangeTombstone>> {
+        let ucmp = self.internal_comparator.user_comparator.as_ref();
+        let mut iter =
+            c.new_input_iterator(self.internal_comparator.clone(), self.table_cache.clone());
+        iter.seek_to_first();
+        let mut tombstones = vec![];
+        while iter.valid() {
+            if let Some(k) = ParsedInternalKey::decode_from(iter.key()) {
+                if ucmp.compare(k.user_key.as_slice(), key) != CmpOrdering::Less {
+                    break;
+                }
+                if k.value_type == ValueType::RangeDeletion
+                    && ucmp.compare(key, iter.value().as_slice()) == CmpOrdering::Less
+                {
+                    tombstones.push(RangeTombstone::new(
+                        k.user_key.as_slice(),
+                        iter.value().as_slice(),
+                        k.seq,
+                    ));
+                }
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(tombstones)
+    }
+
+    // Adds an entry to the current output file of the compaction
+    fn add_compaction_output(&self, c: &mut Compaction, key: &[u8], value: &[u8]) -> Result<()> {
+        // Rotate a new output file if the current one reaches the target size of the
+        // output level and doesn't overlap `key`. A single entry larger than the target
+        // still makes up a file.
+        if let Some(builder) = c.builder.as_ref() {
+            if builder.file_size() >= self.options.max_file_size_for_level(c.output_level)
+                && c.can_finish_output_before(
+                    self.internal_comparator.user_comparator.as_ref(),
+                    key,
+                )
+            {
+                self.finish_output_file(c, true)?;
+            }
+        }
+        // Open output file if necessary
+        if c.builder.is_none() {
+            self.versions
+                .lock()
+                .unwrap()
+                .open_compaction_output_file(c)?;
+        }
+        let last = c.outputs.len() - 1;
+        // TODO: InternalKey::decoded_from adds extra cost of copying
+        let is_first = c.builder.as_ref().unwrap().num_entries() == 0;
+        if is_first {
+            // We have a brand new builder so use current key as smallest
+            c.outputs[last].smallest = Arc::new(InternalKey::decoded_from(key));
+        }
+        // Keep updating the largest, which covers the range tombstones as well
+        let largest = match tombstone_end(key, value) {
+            Some(end) => tombstone_largest_key(end),
+            None => InternalKey::decoded_from(key),
+        };
+        if is_first
+            || self
+                .internal_comparator
+                .compare(largest.data(), c.outputs[last].largest.data())
+                == CmpOrdering::Greater
+        {
+            c.outputs[last].largest = Arc::new(largest);
+        }
+        c.builder.as_mut().unwrap().add(key, value)
+    }
+
+    // Folds the entries of the user key from the current position of `iter`, which must
+    // be a merge operand visible to all the snapshots. Since the entries are sorted from
+    // the newest to the oldest, the folding stops at the first value or deletion, and
+    // `iter` is advanced to the first entry not folded.
+    //
+    // Returns the entries to be written into the output.
+    fn merge_compaction_entries(
+        &self,
+        c: &mut Compaction,
+        iter: &mut dyn Iterator,
+        range_tombstones: &[RangeTombstone],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let merge_operator = match &self.options.merge_operator {
+            Some(m) => m.clone(),
+            None => {
+                return Err(WickErr::new(
+                    Status::NotSupported,
+                    Some("merge operands found but merge operator is not set"),
+                ))
+            }
+        };
+        let ucmp = self.internal_comparator.user_comparator.as_ref();
+        let first = ParsedInternalKey::decode_from(iter.key()).unwrap();
+        let user_key = Vec::from(first.user_key.as_slice());
+        let seq = first.seq;
+        // the merge entries from the newest to the oldest
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        let mut base = None;
+        let mut base_found = false;
+        while iter.valid() {
+            let ikey = iter.key();
+            match ParsedInternalKey::decode_from(ikey.clone()) {
+                Some(pkey)
+                    if ucmp.compare(pkey.user_key.as_slice(), user_key.as_slice())
+                        == CmpOrdering::Equal =>
+                {
+                    if pkey.value_type == ValueType::RangeDeletion
+                        || max_covering_tombstone_seq(
+                            range_tombstones,
+                            ucmp,
+                            user_key.as_slice(),
+                            c.oldest_snapshot_alive,
+                        )
+                        .is_some_and(|t| t > pkey.seq)
+                    {
+                        // The older entries are deleted by a range tombstone, which
+                        // is left to the caller
+                        base_found = true;
+                        break;
+                    }
+                    match pkey.value_type {
+                        ValueType::Merge => entries.push((
+                            Vec::from(ikey.as_slice()),
+                            Vec::from(iter.value().as_slice()),
+                        )),
+                        ValueType::Value => {
+                            base = Some(Vec::from(iter.value().as_slice()));
+                            base_found = true;
                         }
+                        ValueType::Deletion => base_found = true,
+                        _ => {}
+                    }
+                    iter.next();
+                    if base_found {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        if !base_found && c.key_exist_in_deeper_level(&Slice::from(user_key.as_slice())) {
+            // The existing value might live in the deeper levels so only the
+            // operands could be combined
+            let mut operands = entries.iter().rev().map(|(_, v)| v);
+            let mut merged = operands.next().unwrap().clone();
+            for operand in operands {
+                match merge_operator.partial_merge(
+                    user_key.as_slice(),
+                    merged.as_slice(),
+                    operand.as_slice(),
+                ) {
+                    Some(m) => merged = m,
+                    None => return Ok(entries),
+                }
+            }
+            let newest = entries.swap_remove(0).0;
+            return Ok(vec![(newest, merged)]);
+        }
+        let operands: Vec<Vec<u8>> = entries.drain(..).rev().map(|(_, v)| v).collect();
+        match merge_operator.full_merge(user_key.as_slice(), base.as_deref(), operands.as_slice()) {
+            Some(value) => {
+                let key =
+                    InternalKey::new(&Slice::from(user_key.as_slice()), seq, ValueType::Value);
+                Ok(vec![(Vec::from(key.data()), value)])
+            }
+            None => Err(WickErr::new(
+                Status::Corruption,
+                Some("merge operator failed to merge the operands"),
+            )),
+        }
+    }
+
+    // Creates an empty memtable by the `memtable_factory` in options
+    fn new_memtable(&self) -> MemTable {
+        MemTable::with_factory(
+            self.internal_comparator.clone(),
+            self.options.memtable_factory.as_ref(),
+        )
+    }
+
+    // Clears the background error and flushes all the memtables into new tables,
+    // which also switches to a new WAL in case the error comes from the current one
+    fn resume(&self) -> Result<()> {
+        if self.read_only {
+            return Err(WickErr::new(
+                Status::NotSupported,
+                Some("resume a read-only db"),
+            ));
+        }
+        {
+            // Locks the `VersionSet` so that no background work is picked meanwhile
+            let _versions = self.versions.lock().unwrap();
+            match self.bg_error.write().unwrap().take() {
+                Some(e) => info!("Resuming from the background error: {:?}", e),
+                None => return Ok(()),
+            }
+            self.bg_error_resume_count.store(0, Ordering::Release);
+        }
+        // flush the immutable memtables left by the failed background work
+        self.maybe_schedule_compaction();
+        self.force_compact_mem_table()
+    }
+
+    // Called by the background thread after a background work. If the work fails with
+    // a `Status::IOError`, clears the error after a backoff so that the work is retried,
+    // at most `max_bg_error_resume_count` times in a row.
+    fn maybe_auto_resume(&self) {
+        let transient = match self.bg_error.read().unwrap().as_ref() {
+            Some(e) => e.status() == Status::IOError,
+            None => {
+                self.bg_error_resume_count.store(0, Ordering::Release);
+                return;
+            }
+        };
+        let attempt = self.bg_error_resume_count.load(Ordering::Acquire);
+        if !transient || attempt >= self.options.max_bg_error_resume_count {
+            return;
+        }
+        self.bg_error_resume_count
+            .store(attempt + 1, Ordering::Release);
+        let backoff = Duration::from_millis(self.options.bg_error_resume_retry_interval)
+            * 2u32.pow(attempt.min(16) as u32);
+        let deadline = Instant::now() + backoff;
+        while Instant::now() < deadline {
+            if self.is_shutting_down.load(Ordering::Acquire) {
+                return;
+            }
+            thread::sleep(
+                Duration::from_millis(10).min(deadline.saturating_duration_since(Instant::now())),
+            );
+        }
+        let _versions = self.versions.lock().unwrap();
+        if let Some(e) = self.bg_error.write().unwrap().take() {
+            info!(
+                "Retrying the background work (attempt {}) after error: {:?}",
+                attempt + 1,
+                e
+            );
+        }
+    }
+
+    // Replace the `bg_error` with new WickErr if it's None
+    fn record_bg_error(&self, e: WickErr) {
+        let old = self.bg_error.read().unwrap();
+        if old.is_none() {
+            mem::drop(old);
+            let mut x = self.bg_error.write().unwrap();
+            *x = Some(e);
+            self.background_work_finished_signal.notify_all();
+        }
+    }
+
+    // Check whether db needs to run a compaction. DB will run a compaction when:
+    // 1. no background compaction is running
+    // 2. DB is not shutting down
+    // 3. no error has been encountered
+    // 4. there is an immutable table or a manual compaction request or current version needs to be compacted
+    fn maybe_schedule_compaction(&self) {
+        if self.read_only
+            // No compaction in a read-only DB
+        || self.background_compaction_scheduled.load(Ordering::Acquire)
+            // Already scheduled
+        || self.is_shutting_down.load(Ordering::Acquire)
+            // DB is being shutting down
+        || self.bg_error.read().unwrap().is_some()
+            // Got err
+        ||  (self.im_mem.read().unwrap().is_empty()
+            && !self.versions.lock().unwrap().needs_compaction())
+        {
+            // No work needs to be done
+        } else {
+            self.background_compaction_scheduled
+                .store(true, Ordering::Release);
+            if let Err(e) = self.do_compaction.0.send(()) {
+                error!(
+                    "[schedule compaction] Fail sending signal to compaction channel: {}",
+                    e
+                )
+            }
+        }
+    }
+
+    // Finish the current output file by calling `buidler.finish` and insert it into the table cache.
+    // The file is abandoned if the inputs are not read successfully.
+    fn finish_output_file(&self, compact: &mut Compaction, input_ok: bool) -> Result<()> {
+        assert!(!compact.outputs.is_empty());
+        assert!(compact.builder.is_some());
+        let current_entries = compact.builder.as_ref().unwrap().num_entries();
+        let status = if input_ok {
+            compact.builder.as_mut().unwrap().finish(true)
+        } else {
+            compact.builder.as_mut().unwrap().close();
+            Ok(())
+        };
+        let current_bytes = compact.builder.as_ref().unwrap().file_size();
+        // update current output
+        let length = compact.outputs.len();
+        compact.outputs[length - 1].file_size = current_bytes;
+        compact.outputs[length - 1].has_range_tombstones =
+            compact.builder.as_ref().unwrap().has_range_tombstones();
+        compact.total_bytes += current_bytes;
+        compact.builder = None;
+        if status.is_ok() && current_entries > 0 {
+            let output_number = compact.outputs[length - 1].number;
+            // make sure that the new file is in the cache
+            let mut it = self.table_cache.new_iter(
+                Rc::new(ReadOptions {
+                    fill_cache: false,
+                    ..Default::default()
+                }),
+                compact.output_level,
+                output_number,
+                current_bytes,
+            );
+            it.status()?;
+            info!(
+                "Generated table #{}@{}: {} keys, {} bytes",
+                output_number, compact.level, current_entries, current_bytes
+            );
+        }
+        status
+    }
+}
+
+// A wrapper struct for scheduling `WriteBatch`
+struct BatchTask {
+    batch: WriteBatch,
+    signal: Sender<BatchSignal>,
+    options: WriteOptions,
+}
+
+// The messages sent to a writer waiting in `schedule_batch_and_wait`
+enum BatchSignal {
+    // Inserts the batch with the sequence number set into the memtable and
+    // sends the result back by the sender
+    Insert(WriteBatch, Sender<Result<()>>),
+    // The write is finished with the result
+    Done(Result<()>),
+}
+
+impl BatchTask {
+    fn new(batch: WriteBatch, signal: Sender<BatchSignal>, options: WriteOptions) -> Self {
+        Self {
+            batch,
+            signal,
+            options,
+        }
+    }
+}
+
+/// Build a Table file from the contents of `iter`.  The generated file
+/// will be named according to `meta.number`.  On success, the rest of
+/// meta will be filled with metadata about the generated table.
+/// If no data is present in iter, `meta.file_size` will be set to
+/// zero, and no Table file will be produced.
+/// The blocks of the table are compressed by `compression`.
+// An immutable memtable waiting for being flushed
+struct ImmutableMemTable {
+    mem: MemTable,
+    // The number of the log file created when the memtable is rotated. The older
+    // log files are obsolete after the memtable is flushed.
+    log_number: u64,
+}
+
+// An external sstable to be ingested with its user key range
+struct ExternalFile {
+    path: String,
+    table: Arc<Table>,
+    verify_checksums: bool,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+}
+
+// Sets the snapshots that the compaction `c` must preserve from the live
+// snapshots in `versions`
+fn set_compaction_snapshots(c: &mut Compaction, versions: &mut VersionSet) {
+    let snapshots = &mut versions.snapshots;
+    // Cleanup all redundant snapshots first
+    snapshots.gc();
+    if snapshots.is_empty() {
+        c.oldest_snapshot_alive = versions.get_last_sequence();
+        c.newest_snapshot = None;
+    } else {
+        c.oldest_snapshot_alive = snapshots.oldest().sequence_number();
+        c.newest_snapshot = Some(snapshots.newest().sequence_number());
+    }
+}
+
+pub(crate) fn build_table<'a>(
+    options: Arc<Options>,
+    db_name: &str,
+    table_cache: Arc<TableCache>,
+    mut iter: Box<dyn Iterator + 'a>,
+    meta: &mut FileMetaData,
+    compression: CompressionType,
+) -> Result<()> {
+    meta.file_size = 0;
+    iter.seek_to_first();
+    let file_name = generate_filename(db_name, FileType::Table, meta.number);
+    let mut status = Ok(());
+    if iter.valid() {
+        let file = options.env.create(file_name.as_str())?;
+        let mut builder = TableBuilder::new(file, compression, options.clone());
+        let icmp = InternalKeyComparator::new(options.comparator.clone());
+        let mut prev_key = Slice::default();
+        let smallest_key = iter.key();
+        // the largest key bounding the ranges of the range tombstones
+        let mut tombstones_largest: Option<InternalKey> = None;
+        while iter.valid() {
+            let key = iter.key();
+            let value = iter.value();
+            let s = builder.add(key.as_slice(), value.as_slice());
+            if s.is_err() {
+                status = s;
+                break;
+            }
+            if let Some(end) = tombstone_end(key.as_slice(), value.as_slice()) {
+                let largest = tombstone_largest_key(end);
+                let larger = match &tombstones_largest {
+                    Some(k) => icmp.compare(largest.data(), k.data()) == CmpOrdering::Greater,
+                    None => true,
+                };
+                if larger {
+                    tombstones_largest = Some(largest);
+                }
+            }
+            prev_key = key;
+            iter.next();
+        }
+        if status.is_ok() {
+            meta.smallest = Arc::new(InternalKey::decoded_from(smallest_key.as_slice()));
+            meta.largest = match tombstones_largest {
+                // the file covers the keys deleted by its range tombstones
+                Some(largest)
+                    if icmp.compare(largest.data(), prev_key.as_slice())
+                        == CmpOrdering::Greater =>
+                {
+                    Arc::new(largest)
+                }
+                _ => Arc::new(InternalKey::decoded_from(prev_key.as_slice())),
+            };
+            meta.creation_time = unix_time_secs();
+            meta.has_range_tombstones = builder.has_range_tombstones();
+            status = builder.finish(true).and_then(|_| {
+                meta.file_size = builder.file_size();
+                // make sure that the new file is in the cache
+                let mut it = table_cache.new_iter(
+                    Rc::new(ReadOptions {
+                        fill_cache: false,
+                        ..Default::default()
+                    }),
+                    0,
+                    meta.number,
+                    meta.file_size,
+                );
+                it.status()
+            })
+        }
+    }
+
+    let iter_status = iter.status();
+    if iter_status.is_err() {
+        status = iter_status;
+    };
+    if status.is_err() || meta.file_size == 0 {
+        options.env.remove(file_name.as_str())?;
+        status
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::BatchHandler;
+    use crate::cache::lru::SharedLRUCache;
+    use crate::cache::Cache;
+    use crate::compaction::pick_sorted_runs;
+    use crate::compaction::CompactionProgress;
+    use crate::db::event_listener::{CompactionJobInfo, EventListener};
+    use crate::filter::bloom::BloomFilter;
+    use crate::mem::{MemTableRep, MemTableRepFactory, SkiplistFactory};
+    use crate::options::{CacheEvictionPolicy, CompactionStyle, UniversalCompactionOptions};
+    use crate::sstable::block::Block;
+    use crate::sstable::external::ExternalTableBuilder;
+    use crate::storage::mem::MemStorage;
+    use crate::util::compaction_filter::CompactionFilter;
+    use crate::util::comparator::{
+        BytewiseComparator, Comparator, NumericComparator, ReverseComparator,
+    };
+    use crate::util::merge_operator::MergeOperator;
+    use crate::util::prefix_extractor::FixedLengthPrefixExtractor;
+    use std::collections::{HashMap, HashSet};
+    use std::path::MAIN_SEPARATOR;
+    use std::sync::atomic::{AtomicU64, AtomicUsize};
+
+    // Treats the values as decimal counters
+    struct AddOperator {}
+
+    impl AddOperator {
+        fn parse(v: &[u8]) -> u64 {
+            String::from_utf8_lossy(v).parse().unwrap()
+        }
+    }
+
+    impl MergeOperator for AddOperator {
+        fn name(&self) -> &str {
+            "AddOperator"
+        }
+
+        fn full_merge(
+            &self,
+            _key: &[u8],
+            existing_value: Option<&[u8]>,
+            operands: &[Vec<u8>],
+        ) -> Option<Vec<u8>> {
+            let base = existing_value.map_or(0, Self::parse);
+            let sum = operands.iter().fold(base, |acc, o| acc + Self::parse(o));
+            Some(sum.to_string().into_bytes())
+        }
+
+        fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+            Some(
+                (Self::parse(left) + Self::parse(right))
+                    .to_string()
+                    .into_bytes(),
+            )
+        }
+    }
+
+    fn new_test_db(mut options: Options, name: &str) -> WickDB {
+        options.env = Arc::new(MemStorage::default());
+        WickDB::open_db(options, name.to_owned()).unwrap()
+    }
+
+    fn get_str(db: &WickDB, key: &str) -> Option<String> {
+        db.get(ReadOptions::default(), Slice::from(key))
+            .unwrap()
+            .map(|v| String::from_utf8(v).unwrap())
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        use crate::db::format::InternalKeyComparator;
+        use crate::util::rate_limiter::{GenericRateLimiter, RateLimiter};
+        use std::time::Instant;
+        // 20 KB per second with a burst of 2 KB
+        let limiter = Arc::new(GenericRateLimiter::new(20 * 1024));
+        let options = Arc::new(Options {
+            env: Arc::new(MemStorage::default()),
+            compression: CompressionType::NoCompression,
+            rate_limiter: Some(limiter.clone()),
+            ..Default::default()
+        });
+        // foreground writes are not throttled
+        let db = new_test_db(
+            Options {
+                rate_limiter: Some(limiter.clone()),
+                ..Default::default()
+            },
+            "test_rate_limiter",
+        );
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+        }
+        assert_eq!(limiter.total_bytes_requested(), 0);
+
+        let mem = MemTable::new(Arc::new(InternalKeyComparator::new(
+            options.comparator.clone(),
+        )));
+        let value = vec![b'v'; 100];
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            mem.add(i + 1, ValueType::Value, k.as_bytes(), value.as_slice());
+        }
+        let table_cache = Arc::new(TableCache::new("db".to_owned(), options.clone(), 10));
+        let mut meta = FileMetaData {
+            number: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        build_table(
+            options.clone(),
+            "db",
+            table_cache,
+            mem.iter(),
+            &mut meta,
+            CompressionType::NoCompression,
+        )
+        .unwrap();
+        let elapsed = start.elapsed();
+        assert!(meta.file_size > 10 * 1024);
+        // every byte of the table is requested through the limiter
+        assert_eq!(limiter.total_bytes_requested(), meta.file_size);
+        let min_elapsed = (meta.file_size - 2 * 1024) * 1000 / (20 * 1024);
+        assert!(
+            elapsed >= Duration::from_millis(min_elapsed * 9 / 10),
+            "wrote {} bytes in {:?}",
+            meta.file_size,
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_comparator_mismatch() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let mut db = WickDB::open_db(
+            Options {
+                env: env.clone(),
+                ..Default::default()
+            },
+            "test_comparator_mismatch".to_owned(),
+        )
+        .unwrap();
+        db.put(WriteOptions::default(), Slice::from("a"), Slice::from("a"))
+            .unwrap();
+        db.close().unwrap();
+        let reverse = Arc::new(ReverseComparator::new(Arc::new(BytewiseComparator::new())));
+        let e = WickDB::open_db(
+            Options {
+                env: env.clone(),
+                comparator: reverse,
+                ..Default::default()
+            },
+            "test_comparator_mismatch".to_owned(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+        // the same comparator is fine
+        WickDB::open_db(
+            Options {
+                env,
+                ..Default::default()
+            },
+            "test_comparator_mismatch".to_owned(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_numeric_comparator() {
+        let options = Options {
+            comparator: Arc::new(NumericComparator::new()),
+            block_size: 256,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_numeric_comparator");
+        let n = 1000u64;
+        // the keys are written in the bytewise order
+        let mut keys: Vec<[u8; 8]> = (0..n).map(|i| (i * 997).to_be_bytes()).collect();
+        keys.sort_by_key(|k| u64::from_le_bytes(*k));
+        for k in keys.iter() {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(&k[..]),
+                Slice::from(&k[..]),
+            )
+            .unwrap();
+        }
+        // the index blocks are built with the shortened keys
+        db.compact_range(None, None).unwrap();
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..n {
+            assert!(iter.valid());
+            let mut k = [0; 8];
+            k.copy_from_slice(iter.key().as_slice());
+            assert_eq!(u64::from_be_bytes(k), i * 997);
+            iter.next();
+        }
+        assert!(!iter.valid());
+        for i in (0..n).step_by(7) {
+            let k = (i * 997).to_be_bytes();
+            assert_eq!(
+                db.get(ReadOptions::default(), Slice::from(&k[..])).unwrap(),
+                Some(k.to_vec())
+            );
+            let k = (i * 997 + 1).to_be_bytes();
+            assert_eq!(
+                db.get(ReadOptions::default(), Slice::from(&k[..])).unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let db = new_test_db(Options::default(), "test_verify_checksums");
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        db.verify_checksums().unwrap();
+
+        // flips a byte in a table
+        let env = db.inner.env.clone();
+        let table = env
+            .list("test_verify_checksums")
+            .unwrap()
+            .into_iter()
+            .map(|f| f.to_str().unwrap().to_owned())
+            .find(|f| f.ends_with(".sst"))
+            .unwrap();
+        let mut contents = vec![];
+        env.open(table.as_str())
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        contents[10] ^= 0xff;
+        env.create(table.as_str())
+            .unwrap()
+            .write(contents.as_slice())
+            .unwrap();
+        let e = db.verify_checksums().unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+        assert!(e.context().unwrap().starts_with(table.as_str()));
+
+        // the reads report the file and the block
+        let read_opt = || ReadOptions {
+            verify_checksums: true,
+            ..Default::default()
+        };
+        let expected_context = format!("table {}: block at offset 0 with size", table);
+        let e = db.get(read_opt(), Slice::from("key000")).unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+        assert!(e.context().unwrap().starts_with(expected_context.as_str()));
+        let mut iter = db.iter(read_opt());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        let e = iter.status().unwrap_err();
+        assert!(e.context().unwrap().starts_with(expected_context.as_str()));
+    }
+
+    #[test]
+    fn test_invalid_block_restart_interval() {
+        let options = Options {
+            env: Arc::new(MemStorage::default()),
+            block_restart_interval: 0,
+            ..Default::default()
+        };
+        let e = WickDB::open_db(options, "test_invalid_block_restart_interval".to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+    }
+
+    #[test]
+    fn test_disable_wal() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_disable_wal".to_owned();
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let put = |db: &WickDB, k: &str, v: &str, disable_wal: bool| {
+            db.put(
+                WriteOptions {
+                    disable_wal,
+                    ..Default::default()
+                },
+                Slice::from(k),
+                Slice::from(v),
+            )
+        };
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        let e = db
+            .put(
+                WriteOptions {
+                    sync: true,
+                    disable_wal: true,
+                },
+                Slice::from("a"),
+                Slice::from("a"),
+            )
+            .unwrap_err();
+        assert_eq!(e.status(), Status::InvalidArgument);
+        // the writes without the WAL are persisted by a flush
+        put(&db, "a", "a1", true).unwrap();
+        db.compact_range(None, None).unwrap();
+        put(&db, "a", "a2", false).unwrap();
+        put(&db, "a", "a3", true).unwrap();
+        put(&db, "b", "b1", true).unwrap();
+        put(&db, "c", "c1", false).unwrap();
+        assert_eq!(get_str(&db, "a"), Some("a3".to_owned()));
+        assert_eq!(get_str(&db, "b"), Some("b1".to_owned()));
+        db.close().unwrap();
+
+        // the writes without the WAL since the last flush are lost
+        let db = WickDB::open_db(new_options(), name).unwrap();
+        assert_eq!(get_str(&db, "a"), Some("a2".to_owned()));
+        assert_eq!(get_str(&db, "b"), None);
+        assert_eq!(get_str(&db, "c"), Some("c1".to_owned()));
+        put(&db, "b", "b2", false).unwrap();
+        assert_eq!(get_str(&db, "b"), Some("b2".to_owned()));
+    }
+
+    // Lists the files in the DB directory whose names end with `suffix`
+    fn list_files(env: &Arc<dyn Storage>, dir: &str, suffix: &str) -> Vec<String> {
+        let mut files: Vec<String> = env
+            .list(dir)
+            .unwrap()
+            .into_iter()
+            .map(|f| f.to_str().unwrap().to_owned())
+            .filter(|f| f.ends_with(suffix))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_repair() {
+        let name = "test_repair";
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let put = |db: &WickDB, k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let mut db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        for i in 0..100 {
+            put(&db, format!("k{:03}", i).as_str(), "v1");
+        }
+        db.flush(true).unwrap();
+        for i in 50..150 {
+            put(&db, format!("k{:03}", i).as_str(), "v2");
+        }
+        db.flush(true).unwrap();
+        put(&db, "z", "v3");
+        db.delete(WriteOptions::default(), Slice::from("k000"))
+            .unwrap();
+        // "a" never overlaps with the other tables
+        let mut builder = ExternalTableBuilder::new(
+            env.create("test_repair_ext.sst").unwrap(),
+            Arc::new(Options::default()),
+        );
+        builder.add(b"a", b"v4").unwrap();
+        builder.finish().unwrap();
+        db.ingest_external_files(
+            &[PathBuf::from("test_repair_ext.sst")],
+            IngestExternalFileOptions::default(),
+        )
+        .unwrap();
+        db.close().unwrap();
+
+        // overwrite the manifest and add an unreadable table and a `.ldb` table
+        let manifests = || {
+            list_files(&env, name, "")
+                .into_iter()
+                .filter(|f| f.contains("MANIFEST-"))
+                .collect::<Vec<_>>()
+        };
+        for manifest in manifests() {
+            env.create(manifest.as_str())
+                .unwrap()
+                .write(b"broken")
+                .unwrap();
+        }
+        let unreadable = generate_filename(name, FileType::Table, 999);
+        env.create(unreadable.as_str())
+            .unwrap()
+            .write(b"broken")
+            .unwrap();
+        let tables = list_files(&env, name, ".sst");
+        assert_eq!(tables.len(), 4);
+        env.rename(
+            tables[0].as_str(),
+            tables[0].replace(".sst", ".ldb").as_str(),
+        )
+        .unwrap();
+        assert!(WickDB::open_db(new_options(), name.to_owned()).is_err());
+
+        let report = WickDB::repair(new_options(), name.to_owned()).unwrap();
+        assert_eq!(report.tables.len(), 4);
+        let levels: Vec<usize> = report.tables.iter().map(|t| t.level).collect();
+        assert_eq!(levels.iter().filter(|l| **l == 6).count(), 1);
+        assert_eq!(levels.iter().filter(|l| **l == 0).count(), 3);
+        // 200 puts, a put and a deletion in the log, and an ingested put
+        assert_eq!(report.entries, 203);
+        assert_eq!(report.last_sequence, 203);
+        assert_eq!(report.converted_logs.len(), 1);
+        assert_eq!(report.corrupted_files, vec![unreadable]);
+        let lost = format!("{}{}{}", name, MAIN_SEPARATOR, repair::LOST_DIR);
+        let archived = list_files(&env, lost.as_str(), "");
+        assert_eq!(archived, {
+            let mut files = report.archived_files.clone();
+            files.sort();
+            files
+        });
+        assert_eq!(archived.iter().filter(|f| f.ends_with(".sst")).count(), 1);
+        assert_eq!(archived.iter().filter(|f| f.ends_with(".log")).count(), 1);
+        assert!(archived.iter().any(|f| f.contains("MANIFEST-")));
+        assert_eq!(manifests().len(), 1);
+        assert!(list_files(&env, name, ".ldb").is_empty());
+
+        let db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        assert_eq!(get_str(&db, "k000"), None);
+        assert_eq!(get_str(&db, "k001"), Some("v1".to_owned()));
+        assert_eq!(get_str(&db, "k050"), Some("v2".to_owned()));
+        assert_eq!(get_str(&db, "k149"), Some("v2".to_owned()));
+        assert_eq!(get_str(&db, "a"), Some("v4".to_owned()));
+        assert_eq!(get_str(&db, "z"), Some("v3".to_owned()));
+        put(&db, "k001", "v5");
+        assert_eq!(get_str(&db, "k001"), Some("v5".to_owned()));
+        db.compact_range(None, None).unwrap();
+        assert_eq!(get_str(&db, "k001"), Some("v5".to_owned()));
+        assert_eq!(get_str(&db, "k100"), Some("v2".to_owned()));
+    }
+
+    #[test]
+    fn test_repair_salvages_corrupted_table() {
+        let name = "test_repair_salvages_corrupted_table";
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let new_options = || Options {
+            env: env.clone(),
+            block_size: 1024,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let mut db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        for i in 0..1000 {
+            let k = format!("key{:04}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+        }
+        db.flush(true).unwrap();
+        db.close().unwrap();
+
+        // flips a byte in the first data block
+        let table = list_files(&env, name, ".sst").pop().unwrap();
+        let mut contents = vec![];
+        env.open(table.as_str())
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        contents[10] ^= 0xff;
+        env.create(table.as_str())
+            .unwrap()
+            .write(contents.as_slice())
+            .unwrap();
+
+        let report = WickDB::repair(new_options(), name.to_owned()).unwrap();
+        assert_eq!(report.corrupted_files, vec![table.clone()]);
+        assert_eq!(report.tables.len(), 1);
+        assert_eq!(report.tables[0].path, table);
+        assert!(report.entries > 900 && report.entries < 1000);
+        // the original table is kept
+        let archived = format!(
+            "{}{}{}{}{}",
+            name,
+            MAIN_SEPARATOR,
+            repair::LOST_DIR,
+            MAIN_SEPARATOR,
+            Path::new(table.as_str())
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert!(report.archived_files.contains(&archived));
+        assert_eq!(
+            env.open(archived.as_str()).unwrap().len().unwrap(),
+            contents.len() as u64
+        );
+
+        let db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        assert_eq!(get_str(&db, "key0000"), None);
+        assert_eq!(get_str(&db, "key0999"), Some("key0999".to_owned()));
+        db.verify_checksums().unwrap();
+    }
+
+    #[test]
+    fn test_clock_table_cache() {
+        let options = Options {
+            table_cache_eviction_policy: CacheEvictionPolicy::Clock,
+            block_cache: Some(CacheEvictionPolicy::Clock.new_cache(1 << 20)),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_clock_table_cache");
+        for i in 0..500 {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+            if i % 100 == 99 {
+                db.flush(true).unwrap();
+            }
+        }
+        for _ in 0..2 {
+            for i in 0..500 {
+                let k = format!("key{:03}", i);
+                assert_eq!(get_str(&db, k.as_str()), Some(k));
+            }
+            db.compact_range(None, None).unwrap();
+        }
+        let block_cache = db.inner.options.block_cache.as_ref().unwrap();
+        assert!(block_cache.stats().hits > 0);
+    }
+
+    // Counts the memtables created by the `SkiplistFactory`
+    #[derive(Default)]
+    struct CountingFactory {
+        created: AtomicUsize,
+    }
+
+    impl MemTableRepFactory for CountingFactory {
+        fn name(&self) -> &str {
+            "CountingFactory"
+        }
+
+        fn create(&self, icmp: Arc<dyn Comparator>) -> Box<dyn MemTableRep> {
+            self.created.fetch_add(1, Ordering::Release);
+            SkiplistFactory::default().create(icmp)
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let db = new_test_db(Options::default(), "test_flush");
+        let files_count = |db: &WickDB| {
+            let versions = db.inner.versions.lock().unwrap();
+            (0..db.inner.options.max_levels as usize)
+                .map(|l| versions.level_files_count(l))
+                .sum::<usize>()
+        };
+        let next_file_number =
+            |db: &WickDB| db.inner.versions.lock().unwrap().get_next_file_number();
+        // no-op on an empty memtable
+        let next = next_file_number(&db);
+        db.flush(true).unwrap();
+        db.flush(false).unwrap();
+        assert_eq!(next_file_number(&db), next);
+        assert_eq!(files_count(&db), 0);
+
+        db.put(WriteOptions::default(), Slice::from("a"), Slice::from("a"))
+            .unwrap();
+        db.flush(true).unwrap();
+        assert_eq!(files_count(&db), 1);
+        assert!(db.inner.mem.read().unwrap().is_empty());
+        assert!(db.inner.im_mem.read().unwrap().is_empty());
+        db.flush(true).unwrap();
+        assert_eq!(files_count(&db), 1);
+        assert_eq!(get_str(&db, "a"), Some("a".to_owned()));
+
+        // the flushes in progress are coalesced
+        db.put(WriteOptions::default(), Slice::from("b"), Slice::from("b"))
+            .unwrap();
+        db.flush(false).unwrap();
+        db.flush(false).unwrap();
+        db.flush(true).unwrap();
+        assert_eq!(files_count(&db), 2);
+        db.put(WriteOptions::default(), Slice::from("c"), Slice::from("c"))
+            .unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || db.flush(true))
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+        assert_eq!(files_count(&db), 3);
+        for k in ["a", "b", "c"].iter() {
+            assert_eq!(get_str(&db, k), Some(k.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_flush_to_lowest_level() {
+        let options = Options {
+            max_levels: 4,
+            flush_to_lowest_level: true,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_flush_to_lowest_level");
+        let levels = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .map(|l| current.get_level_files(l).len())
+                .collect::<Vec<_>>()
+        };
+        let flush = |start: usize, end: usize, value: &str| {
+            for i in start..end {
+                let k = format!("key{:03}", i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(value),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+        };
+        // the disjoint ranges go to the deepest level
+        flush(0, 100, "v1");
+        flush(200, 300, "v1");
+        assert_eq!(levels(&db), vec![0, 0, 0, 2]);
+        // the overlapping ones are placed above the overlapped files
+        flush(50, 60, "v2");
+        assert_eq!(levels(&db), vec![0, 0, 1, 2]);
+        flush(55, 250, "v3");
+        assert_eq!(levels(&db), vec![0, 1, 1, 2]);
+        // level 0 is the fallback
+        flush(0, 300, "v4");
+        assert_eq!(levels(&db), vec![1, 1, 1, 2]);
+        flush(10, 20, "v5");
+        assert_eq!(levels(&db), vec![2, 1, 1, 2]);
+        for (k, v) in [(0, "v4"), (15, "v5"), (57, "v4"), (150, "v4"), (299, "v4")].iter() {
+            assert_eq!(
+                get_str(&db, format!("key{:03}", k).as_str()),
+                Some(v.to_string())
+            );
+        }
+    }
+    #[test]
+    fn test_get_property() {
+        let db = new_test_db(Options::default(), "test_get_property");
+        let property = |name: &str| -> u64 { db.get_property(name).unwrap().parse().unwrap() };
+        assert_eq!(property("wickdb.num-files-at-level0"), 0);
+        assert_eq!(property("wickdb.total-sst-files-size"), 0);
+        let empty_mem_size = property("wickdb.mem-table-size");
+        let value = "v".repeat(1000);
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(key.as_str()),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
+        }
+        assert!(property("wickdb.mem-table-size") > empty_mem_size + 10000);
+        db.flush(true).unwrap();
+        let level = db.inner.options.max_mem_compact_level;
+        let files = (0..db.inner.options.max_levels as usize)
+            .map(|l| property(format!("wickdb.num-files-at-level{}", l).as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(files.iter().sum::<u64>(), 1);
+        assert!(files[..=level].contains(&1));
+        assert!(property("wickdb.total-sst-files-size") > 0);
+        assert_eq!(property("wickdb.mem-table-size"), empty_mem_size);
+
+        // unknown properties
+        for name in [
+            "wickdb.num-files-at-level",
+            "wickdb.num-files-at-levelx",
+            "wickdb.num-files-at-level100",
+            "wickdb.unknown",
+            "num-files-at-level0",
+            "leveldb.total-sst-files-size",
+        ]
+        .iter()
+        {
+            assert_eq!(db.get_property(name), None, "{}", name);
+        }
+    }
+
+    // Records the events and reads the DB in the callbacks
+    #[derive(Default)]
+    struct RecordingListener {
+        db: Mutex<Option<WickDB>>,
+        flushes: Mutex<Vec<FlushJobInfo>>,
+        compactions: Mutex<Vec<CompactionJobInfo>>,
+    }
+
+    impl RecordingListener {
+        fn read_db(&self) {
+            if let Some(db) = self.db.lock().unwrap().as_ref() {
+                // the version set is not locked by the callers
+                assert!(db.get_property("wickdb.num-files-at-level0").is_some());
+            }
+        }
+    }
+
+    impl EventListener for RecordingListener {
+        fn on_flush_completed(&self, info: &FlushJobInfo) {
+            self.read_db();
+            self.flushes.lock().unwrap().push(info.clone());
+        }
+
+        fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+            self.read_db();
+            self.compactions.lock().unwrap().push(info.clone());
+        }
+    }
+
+    #[test]
+    fn test_event_listener() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let listener = Arc::new(RecordingListener::default());
+        let options = Options {
+            env: env.clone(),
+            listeners: vec![listener.clone()],
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_event_listener".to_owned()).unwrap();
+        *listener.db.lock().unwrap() = Some(db.clone());
+        for i in 0..3 {
+            for k in ["a", "b", "c"].iter() {
+                let v = format!("{}{}", k, i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(*k),
+                    Slice::from(v.as_str()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+        }
+        let flushes = listener.flushes.lock().unwrap().clone();
+        assert_eq!(flushes.len(), 3);
+        db.compact_range(None, None).unwrap();
+        let compactions = listener.compactions.lock().unwrap().clone();
+        assert!(!compactions.is_empty());
+
+        // the live files are the flushed and compacted ones which are not removed
+        let mut expected = HashSet::new();
+        for f in flushes.iter() {
+            assert!(f.file.file_size > 0);
+            assert!(f.file.path.ends_with(".sst"));
+            expected.insert(f.file.number);
+        }
+        for (i, c) in compactions.iter().enumerate() {
+            if i + 1 == compactions.len() {
+                // the bottommost level is rewritten in place at last
+                assert_eq!(c.output_level, c.level);
+            } else {
+                assert_eq!(c.output_level, c.level + 1);
+            }
+            assert!(!c.input_files.is_empty());
+            for f in c.input_files.iter() {
+                assert!(expected.remove(&f.number));
+                assert!(f.level == c.level || f.level == c.output_level);
+            }
+            for f in c.output_files.iter() {
+                assert_eq!(f.level, c.output_level);
+                expected.insert(f.number);
+            }
+        }
+        let current = db.inner.versions.lock().unwrap().current();
+        let mut live = HashSet::new();
+        for level in 0..db.inner.options.max_levels as usize {
+            for f in current.get_level_files(level) {
+                live.insert(f.number);
+            }
+        }
+        assert_eq!(live, expected);
+        for c in compactions.iter().filter(|c| !c.is_trivial_move) {
+            for f in c.input_files.iter() {
+                assert!(!env.exists(f.path.as_str()));
+            }
+        }
+        assert_eq!(get_str(&db, "a"), Some("a2".to_owned()));
+
+        // the statistics agree with the listener
+        let stats = db.statistics();
+        assert_eq!(stats.flush_count, 3);
+        assert_eq!(
+            stats.bytes_flushed,
+            flushes.iter().map(|f| f.file.file_size).sum::<u64>()
+        );
+        let trivial_moves = compactions.iter().filter(|c| c.is_trivial_move).count();
+        assert_eq!(stats.trivial_move_count, trivial_moves as u64);
+        assert_eq!(
+            stats.compaction_count,
+            (compactions.len() - trivial_moves) as u64
+        );
+        let mut written = vec![0; db.inner.options.max_levels as usize];
+        for c in compactions.iter().filter(|c| !c.is_trivial_move) {
+            for f in c.output_files.iter() {
+                written[c.output_level] += f.file_size;
+            }
+        }
+        assert_eq!(stats.compaction_bytes_written, written);
+        assert!(stats.compaction_bytes_read.iter().sum::<u64>() > 0);
+        assert_eq!(stats.write_stall_count, 0);
+        // breaks the reference cycle
+        listener.db.lock().unwrap().take();
+    }
+
+    #[test]
+    fn test_memtable_factory() {
+        let factory = Arc::new(CountingFactory::default());
+        let options = Options {
+            write_buffer_size: 64 << 10,
+            memtable_factory: factory.clone(),
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_memtable_factory".to_owned()).unwrap();
+        let created = factory.created.load(Ordering::Acquire);
+        assert!(created > 0);
+        let value = "v".repeat(1024);
+        for i in 0..200 {
+            let key = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(key.as_str()),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
+        }
+        // every rotated memtable is created by the factory
+        assert!(factory.created.load(Ordering::Acquire) > created);
+        for i in 0..200 {
+            let key = format!("key{:03}", i);
+            assert_eq!(get_str(&db, key.as_str()), Some(value.clone()));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_memtable_write() {
+        let options = Options {
+            allow_concurrent_memtable_write: true,
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_concurrent_memtable_write".to_owned()).unwrap();
+        let writers = 8;
+        let n = 500;
+        let handles: Vec<_> = (0..writers)
+            .map(|w| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..n {
+                        let mut batch = WriteBatch::new();
+                        let key = format!("{:04}_{}", i, w);
+                        batch.put(key.as_bytes(), key.as_bytes());
+                        batch.put(format!("{}_last", w).as_bytes(), key.as_bytes());
+                        db.write(WriteOptions::default(), batch).unwrap();
                     }
-                    None => versions.pick_compaction(),
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(
+            db.inner.versions.lock().unwrap().get_last_sequence(),
+            (writers * n * 2) as u64
+        );
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..n {
+            for w in 0..writers {
+                let key = format!("{:04}_{}", i, w);
+                assert_eq!(iter.key().as_str(), key);
+                assert_eq!(iter.value().as_str(), key);
+                iter.next();
+            }
+        }
+        // the batches of a writer are applied in order
+        for w in 0..writers {
+            let key = format!("{}_last", w);
+            assert_eq!(
+                get_str(&db, key.as_str()),
+                Some(format!("{:04}_{}", n - 1, w))
+            );
+        }
+    }
+
+    // A `Storage` blocking the creation of the sstables once the given number
+    // of them are created, until more are allowed
+    struct BlockingTableStorage {
+        inner: MemStorage,
+        allowed: Mutex<usize>,
+        cond: Condvar,
+    }
+
+    impl BlockingTableStorage {
+        fn new(allowed: usize) -> Self {
+            Self {
+                inner: MemStorage::default(),
+                allowed: Mutex::new(allowed),
+                cond: Condvar::new(),
+            }
+        }
+
+        fn set_allowed(&self, allowed: usize) {
+            *self.allowed.lock().unwrap() = allowed;
+            self.cond.notify_all();
+        }
+    }
+
+    impl Storage for BlockingTableStorage {
+        fn create(&self, name: &str) -> Result<Box<dyn File>> {
+            if name.ends_with(".sst") {
+                let mut allowed = self.allowed.lock().unwrap();
+                while *allowed == 0 {
+                    allowed = self.cond.wait(allowed).unwrap();
                 }
-            } {
-                if is_manual && compaction.is_trivial_move() {
-                    // just move file to next level
-                    let f = compaction.inputs[CompactionInputsRelation::Source as usize]
-                        .first()
-                        .unwrap();
-                    compaction.edit.delete_file(compaction.level, f.number);
-                    compaction.edit.add_file(
-                        compaction.level + 1,
-                        f.number,
-                        f.file_size,
-                        f.smallest.clone(),
-                        f.largest.clone(),
-                    );
-                    if let Err(e) = versions.log_and_apply(&mut compaction.edit) {
-                        debug!("Error in compaction: {:?}", &e);
-                        self.record_bg_error(e);
-                    }
-                    let current_summary = versions.current().level_summary();
-                    info!(
-                        "Moved #{} to level-{} {} bytes, current level summary: {}",
+                *allowed -= 1;
+            }
+            self.inner.create(name)
+        }
+        fn open(&self, name: &str) -> Result<Box<dyn File>> {
+            self.inner.open(name)
+        }
+        fn remove(&self, name: &str) -> Result<()> {
+            self.inner.remove(name)
+        }
+        fn remove_dir(&self, dir: &str, recursively: bool) -> Result<()> {
+            self.inner.remove_dir(dir, recursively)
+        }
+        fn exists(&self, name: &str) -> bool {
+            self.inner.exists(name)
+        }
+        fn rename(&self, old: &str, new: &str) -> Result<()> {
+            self.inner.rename(old, new)
+        }
+        fn mkdir_all(&self, dir: &str) -> Result<()> {
+            self.inner.mkdir_all(dir)
+        }
+        fn list(&self, dir: &str) -> Result<Vec<PathBuf>> {
+            self.inner.list(dir)
+        }
+    }
+
+    #[test]
+    fn test_max_write_buffer_number() {
+        let options = Options {
+            max_write_buffer_number: 0,
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let e = WickDB::open_db(options, "test_max_write_buffer_number".to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+
+        let env = Arc::new(BlockingTableStorage::new(0));
+        let options = Options {
+            write_buffer_size: 64 << 10,
+            max_write_buffer_number: 2,
+            env: env.clone(),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_max_write_buffer_number".to_owned()).unwrap();
+        let total = 400;
+        let written = Arc::new(AtomicUsize::new(0));
+        let writer = {
+            let db = db.clone();
+            let written = written.clone();
+            thread::spawn(move || {
+                let value = vec![b'v'; 1024];
+                for i in 0..total {
+                    let key = format!("key{:04}", i);
+                    db.put(
+                        WriteOptions::default(),
+                        Slice::from(key.as_str()),
+                        Slice::from(value.as_slice()),
+                    )?;
+                    written.fetch_add(1, Ordering::Release);
+                }
+                Ok(())
+            })
+        };
+        // the writes stall once the immutable memtables queue is full
+        let mut last = written.load(Ordering::Acquire);
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let current = written.load(Ordering::Acquire);
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+        assert!(last < total);
+        assert_eq!(db.inner.im_mem.read().unwrap().len(), 2);
+        assert_eq!(get_str(&db, "key0000"), Some("v".repeat(1024)));
+
+        // the writes resume after the memtables are flushed
+        env.set_allowed(usize::MAX);
+        let res: Result<()> = writer.join().unwrap();
+        res.unwrap();
+        assert_eq!(written.load(Ordering::Acquire), total);
+        for i in 0..total {
+            let key = format!("key{:04}", i);
+            assert_eq!(get_str(&db, key.as_str()), Some("v".repeat(1024)));
+        }
+    }
+
+    #[test]
+    fn test_level0_stop_writes_trigger() {
+        let options = Options {
+            level0_file_num_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 3,
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let e = WickDB::open_db(options, "test_level0_stop_writes_trigger".to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+
+        let env = Arc::new(BlockingTableStorage::new(0));
+        let options = Options {
+            level0_file_num_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 4,
+            level0_stop_writes_trigger: 4,
+            env: env.clone(),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_level0_stop_writes_trigger".to_owned()).unwrap();
+        let l0_files = || db.get_property("wickdb.num-files-at-level0").unwrap();
+        // Flush overlapping tables until L0 reaches the stop trigger. Only the
+        // table of every flush is allowed to be created, so the compaction
+        // scheduled at the trigger is blocked.
+        let mut i = 0;
+        while l0_files() != "4" {
+            for k in ["a", "z"].iter() {
+                let v = format!("{}{}", k, i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(*k),
+                    Slice::from(v.as_str()),
+                )
+                .unwrap();
+            }
+            env.set_allowed(1);
+            db.flush(true).unwrap();
+            i += 1;
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let db = db.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let res = db.put(WriteOptions::default(), Slice::from("m"), Slice::from("m"));
+                done.store(true, Ordering::Release);
+                res
+            })
+        };
+        // the write is blocked even though there is room in the memtable
+        thread::sleep(Duration::from_millis(200));
+        assert!(!done.load(Ordering::Acquire));
+
+        // the write resumes after the L0 files are compacted
+        env.set_allowed(usize::MAX);
+        writer.join().unwrap().unwrap();
+        assert!(done.load(Ordering::Acquire));
+        assert!(l0_files().parse::<usize>().unwrap() < 4);
+        // the blocked write is the only stall
+        let stats = db.statistics();
+        assert_eq!(stats.write_stall_count, 1);
+        assert!(stats.write_stall_micros >= 200_000);
+        assert_eq!(get_str(&db, "m"), Some("m".to_owned()));
+        assert_eq!(get_str(&db, "a"), Some(format!("a{}", i - 1)));
+        assert_eq!(get_str(&db, "z"), Some(format!("z{}", i - 1)));
+    }
+
+    // Flushes a memtable with the table creation failing by an injected error in
+    // the given storage. Returns once the error is recorded.
+    fn fail_flush(db: &WickDB, env: &BlockingTableStorage) {
+        db.put(WriteOptions::default(), Slice::from("a"), Slice::from("v1"))
+            .unwrap();
+        // the flush is blocked until the error is injected
+        db.flush(false).unwrap();
+        env.inner.set_create_error(true);
+        env.set_allowed(usize::MAX);
+        while db.inner.bg_error.read().unwrap().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_delete_files_in_range() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let name = "test_delete_files_in_range";
+        let mut db = WickDB::open_db(options(), name.to_owned()).unwrap();
+        // the tables of [a00, a49], [b00, b49], [c00, c49] and [a60, c60]
+        for (prefix, keys) in [("a", 0..50), ("b", 0..50), ("c", 0..50)].iter().cloned() {
+            for i in keys {
+                let k = format!("{}{:02}", prefix, i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(k.as_str()),
+                )
+                .unwrap();
+            }
+            db.inner.force_compact_mem_table().unwrap();
+        }
+        for k in ["a60", "c60"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from(*k))
+                .unwrap();
+        }
+        db.inner.force_compact_mem_table().unwrap();
+        // a key in the memtable is not deleted
+        db.put(
+            WriteOptions::default(),
+            Slice::from("b99"),
+            Slice::from("b99"),
+        )
+        .unwrap();
+        let table_files = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            let mut files = vec![];
+            for level in 0..db.inner.options.max_levels as usize {
+                for f in current.get_level_files(level) {
+                    files.push((
                         f.number,
-                        compaction.level + 1,
-                        f.file_size,
-                        current_summary
+                        String::from_utf8(f.smallest.user_key().to_vec()).unwrap(),
+                    ));
+                }
+            }
+            files.sort();
+            files
+        };
+        let files = table_files(&db);
+        assert_eq!(files.len(), 4);
+        let b_table = files
+            .iter()
+            .find(|(_, smallest)| smallest == "b00")
+            .unwrap()
+            .0;
+
+        // nothing is deleted by an empty range or a range without any whole file
+        db.delete_files_in_range(b"c", b"b").unwrap();
+        db.delete_files_in_range(b"b", b"b49").unwrap();
+        assert_eq!(table_files(&db), files);
+
+        // the input files of a running compaction are skipped
+        db.inner
+            .versions
+            .lock()
+            .unwrap()
+            .compacting_files
+            .insert(b_table);
+        db.delete_files_in_range(b"b", b"c").unwrap();
+        assert_eq!(table_files(&db), files);
+        db.inner.versions.lock().unwrap().compacting_files.clear();
+
+        db.delete_files_in_range(b"b", b"c").unwrap();
+        let b_path = generate_filename(name, FileType::Table, b_table);
+        assert!(!env.exists(b_path.as_str()));
+        assert_eq!(table_files(&db).len(), 3);
+        let check = |db: &WickDB| {
+            assert_eq!(get_str(db, "b10"), None);
+            assert_eq!(get_str(db, "b99"), Some("b99".to_owned()));
+            for k in ["a10", "a60", "c10", "c60"].iter() {
+                assert_eq!(get_str(db, k), Some(k.to_string()));
+            }
+        };
+        check(&db);
+        // the deletion is recorded in the manifest
+        db.close().unwrap();
+        let db = WickDB::open_db(options(), name.to_owned()).unwrap();
+        check(&db);
+
+        db.delete_files_in_range(b"a", b"d").unwrap();
+        assert!(table_files(&db).is_empty());
+        assert_eq!(get_str(&db, "a10"), None);
+    }
+
+    #[test]
+    fn test_resume() {
+        let env = Arc::new(BlockingTableStorage::new(0));
+        let options = Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_resume".to_owned()).unwrap();
+        db.resume().unwrap();
+        fail_flush(&db, &env);
+        // the writes fail until the DB is resumed
+        for _ in 0..2 {
+            let e = db
+                .put(WriteOptions::default(), Slice::from("b"), Slice::from("v2"))
+                .unwrap_err();
+            assert_eq!(e.status(), Status::IOError);
+        }
+        assert_eq!(db.flush(true).unwrap_err().status(), Status::IOError);
+        // resuming fails again while the disk is still full
+        assert_eq!(db.resume().unwrap_err().status(), Status::IOError);
+        assert_eq!(get_str(&db, "a"), Some("v1".to_owned()));
+
+        env.inner.set_create_error(false);
+        db.resume().unwrap();
+        assert!(db.inner.im_mem.read().unwrap().is_empty());
+        assert_eq!(db.statistics().flush_count, 1);
+        db.put(WriteOptions::default(), Slice::from("b"), Slice::from("v2"))
+            .unwrap();
+        db.flush(true).unwrap();
+        assert_eq!(get_str(&db, "a"), Some("v1".to_owned()));
+        assert_eq!(get_str(&db, "b"), Some("v2".to_owned()));
+    }
+
+    #[test]
+    fn test_auto_resume() {
+        for &max_resume_count in [1, 10].iter() {
+            let env = Arc::new(BlockingTableStorage::new(0));
+            let options = Options {
+                env: env.clone(),
+                max_bg_error_resume_count: max_resume_count,
+                bg_error_resume_retry_interval: 50,
+                ..Default::default()
+            };
+            let db = WickDB::open_db(options, "test_auto_resume".to_owned()).unwrap();
+            fail_flush(&db, &env);
+            assert_eq!(
+                db.put(WriteOptions::default(), Slice::from("b"), Slice::from("v2"))
+                    .unwrap_err()
+                    .status(),
+                Status::IOError
+            );
+            // the retries after 50ms and 150ms fail, and the one after 350ms succeeds
+            thread::sleep(Duration::from_millis(200));
+            env.inner.set_create_error(false);
+            thread::sleep(Duration::from_millis(500));
+            let res = db.put(WriteOptions::default(), Slice::from("b"), Slice::from("v2"));
+            if max_resume_count == 1 {
+                // the only retry has failed
+                assert_eq!(res.unwrap_err().status(), Status::IOError);
+                db.resume().unwrap();
+            } else {
+                res.unwrap();
+            }
+            assert!(db.inner.im_mem.read().unwrap().is_empty());
+            assert_eq!(db.statistics().flush_count, 1);
+            assert_eq!(get_str(&db, "a"), Some("v1".to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_open_read_only".to_owned();
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let e = WickDB::open_read_only(new_options(), name.clone())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+        assert_eq!(e.context(), Some("db test_open_read_only"));
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+        }
+        // the first half lives in the tables and the second half in the log
+        db.compact_range(None, None).unwrap();
+        for i in 50..100 {
+            let v = format!("new{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(format!("key{:03}", i).as_str()),
+                Slice::from(v.as_str()),
+            )
+            .unwrap();
+        }
+        db.close().unwrap();
+        // The info log is skipped since the global logger is shared by the DBs
+        // in this process
+        let info_log = generate_filename(name.as_str(), FileType::InfoLog, 0);
+        let dump_files = || {
+            let mut files = env.list(name.as_str()).unwrap();
+            files.sort();
+            files
+                .iter()
+                .filter(|f| f.to_str().unwrap() != info_log)
+                .map(|f| {
+                    let mut buf = vec![];
+                    env.open(f.to_str().unwrap())
+                        .unwrap()
+                        .read_all(&mut buf)
+                        .unwrap();
+                    (f.clone(), buf)
+                })
+                .collect::<Vec<_>>()
+        };
+        let files = dump_files();
+
+        // multiple read-only instances share the same DB
+        let db1 = WickDB::open_read_only(new_options(), name.clone()).unwrap();
+        let db2 = WickDB::open_read_only(new_options(), name.clone()).unwrap();
+        for db in [&db1, &db2].iter() {
+            assert_eq!(get_str(db, "key000"), Some("key000".to_owned()));
+            assert_eq!(get_str(db, "key049"), Some("key049".to_owned()));
+            assert_eq!(get_str(db, "key050"), Some("new050".to_owned()));
+            assert_eq!(get_str(db, "key100"), None);
+            let mut iter = db.iter(ReadOptions::default());
+            iter.seek_to_first();
+            assert_eq!(collect_keys(iter.as_mut(), true).len(), 100);
+        }
+        let e = db1
+            .put(WriteOptions::default(), Slice::from("a"), Slice::from("a"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+        let e = db1
+            .delete(WriteOptions::default(), Slice::from("key000"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+        assert_eq!(
+            db1.compact_range(None, None).unwrap_err().status(),
+            Status::NotSupported
+        );
+        assert_eq!(get_str(&db1, "key000"), Some("key000".to_owned()));
+        // nothing on disk is changed
+        assert!(files == dump_files());
+    }
+
+    #[test]
+    fn test_secondary() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_secondary".to_owned();
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let put = |db: &WickDB, k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let primary = WickDB::open_db(new_options(), name.clone()).unwrap();
+        put(&primary, "a", "a1");
+        put(&primary, "b", "b1");
+        let secondary = WickDB::open_as_secondary(new_options(), name.clone()).unwrap();
+        assert_eq!(get_str(&secondary, "a"), Some("a1".to_owned()));
+
+        // the new writes and tables are invisible until catching up
+        put(&primary, "a", "a2");
+        put(&primary, "c", "c1");
+        primary.compact_range(None, None).unwrap();
+        put(&primary, "d", "d1");
+        assert_eq!(get_str(&secondary, "c"), None);
+        let mut old_iter = secondary.iter(ReadOptions::default());
+        secondary.try_catch_up_with_primary().unwrap();
+        // the records in the tables are not shadowed by the old memtable
+        assert_eq!(get_str(&secondary, "a"), Some("a2".to_owned()));
+        assert_eq!(get_str(&secondary, "c"), Some("c1".to_owned()));
+        assert_eq!(get_str(&secondary, "d"), Some("d1".to_owned()));
+        let current = secondary.inner.versions.lock().unwrap().current();
+        assert!(
+            (0..new_options().max_levels as usize).any(|l| !current.get_level_files(l).is_empty())
+        );
+        // the iterator created before the catch-up reads the old data
+        old_iter.seek_to_first();
+        assert_eq!(collect_keys(old_iter.as_mut(), true), vec!["a", "b"]);
+        let mut iter = secondary.iter(ReadOptions::default());
+        iter.seek_to_first();
+        assert_eq!(collect_keys(iter.as_mut(), true), vec!["a", "b", "c", "d"]);
+
+        // catching up with nothing new is fine
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get_str(&secondary, "d"), Some("d1".to_owned()));
+        let e = secondary
+            .put(WriteOptions::default(), Slice::from("e"), Slice::from("e"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+        for db in [
+            primary,
+            WickDB::open_read_only(new_options(), name).unwrap(),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                db.try_catch_up_with_primary().unwrap_err().status(),
+                Status::NotSupported
+            );
+        }
+    }
+
+    #[test]
+    fn test_ingest_external_files() {
+        use crate::sstable::external::ExternalTableBuilder;
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_ingest_external_files".to_owned();
+        let new_options = || Options {
+            env: env.clone(),
+            // the flushed memtables stay in level 0
+            max_mem_compact_level: 0,
+            ..Default::default()
+        };
+        let build = |file: &str, kvs: &[(&str, &str)]| {
+            let mut builder =
+                ExternalTableBuilder::new(env.create(file).unwrap(), Arc::new(new_options()));
+            for (k, v) in kvs {
+                builder.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+            builder.finish().unwrap();
+            PathBuf::from(file)
+        };
+        let levels = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .map(|l| current.get_level_files(l).len())
+                .collect::<Vec<_>>()
+        };
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        let max_level = db.inner.options.max_levels as usize - 1;
+
+        // two non-overlapping files go to the bottom level
+        let first: Vec<(String, String)> = (0..50)
+            .map(|i| (format!("key{:03}", i), format!("first{:03}", i)))
+            .collect();
+        let second: Vec<(String, String)> = (50..100)
+            .map(|i| (format!("key{:03}", i), format!("second{:03}", i)))
+            .collect();
+        fn borrow(kvs: &[(String, String)]) -> Vec<(&str, &str)> {
+            kvs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+        }
+        let f1 = build("external1", &borrow(&first));
+        let f2 = build("external2", &borrow(&second));
+        let last_seq = db.inner.versions.lock().unwrap().get_last_sequence();
+        db.ingest_external_files(&[f1, f2], IngestExternalFileOptions::default())
+            .unwrap();
+        assert_eq!(
+            db.inner.versions.lock().unwrap().get_last_sequence(),
+            last_seq + 2
+        );
+        assert_eq!(levels(&db)[max_level], 2);
+        for (k, v) in first.iter().chain(second.iter()) {
+            assert_eq!(get_str(&db, k), Some(v.clone()));
+        }
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 100);
+        // the external files are left untouched
+        assert!(env.exists("external1"));
+
+        // the ingested file overlapping the memtable needs a flush
+        let snapshot = db.get_snapshot();
+        db.put(
+            WriteOptions::default(),
+            Slice::from("key010"),
+            Slice::from("put"),
+        )
+        .unwrap();
+        let f3 = build("external3", &[("key010", "third"), ("key020", "third")]);
+        let e = db
+            .ingest_external_files(
+                std::slice::from_ref(&f3),
+                IngestExternalFileOptions {
+                    allow_blocking_flush: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert_eq!(e.status(), Status::InvalidArgument);
+        db.ingest_external_files(&[f3], IngestExternalFileOptions::default())
+            .unwrap();
+        // overlaps the flushed memtable in level 0
+        assert_eq!(levels(&db)[0], 2);
+        assert_eq!(get_str(&db, "key010"), Some("third".to_owned()));
+        assert_eq!(get_str(&db, "key020"), Some("third".to_owned()));
+        let read_opt = ReadOptions {
+            snapshot: Some((*snapshot).clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            db.get(read_opt, Slice::from("key010")).unwrap(),
+            Some(b"first010".to_vec())
+        );
+
+        // the later one of the overlapping files is newer
+        let f4 = build("external4", &[("x", "fourth"), ("z", "fourth")]);
+        let f5 = build("external5", &[("y", "fifth"), ("z", "fifth")]);
+        db.ingest_external_files(&[f4, f5], IngestExternalFileOptions::default())
+            .unwrap();
+        assert_eq!(get_str(&db, "x"), Some("fourth".to_owned()));
+        assert_eq!(get_str(&db, "z"), Some("fifth".to_owned()));
+
+        // a missing file fails the whole ingestion
+        let f6 = build("external6", &[("w", "sixth")]);
+        let before = levels(&db);
+        assert!(db
+            .ingest_external_files(
+                &[f6, PathBuf::from("missing")],
+                IngestExternalFileOptions::default()
+            )
+            .is_err());
+        assert_eq!(levels(&db), before);
+        assert_eq!(get_str(&db, "w"), None);
+
+        // the ingested files are recorded in the manifest
+        db.close().unwrap();
+        let db = WickDB::open_db(new_options(), name).unwrap();
+        assert_eq!(levels(&db), before);
+        assert_eq!(get_str(&db, "key000"), Some("first000".to_owned()));
+        assert_eq!(get_str(&db, "key099"), Some("second099".to_owned()));
+        assert_eq!(get_str(&db, "key010"), Some("third".to_owned()));
+        assert_eq!(get_str(&db, "z"), Some("fifth".to_owned()));
+        db.put(
+            WriteOptions::default(),
+            Slice::from("z"),
+            Slice::from("put"),
+        )
+        .unwrap();
+        assert_eq!(get_str(&db, "z"), Some("put".to_owned()));
+    }
+
+    #[test]
+    fn test_create_checkpoint() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let new_options = || Options {
+            env: env.clone(),
+            write_buffer_size: 64 * 1024,
+            ..Default::default()
+        };
+        let put = |db: &WickDB, k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let mut db = WickDB::open_db(new_options(), "test_create_checkpoint".to_owned()).unwrap();
+        let value = "x".repeat(1000);
+        for i in 0..200 {
+            put(&db, format!("key{:03}", i).as_str(), value.as_str());
+        }
+        // some of the writes are only in the memtable
+        put(&db, "a", "a1");
+        db.create_checkpoint(Path::new("checkpoint")).unwrap();
+        let e = db.create_checkpoint(Path::new("checkpoint")).unwrap_err();
+        assert_eq!(e.status(), Status::InvalidArgument);
+        assert_eq!(e.context(), Some("directory checkpoint"));
+        put(&db, "a", "a2");
+        put(&db, "b", "b1");
+        // the checkpoint lives after the original DB is destroyed
+        db.destroy().unwrap();
+
+        let checkpoint = WickDB::open_db(new_options(), "checkpoint".to_owned()).unwrap();
+        assert_eq!(get_str(&checkpoint, "a"), Some("a1".to_owned()));
+        assert_eq!(get_str(&checkpoint, "b"), None);
+        for i in 0..200 {
+            assert_eq!(
+                get_str(&checkpoint, format!("key{:03}", i).as_str()),
+                Some(value.clone())
+            );
+        }
+        put(&checkpoint, "b", "b2");
+        assert_eq!(get_str(&checkpoint, "b"), Some("b2".to_owned()));
+    }
+
+    #[test]
+    fn test_get_approximate_sizes() {
+        let options = Options {
+            write_buffer_size: 100 * 1024,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_get_approximate_sizes");
+        let value = vec![b'x'; 1000];
+        let n = 1000;
+        for i in 0..n {
+            let k = format!("key{:04}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(value.as_slice()),
+            )
+            .unwrap();
+        }
+        // most of the data has been flushed into sstables
+        let current = db.inner.versions.lock().unwrap().current();
+        let files: usize = (0..db.inner.options.max_levels as usize)
+            .map(|level| current.get_level_files(level).len())
+            .sum();
+        assert!(files > 0);
+        for i in (0..n).step_by(97) {
+            let k = format!("key{:04}", i);
+            assert_eq!(
+                db.get(ReadOptions::default(), Slice::from(k.as_str()))
+                    .unwrap(),
+                Some(value.clone())
+            );
+        }
+        let total = db.get_approximate_sizes(&[(b"", b"\xff")])[0];
+        let written = (n * value.len()) as u64;
+        assert!(
+            total >= written && total <= written * 11 / 10,
+            "total size {}, written {}",
+            total,
+            written
+        );
+        // disjoint ranges sum to the total size
+        let bounds: Vec<String> = (0..=10).map(|i| format!("key{:04}", i * 100)).collect();
+        let ranges: Vec<(&[u8], &[u8])> = (0..10)
+            .map(|i| (bounds[i].as_bytes(), bounds[i + 1].as_bytes()))
+            .collect();
+        let sizes = db.get_approximate_sizes(&ranges);
+        let sum: u64 = sizes.iter().sum();
+        assert!(
+            sum <= total && sum >= total * 9 / 10,
+            "sum {}, total {}",
+            sum,
+            total
+        );
+        for size in sizes {
+            assert!(size >= written / 20 && size <= written / 5, "{}", size);
+        }
+        // empty ranges
+        assert_eq!(
+            db.get_approximate_sizes(&[(b"a", b"b"), (b"key", b"key")]),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn test_iter_skips_range_tombstones() {
+        let db = new_test_db(Options::default(), "test_iter_skips_range_tombstones");
+        let n = 10000;
+        let key = |i: usize| format!("key{:05}", i);
+        for i in 0..n {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(key(i).as_str()),
+                Slice::from("old"),
+            )
+            .unwrap();
+        }
+        db.inner.force_compact_mem_table().unwrap();
+        let snapshot = db.get_snapshot();
+        let mut batch = WriteBatch::new();
+        // overlapping tombstones covering all the keys except the first and the last 10
+        batch.delete_range(key(10).as_bytes(), key(6000).as_bytes());
+        batch.delete_range(key(3000).as_bytes(), key(n - 10).as_bytes());
+        db.write(WriteOptions::default(), batch).unwrap();
+        // the tombstones are read from the table
+        db.inner.force_compact_mem_table().unwrap();
+        let survivors = [10, 5000, 7777, n - 11];
+        for i in survivors.iter() {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(key(*i).as_str()),
+                Slice::from("new"),
+            )
+            .unwrap();
+        }
+        let entry = |i: usize, v: &str| (key(i), v.to_owned());
+        let mut expected: Vec<(String, String)> = (0..10).map(|i| entry(i, "old")).collect();
+        expected.extend(survivors.iter().map(|i| entry(*i, "new")));
+        expected.extend((n - 10..n).map(|i| entry(i, "old")));
+
+        let mut iter = db.iter(ReadOptions::default());
+        let mut visited = vec![];
+        iter.seek_to_first();
+        while iter.valid() {
+            visited.push((
+                iter.key().as_str().to_owned(),
+                iter.value().as_str().to_owned(),
+            ));
+            iter.next();
+        }
+        assert_eq!(visited, expected);
+        visited.clear();
+        iter.seek_to_last();
+        while iter.valid() {
+            visited.push((
+                iter.key().as_str().to_owned(),
+                iter.value().as_str().to_owned(),
+            ));
+            iter.prev();
+        }
+        expected.reverse();
+        assert_eq!(visited, expected);
+        // seek into the deleted range
+        iter.seek(&Slice::from(key(20).as_str()));
+        assert_eq!(iter.key().as_str(), key(5000));
+        iter.prev();
+        assert_eq!(iter.key().as_str(), key(10));
+        iter.next();
+        iter.next();
+        assert_eq!(iter.key().as_str(), key(7777));
+
+        // the older snapshot sees all the keys
+        let mut iter = db.iter(ReadOptions {
+            snapshot: Some((*snapshot).clone()),
+            ..Default::default()
+        });
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            assert_eq!(iter.value().as_str(), "old");
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, n);
+    }
+
+    #[test]
+    fn test_compaction_readahead() {
+        let options = Options {
+            block_size: 256,
+            compaction_readahead_size: 4096,
+            max_mem_compact_level: 0,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_compaction_readahead");
+        for round in 0..4 {
+            for i in (round..2000).step_by(4) {
+                let k = format!("key{:04}", i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(k.repeat(4).as_str()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        // the blocks read by the compaction are not cached
+        let cache = db.inner.options.block_cache.clone().unwrap();
+        assert_eq!(cache.total_charge(), 0);
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..2000 {
+            let k = format!("key{:04}", i);
+            assert_eq!(iter.key().as_str(), k);
+            assert_eq!(iter.value().as_str(), k.repeat(4));
+            iter.next();
+        }
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let options = Options {
+            write_buffer_size: 100 * 1024,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_compact_range");
+        let value = vec![b'x'; 1000];
+        let n = 1000;
+        for i in 0..n {
+            let k = format!("key{:04}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(value.as_slice()),
+            )
+            .unwrap();
+        }
+        let before = db.get_approximate_sizes(&[(b"", b"\xff")])[0];
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"key0000", b"key0800");
+        db.write(WriteOptions::default(), batch).unwrap();
+        db.compact_range(None, None).unwrap();
+        // the memtable is flushed and the deleted entries are discarded
+        let mut iter = db.inner.mem.read().unwrap().iter();
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        let after = db.get_approximate_sizes(&[(b"", b"\xff")])[0];
+        assert!(
+            after < before / 4,
+            "size before {}, after {}",
+            before,
+            after
+        );
+        assert_eq!(get_str(&db, "key0000"), None);
+        assert_eq!(get_str(&db, "key0799"), None);
+        assert_eq!(
+            db.get(ReadOptions::default(), Slice::from("key0800"))
+                .unwrap(),
+            Some(value.clone())
+        );
+        // the remaining keys are all yielded from the compacted tables
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, n - 800);
+        // every level except the deepest one is empty
+        let current = db.inner.versions.lock().unwrap().current();
+        let levels_with_files: Vec<usize> = (0..db.inner.options.max_levels as usize)
+            .filter(|level| !current.get_level_files(*level).is_empty())
+            .collect();
+        assert_eq!(levels_with_files.len(), 1);
+    }
+
+    #[test]
+    fn test_flush_records_current_log_number() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_flush_records_current_log_number".to_owned();
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from(k.as_str()),
+            )
+            .unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        let table_files = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .flat_map(|level| current.get_level_files(level).iter().map(|f| f.number))
+                .collect::<Vec<_>>()
+        };
+        let files = table_files(&db);
+        db.close().unwrap();
+        // the flushed log is not replayed into a new table
+        let db = WickDB::open_db(new_options(), name).unwrap();
+        assert_eq!(table_files(&db), files);
+        assert_eq!(get_str(&db, "key000"), Some("key000".to_owned()));
+        assert_eq!(get_str(&db, "key099"), Some("key099".to_owned()));
+    }
+
+    #[test]
+    fn test_compaction_deletes_inputs() {
+        let db = new_test_db(Options::default(), "test_compaction_deletes_inputs");
+        for round in 0..3 {
+            for i in 0..100 {
+                let k = format!("key{:03}", i);
+                let v = format!("{}{}", k, round);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(v.as_str()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        let current = db.inner.versions.lock().unwrap().current();
+        let mut live = (0..db.inner.options.max_levels as usize)
+            .flat_map(|level| current.get_level_files(level).iter().map(|f| f.number))
+            .collect::<Vec<_>>();
+        live.sort();
+        // only the tables of the current version are left
+        let mut tables = db
+            .inner
+            .env
+            .list(db.inner.db_name.as_str())
+            .unwrap()
+            .iter()
+            .filter_map(|f| match parse_filename(f) {
+                Some((FileType::Table, number)) => Some(number),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        tables.sort();
+        assert_eq!(tables, live);
+        assert_eq!(get_str(&db, "key000"), Some("key0002".to_owned()));
+    }
+
+    #[test]
+    fn test_compaction_at_level0_threshold() {
+        let db = new_test_db(Options::default(), "test_compaction_at_level0_threshold");
+        let threshold = db.inner.options.level0_file_num_compaction_trigger;
+        let l0_files = || {
+            db.get_property("wickdb.num-files-at-level0")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        // flush overlapping tables until level 0 exactly reaches the threshold
+        let mut i = 0;
+        while l0_files() < threshold {
+            for k in ["a", "z"].iter() {
+                let v = format!("{}{}", k, i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(*k),
+                    Slice::from(v.as_str()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+            i += 1;
+        }
+        // the compaction is scheduled once the score reaches 1
+        let mut retries = 0;
+        while l0_files() >= threshold && retries < 500 {
+            thread::sleep(Duration::from_millis(10));
+            retries += 1;
+        }
+        assert!(l0_files() < threshold);
+        assert_eq!(get_str(&db, "a"), Some(format!("a{}", i - 1)));
+    }
+
+    #[test]
+    fn test_target_file_size_per_level() {
+        let options = Options {
+            write_buffer_size: 8 * 1024 * 1024,
+            compression: CompressionType::NoCompression,
+            target_file_size_base: 1024 * 1024,
+            target_file_size_multiplier: 2,
+            max_mem_compact_level: 0,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_target_file_size_per_level");
+        let value = vec![b'x'; 1000];
+        // the last data block and the meta blocks are written after the target is reached
+        let slack = 64 * 1024;
+        let put = |k: &str, v: &[u8]| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let files = |level: usize| {
+            db.inner
+                .versions
+                .lock()
+                .unwrap()
+                .current()
+                .get_level_files(level)
+                .to_vec()
+        };
+        // a value larger than the target of any level
+        let huge = vec![b'y'; 3 * 1024 * 1024];
+        put("key00000", huge.as_slice());
+        // the even keys go to level 2
+        for i in (2..6000).step_by(2) {
+            put(format!("key{:05}", i).as_str(), value.as_slice());
+        }
+        db.compact_range(None, None).unwrap();
+        db.inner
+            .manual_compact_range(1, false, None, None, &CompactRangeOptions::default())
+            .unwrap();
+        assert!(files(1).is_empty());
+        // the odd keys are compacted with the overlapping even keys into level 2
+        for i in (1..6000).step_by(2) {
+            put(format!("key{:05}", i).as_str(), value.as_slice());
+        }
+        db.inner.force_compact_mem_table().unwrap();
+        db.inner
+            .manual_compact_range(0, false, None, None, &CompactRangeOptions::default())
+            .unwrap();
+        let l1_files = files(1);
+        assert!(l1_files.len() > 1);
+        for f in l1_files.iter().take(l1_files.len() - 1) {
+            assert!(f.file_size >= 1024 * 1024);
+            assert!(f.file_size < 1024 * 1024 + slack);
+        }
+        db.inner
+            .manual_compact_range(1, false, None, None, &CompactRangeOptions::default())
+            .unwrap();
+        let l2_files = files(2);
+        // the huge value makes up a file alone
+        let first = &l2_files[0];
+        assert_eq!(first.smallest.user_key(), b"key00000");
+        assert_eq!(first.largest.user_key(), b"key00000");
+        assert!(first.file_size > huge.len() as u64);
+        for f in l2_files.iter().skip(1) {
+            assert!(f.file_size < 2 * 1024 * 1024 + slack);
+        }
+        // larger than the target of level 1
+        assert!(l2_files
+            .iter()
+            .skip(1)
+            .any(|f| f.file_size >= 2 * 1024 * 1024));
+        assert_eq!(
+            db.get(ReadOptions::default(), Slice::from("key00000"))
+                .unwrap(),
+            Some(huge)
+        );
+        assert_eq!(get_str(&db, "key05999"), Some("x".repeat(1000)));
+    }
+
+    #[test]
+    fn test_max_bytes_for_level() {
+        // Returns the deepest level with files after writing the same data
+        let deepest_level = |base: u64, name: &str| {
+            let options = Options {
+                write_buffer_size: 64 * 1024,
+                compression: CompressionType::NoCompression,
+                max_mem_compact_level: 0,
+                max_bytes_for_level_base: base,
+                max_bytes_for_level_multiplier: 2,
+                ..Default::default()
+            };
+            let db = new_test_db(options, name);
+            let value = vec![b'x'; 1000];
+            for i in 0..3000 {
+                let k = format!("key{:05}", i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(value.as_slice()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+            // wait for the background compactions
+            while db
+                .inner
+                .background_compaction_scheduled
+                .load(Ordering::Acquire)
+                || db.inner.versions.lock().unwrap().needs_compaction()
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(get_str(&db, "key02999"), Some("x".repeat(1000)));
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .filter(|level| !current.get_level_files(*level).is_empty())
+                .max()
+                .unwrap()
+        };
+        let small = deepest_level(256 * 1024, "test_max_bytes_for_level_small");
+        let large = deepest_level(64 * 1024 * 1024, "test_max_bytes_for_level_large");
+        assert_eq!(large, 1);
+        assert!(small >= 3, "deepest level {}", small);
+
+        let options = Options {
+            env: Arc::new(MemStorage::default()),
+            max_bytes_for_level_multiplier: 1,
+            ..Default::default()
+        };
+        let e = WickDB::open_db(options, "test_max_bytes_for_level".to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+    }
+
+    #[test]
+    fn test_level_compaction_dynamic_level_bytes() {
+        let new_options = |env: Arc<dyn Storage>, dynamic: bool| Options {
+            env,
+            write_buffer_size: 64 * 1024,
+            compression: CompressionType::NoCompression,
+            max_mem_compact_level: 0,
+            max_bytes_for_level_base: 256 * 1024,
+            max_bytes_for_level_multiplier: 8,
+            level_compaction_dynamic_level_bytes: dynamic,
+            ..Default::default()
+        };
+        let wait_for_compactions = |db: &WickDB| {
+            db.flush(true).unwrap();
+            while db
+                .inner
+                .background_compaction_scheduled
+                .load(Ordering::Acquire)
+                || db.inner.versions.lock().unwrap().needs_compaction()
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let level_sizes = |db: &WickDB| -> Vec<u64> {
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .map(|level| VersionSet::total_file_size(current.get_level_files(level)))
+                .collect()
+        };
+        let value = vec![b'x'; 1000];
+        let write = |db: &WickDB, keys: &mut dyn std::iter::Iterator<Item = usize>| {
+            for i in keys {
+                let k = format!("key{:05}", i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from(value.as_slice()),
+                )
+                .unwrap();
+            }
+            wait_for_compactions(db);
+        };
+        // Returns the space amplification after loading the keys and overwriting half of them
+        let space_amp = |db: &WickDB| {
+            write(db, &mut (0..3000).chain(0..1500));
+            let total: u64 = level_sizes(db).iter().sum();
+            // only the live entries are left after the full compaction
+            db.compact_range(None, None).unwrap();
+            wait_for_compactions(db);
+            let sizes = level_sizes(db);
+            assert_eq!(sizes.iter().filter(|s| **s > 0).count(), 1);
+            total as f64 / sizes.iter().sum::<u64>() as f64
+        };
+        let static_db = WickDB::open_db(
+            new_options(Arc::new(MemStorage::default()), false),
+            "test_static_level_bytes".to_owned(),
+        )
+        .unwrap();
+        let dynamic_db = WickDB::open_db(
+            new_options(Arc::new(MemStorage::default()), true),
+            "test_dynamic_level_bytes".to_owned(),
+        )
+        .unwrap();
+        let static_amp = space_amp(&static_db);
+        let dynamic_amp = space_amp(&dynamic_db);
+        assert!(
+            dynamic_amp < static_amp,
+            "space amplification of the static levels {}, the dynamic levels {}",
+            static_amp,
+            dynamic_amp
+        );
+
+        // enabling it on an existing DB compacts the levels into the dynamic limits
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_migrate_to_dynamic_level_bytes";
+        let mut db = WickDB::open_db(new_options(env.clone(), false), name.to_owned()).unwrap();
+        write(&db, &mut (0..3000).chain(0..1500));
+        let before = level_sizes(&db);
+        db.close().unwrap();
+        let db = WickDB::open_db(new_options(env, true), name.to_owned()).unwrap();
+        wait_for_compactions(&db);
+        let after = level_sizes(&db);
+        let bottommost = after.iter().rposition(|s| *s > 0).unwrap();
+        for level in 1..bottommost {
+            let max_bytes =
+                (after[bottommost] / 8u64.pow((bottommost - level) as u32)).max(256 * 1024);
+            assert!(after[level] <= max_bytes, "{:?} -> {:?}", before, after);
+        }
+        assert!(after.iter().sum::<u64>() < before.iter().sum::<u64>());
+        for i in (0..3000).step_by(100) {
+            let k = format!("key{:05}", i);
+            assert_eq!(
+                db.get(ReadOptions::default(), Slice::from(k.as_str()))
+                    .unwrap(),
+                Some(value.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_compact_range_with_concurrent_writes() {
+        let options = Options {
+            write_buffer_size: 20 * 1024,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_compact_range_with_concurrent_writes");
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..2000 {
+                    let k = format!("key{:04}", i);
+                    db.put(
+                        WriteOptions::default(),
+                        Slice::from(k.as_str()),
+                        Slice::from(k.repeat(20).as_str()),
                     )
+                    .unwrap();
+                }
+            })
+        };
+        for i in 0..10 {
+            let start = format!("key{:04}", i * 200);
+            let end = format!("key{:04}", i * 200 + 199);
+            db.compact_range(Some(start.as_bytes()), Some(end.as_bytes()))
+                .unwrap();
+        }
+        writer.join().unwrap();
+        db.compact_range(None, None).unwrap();
+        for i in (0..2000).step_by(71) {
+            let k = format!("key{:04}", i);
+            assert_eq!(get_str(&db, k.as_str()), Some(k.repeat(20)));
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let options = Options {
+            merge_operator: Some(Arc::new(AddOperator {})),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_merge");
+        let merge = |v: &str| {
+            db.merge(WriteOptions::default(), Slice::from("c"), Slice::from(v))
+                .unwrap()
+        };
+        // no base value
+        merge("1");
+        assert_eq!(get_str(&db, "c"), Some("1".to_owned()));
+        db.put(WriteOptions::default(), Slice::from("c"), Slice::from("10"))
+            .unwrap();
+        merge("2");
+        merge("3");
+        assert_eq!(get_str(&db, "c"), Some("15".to_owned()));
+        db.delete(WriteOptions::default(), Slice::from("c"))
+            .unwrap();
+        assert_eq!(get_str(&db, "c"), None);
+        merge("5");
+        assert_eq!(get_str(&db, "c"), Some("5".to_owned()));
+
+        let mut batch = WriteBatch::new();
+        batch.merge(b"c", b"1");
+        batch.put(b"d", b"7");
+        batch.merge(b"d", b"1");
+        db.write(WriteOptions::default(), batch).unwrap();
+        assert_eq!(get_str(&db, "c"), Some("6".to_owned()));
+        assert_eq!(get_str(&db, "d"), Some("8".to_owned()));
+    }
+
+    #[test]
+    fn test_merge_without_operator() {
+        let db = new_test_db(Options::default(), "test_merge_without_operator");
+        let e = db
+            .merge(WriteOptions::default(), Slice::from("a"), Slice::from("1"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+
+        let mut batch = WriteBatch::new();
+        batch.merge(b"a", b"1");
+        db.write(WriteOptions::default(), batch).unwrap();
+        let e = db
+            .get(ReadOptions::default(), Slice::from("a"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+        let e = db
+            .get_merge_operands(ReadOptions::default(), Slice::from("a"))
+            .unwrap_err();
+        assert_eq!(e.status(), Status::NotSupported);
+    }
+
+    #[test]
+    fn test_iter_merges_operands() {
+        let options = Options {
+            merge_operator: Some(Arc::new(AddOperator {})),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_iter_merges_operands");
+        let merge = |k: &str, v: &str| {
+            db.merge(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let collect = |forward: bool| {
+            let mut iter = db.iter(ReadOptions::default());
+            let mut entries = vec![];
+            if forward {
+                iter.seek_to_first();
+            } else {
+                iter.seek_to_last();
+            }
+            while iter.valid() {
+                entries.push((
+                    iter.key().as_str().to_owned(),
+                    iter.value().as_str().to_owned(),
+                ));
+                if forward {
+                    iter.next();
                 } else {
-                    let level = compaction.level;
-                    info!(
-                        "Compacting {}@{} + {}@{} files",
-                        compaction.inputs[CompactionInputsRelation::Source as usize].len(),
-                        level,
-                        compaction.inputs[CompactionInputsRelation::Parent as usize].len(),
-                        level + 1
-                    );
-                    {
-                        let snapshots = &mut versions.snapshots;
-                        // Cleanup all redundant snapshots first
-                        snapshots.gc();
-                        if snapshots.is_empty() {
-                            compaction.oldest_snapshot_alive = versions.get_last_sequence();
-                        } else {
-                            compaction.oldest_snapshot_alive = snapshots.oldest().sequence();
-                        }
+                    iter.prev();
+                }
+            }
+            iter.status().unwrap();
+            entries
+        };
+        let entries = |kvs: &[(&str, &str)]| {
+            kvs.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        for k in ["b", "c", "e"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from("10"))
+                .unwrap();
+        }
+        merge("c", "5");
+        merge("d", "5");
+        db.delete(WriteOptions::default(), Slice::from("e"))
+            .unwrap();
+        merge("e", "1");
+        let expected = entries(&[("b", "10"), ("c", "15"), ("d", "5"), ("e", "1")]);
+        assert_eq!(collect(true), expected);
+        assert_eq!(
+            collect(false),
+            expected.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        // the operands are merged across the tables and the memtable
+        db.inner.force_compact_mem_table().unwrap();
+        merge("c", "1");
+        merge("d", "1");
+        let expected = entries(&[("b", "10"), ("c", "16"), ("d", "6"), ("e", "1")]);
+        assert_eq!(collect(true), expected);
+        assert_eq!(
+            collect(false),
+            expected.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        // change the direction on a merged entry
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek(&Slice::from("c"));
+        assert_eq!(iter.value().as_str(), "16");
+        iter.next();
+        assert_eq!(iter.value().as_str(), "6");
+        iter.prev();
+        assert_eq!(iter.key().as_str(), "c");
+        assert_eq!(iter.value().as_str(), "16");
+        iter.next();
+        assert_eq!(iter.key().as_str(), "d");
+        iter.next();
+        iter.next();
+        assert!(!iter.valid());
+        iter.seek(&Slice::from("e"));
+        iter.prev();
+        assert_eq!(iter.key().as_str(), "d");
+        assert_eq!(iter.value().as_str(), "6");
+        // the last key has nothing but an operand
+        merge("f", "3");
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek(&Slice::from("f"));
+        assert_eq!(iter.value().as_str(), "3");
+        iter.prev();
+        assert_eq!(iter.key().as_str(), "e");
+        assert_eq!(iter.value().as_str(), "1");
+    }
+
+    #[test]
+    fn test_get_merge_operands() {
+        let options = Options {
+            merge_operator: Some(Arc::new(AddOperator {})),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_get_merge_operands");
+        let merge = |v: &str| {
+            db.merge(WriteOptions::default(), Slice::from("c"), Slice::from(v))
+                .unwrap()
+        };
+        let operands = |read_opt: ReadOptions| {
+            let (base, operands) = db.get_merge_operands(read_opt, Slice::from("c")).unwrap();
+            let to_string = |v: Vec<u8>| String::from_utf8(v).unwrap();
+            (
+                base.map(to_string),
+                operands.into_iter().map(to_string).collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(operands(ReadOptions::default()), (None, vec![]));
+        db.put(WriteOptions::default(), Slice::from("c"), Slice::from("10"))
+            .unwrap();
+        merge("1");
+        merge("2");
+        // the operands are collected across the memtable and the tables
+        db.inner.force_compact_mem_table().unwrap();
+        let snapshot = db.get_snapshot();
+        merge("3");
+        merge("4");
+        assert_eq!(
+            operands(ReadOptions::default()),
+            (
+                Some("10".to_owned()),
+                vec!["1", "2", "3", "4"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            )
+        );
+        assert_eq!(get_str(&db, "c"), Some("20".to_owned()));
+        let read_opt = ReadOptions {
+            snapshot: Some((*snapshot).clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            operands(read_opt),
+            (Some("10".to_owned()), vec!["1".to_owned(), "2".to_owned()])
+        );
+        // the operands before a deletion are invisible
+        db.delete(WriteOptions::default(), Slice::from("c"))
+            .unwrap();
+        merge("5");
+        assert_eq!(
+            operands(ReadOptions::default()),
+            (None, vec!["5".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let open = || {
+            WickDB::open_db(
+                Options {
+                    env: env.clone(),
+                    ..Default::default()
+                },
+                "test_concurrent_writes".to_owned(),
+            )
+            .unwrap()
+        };
+        let mut db = open();
+        let (threads, n) = (16, 200);
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..n {
+                        let v = i.to_string();
+                        let mut batch = WriteBatch::new();
+                        batch.put(format!("t{:02}_{:03}", t, i).as_bytes(), v.as_bytes());
+                        // overwritten in the order of the writes in this thread
+                        batch.put(format!("t{:02}", t).as_bytes(), v.as_bytes());
+                        let options = WriteOptions {
+                            sync: i % 10 == 0,
+                            ..Default::default()
+                        };
+                        db.write(options, batch).unwrap();
                     }
-                    self.delete_obsolete_files(self.do_compaction(&mut compaction));
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        let check = |db: &WickDB| {
+            for t in 0..threads {
+                assert_eq!(
+                    get_str(db, format!("t{:02}", t).as_str()),
+                    Some((n - 1).to_string())
+                );
+                for i in 0..n {
+                    assert_eq!(
+                        get_str(db, format!("t{:02}_{:03}", t, i).as_str()),
+                        Some(i.to_string())
+                    );
                 }
-                if !self.is_shutting_down.load(Ordering::Acquire) {
-                    if let Some(e) = self.bg_error.read().unwrap().as_ref() {
-                        info!("Compaction error: {:?}", e)
+            }
+        };
+        check(&db);
+        assert_eq!(
+            db.inner.versions.lock().unwrap().get_last_sequence(),
+            threads * n * 2
+        );
+        // all the writes are recovered from the WAL
+        db.close().unwrap();
+        check(&open());
+    }
+
+    #[test]
+    fn test_get_with_seq() {
+        let db = new_test_db(Options::default(), "test_get_with_seq");
+        let put = |k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let get = |k: &str, snapshot: Option<Snapshot>| {
+            let read_opt = || ReadOptions {
+                snapshot: snapshot.clone(),
+                ..Default::default()
+            };
+            let value = db
+                .get_with_seq(read_opt(), Slice::from(k))
+                .unwrap()
+                .map(|(v, seq)| (String::from_utf8(v).unwrap(), seq));
+            let latest = db.get_latest_seq(read_opt(), Slice::from(k)).unwrap();
+            (value, latest)
+        };
+        put("a", "v1");
+        put("b", "v2");
+        assert_eq!(get("a", None), (Some(("v1".to_owned(), 1)), Some(1)));
+        put("a", "v3");
+        let snapshot = db.get_snapshot();
+        assert_eq!(snapshot.sequence_number(), 3);
+        db.delete(WriteOptions::default(), Slice::from("a"))
+            .unwrap();
+        // the deletion is reported by the latest sequence
+        assert_eq!(get("a", None), (None, Some(4)));
+        assert_eq!(
+            get("a", Some((*snapshot).clone())),
+            (Some(("v3".to_owned(), 3)), Some(3))
+        );
+        assert_eq!(get("c", None), (None, None));
+        db.write(WriteOptions::default(), {
+            let mut batch = WriteBatch::new();
+            batch.delete_range(b"b", b"c");
+            batch
+        })
+        .unwrap();
+        assert_eq!(get("b", None), (None, Some(5)));
+        put("c", "v6");
+
+        // the sequences are kept in the sstables
+        db.inner.force_compact_mem_table().unwrap();
+        assert_eq!(get("a", None), (None, Some(4)));
+        assert_eq!(
+            get("a", Some((*snapshot).clone())),
+            (Some(("v3".to_owned(), 3)), Some(3))
+        );
+        // the key shadowed by the range tombstone is reported by the tombstone's sequence
+        assert_eq!(get("b", None), (None, Some(5)));
+        assert_eq!(get("c", None), (Some(("v6".to_owned(), 6)), Some(6)));
+    }
+
+    #[test]
+    fn test_get_detailed() {
+        let db = new_test_db(Options::default(), "test_get_detailed");
+        let get = |k: &str, snapshot: Option<Snapshot>| {
+            db.get_detailed(
+                ReadOptions {
+                    snapshot,
+                    ..Default::default()
+                },
+                Slice::from(k),
+            )
+            .unwrap()
+        };
+        for k in ["a", "b", "c"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from("v"))
+                .unwrap();
+        }
+        let snapshot = db.get_snapshot();
+        db.delete(WriteOptions::default(), Slice::from("a"))
+            .unwrap();
+        db.write(WriteOptions::default(), {
+            let mut batch = WriteBatch::new();
+            batch.delete_range(b"b", b"c");
+            batch
+        })
+        .unwrap();
+        let check = || {
+            assert_eq!(get("a", None), GetResult::Deleted);
+            assert_eq!(
+                get("a", Some(snapshot.snapshot())),
+                GetResult::Found(b"v".to_vec())
+            );
+            assert_eq!(get("c", None), GetResult::Found(b"v".to_vec()));
+            assert_eq!(get("d", None), GetResult::NotPresent);
+        };
+        check();
+        assert_eq!(get("b", None), GetResult::Deleted);
+        assert_eq!(
+            db.get(ReadOptions::default(), Slice::from("a")).unwrap(),
+            None
+        );
+        // the deletion is kept in the sstable
+        db.inner.force_compact_mem_table().unwrap();
+        check();
+        // the range tombstone is kept in the sstable as well
+        assert_eq!(get("b", None), GetResult::Deleted);
+        // a range tombstone flushed after the covered key
+        db.put(WriteOptions::default(), Slice::from("e"), Slice::from("v"))
+            .unwrap();
+        db.inner.force_compact_mem_table().unwrap();
+        db.write(WriteOptions::default(), {
+            let mut batch = WriteBatch::new();
+            batch.delete_range(b"e", b"f");
+            batch
+        })
+        .unwrap();
+        db.inner.force_compact_mem_table().unwrap();
+        assert_eq!(get("e", None), GetResult::Deleted);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        // Collects the puts in a batch
+        #[derive(Default)]
+        struct Puts(Vec<(Vec<u8>, Vec<u8>)>);
+        impl BatchHandler for Puts {
+            fn put(&mut self, key: &[u8], value: &[u8]) {
+                self.0.push((key.to_vec(), value.to_vec()))
+            }
+            fn delete(&mut self, _key: &[u8]) {}
+            fn merge(&mut self, _key: &[u8], _value: &[u8]) {}
+            fn delete_range(&mut self, _start: &[u8], _end: &[u8]) {}
+        }
+
+        let db = new_test_db(Options::default(), "test_subscribe");
+        db.put(WriteOptions::default(), Slice::from("a"), Slice::from("a"))
+            .unwrap();
+        let subscription = db.subscribe(1000);
+        let slow = db.subscribe(1);
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let k = format!("t{}_{:02}", t, i);
+                        db.put(
+                            WriteOptions::default(),
+                            Slice::from(k.as_str()),
+                            Slice::from(k.as_str()),
+                        )
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        // the batches are delivered in the order of the sequence numbers
+        let mut puts = Puts::default();
+        let mut next_seq = 2;
+        while puts.0.len() < 200 {
+            let batch = subscription.receiver().recv().unwrap();
+            assert_eq!(batch.get_sequence(), next_seq);
+            next_seq += u64::from(batch.count());
+            batch.iterate(&mut puts).unwrap();
+        }
+        assert!(subscription.receiver().is_empty());
+        assert_eq!(subscription.dropped_count(), 0);
+        let mut keys: Vec<_> = puts.0.iter().map(|(k, _)| k.clone()).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 200);
+        assert_eq!(slow.receiver().len(), 1);
+        assert!(slow.dropped_count() > 0);
+    }
+
+    #[test]
+    fn test_write_appended_batches() {
+        let db = new_test_db(Options::default(), "test_write_appended_batches");
+        // the sub-batches are built in parallel
+        let workers: Vec<_> = (0..4)
+            .map(|w| {
+                thread::spawn(move || {
+                    let mut batch = WriteBatch::new();
+                    for i in 0..25 {
+                        let k = format!("key{}_{:02}", w, i);
+                        batch.put(k.as_bytes(), k.as_bytes());
                     }
+                    batch.delete(format!("key{}_00", w).as_bytes());
+                    batch
+                })
+            })
+            .collect();
+        let mut batch = WriteBatch::new();
+        for worker in workers {
+            batch.append(&worker.join().unwrap());
+        }
+        batch.append(&WriteBatch::new());
+        assert_eq!(batch.count(), 4 * 26);
+        let last_seq = db.inner.versions.lock().unwrap().get_last_sequence();
+        db.write(WriteOptions::default(), batch).unwrap();
+        // every record takes a sequence number
+        assert_eq!(
+            db.inner.versions.lock().unwrap().get_last_sequence(),
+            last_seq + 4 * 26
+        );
+        for w in 0..4 {
+            assert_eq!(get_str(&db, format!("key{}_00", w).as_str()), None);
+            for i in 1..25 {
+                let k = format!("key{}_{:02}", w, i);
+                assert_eq!(get_str(&db, k.as_str()), Some(k));
+            }
+        }
+    }
+
+    // Orders the keys in the reverse bytewise order
+    fn bounded_read_options(lower: Option<&str>, upper: Option<&str>) -> ReadOptions {
+        ReadOptions {
+            iterate_lower_bound: lower.map(|b| Vec::from(b.as_bytes())),
+            iterate_upper_bound: upper.map(|b| Vec::from(b.as_bytes())),
+            ..Default::default()
+        }
+    }
+
+    fn collect_keys(iter: &mut dyn Iterator, forward: bool) -> Vec<String> {
+        let mut keys = vec![];
+        while iter.valid() {
+            keys.push(iter.key().as_str().to_owned());
+            if forward {
+                iter.next();
+            } else {
+                iter.prev();
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn test_total_order_seek() {
+        let options = Options {
+            prefix_extractor: Some(Arc::new(FixedLengthPrefixExtractor::new(2))),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_total_order_seek");
+        for k in ["ab2", "ba1", "aa1", "ab1", "bb1"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from(*k))
+                .unwrap();
+        }
+        db.inner.force_compact_mem_table().unwrap();
+        for k in ["aa2", "b"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from(*k))
+                .unwrap();
+        }
+        // the iterator stops at the end of the prefix of the seek target by default
+        let mut iter = db.iter(ReadOptions::default());
+        iter.seek(&Slice::from("ab"));
+        assert_eq!(collect_keys(iter.as_mut(), true), vec!["ab1", "ab2"]);
+        iter.seek_to_first();
+        assert_eq!(collect_keys(iter.as_mut(), true).len(), 7);
+
+        let read_opt = ReadOptions {
+            total_order_seek: true,
+            ..Default::default()
+        };
+        let mut iter = db.iter(read_opt);
+        iter.seek(&Slice::from("ab"));
+        assert_eq!(
+            collect_keys(iter.as_mut(), true),
+            vec!["ab1", "ab2", "b", "ba1", "bb1"]
+        );
+        iter.seek(&Slice::from("ab2"));
+        assert_eq!(
+            collect_keys(iter.as_mut(), false),
+            vec!["ab2", "ab1", "aa2", "aa1"]
+        );
+    }
+
+    #[test]
+    fn test_iterate_bounds() {
+        let tests: Vec<(Arc<dyn Comparator>, Vec<&str>)> = vec![
+            (
+                Arc::new(BytewiseComparator::new()),
+                vec!["a", "b", "c", "d", "e", "f"],
+            ),
+            (
+                Arc::new(ReverseComparator::new(Arc::new(BytewiseComparator::new()))),
+                vec!["f", "e", "d", "c", "b", "a"],
+            ),
+        ];
+        for (cmp, ordered) in tests {
+            let options = Options {
+                comparator: cmp,
+                ..Default::default()
+            };
+            let db = new_test_db(options, "test_iterate_bounds");
+            for k in ["c", "a", "f", "d", "b", "e"].iter() {
+                db.put(WriteOptions::default(), Slice::from(*k), Slice::from(*k))
+                    .unwrap();
+            }
+            db.delete(WriteOptions::default(), Slice::from(ordered[3]))
+                .unwrap();
+            // the expected keys: ordered[1], ordered[2], ordered[4]
+            let lower = Some(ordered[1]);
+            let upper = Some(ordered[5]);
+
+            let mut iter = db.iter(bounded_read_options(lower, upper));
+            iter.seek_to_first();
+            assert_eq!(
+                collect_keys(iter.as_mut(), true),
+                vec![ordered[1], ordered[2], ordered[4]]
+            );
+            iter.seek_to_last();
+            assert_eq!(
+                collect_keys(iter.as_mut(), false),
+                vec![ordered[4], ordered[2], ordered[1]]
+            );
+            // seek before the lower bound
+            iter.seek(&Slice::from(ordered[0]));
+            assert_eq!(iter.key().as_str(), ordered[1]);
+            iter.prev();
+            assert!(!iter.valid());
+            // seek to the upper bound
+            iter.seek(&Slice::from(ordered[5]));
+            assert!(!iter.valid());
+            iter.seek(&Slice::from(ordered[4]));
+            assert_eq!(iter.key().as_str(), ordered[4]);
+            iter.next();
+            assert!(!iter.valid());
+
+            // only the lower bound
+            let mut iter = db.iter(bounded_read_options(lower, None));
+            iter.seek_to_last();
+            assert_eq!(
+                collect_keys(iter.as_mut(), false),
+                vec![ordered[5], ordered[4], ordered[2], ordered[1]]
+            );
+            // only the upper bound
+            let mut iter = db.iter(bounded_read_options(None, upper));
+            iter.seek_to_first();
+            assert_eq!(
+                collect_keys(iter.as_mut(), true),
+                vec![ordered[0], ordered[1], ordered[2], ordered[4]]
+            );
+            // an empty range
+            let mut iter = db.iter(bounded_read_options(upper, lower));
+            iter.seek_to_first();
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            assert!(!iter.valid());
+        }
+    }
+
+    // Rotates the memtable and waits until all the immutable memtables are flushed
+    fn flush(db: &WickDB) {
+        let mut versions = db.inner.make_room_for_write(true).unwrap();
+        while !db.inner.im_mem.read().unwrap().is_empty() {
+            versions = db
+                .inner
+                .background_work_finished_signal
+                .wait(versions)
+                .unwrap();
+        }
+    }
+
+    // Returns all the table files with their levels
+    fn files(db: &WickDB) -> Vec<(usize, Arc<FileMetaData>)> {
+        let current = db.inner.versions.lock().unwrap().current();
+        (0..db.inner.options.max_levels as usize)
+            .flat_map(|level| {
+                current
+                    .get_level_files(level)
+                    .iter()
+                    .map(|f| (level, f.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_range_tombstone_file_bounds() {
+        let db = new_test_db(Options::default(), "test_range_tombstone_file_bounds");
+        db.put(WriteOptions::default(), Slice::from("k5"), Slice::from("v"))
+            .unwrap();
+        flush(&db);
+        let (data_level, data_file) = files(&db)[0].clone();
+        assert!(data_level > 0);
+        // the snapshot keeps the tombstone and the covered entry from being dropped by
+        // the compaction below
+        let _snapshot = db.get_snapshot();
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"k0", b"k9");
+        db.write(WriteOptions::default(), batch).unwrap();
+        flush(&db);
+        // the table of the tombstone covers the whole range of the tombstone, so it
+        // overlaps the data and is placed above it
+        let (level, f) = files(&db)
+            .into_iter()
+            .find(|(_, f)| f.number != data_file.number)
+            .unwrap();
+        assert!(level < data_level);
+        assert_eq!(f.smallest.user_key(), b"k0");
+        assert_eq!(f.largest.user_key(), b"k9");
+
+        db.compact_range(None, None).unwrap();
+        // the compaction output covers the range of the tombstone as well
+        let files = files(&db);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1.smallest.user_key(), b"k0");
+        assert_eq!(files[0].1.largest.user_key(), b"k9");
+    }
+
+    #[test]
+    fn test_range_tombstone_dropped_by_bottommost_compaction() {
+        let db = new_test_db(
+            Options::default(),
+            "test_range_tombstone_dropped_by_bottommost_compaction",
+        );
+        for k in ["k1", "k5", "m"].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from("v"))
+                .unwrap();
+        }
+        flush(&db);
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"k0", b"k9");
+        db.write(WriteOptions::default(), batch).unwrap();
+        flush(&db);
+        db.compact_range(None, None).unwrap();
+        // both the tombstone and the covered entries are dropped since no snapshot
+        // sees them and no deeper level holds the covered keys
+        let files = files(&db);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1.smallest.user_key(), b"m");
+        assert_eq!(files[0].1.largest.user_key(), b"m");
+        let current = db.inner.versions.lock().unwrap().current();
+        for level in 0..db.inner.options.max_levels as usize {
+            for f in current.get_level_files(level) {
+                let tombstones = db
+                    .inner
+                    .table_cache
+                    .range_tombstones(level, f.number, f.file_size)
+                    .unwrap();
+                assert!(tombstones.is_empty());
+            }
+        }
+        assert_eq!(get_str(&db, "k1"), None);
+        assert_eq!(get_str(&db, "k5"), None);
+        assert_eq!(get_str(&db, "m"), Some("v".to_owned()));
+    }
+
+    #[test]
+    fn test_file_meta_records_range_tombstones() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let name = "test_file_meta_records_range_tombstones";
+        let new_options = || Options {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let mut db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        db.put(WriteOptions::default(), Slice::from("k1"), Slice::from("v"))
+            .unwrap();
+        flush(&db);
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"k0", b"k9");
+        db.write(WriteOptions::default(), batch).unwrap();
+        flush(&db);
+        let flags = |db: &WickDB| {
+            let mut files = files(db);
+            files.sort_by_key(|(_, f)| f.number);
+            files
+                .iter()
+                .map(|(_, f)| f.has_range_tombstones)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(flags(&db), vec![false, true]);
+        db.close().unwrap();
+
+        // the flags are recovered from the manifest
+        let db = WickDB::open_db(new_options(), name.to_owned()).unwrap();
+        assert_eq!(flags(&db), vec![false, true]);
+        assert_eq!(get_str(&db, "k1"), None);
+    }
+
+    #[test]
+    fn test_compact_range_rewrites_bottommost_level() {
+        let options = Options {
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_compact_range_rewrites_bottommost_level");
+        let old_value = "v".repeat(10000);
+        db.put(
+            WriteOptions::default(),
+            Slice::from("k"),
+            Slice::from(old_value.as_str()),
+        )
+        .unwrap();
+        let snapshot = db.get_snapshot();
+        db.put(WriteOptions::default(), Slice::from("k"), Slice::from("v"))
+            .unwrap();
+        db.compact_range(None, None).unwrap();
+        // the old value is kept for the snapshot
+        let before = files(&db);
+        assert_eq!(before.len(), 1);
+        assert!(before[0].1.file_size > 10000);
+        db.release_snapshot(snapshot);
+        // no file above the bottommost level overlaps the range
+        db.compact_range(None, None).unwrap();
+        let after = files(&db);
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].0, before[0].0);
+        assert_ne!(after[0].1.number, before[0].1.number);
+        assert!(after[0].1.file_size < 10000);
+        assert_eq!(get_str(&db, "k"), Some("v".to_owned()));
+    }
+
+    #[test]
+    fn test_get_honours_flushed_range_tombstones() {
+        let options = Options {
+            merge_operator: Some(Arc::new(AddOperator {})),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_get_honours_flushed_range_tombstones");
+        for i in 1..9 {
+            let k = format!("k{}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from("10"),
+            )
+            .unwrap();
+        }
+        db.merge(WriteOptions::default(), Slice::from("k5"), Slice::from("1"))
+            .unwrap();
+        flush(&db);
+        assert_eq!(get_str(&db, "k5"), Some("11".to_owned()));
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"k0", b"k9");
+        db.write(WriteOptions::default(), batch).unwrap();
+        flush(&db);
+        // the tombstone and the covered entries are in different tables
+        assert_eq!(files(&db).len(), 2);
+        assert_eq!(get_str(&db, "k1"), None);
+        assert_eq!(get_str(&db, "k5"), None);
+        assert_eq!(get_str(&db, "k8"), None);
+
+        // the operands newer than the tombstone are merged without the covered base value
+        db.merge(WriteOptions::default(), Slice::from("k6"), Slice::from("2"))
+            .unwrap();
+        flush(&db);
+        assert_eq!(get_str(&db, "k6"), Some("2".to_owned()));
+        // the entries newer than the tombstone are not deleted
+        db.put(WriteOptions::default(), Slice::from("k7"), Slice::from("3"))
+            .unwrap();
+        flush(&db);
+        assert_eq!(get_str(&db, "k7"), Some("3".to_owned()));
+        assert_eq!(get_str(&db, "k4"), None);
+    }
+
+    #[test]
+    fn test_multi_get() {
+        let options = Options {
+            merge_operator: Some(Arc::new(AddOperator {})),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_multi_get");
+        for (k, v) in [("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")].iter() {
+            db.put(WriteOptions::default(), Slice::from(*k), Slice::from(*v))
+                .unwrap();
+        }
+        db.delete(WriteOptions::default(), Slice::from("b"))
+            .unwrap();
+        db.merge(WriteOptions::default(), Slice::from("c"), Slice::from("10"))
+            .unwrap();
+        db.put(
+            WriteOptions::default(),
+            Slice::from("a"),
+            Slice::from("100"),
+        )
+        .unwrap();
+
+        let keys: Vec<&[u8]> = vec![b"d", b"a", b"x", b"b", b"c", b"a"];
+        let results: Vec<Option<String>> = db
+            .multi_get(ReadOptions::default(), keys.as_slice())
+            .into_iter()
+            .map(|r| r.unwrap().map(|v| String::from_utf8(v).unwrap()))
+            .collect();
+        let expected: Vec<Option<String>> =
+            vec![Some("4"), Some("100"), None, None, Some("13"), Some("100")]
+                .into_iter()
+                .map(|v| v.map(|v| v.to_owned()))
+                .collect();
+        assert_eq!(results, expected);
+
+        // the keys covered by a range tombstone flushed after them are hidden
+        flush(&db);
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"c", b"d");
+        db.write(WriteOptions::default(), batch).unwrap();
+        flush(&db);
+        let results: Vec<Option<Vec<u8>>> = db
+            .multi_get(ReadOptions::default(), &[b"a", b"c", b"d"])
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            results,
+            vec![Some(b"100".to_vec()), None, Some(b"4".to_vec())]
+        );
+
+        assert!(db.multi_get(ReadOptions::default(), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_subcompactions() {
+        // Returns the contents and the table files of every level after the compactions
+        let compact = |max_subcompactions: usize| {
+            let db = new_test_db(
+                Options {
+                    max_subcompactions,
+                    ..Default::default()
+                },
+                "test_subcompactions",
+            );
+            let value =
+                |i: usize, version: &str| format!("{:05}_{}_{}", i, version, "x".repeat(80));
+            // the tables with disjoint key ranges
+            for r in 0..6 {
+                for i in r * 500..(r + 1) * 500 {
+                    let key = format!("key{:05}", i);
+                    db.put(
+                        WriteOptions::default(),
+                        Slice::from(key.as_str()),
+                        Slice::from(value(i, "v1").as_str()),
+                    )
+                    .unwrap();
                 }
-                if is_manual {
-                    versions.manual_compaction.as_mut().unwrap().done = true;
+                db.flush(true).unwrap();
+            }
+            // a table overlapping all of them
+            let mut batch = WriteBatch::new();
+            for i in 0..3000 {
+                let key = format!("key{:05}", i);
+                if i % 5 == 0 {
+                    batch.delete(key.as_bytes());
+                } else if i % 3 == 0 {
+                    batch.put(key.as_bytes(), value(i, "v2").as_bytes());
                 }
             }
+            // the tombstone covers the keys in several subcompactions
+            batch.delete_range(b"key00700", b"key02300");
+            db.write(WriteOptions::default(), batch).unwrap();
+            db.flush(true).unwrap();
+            db.compact_range(None, None).unwrap();
+
+            let mut contents = vec![];
+            let mut iter = db.iter(ReadOptions::default());
+            iter.seek_to_first();
+            while iter.valid() {
+                contents.push((
+                    iter.key().as_slice().to_vec(),
+                    iter.value().as_slice().to_vec(),
+                ));
+                iter.next();
+            }
+            let versions = db.inner.versions.lock().unwrap();
+            let current = versions.current();
+            let files: Vec<Vec<Arc<FileMetaData>>> = (0..db.inner.options.max_levels as usize)
+                .map(|l| current.get_level_files(l).to_vec())
+                .collect();
+            (contents, files)
+        };
+
+        let (expected, single_files) = compact(1);
+        assert_eq!(expected.len(), 3000 - 600 - 1600 + 1600 / 5);
+        let (contents, files) = compact(4);
+        assert_eq!(contents, expected);
+        let ucmp = BytewiseComparator::new();
+        for level_files in files.iter().skip(1) {
+            // the outputs of the subcompactions never overlap
+            for pair in level_files.windows(2) {
+                assert_eq!(
+                    ucmp.compare(pair[0].largest.user_key(), pair[1].smallest.user_key()),
+                    CmpOrdering::Less
+                );
+            }
+        }
+        // the compaction into the bottom level is split into several outputs
+        let count = |files: &[Vec<Arc<FileMetaData>>]| files.iter().map(|f| f.len()).sum::<usize>();
+        assert_eq!(count(single_files.as_slice()), 1);
+        assert!(count(files.as_slice()) > 1);
+    }
+
+    #[test]
+    fn test_universal_compaction() {
+        // Returns the bytes rewritten by the compactions and the DB
+        let load = |compaction_style: CompactionStyle| {
+            let listener = Arc::new(RecordingListener::default());
+            let db = new_test_db(
+                Options {
+                    write_buffer_size: 64 << 10,
+                    compression: CompressionType::NoCompression,
+                    compaction_style,
+                    listeners: vec![listener.clone()],
+                    ..Default::default()
+                },
+                "test_universal_compaction",
+            );
+            let mut expected = HashMap::new();
+            let mut x: u64 = 1;
+            for i in 0..20000 {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let key = format!("key{:06}", (x >> 33) % 100000);
+                let value = format!("{:06}{}", i, "v".repeat(100));
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(key.as_str()),
+                    Slice::from(value.as_str()),
+                )
+                .unwrap();
+                expected.insert(key, value);
+            }
+            db.flush(true).unwrap();
+            // wait for the background compactions
+            while db
+                .inner
+                .background_compaction_scheduled
+                .load(Ordering::Acquire)
+                || db.inner.versions.lock().unwrap().needs_compaction()
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+            for (k, v) in expected.iter() {
+                assert_eq!(get_str(&db, k.as_str()).as_ref(), Some(v));
+            }
+            let rewritten: u64 = listener
+                .compactions
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|c| !c.is_trivial_move)
+                .flat_map(|c| c.output_files.iter().map(|f| f.file_size))
+                .sum();
+            (rewritten, db)
+        };
+
+        let (leveled, _) = load(CompactionStyle::Level);
+        let (universal, db) = load(CompactionStyle::Universal);
+        assert!(universal > 0);
+        assert!(universal < leveled, "{} >= {}", universal, leveled);
+        // the number of the sorted runs is bounded
+        let runs = db.inner.versions.lock().unwrap().current().sorted_runs();
+        assert!(
+            runs.len()
+                <= db
+                    .inner
+                    .options
+                    .universal_compaction_options
+                    .max_sorted_runs
+        );
+        let sizes: Vec<u64> = runs.iter().map(|r| r.size).collect();
+        assert!(
+            pick_sorted_runs(&runs, &db.inner.options).is_none(),
+            "{:?}",
+            sizes
+        );
+
+        let options = Options {
+            universal_compaction_options: UniversalCompactionOptions {
+                min_merge_width: 1,
+                ..Default::default()
+            },
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let e = WickDB::open_db(options, "test_universal_compaction".to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::InvalidArgument);
+    }
+
+    // Removes the entries expired by the timestamp in the first 8 bytes of the value
+    // and rewrites the value "rewrite"
+    struct TtlFilter {
+        now: AtomicU64,
+    }
+
+    impl CompactionFilter for TtlFilter {
+        fn name(&self) -> &str {
+            "TtlFilter"
+        }
+
+        fn filter(&self, _level: usize, _key: &[u8], value: &[u8]) -> Decision {
+            let expire: u64 = std::str::from_utf8(&value[..8]).unwrap().parse().unwrap();
+            if expire <= self.now.load(Ordering::Acquire) {
+                Decision::Remove
+            } else if &value[8..] == b"rewrite" {
+                Decision::ChangeValue(format!("{:08}rewritten", expire).into_bytes())
+            } else {
+                Decision::Keep
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter() {
+        let filter = Arc::new(TtlFilter {
+            now: AtomicU64::new(100),
+        });
+        let db = new_test_db(
+            Options {
+                compaction_filter: Some(filter.clone()),
+                max_mem_compact_level: 0,
+                ..Default::default()
+            },
+            "test_compaction_filter",
+        );
+        let put = |key: &str, expire: u64, payload: &str| {
+            let value = format!("{:08}{}", expire, payload);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(key),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
+        };
+        for i in 0..10 {
+            put(format!("old{}", i).as_str(), 50, "v");
+        }
+        let snapshot = db.get_snapshot();
+        for i in 0..10 {
+            put(format!("new{}", i).as_str(), 50, "v");
+            put(format!("live{}", i).as_str(), 1000, "v");
+        }
+        put("rewrite", 1000, "rewrite");
+        // compacts the level-0 table into level 1
+        db.compact_range(None, None).unwrap();
+        assert_eq!(
+            db.inner
+                .versions
+                .lock()
+                .unwrap()
+                .current()
+                .get_level_files(1)
+                .len(),
+            1
+        );
+        let read_opt = || ReadOptions {
+            snapshot: Some((*snapshot).clone()),
+            ..Default::default()
+        };
+        for i in 0..10 {
+            // the expired keys visible to the snapshot are never filtered
+            let old = format!("old{}", i);
+            assert_eq!(get_str(&db, old.as_str()), Some("00000050v".to_owned()));
+            assert_eq!(
+                db.get(read_opt(), Slice::from(old.as_str())).unwrap(),
+                Some(b"00000050v".to_vec())
+            );
+            assert_eq!(get_str(&db, format!("new{}", i).as_str()), None);
+            assert_eq!(
+                get_str(&db, format!("live{}", i).as_str()),
+                Some("00001000v".to_owned())
+            );
+        }
+        assert_eq!(
+            get_str(&db, "rewrite"),
+            Some("00001000rewritten".to_owned())
+        );
+        assert_eq!(db.get(read_opt(), Slice::from("rewrite")).unwrap(), None);
+
+        // all the expired keys are dropped without tombstones once the snapshot is released
+        drop(snapshot);
+        filter.now.store(200, Ordering::Release);
+        db.inner
+            .manual_compact_range(1, false, None, None, &CompactRangeOptions::default())
+            .unwrap();
+        assert_eq!(
+            db.inner
+                .versions
+                .lock()
+                .unwrap()
+                .current()
+                .get_level_files(2)
+                .len(),
+            1
+        );
+        for i in 0..10 {
+            assert_eq!(get_str(&db, format!("old{}", i).as_str()), None);
+        }
+        let mut entries = 0;
+        let iters = db.inner.versions.lock().unwrap().current_iters(
+            Rc::new(ReadOptions::default()),
+            db.inner.table_cache.clone(),
+        );
+        for mut iter in iters {
+            iter.seek_to_first();
+            while iter.valid() {
+                entries += 1;
+                iter.next();
+            }
+        }
+        assert_eq!(entries, 11);
+    }
+
+    #[test]
+    fn test_ttl_compaction() {
+        let env = Arc::new(MemStorage::default());
+        let filter = Arc::new(TtlFilter {
+            now: AtomicU64::new(100),
+        });
+        let new_options = || Options {
+            env: env.clone(),
+            max_levels: 3,
+            ttl: 2,
+            compaction_filter: Some(filter.clone()),
+            ..Default::default()
+        };
+        let name = "test_ttl_compaction".to_owned();
+        let last_level_file = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            let files = current.get_level_files(2);
+            assert_eq!(files.len(), 1);
+            (files[0].number, files[0].creation_time)
+        };
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        for i in 0..10 {
+            for (key, expire) in [(format!("expired{}", i), 50), (format!("live{}", i), 1000)] {
+                let value = format!("{:08}v", expire);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(key.as_str()),
+                    Slice::from(value.as_str()),
+                )
+                .unwrap();
+            }
+        }
+        // the memtable is flushed into the last level without being filtered
+        db.flush(true).unwrap();
+        let (number, creation_time) = last_level_file(&db);
+        assert!(creation_time > 0);
+        assert_eq!(get_str(&db, "expired0"), Some("00000050v".to_owned()));
+        db.close().unwrap();
+
+        // the file exceeding the ttl is picked after reopening the DB
+        thread::sleep(Duration::from_secs(3));
+        let db = WickDB::open_db(new_options(), name).unwrap();
+        while db
+            .inner
+            .background_compaction_scheduled
+            .load(Ordering::Acquire)
+            || db.inner.versions.lock().unwrap().needs_compaction()
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let (new_number, new_creation_time) = last_level_file(&db);
+        assert_ne!(new_number, number);
+        assert!(new_creation_time >= creation_time + 3);
+        for i in 0..10 {
+            assert_eq!(get_str(&db, format!("expired{}", i).as_str()), None);
+            assert_eq!(
+                get_str(&db, format!("live{}", i).as_str()),
+                Some("00001000v".to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn test_cancel_manual_compaction() {
+        let env = Arc::new(BlockingTableStorage::new(usize::MAX));
+        let options = Options {
+            max_mem_compact_level: 0,
+            compression: CompressionType::NoCompression,
+            env: env.clone(),
+            ..Default::default()
+        };
+        let name = "test_cancel_manual_compaction";
+        let db = WickDB::open_db(options, name.to_owned()).unwrap();
+        let value = "v".repeat(1000);
+        for round in 0..2 {
+            for i in 0..3000 {
+                let key = format!("key{:04}", i);
+                let value = format!("{}{}", round, value);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(key.as_str()),
+                    Slice::from(value.as_str()),
+                )
+                .unwrap();
+            }
+            db.flush(true).unwrap();
+        }
+        let live_files = |db: &WickDB| {
+            let current = db.inner.versions.lock().unwrap().current();
+            (0..db.inner.options.max_levels as usize)
+                .map(|l| {
+                    current
+                        .get_level_files(l)
+                        .iter()
+                        .map(|f| f.number)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let tables = || {
+            env.list(name)
+                .unwrap()
+                .iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "sst"))
+                .count()
+        };
+        let before = live_files(&db);
+        assert_eq!(before[0].len(), 2);
+        assert_eq!(tables(), 2);
+
+        // Only one output file is allowed to be created until the cancellation
+        env.set_allowed(1);
+        let canceled = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(CompactionProgress::default());
+        let handle = {
+            let db = db.clone();
+            let options = CompactRangeOptions {
+                progress: Some(progress.clone()),
+                canceled: Some(canceled.clone()),
+            };
+            thread::spawn(move || db.compact_range_with_options(None, None, options))
+        };
+        while *env.allowed.lock().unwrap() > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        canceled.store(true, Ordering::Release);
+        env.set_allowed(usize::MAX);
+        let e = handle.join().unwrap().unwrap_err();
+        assert_eq!(e.status(), Status::Aborted);
+        // none of the outputs is visible and the files are removed
+        assert_eq!(live_files(&db), before);
+        assert_eq!(tables(), 2);
+        assert_eq!(progress.bytes_read(), 0);
+        assert_eq!(progress.bytes_written(), 0);
+        assert!(db.inner.bg_error.read().unwrap().is_none());
+        for i in 0..3000 {
+            let key = format!("key{:04}", i);
+            assert_eq!(get_str(&db, key.as_str()), Some(format!("1{}", value)));
+        }
+
+        // the DB is still able to be compacted
+        let progress = Arc::new(CompactionProgress::default());
+        db.compact_range_with_options(
+            None,
+            None,
+            CompactRangeOptions {
+                progress: Some(progress.clone()),
+                canceled: Some(Arc::new(AtomicBool::new(false))),
+            },
+        )
+        .unwrap();
+        let after = live_files(&db);
+        assert!(after[0].is_empty());
+        assert!(after[1].len() > 1);
+        assert!(progress.bytes_read() > 0);
+        assert!(progress.bytes_written() > 0);
+        for i in 0..3000 {
+            let key = format!("key{:04}", i);
+            assert_eq!(get_str(&db, key.as_str()), Some(format!("1{}", value)));
         }
     }
 
-    // Merging files in level n into file in level n + 1 and
-    // keep the still-in-use files
-    fn do_compaction(&self, c: &mut Compaction) -> MutexGuard<VersionSet> {
-        let now = SystemTime::now();
-        let mut input_iter =
-            c.new_input_iterator(self.internal_comparator.clone(), self.table_cache.clone());
-        let mut mem_compaction_duration = 0;
-        input_iter.seek_to_first();
+    #[test]
+    fn test_wal_recovery_mode() {
+        let env = Arc::new(MemStorage::default());
+        let new_options = |mode| Options {
+            env: env.clone(),
+            paranoid_checks: true,
+            wal_recovery_mode: mode,
+            ..Default::default()
+        };
+        // Writes 10 records into the log and corrupts the log by `corrupt`
+        let prepare = |name: &str, corrupt: &dyn Fn(&mut Vec<u8>)| {
+            let mut db = WickDB::open_db(
+                new_options(WALRecoveryMode::TolerateCorruptedTailRecords),
+                name.to_owned(),
+            )
+            .unwrap();
+            for i in 0..10 {
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(format!("key{}", i).as_str()),
+                    Slice::from("v".repeat(1000).as_str()),
+                )
+                .unwrap();
+            }
+            db.close().unwrap();
+            let logs: Vec<_> = env
+                .list(name)
+                .unwrap()
+                .into_iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "log"))
+                .collect();
+            assert_eq!(logs.len(), 1);
+            let log = logs[0].to_str().unwrap();
+            let mut buf = vec![];
+            env.open(log).unwrap().read_all(&mut buf).unwrap();
+            corrupt(&mut buf);
+            env.create(log).unwrap().write(buf.as_slice()).unwrap();
+        };
+        let open = |mode, name: &str| WickDB::open_db(new_options(mode), name.to_owned());
 
-        // the current user key to be compacted
+        // a torn trailing record
+        let name = "test_wal_recovery_mode_torn_tail";
+        prepare(name, &|buf| buf.truncate(buf.len() - 10));
+        assert_eq!(
+            open(WALRecoveryMode::AbsoluteConsistency, name)
+                .err()
+                .unwrap()
+                .status(),
+            Status::Corruption
+        );
+        let mut db = open(WALRecoveryMode::TolerateCorruptedTailRecords, name).unwrap();
+        for i in 0..9 {
+            assert!(get_str(&db, format!("key{}", i).as_str()).is_some());
+        }
+        assert_eq!(get_str(&db, "key9"), None);
+        db.put(
+            WriteOptions::default(),
+            Slice::from("key9"),
+            Slice::from("v"),
+        )
+        .unwrap();
+        db.close().unwrap();
+        // the new records are not appended after the torn one
+        let db = open(WALRecoveryMode::AbsoluteConsistency, name).unwrap();
+        assert_eq!(get_str(&db, "key9"), Some("v".to_owned()));
 
-        let mut current_ukey = Slice::default();
-        let mut has_current_ukey = false;
-        let mut last_sequence_for_key = u64::max_value();
+        // a corrupted record in the middle
+        let name = "test_wal_recovery_mode_corrupted_middle";
+        prepare(name, &|buf| {
+            let pos = buf.len() / 2
+                + buf[buf.len() / 2..]
+                    .iter()
+                    .position(|b| *b == b'v')
+                    .unwrap();
+            buf[pos] = b'x';
+        });
+        for mode in [
+            WALRecoveryMode::TolerateCorruptedTailRecords,
+            WALRecoveryMode::AbsoluteConsistency,
+        ] {
+            assert_eq!(open(mode, name).err().unwrap().status(), Status::Corruption);
+        }
+    }
 
-        let icmp = self.internal_comparator.clone();
-        let ucmp = icmp.user_comparator.as_ref();
-        let mut status = Ok(());
-        // Iterate every key
-        while input_iter.valid() && !self.is_shutting_down.load(Ordering::Acquire) {
-            // Prioritize immutable compaction work
-            if self.im_mem.read().unwrap().is_some() {
-                let imm_start = SystemTime::now();
-                self.compact_mem_table();
-                mem_compaction_duration = imm_start.elapsed().unwrap().as_micros() as u64;
-            }
-            let ikey = input_iter.key();
-            // Checkout whether we need rotate a new output file
-            if c.should_stop_before(&ikey, icmp.clone()) && c.builder.is_some() {
-                status = self.finish_output_file(c, input_iter.valid());
-                if status.is_err() {
-                    break;
-                }
-            }
-            let mut drop = false;
-            match ParsedInternalKey::decode_from(ikey.clone()) {
-                Some(key) => {
-                    if !has_current_ukey
-                        || ucmp.compare(key.user_key.as_slice(), current_ukey.as_slice())
-                            != CmpOrdering::Equal
-                    {
-                        // First occurrence of this user key
-                        current_ukey = key.user_key.clone();
-                        has_current_ukey = true;
-                        last_sequence_for_key = u64::max_value();
-                    }
-                    // Keep the still-in-use old key or not
-                    if last_sequence_for_key <= c.oldest_snapshot_alive
-                        || (key.value_type == ValueType::Deletion
-                            && key.seq <= c.oldest_snapshot_alive
-                            && !c.key_exist_in_deeper_level(&key.user_key))
-                    {
-                        // For this user key:
-                        // (1) there is no data in higher levels
-                        // (2) data in lower levels will have larger sequence numbers
-                        // (3) data in layers that are being compacted here and have
-                        //     smaller sequence numbers will be dropped in the next
-                        //     few iterations of this loop
-                        //     (by last_sequence_for_key <= c.smallest_snapshot above).
-                        // Therefore this deletion marker is obsolete and can be dropped.
-                        drop = true
-                    }
-                    last_sequence_for_key = key.seq;
-                    if !drop {
-                        // Open output file if necessary
-                        if c.builder.is_none() {
-                            status = self.versions.lock().unwrap().open_compaction_output_file(c);
-                            if status.is_err() {
-                                break;
-                            }
-                        }
-                        let last = c.outputs.len() - 1;
-                        // TODO: InternalKey::decoded_from adds extra cost of copying
-                        if c.builder.as_ref().unwrap().num_entries() == 0 {
-                            // We have a brand new builder so use current key as smallest
-                            c.outputs[last].smallest =
-                                Rc::new(InternalKey::decoded_from(ikey.as_slice()));
-                        }
-                        // Keep updating the largest
-                        c.outputs[last].largest =
-                            Rc::new(InternalKey::decoded_from(ikey.as_slice()));
-                        let _ = c
-                            .builder
-                            .as_mut()
-                            .unwrap()
-                            .add(ikey.as_slice(), input_iter.value().as_slice());
-                        let builder = c.builder.as_ref().unwrap();
-                        // Rotate a new output file if the current one is big enough
-                        if builder.file_size() >= self.options.max_file_size {
-                            status = self.finish_output_file(c, input_iter.valid());
-                            if status.is_err() {
-                                break;
-                            }
-                        }
-                    }
-                }
-                None => {
-                    current_ukey = Slice::default();
-                    has_current_ukey = false;
-                    last_sequence_for_key = u64::max_value();
-                }
-            }
-            input_iter.next();
+    #[test]
+    fn test_recycle_log_files() {
+        let env = Arc::new(MemStorage::default());
+        let new_options = || Options {
+            env: env.clone(),
+            paranoid_checks: true,
+            recycle_log_file_num: 1,
+            ..Default::default()
+        };
+        let name = "test_recycle_log_files".to_owned();
+        let log_name = |n| generate_filename(name.as_str(), FileType::Log, n);
+        let log_size = |n| env.open(log_name(n).as_str()).unwrap().len().unwrap();
+        let value = "old".repeat(100);
+        let mut db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        for i in 0..100 {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(format!("key{:03}", i).as_str()),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
         }
-        // TODO: simplify the implementation
-        if status.is_ok() && self.is_shutting_down.load(Ordering::Acquire) {
-            status = Err(WickErr::new(
-                Status::IOError,
-                Some("Deleting DB during compaction"),
-            ))
+        let old_log = db.inner.versions.lock().unwrap().get_log_number();
+        let old_size = log_size(old_log);
+        db.flush(true).unwrap();
+        // the flushed log is kept for recycling
+        assert_eq!(
+            db.inner.versions.lock().unwrap().recycled_logs,
+            VecDeque::from(vec![old_log])
+        );
+        assert!(env.exists(log_name(old_log).as_str()));
+        for i in 0..100 {
+            db.delete(
+                WriteOptions::default(),
+                Slice::from(format!("key{:03}", i).as_str()),
+            )
+            .unwrap();
         }
-        if status.is_ok() && c.builder.is_some() {
-            status = self.finish_output_file(c, input_iter.valid())
+        db.flush(true).unwrap();
+
+        // the new log overwrites the recycled file
+        let log = db.inner.versions.lock().unwrap().get_log_number();
+        assert!(!env.exists(log_name(old_log).as_str()));
+        assert_eq!(log_size(log), old_size);
+        db.put(
+            WriteOptions::default(),
+            Slice::from("key000"),
+            Slice::from("new"),
+        )
+        .unwrap();
+        db.put(
+            WriteOptions::default(),
+            Slice::from("other"),
+            Slice::from("v"),
+        )
+        .unwrap();
+        db.close().unwrap();
+        assert_eq!(log_size(log), old_size);
+
+        // only the new records are read from the recycled file
+        let mut reader = Reader::new(
+            env.open(log_name(log).as_str()).unwrap(),
+            None,
+            true,
+            0,
+            WALRecoveryMode::TolerateCorruptedTailRecords,
+            BLOCK_SIZE,
+            log,
+        );
+        let mut count = 0;
+        let mut buf = vec![];
+        while reader.read_record(&mut buf) {
+            count += 1;
         }
+        assert_eq!(count, 2);
+        assert!(reader.tail_dropped());
 
-        if status.is_ok() {
-            status = input_iter.status()
+        let db = WickDB::open_db(new_options(), name.clone()).unwrap();
+        assert_eq!(get_str(&db, "key000"), Some("new".to_owned()));
+        assert_eq!(get_str(&db, "other"), Some("v".to_owned()));
+        for i in 1..100 {
+            assert_eq!(get_str(&db, format!("key{:03}", i).as_str()), None);
         }
-        // Calculate the stats of this compaction
-        let mut versions = self.versions.lock().unwrap();
-        versions.compaction_stats[c.level + 1].accumulate(
-            now.elapsed().unwrap().as_micros() as u64 - mem_compaction_duration,
-            c.bytes_read(),
-            c.bytes_written(),
+
+        // incompatible with the absolute consistency
+        let options = Options {
+            wal_recovery_mode: WALRecoveryMode::AbsoluteConsistency,
+            ..new_options()
+        };
+        assert_eq!(
+            WickDB::open_db(options, name).err().unwrap().status(),
+            Status::InvalidArgument
         );
-        if status.is_ok() {
-            info!(
-                "Compacted {}@{} + {}@{} files => {} bytes",
-                c.inputs[CompactionInputsRelation::Source as usize].len(),
-                c.level,
-                c.inputs[CompactionInputsRelation::Parent as usize].len(),
-                c.level + 1,
-                c.total_bytes,
+    }
+
+    #[test]
+    fn test_log_block_size() {
+        let env = Arc::new(MemStorage::default());
+        let new_options = |block_size| Options {
+            env: env.clone(),
+            paranoid_checks: true,
+            log_block_size: block_size,
+            ..Default::default()
+        };
+        let name = "test_log_block_size".to_owned();
+        for block_size in [0, 1 << 17].iter() {
+            assert_eq!(
+                WickDB::open_db(new_options(*block_size), name.clone())
+                    .err()
+                    .unwrap()
+                    .status(),
+                Status::InvalidArgument
             );
-            c.apply_to_edit();
-            status = versions.log_and_apply(&mut c.edit);
         }
-        if let Err(e) = status {
-            self.record_bg_error(e)
+        let value = "v".repeat(3000);
+        let mut db = WickDB::open_db(new_options(1024), name.clone()).unwrap();
+        for i in 0..10 {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(format!("key{}", i).as_str()),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
         }
+        db.close().unwrap();
 
-        let summary = versions.current().level_summary();
-        info!("compacted to : {}", summary);
+        // the block size must match the existing one
+        assert_eq!(
+            WickDB::open_db(new_options(BLOCK_SIZE), name.clone())
+                .err()
+                .unwrap()
+                .status(),
+            Status::InvalidArgument
+        );
+        // the records are recovered from the log
+        let db = WickDB::open_db(new_options(1024), name).unwrap();
+        for i in 0..10 {
+            assert_eq!(
+                get_str(&db, format!("key{}", i).as_str()),
+                Some(value.clone())
+            );
+        }
+    }
 
-        // Close unclosed table builder and remove files in `pending_outputs`
-        if let Some(builder) = c.builder.as_mut() {
-            builder.close()
+    #[test]
+    fn test_snapshot_iterator() {
+        let db = new_test_db(Options::default(), "test_snapshot_iterator");
+        let put = |k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        let scan = |snapshot: Option<&SnapshotHandle>| {
+            let mut iter = db.iter(ReadOptions {
+                snapshot: snapshot.map(|s| s.snapshot()),
+                ..Default::default()
+            });
+            iter.seek_to_first();
+            let mut res = vec![];
+            while iter.valid() {
+                res.push(format!("{}={}", iter.key().as_str(), iter.value().as_str()));
+                iter.next();
+            }
+            res
+        };
+        // Returns the number of the entries of `key` in the sstables
+        let table_entries = |key: &str| {
+            let iters = db.inner.versions.lock().unwrap().current_iters(
+                Rc::new(ReadOptions::default()),
+                db.inner.table_cache.clone(),
+            );
+            let mut count = 0;
+            for mut iter in iters {
+                iter.seek_to_first();
+                while iter.valid() {
+                    if extract_user_key(iter.key().as_slice()).as_slice() == key.as_bytes() {
+                        count += 1;
+                    }
+                    iter.next();
+                }
+            }
+            count
+        };
+        for k in ["a", "b", "c"].iter() {
+            put(k, "1");
         }
-        for output in c.outputs.iter() {
-            versions.pending_outputs.remove(&output.number);
+        db.flush(true).unwrap();
+        put("b", "2");
+        let snapshot = db.get_snapshot();
+
+        // the newer writes land in the memtable and the sstables
+        put("b", "3");
+        put("d", "1");
+        db.delete(WriteOptions::default(), Slice::from("a"))
+            .unwrap();
+        db.flush(true).unwrap();
+        db.compact_range(None, None).unwrap();
+        put("e", "1");
+        assert_eq!(scan(Some(&snapshot)), vec!["a=1", "b=2", "c=1"]);
+        assert_eq!(scan(None), vec!["b=3", "c=1", "d=1", "e=1"]);
+        // the entries visible to the snapshot survive the compaction
+        assert_eq!(table_entries("b"), 2);
+        assert_eq!(table_entries("a"), 2);
+
+        // an iterator without a snapshot ignores the writes after it's created
+        // even if they are compacted
+        let mut iter = db.iter(ReadOptions::default());
+        put("b", "4");
+        put("f", "1");
+        db.flush(true).unwrap();
+        db.compact_range(None, None).unwrap();
+        iter.seek_to_first();
+        let mut res = vec![];
+        while iter.valid() {
+            res.push(format!("{}={}", iter.key().as_str(), iter.value().as_str()));
+            iter.next();
         }
-        versions
+        assert!(iter.status().is_ok());
+        assert_eq!(res, vec!["b=3", "c=1", "d=1", "e=1"]);
+        assert_eq!(scan(Some(&snapshot)), vec!["a=1", "b=2", "c=1"]);
+
+        // the old entries are compacted away after the snapshots are released
+        drop(snapshot);
+        drop(iter);
+        put("c", "2");
+        db.flush(true).unwrap();
+        db.compact_range(None, None).unwrap();
+        assert_eq!(table_entries("a"), 0);
+        assert_eq!(table_entries("b"), 1);
+        assert_eq!(scan(None), vec!["b=4", "c=2", "d=1", "e=1", "f=1"]);
     }
 
-    // Replace the `bg_error` with new WickErr if it's None
-    fn record_bg_error(&self, e: WickErr) {
-        let old = self.bg_error.read().unwrap();
-        if old.is_none() {
-            mem::drop(old);
-            let mut x = self.bg_error.write().unwrap();
-            *x = Some(e);
-            self.background_work_finished_signal.notify_all();
+    #[test]
+    fn test_snapshot_handle() {
+        let db = new_test_db(Options::default(), "test_snapshot_handle");
+        let put = |k: &str, v: &str| {
+            db.put(WriteOptions::default(), Slice::from(k), Slice::from(v))
+                .unwrap()
+        };
+        put("a", "1");
+        db.flush(true).unwrap();
+        let s1 = db.get_snapshot();
+        put("a", "2");
+        let mut s2 = db.get_snapshot();
+        put("a", "3");
+        let s3 = db.get_snapshot();
+        let get = |s: &SnapshotHandle| {
+            db.get(
+                ReadOptions {
+                    snapshot: Some(s.snapshot()),
+                    ..Default::default()
+                },
+                Slice::from("a"),
+            )
+            .unwrap()
+            .unwrap()
+        };
+        assert_eq!(get(&s1), b"1");
+        assert_eq!(get(&s2), b"2");
+        assert_eq!(get(&s3), b"3");
+
+        // the handle is able to be released in another thread
+        let oldest = |db: &WickDB| {
+            let mut versions = db.inner.versions.lock().unwrap();
+            versions.snapshots.gc();
+            if versions.snapshots.is_empty() {
+                None
+            } else {
+                Some(versions.snapshots.oldest().sequence_number())
+            }
+        };
+        assert_eq!(oldest(&db), Some(s1.sequence_number()));
+        thread::spawn(move || drop(s1)).join().unwrap();
+        assert_eq!(oldest(&db), Some(s2.sequence_number()));
+
+        // releasing explicitly and then dropping is safe
+        s2.release();
+        assert!(s2.is_released());
+        s2.release();
+        assert_eq!(oldest(&db), Some(s3.sequence_number()));
+        drop(s2);
+        // the snapshot list is cleaned up at once by `release_snapshot`
+        db.release_snapshot(s3);
+        assert!(db.inner.versions.lock().unwrap().snapshots.is_empty());
+
+        // the old values are compacted away after all the snapshots are released
+        db.flush(true).unwrap();
+        db.compact_range(None, None).unwrap();
+        let iters = db.inner.versions.lock().unwrap().current_iters(
+            Rc::new(ReadOptions::default()),
+            db.inner.table_cache.clone(),
+        );
+        let mut count = 0;
+        for mut iter in iters {
+            iter.seek_to_first();
+            while iter.valid() {
+                count += 1;
+                iter.next();
+            }
         }
+        assert_eq!(count, 1);
     }
 
-    // Check whether db needs to run a compaction. DB will run a compaction when:
-    // 1. no background compaction is running
-    // 2. DB is not shutting down
-    // 3. no error has been encountered
-    // 4. there is an immutable table or a manual compaction request or current version needs to be compacted
-    fn maybe_schedule_compaction(&self) {
-        if self.background_compaction_scheduled.load(Ordering::Acquire)
-            // Already scheduled
-        || self.is_shutting_down.load(Ordering::Acquire)
-            // DB is being shutting down
-        || self.bg_error.read().unwrap().is_some()
-            // Got err
-        ||  (self.im_mem.read().unwrap().is_none()
-            && !self.versions.lock().unwrap().needs_compaction())
-        {
-            // No work needs to be done
-        } else {
-            self.background_compaction_scheduled
-                .store(true, Ordering::Release);
-            if let Err(e) = self.do_compaction.0.send(()) {
-                error!(
-                    "[schedule compaction] Fail sending signal to compaction channel: {}",
-                    e
-                )
+    #[test]
+    fn test_key_may_exist() {
+        let options = Options {
+            filter_policy: Some(Arc::new(BloomFilter::new(10))),
+            ..Default::default()
+        };
+        let db = new_test_db(options, "test_key_may_exist");
+        let may_exist = |k: &str| db.key_may_exist(ReadOptions::default(), Slice::from(k));
+        for i in (0..200).step_by(2) {
+            let k = format!("key{:03}", i);
+            db.put(
+                WriteOptions::default(),
+                Slice::from(k.as_str()),
+                Slice::from("v"),
+            )
+            .unwrap();
+        }
+        db.flush(true).unwrap();
+        db.compact_range(None, None).unwrap();
+        // keys out of the range of all the tables
+        assert!(!may_exist("a"));
+        assert!(!may_exist("z"));
+        let mut negatives = 0;
+        for i in 0..200 {
+            let k = format!("key{:03}", i);
+            if i % 2 == 0 {
+                // never false negative
+                assert!(may_exist(k.as_str()));
+            } else if !may_exist(k.as_str()) {
+                negatives += 1;
             }
         }
+        assert!(negatives > 90, "{} absent keys are filtered", negatives);
+
+        // the memtable tells the newest state of the key
+        db.put(WriteOptions::default(), Slice::from("a"), Slice::from("v"))
+            .unwrap();
+        db.delete(WriteOptions::default(), Slice::from("key000"))
+            .unwrap();
+        assert!(may_exist("a"));
+        assert!(!may_exist("key000"));
+        let snapshot = db.get_snapshot();
+        db.delete(WriteOptions::default(), Slice::from("a"))
+            .unwrap();
+        assert!(!may_exist("a"));
+        assert!(db.key_may_exist(
+            ReadOptions {
+                snapshot: Some(snapshot.snapshot()),
+                ..Default::default()
+            },
+            Slice::from("a")
+        ));
     }
 
-    // Finish the current output file by calling `buidler.finish` and insert it into the table cache
-    fn finish_output_file(&self, compact: &mut Compaction, input_iter_valid: bool) -> Result<()> {
-        assert!(!compact.outputs.is_empty());
-        assert!(compact.builder.is_some());
-        let current_entries = compact.builder.as_ref().unwrap().num_entries();
-        let status = if input_iter_valid {
-            compact.builder.as_mut().unwrap().finish(true)
-        } else {
-            compact.builder.as_mut().unwrap().close();
-            Ok(())
+    #[test]
+    fn test_max_open_files() {
+        let open_tables = |max_open_files: usize| {
+            let env = Arc::new(MemStorage::default());
+            let options = Options {
+                env: env.clone(),
+                max_open_files,
+                ..Default::default()
+            };
+            let db = WickDB::open_db(options, "test_max_open_files".to_owned()).unwrap();
+            let base = env.open_handles();
+            for i in 0..100 {
+                let k = format!("key{:03}", i);
+                db.put(
+                    WriteOptions::default(),
+                    Slice::from(k.as_str()),
+                    Slice::from("v"),
+                )
+                .unwrap();
+                db.flush(true).unwrap();
+            }
+            let files: usize = (0..7)
+                .map(|l| {
+                    db.get_property(&format!("wickdb.num-files-at-level{}", l))
+                        .unwrap()
+                        .parse::<usize>()
+                        .unwrap()
+                })
+                .sum();
+            assert_eq!(files, 100);
+            for i in 0..100 {
+                let k = format!("key{:03}", i);
+                assert_eq!(get_str(&db, k.as_str()).unwrap(), "v");
+            }
+            env.open_handles() - base
         };
-        let current_bytes = compact.builder.as_ref().unwrap().file_size();
-        // update current output
-        let length = compact.outputs.len();
-        compact.outputs[length - 1].file_size = current_bytes;
-        compact.total_bytes += current_bytes;
-        compact.builder = None;
-        if status.is_ok() && current_entries > 0 {
-            let output_number = compact.outputs[length - 1].number;
-            // make sure that the new file is in the cache
-            let mut it = self.table_cache.new_iter(
-                Rc::new(ReadOptions::default()),
-                output_number,
-                current_bytes,
-            );
-            it.status()?;
-            info!(
-                "Generated table #{}@{}: {} keys, {} bytes",
-                output_number, compact.level, current_entries, current_bytes
-            );
-        }
-        status
+        // the table cache holds 64 tables at most
+        assert!(open_tables(0) <= 64);
+        assert!(open_tables(1000) >= 100);
     }
-}
 
-// A wrapper struct for scheduling `WriteBatch`
-struct BatchTask {
-    batch: WriteBatch,
-    signal: Sender<Result<()>>,
-    options: WriteOptions,
-}
+    #[test]
+    fn test_initial_sequence() {
+        let name = "test_initial_sequence";
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let open = |initial_sequence: u64| {
+            WickDB::open_db(
+                Options {
+                    env: env.clone(),
+                    initial_sequence,
+                    ..Default::default()
+                },
+                name.to_owned(),
+            )
+        };
+        let get = |db: &WickDB, k: &str| {
+            db.get_with_seq(ReadOptions::default(), Slice::from(k))
+                .unwrap()
+                .unwrap()
+        };
+        let mut db = open(1000).unwrap();
+        db.put(WriteOptions::default(), Slice::from("k"), Slice::from("v1"))
+            .unwrap();
+        assert_eq!(get(&db, "k"), (b"v1".to_vec(), 1001));
+        db.put(WriteOptions::default(), Slice::from("k"), Slice::from("v2"))
+            .unwrap();
+        assert_eq!(get(&db, "k"), (b"v2".to_vec(), 1002));
+        db.flush(true).unwrap();
+        db.put(WriteOptions::default(), Slice::from("k"), Slice::from("v3"))
+            .unwrap();
+        db.close().unwrap();
 
-impl BatchTask {
-    fn new(batch: WriteBatch, signal: Sender<Result<()>>, options: WriteOptions) -> Self {
-        Self {
-            batch,
-            signal,
-            options,
+        // the recovered sequence is larger than the option
+        let err = open(1000).err().unwrap();
+        assert_eq!(err.status(), Status::InvalidArgument);
+        assert!(err.to_string().contains("initial_sequence"));
+
+        // the option is ignored when equal to the recovered sequence, and the
+        // raised sequence persists across reopens
+        for initial_sequence in &[1003, 0] {
+            let mut db = open(*initial_sequence).unwrap();
+            assert_eq!(get_str(&db, "k"), Some("v3".to_owned()));
+            assert_eq!(db.inner.versions.lock().unwrap().get_last_sequence(), 1003);
+            db.close().unwrap();
         }
+
+        // a larger option raises the sequence of a DB with data
+        let db = open(5000).unwrap();
+        assert_eq!(get_str(&db, "k"), Some("v3".to_owned()));
+        db.put(WriteOptions::default(), Slice::from("k"), Slice::from("v4"))
+            .unwrap();
+        assert_eq!(get(&db, "k"), (b"v4".to_vec(), 5001));
     }
-}
 
-/// Build a Table file from the contents of `iter`.  The generated file
-/// will be named according to `meta.number`.  On success, the rest of
-/// meta will be filled with metadata about the generated table.
-/// If no data is present in iter, `meta.file_size` will be set to
-/// zero, and no Table file will be produced.
-pub(crate) fn build_table<'a>(
-    options: Arc<Options>,
-    db_name: &str,
-    table_cache: Arc<TableCache>,
-    mut iter: Box<dyn Iterator + 'a>,
-    meta: &mut FileMetaData,
-) -> Result<()> {
-    meta.file_size = 0;
-    iter.seek_to_first();
-    let file_name = generate_filename(db_name, FileType::Table, meta.number);
-    let mut status = Ok(());
-    if iter.valid() {
-        let file = options.env.create(file_name.as_str())?;
-        let mut builder = TableBuilder::new(file, options.clone());
-        let mut prev_key = Slice::default();
-        let smallest_key = iter.key();
-        while iter.valid() {
-            let key = iter.key();
-            let value = iter.value();
-            let s = builder.add(key.as_slice(), value.as_slice());
-            if s.is_err() {
-                status = s;
-                break;
-            }
-            prev_key = key;
+    #[test]
+    fn test_warm_cache() {
+        let name = "test_warm_cache";
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let open = |capacity: usize| {
+            let cache: Arc<dyn Cache<Arc<Block>>> =
+                Arc::new(SharedLRUCache::with_config(capacity, 0, None));
+            let options = Options {
+                env: env.clone(),
+                block_size: 1024,
+                block_cache: Some(cache.clone()),
+                ..Default::default()
+            };
+            (WickDB::open_db(options, name.to_owned()).unwrap(), cache)
+        };
+        let value = "v".repeat(100);
+        let (mut db, _) = open(1 << 20);
+        for i in 0..1000 {
+            db.put(
+                WriteOptions::default(),
+                Slice::from(format!("k{:04}", i).as_str()),
+                Slice::from(value.as_str()),
+            )
+            .unwrap();
         }
-        if status.is_ok() {
-            meta.smallest = Rc::new(InternalKey::decoded_from(smallest_key.as_slice()));
-            meta.largest = Rc::new(InternalKey::decoded_from(prev_key.as_slice()));
-            status = builder.finish(true).and_then(|_| {
-                meta.file_size = builder.file_size();
-                // make sure that the new file is in the cache
-                let mut it = table_cache.new_iter(
-                    Rc::new(ReadOptions::default()),
-                    meta.number,
-                    meta.file_size,
-                );
-                it.status()
-            })
+        db.flush(true).unwrap();
+        db.close().unwrap();
+
+        let (mut db, cache) = open(1 << 20);
+        let loaded = db
+            .warm_cache(ReadOptions::default(), b"k0200", b"k0400", usize::MAX, None)
+            .unwrap();
+        assert!(loaded > 10);
+        // all the reads in the range hit the cache
+        cache.reset_stats();
+        for i in 200..400 {
+            assert_eq!(get_str(&db, &format!("k{:04}", i)), Some(value.clone()));
         }
-    }
+        assert_eq!(cache.stats().misses, 0);
+        assert_eq!(
+            db.warm_cache(ReadOptions::default(), b"k0200", b"k0400", usize::MAX, None)
+                .unwrap(),
+            0
+        );
+        // an empty range or a zero budget loads nothing
+        assert_eq!(
+            db.warm_cache(ReadOptions::default(), b"k0400", b"k0400", usize::MAX, None)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            db.warm_cache(ReadOptions::default(), b"k0400", b"k0600", 0, None)
+                .unwrap(),
+            0
+        );
+        let partial = db
+            .warm_cache(ReadOptions::default(), b"k0400", b"k0600", 10 << 10, None)
+            .unwrap();
+        assert!(partial > 0 && partial < loaded);
+        db.close().unwrap();
 
-    let iter_status = iter.status();
-    if iter_status.is_err() {
-        status = iter_status;
-    };
-    if status.is_err() || meta.file_size == 0 {
-        options.env.remove(file_name.as_str())?;
-        status
-    } else {
-        Ok(())
+        // the warming stops before evicting the blocks it loaded
+        let (db, cache) = open(16 << 10);
+        cache.reset_stats();
+        let loaded = db
+            .warm_cache(ReadOptions::default(), b"k0000", b"k1000", usize::MAX, None)
+            .unwrap();
+        assert!(loaded > 0 && loaded < 100);
+        assert_eq!(cache.stats().evictions, 0);
+        assert!(cache.total_charge() <= 16 << 10);
+
+        // the warming is able to be canceled
+        let canceled = Arc::new(AtomicBool::new(true));
+        let e = db
+            .warm_cache(
+                ReadOptions::default(),
+                b"k0000",
+                b"k1000",
+                usize::MAX,
+                Some(canceled),
+            )
+            .unwrap_err();
+        assert_eq!(e.status(), Status::Aborted);
     }
 }
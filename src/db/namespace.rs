@@ -0,0 +1,345 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::batch::WriteBatch;
+use crate::db::{WickDB, DB};
+use crate::iterator::Iterator;
+use crate::options::{ReadOptions, WriteOptions};
+use crate::util::slice::Slice;
+use crate::util::status::Result;
+use crate::util::varint::VarintU64;
+
+/// A `Namespace` is a logically separate keyspace in a `WickDB`. Every key
+/// is transparently prefixed by the namespace prefix on write and the prefix
+/// is stripped on read. The iterators never go out of the namespace.
+///
+/// The prefix is the length of the namespace name in varint followed by the name,
+/// so no prefix is a prefix of another one and the namespaces never overlap, even
+/// if a name is a prefix of another name like "a" and "ab".
+///
+/// The keys in a namespace must be adjacent in the key order, so the
+/// comparator in `Options` should order the keys by bytes, like the default
+/// `BytewiseComparator`.
+#[derive(Clone)]
+pub struct Namespace {
+    db: WickDB,
+    prefix: Vec<u8>,
+}
+
+impl Namespace {
+    pub(super) fn new(db: WickDB, name: &[u8]) -> Self {
+        let mut prefix = Vec::with_capacity(name.len() + 1);
+        VarintU64::put_varint(&mut prefix, name.len() as u64);
+        prefix.extend_from_slice(name);
+        Self { db, prefix }
+    }
+
+    /// Returns the prefix of the keys in this namespace, which is the encoded name
+    #[inline]
+    pub fn prefix(&self) -> &[u8] {
+        self.prefix.as_slice()
+    }
+
+    /// Sets the value for the given key in the namespace
+    pub fn put(&self, write_opt: WriteOptions, key: Slice, value: Slice) -> Result<()> {
+        let k = self.key(key.as_slice());
+        self.db.put(write_opt, Slice::from(k.as_slice()), value)
+    }
+
+    /// Gets the value for the given key in the namespace
+    pub fn get(&self, read_opt: ReadOptions, key: Slice) -> Result<Option<Vec<u8>>> {
+        let k = self.key(key.as_slice());
+        self.db.get(read_opt, Slice::from(k.as_slice()))
+    }
+
+    /// Gets the values for the given keys in the namespace from a same snapshot
+    pub fn multi_get(&self, read_opt: ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| self.key(k)).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        self.db.multi_get(read_opt, keys.as_slice())
+    }
+
+    /// Merges the operand `value` into the existing value of the given key in the namespace
+    pub fn merge(&self, write_opt: WriteOptions, key: Slice, value: Slice) -> Result<()> {
+        let k = self.key(key.as_slice());
+        self.db.merge(write_opt, Slice::from(k.as_slice()), value)
+    }
+
+    /// Deletes the value for the given key in the namespace
+    pub fn delete(&self, write_opt: WriteOptions, key: Slice) -> Result<()> {
+        let k = self.key(key.as_slice());
+        self.db.delete(write_opt, Slice::from(k.as_slice()))
+    }
+
+    /// Erases all the keys in `[start, end)` in the namespace.
+    /// The keys in other namespaces are never affected.
+    pub fn delete_range(&self, write_opt: WriteOptions, start: &[u8], end: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(self.key(start).as_slice(), self.key(end).as_slice());
+        self.db.write(write_opt, batch)
+    }
+
+    /// Returns an iterator over the contents of the namespace. The bounds in
+    /// `read_opt` are keys in the namespace as well.
+    pub fn iter(&self, mut read_opt: ReadOptions) -> Box<dyn Iterator> {
+        read_opt.iterate_lower_bound = Some(match &read_opt.iterate_lower_bound {
+            Some(lower) => self.key(lower),
+            None => self.prefix.clone(),
+        });
+        read_opt.iterate_upper_bound = match &read_opt.iterate_upper_bound {
+            Some(upper) => Some(self.key(upper)),
+            None => prefix_upper_bound(self.prefix.as_slice()),
+        };
+        Box::new(NamespaceIterator {
+            inner: self.db.iter(read_opt),
+            prefix: self.prefix.clone(),
+        })
+    }
+
+    // Prepends the namespace prefix to `key`
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut k = Vec::with_capacity(self.prefix.len() + key.len());
+        k.extend_from_slice(self.prefix.as_slice());
+        k.extend_from_slice(key);
+        k
+    }
+}
+
+// Returns the smallest key which is greater than all the keys with the given prefix.
+// Returns `None` if there is no such key.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = Vec::from(prefix);
+    while let Some(last) = bound.pop() {
+        if last != 0xff {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+    None
+}
+
+// An iterator bounded in a namespace which strips the prefix of the keys
+struct NamespaceIterator {
+    inner: Box<dyn Iterator>,
+    prefix: Vec<u8>,
+}
+
+impl Iterator for NamespaceIterator {
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.inner.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) {
+        self.inner.seek_to_last()
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        let mut t = self.prefix.clone();
+        t.extend_from_slice(target.as_slice());
+        self.inner.seek(&Slice::from(t.as_slice()))
+    }
+
+    fn next(&mut self) {
+        self.inner.next()
+    }
+
+    fn prev(&mut self) {
+        self.inner.prev()
+    }
+
+    fn key(&self) -> Slice {
+        let key = self.inner.key();
+        Slice::from(&key.as_slice()[self.prefix.len()..])
+    }
+
+    fn value(&self) -> Slice {
+        self.inner.value()
+    }
+
+    fn status(&mut self) -> Result<()> {
+        self.inner.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::storage::mem::MemStorage;
+    use std::sync::Arc;
+
+    fn collect(iter: &mut dyn Iterator) -> Vec<(String, String)> {
+        let mut res = vec![];
+        while iter.valid() {
+            res.push((
+                iter.key().as_str().to_owned(),
+                iter.value().as_str().to_owned(),
+            ));
+            iter.next();
+        }
+        res
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_upper_bound(b"a\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+    }
+
+    #[test]
+    fn test_namespace() {
+        let options = Options {
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(options, "test_namespace".to_owned()).unwrap();
+        let ns1 = db.namespace(b"a");
+        let ns2 = db.namespace(b"b");
+        for k in ["1", "2", "3"].iter() {
+            let v1 = format!("ns1_{}", k);
+            let v2 = format!("ns2_{}", k);
+            ns1.put(
+                WriteOptions::default(),
+                Slice::from(*k),
+                Slice::from(v1.as_str()),
+            )
+            .unwrap();
+            ns2.put(
+                WriteOptions::default(),
+                Slice::from(*k),
+                Slice::from(v2.as_str()),
+            )
+            .unwrap();
+        }
+        db.put(WriteOptions::default(), Slice::from("c"), Slice::from("c"))
+            .unwrap();
+        assert_eq!(
+            ns1.get(ReadOptions::default(), Slice::from("1")).unwrap(),
+            Some(b"ns1_1".to_vec())
+        );
+        assert_eq!(ns2.prefix(), b"\x01b");
+        assert_eq!(
+            db.get(ReadOptions::default(), Slice::from("\x01b1"))
+                .unwrap(),
+            Some(b"ns2_1".to_vec())
+        );
+        assert_eq!(
+            ns1.get(ReadOptions::default(), Slice::from("c")).unwrap(),
+            None
+        );
+        let res = ns2.multi_get(ReadOptions::default(), &[b"3", b"4"]);
+        assert_eq!(res[0].as_ref().unwrap(), &Some(b"ns2_3".to_vec()));
+        assert_eq!(res[1].as_ref().unwrap(), &None);
+
+        // iterators are bounded in the namespace
+        let mut iter = ns1.iter(ReadOptions::default());
+        iter.seek_to_first();
+        assert_eq!(
+            collect(iter.as_mut()),
+            vec![
+                ("1".to_owned(), "ns1_1".to_owned()),
+                ("2".to_owned(), "ns1_2".to_owned()),
+                ("3".to_owned(), "ns1_3".to_owned()),
+            ]
+        );
+        iter.seek_to_last();
+        assert_eq!(iter.key().as_str(), "3");
+        iter.seek(&Slice::from("2"));
+        assert_eq!(iter.key().as_str(), "2");
+        iter.seek(&Slice::from("4"));
+        assert!(!iter.valid());
+        let mut iter = ns2.iter(ReadOptions {
+            iterate_upper_bound: Some(b"3".to_vec()),
+            ..Default::default()
+        });
+        iter.seek_to_last();
+        assert_eq!(iter.key().as_str(), "2");
+
+        // range deletions never cross the namespace
+        ns1.delete_range(WriteOptions::default(), b"", b"\xff")
+            .unwrap();
+        for k in ["1", "2", "3"].iter() {
+            assert_eq!(
+                ns1.get(ReadOptions::default(), Slice::from(*k)).unwrap(),
+                None
+            );
+            assert!(ns2
+                .get(ReadOptions::default(), Slice::from(*k))
+                .unwrap()
+                .is_some());
+        }
+        assert!(db
+            .get(ReadOptions::default(), Slice::from("c"))
+            .unwrap()
+            .is_some());
+        ns2.delete(WriteOptions::default(), Slice::from("1"))
+            .unwrap();
+        assert_eq!(
+            ns2.get(ReadOptions::default(), Slice::from("1")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_namespace_name_is_prefix_of_another() {
+        let options = Options {
+            env: Arc::new(MemStorage::default()),
+            ..Default::default()
+        };
+        let db = WickDB::open_db(
+            options,
+            "test_namespace_name_is_prefix_of_another".to_owned(),
+        )
+        .unwrap();
+        let a = db.namespace(b"a");
+        let ab = db.namespace(b"ab");
+        ab.put(
+            WriteOptions::default(),
+            Slice::from("1"),
+            Slice::from("ab_1"),
+        )
+        .unwrap();
+        a.put(
+            WriteOptions::default(),
+            Slice::from("b1"),
+            Slice::from("a_b1"),
+        )
+        .unwrap();
+        assert_eq!(
+            ab.get(ReadOptions::default(), Slice::from("1")).unwrap(),
+            Some(b"ab_1".to_vec())
+        );
+        let mut iter = a.iter(ReadOptions::default());
+        iter.seek_to_first();
+        assert_eq!(
+            collect(iter.as_mut()),
+            vec![("b1".to_owned(), "a_b1".to_owned())]
+        );
+        a.delete_range(WriteOptions::default(), b"", b"\xff")
+            .unwrap();
+        assert_eq!(
+            a.get(ReadOptions::default(), Slice::from("b1")).unwrap(),
+            None
+        );
+        assert_eq!(
+            ab.get(ReadOptions::default(), Slice::from("1")).unwrap(),
+            Some(b"ab_1".to_vec())
+        );
+    }
+}
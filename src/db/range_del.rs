@@ -0,0 +1,251 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::db::format::{InternalKey, ValueType, MAX_KEY_SEQUENCE};
+use crate::util::coding::decode_fixed_64;
+use crate::util::comparator::Comparator;
+use crate::util::slice::Slice;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// A `RangeTombstone` deletes every user key in `[start, end)` whose sequence
+/// number is smaller than `seq`.
+///
+/// A range tombstone is stored as an `ValueType::RangeDeletion` entry whose user key
+/// is `start` and whose value is `end`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], seq: u64) -> Self {
+        Self {
+            start: Vec::from(start),
+            end: Vec::from(end),
+            seq,
+        }
+    }
+
+    /// Returns true if the tombstone is visible at `sequence` and `key` lies in `[start, end)`
+    #[inline]
+    pub fn covers(&self, ucmp: &dyn Comparator, key: &[u8], sequence: u64) -> bool {
+        self.seq <= sequence
+            && ucmp.compare(self.start.as_slice(), key) != Ordering::Greater
+            && ucmp.compare(key, self.end.as_slice()) == Ordering::Less
+    }
+}
+
+/// Returns the end of the range tombstone if the entry of internal key `ikey` and
+/// `value` is a range tombstone
+#[inline]
+pub fn tombstone_end<'a>(ikey: &[u8], value: &'a [u8]) -> Option<&'a [u8]> {
+    if ikey.len() >= 8
+        && decode_fixed_64(&ikey[ikey.len() - 8..]) & 0xff == ValueType::RangeDeletion as u64
+    {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Returns the largest key of a table holding a range tombstone which ends at `end`.
+/// Since `end` is exclusive, the key sorts before all the entries of `end`.
+#[inline]
+pub fn tombstone_largest_key(end: &[u8]) -> InternalKey {
+    InternalKey::new(
+        &Slice::from(end),
+        MAX_KEY_SEQUENCE,
+        ValueType::RangeDeletion,
+    )
+}
+
+/// Returns the largest sequence number of the tombstones which are visible at
+/// `sequence` and cover `key`. Returns `None` if there is no such tombstone.
+pub fn max_covering_tombstone_seq(
+    tombstones: &[RangeTombstone],
+    ucmp: &dyn Comparator,
+    key: &[u8],
+    sequence: u64,
+) -> Option<u64> {
+    tombstones
+        .iter()
+        .filter(|t| t.covers(ucmp, key, sequence))
+        .map(|t| t.seq)
+        .max()
+}
+
+/// A `FragmentedRangeTombstones` keeps the range tombstones as the sorted and
+/// non-overlapping fragments, each of which carries the largest sequence number
+/// of the tombstones covering it. So the tombstone covering a key is found by a
+/// binary search no matter how many tombstones overlap.
+pub struct FragmentedRangeTombstones {
+    ucmp: Arc<dyn Comparator>,
+    fragments: Vec<RangeTombstone>,
+}
+
+impl FragmentedRangeTombstones {
+    pub fn new(ucmp: Arc<dyn Comparator>) -> Self {
+        Self {
+            ucmp,
+            fragments: vec![],
+        }
+    }
+
+    /// Returns true if there is no tombstone
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Adds a tombstone. The overlapped parts of the existing fragments are split
+    /// out and take the larger sequence number.
+    pub fn add(&mut self, tombstone: RangeTombstone) {
+        let ucmp = self.ucmp.clone();
+        let less = |a: &[u8], b: &[u8]| ucmp.compare(a, b) == Ordering::Less;
+        let (start, end, seq) = (tombstone.start, tombstone.end, tombstone.seq);
+        if !less(start.as_slice(), end.as_slice()) {
+            return;
+        }
+        let mut fragments = Vec::with_capacity(self.fragments.len() + 2);
+        let mut push = |start: &[u8], end: &[u8], seq: u64| {
+            if less(start, end) {
+                fragments.push(RangeTombstone::new(start, end, seq));
+            }
+        };
+        // the start of the part of the new tombstone not pushed yet
+        let mut cursor = start.clone();
+        for f in self.fragments.drain(..) {
+            if !less(f.start.as_slice(), end.as_slice()) {
+                push(cursor.as_slice(), end.as_slice(), seq);
+                cursor = end.clone();
+                push(f.start.as_slice(), f.end.as_slice(), f.seq);
+            } else if !less(start.as_slice(), f.end.as_slice()) {
+                push(f.start.as_slice(), f.end.as_slice(), f.seq);
+            } else {
+                // `f` overlaps the new tombstone in `[overlap_start, overlap_end)`
+                let overlap_start = if less(f.start.as_slice(), start.as_slice()) {
+                    push(f.start.as_slice(), start.as_slice(), f.seq);
+                    start.as_slice()
+                } else {
+                    f.start.as_slice()
+                };
+                push(cursor.as_slice(), overlap_start, seq);
+                let overlap_end = if less(end.as_slice(), f.end.as_slice()) {
+                    end.as_slice()
+                } else {
+                    f.end.as_slice()
+                };
+                push(overlap_start, overlap_end, seq.max(f.seq));
+                cursor = Vec::from(overlap_end);
+                push(end.as_slice(), f.end.as_slice(), f.seq);
+            }
+        }
+        push(cursor.as_slice(), end.as_slice(), seq);
+        self.fragments = fragments;
+    }
+
+    /// Returns the fragment covering `key`, whose sequence number is the largest
+    /// one of the tombstones covering `key`
+    pub fn covering(&self, key: &[u8]) -> Option<&RangeTombstone> {
+        let i = self
+            .fragments
+            .partition_point(|f| self.ucmp.compare(f.end.as_slice(), key) != Ordering::Greater);
+        self.fragments
+            .get(i)
+            .filter(|f| self.ucmp.compare(f.start.as_slice(), key) != Ordering::Greater)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::BytewiseComparator;
+
+    #[test]
+    fn test_fragmented_range_tombstones() {
+        let mut tombstones = FragmentedRangeTombstones::new(Arc::new(BytewiseComparator::new()));
+        assert!(tombstones.is_empty());
+        tombstones.add(RangeTombstone::new(b"c", b"f", 10));
+        tombstones.add(RangeTombstone::new(b"a", b"d", 5));
+        tombstones.add(RangeTombstone::new(b"e", b"h", 20));
+        tombstones.add(RangeTombstone::new(b"j", b"k", 1));
+        // empty
+        tombstones.add(RangeTombstone::new(b"x", b"x", 100));
+        let fragments: Vec<(&[u8], &[u8], u64)> = tombstones
+            .fragments
+            .iter()
+            .map(|f| (f.start.as_slice(), f.end.as_slice(), f.seq))
+            .collect();
+        assert_eq!(
+            fragments,
+            vec![
+                (&b"a"[..], &b"c"[..], 5),
+                (b"c", b"d", 10),
+                (b"d", b"e", 10),
+                (b"e", b"f", 20),
+                (b"f", b"h", 20),
+                (b"j", b"k", 1),
+            ]
+        );
+        let tests: Vec<(&[u8], Option<u64>)> = vec![
+            (b"", None),
+            (b"a", Some(5)),
+            (b"c", Some(10)),
+            (b"e", Some(20)),
+            (b"g", Some(20)),
+            (b"h", None),
+            (b"i", None),
+            (b"j", Some(1)),
+            (b"k", None),
+            (b"x", None),
+        ];
+        for (key, expect) in tests {
+            assert_eq!(
+                tombstones.covering(key).map(|t| t.seq),
+                expect,
+                "key: {:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_covering_tombstone_seq() {
+        let ucmp = BytewiseComparator::new();
+        let tombstones = vec![
+            RangeTombstone::new(b"b", b"d", 5),
+            RangeTombstone::new(b"c", b"f", 10),
+        ];
+        let tests: Vec<(&[u8], u64, Option<u64>)> = vec![
+            (b"a", 100, None),
+            (b"b", 100, Some(5)),
+            (b"c", 100, Some(10)),
+            (b"c", 9, Some(5)),
+            (b"c", 4, None),
+            (b"d", 100, Some(10)),
+            (b"f", 100, None),
+        ];
+        for (key, sequence, expect) in tests {
+            assert_eq!(
+                max_covering_tombstone_seq(&tombstones, &ucmp, key, sequence),
+                expect,
+                "key: {:?}, sequence: {}",
+                key,
+                sequence
+            );
+        }
+    }
+}
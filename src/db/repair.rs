@@ -0,0 +1,545 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilds the manifest of a DB from the tables and the logs in its directory.
+//!
+//! The repair is done in the following steps:
+//!
+//! 1. Every log is converted into level 0 tables. The corrupted records are dropped.
+//! 2. Every table is scanned to find its key range and its largest sequence number.
+//!    A table that can't be opened is moved into the `lost` directory. A table
+//!    with unreadable blocks is rewritten with the readable entries, and the
+//!    original one is moved into the `lost` directory.
+//! 3. The tables overlapping with no other table are placed in the bottommost level,
+//!    and the others in level 0 ordered by their sequence numbers.
+//! 4. A new manifest is written, and then the logs and the old manifests are moved
+//!    into the `lost` directory.
+//!
+//! No file is removed by the repair except the temporary ones it creates itself.
+
+use crate::batch::{WriteBatch, HEADER_SIZE};
+use crate::db::build_table;
+use crate::db::event_listener::TableFileInfo;
+use crate::db::filename::{generate_filename, parse_filename, update_current, FileType};
+use crate::db::format::{parse_internal_key, InternalKeyComparator, ValueType};
+use crate::iterator::Iterator;
+use crate::mem::{MemTable, MemoryTable};
+use crate::options::{Options, ReadOptions, WALRecoveryMode};
+use crate::record::reader::Reader;
+use crate::record::writer::Writer;
+use crate::record::BLOCK_SIZE;
+use crate::sstable::table::{new_table_iterator, Table, TableBuilder};
+use crate::table_cache::TableCache;
+use crate::util::comparator::Comparator;
+use crate::util::reporter::LogReporter;
+use crate::util::slice::Slice;
+use crate::util::status::Result;
+use crate::version::version_edit::{unix_time_secs, FileMetaData, VersionEdit};
+use std::cmp::Ordering;
+use std::path::{Path, MAIN_SEPARATOR};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The name of the directory in a DB where the repair moves the files it doesn't use
+pub const LOST_DIR: &str = "lost";
+
+/// What `WickDB::repair` salvaged from a DB
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// The tables in the new manifest
+    pub tables: Vec<TableFileInfo>,
+    /// The number of entries in all the `tables`
+    pub entries: u64,
+    /// The numbers of the logs converted into tables
+    pub converted_logs: Vec<u64>,
+    /// The original paths of the logs and the tables which are partially or
+    /// completely unreadable
+    pub corrupted_files: Vec<String>,
+    /// The paths of all the files moved into the `lost` directory
+    pub archived_files: Vec<String>,
+    /// The largest sequence number found in the tables
+    pub last_sequence: u64,
+}
+
+// A table found by the repair
+struct TableInfo {
+    meta: FileMetaData,
+    entries: u64,
+    max_sequence: u64,
+    // The largest user key covered by the table, including the ends of its range tombstones
+    largest_covered: Vec<u8>,
+}
+
+pub(crate) struct Repairer {
+    db_name: String,
+    options: Arc<Options>,
+    icmp: Arc<InternalKeyComparator>,
+    table_cache: Arc<TableCache>,
+    next_file_number: u64,
+    report: RepairReport,
+}
+
+impl Repairer {
+    pub fn new(options: Arc<Options>, db_name: String) -> Self {
+        let icmp = Arc::new(InternalKeyComparator::new(options.comparator.clone()));
+        let table_cache = Arc::new(TableCache::new(
+            db_name.clone(),
+            options.clone(),
+            options.table_cache_size(),
+        ));
+        Self {
+            db_name,
+            options,
+            icmp,
+            table_cache,
+            next_file_number: 1,
+            report: RepairReport::default(),
+        }
+    }
+
+    pub fn run(mut self) -> Result<RepairReport> {
+        let env = self.options.env.clone();
+        let lock_file =
+            env.create(generate_filename(self.db_name.as_str(), FileType::Lock, 0).as_str())?;
+        lock_file.lock()?;
+        let result = self.repair();
+        lock_file.unlock()?;
+        result.map(|_| self.report)
+    }
+
+    fn repair(&mut self) -> Result<()> {
+        let (mut tables, logs, manifests) = self.find_files()?;
+        for log_number in logs.iter() {
+            tables.extend(self.convert_log_to_tables(*log_number)?);
+        }
+        let mut found = vec![];
+        for number in tables {
+            if let Some(t) = self.scan_table(number)? {
+                found.push(t);
+            }
+        }
+        let edit = self.place_tables(found)?;
+        self.write_manifest(edit)?;
+        for log_number in logs {
+            self.archive(&generate_filename(
+                self.db_name.as_str(),
+                FileType::Log,
+                log_number,
+            ))?;
+        }
+        for manifest in manifests {
+            self.archive(&manifest)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn new_file_number(&mut self) -> u64 {
+        let n = self.next_file_number;
+        self.next_file_number += 1;
+        n
+    }
+
+    // Returns the numbers of the tables, the numbers of the logs in increasing order
+    // and the paths of the manifests. The `.ldb` tables are renamed to `.sst`.
+    fn find_files(&mut self) -> Result<(Vec<u64>, Vec<u64>, Vec<String>)> {
+        let env = self.options.env.clone();
+        let mut tables = vec![];
+        let mut logs = vec![];
+        let mut manifests = vec![];
+        let mut max_number = 0;
+        for path in env.list(self.db_name.as_str())? {
+            let path_str = path.to_string_lossy().into_owned();
+            if let Some(number) = parse_ldb_filename(&path) {
+                let sst = generate_filename(self.db_name.as_str(), FileType::Table, number);
+                if env.exists(sst.as_str()) {
+                    // keep both of them and use the `.sst` one
+                    info!("[repair] {} is ignored since {} exists", path_str, sst);
+                } else {
+                    env.rename(path_str.as_str(), sst.as_str())?;
+                    tables.push(number);
+                }
+                max_number = max_number.max(number);
+                continue;
+            }
+            if let Some((file_type, number)) = parse_filename(&path) {
+                match file_type {
+                    FileType::Table => tables.push(number),
+                    FileType::Log => logs.push(number),
+                    FileType::Manifest => manifests.push(path_str),
+                    _ => {}
+                }
+                max_number = max_number.max(number);
+            }
+        }
+        tables.sort();
+        logs.sort();
+        self.next_file_number = max_number + 1;
+        Ok((tables, logs, manifests))
+    }
+
+    // Replays the log into the memtables and writes them into new tables.
+    // Returns the numbers of the new tables.
+    fn convert_log_to_tables(&mut self, log_number: u64) -> Result<Vec<u64>> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Log, log_number);
+        let log_file = match self.options.env.open(file_name.as_str()) {
+            Ok(f) => f,
+            Err(e) => {
+                info!("[repair] failed to open log {}: {:?}", file_name, e);
+                self.report.corrupted_files.push(file_name);
+                return Ok(vec![]);
+            }
+        };
+        let reporter = LogReporter::new();
+        let mut reader = Reader::new(
+            log_file,
+            Some(Box::new(reporter.clone())),
+            true,
+            0,
+            WALRecoveryMode::TolerateCorruptedTailRecords,
+            self.options.log_block_size,
+            log_number,
+        );
+        let mut tables = vec![];
+        let mut mem = self.new_memtable();
+        let mut record_buf = vec![];
+        let mut batch = WriteBatch::new();
+        let mut dropped = false;
+        while reader.read_record(&mut record_buf) {
+            if record_buf.len() < HEADER_SIZE {
+                dropped = true;
+                continue;
+            }
+            batch.set_contents(&mut record_buf);
+            if let Err(e) = batch.insert_into(&mem) {
+                info!("[repair] log #{} ignores a batch: {:?}", log_number, e);
+                dropped = true;
+            }
+            if mem.approximate_memory_usage() > self.options.write_buffer_size {
+                tables.extend(self.write_memtable(&mem)?);
+                mem = self.new_memtable();
+            }
+        }
+        tables.extend(self.write_memtable(&mem)?);
+        if dropped || reporter.result().is_err() {
+            self.report.corrupted_files.push(file_name);
+        }
+        self.report.converted_logs.push(log_number);
+        info!(
+            "[repair] log #{} is converted into {:?}",
+            log_number, tables
+        );
+        Ok(tables)
+    }
+
+    fn new_memtable(&self) -> MemTable {
+        MemTable::with_factory(self.icmp.clone(), self.options.memtable_factory.as_ref())
+    }
+
+    fn write_memtable(&mut self, mem: &MemTable) -> Result<Option<u64>> {
+        let mut meta = FileMetaData {
+            number: self.new_file_number(),
+            ..Default::default()
+        };
+        build_table(
+            self.options.clone(),
+            self.db_name.as_str(),
+            self.table_cache.clone(),
+            mem.iter(),
+            &mut meta,
+            self.options.compression_for_level(0),
+        )?;
+        Ok(if meta.file_size > 0 {
+            Some(meta.number)
+        } else {
+            None
+        })
+    }
+
+    // Scans all the entries in the table. Returns `None` if nothing in the table
+    // is readable.
+    fn scan_table(&mut self, number: u64) -> Result<Option<TableInfo>> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Table, number);
+        let info = self.open_table(number).map(|table| {
+            let mut iter = new_table_iterator(table, Rc::new(scan_options()));
+            let mut info = scan_entries(iter.as_mut(), self.options.comparator.as_ref());
+            if iter.status().is_err() {
+                info.1 = true;
+            }
+            info
+        });
+        match info {
+            Ok((Some(mut t), false)) => {
+                t.meta.number = number;
+                t.meta.file_size = self.options.env.open(file_name.as_str())?.len()?;
+                Ok(Some(t))
+            }
+            Ok((_, true)) => {
+                self.report.corrupted_files.push(file_name.clone());
+                self.salvage_table(number)
+            }
+            Ok((None, false)) => {
+                // a table without entries is never produced by the DB
+                self.report.corrupted_files.push(file_name.clone());
+                self.archive(&file_name)?;
+                Ok(None)
+            }
+            Err(e) => {
+                info!("[repair] failed to open table {}: {:?}", file_name, e);
+                self.report.corrupted_files.push(file_name.clone());
+                self.archive(&file_name)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn open_table(&self, number: u64) -> Result<Arc<Table>> {
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Table, number);
+        let file = self.options.env.open(file_name.as_str())?;
+        let size = file.len()?;
+        Ok(Arc::new(Table::open(file, size, self.options.clone())?))
+    }
+
+    // Copies the readable entries of the table into a new file, moves the
+    // original table into the `lost` directory and renames the new file as the
+    // original one, so that the table keeps its number.
+    fn salvage_table(&mut self, number: u64) -> Result<Option<TableInfo>> {
+        let env = self.options.env.clone();
+        let file_name = generate_filename(self.db_name.as_str(), FileType::Table, number);
+        let tmp_number = self.new_file_number();
+        let tmp_name = generate_filename(self.db_name.as_str(), FileType::Table, tmp_number);
+        let mut iter = new_table_iterator(self.open_table(number)?, Rc::new(scan_options()));
+        let mut builder = TableBuilder::new(
+            env.create(tmp_name.as_str())?,
+            self.options.compression_for_level(0),
+            self.options.clone(),
+        );
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key();
+            if parse_internal_key(key.as_slice()).is_ok() {
+                builder.add(key.as_slice(), iter.value().as_slice())?;
+            }
+            iter.next();
+        }
+        if builder.num_entries() == 0 {
+            builder.close();
+            env.remove(tmp_name.as_str())?;
+            self.archive(&file_name)?;
+            return Ok(None);
+        }
+        builder.finish(true)?;
+        let file_size = builder.file_size();
+        self.archive(&file_name)?;
+        env.rename(tmp_name.as_str(), file_name.as_str())?;
+        let table = self.open_table(number)?;
+        let mut iter = new_table_iterator(table, Rc::new(scan_options()));
+        let (t, _) = scan_entries(iter.as_mut(), self.options.comparator.as_ref());
+        Ok(t.map(|mut t| {
+            info!(
+                "[repair] table #{} is salvaged with {} entries",
+                number, t.entries
+            );
+            t.meta.number = number;
+            t.meta.file_size = file_size;
+            t
+        }))
+    }
+
+    // Places the tables which overlap with no other table in the bottommost level,
+    // and the others in level 0. The level 0 tables are renumbered in the order of
+    // their largest sequence numbers since the newer ones must have larger numbers.
+    fn place_tables(&mut self, mut tables: Vec<TableInfo>) -> Result<VersionEdit> {
+        let ucmp = self.options.comparator.clone();
+        tables.sort_by(|a, b| ucmp.compare(a.meta.smallest.user_key(), b.meta.smallest.user_key()));
+        let mut overlapped = vec![false; tables.len()];
+        let mut covered_end: Option<&[u8]> = None;
+        let mut covered_by = 0;
+        for (i, t) in tables.iter().enumerate() {
+            if let Some(end) = covered_end {
+                if ucmp.compare(t.meta.smallest.user_key(), end) != Ordering::Greater {
+                    overlapped[i] = true;
+                    overlapped[covered_by] = true;
+                }
+            }
+            let end = t.largest_covered.as_slice();
+            let extends = match covered_end {
+                Some(e) => ucmp.compare(end, e) == Ordering::Greater,
+                None => true,
+            };
+            if extends {
+                covered_end = Some(end);
+                covered_by = i;
+            }
+        }
+
+        let bottommost = self.options.max_levels as usize - 1;
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        let mut level0 = vec![];
+        for (t, overlapped) in tables.into_iter().zip(overlapped) {
+            if overlapped {
+                level0.push(t);
+            } else {
+                self.add_table(&mut edit, bottommost, t);
+            }
+        }
+        level0.sort_by_key(|t| (t.max_sequence, t.meta.number));
+        let env = self.options.env.clone();
+        for mut t in level0 {
+            let number = self.new_file_number();
+            env.rename(
+                generate_filename(self.db_name.as_str(), FileType::Table, t.meta.number).as_str(),
+                generate_filename(self.db_name.as_str(), FileType::Table, number).as_str(),
+            )?;
+            t.meta.number = number;
+            self.add_table(&mut edit, 0, t);
+        }
+        Ok(edit)
+    }
+
+    fn add_table(&mut self, edit: &mut VersionEdit, level: usize, mut t: TableInfo) {
+        t.meta.creation_time = unix_time_secs();
+        edit.add_file(level, &t.meta);
+        self.report.entries += t.entries;
+        self.report.last_sequence = self.report.last_sequence.max(t.max_sequence);
+        self.report
+            .tables
+            .push(TableFileInfo::new(self.db_name.as_str(), level, &t.meta));
+    }
+
+    fn write_manifest(&mut self, mut edit: VersionEdit) -> Result<()> {
+        let env = self.options.env.clone();
+        let manifest_number = self.new_file_number();
+        edit.set_comparator_name(self.options.comparator.name().to_owned());
+        if self.options.log_block_size != BLOCK_SIZE {
+            edit.set_log_block_size(self.options.log_block_size as u64);
+        }
+        edit.set_log_number(0);
+        edit.set_next_file(self.next_file_number);
+        edit.set_last_sequence(self.report.last_sequence);
+        let manifest_name =
+            generate_filename(self.db_name.as_str(), FileType::Manifest, manifest_number);
+        let mut writer = Writer::new(env.create(manifest_name.as_str())?);
+        let mut record = vec![];
+        edit.encode_to(&mut record);
+        if let Err(e) = writer
+            .add_record(&Slice::from(&record))
+            .and_then(|_| writer.sync())
+        {
+            env.remove(manifest_name.as_str())?;
+            return Err(e);
+        }
+        update_current(env, self.db_name.as_str(), manifest_number)
+    }
+
+    // Moves the file into the `lost` directory without overwriting anything there
+    fn archive(&mut self, path: &str) -> Result<()> {
+        let env = self.options.env.clone();
+        let lost_dir = format!("{}{}{}", self.db_name, MAIN_SEPARATOR, LOST_DIR);
+        env.mkdir_all(lost_dir.as_str())?;
+        let file_name = Path::new(path)
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let mut dest = format!("{}{}{}", lost_dir, MAIN_SEPARATOR, file_name);
+        let mut suffix = 1;
+        while env.exists(dest.as_str()) {
+            dest = format!("{}{}{}.{}", lost_dir, MAIN_SEPARATOR, file_name, suffix);
+            suffix += 1;
+        }
+        env.rename(path, dest.as_str())?;
+        info!("[repair] {} is moved to {}", path, dest);
+        self.report.archived_files.push(dest);
+        Ok(())
+    }
+}
+
+fn scan_options() -> ReadOptions {
+    ReadOptions {
+        verify_checksums: true,
+        fill_cache: false,
+        ..Default::default()
+    }
+}
+
+// Returns `{number}` if the file is `{number}.ldb`
+fn parse_ldb_filename(path: &Path) -> Option<u64> {
+    if path.extension()?.to_str()? != "ldb" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse::<u64>().ok()
+}
+
+// Scans the entries of a table. Returns the table found if there is any valid
+// entry, and whether any entry is invalid. The errors of the iterator are not
+// checked.
+fn scan_entries(iter: &mut dyn Iterator, ucmp: &dyn Comparator) -> (Option<TableInfo>, bool) {
+    let mut invalid = false;
+    let mut info: Option<TableInfo> = None;
+    iter.seek_to_first();
+    while iter.valid() {
+        let key = iter.key();
+        match parse_internal_key(key.as_slice()) {
+            Ok(ikey) => {
+                let is_tombstone = ikey.value_type() == ValueType::RangeDeletion;
+                let covered = if is_tombstone {
+                    iter.value().as_slice().to_vec()
+                } else {
+                    ikey.user_key().to_vec()
+                };
+                let seq = ikey.sequence();
+                let ikey = Arc::new(ikey);
+                match info.as_mut() {
+                    Some(t) => {
+                        t.meta.largest = ikey;
+                        t.meta.has_range_tombstones |= is_tombstone;
+                        t.entries += 1;
+                        t.max_sequence = t.max_sequence.max(seq);
+                        if ucmp.compare(covered.as_slice(), t.largest_covered.as_slice())
+                            == Ordering::Greater
+                        {
+                            t.largest_covered = covered;
+                        }
+                    }
+                    None => {
+                        info = Some(TableInfo {
+                            meta: FileMetaData {
+                                smallest: ikey.clone(),
+                                largest: ikey,
+                                has_range_tombstones: is_tombstone,
+                                ..Default::default()
+                            },
+                            entries: 1,
+                            max_sequence: seq,
+                            largest_covered: covered,
+                        })
+                    }
+                }
+            }
+            Err(_) => invalid = true,
+        }
+        iter.next();
+    }
+    (info, invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ldb_filename() {
+        assert_eq!(parse_ldb_filename(Path::new("db/000012.ldb")), Some(12));
+        assert_eq!(parse_ldb_filename(Path::new("db/000012.sst")), None);
+        assert_eq!(parse_ldb_filename(Path::new("db/abc.ldb")), None);
+    }
+}
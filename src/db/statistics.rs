@@ -0,0 +1,144 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A snapshot of the counters of the flushes, the compactions and the write stalls
+/// of a `WickDB` since it's opened. All the counters are monotonic.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// The number of memtables flushed into tables
+    pub flush_count: u64,
+    /// The total size of the tables built by the flushes
+    pub bytes_flushed: u64,
+    /// The number of compactions rewriting the input files
+    pub compaction_count: u64,
+    /// The number of compactions moving a file to the next level without rewriting it
+    pub trivial_move_count: u64,
+    /// The total bytes read by the compactions by their output levels
+    pub compaction_bytes_read: Vec<u64>,
+    /// The total bytes written by the compactions by their output levels
+    pub compaction_bytes_written: Vec<u64>,
+    /// The number of writes delayed or stopped because of too many level 0 files
+    /// or immutable memtables
+    pub write_stall_count: u64,
+    /// The total time the writes spend on waiting in the stalls in microseconds
+    pub write_stall_micros: u64,
+}
+
+impl Statistics {
+    /// Returns the total bytes written by the compactions at all levels
+    pub fn total_bytes_compacted(&self) -> u64 {
+        self.compaction_bytes_written.iter().sum()
+    }
+}
+
+/// The counters of a DB updated by the background work and the writers
+pub(crate) struct StatisticsCounters {
+    flush_count: AtomicU64,
+    bytes_flushed: AtomicU64,
+    compaction_count: AtomicU64,
+    trivial_move_count: AtomicU64,
+    compaction_bytes_read: Vec<AtomicU64>,
+    compaction_bytes_written: Vec<AtomicU64>,
+    write_stall_count: AtomicU64,
+    write_stall_micros: AtomicU64,
+}
+
+impl StatisticsCounters {
+    pub fn new(max_levels: usize) -> Self {
+        Self {
+            flush_count: AtomicU64::new(0),
+            bytes_flushed: AtomicU64::new(0),
+            compaction_count: AtomicU64::new(0),
+            trivial_move_count: AtomicU64::new(0),
+            compaction_bytes_read: (0..max_levels).map(|_| AtomicU64::new(0)).collect(),
+            compaction_bytes_written: (0..max_levels).map(|_| AtomicU64::new(0)).collect(),
+            write_stall_count: AtomicU64::new(0),
+            write_stall_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_flush(&self, bytes: u64) {
+        self.flush_count.fetch_add(1, Ordering::Relaxed);
+        self.bytes_flushed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_compaction(&self, output_level: usize, bytes_read: u64, bytes_written: u64) {
+        self.compaction_count.fetch_add(1, Ordering::Relaxed);
+        self.compaction_bytes_read[output_level].fetch_add(bytes_read, Ordering::Relaxed);
+        self.compaction_bytes_written[output_level].fetch_add(bytes_written, Ordering::Relaxed);
+    }
+
+    pub fn record_trivial_move(&self) {
+        self.trivial_move_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a write waiting for `stall`. Nothing is recorded if `stall` is zero.
+    pub fn record_write_stall(&self, stall: Duration) {
+        if stall > Duration::default() {
+            self.write_stall_count.fetch_add(1, Ordering::Relaxed);
+            self.write_stall_micros
+                .fetch_add(stall.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> Statistics {
+        let load =
+            |counters: &[AtomicU64]| counters.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        Statistics {
+            flush_count: self.flush_count.load(Ordering::Relaxed),
+            bytes_flushed: self.bytes_flushed.load(Ordering::Relaxed),
+            compaction_count: self.compaction_count.load(Ordering::Relaxed),
+            trivial_move_count: self.trivial_move_count.load(Ordering::Relaxed),
+            compaction_bytes_read: load(self.compaction_bytes_read.as_slice()),
+            compaction_bytes_written: load(self.compaction_bytes_written.as_slice()),
+            write_stall_count: self.write_stall_count.load(Ordering::Relaxed),
+            write_stall_micros: self.write_stall_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics_counters() {
+        let counters = StatisticsCounters::new(3);
+        counters.record_flush(100);
+        counters.record_flush(50);
+        counters.record_compaction(1, 150, 120);
+        counters.record_compaction(2, 120, 100);
+        counters.record_compaction(1, 10, 10);
+        counters.record_trivial_move();
+        counters.record_write_stall(Duration::default());
+        counters.record_write_stall(Duration::from_millis(2));
+        let stats = counters.snapshot();
+        assert_eq!(
+            stats,
+            Statistics {
+                flush_count: 2,
+                bytes_flushed: 150,
+                compaction_count: 3,
+                trivial_move_count: 1,
+                compaction_bytes_read: vec![0, 160, 120],
+                compaction_bytes_written: vec![0, 130, 100],
+                write_stall_count: 1,
+                write_stall_micros: 2000,
+            }
+        );
+        assert_eq!(stats.total_bytes_compacted(), 230);
+    }
+}
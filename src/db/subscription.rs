@@ -0,0 +1,114 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::batch::WriteBatch;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A `Subscription` receives the committed `WriteBatch`es of a `WickDB` in the
+/// order of their sequence numbers. Every batch is delivered after it's written
+/// into the WAL and the memtable, with its first sequence number set, so the
+/// records are able to be read by `WriteBatch::iterate`.
+///
+/// The concurrent writes might be delivered as one batch. The batches are
+/// buffered in a bounded channel, and the ones committed when the buffer is full
+/// are dropped and counted by `dropped_count`. A gap in the sequence numbers also
+/// indicates the dropped batches.
+pub struct Subscription {
+    receiver: Receiver<WriteBatch>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Returns the receiver of the committed batches
+    #[inline]
+    pub fn receiver(&self) -> &Receiver<WriteBatch> {
+        &self.receiver
+    }
+
+    /// Returns the number of batches dropped because the buffer was full
+    #[inline]
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Acquire)
+    }
+}
+
+struct Subscriber {
+    sender: Sender<WriteBatch>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// All the subscribers of a DB
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    inner: Mutex<Vec<Subscriber>>,
+}
+
+impl Subscribers {
+    /// Creates a new `Subscription` buffering at most `capacity` batches
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.inner.lock().unwrap().push(Subscriber {
+            sender,
+            dropped: dropped.clone(),
+        });
+        Subscription { receiver, dropped }
+    }
+
+    /// Delivers `batch` to all the subscribers without blocking.
+    /// The subscriptions which are dropped are removed.
+    pub fn publish(&self, batch: &WriteBatch) {
+        let mut subscribers = self.inner.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        subscribers.retain(|s| match s.sender.try_send(batch.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                s.dropped.fetch_add(1, Ordering::Release);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let subscribers = Subscribers::default();
+        let s1 = subscribers.subscribe(1);
+        let s2 = subscribers.subscribe(2);
+        for i in 0..3 {
+            let mut batch = WriteBatch::new();
+            batch.put(b"k", b"v");
+            batch.set_sequence(i + 1);
+            subscribers.publish(&batch);
+        }
+        assert_eq!(s1.receiver().len(), 1);
+        assert_eq!(s1.dropped_count(), 2);
+        assert_eq!(s2.receiver().len(), 2);
+        assert_eq!(s2.dropped_count(), 1);
+        assert_eq!(s2.receiver().recv().unwrap().get_sequence(), 1);
+        assert_eq!(s2.receiver().recv().unwrap().get_sequence(), 2);
+        // the dropped subscription is removed
+        drop(s1);
+        subscribers.publish(&WriteBatch::new());
+        assert_eq!(subscribers.inner.lock().unwrap().len(), 1);
+    }
+}
@@ -19,14 +19,32 @@ use crate::filter::FilterPolicy;
 use crate::util::hash::hash;
 use crate::util::slice::Slice;
 
+/// A bloom filter which uses `bits_per_key` bits for every key. The hash count
+/// is stored in every filter so that the filters created with different
+/// `bits_per_key` are always readable.
+///
+/// A bloom filter created by `new_prefix` is built over the key prefixes given by
+/// `Options::prefix_extractor` only, and it has a different name from the whole key
+/// one so that a table built in either mode is always queried in the same mode.
 pub struct BloomFilter {
     // the hash count for a key
     k: usize,
     bits_per_key: usize,
+    whole_key: bool,
 }
 
 impl BloomFilter {
     pub fn new(bits_per_key: usize) -> Self {
+        Self::new_with_mode(bits_per_key, true)
+    }
+
+    /// Creates a bloom filter built over the key prefixes only. Keys out of the domain
+    /// of the prefix extractor are never filtered.
+    pub fn new_prefix(bits_per_key: usize) -> Self {
+        Self::new_with_mode(bits_per_key, false)
+    }
+
+    fn new_with_mode(bits_per_key: usize, whole_key: bool) -> Self {
         // 0.69 =~ ln(2) and we intentionally round down to reduce probing cost a little bit
         let mut k = bits_per_key as f32 * 0.69;
         if k > 30f32 {
@@ -37,8 +55,45 @@ impl BloomFilter {
         Self {
             k: k as usize,
             bits_per_key,
+            whole_key,
+        }
+    }
+
+    /// Returns the number of bits used for every key
+    #[inline]
+    pub fn bits_per_key(&self) -> usize {
+        self.bits_per_key
+    }
+
+    /// Returns the number of probes for every key
+    #[inline]
+    pub fn hash_count(&self) -> usize {
+        self.k
+    }
+
+    /// Estimates the theoretical false positive rate of a filter which
+    /// contains `num_entries` keys: `(1 - e^(-k * n / m)) ^ k`
+    pub fn false_positive_rate(&self, num_entries: usize) -> f64 {
+        if num_entries == 0 {
+            return 0.0;
         }
+        let bits = Self::filter_bits(num_entries, self.bits_per_key) as f64;
+        let k = self.k as f64;
+        (1.0 - (-k * num_entries as f64 / bits).exp()).powf(k)
     }
+
+    // Returns the number of bits in a filter for `n` keys
+    fn filter_bits(n: usize, bits_per_key: usize) -> usize {
+        let mut bits = n * bits_per_key;
+        // For small n, we can see a very high false positive rate.  Fix it
+        // by enforcing a minimum bloom filter length.
+        if bits < 64 {
+            bits = 64;
+        };
+        let bytes = (bits + 7) / 8;
+        bytes * 8 // round to multi 8
+    }
+
     fn bloom_hash(data: &[u8]) -> u32 {
         hash(data, 0xc6a4a793)
     }
@@ -46,7 +101,16 @@ impl BloomFilter {
 
 impl FilterPolicy for BloomFilter {
     fn name(&self) -> &str {
-        "leveldb.BuiltinBloomFilter"
+        if self.whole_key {
+            "leveldb.BuiltinBloomFilter"
+        } else {
+            "wickdb.BuiltinPrefixBloomFilter"
+        }
+    }
+
+    #[inline]
+    fn whole_key_filtering(&self) -> bool {
+        self.whole_key
     }
 
     fn may_contain(&self, filter: &[u8], key: &Slice) -> bool {
@@ -78,15 +142,8 @@ impl FilterPolicy for BloomFilter {
 
     fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8> {
         // Compute bloom filter size (in both bits and bytes)
-        let mut bits = keys.len() * self.bits_per_key;
-
-        // For small n, we can see a very high false positive rate.  Fix it
-        // by enforcing a minimum bloom filter length.
-        if bits < 64 {
-            bits = 64;
-        };
-        let bytes = (bits + 7) / 8;
-        bits = bytes * 8; // round to multi 8
+        let bits = Self::filter_bits(keys.len(), self.bits_per_key);
+        let bytes = bits / 8;
 
         let mut dst: Vec<u8> = vec![0; bytes + 1]; // the extra place of the length bits
         dst[bytes] = self.k as u8;
@@ -117,8 +174,12 @@ mod tests {
 
     impl Harness {
         pub fn new() -> Self {
+            Self::with_bits_per_key(10)
+        }
+
+        pub fn with_bits_per_key(bits_per_key: usize) -> Self {
             Self {
-                policy: Box::new(BloomFilter::new(10)),
+                policy: Box::new(BloomFilter::new(bits_per_key)),
                 filter: vec![],
                 keys: vec![],
             }
@@ -249,4 +310,56 @@ mod tests {
             "mediocre false positive rate is more than expected"
         );
     }
+
+    #[test]
+    fn test_bloom_filter_bits_per_key() {
+        let n = 10000;
+        let mut rates = vec![];
+        for bits_per_key in [10, 16].iter() {
+            let mut h = Harness::with_bits_per_key(*bits_per_key);
+            for i in 0..n {
+                h.add_num(i);
+            }
+            h.build();
+            assert!(h.filter_len() >= (n as usize) * bits_per_key / 8);
+            let mut rate = 0.0;
+            for i in 0..n {
+                if h.assert_num(i + 1000000000, true, true) {
+                    rate += 1.0;
+                }
+            }
+            rate /= n as f64;
+            let expected = BloomFilter::new(*bits_per_key).false_positive_rate(n as usize);
+            assert!(
+                rate <= expected * 2.0,
+                "false positive rate {} with {} bits per key, expected {}",
+                rate,
+                bits_per_key,
+                expected
+            );
+            rates.push(expected);
+        }
+        assert!(rates[1] < rates[0]);
+
+        // filters are readable with a different bits per key
+        let mut h = Harness::with_bits_per_key(16);
+        h.add_key(Vec::from("hello"));
+        h.build();
+        let policy = BloomFilter::new(10);
+        assert!(policy.may_contain(h.filter.as_slice(), &Slice::from("hello")));
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        let f = BloomFilter::new(10);
+        assert_eq!(f.bits_per_key(), 10);
+        assert_eq!(f.hash_count(), 6);
+        assert_eq!(f.false_positive_rate(0), 0.0);
+        // about 0.84% for 10 bits per key
+        let rate = f.false_positive_rate(1000);
+        assert!(rate > 0.008 && rate < 0.009, "{}", rate);
+        // a small filter is at least 64 bits
+        assert!(f.false_positive_rate(1) < rate);
+        assert!(BloomFilter::new(16).false_positive_rate(1000) < 0.001);
+    }
 }
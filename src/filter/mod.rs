@@ -15,9 +15,13 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use crate::filter::bloom::BloomFilter;
+use crate::filter::xor::XorFilter;
 use crate::util::slice::Slice;
+use std::sync::Arc;
 
 pub mod bloom;
+pub mod xor;
 
 /// `FilterPolicy` is an algorithm for probabilistically encoding a set of keys.
 /// The canonical implementation is a Bloom filter.
@@ -31,7 +35,7 @@ pub mod bloom;
 /// these filters, the `FilterPolicy` name at the time of writing must equal the
 /// name at the time of reading. If they do not match, the filters will be
 /// ignored, which will not affect correctness but may affect performance.
-pub trait FilterPolicy {
+pub trait FilterPolicy: Send + Sync {
     /// Return the name of this policy.  Note that if the filter encoding
     /// changes in an incompatible way, the name returned by this method
     /// must be changed.  Otherwise, old incompatible filters may be
@@ -46,4 +50,32 @@ pub trait FilterPolicy {
     /// Creates a filter based on given keys
     // TODO: use another type instead of &[Vec<u8>]
     fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8>;
+
+    /// Returns false if only the prefixes of the keys given by `Options::prefix_extractor`
+    /// are added into the filters, so that a lookup consults the filter by the prefix
+    /// of the key only. The name of such a policy must differ from the whole key one
+    /// since the mode of a table is known by the policy name stored in it.
+    fn whole_key_filtering(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the builtin `FilterPolicy` with the given name. The builtin filters
+/// don't need the parameters used at the time of writing to read a filter, so
+/// the tables built with any of them can be read regardless of the configured
+/// policy.
+pub fn builtin_policy(name: &str) -> Option<Arc<dyn FilterPolicy>> {
+    let bloom = BloomFilter::new(10);
+    if name == bloom.name() {
+        return Some(Arc::new(bloom));
+    }
+    let prefix_bloom = BloomFilter::new_prefix(10);
+    if name == prefix_bloom.name() {
+        return Some(Arc::new(prefix_bloom));
+    }
+    let xor = XorFilter::new();
+    if name == xor.name() {
+        return Some(Arc::new(xor));
+    }
+    None
 }
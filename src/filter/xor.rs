@@ -0,0 +1,264 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::filter::FilterPolicy;
+use crate::util::coding::{decode_fixed_32, decode_fixed_64, put_fixed_32, put_fixed_64};
+use crate::util::hash::hash;
+use crate::util::slice::Slice;
+
+// seed (8 bytes) + block length (4 bytes)
+const XOR_FILTER_TRAILER_SIZE: usize = 12;
+
+// The construction fails with a tiny probability for a seed.
+// Gives up after too many tries since that means there are duplicated hashes.
+const MAX_CONSTRUCTION_ATTEMPTS: usize = 64;
+
+/// A static xor filter with 8-bit fingerprints. It takes about 9.84 bits per
+/// key for a false positive rate of about 0.39%, which is smaller than a
+/// `BloomFilter` at the similar rate.
+///
+/// The filter is encoded as:
+///
+/// ```text
+/// +---------------------------+----------+--------------------+
+/// | fingerprints (3 * length) | seed (8) | block length (4)   |
+/// +---------------------------+----------+--------------------+
+/// ```
+#[derive(Default)]
+pub struct XorFilter {}
+
+impl XorFilter {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // A 64 bits hash of the key made of two 32 bits hashes
+    #[inline]
+    fn key_hash(key: &[u8]) -> u64 {
+        (u64::from(hash(key, 0xbc9f_1d34)) << 32) | u64::from(hash(key, 0x7a2b_3c4d))
+    }
+
+    // The finalizer of murmur3
+    #[inline]
+    fn mix(h: u64, seed: u64) -> u64 {
+        let mut h = h.wrapping_add(seed);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^= h >> 33;
+        h
+    }
+
+    #[inline]
+    fn fingerprint(h: u64) -> u8 {
+        (h ^ (h >> 32)) as u8
+    }
+
+    // Maps a 32 bits hash into [0, n)
+    #[inline]
+    fn reduce(h: u32, n: u32) -> u32 {
+        ((u64::from(h) * u64::from(n)) >> 32) as u32
+    }
+
+    // Returns the 3 slots of the hash in the 3 blocks
+    #[inline]
+    fn slots(h: u64, block_length: u32) -> [usize; 3] {
+        let bl = block_length as usize;
+        [
+            Self::reduce(h as u32, block_length) as usize,
+            Self::reduce(h.rotate_left(21) as u32, block_length) as usize + bl,
+            Self::reduce(h.rotate_left(42) as u32, block_length) as usize + 2 * bl,
+        ]
+    }
+
+    // splitmix64 used to generate the seeds
+    #[inline]
+    fn next_seed(seed: &mut u64) -> u64 {
+        *seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Tries to build the fingerprints by peeling the 3-hypergraph.
+    // Returns `None` if the graph can not be fully peeled for the `seed`.
+    fn try_build(hashes: &[u64], seed: u64, block_length: u32) -> Option<Vec<u8>> {
+        let capacity = block_length as usize * 3;
+        // the xor of the hashes and the count of the keys mapped into every slot
+        let mut masks = vec![0u64; capacity];
+        let mut counts = vec![0u32; capacity];
+        for key_hash in hashes {
+            let h = Self::mix(*key_hash, seed);
+            for &i in Self::slots(h, block_length).iter() {
+                masks[i] ^= h;
+                counts[i] += 1;
+            }
+        }
+        let mut queue: Vec<usize> = (0..capacity).filter(|i| counts[*i] == 1).collect();
+        let mut stack: Vec<(usize, u64)> = Vec::with_capacity(hashes.len());
+        while let Some(i) = queue.pop() {
+            if counts[i] == 0 {
+                continue;
+            }
+            let h = masks[i];
+            stack.push((i, h));
+            for &j in Self::slots(h, block_length).iter() {
+                masks[j] ^= h;
+                counts[j] -= 1;
+                if counts[j] == 1 {
+                    queue.push(j);
+                }
+            }
+        }
+        if stack.len() != hashes.len() {
+            return None;
+        }
+        let mut fingerprints = vec![0u8; capacity];
+        for (i, h) in stack.iter().rev() {
+            let [a, b, c] = Self::slots(*h, block_length);
+            // the slot `i` itself is still zero
+            fingerprints[*i] =
+                Self::fingerprint(*h) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+        }
+        Some(fingerprints)
+    }
+}
+
+impl FilterPolicy for XorFilter {
+    fn name(&self) -> &str {
+        "wickdb.XorFilter"
+    }
+
+    fn may_contain(&self, filter: &[u8], key: &Slice) -> bool {
+        if filter.len() < XOR_FILTER_TRAILER_SIZE {
+            return false;
+        }
+        let n = filter.len() - XOR_FILTER_TRAILER_SIZE;
+        let seed = decode_fixed_64(&filter[n..n + 8]);
+        let block_length = decode_fixed_32(&filter[n + 8..]);
+        if block_length == 0 {
+            // no key in the filter
+            return false;
+        }
+        if block_length as usize * 3 != n {
+            // Corrupted or a potentially new encoding. Consider it a match.
+            return true;
+        }
+        let h = Self::mix(Self::key_hash(key.as_slice()), seed);
+        let [a, b, c] = Self::slots(h, block_length);
+        Self::fingerprint(h) == filter[a] ^ filter[b] ^ filter[c]
+    }
+
+    fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8> {
+        // The peeling never completes with duplicated keys
+        let mut hashes: Vec<u64> = keys.iter().map(|k| Self::key_hash(k)).collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        let mut dst = vec![];
+        if hashes.is_empty() {
+            put_fixed_64(&mut dst, 0);
+            put_fixed_32(&mut dst, 0);
+            return dst;
+        }
+        let capacity = 32 + (1.23 * hashes.len() as f64).ceil() as usize;
+        let block_length = (capacity / 3) as u32;
+        let mut state = 0u64;
+        for _ in 0..MAX_CONSTRUCTION_ATTEMPTS {
+            let seed = Self::next_seed(&mut state);
+            if let Some(fingerprints) = Self::try_build(&hashes, seed, block_length) {
+                dst = fingerprints;
+                put_fixed_64(&mut dst, seed);
+                put_fixed_32(&mut dst, block_length);
+                return dst;
+            }
+        }
+        // Fails to build a filter. Returns a corrupted one which matches every key.
+        put_fixed_64(&mut dst, 0);
+        put_fixed_32(&mut dst, u32::MAX);
+        dst
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::encode_fixed_32;
+
+    fn num_key(n: u32) -> Vec<u8> {
+        let mut k = vec![0; 4];
+        encode_fixed_32(k.as_mut_slice(), n);
+        k
+    }
+
+    fn false_positive_rate(policy: &dyn FilterPolicy, filter: &[u8], n: u32) -> f64 {
+        let mut count = 0;
+        for i in 0..n {
+            if policy.may_contain(filter, &Slice::from(num_key(i + 1_000_000_000).as_slice())) {
+                count += 1;
+            }
+        }
+        f64::from(count) / f64::from(n)
+    }
+
+    #[test]
+    fn test_xor_filter_empty() {
+        let f = XorFilter::new();
+        let filter = f.create_filter(&[]);
+        assert!(!f.may_contain(&filter, &Slice::from("hello")));
+        assert!(!f.may_contain(&[], &Slice::from("hello")));
+    }
+
+    #[test]
+    fn test_xor_filter_round_trip() {
+        let f = XorFilter::new();
+        let mut n = 1;
+        while n <= 10000 {
+            let keys: Vec<Vec<u8>> = (0..n).map(num_key).collect();
+            let filter = f.create_filter(&keys);
+            assert!(
+                filter.len() <= (n as usize) * 123 / 100 + 33 + XOR_FILTER_TRAILER_SIZE,
+                "filter len {} for {} keys",
+                filter.len(),
+                n
+            );
+            // decoded by another instance
+            let reader = XorFilter::new();
+            for k in keys.iter() {
+                assert!(reader.may_contain(&filter, &Slice::from(k.as_slice())));
+            }
+            let rate = false_positive_rate(&reader, &filter, 10000);
+            assert!(rate <= 0.01, "false positive rate {} at len {}", rate, n);
+            n *= 10;
+        }
+    }
+
+    #[test]
+    fn test_xor_filter_duplicated_keys() {
+        let f = XorFilter::new();
+        let keys = vec![
+            Vec::from("a"),
+            Vec::from("b"),
+            Vec::from("a"),
+            Vec::from("b"),
+        ];
+        let filter = f.create_filter(&keys);
+        assert!(f.may_contain(&filter, &Slice::from("a")));
+        assert!(f.may_contain(&filter, &Slice::from("b")));
+        // the corrupted filter matches every key
+        let mut corrupted = filter.clone();
+        corrupted.remove(0);
+        assert!(f.may_contain(&corrupted, &Slice::from("c")));
+    }
+}
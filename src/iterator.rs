@@ -17,12 +17,14 @@
 
 use crate::options::ReadOptions;
 use crate::util::comparator::Comparator;
+use crate::util::prefix_extractor::PrefixExtractor;
 use crate::util::slice::Slice;
 use crate::util::status::{Result, WickErr};
 use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem;
 use std::rc::Rc;
+use std::slice;
 use std::sync::Arc;
 
 /// A common trait for iterating all the key/value entries.
@@ -55,6 +57,18 @@ pub trait Iterator {
     /// REQUIRES: `valid()`
     fn prev(&mut self);
 
+    /// Position at the last key in the source that is at or before target.
+    /// The iterator is valid after this call iff the source contains
+    /// an entry that comes at or before target.
+    fn seek_for_prev(&mut self, target: &Slice) {
+        self.seek(target);
+        if !self.valid() {
+            self.seek_to_last();
+        } else if self.key().as_slice() != target.as_slice() {
+            self.prev();
+        }
+    }
+
     /// Return the key for the current entry.  The underlying storage for
     /// the returned slice is valid only until the next modification of
     /// the iterator.
@@ -67,14 +81,59 @@ pub trait Iterator {
     /// REQUIRES: `valid()`
     fn value(&self) -> Slice;
 
+    /// Returns the key for the current entry as a slice borrowed from the
+    /// iterator. The borrow ends at the next modification of the iterator
+    /// (e.g. `next()`), so the key can be inspected without copying it out.
+    /// REQUIRES: `valid()`
+    ///
+    /// # Safety
+    ///
+    /// The `Slice` returned by `key()` must refer to the storage owned or pinned
+    /// by the iterator, which stays alive and unchanged until the next modification
+    /// of the iterator. It's true for the block, memtable and merging iterators but
+    /// not for an iterator returning a key built in `key()` itself.
+    #[inline]
+    unsafe fn key_slice(&self) -> &[u8] {
+        borrow_slice(&self.key())
+    }
+
+    /// Returns the value for the current entry as a slice borrowed from the
+    /// iterator. The borrow ends at the next modification of the iterator.
+    /// REQUIRES: `valid()`
+    ///
+    /// # Safety
+    ///
+    /// Same as `key_slice` for the `Slice` returned by `value()`.
+    #[inline]
+    unsafe fn value_slice(&self) -> &[u8] {
+        borrow_slice(&self.value())
+    }
+
     /// If an error has occurred, return it.  Else return an ok status.
+    ///
+    /// An iterator becomes invalid either at the end of the data or when it fails to
+    /// read the data, e.g. a corrupted block, so `status` should be checked once
+    /// `valid()` returns false. The error is taken out and returned only once.
     fn status(&mut self) -> Result<()>;
 }
 
+// Extends the lifetime of the data referred by `s` to `'a`. The caller must make
+// sure that the data outlives `'a`.
+#[inline]
+unsafe fn borrow_slice<'a>(s: &Slice) -> &'a [u8] {
+    if s.is_empty() {
+        &[]
+    } else {
+        slice::from_raw_parts(s.as_ptr(), s.size())
+    }
+}
+
 /// An special iterator calls all `tasks` before dropping
 pub struct IterWithCleanup {
     inner_iter: Box<dyn Iterator>,
     tasks: Vec<Box<FnMut()>>,
+    // attached to the error returned by `status`
+    err_context: Option<String>,
 }
 
 impl IterWithCleanup {
@@ -82,12 +141,18 @@ impl IterWithCleanup {
         Self {
             inner_iter: iter,
             tasks: vec![],
+            err_context: None,
         }
     }
 
     pub fn register_task(&mut self, task: Box<FnMut()>) {
         self.tasks.push(task)
     }
+
+    /// Sets the context attached to the error of the inner iterator
+    pub fn set_err_context(&mut self, context: String) {
+        self.err_context = Some(context)
+    }
 }
 
 impl Drop for IterWithCleanup {
@@ -132,7 +197,13 @@ impl Iterator for IterWithCleanup {
     }
 
     fn status(&mut self) -> Result<()> {
-        self.inner_iter.status()
+        match &self.err_context {
+            Some(context) => self
+                .inner_iter
+                .status()
+                .map_err(|e| e.with_context(context.clone())),
+            None => self.inner_iter.status(),
+        }
     }
 }
 
@@ -232,7 +303,8 @@ impl ConcatenateIterator {
     // Same as `InitDataBlock` in C++ implementation
     fn next_derived_iter(&mut self) {
         if !self.origin.valid() {
-            self.derived = None
+            // keep the error of the current derived iterator
+            self.set_derived(None)
         } else {
             let v = self.origin.value();
             if self.derived.is_none()
@@ -262,41 +334,35 @@ impl ConcatenateIterator {
     // Skip invalid results util finding a valid derived iter by `next()`
     // If found, set derived iter to the first
     fn skip_forward(&mut self) {
-        while let Some(di) = &self.derived {
-            if !di.valid() {
-                break;
+        while !matches!(&self.derived, Some(di) if di.valid()) {
+            if !self.origin.valid() {
+                self.set_derived(None);
+                return;
             }
             // yield next derived iter
-            if !self.origin.valid() {
-                self.set_derived(None)
-            } else {
-                self.origin.next();
-                self.next_derived_iter();
-                if let Some(i) = &mut self.derived {
-                    // init to the first
-                    i.seek_to_first();
-                }
+            self.origin.next();
+            self.next_derived_iter();
+            if let Some(i) = &mut self.derived {
+                // init to the first
+                i.seek_to_first();
             }
         }
     }
 
     // Skip invalid results util finding a valid derived iter by `prev()`
-    // If found, set derived iter to the first
+    // If found, set derived iter to the last
     fn skip_backward(&mut self) {
-        while let Some(di) = &self.derived {
-            if !di.valid() {
-                break;
-            }
-            // yield next derived iter
+        while !matches!(&self.derived, Some(di) if di.valid()) {
             if !self.origin.valid() {
-                self.set_derived(None)
-            } else {
-                self.origin.prev();
-                self.next_derived_iter();
-                if let Some(i) = &mut self.derived {
-                    // init to the first
-                    i.seek_to_last();
-                }
+                self.set_derived(None);
+                return;
+            }
+            // yield prev derived iter
+            self.origin.prev();
+            self.next_derived_iter();
+            if let Some(i) = &mut self.derived {
+                // init to the last
+                i.seek_to_last();
             }
         }
     }
@@ -340,8 +406,9 @@ impl Iterator for ConcatenateIterator {
 
     fn seek(&mut self, target: &Slice) {
         self.origin.seek(target);
+        self.next_derived_iter();
         if let Some(di) = self.derived.as_mut() {
-            di.seek_to_first()
+            di.seek(target)
         }
         self.skip_forward();
     }
@@ -424,11 +491,11 @@ impl MergingIterator {
         let mut index = self.current_index;
         for (i, child) in self.children.iter().enumerate() {
             if child.borrow().valid()
-                && (self.cmp.compare(
-                    child.borrow().key().as_slice(),
-                    smallest.as_ref().unwrap().borrow().key().as_slice(),
-                ) == Ordering::Less
-                    || smallest.is_none())
+                && (smallest.is_none()
+                    || self.cmp.compare(
+                        child.borrow().key().as_slice(),
+                        smallest.as_ref().unwrap().borrow().key().as_slice(),
+                    ) == Ordering::Less)
             {
                 smallest = Some(child.clone());
                 index = i
@@ -552,12 +619,192 @@ impl Iterator for MergingIterator {
     }
 }
 
+/// An iterator that only yields the entries whose key has the same prefix
+/// as the target of the last `seek`. The prefix is extracted by a `PrefixExtractor`.
+/// `seek_to_first` and `seek_to_last` reset the restriction so the
+/// iterator behaves just like the inner one.
+pub struct PrefixIterator {
+    inner: Box<dyn Iterator>,
+    extractor: Arc<dyn PrefixExtractor>,
+    // the prefix of the last seek target
+    prefix: Option<Vec<u8>>,
+}
+
+impl PrefixIterator {
+    pub fn new(inner: Box<dyn Iterator>, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        Self {
+            inner,
+            extractor,
+            prefix: None,
+        }
+    }
+}
+
+impl Iterator for PrefixIterator {
+    fn valid(&self) -> bool {
+        if !self.inner.valid() {
+            return false;
+        }
+        match &self.prefix {
+            Some(prefix) => {
+                let key = self.inner.key();
+                let k = key.as_slice();
+                self.extractor.in_domain(k) && self.extractor.transform(k) == prefix.as_slice()
+            }
+            None => true,
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.prefix = None;
+        self.inner.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) {
+        self.prefix = None;
+        self.inner.seek_to_last()
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        let t = target.as_slice();
+        self.prefix = if self.extractor.in_domain(t) {
+            Some(Vec::from(self.extractor.transform(t)))
+        } else {
+            None
+        };
+        self.inner.seek(target)
+    }
+
+    fn next(&mut self) {
+        self.inner.next()
+    }
+
+    fn prev(&mut self) {
+        self.inner.prev()
+    }
+
+    fn key(&self) -> Slice {
+        self.inner.key()
+    }
+
+    fn value(&self) -> Slice {
+        self.inner.value()
+    }
+
+    fn status(&mut self) -> Result<()> {
+        self.inner.status()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::iterator::{EmptyIterator, IterWithCleanup};
+    use crate::db::format::{InternalKey, InternalKeyComparator, ParsedInternalKey, ValueType};
+    use crate::iterator::{
+        ConcatenateIterator, DerivedIterFactory, EmptyIterator, IterWithCleanup, Iterator,
+        MergingIterator, PrefixIterator,
+    };
+    use crate::mem::{MemTable, MemoryTable};
+    use crate::options::ReadOptions;
+    use crate::sstable::block::{Block, BlockBuilder};
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::prefix_extractor::FixedLengthPrefixExtractor;
+    use crate::util::slice::Slice;
+    use crate::util::status::{Result, Status};
     use std::cell::RefCell;
     use std::mem;
     use std::rc::Rc;
+    use std::sync::Arc;
+
+    // An iterator over sorted bytewise keys. The value of each entry is its key.
+    pub struct TestIter {
+        keys: Vec<Vec<u8>>,
+        // `keys.len()` means invalid
+        current: usize,
+    }
+
+    impl TestIter {
+        pub fn new(mut keys: Vec<&str>) -> Self {
+            keys.sort();
+            let keys: Vec<Vec<u8>> = keys.iter().map(|k| Vec::from(k.as_bytes())).collect();
+            let current = keys.len();
+            Self { keys, current }
+        }
+    }
+
+    impl Iterator for TestIter {
+        fn valid(&self) -> bool {
+            self.current < self.keys.len()
+        }
+
+        fn seek_to_first(&mut self) {
+            self.current = 0;
+        }
+
+        fn seek_to_last(&mut self) {
+            self.current = if self.keys.is_empty() {
+                0
+            } else {
+                self.keys.len() - 1
+            };
+        }
+
+        fn seek(&mut self, target: &Slice) {
+            self.current = self
+                .keys
+                .iter()
+                .position(|k| k.as_slice() >= target.as_slice())
+                .unwrap_or(self.keys.len());
+        }
+
+        fn next(&mut self) {
+            assert!(self.valid());
+            self.current += 1;
+        }
+
+        fn prev(&mut self) {
+            assert!(self.valid());
+            if self.current == 0 {
+                self.current = self.keys.len();
+            } else {
+                self.current -= 1;
+            }
+        }
+
+        fn key(&self) -> Slice {
+            Slice::from(self.keys[self.current].as_slice())
+        }
+
+        fn value(&self) -> Slice {
+            self.key()
+        }
+
+        fn status(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn collect_forward(iter: &mut dyn Iterator) -> Vec<String> {
+        let mut result = vec![];
+        while iter.valid() {
+            result.push(iter.key().as_str().to_owned());
+            iter.next();
+        }
+        result
+    }
+
+    // Yields the keys of a group named by the largest key of it
+    struct TestGroupFactory {}
+
+    impl DerivedIterFactory for TestGroupFactory {
+        fn produce(&self, _: Rc<ReadOptions>, value: &Slice) -> Result<Box<dyn Iterator>> {
+            let keys = match value.as_str() {
+                "b" => vec!["a", "b"],
+                "d" => vec!["c", "d"],
+                _ => vec!["e", "f"],
+            };
+            Ok(Box::new(TestIter::new(keys)))
+        }
+    }
 
     struct TestCleanup {
         results: Vec<usize>,
@@ -578,4 +825,184 @@ mod tests {
             assert_eq!(i, test_cleaned_up.borrow().results[i]);
         }
     }
+
+    #[test]
+    fn test_borrowed_key_and_value() {
+        let icmp = Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        )));
+        let mem = MemTable::new(icmp.clone());
+        let mut builder = BlockBuilder::new(2, icmp.clone());
+        for i in 0..10u64 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            let ikey = InternalKey::new(&Slice::from(key.as_str()), i + 1, ValueType::Value);
+            mem.add(i + 1, ValueType::Value, key.as_bytes(), value.as_bytes());
+            builder.add(ikey.data(), value.as_bytes());
+        }
+        let block = Block::new(Vec::from(builder.finish())).unwrap();
+        for mut iter in [mem.iter(), block.iter(icmp.clone())] {
+            iter.seek_to_first();
+            let mut i = 0;
+            while iter.valid() {
+                // Safe because the memtable and the block own the entries
+                let key = unsafe { iter.key_slice() };
+                assert_eq!(key, iter.key().as_slice());
+                assert_eq!(
+                    ParsedInternalKey::decode_from(Slice::from(key))
+                        .unwrap()
+                        .user_key
+                        .as_str(),
+                    format!("key{:03}", i)
+                );
+                assert_eq!(
+                    unsafe { iter.value_slice() },
+                    format!("value{:03}", i).as_bytes()
+                );
+                iter.next();
+                i += 1;
+            }
+            assert_eq!(i, 10);
+        }
+    }
+
+    #[test]
+    fn test_prefix_iterator() {
+        let inner = TestIter::new(vec!["a", "ab1", "ab2", "ab3", "ac1", "ac2", "b"]);
+        let mut iter = PrefixIterator::new(
+            Box::new(inner),
+            Arc::new(FixedLengthPrefixExtractor::new(2)),
+        );
+        iter.seek(&Slice::from("ab"));
+        assert_eq!(collect_forward(&mut iter), vec!["ab1", "ab2", "ab3"]);
+        iter.seek(&Slice::from("ab2"));
+        assert_eq!(collect_forward(&mut iter), vec!["ab2", "ab3"]);
+        iter.seek(&Slice::from("ac2"));
+        assert!(iter.valid());
+        iter.prev();
+        assert_eq!(iter.key().as_str(), "ac1");
+        iter.prev();
+        assert!(!iter.valid());
+        // no entries with the prefix
+        iter.seek(&Slice::from("aa"));
+        assert!(!iter.valid());
+        iter.seek(&Slice::from("bb"));
+        assert!(!iter.valid());
+        // targets out of the domain are not restricted
+        iter.seek(&Slice::from("a"));
+        assert_eq!(
+            collect_forward(&mut iter),
+            vec!["a", "ab1", "ab2", "ab3", "ac1", "ac2", "b"]
+        );
+        iter.seek(&Slice::from("ac"));
+        iter.seek_to_first();
+        assert_eq!(collect_forward(&mut iter).len(), 7);
+    }
+
+    #[test]
+    fn test_concatenate_iterator_forward() {
+        let mut iter = ConcatenateIterator::new(
+            Rc::new(ReadOptions::default()),
+            Box::new(TestIter::new(vec!["b", "d", "f"])),
+            Box::new(TestGroupFactory {}),
+        );
+        iter.seek_to_first();
+        assert_eq!(
+            collect_forward(&mut iter),
+            vec!["a", "b", "c", "d", "e", "f"]
+        );
+        iter.seek(&Slice::from("c"));
+        assert_eq!(collect_forward(&mut iter), vec!["c", "d", "e", "f"]);
+        iter.seek(&Slice::from("bb"));
+        assert_eq!(iter.key().as_str(), "c");
+        iter.seek(&Slice::from("ff"));
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_merging_iterator_forward_and_reverse() {
+        let icmp = Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        )));
+        // keys with an even index go into the memtable and the others into a block
+        let mem = MemTable::new(icmp.clone());
+        let mut builder = BlockBuilder::new(3, icmp.clone());
+        let mut expected = vec![];
+        for i in 0..30u64 {
+            let key = format!("key{:03}", i);
+            let ikey = InternalKey::new(&Slice::from(key.as_str()), i + 1, ValueType::Value);
+            if i % 2 == 0 {
+                mem.add(i + 1, ValueType::Value, key.as_bytes(), key.as_bytes());
+            } else {
+                builder.add(ikey.data(), key.as_bytes());
+            }
+            expected.push(key);
+        }
+        let block = Block::new(Vec::from(builder.finish())).unwrap();
+        let children: Vec<Rc<RefCell<Box<dyn Iterator>>>> = vec![
+            Rc::new(RefCell::new(mem.iter())),
+            Rc::new(RefCell::new(block.iter(icmp.clone()))),
+        ];
+        let mut iter = MergingIterator::new(icmp.clone(), children);
+        let user_key = |iter: &MergingIterator| {
+            let parsed = ParsedInternalKey::decode_from(iter.key()).unwrap();
+            assert_eq!(parsed.user_key, iter.value());
+            parsed.user_key.as_str().to_owned()
+        };
+
+        iter.seek_to_first();
+        let mut forward = vec![];
+        while iter.valid() {
+            forward.push(user_key(&iter));
+            iter.next();
+        }
+        assert_eq!(forward, expected);
+
+        iter.seek_to_last();
+        let mut backward = vec![];
+        while iter.valid() {
+            backward.push(user_key(&iter));
+            iter.prev();
+        }
+        expected.reverse();
+        assert_eq!(backward, expected);
+
+        // switch directions in the middle of a traversal
+        let target = InternalKey::new(&Slice::from("key010"), u64::MAX >> 8, ValueType::Value);
+        iter.seek(&Slice::from(target.data()));
+        assert_eq!(user_key(&iter), "key010");
+        iter.next();
+        assert_eq!(user_key(&iter), "key011");
+        iter.prev();
+        assert_eq!(user_key(&iter), "key010");
+        iter.prev();
+        assert_eq!(user_key(&iter), "key009");
+        iter.next();
+        assert_eq!(user_key(&iter), "key010");
+        iter.seek_for_prev(&Slice::from(target.data()));
+        assert_eq!(user_key(&iter), "key009");
+    }
+
+    #[test]
+    fn test_merging_iterator_child_error() {
+        let cmp = Arc::new(BytewiseComparator::new());
+        let mut builder = BlockBuilder::new(16, cmp.clone());
+        for k in ["b", "d", "f"].iter() {
+            builder.add(k.as_bytes(), k.as_bytes());
+        }
+        let mut data = Vec::from(builder.finish());
+        // the second entry shares more bytes than the length of the previous key
+        data[5] = 100;
+        let block = Block::new(data).unwrap();
+        let children: Vec<Rc<RefCell<Box<dyn Iterator>>>> = vec![
+            Rc::new(RefCell::new(Box::new(TestIter::new(vec!["a", "c", "e"])))),
+            Rc::new(RefCell::new(block.iter(cmp.clone()))),
+        ];
+        let mut iter = MergingIterator::new(cmp, children);
+        iter.seek_to_first();
+        // the entries after the corruption in the block are lost
+        assert_eq!(collect_forward(&mut iter), vec!["a", "b", "c", "e"]);
+        assert_eq!(iter.status().unwrap_err().status(), Status::Corruption);
+        assert!(iter.status().is_ok());
+    }
 }
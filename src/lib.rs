@@ -45,17 +45,42 @@ pub mod storage;
 mod table_cache;
 mod version;
 
-pub use batch::WriteBatch;
-pub use cache::{Cache, HandleRef};
-pub use compaction::ManualCompaction;
-pub use db::{WickDB, DB};
+pub use batch::{BatchHandler, WriteBatch};
+pub use cache::{Cache, CacheStats, HandleRef};
+pub use compaction::{CompactionProgress, ManualCompaction};
+pub use db::event_listener::{CompactionJobInfo, EventListener, FlushJobInfo, TableFileInfo};
+pub use db::format::{
+    encode_internal_key, parse_internal_key, InternalKey, ValueType, MAX_KEY_SEQUENCE,
+    TYPE_DELETION, TYPE_MERGE, TYPE_RANGE_DELETION, TYPE_VALUE,
+};
+pub use db::namespace::Namespace;
+pub use db::repair::RepairReport;
+pub use db::statistics::Statistics;
+pub use db::subscription::Subscription;
+pub use db::{GetResult, WickDB, DB};
 pub use filter::bloom::BloomFilter;
+pub use filter::xor::XorFilter;
 pub use iterator::Iterator;
 pub use log::{LevelFilter, Log};
-pub use options::{CompressionType, Options, ReadOptions, WriteOptions};
+pub use mem::{MemTableRep, MemTableRepFactory, SkiplistFactory, SkiplistRep};
+pub use options::{
+    CacheEvictionPolicy, CompactRangeOptions, CompactionStyle, CompressionType, Compressor,
+    IndexType, IngestExternalFileOptions, Options, ReadOptions, UniversalCompactionOptions,
+    WALRecoveryMode, WriteOptions,
+};
+pub use snapshot::{Snapshot, SnapshotHandle};
 pub use sstable::block::Block;
+pub use sstable::external::ExternalTableBuilder;
+pub use sstable::table::{Table, TableIterator};
+pub use storage::mem::MemStorage;
 pub use storage::{File, Storage};
-pub use util::comparator::Comparator;
+pub use util::compaction_filter::{CompactionFilter, Decision};
+pub use util::comparator::{
+    BytewiseComparator, Comparator, NoShorteningComparator, NumericComparator, ReverseComparator,
+};
+pub use util::merge_operator::MergeOperator;
+pub use util::prefix_extractor::{FixedLengthPrefixExtractor, PrefixExtractor};
+pub use util::rate_limiter::{GenericRateLimiter, RateLimiter};
 pub use util::slice::Slice;
 pub use util::status::{Result, Status, WickErr};
 pub use util::varint::*;
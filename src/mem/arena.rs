@@ -13,6 +13,7 @@
 
 use std::cell::RefCell;
 use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{mem, ptr};
 
 const BLOCK_SIZE: usize = 4096;
@@ -34,8 +35,8 @@ pub trait Arena {
 ///
 /// # NOTICE:
 ///
-/// `BlockArena` must only be used with single thread writing since we use `RefCell` when
-/// allocating new blocks.
+/// The allocations are serialized by a mutex so that `BlockArena` could be shared by
+/// the concurrent writers. The `blocks` are only modified by the allocations.
 ///
 pub struct BlockArena {
     pub(super) ptr: AtomicPtr<u8>,
@@ -43,8 +44,13 @@ pub struct BlockArena {
     pub(super) blocks: RefCell<Vec<Vec<u8>>>,
     // Total memory usage of the arena.
     pub(super) memory_usage: AtomicUsize,
+    // Held while allocating
+    lock: Mutex<()>,
 }
 
+unsafe impl Send for BlockArena {}
+unsafe impl Sync for BlockArena {}
+
 impl BlockArena {
     /// Create an AggressiveArena with given cap.
     /// This function will allocate a cap size memory block directly for further usage
@@ -54,6 +60,7 @@ impl BlockArena {
             bytes_remaining: AtomicUsize::new(0),
             blocks: RefCell::new(vec![]),
             memory_usage: AtomicUsize::new(0),
+            lock: Mutex::new(()),
         }
     }
 
@@ -89,6 +96,7 @@ impl Arena for BlockArena {
         // 0-byte allocations, so we disallow them here (we don't need
         // them for our internal use).
         assert!(chunk > 0);
+        let _guard = self.lock.lock().unwrap();
         if chunk <= self.bytes_remaining.load(Ordering::Acquire) {
             let p = self.ptr.load(Ordering::Acquire);
             unsafe {
@@ -103,6 +111,7 @@ impl Arena for BlockArena {
 
     fn allocate_aligned(&self, chunk: usize) -> *mut u8 {
         assert!(chunk > 0);
+        let _guard = self.lock.lock().unwrap();
         let ptr_size = mem::size_of::<usize>();
         let align = if ptr_size > 8 { ptr_size } else { 8 };
         // the align should be a pow(2)
@@ -19,6 +19,7 @@ mod arena;
 mod skiplist;
 
 use crate::db::format::{InternalKeyComparator, LookupKey, ValueType};
+use crate::db::range_del::{max_covering_tombstone_seq, RangeTombstone};
 use crate::iterator::Iterator;
 use crate::mem::arena::BlockArena;
 use crate::mem::skiplist::{Skiplist, SkiplistIterator};
@@ -29,7 +30,7 @@ use crate::util::status::Status;
 use crate::util::status::{Result, WickErr};
 use crate::util::varint::VarintU32;
 use std::cmp::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 pub trait MemoryTable {
     /// Returns an estimate of the number of bytes of data in use by this
@@ -42,37 +43,154 @@ pub trait MemoryTable {
     /// Add an entry into memtable that maps key to value at the
     /// specified sequence number and with the specified type.
     /// Typically value will be empty if the type is `Deletion`.
-    ///
-    /// The 'key' and 'value' will be bundled together into an 'entry':
-    ///
-    /// ```text
-    ///   +=================================+
-    ///   |       format of the entry       |
-    ///   +=================================+
-    ///   | varint32 of internal key length |
-    ///   +---------------------------------+ ---------------
-    ///   | user key bytes                  |
-    ///   +---------------------------------+   internal key
-    ///   | sequence (7)       |   type (1) |
-    ///   +---------------------------------+ ---------------
-    ///   | varint32 of value length        |
-    ///   +---------------------------------+
-    ///   | value bytes                     |
-    ///   +---------------------------------+
-    /// ```
-    ///
+    /// If the type is `RangeDeletion`, the user keys in `[key, value)` are deleted.
     fn add(&self, seq_number: u64, val_type: ValueType, key: &[u8], value: &[u8]);
 
     /// If memtable contains a value for key, returns it in `Some(Ok())`.
-    /// If memtable contains a deletion for key, returns `Some(Err(Status::NotFound))` .
+    /// If memtable contains a deletion for key or a range tombstone newer than the
+    /// value covers the key, returns `Some(Err(Status::NotFound))` .
     /// If memtable does not contain the key, return `None`
-    fn get(&self, key: &LookupKey) -> Option<Result<Slice>>;
+    /// The merge operands of the key are skipped.
+    #[allow(dead_code)]
+    fn get(&self, key: &LookupKey) -> Option<Result<Slice>> {
+        self.get_with_merge_operands(key, &mut vec![], &mut None)
+    }
+
+    /// Same as `get` except that the merge operands of the key which are newer
+    /// than the returned value or deletion are pushed into `operands` from the newest
+    /// to the oldest.
+    /// If `newest_seq` is `None`, it's set to the sequence number of the newest entry
+    /// found, which is a value, a deletion, a merge operand or a covering range tombstone.
+    fn get_with_merge_operands(
+        &self,
+        key: &LookupKey,
+        operands: &mut Vec<Vec<u8>>,
+        newest_seq: &mut Option<u64>,
+    ) -> Option<Result<Slice>>;
+}
+
+/// The container of the entries in a `MemTable`, which keeps the internal keys
+/// and their values in the order of the internal key comparator.
+///
+/// The entries are inserted by a single writer at a time unless
+/// `allow_concurrent_insert` returns true. The insertions may be concurrent
+/// with the readers.
+pub trait MemTableRep {
+    /// Inserts an entry with the given internal key. The internal keys are unique.
+    fn insert(&self, internal_key: &[u8], value: &[u8]);
+
+    /// Calls `f` with the internal key and the value of the entries in order,
+    /// starting at the first one whose internal key is not less than `internal_key`,
+    /// until `f` returns false or there are no more entries.
+    /// The memory of the key and value passed to `f` must be valid as long as the
+    /// `MemTableRep` is alive since the values are returned by the `MemTable`
+    /// without copying.
+    ///
+    /// By default, the entries are found by an iterator, which requires the iterator
+    /// to yield the memory owned by the `MemTableRep` as well.
+    fn get(&self, internal_key: &[u8], f: &mut dyn FnMut(&[u8], &[u8]) -> bool) {
+        let mut iter = self.iter();
+        iter.seek(&Slice::from(internal_key));
+        while iter.valid() && f(iter.key().as_slice(), iter.value().as_slice()) {
+            iter.next();
+        }
+    }
+
+    /// Returns an iterator over the entries, whose keys are the internal keys.
+    /// The iterator is seeked by the internal keys as well.
+    fn iter(&self) -> Box<dyn Iterator>;
+
+    /// Returns an estimate of the number of bytes of data in use
+    fn approximate_memory_usage(&self) -> usize;
+
+    /// Returns true if `insert` is able to be called by several threads concurrently
+    fn allow_concurrent_insert(&self) -> bool {
+        false
+    }
+}
+
+/// A `MemTableRepFactory` creates a new `MemTableRep` for every memtable
+pub trait MemTableRepFactory: Send + Sync {
+    /// The name of the factory
+    fn name(&self) -> &str;
+
+    /// Creates an empty `MemTableRep` ordering the internal keys by `icmp`
+    fn create(&self, icmp: Arc<dyn Comparator>) -> Box<dyn MemTableRep>;
+}
+
+/// Creates the `SkiplistRep`s. This is the default `MemTableRepFactory`.
+#[derive(Default)]
+pub struct SkiplistFactory {}
+
+impl MemTableRepFactory for SkiplistFactory {
+    fn name(&self) -> &str {
+        "wickdb.SkiplistFactory"
+    }
+
+    fn create(&self, icmp: Arc<dyn Comparator>) -> Box<dyn MemTableRep> {
+        Box::new(SkiplistRep::new(icmp))
+    }
+}
+
+/// A `MemTableRep` backed by a skiplist in an arena.
+///
+/// The internal key and the value are bundled together into an entry:
+///
+/// ```text
+///   +=================================+
+///   |       format of the entry       |
+///   +=================================+
+///   | varint32 of internal key length |
+///   +---------------------------------+ ---------------
+///   | user key bytes                  |
+///   +---------------------------------+   internal key
+///   | sequence (7)       |   type (1) |
+///   +---------------------------------+ ---------------
+///   | varint32 of value length        |
+///   +---------------------------------+
+///   | value bytes                     |
+///   +---------------------------------+
+/// ```
+pub struct SkiplistRep {
+    table: Arc<Skiplist>,
+}
+
+impl SkiplistRep {
+    pub fn new(icmp: Arc<dyn Comparator>) -> Self {
+        let arena = BlockArena::new();
+        let kcmp = Arc::new(KeyComparator { cmp: icmp });
+        Self {
+            table: Arc::new(Skiplist::new(kcmp, Box::new(arena))),
+        }
+    }
+}
+
+impl MemTableRep for SkiplistRep {
+    fn insert(&self, internal_key: &[u8], value: &[u8]) {
+        let mut buf = vec![];
+        VarintU32::put_varint_prefixed_slice(&mut buf, internal_key);
+        VarintU32::put_varint_prefixed_slice(&mut buf, value);
+        // TODO: remove redundant copying
+        self.table.insert(Slice::from(buf.as_slice()));
+    }
+
+    fn iter(&self) -> Box<dyn Iterator> {
+        Box::new(MemTableIterator::new(self.table.clone()))
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.table.arena.memory_used()
+    }
+
+    fn allow_concurrent_insert(&self) -> bool {
+        true
+    }
 }
 
 // KeyComparator is a wrapper for InternalKeyComparator. It will convert the input mem key
 // to the internal key before comparing.
 struct KeyComparator {
-    cmp: Arc<InternalKeyComparator>,
+    cmp: Arc<dyn Comparator>,
 }
 
 impl Comparator for KeyComparator {
@@ -104,64 +222,134 @@ impl Comparator for KeyComparator {
 
 /// In-memory write buffer
 pub struct MemTable {
-    cmp: Arc<KeyComparator>,
-    table: Arc<Skiplist>,
+    icmp: Arc<InternalKeyComparator>,
+    table: Box<dyn MemTableRep>,
+    // All the range tombstones in the `table`. They are also kept here so that
+    // a point lookup doesn't need to scan the table to find the covering tombstones.
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
 }
 
 impl MemTable {
-    pub fn new(cmp: Arc<InternalKeyComparator>) -> Self {
-        let arena = BlockArena::new();
-        let kcmp = Arc::new(KeyComparator { cmp });
-        let table = Arc::new(Skiplist::new(kcmp.clone(), Box::new(arena)));
-        Self { cmp: kcmp, table }
+    /// Creates a `MemTable` backed by a `SkiplistRep`
+    pub fn new(icmp: Arc<InternalKeyComparator>) -> Self {
+        Self::with_factory(icmp, &SkiplistFactory::default())
+    }
+
+    /// Creates a `MemTable` backed by the `MemTableRep` created by `factory`
+    pub fn with_factory(
+        icmp: Arc<InternalKeyComparator>,
+        factory: &dyn MemTableRepFactory,
+    ) -> Self {
+        let table = factory.create(icmp.clone());
+        Self {
+            icmp,
+            table,
+            range_tombstones: RwLock::new(vec![]),
+        }
+    }
+
+    /// Returns all the range tombstones added so far
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().unwrap().clone()
+    }
+
+    /// Returns true if there is no entry in the memtable
+    pub fn is_empty(&self) -> bool {
+        let mut iter = self.iter();
+        iter.seek_to_first();
+        !iter.valid()
+    }
+
+    /// Returns true if the entries are able to be added by several threads concurrently
+    #[inline]
+    pub fn allow_concurrent_insert(&self) -> bool {
+        self.table.allow_concurrent_insert()
     }
 }
 
 impl MemoryTable for MemTable {
     fn approximate_memory_usage(&self) -> usize {
-        self.table.arena.memory_used()
+        self.table.approximate_memory_usage()
     }
 
     fn iter(&self) -> Box<dyn Iterator> {
-        Box::new(MemTableIterator::new(self.table.clone()))
+        self.table.iter()
     }
 
     fn add(&self, seq_number: u64, val_type: ValueType, key: &[u8], value: &[u8]) {
-        let key_size = key.len();
-        let internal_key_size = key_size + 8;
-        let mut buf = vec![];
-        VarintU32::put_varint(&mut buf, internal_key_size as u32);
-        buf.extend_from_slice(key);
-        put_fixed_64(&mut buf, (seq_number << 8) | val_type as u64);
-        VarintU32::put_varint_prefixed_slice(&mut buf, value);
-        // TODO: remove redundant copying
-        self.table.insert(Slice::from(buf.as_slice()))
+        let mut internal_key = Vec::with_capacity(key.len() + 8);
+        internal_key.extend_from_slice(key);
+        put_fixed_64(&mut internal_key, (seq_number << 8) | val_type as u64);
+        self.table.insert(internal_key.as_slice(), value);
+        if val_type == ValueType::RangeDeletion {
+            self.range_tombstones
+                .write()
+                .unwrap()
+                .push(RangeTombstone::new(key, value, seq_number));
+        }
     }
 
-    fn get(&self, key: &LookupKey) -> Option<Result<Slice>> {
-        let mk = key.mem_key();
-        // internal key
-        let mut iter = self.iter();
-        iter.seek(&mk);
-        if iter.valid() {
-            let internal_key = iter.key();
-            // only check the user key here
-            match self.cmp.cmp.user_comparator.compare(
-                Slice::new(internal_key.as_ptr(), internal_key.size() - 8).as_slice(),
-                key.user_key().as_slice(),
-            ) {
-                Ordering::Equal => {
-                    let tag = decode_fixed_64(&internal_key.as_slice()[internal_key.size() - 8..]);
-                    match ValueType::from(tag & 0xff as u64) {
-                        ValueType::Value => return Some(Ok(iter.value())),
-                        ValueType::Deletion => {
-                            return Some(Err(WickErr::new(Status::NotFound, None)))
-                        }
-                        ValueType::Unknown => { /* fallback to None*/ }
+    fn get_with_merge_operands(
+        &self,
+        key: &LookupKey,
+        operands: &mut Vec<Vec<u8>>,
+        newest_seq: &mut Option<u64>,
+    ) -> Option<Result<Slice>> {
+        let ukey = key.user_key();
+        let ucmp = self.icmp.user_comparator.as_ref();
+        let lookup_ikey = key.internal_key();
+        let snapshot = decode_fixed_64(&lookup_ikey.as_slice()[lookup_ikey.size() - 8..]) >> 8;
+        let tombstone_seq = max_covering_tombstone_seq(
+            self.range_tombstones.read().unwrap().as_slice(),
+            ucmp,
+            ukey.as_slice(),
+            snapshot,
+        );
+        let mut result = None;
+        self.table
+            .get(lookup_ikey.as_slice(), &mut |internal_key, value| {
+                // only check the user key here
+                let (user_key, tag) = internal_key.split_at(internal_key.len() - 8);
+                if ucmp.compare(user_key, ukey.as_slice()) != Ordering::Equal {
+                    return false;
+                }
+                let tag = decode_fixed_64(tag);
+                if let Some(seq) = tombstone_seq {
+                    if seq > tag >> 8 {
+                        // the entry is deleted by a newer range tombstone
+                        return false;
+                    }
+                }
+                let value_type = ValueType::from(tag & 0xff);
+                if newest_seq.is_none() && value_type != ValueType::RangeDeletion {
+                    *newest_seq = Some(tag >> 8);
+                }
+                match value_type {
+                    ValueType::Value => {
+                        result = Some(Ok(Slice::from(value)));
+                        false
+                    }
+                    ValueType::Deletion => {
+                        result = Some(Err(WickErr::new(Status::NotFound, None)));
+                        false
                     }
+                    ValueType::Merge => {
+                        operands.push(Vec::from(value));
+                        true
+                    }
+                    // range tombstones have been checked by `range_tombstones`
+                    ValueType::RangeDeletion => true,
+                    ValueType::Unknown => false,
                 }
-                _ => return None,
+            });
+        if result.is_some() {
+            return result;
+        }
+        if tombstone_seq.is_some() {
+            if newest_seq.is_none() {
+                *newest_seq = tombstone_seq;
             }
+            return Some(Err(WickErr::new(Status::NotFound, None)));
         }
         None
     }
@@ -169,12 +357,14 @@ impl MemoryTable for MemTable {
 
 pub struct MemTableIterator {
     iter: SkiplistIterator,
+    // the buffer for encoding the seek target as a mem key
+    tmp: Vec<u8>,
 }
 
 impl MemTableIterator {
     pub fn new(table: Arc<Skiplist>) -> Self {
         let iter = SkiplistIterator::new(table);
-        Self { iter }
+        Self { iter, tmp: vec![] }
     }
 }
 
@@ -191,8 +381,11 @@ impl Iterator for MemTableIterator {
         self.iter.seek_to_last()
     }
 
+    // `target` is an internal key
     fn seek(&mut self, target: &Slice) {
-        self.iter.seek(target)
+        self.tmp.clear();
+        VarintU32::put_varint_prefixed_slice(&mut self.tmp, target.as_slice());
+        self.iter.seek(&Slice::from(self.tmp.as_slice()))
     }
 
     fn next(&mut self) {
@@ -232,10 +425,121 @@ fn extract_varint32_encoded_slice(origin: &mut Slice) -> Slice {
 #[cfg(test)]
 mod tests {
     use crate::db::format::{InternalKeyComparator, LookupKey, ParsedInternalKey, ValueType};
-    use crate::mem::{MemTable, MemoryTable};
-    use crate::util::comparator::BytewiseComparator;
-    use crate::util::status::Status;
-    use std::sync::Arc;
+    use crate::iterator::Iterator;
+    use crate::mem::{MemTable, MemTableRep, MemTableRepFactory, MemoryTable};
+    use crate::util::comparator::{BytewiseComparator, Comparator};
+    use crate::util::slice::Slice;
+    use crate::util::status::{Result, Status};
+    use std::cmp::Ordering;
+    use std::sync::{Arc, RwLock};
+
+    // A `MemTableRep` keeping the entries in a sorted vector
+    struct VecRep {
+        cmp: Arc<dyn Comparator>,
+        entries: RwLock<Vec<(Vec<u8>, Vec<u8>)>>,
+    }
+
+    impl MemTableRep for VecRep {
+        // the iterator yields the memory of a copy
+        fn get(&self, internal_key: &[u8], f: &mut dyn FnMut(&[u8], &[u8]) -> bool) {
+            let entries = self.entries.read().unwrap();
+            for (k, v) in entries.iter() {
+                if self.cmp.compare(k, internal_key) != Ordering::Less && !f(k, v) {
+                    break;
+                }
+            }
+        }
+
+        fn insert(&self, internal_key: &[u8], value: &[u8]) {
+            let mut entries = self.entries.write().unwrap();
+            let i = entries
+                .binary_search_by(|(k, _)| self.cmp.compare(k, internal_key))
+                .unwrap_err();
+            entries.insert(i, (internal_key.to_vec(), value.to_vec()));
+        }
+
+        fn iter(&self) -> Box<dyn Iterator> {
+            let entries = self.entries.read().unwrap().clone();
+            Box::new(VecRepIterator {
+                cmp: self.cmp.clone(),
+                pos: entries.len(),
+                entries,
+            })
+        }
+
+        fn approximate_memory_usage(&self) -> usize {
+            self.entries
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| k.len() + v.len())
+                .sum()
+        }
+    }
+
+    // Iterates a copy of the entries. `pos` is the length of `entries` if it's invalid.
+    struct VecRepIterator {
+        cmp: Arc<dyn Comparator>,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        pos: usize,
+    }
+
+    impl Iterator for VecRepIterator {
+        fn valid(&self) -> bool {
+            self.pos < self.entries.len()
+        }
+
+        fn seek_to_first(&mut self) {
+            self.pos = 0
+        }
+
+        fn seek_to_last(&mut self) {
+            self.pos = self.entries.len().saturating_sub(1)
+        }
+
+        fn seek(&mut self, target: &Slice) {
+            self.pos = self
+                .entries
+                .iter()
+                .position(|(k, _)| self.cmp.compare(k, target.as_slice()) != Ordering::Less)
+                .unwrap_or(self.entries.len())
+        }
+
+        fn next(&mut self) {
+            self.pos += 1
+        }
+
+        fn prev(&mut self) {
+            self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len())
+        }
+
+        fn key(&self) -> Slice {
+            Slice::from(self.entries[self.pos].0.as_slice())
+        }
+
+        fn value(&self) -> Slice {
+            Slice::from(self.entries[self.pos].1.as_slice())
+        }
+
+        fn status(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct VecRepFactory {}
+
+    impl MemTableRepFactory for VecRepFactory {
+        fn name(&self) -> &str {
+            "VecRepFactory"
+        }
+
+        fn create(&self, icmp: Arc<dyn Comparator>) -> Box<dyn MemTableRep> {
+            Box::new(VecRep {
+                cmp: icmp,
+                entries: RwLock::new(vec![]),
+            })
+        }
+    }
 
     fn new_mem_table() -> MemTable {
         let icmp = Arc::new(InternalKeyComparator::new(Arc::new(
@@ -283,6 +587,134 @@ mod tests {
         assert_eq!(b"boo", v.unwrap().unwrap().as_slice());
     }
 
+    #[test]
+    fn test_memtable_with_custom_rep() {
+        let icmp = Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        )));
+        let memtable = MemTable::with_factory(icmp, &VecRepFactory {});
+        let entries = add_test_data_set(&memtable);
+        memtable.add(5, ValueType::Merge, b"foo", b"m5");
+        memtable.add(6, ValueType::RangeDeletion, b"a", b"b");
+        assert!(memtable.approximate_memory_usage() > 0);
+
+        let v = memtable.get(&LookupKey::new(b"foo", 10));
+        assert_eq!(b"val3", v.unwrap().unwrap().as_slice());
+        let v = memtable.get(&LookupKey::new(b"foo", 3));
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+        let v = memtable.get(&LookupKey::new(b"foo", 1));
+        assert_eq!(b"val1", v.unwrap().unwrap().as_slice());
+        let v = memtable.get(&LookupKey::new(b"foo", 0));
+        assert!(v.is_none());
+        let v = memtable.get(&LookupKey::new(b"aa", 10));
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+        let mut operands = vec![];
+        let v =
+            memtable.get_with_merge_operands(&LookupKey::new(b"foo", 10), &mut operands, &mut None);
+        assert_eq!(b"val3", v.unwrap().unwrap().as_slice());
+        assert_eq!(operands, vec![b"m5".to_vec()]);
+
+        // the iterator yields the internal keys in order
+        let mut iter = memtable.iter();
+        iter.seek(&LookupKey::new(b"boo", 10).internal_key());
+        let mut keys = vec![];
+        while iter.valid() {
+            let pkey = ParsedInternalKey::decode_from(iter.key()).unwrap();
+            keys.push((pkey.user_key.as_str().to_owned(), pkey.seq));
+            iter.next();
+        }
+        let expected: Vec<(String, u64)> = vec![
+            ("boo", 2),
+            ("foo", 5),
+            ("foo", 4),
+            ("foo", 3),
+            ("foo", 2),
+            ("foo", 1),
+        ]
+        .into_iter()
+        .map(|(k, seq)| (k.to_owned(), seq))
+        .collect();
+        assert_eq!(keys, expected);
+        iter.seek_to_last();
+        assert_eq!(iter.value().as_str(), entries.last().unwrap().1);
+    }
+
+    #[test]
+    fn test_memtable_range_deletion() {
+        let memtable = new_mem_table();
+        memtable.add(1, ValueType::Value, b"a", b"va");
+        memtable.add(2, ValueType::Value, b"b", b"vb");
+        memtable.add(3, ValueType::Value, b"c", b"vc");
+        memtable.add(4, ValueType::RangeDeletion, b"b", b"d");
+        memtable.add(5, ValueType::Value, b"c", b"vc2");
+
+        let v = memtable.get(&LookupKey::new(b"a", 10));
+        assert_eq!(b"va", v.unwrap().unwrap().as_slice());
+        // covered by the tombstone
+        let v = memtable.get(&LookupKey::new(b"b", 10));
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+        // written after the tombstone
+        let v = memtable.get(&LookupKey::new(b"c", 10));
+        assert_eq!(b"vc2", v.unwrap().unwrap().as_slice());
+        // the tombstone also hides the keys not in memtable
+        let v = memtable.get(&LookupKey::new(b"bb", 10));
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+        // the end is exclusive
+        let v = memtable.get(&LookupKey::new(b"d", 10));
+        assert!(v.is_none());
+        // the tombstone is invisible to older snapshots
+        let v = memtable.get(&LookupKey::new(b"b", 3));
+        assert_eq!(b"vb", v.unwrap().unwrap().as_slice());
+        let v = memtable.get(&LookupKey::new(b"c", 4));
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+    }
+
+    #[test]
+    fn test_memtable_merge_operands() {
+        let memtable = new_mem_table();
+        memtable.add(1, ValueType::Merge, b"a", b"m1");
+        memtable.add(2, ValueType::Value, b"b", b"vb");
+        memtable.add(3, ValueType::Merge, b"b", b"m3");
+        memtable.add(4, ValueType::Merge, b"b", b"m4");
+        memtable.add(5, ValueType::Deletion, b"c", b"");
+        memtable.add(6, ValueType::Merge, b"c", b"m6");
+
+        // no base in memtable
+        let mut operands = vec![];
+        let v =
+            memtable.get_with_merge_operands(&LookupKey::new(b"a", 10), &mut operands, &mut None);
+        assert!(v.is_none());
+        assert_eq!(operands, vec![b"m1".to_vec()]);
+
+        let mut operands = vec![];
+        let v =
+            memtable.get_with_merge_operands(&LookupKey::new(b"b", 10), &mut operands, &mut None);
+        assert_eq!(b"vb", v.unwrap().unwrap().as_slice());
+        assert_eq!(operands, vec![b"m4".to_vec(), b"m3".to_vec()]);
+
+        let mut operands = vec![];
+        let v =
+            memtable.get_with_merge_operands(&LookupKey::new(b"b", 3), &mut operands, &mut None);
+        assert_eq!(b"vb", v.unwrap().unwrap().as_slice());
+        assert_eq!(operands, vec![b"m3".to_vec()]);
+
+        let mut operands = vec![];
+        let mut seq = None;
+        let v =
+            memtable.get_with_merge_operands(&LookupKey::new(b"c", 10), &mut operands, &mut seq);
+        assert_eq!(Status::NotFound, v.unwrap().unwrap_err().status());
+        assert_eq!(operands, vec![b"m6".to_vec()]);
+        // the sequence of the newest operand
+        assert_eq!(seq, Some(6));
+        let mut seq = None;
+        memtable.get_with_merge_operands(&LookupKey::new(b"c", 5), &mut vec![], &mut seq);
+        assert_eq!(seq, Some(5));
+
+        // `get` skips the operands
+        let v = memtable.get(&LookupKey::new(b"b", 10));
+        assert_eq!(b"vb", v.unwrap().unwrap().as_slice());
+    }
+
     #[test]
     fn test_memtable_iter() {
         let memtable = new_mem_table();
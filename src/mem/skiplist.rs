@@ -78,6 +78,15 @@ impl Node {
         self.next_nodes[height - 1].store(node, Ordering::Release);
     }
 
+    /// Sets the next node at the given height to `node` only if it's still `expected`.
+    /// Returns whether the next node is updated.
+    #[inline]
+    pub fn cas_next(&self, height: usize, expected: *mut Node, node: *mut Node) -> bool {
+        self.next_nodes[height - 1]
+            .compare_exchange(expected, node, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
     #[inline]
     pub fn key(&self) -> &Slice {
         &self.key
@@ -85,7 +94,7 @@ impl Node {
 }
 
 /// A skiplist with an memory based arena. The skiplist
-/// should be thread safe for reading
+/// is thread safe for both reading and inserting.
 pub struct Skiplist {
     // current max height
     // Should be handled atomically
@@ -98,6 +107,11 @@ pub struct Skiplist {
     pub arena: Box<dyn Arena>,
 }
 
+// The nodes are never removed, and the insertions only link the nodes by
+// atomic operations.
+unsafe impl Send for Skiplist {}
+unsafe impl Sync for Skiplist {}
+
 impl Skiplist {
     /// Create a new Skiplist with the given arena capacity
     pub fn new(cmp: Arc<dyn Comparator>, mut arena: Box<dyn Arena>) -> Self {
@@ -116,31 +130,38 @@ impl Skiplist {
     ///
     /// # NOTICE:
     ///
-    /// Concurrent insertion is not thread safe but concurrent reading with a
-    /// single writer is safe.
+    /// The insertion is lock-free so it's safe to insert from several threads
+    /// concurrently, as well as reading concurrently. The new node is linked from
+    /// the bottom level to the top, so a reader always finds it at the bottom level
+    /// once it's visible at any level.
     ///
     pub fn insert(&self, key: Slice) {
-        let mut prev = [ptr::null_mut(); MAX_HEIGHT];
-        let node = self.find_greater_or_equal(&key, Some(&mut prev));
-        if !node.is_null() {
-            unsafe {
-                assert_ne!(
-                    (&(*node)).key().compare(&key),
-                    CmpOrdering::Equal,
-                    "[skiplist] duplicate insertion [key={:?}] is not allowed",
-                    key
-                );
-            }
-        }
         let height = rand_height();
-        let max_height = self.max_height.load(Ordering::Acquire);
-        if height > max_height {
-            #[allow(clippy::needless_range_loop)]
-            for i in max_height..height {
-                prev[i] = self.head;
+        let mut max_height = self.max_height.load(Ordering::Acquire);
+        while height > max_height {
+            // The new levels are empty until a node is linked in, and the readers
+            // take a null next node as the end of a level
+            match self.max_height.compare_exchange_weak(
+                max_height,
+                height,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => max_height = height,
+                Err(h) => max_height = h,
             }
-            self.max_height.store(height, Ordering::Release);
         }
+        // `prev[i].key < key <= next[i].key` at the level `i + 1`
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null_mut(); MAX_HEIGHT];
+        let mut before = self.head;
+        for level in (1..=max_height).rev() {
+            let (p, n) = self.find_splice_for_level(&key, before, level);
+            prev[level - 1] = p;
+            next[level - 1] = n;
+            before = p;
+        }
+        self.check_unique(&key, next[0]);
         // allocate the key
         let k = self.arena.allocate(key.size());
         unsafe {
@@ -154,8 +175,52 @@ impl Skiplist {
         );
         unsafe {
             for i in 1..=height {
-                (*new_node).set_next(i, (*(prev[i - 1])).get_next(i));
-                (*(prev[i - 1])).set_next(i, new_node);
+                loop {
+                    (*new_node).set_next(i, next[i - 1]);
+                    if (*(prev[i - 1])).cas_next(i, next[i - 1], new_node) {
+                        break;
+                    }
+                    // Another node is inserted between `prev` and `next` concurrently.
+                    // The splice is still valid for the new key on the right of `prev`.
+                    let (p, n) = self.find_splice_for_level(&key, prev[i - 1], i);
+                    prev[i - 1] = p;
+                    next[i - 1] = n;
+                    if i == 1 {
+                        self.check_unique(&key, n);
+                    }
+                }
+            }
+        }
+    }
+
+    // Finds the nodes `prev` and `next` at the given level such that
+    // `prev.key < key <= next.key`, starting from the node `before` whose key is
+    // less than `key`.
+    fn find_splice_for_level(
+        &self,
+        key: &Slice,
+        mut before: *mut Node,
+        level: usize,
+    ) -> (*mut Node, *mut Node) {
+        loop {
+            let next = unsafe { (*before).get_next(level) };
+            if self.key_is_less_than_or_equal(key, next) {
+                return (before, next);
+            }
+            before = next;
+        }
+    }
+
+    // Panics if the key of the `next` node equals `key`
+    fn check_unique(&self, key: &Slice, next: *mut Node) {
+        if !next.is_null() {
+            unsafe {
+                assert_ne!(
+                    (&(*next)).key().compare(key),
+                    CmpOrdering::Equal,
+                    "[skiplist] duplicate insertion [key={:?}] is not allowed",
+                    key
+                );
             }
         }
     }
@@ -888,4 +953,68 @@ mod tests {
             run_concurrent()
         }
     }
+
+    #[test]
+    fn test_concurrent_insert() {
+        let writers = 8;
+        let n = 2000;
+        let skl = Arc::new(Skiplist::new(
+            Arc::new(U64Comparator {}),
+            Box::new(BlockArena::new()),
+        ));
+        let done = Arc::new(AtomicBool::new(false));
+        // the reader always observes the keys in order
+        let reader = {
+            let skl = skl.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::Acquire) {
+                    let mut iter = SkiplistIterator::new(skl.clone());
+                    iter.seek_to_first();
+                    let mut last = None;
+                    while iter.valid() {
+                        let k = decode_fixed_64(iter.key().as_slice());
+                        if let Some(l) = last {
+                            assert!(l < k, "key {} is after {}", k, l);
+                        }
+                        last = Some(k);
+                        iter.next();
+                    }
+                }
+            })
+        };
+        let handles: Vec<_> = (0..writers)
+            .map(|w| {
+                let skl = skl.clone();
+                thread::spawn(move || {
+                    for i in 0..n {
+                        let mut key = vec![];
+                        put_fixed_64(&mut key, i * writers + w);
+                        skl.insert(Slice::from(key.as_slice()));
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().expect("Insert thread panics");
+        }
+        done.store(true, Ordering::Release);
+        reader.join().expect("Read thread panics");
+
+        // every level is ordered and the bottom level has all the keys
+        for level in 1..=skl.max_height.load(Ordering::Acquire) {
+            let mut keys = vec![];
+            unsafe {
+                let mut node = (*skl.head).get_next(level);
+                while !node.is_null() {
+                    keys.push(decode_fixed_64((*node).key().as_slice()));
+                    node = (*node).get_next(level);
+                }
+            }
+            assert!(keys.windows(2).all(|w| w[0] < w[1]));
+            if level == 1 {
+                assert_eq!(keys, (0..n * writers).collect::<Vec<u64>>());
+            }
+        }
+    }
 }
@@ -15,26 +15,55 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use crate::cache::clock::ClockCache;
 use crate::cache::lru::SharedLRUCache;
 use crate::cache::Cache;
+use crate::compaction::CompactionProgress;
+use crate::db::event_listener::EventListener;
 use crate::db::filename::{generate_filename, FileType};
+use crate::db::format::MAX_KEY_SEQUENCE;
 use crate::filter::FilterPolicy;
 use crate::logger::Logger;
-use crate::options::CompressionType::{NoCompression, SnappyCompression, Unknown};
+use crate::mem::{MemTableRepFactory, SkiplistFactory};
+use crate::options::CompressionType::{
+    Custom, Lz4Compression, NoCompression, SnappyCompression, Unknown, ZstdCompression,
+};
+use crate::record::{BLOCK_SIZE, RECYCLABLE_HEADER_SIZE};
 use crate::snapshot::Snapshot;
 use crate::sstable::block::Block;
+use crate::sstable::filter_block::DEFAULT_FILTER_BASE_LG;
 use crate::storage::file::FileStorage;
 use crate::storage::Storage;
+use crate::util::compaction_filter::CompactionFilter;
 use crate::util::comparator::{BytewiseComparator, Comparator};
+use crate::util::merge_operator::MergeOperator;
+use crate::util::prefix_extractor::PrefixExtractor;
+use crate::util::rate_limiter::RateLimiter;
+use crate::util::status::{Result, Status, WickErr};
 use crate::LevelFilter;
 use crate::Log;
-use std::rc::Rc;
+use hashbrown::HashMap;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-#[derive(Clone, Copy, Debug)]
+/// The smallest tag that can be used by `CompressionType::Custom`.
+/// Tags below it are reserved for the builtin algorithms.
+pub const MIN_CUSTOM_COMPRESSION_TAG: u8 = 0x40;
+
+/// The compression algorithm used for a block.
+///
+/// The tag of each type is persisted in the block trailer, so the tags of
+/// existing types must never be changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
-    NoCompression = 0,
-    SnappyCompression = 1,
+    NoCompression,
+    SnappyCompression,
+    ZstdCompression,
+    Lz4Compression,
+    /// A user defined algorithm identified by a tag no less than
+    /// `MIN_CUSTOM_COMPRESSION_TAG`. The `Compressor` for the tag must be
+    /// registered in `Options::compressors` both when writing and reading.
+    Custom(u8),
     Unknown,
 }
 
@@ -43,11 +72,139 @@ impl From<u8> for CompressionType {
         match i {
             0 => NoCompression,
             1 => SnappyCompression,
+            2 => ZstdCompression,
+            3 => Lz4Compression,
+            t if t >= MIN_CUSTOM_COMPRESSION_TAG => Custom(t),
             _ => Unknown,
         }
     }
 }
 
+impl From<CompressionType> for u8 {
+    /// # Panics
+    ///
+    /// * `Unknown` has no tag
+    fn from(t: CompressionType) -> Self {
+        match t {
+            NoCompression => 0,
+            SnappyCompression => 1,
+            ZstdCompression => 2,
+            Lz4Compression => 3,
+            Custom(tag) => tag,
+            Unknown => panic!("[compression type] unknown compression type has no tag"),
+        }
+    }
+}
+
+/// The layout of the index of a table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    /// A single index block with an entry for every data block, which is read
+    /// when the table is opened
+    Flat,
+    /// The index entries are split into the partitions of about
+    /// `Options::index_partition_size` bytes, and a top-level index block points
+    /// to the partitions. Only the top-level index is read when the table is
+    /// opened, and a partition is read through the block cache when it's needed.
+    /// This makes opening a huge table cheap at the cost of one more block read
+    /// on a cache miss.
+    TwoLevel,
+}
+
+/// The eviction policy of a cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheEvictionPolicy {
+    /// The least recently used entry is evicted first by a `SharedLRUCache`
+    LeastRecentlyUsed,
+    /// The entries are evicted by the CLOCK algorithm of a `ClockCache`, which
+    /// approximates `LeastRecentlyUsed` with less lock contention on the lookups
+    Clock,
+}
+
+impl CacheEvictionPolicy {
+    /// Creates a cache with the given capacity evicting the entries by this policy.
+    /// It could be used to create the `block_cache` as well.
+    pub fn new_cache<T: 'static + Clone + Send + Sync>(self, capacity: usize) -> Arc<dyn Cache<T>> {
+        match self {
+            CacheEvictionPolicy::LeastRecentlyUsed => Arc::new(SharedLRUCache::new(capacity)),
+            CacheEvictionPolicy::Clock => Arc::new(ClockCache::new(capacity)),
+        }
+    }
+}
+
+/// The way the table files are organized and compacted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// Every level except L0 is a sorted run of non-overlapping files, and the
+    /// files in a level exceeding its size limit are merged into the next level.
+    Level,
+    /// Every L0 file and every non-empty deeper level is a sorted run, and the
+    /// newest sorted runs with similar sizes are merged into a bigger one. It
+    /// rewrites the data fewer times than `Level` at the cost of more space and
+    /// slower reads. See `UniversalCompactionOptions` for details.
+    Universal,
+}
+
+/// The way the corruptions in the WAL are handled when a DB is opened
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WALRecoveryMode {
+    /// The incomplete record at the tail of a log, which is usually left by a crash
+    /// in the middle of a write, is ignored. The corrupted records at the tail are
+    /// dropped as well since nothing follows them. Any other corruption fails the
+    /// recovery with `Status::Corruption`.
+    TolerateCorruptedTailRecords,
+    /// Any corruption, including an incomplete record at the tail, fails the
+    /// recovery with `Status::Corruption`. Suitable for the applications which
+    /// never expect to lose a write acknowledged by the DB.
+    AbsoluteConsistency,
+}
+
+/// The options of `CompactionStyle::Universal`.
+///
+/// Once there are at least `Options::level0_file_num_compaction_trigger` sorted
+/// runs, the sorted runs are checked from the newest to the oldest. A run is
+/// picked if its size is not greater than the total size of the newer runs picked
+/// plus `size_ratio` percent, and the picked runs are merged if there are at least
+/// `min_merge_width` of them. If no run is picked but there are more than
+/// `max_sorted_runs` runs, the newest runs are merged to keep the number of the
+/// sorted runs within `max_sorted_runs`.
+#[derive(Clone, Debug)]
+pub struct UniversalCompactionOptions {
+    /// The percentage of the flexibility when comparing the size of a run with
+    /// the total size of the newer ones. Default is 1.
+    pub size_ratio: u64,
+    /// The minimum number of the sorted runs merged by the size ratio.
+    /// Must be at least 2. Default is 2.
+    pub min_merge_width: usize,
+    /// The maximum number of the sorted runs merged by the size ratio.
+    /// Must not be less than `min_merge_width`. Default is unlimited.
+    pub max_merge_width: usize,
+    /// The maximum number of the sorted runs. Must be at least 1. Default is 8.
+    pub max_sorted_runs: usize,
+}
+
+impl Default for UniversalCompactionOptions {
+    fn default() -> Self {
+        Self {
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: usize::MAX,
+            max_sorted_runs: 8,
+        }
+    }
+}
+
+/// A `Compressor` provides a customized block compression algorithm.
+/// It is registered in `Options::compressors` with the tag of a `CompressionType::Custom`.
+pub trait Compressor: Send + Sync {
+    /// Compress the given raw block data
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decompress the given data produced by `compress`.
+    /// An error should be returned if the data is malformed.
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
 /// Options to control the behavior of a database (passed to `DB::Open`)
 pub struct Options {
     // -------------------
@@ -66,35 +223,96 @@ pub struct Options {
     /// If true, an error is raised if the database already exists.
     pub error_if_exists: bool,
 
+    /// If non-zero, the last sequence number of the DB is raised to this value
+    /// at open so that the new writes get larger sequence numbers, which is used
+    /// to preserve the versions of the data migrated from another engine. The
+    /// open fails with `Status::InvalidArgument` if the DB has recovered a larger
+    /// sequence number, so the option should be cleared once the DB is written.
+    /// Default is 0.
+    pub initial_sequence: u64,
+
     /// If true, the implementation will do aggressive checking of the
     /// data it is processing and will stop early if it detects any
     /// errors.  This may have unforeseen ramifications: for example, a
     /// corruption of one DB entry may cause a large number of entries to
     /// become unreadable or for the entire DB to become unopenable.
+    ///
+    /// The keys added into a table by flushes and compactions are checked to be
+    /// strictly increasing, and so are the index keys of a table on opening.
+    /// A violation, which is usually caused by a broken `comparator`, is reported
+    /// as `Status::Corruption` instead of writing or reading a broken table.
     pub paranoid_checks: bool,
 
+    /// The number of times in a row a background flush or compaction failing with
+    /// a `Status::IOError` is retried automatically, which is usually a transient
+    /// error like running out of disk space. The DB rejects the writes with the
+    /// error until the retry succeeds, or until `WickDB::resume` is called once
+    /// the retries are used up. The default 0 disables the automatic retries.
+    pub max_bg_error_resume_count: usize,
+
+    /// The time in milliseconds to wait before the first automatic retry of a
+    /// failed background work, which is doubled for every following retry.
+    pub bg_error_resume_retry_interval: u64,
+
+    /// The way the corruptions in the WAL are handled on recovery.
+    /// Default is `WALRecoveryMode::TolerateCorruptedTailRecords`.
+    pub wal_recovery_mode: WALRecoveryMode,
+
+    /// If true, the table files are mapped into memory for reading when the
+    /// storage supports it. Otherwise they are read by `pread`.
+    pub allow_mmap_reads: bool,
+
     /// Use the specified object to interact with the environment,
     pub env: Arc<dyn Storage>,
     // -------------------
     // Parameters that affect compaction:
     /// The max number of levels except L)
+    /// Must be at least 2.
     pub max_levels: u8,
 
+    /// The style of the compaction. Default is `CompactionStyle::Level`.
+    pub compaction_style: CompactionStyle,
+
+    /// The options used when `compaction_style` is `CompactionStyle::Universal`
+    pub universal_compaction_options: UniversalCompactionOptions,
+
     /// The number of files necessary to trigger an L0 compaction.
-    pub l0_compaction_threshold: usize,
+    /// Must be at least 1.
+    pub level0_file_num_compaction_trigger: usize,
 
-    /// Soft limit on the number of L0 files. Writes are slowed down when this
-    /// threshold is reached.
-    pub l0_slowdown_writes_threshold: usize,
+    /// Soft limit on the number of L0 files. Every write is delayed by 1ms
+    /// when this threshold is reached. Must not be less than
+    /// `level0_file_num_compaction_trigger`.
+    pub level0_slowdown_writes_trigger: usize,
 
-    /// Hard limit on the number of L0 files. Writes are stopped when this
-    /// threshold is reached.
-    pub l0_stop_writes_threshold: usize,
+    /// Hard limit on the number of L0 files. Writes are blocked when this
+    /// threshold is reached, until the L0 files are compacted below it.
+    /// Must not be less than `level0_slowdown_writes_trigger`.
+    pub level0_stop_writes_trigger: usize,
 
     /// The maximum number of bytes for L1. The maximum number of bytes for other
     /// levels is computed dynamically based on this value. When the maximum
     /// number of bytes for a level is exceeded, compaction is requested.
-    pub l1_max_bytes: u64,
+    pub max_bytes_for_level_base: u64,
+
+    /// The maximum number of bytes for level `L + 1` is `max_bytes_for_level_multiplier`
+    /// times the one of level `L`. A smaller multiplier makes more levels for the
+    /// same amount of data, which trades more write amplification for less space
+    /// amplification. Must be larger than 1. Default is 10.
+    pub max_bytes_for_level_multiplier: u64,
+
+    /// If true, the maximum number of bytes of the levels above the bottommost
+    /// non-empty level is derived from the actual size of the bottommost level:
+    /// level `L` is allowed `size(bottommost) / max_bytes_for_level_multiplier ^ (bottommost - L)`
+    /// bytes but no less than `max_bytes_for_level_base`. The bottommost level
+    /// itself keeps the static limit so the data still moves to the deeper levels
+    /// as it grows. This keeps the total size close to the size of the bottommost
+    /// level when the DB is much smaller than the capacity of its deepest level.
+    ///
+    /// The limits are computed from the current files, so the option could be
+    /// switched on or off on an existing DB and the levels are reshaped by the
+    /// following compactions. Default is false.
+    pub level_compaction_dynamic_level_bytes: bool,
 
     /// Maximum level to which a new compacted memtable is pushed if it
     /// does not create overlap.  We try to push to level 2 to avoid the
@@ -102,28 +320,88 @@ pub struct Options {
     /// expensive manifest file operations.  We do not push all the way to
     /// the largest level since that can generate a lot of wasted disk
     /// space if the same key space is being repeatedly overwritten.
+    /// Must be less than `max_levels`.
     pub max_mem_compact_level: usize,
 
-    /// Approximate gap in bytes between samples of data read during iteration
+    /// If true, a flushed memtable is placed at the deepest level such that
+    /// neither the level nor the upper levels overlap its user key range, the
+    /// same as an ingested file, instead of being limited by `max_mem_compact_level`.
+    /// A memtable overlapping the level 0 files still goes to level 0. This
+    /// saves the compactions moving the data down for the workloads which rarely
+    /// overwrite the keys. Ignored by the universal compaction. Default is false.
+    pub flush_to_lowest_level: bool,
+
+    /// Approximate gap in bytes between samples of data read during iteration.
+    /// Must be at least 1.
     pub read_bytes_period: u64,
 
+    /// The maximum number of threads a compaction is split into. A compaction
+    /// with several input files is partitioned into subcompactions at the smallest
+    /// keys of the input files, and the subcompactions are run in parallel, each
+    /// writing its own output files. Must be at least 1, and 1 disables the
+    /// subcompactions.
+    ///
+    /// Since the range tombstones are stored with their start keys, every
+    /// subcompaction except the first one also scans the inputs before its range
+    /// for the tombstones covering it.
+    pub max_subcompactions: usize,
+
+    /// If non-zero, the compactions read their input tables `compaction_readahead_size`
+    /// bytes at a time and take the following blocks from the buffer, which turns
+    /// the reads of the blocks one by one into a few large sequential reads. This
+    /// helps a lot on the spinning disks. The blocks read by the compactions are
+    /// never inserted into the block cache. Default is 0 (disabled).
+    pub compaction_readahead_size: usize,
+
+    /// The table files older than `ttl` seconds are compacted even if no level
+    /// needs a compaction, so the deleted or expired data in the files which are
+    /// rarely picked is eventually reclaimed. The files in the last level are
+    /// rewritten in place. The oldest file is picked first, and the files are
+    /// always rewritten so the `compaction_filter` is applied on them.
+    ///
+    /// 0 disables it. Only works with `CompactionStyle::Level`.
+    pub ttl: u64,
+
     // -------------------
     // Parameters that affect performance:
     /// Amount of data to build up in memory (backed by an unsorted log
     /// on disk) before converting to a sorted on-disk file.
     ///
     /// Larger values increase performance, especially during bulk loads.
-    /// Up to two write buffers may be held in memory at the same time,
-    /// so you may wish to adjust this parameter to control memory usage.
-    /// Also, a larger write buffer will result in a longer recovery time
-    /// the next time the database is opened.
+    /// Up to `max_write_buffer_number + 1` write buffers may be held in memory
+    /// at the same time, so you may wish to adjust this parameter to control
+    /// memory usage. Also, a larger write buffer will result in a longer recovery
+    /// time the next time the database is opened.
     pub write_buffer_size: usize,
 
+    /// The maximum number of the immutable memtables waiting for being flushed.
+    /// The writes stall when the memtable is full and there are already so many
+    /// immutable memtables, until one of them is flushed. Must be at least 1.
+    pub max_write_buffer_number: usize,
+
+    /// Creates the `MemTableRep` which holds the entries of every memtable.
+    /// The default is a `SkiplistFactory`.
+    pub memtable_factory: Arc<dyn MemTableRepFactory>,
+
+    /// If true, the batches grouped into one WAL record are inserted into the
+    /// memtable in parallel by their own writers, when the `MemTableRep` supports
+    /// concurrent insertions. Otherwise the group is inserted by a single thread.
+    pub allow_concurrent_memtable_write: bool,
+
     /// Number of open files that can be used by the DB.  You may need to
     /// increase this if your database has a large working set (budget
     /// one open file per 2MB of working set).
+    ///
+    /// At most `max_open_files - non_table_cache_files` sstables are kept open
+    /// by the table cache. An sstable evicted from the cache is closed together
+    /// with its index block and filter block once it's not being read.
+    /// The value is clipped to `[64 + non_table_cache_files, 50000]`.
     pub max_open_files: usize,
 
+    /// The eviction policy of the table cache deciding which sstable is closed
+    /// when there are too many open files.
+    pub table_cache_eviction_policy: CacheEvictionPolicy,
+
     // -------------------
     // Control over blocks (user data is stored in a set of blocks, and
     // a block is the unit of reading from disk).
@@ -131,7 +409,20 @@ pub struct Options {
     /// If null, we will automatically create and use an 8MB internal cache.
     pub block_cache: Option<Arc<dyn Cache<Arc<Block>>>>,
 
-    /// Number of sstables that remains out of table cache
+    /// If true, the index block and the filter block of an sstable are loaded into
+    /// the `block_cache` when the table is opened and read through the cache later,
+    /// so they are charged to the cache and could be evicted like the data blocks.
+    /// Otherwise they are held by the opened table until it's closed.
+    /// Ignored if `block_cache` is `None`.
+    pub cache_index_and_filter_blocks: bool,
+
+    /// If true and `cache_index_and_filter_blocks` is set, the index block and the
+    /// filter block of a level 0 sstable are pinned in the `block_cache` as long as
+    /// the table is open, so the reads of the level 0 tables never load them again.
+    pub pin_l0_filter_and_index_blocks_in_cache: bool,
+
+    /// Number of open files reserved for the files other than the sstables,
+    /// such as the WAL, the MANIFEST and the info log
     pub non_table_cache_files: usize,
 
     /// Approximate size of user data packed per block.  Note that the
@@ -140,12 +431,31 @@ pub struct Options {
     /// compression is enabled.  This parameter can be changed dynamically.
     pub block_size: usize,
 
-    /// Number of keys between restart points for delta encoding of keys.
+    /// Number of keys between restart points for delta encoding of keys in
+    /// the data blocks. The index blocks always restart at every key so that a
+    /// seek in them is a pure binary search.
     /// This parameter can be changed dynamically.  Most clients should
-    /// leave this parameter alone.
+    /// leave this parameter alone. A smaller interval makes the seeks within
+    /// a block faster at the cost of a worse prefix compression of the keys.
+    /// Must be at least 1.
     pub block_restart_interval: usize,
 
-    /// The DB will write up to this amount of bytes to a file before
+    /// The layout of the index of the new tables. The tables built with any
+    /// index type are readable regardless of this option. Default is `Flat`.
+    pub index_type: IndexType,
+
+    /// Approximate size of the index entries packed per partition when
+    /// `index_type` is `TwoLevel`.
+    pub index_partition_size: usize,
+
+    /// If true, the index entries of the new tables are the full last keys of the
+    /// data blocks instead of the shortened separators computed by the comparator,
+    /// as if the comparator were wrapped in a `NoShorteningComparator`. This makes
+    /// the index blocks larger and is meant for diagnosing the index issues.
+    /// Default is false.
+    pub disable_key_shortening: bool,
+
+    /// The DB will write up to this amount of bytes to a file in level 1 before
     /// switching to a new one.
     /// Most clients should leave this parameter alone.  However if your
     /// filesystem is more efficient with larger files, you could
@@ -153,40 +463,214 @@ pub struct Options {
     /// compactions and hence longer latency/performance hiccups.
     /// Another reason to increase this parameter might be when you are
     /// initially populating a large database.
-    pub max_file_size: u64,
+    pub target_file_size_base: u64,
+
+    /// The target file size of level `L + 1` is `target_file_size_multiplier`
+    /// times the one of level `L`, so the deeper levels are made of fewer and
+    /// larger files. Level 0 uses `target_file_size_base` as well.
+    /// Default is 1, which gives the files in all the levels the same target size.
+    pub target_file_size_multiplier: u64,
 
     /// Compress blocks using the specified compression algorithm.  This
     /// parameter can be changed dynamically. Default is SnappyCompression.
     pub compression: CompressionType,
 
+    /// Compress blocks of the tables in level `i` using `compression_per_level[i]`.
+    /// This makes it possible to, for example, disable compression in level 0 to
+    /// speed up flushing while still compressing the bigger levels.
+    /// The levels not covered by this vector use `compression`.
+    ///
+    /// REQUIRES: `compression_per_level.len()` <= `max_levels`
+    pub compression_per_level: Vec<CompressionType>,
+
+    /// The compression level used when `compression` is `ZstdCompression`.
+    /// Higher levels trade compression speed for a better ratio. It has no
+    /// effect on reading since the level is not needed for decompression.
+    /// Default is 3, which is zstd's own default level.
+    pub zstd_compression_level: i32,
+
+    /// The registered customized compressors keyed by the tag of `CompressionType::Custom`.
+    /// Reading a block compressed by a custom type whose compressor is not
+    /// registered results in a corruption error.
+    pub compressors: HashMap<u8, Arc<dyn Compressor>>,
+
     /// If true, append to existing MANIFEST and log files when a database is opened.
     /// This can significantly speed up open.
     pub reuse_logs: bool,
 
+    /// The number of the obsolete log files kept for being recycled. A new log
+    /// overwrites a recycled file in place instead of creating a new file, which
+    /// saves the file creations and deletions. The records are written in a
+    /// recyclable format carrying the log number, so the stale records of the
+    /// previous log in the file are never replayed.
+    ///
+    /// Since the first invalid record is considered as the end of a recyclable
+    /// log, this is incompatible with `WALRecoveryMode::AbsoluteConsistency`.
+    ///
+    /// Default: 0, which disables the recycling
+    pub recycle_log_file_num: usize,
+
+    /// The size of the blocks in the log files. The records are split at the block
+    /// boundaries so a larger block suits the devices with larger optimal I/O sizes.
+    /// The size is recorded in the MANIFEST and the DB fails to be opened with a
+    /// different one. It must be larger than the record header and at most 64KB.
+    ///
+    /// Default: 32KB
+    pub log_block_size: usize,
+
     /// If non-null, use the specified filter policy to reduce disk reads.
-    /// Many applications will benefit from passing the result of
-    /// NewBloomFilterPolicy() here.
-    pub filter_policy: Option<Rc<dyn FilterPolicy>>,
+    /// Many applications will benefit from passing a `BloomFilter` here,
+    /// e.g. `Some(Arc::new(BloomFilter::new(10)))`. The bits per key only
+    /// affect the new tables since the hash count is encoded in every filter.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+
+    /// The log of the bytes of data blocks covered by one filter in the filter block.
+    /// A filter is generated for every `1 << filter_base_lg` bytes so smaller values
+    /// produce more accurate but larger filter blocks. The value is persisted in each
+    /// table so it could be changed without breaking the existing tables.
+    /// Default is 11 (2KiB).
+    pub filter_base_lg: usize,
+
+    /// If non-zero, the new tables store a bloom filter over all their user keys
+    /// with `table_filter_bits_per_key` bits per key. These filters are kept in
+    /// memory by the table cache even after the tables are evicted from it, so a
+    /// point lookup skips opening a table which definitely has no entry of the key.
+    /// The tables without such a filter are queried as usual. Default is 0 (disabled).
+    pub table_filter_bits_per_key: usize,
+
+    /// If non-null, the prefix of every key is extracted by the given extractor.
+    /// An iterator positioned by `seek` stops once the prefix of the current key
+    /// differs from the prefix of the seek target unless `ReadOptions::total_order_seek`
+    /// is set. The prefixes are also added
+    /// into the filter blocks when `filter_policy` is set, and only the prefixes
+    /// are added if the policy is a prefix one like `BloomFilter::new_prefix`.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+
+    /// The merge operator used to fold the operands written by `WriteBatch::merge`.
+    /// Must be set if the DB contains any merge operand.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+
+    /// If non-null, the entries rewritten by compactions are filtered by the given
+    /// filter, which is able to remove or modify them. See `CompactionFilter`.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// If non-null, the table files written by flushes and compactions are
+    /// throttled by the given limiter. Writes to the WAL are never throttled.
+    pub rate_limiter: Option<Arc<dyn RateLimiter>>,
 
     /// The underlying logger default to a `LOG` file
     pub logger: Option<Box<dyn Log>>,
 
+    /// The listeners notified when a flush or a compaction finishes
+    pub listeners: Vec<Arc<dyn EventListener>>,
+
     /// The maximum log level
     pub logger_level: LevelFilter,
 }
 
 impl Options {
+    /// Checks the invariants of the options, which is done by `WickDB::open_db`
+    /// before anything is opened. Returns `Status::InvalidArgument` with a message
+    /// naming the offending option if any of them is violated.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: &'static str| Err(WickErr::new(Status::InvalidArgument, Some(msg)));
+        if self.initial_sequence > MAX_KEY_SEQUENCE {
+            return invalid("initial_sequence must be no larger than MAX_KEY_SEQUENCE");
+        }
+        if self.max_levels < 2 {
+            return invalid("max_levels must be at least 2");
+        }
+        if self.compression_per_level.len() > self.max_levels as usize {
+            return invalid("compression_per_level has more entries than max_levels");
+        }
+        for c in self
+            .compression_per_level
+            .iter()
+            .chain(Some(&self.compression))
+        {
+            if let Custom(tag) = c {
+                if *tag < MIN_CUSTOM_COMPRESSION_TAG {
+                    return invalid("compression has a custom tag reserved for builtin types");
+                }
+                if !self.compressors.contains_key(tag) {
+                    return invalid("compression has a custom tag not registered in compressors");
+                }
+            }
+        }
+        if self.max_mem_compact_level >= self.max_levels as usize {
+            return invalid("max_mem_compact_level must be less than max_levels");
+        }
+        if self.max_write_buffer_number < 1 {
+            return invalid("max_write_buffer_number must be at least 1");
+        }
+        if self.level0_file_num_compaction_trigger < 1
+            || self.level0_slowdown_writes_trigger < self.level0_file_num_compaction_trigger
+            || self.level0_stop_writes_trigger < self.level0_slowdown_writes_trigger
+        {
+            return invalid("level0 triggers must satisfy 1 <= compaction <= slowdown <= stop");
+        }
+        if self.max_bytes_for_level_base < 1 {
+            return invalid("max_bytes_for_level_base must be at least 1");
+        }
+        if self.max_bytes_for_level_multiplier <= 1 {
+            return invalid("max_bytes_for_level_multiplier must be larger than 1");
+        }
+        let universal = &self.universal_compaction_options;
+        if universal.min_merge_width < 2
+            || universal.max_merge_width < universal.min_merge_width
+            || universal.max_sorted_runs < 1
+        {
+            return invalid("invalid universal compaction options");
+        }
+        if self.max_subcompactions < 1 {
+            return invalid("max_subcompactions must be at least 1");
+        }
+        if self.read_bytes_period < 1 {
+            return invalid("read_bytes_period must be at least 1");
+        }
+        if self.block_restart_interval < 1 {
+            return invalid("block_restart_interval must be at least 1");
+        }
+        if self.index_type == IndexType::TwoLevel && self.index_partition_size < 1 {
+            return invalid("index_partition_size must be at least 1 for the two-level index");
+        }
+        if self.log_block_size <= RECYCLABLE_HEADER_SIZE || self.log_block_size > 1 << 16 {
+            return invalid(
+                "log_block_size must be larger than the record header and at most 64KB",
+            );
+        }
+        if self.recycle_log_file_num > 0
+            && self.wal_recovery_mode == WALRecoveryMode::AbsoluteConsistency
+        {
+            return invalid(
+                "recycle_log_file_num is incompatible with WALRecoveryMode::AbsoluteConsistency",
+            );
+        }
+        Ok(())
+    }
+
     /// Maximum number of bytes in all compacted files.  We avoid expanding
     /// the lower level file set of a compaction if it would make the
     /// total compaction cover more than this many bytes.
-    pub(crate) fn expanded_compaction_byte_size_limit(&self) -> u64 {
-        25 * self.max_file_size
+    pub(crate) fn expanded_compaction_byte_size_limit(&self, level: usize) -> u64 {
+        25 * self.max_file_size_for_level(level)
     }
 
     /// Maximum bytes of overlaps in grandparent (i.e., level+2) before we
     /// stop building a single file in a level->level+1 compaction.
-    pub(crate) fn max_grandparent_overlap_bytes(&self) -> u64 {
-        10 * self.max_file_size as u64
+    pub(crate) fn max_grandparent_overlap_bytes(&self, level: usize) -> u64 {
+        10 * self.max_file_size_for_level(level)
+    }
+
+    /// The size of a file at which the compaction outputting into the given level
+    /// switches to a new file
+    pub(crate) fn max_file_size_for_level(&self, mut level: usize) -> u64 {
+        let mut result = self.target_file_size_base;
+        while level > 1 {
+            result = result.saturating_mul(self.target_file_size_multiplier);
+            level -= 1;
+        }
+        result
     }
 
     /// Maximum bytes of total files in a given level
@@ -195,28 +679,41 @@ impl Options {
         // the level-0 compaction threshold based on number of files.
 
         // Result for both level-0 and level-1
-        let mut result = self.l1_max_bytes;
+        let mut result = self.max_bytes_for_level_base;
         while level > 1 {
-            result *= 10;
+            result = result.saturating_mul(self.max_bytes_for_level_multiplier);
             level -= 1;
         }
         result
     }
 
-    /// Reserve `non_table_cache_files` files or so for other uses and give the rest to TableCache
-    pub(crate) fn table_cache_size(&self) -> usize {
+    /// Returns the compression type used by the tables in the given level
+    pub(crate) fn compression_for_level(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.get(level) {
+            Some(c) => *c,
+            None => self.compression,
+        }
+    }
+
+    /// Returns the max number of the sstables kept open by the table cache.
+    /// `non_table_cache_files` files or so are reserved for other uses and the
+    /// rest is given to the table cache.
+    pub fn table_cache_size(&self) -> usize {
         self.max_open_files - self.non_table_cache_files
     }
 
     /// Initialize Options by limiting ranges of some flags, applying customized Logger and etc.
-    pub(crate) fn initialize(&mut self, db_name: String) {
+    // Sanitizes the options. No info log file is created for a read-only DB.
+    pub(crate) fn initialize(&mut self, db_name: String, read_only: bool) {
         self.max_open_files =
             Self::clip_range(self.max_open_files, 64 + self.non_table_cache_files, 50000);
         self.write_buffer_size = Self::clip_range(self.write_buffer_size, 64 << 10, 1 << 30);
-        self.max_file_size = Self::clip_range(self.max_file_size, 1 << 20, 1 << 30);
+        self.target_file_size_base = Self::clip_range(self.target_file_size_base, 1 << 20, 1 << 30);
+        self.target_file_size_multiplier = self.target_file_size_multiplier.max(1);
         self.block_size = Self::clip_range(self.block_size, 1 << 10, 4 << 20);
+        self.filter_base_lg = Self::clip_range(self.filter_base_lg, 1, 30);
 
-        if self.logger.is_none() {
+        if self.logger.is_none() && !read_only {
             let _ = self.env.mkdir_all(&db_name);
             if let Ok(f) = self
                 .env
@@ -258,26 +755,61 @@ impl Default for Options {
             comparator: Arc::new(BytewiseComparator::new()),
             create_if_missing: true,
             error_if_exists: false,
+            initial_sequence: 0,
             paranoid_checks: false,
+            max_bg_error_resume_count: 0,
+            bg_error_resume_retry_interval: 1000,
+            wal_recovery_mode: WALRecoveryMode::TolerateCorruptedTailRecords,
+            allow_mmap_reads: false,
             env: Arc::new(FileStorage {}),
             max_levels: 7,
-            l0_compaction_threshold: 4,
-            l0_slowdown_writes_threshold: 8,
-            l0_stop_writes_threshold: 12,
-            l1_max_bytes: 64 * 1024 * 1024, // 64MB
+            compaction_style: CompactionStyle::Level,
+            universal_compaction_options: UniversalCompactionOptions::default(),
+            level0_file_num_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 12,
+            max_bytes_for_level_base: 64 * 1024 * 1024, // 64MB
+            max_bytes_for_level_multiplier: 10,
+            level_compaction_dynamic_level_bytes: false,
             max_mem_compact_level: 2,
+            flush_to_lowest_level: false,
             read_bytes_period: 1048576,
+            max_subcompactions: 1,
+            compaction_readahead_size: 0,
+            ttl: 0,
             write_buffer_size: 4 * 1024 * 1024, // 4MB
+            max_write_buffer_number: 1,
+            memtable_factory: Arc::new(SkiplistFactory::default()),
+            allow_concurrent_memtable_write: false,
             max_open_files: 500,
+            table_cache_eviction_policy: CacheEvictionPolicy::LeastRecentlyUsed,
             block_cache: Some(Arc::new(SharedLRUCache::new(8 << 20))),
+            cache_index_and_filter_blocks: false,
+            pin_l0_filter_and_index_blocks_in_cache: false,
             non_table_cache_files: 10,
             block_size: 4 * 1024, // 4KB
             block_restart_interval: 16,
-            max_file_size: 2 * 1024 * 1024, // 2MB
+            index_type: IndexType::Flat,
+            index_partition_size: 4 * 1024, // 4KB
+            disable_key_shortening: false,
+            target_file_size_base: 2 * 1024 * 1024, // 2MB
+            target_file_size_multiplier: 1,
             compression: SnappyCompression,
+            compression_per_level: vec![],
+            zstd_compression_level: 3,
+            compressors: HashMap::new(),
             reuse_logs: true,
+            recycle_log_file_num: 0,
+            log_block_size: BLOCK_SIZE,
             filter_policy: None,
+            filter_base_lg: DEFAULT_FILTER_BASE_LG,
+            table_filter_bits_per_key: 0,
+            prefix_extractor: None,
+            merge_operator: None,
+            compaction_filter: None,
+            rate_limiter: None,
             logger: None,
+            listeners: vec![],
             logger_level: LevelFilter::Info,
         }
     }
@@ -286,26 +818,59 @@ impl Default for Options {
 /// Options that control read operations
 pub struct ReadOptions {
     /// If true, all data read from underlying storage will be
-    /// verified against corresponding checksums.
+    /// verified against corresponding checksums. Turning it off saves the CRC
+    /// computation of every block read for the trusted data.
+    /// Default: true
     pub verify_checksums: bool,
 
     /// Should the data read for this iteration be cached in memory?
     /// Callers may wish to set this field to false for bulk scans.
     pub fill_cache: bool,
 
+    /// If true, the blocks touched by an iterator are pinned in the block cache
+    /// until the iterator is dropped, so that re-seeking to them never re-reads
+    /// the file. Only affects iterators.
+    pub pin_data: bool,
+
     /// If `snapshot` is `None`, read as of the supplied snapshot
     /// (which must belong to the DB that is being read and which must
     /// not have been released).  If `snapshot` is `None`, use an implicit
     /// snapshot of the state at the beginning of this read operation.
     pub snapshot: Option<Snapshot>,
+
+    /// If `iterate_lower_bound` is set, the iterator only yields the keys that are
+    /// greater than or equal to the bound in the order of `comparator`.
+    /// `seek_to_first` and any `seek` to a smaller target are positioned at the bound.
+    pub iterate_lower_bound: Option<Vec<u8>>,
+
+    /// If `iterate_upper_bound` is set, the iterator only yields the keys that are
+    /// strictly less than the bound in the order of `comparator`.
+    /// `seek_to_last` is positioned at the last key before the bound.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+
+    /// If true, an iterator seeks and moves in the total order of `comparator`
+    /// across all the prefixes even if `Options::prefix_extractor` is set, instead
+    /// of stopping at the end of the prefix of the seek target.
+    /// Default: false
+    pub total_order_seek: bool,
+
+    /// If non-zero, an iterator reads the table files `readahead_size` bytes at
+    /// a time and takes the following blocks from the buffer, which benefits the
+    /// long sequential scans. Default: 0
+    pub readahead_size: usize,
 }
 
 impl Default for ReadOptions {
     fn default() -> Self {
         ReadOptions {
-            verify_checksums: false,
+            verify_checksums: true,
             fill_cache: true,
+            pin_data: false,
             snapshot: None,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+            total_order_seek: false,
+            readahead_size: 0,
         }
     }
 }
@@ -326,5 +891,180 @@ pub struct WriteOptions {
     /// crash semantics as the "write()" system call.  A DB write
     /// with sync==true has similar crash semantics to a "write()"
     /// system call followed by "fsync()".
+    ///
+    /// Concurrent writes are grouped into one log record, which is synced
+    /// if any write in the group has sync==true.
     pub sync: bool,
+
+    /// If true, the write is not appended to the WAL (the log file) and only
+    /// goes into the memtable. The write is persisted when the memtable is flushed
+    /// into a sstable, and is lost if the process crashes or the DB is closed before.
+    ///
+    /// A write with both `sync` and `disable_wal` is rejected with `Status::InvalidArgument`.
+    pub disable_wal: bool,
+}
+
+/// Options that control `WickDB::ingest_external_files`
+pub struct IngestExternalFileOptions {
+    /// If true, all the blocks of the external files are verified against
+    /// the checksums when they are read.
+    pub verify_checksums: bool,
+
+    /// If true, the memtable is flushed when it overlaps the key ranges of
+    /// the external files, which blocks the writes until the flush finishes.
+    /// If false, the ingestion fails with `Status::InvalidArgument` instead.
+    pub allow_blocking_flush: bool,
+}
+
+impl Default for IngestExternalFileOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: true,
+            allow_blocking_flush: true,
+        }
+    }
+}
+
+/// Options that control `WickDB::compact_range_with_options`
+#[derive(Clone, Default)]
+pub struct CompactRangeOptions {
+    /// If non-null, the bytes read and written by the compactions of the range
+    /// are accumulated into the given progress.
+    pub progress: Option<Arc<CompactionProgress>>,
+
+    /// If non-null, the compaction of the range is canceled once the flag is set,
+    /// and `compact_range_with_options` fails with `Status::Aborted`.
+    ///
+    /// The range is compacted by a series of compactions and each of them is
+    /// installed atomically. The one running at the cancellation stops and its
+    /// output files are discarded, so no partial output is ever visible. The ones
+    /// finished before are kept, which is consistent as well.
+    pub canceled: Option<Arc<AtomicBool>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_file_size_for_level() {
+        let opts = Options {
+            target_file_size_base: 100,
+            target_file_size_multiplier: 3,
+            ..Default::default()
+        };
+        let sizes: Vec<u64> = (0..4).map(|l| opts.max_file_size_for_level(l)).collect();
+        assert_eq!(sizes, vec![100, 100, 300, 900]);
+        assert_eq!(
+            Options::default().max_file_size_for_level(6),
+            Options::default().target_file_size_base
+        );
+    }
+
+    #[test]
+    fn test_compression_for_level() {
+        let opts = Options {
+            compression: ZstdCompression,
+            compression_per_level: vec![NoCompression, SnappyCompression],
+            ..Default::default()
+        };
+        assert_eq!(opts.compression_for_level(0), NoCompression);
+        assert_eq!(opts.compression_for_level(1), SnappyCompression);
+        for level in 2..opts.max_levels as usize {
+            assert_eq!(opts.compression_for_level(level), ZstdCompression);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        Options::default().validate().unwrap();
+        let tests: Vec<(Options, &str)> = vec![
+            (
+                Options {
+                    initial_sequence: MAX_KEY_SEQUENCE + 1,
+                    ..Default::default()
+                },
+                "initial_sequence",
+            ),
+            (
+                Options {
+                    max_levels: 0,
+                    ..Default::default()
+                },
+                "max_levels",
+            ),
+            (
+                Options {
+                    max_levels: 3,
+                    max_mem_compact_level: 3,
+                    ..Default::default()
+                },
+                "max_mem_compact_level",
+            ),
+            (
+                Options {
+                    max_levels: 2,
+                    compression_per_level: vec![NoCompression; 3],
+                    ..Default::default()
+                },
+                "compression_per_level",
+            ),
+            (
+                Options {
+                    compression: Custom(MIN_CUSTOM_COMPRESSION_TAG),
+                    ..Default::default()
+                },
+                "compressors",
+            ),
+            (
+                Options {
+                    block_restart_interval: 0,
+                    ..Default::default()
+                },
+                "block_restart_interval",
+            ),
+            (
+                Options {
+                    read_bytes_period: 0,
+                    ..Default::default()
+                },
+                "read_bytes_period",
+            ),
+            (
+                Options {
+                    max_bytes_for_level_base: 0,
+                    ..Default::default()
+                },
+                "max_bytes_for_level_base",
+            ),
+            (
+                Options {
+                    level0_slowdown_writes_trigger: 2,
+                    ..Default::default()
+                },
+                "level0 triggers",
+            ),
+            (
+                Options {
+                    index_type: IndexType::TwoLevel,
+                    index_partition_size: 0,
+                    ..Default::default()
+                },
+                "index_partition_size",
+            ),
+            (
+                Options {
+                    recycle_log_file_num: 1,
+                    wal_recovery_mode: WALRecoveryMode::AbsoluteConsistency,
+                    ..Default::default()
+                },
+                "recycle_log_file_num",
+            ),
+        ];
+        for (opts, field) in tests {
+            let e = opts.validate().unwrap_err();
+            assert_eq!(e.status(), Status::InvalidArgument);
+            assert!(e.msg().unwrap().contains(field), "{:?}", e.msg());
+        }
+    }
 }
@@ -19,8 +19,7 @@
 pub mod reader;
 pub mod writer;
 
-/// The max size of a log block
-// TODO: move this to the options
+/// The default size of a log block, which is always used by the MANIFEST
 pub const BLOCK_SIZE: usize = 32768;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -30,6 +29,37 @@ pub enum RecordType {
     First = 2,
     Middle = 3,
     Last = 4,
+    // the types of the records carrying the log number
+    RecyclableFull = 5,
+    RecyclableFirst = 6,
+    RecyclableMiddle = 7,
+    RecyclableLast = 8,
+}
+
+impl RecordType {
+    /// Returns the recyclable type of the record type
+    #[inline]
+    pub fn to_recyclable(self) -> Self {
+        match self {
+            RecordType::Full => RecordType::RecyclableFull,
+            RecordType::First => RecordType::RecyclableFirst,
+            RecordType::Middle => RecordType::RecyclableMiddle,
+            RecordType::Last => RecordType::RecyclableLast,
+            t => t,
+        }
+    }
+
+    /// Returns the legacy type of the record type
+    #[inline]
+    pub fn to_legacy(self) -> Self {
+        match self {
+            RecordType::RecyclableFull => RecordType::Full,
+            RecordType::RecyclableFirst => RecordType::First,
+            RecordType::RecyclableMiddle => RecordType::Middle,
+            RecordType::RecyclableLast => RecordType::Last,
+            t => t,
+        }
+    }
 }
 
 impl From<usize> for RecordType {
@@ -40,6 +70,10 @@ impl From<usize> for RecordType {
             2 => RecordType::First,
             3 => RecordType::Middle,
             4 => RecordType::Last,
+            5 => RecordType::RecyclableFull,
+            6 => RecordType::RecyclableFirst,
+            7 => RecordType::RecyclableMiddle,
+            8 => RecordType::RecyclableLast,
             _ => panic!("invalid RecordType: {}", v),
         }
     }
@@ -55,12 +89,25 @@ impl From<usize> for RecordType {
 /// ```
 pub const HEADER_SIZE: usize = 7;
 
+/// The format of a recyclable record header, which is used when the log files
+/// are recycled. The low 32 bits of the log number tell the records written
+/// into the file from the stale ones of the previous log in the same file:
+///
+/// ```text
+///
+/// | ----- 4bytes ----- | -- 2bytes -- | - 1byte - | --- 4bytes --- |
+///      CRC checksum         length     record type    log number
+///
+/// ```
+pub const RECYCLABLE_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
 #[cfg(test)]
 mod tests {
+    use crate::options::WALRecoveryMode;
     use crate::record::reader::{Reader, Reporter};
     use crate::record::writer::Writer;
-    use crate::record::RecordType::{First, Last, Middle};
-    use crate::record::{BLOCK_SIZE, HEADER_SIZE};
+    use crate::record::RecordType::{First, Full, Last, Middle};
+    use crate::record::{BLOCK_SIZE, HEADER_SIZE, RECYCLABLE_HEADER_SIZE};
     use crate::storage::File;
     use crate::util::coding::encode_fixed_32;
     use crate::util::crc32::{mask, value};
@@ -183,12 +230,15 @@ mod tests {
     #[derive(Clone)]
     struct ReportCollector {
         dropped_bytes: Rc<RefCell<u64>>,
+        // the offsets and sizes of the dropped data
+        drops: Rc<RefCell<Vec<(u64, u64)>>>,
         message: Rc<RefCell<String>>,
     }
 
     impl Reporter for ReportCollector {
-        fn corruption(&mut self, bytes: u64, reason: &str) {
+        fn corruption(&mut self, offset: u64, bytes: u64, reason: &str) {
             *self.dropped_bytes.borrow_mut() += bytes;
+            self.drops.borrow_mut().push((offset, bytes));
             self.message.borrow_mut().push_str(reason);
         }
     }
@@ -197,6 +247,7 @@ mod tests {
         pub fn new() -> Self {
             Self {
                 dropped_bytes: Rc::new(RefCell::new(0)),
+                drops: Rc::new(RefCell::new(vec![])),
                 message: Rc::new(RefCell::new(String::default())),
             }
         }
@@ -210,6 +261,7 @@ mod tests {
         reading: bool,
         reader: Reader,
         writer: Writer,
+        block_size: usize,
     }
     const INITIAL_OFFSET_RECORD_SIZES: [usize; 6] = [
         10000,
@@ -246,17 +298,76 @@ mod tests {
                     Some(Box::new(reporter.clone())),
                     true,
                     0,
+                    WALRecoveryMode::TolerateCorruptedTailRecords,
+                    BLOCK_SIZE,
+                    0,
                 ),
                 writer,
+                block_size: BLOCK_SIZE,
             }
         }
 
         // Replace the current writer with a new one created from the current StringFile
         pub fn reopen_for_append(&mut self) {
-            let writer = Writer::new(Box::new(StringFile::new(self.source.clone())));
+            let mut writer = Writer::with_config(
+                Box::new(StringFile::new(self.source.clone())),
+                self.block_size,
+                None,
+            );
+            writer.set_dest_length(self.written_bytes() as u64);
             self.writer = writer;
         }
 
+        // Writes and reads the records in the blocks of `block_size`
+        pub fn use_block_size(&mut self, block_size: usize) {
+            self.block_size = block_size;
+            self.writer = Writer::with_config(
+                Box::new(StringFile::new(self.source.clone())),
+                block_size,
+                None,
+            );
+            self.reader = Reader::new(
+                Box::new(self.read_source.clone()),
+                Some(Box::new(self.reporter.clone())),
+                true,
+                0,
+                WALRecoveryMode::TolerateCorruptedTailRecords,
+                block_size,
+                0,
+            )
+        }
+
+        // Returns the type of the physical record at `offset`
+        pub fn record_type(&self, offset: usize) -> u8 {
+            self.source.borrow()[offset + HEADER_SIZE - 1]
+        }
+
+        // Writes and reads the recyclable records of the log `log_number`
+        pub fn use_recyclable(&mut self, log_number: u64) {
+            self.writer = Writer::with_config(
+                Box::new(StringFile::new(self.source.clone())),
+                self.block_size,
+                Some(log_number),
+            );
+            self.reader = Reader::new(
+                Box::new(self.read_source.clone()),
+                Some(Box::new(self.reporter.clone())),
+                true,
+                0,
+                WALRecoveryMode::TolerateCorruptedTailRecords,
+                self.block_size,
+                log_number,
+            )
+        }
+
+        // Simulates overwriting the file `old` in place by the data written so far
+        pub fn overwrite(&mut self, old: &[u8]) {
+            let len = self.written_bytes();
+            if len < old.len() {
+                self.source.borrow_mut().extend_from_slice(&old[len..]);
+            }
+        }
+
         pub fn write(&mut self, msg: &str) {
             assert!(!self.reading, "cannot write() when some others are reading");
             self.writer
@@ -309,6 +420,10 @@ mod tests {
             *self.reporter.dropped_bytes.borrow()
         }
 
+        pub fn reported_drops(&self) -> Vec<(u64, u64)> {
+            self.reporter.drops.borrow().clone()
+        }
+
         pub fn reported_msg(&self) -> String {
             self.reporter.message.borrow().clone()
         }
@@ -335,6 +450,21 @@ mod tests {
                 Some(Box::new(self.reporter.clone())),
                 true,
                 initial_offset,
+                WALRecoveryMode::TolerateCorruptedTailRecords,
+                BLOCK_SIZE,
+                0,
+            )
+        }
+
+        pub fn set_recovery_mode(&mut self, mode: WALRecoveryMode) {
+            self.reader = Reader::new(
+                Box::new(self.read_source.clone()),
+                Some(Box::new(self.reporter.clone())),
+                true,
+                0,
+                mode,
+                BLOCK_SIZE,
+                0,
             )
         }
 
@@ -348,6 +478,9 @@ mod tests {
                 Some(Box::new(self.reporter.clone())),
                 true,
                 size + offset_past_end,
+                WALRecoveryMode::TolerateCorruptedTailRecords,
+                BLOCK_SIZE,
+                0,
             );
             let mut buf = vec![];
             assert!(!reader.read_record(&mut buf));
@@ -366,6 +499,9 @@ mod tests {
                 Some(Box::new(self.reporter.clone())),
                 true,
                 initial_offset,
+                WALRecoveryMode::TolerateCorruptedTailRecords,
+                BLOCK_SIZE,
+                0,
             );
             assert!(expected_record_index < INITIAL_OFFSET_LAST_RECORD_OFFSETS.len());
             let mut record = vec![];
@@ -505,6 +641,37 @@ mod tests {
         assert_eq!(EOF, log.read());
     }
 
+    #[test]
+    fn test_non_default_block_size() {
+        let block_size = 1024;
+        let mut log = new_record_test();
+        log.use_block_size(block_size);
+        let foo = big_string("foo", 3 * block_size);
+        log.write(foo.as_str());
+        log.write("bar");
+        // the appended records are aligned to the blocks as well
+        log.reopen_for_append();
+        let baz = big_string("baz", block_size - HEADER_SIZE);
+        log.write(baz.as_str());
+
+        // every block starts with a fragment of `foo`
+        for (i, t) in [First, Middle, Middle, Last].iter().enumerate() {
+            assert_eq!(log.record_type(i * block_size), *t as u8);
+        }
+        let last_fragment = foo.len() - 3 * (block_size - HEADER_SIZE);
+        let bar_offset = 3 * block_size + HEADER_SIZE + last_fragment;
+        assert_eq!(log.record_type(bar_offset), Full as u8);
+        // `baz` spans the boundary of the 4th block
+        assert_eq!(log.record_type(bar_offset + HEADER_SIZE + 3), First as u8);
+        assert_eq!(log.record_type(4 * block_size), Last as u8);
+
+        assert_eq!(foo, log.read());
+        assert_eq!("bar", log.read());
+        assert_eq!(baz, log.read());
+        assert_eq!(EOF, log.read());
+        assert_eq!(0, log.dropped_bytes());
+    }
+
     #[test]
     fn test_random_read() {
         let mut log = new_record_test();
@@ -533,7 +700,6 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "invalid RecordType: 101")]
     fn test_bad_record_type() {
         let mut log = new_record_test();
         let test = "foo";
@@ -541,7 +707,9 @@ mod tests {
         // the record type is in header[6]
         log.increment_byte(6, 100);
         log.fix_checksum(0, test.len());
-        log.read();
+        assert_eq!(EOF, log.read());
+        assert_eq!(3, log.dropped_bytes());
+        assert!(log.match_error("unknown record type"));
     }
 
     #[test]
@@ -555,6 +723,25 @@ mod tests {
         assert_eq!("", log.reported_msg());
     }
 
+    #[test]
+    fn test_truncated_tail_in_absolute_consistency() {
+        // (bytes to shrink, size of the record, reported message)
+        let cases = [
+            (4, 3, "truncated record header"),
+            (1, 3, "truncated record at the end of file"),
+            (14, BLOCK_SIZE, "partial record without end"),
+        ];
+        for (shrink, size, msg) in cases.iter() {
+            let mut log = new_record_test();
+            log.set_recovery_mode(WALRecoveryMode::AbsoluteConsistency);
+            log.write(big_string("foo", *size).as_str());
+            log.shrink_size(*shrink);
+            assert_eq!(EOF, log.read());
+            assert!(log.dropped_bytes() > 0);
+            assert!(log.match_error(msg), "{}", log.reported_msg());
+        }
+    }
+
     #[test]
     fn test_bad_record_length() {
         let mut log = new_record_test();
@@ -636,6 +823,110 @@ mod tests {
         assert!(log.match_error("partial record without end"));
     }
 
+    #[test]
+    fn test_reported_corruption_offsets() {
+        // every record is 10 bytes including the header
+        let mut log = new_record_test();
+        for r in ["foo", "bar", "baz"].iter() {
+            log.write(r);
+        }
+        log.increment_byte(10, 10);
+        assert_eq!("foo", log.read());
+        assert_eq!(EOF, log.read());
+        assert_eq!(vec![(10, 20)], log.reported_drops());
+        assert!(log.match_error("checksum mismatch"));
+
+        let mut log = new_record_test();
+        log.write("foo");
+        log.write(big_string("bar", BLOCK_SIZE - 2 * HEADER_SIZE - 3).as_str());
+        log.write("baz");
+        log.increment_byte(10 + 4, 1);
+        assert_eq!("foo", log.read());
+        assert_eq!("baz", log.read());
+        assert_eq!(vec![(10, BLOCK_SIZE as u64 - 10)], log.reported_drops());
+        assert!(log.match_error("bad record length"));
+
+        let mut log = new_record_test();
+        for r in ["foo", "bar", "baz"].iter() {
+            log.write(r);
+        }
+        log.set_byte(10 + 6, 9);
+        log.fix_checksum(10, 3);
+        assert_eq!("foo", log.read());
+        assert_eq!("baz", log.read());
+        assert_eq!(EOF, log.read());
+        assert_eq!(vec![(10, 3)], log.reported_drops());
+        assert!(log.match_error("unknown record type"));
+    }
+
+    #[test]
+    fn test_recyclable_records() {
+        let mut log = new_record_test();
+        log.use_recyclable(1);
+        // the rest of the first block is padded since it's less than the header
+        let first = big_string("foo", BLOCK_SIZE - 2 * RECYCLABLE_HEADER_SIZE + 3);
+        log.write(first.as_str());
+        log.write(big_string("bar", 3 * BLOCK_SIZE).as_str());
+        log.write("baz");
+        assert_eq!(first, log.read());
+        assert_eq!(big_string("bar", 3 * BLOCK_SIZE), log.read());
+        assert_eq!("baz", log.read());
+        assert_eq!(EOF, log.read());
+        assert_eq!(0, log.dropped_bytes());
+
+        // the log number is protected by the checksum
+        let mut log = new_record_test();
+        log.use_recyclable(1);
+        log.write("foo");
+        log.increment_byte(HEADER_SIZE, 1);
+        log.use_recyclable(2);
+        assert_eq!(EOF, log.read());
+    }
+
+    #[test]
+    fn test_stale_records_in_recycled_file() {
+        let old_records = [
+            "hello".to_owned(),
+            "world".to_owned(),
+            big_string("foo", 2 * BLOCK_SIZE),
+            "bar".to_owned(),
+        ];
+        let cases: Vec<(Option<u64>, Vec<String>)> = vec![
+            // stops at a valid record of the previous log
+            (Some(1), vec!["HELLO".to_owned()]),
+            // stops at the broken record in the middle of the previous one
+            (Some(1), vec!["hi".to_owned(), big_string("x", BLOCK_SIZE)]),
+            // stops at the legacy records
+            (None, vec!["HELLO".to_owned()]),
+            (None, vec!["hi".to_owned(), big_string("x", BLOCK_SIZE)]),
+        ];
+        for (old_log_number, new_records) in cases {
+            let mut log = new_record_test();
+            if let Some(n) = old_log_number {
+                log.use_recyclable(n);
+            }
+            for r in old_records.iter() {
+                log.write(r);
+            }
+            let old = log.source.borrow().clone();
+            log.source.borrow_mut().clear();
+            log.use_recyclable(2);
+            for r in new_records.iter() {
+                log.write(r);
+            }
+            log.overwrite(&old);
+            assert_eq!(log.written_bytes(), old.len());
+            for r in new_records.iter() {
+                assert_eq!(r, &log.read());
+            }
+            assert_eq!(EOF, log.read());
+            assert_eq!(EOF, log.read());
+            assert!(log.reader.tail_dropped());
+            assert_eq!(0, log.dropped_bytes());
+            assert_eq!("", log.reported_msg());
+        }
+    }
+
     #[test]
     fn test_missing_last_is_ignored() {
         let mut log = new_record_test();
@@ -15,18 +15,18 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::record::reader::ReaderError::{BadRecord, EOF};
-use crate::record::{RecordType, BLOCK_SIZE, HEADER_SIZE};
+use crate::options::WALRecoveryMode;
+use crate::record::reader::ReaderError::{BadRecord, Stale, EOF};
+use crate::record::{RecordType, HEADER_SIZE, RECYCLABLE_HEADER_SIZE};
 use crate::storage::File;
 use crate::util::coding::decode_fixed_32;
 use crate::util::crc32::{unmask, value};
-use std::error::Error;
 use std::io::SeekFrom;
 
 enum ReaderError {
     // * We have an internal reading file error
     // * We reaches the end of a log block
-    // * We get a record that larger than BLOCK_SIZE
+    // * We get a record that larger than the block size
     EOF,
     // Indicates that we find an invalid physical record.
     // Currently there are three situations in which this happens:
@@ -34,31 +34,56 @@ enum ReaderError {
     // * The record is a 0-length record (No drop is reported)
     // * The record is below constructor's initial_offset (No drop is reported)
     BadRecord,
+    // Indicates that the rest of the file is left by a previous log in a recycled
+    // file, which is found by a record with a different log number or any invalid
+    // record after the recyclable records (No drop is reported)
+    Stale,
 }
 
 // represent a record
 #[derive(Debug, Clone)]
 struct Record {
     t: RecordType,
+    // the offset of the physical record in the file
+    offset: u64,
     data: Vec<u8>,
 }
 
 /// Notified when log reader encounters corruption.
 pub trait Reporter {
-    /// Some corruption was detected.  "bytes" is the approximate number
-    /// of bytes dropped due to the corruption.
-    fn corruption(&mut self, bytes: u64, reason: &str);
+    /// Some corruption was detected. "offset" is the offset in the file where
+    /// the dropped data starts and "bytes" is the approximate number of bytes
+    /// dropped due to the corruption.
+    fn corruption(&mut self, offset: u64, bytes: u64, reason: &str);
 }
 
-/// A `Reader` is used for reading records from log file.
+/// A `Reader` is used for reading records from log file, which must be written in
+/// the blocks of `block_size`.
 /// The `Reader` always starts reading the records at `initial_offset` of the `file`.
+///
+/// An incomplete record at the end of the file is ignored silently unless
+/// `mode` is `WALRecoveryMode::AbsoluteConsistency`, in which case it's reported
+/// to the `reporter` as a corruption.
+///
+/// The records in the recyclable format are only read if they carry the low 32
+/// bits of `log_number`. Once such a record is found, the first invalid record
+/// is considered as the end of the log since the file might be recycled.
 pub struct Reader {
     // NOTICE: we probably mutate the underlying file in the FilePtr by calling `seek()` and this is not thread safe
     file: Box<dyn File>,
     reporter: Option<Box<dyn Reporter>>,
     // iff check sum for the record
     checksum: bool,
-    // Last Read() indicated EOF by returning < BLOCK_SIZE
+    mode: WALRecoveryMode,
+    // The size of a log block
+    block_size: usize,
+    // The number of the log expected in the recyclable records
+    log_number: u64,
+    // Whether a record in the recyclable format has been read
+    recycled: bool,
+    // Whether an incomplete record at the end of the file is dropped
+    tail_dropped: bool,
+    // Last Read() indicated EOF by returning < block_size
     eof: bool,
     // Offset of the last record returned by `read_record`.
     last_record_offset: u64,
@@ -81,12 +106,20 @@ impl Reader {
         reporter: Option<Box<dyn Reporter>>,
         checksum: bool,
         initial_offset: u64,
+        mode: WALRecoveryMode,
+        block_size: usize,
+        log_number: u64,
     ) -> Self {
         Reader {
             file,
             reporter,
             checksum,
-            buf: vec![0; BLOCK_SIZE],
+            mode,
+            block_size,
+            log_number,
+            recycled: false,
+            tail_dropped: false,
+            buf: vec![0; block_size],
             buf_length: 0,
             eof: false,
             last_record_offset: 0,
@@ -96,6 +129,15 @@ impl Reader {
         }
     }
 
+    /// Returns whether an incomplete record or the stale records of a recycled file
+    /// at the end of the file have been dropped.
+    /// Appending to the file is unsafe then since the record would be followed by
+    /// the new ones.
+    #[inline]
+    pub fn tail_dropped(&self) -> bool {
+        self.tail_dropped
+    }
+
     /// Deliver the file's ownership
     #[inline]
     pub fn into_file(self) -> Box<dyn File> {
@@ -127,14 +169,12 @@ impl Reader {
                     }
                     let fragment_size = record.data.len() as u64;
                     // the start offset of the current read record
-                    let physical_record_offset = self.end_of_buffer_offset
-                        - self.buf_length as u64
-                        - HEADER_SIZE as u64
-                        - fragment_size;
+                    let physical_record_offset = record.offset;
                     match record.t {
                         RecordType::Full => {
                             if in_fragmented_record {
                                 self.report_drop(
+                                    prospective_record_offset,
                                     buf.len() as u64,
                                     "partial record without end(1) for reading a new Full record",
                                 );
@@ -148,6 +188,7 @@ impl Reader {
                         RecordType::First => {
                             if in_fragmented_record {
                                 self.report_drop(
+                                    prospective_record_offset,
                                     buf.len() as u64,
                                     "partial record without end(2) for reading a new First record",
                                 );
@@ -162,6 +203,7 @@ impl Reader {
                         RecordType::Middle => {
                             if !in_fragmented_record {
                                 self.report_drop(
+                                    physical_record_offset,
                                     fragment_size,
                                     format!(
                                         "missing start of fragmented record({:?})",
@@ -177,6 +219,7 @@ impl Reader {
                         RecordType::Last => {
                             if !in_fragmented_record {
                                 self.report_drop(
+                                    physical_record_offset,
                                     fragment_size,
                                     format!(
                                         "missing start of fragmented record({:?})",
@@ -195,6 +238,7 @@ impl Reader {
                         RecordType::Zero => {
                             /* zero type record is considered as irrelevant and should never be read out*/
                         }
+                        _ => unreachable!("the recyclable types are converted to the legacy ones"),
                     }
                 }
                 Err(e) => {
@@ -205,13 +249,31 @@ impl Reader {
                                 // physical record but before completing the next
                                 // one; don't treat it as a corruption,
                                 // just ignore the entire logical record.
+                                self.report_truncation(
+                                    prospective_record_offset,
+                                    buf.len() as u64,
+                                    "partial record without end at the end of file",
+                                );
                                 buf.clear();
                             }
                             return false;
                         }
+                        ReaderError::Stale => {
+                            if in_fragmented_record {
+                                self.report_truncation(
+                                    prospective_record_offset,
+                                    buf.len() as u64,
+                                    "partial record without end before the stale records",
+                                );
+                                buf.clear();
+                            }
+                            self.tail_dropped = true;
+                            return false;
+                        }
                         ReaderError::BadRecord => {
                             if in_fragmented_record {
                                 self.report_drop(
+                                    prospective_record_offset,
                                     buf.len() as u64,
                                     "bad record read in middle of record",
                                 );
@@ -237,6 +299,7 @@ impl Reader {
         loop {
             // we've reached the end of a block and do not have a valid header
             if self.buf_length < HEADER_SIZE {
+                let remaining = self.buf_length;
                 self.clear_buf();
                 if !self.eof {
                     // try to read a block into the buf
@@ -244,12 +307,17 @@ impl Reader {
                         Ok(read) => {
                             self.end_of_buffer_offset += read as u64; // update the end offset here
                             self.buf_length = read;
-                            if read < BLOCK_SIZE as usize {
+                            if read < self.block_size {
                                 self.eof = true;
                             }
                         }
                         Err(e) => {
-                            self.report_drop(BLOCK_SIZE as u64, e.description());
+                            let offset = self.end_of_buffer_offset;
+                            self.report_drop(
+                                offset,
+                                self.block_size as u64,
+                                e.to_string().as_str(),
+                            );
                             self.eof = true;
                             return Err(ReaderError::EOF);
                         }
@@ -259,7 +327,15 @@ impl Reader {
                     // If buffer is non-empty, it means we have a truncated header at the
                     // end of the file, which may be caused by writer
                     // crashing in the middle of writing the header.
-                    // Instead of considering this an error, just report EOF.
+                    // Instead of considering this an error, just report EOF unless the
+                    // absolute consistency is required.
+                    if remaining > 0 {
+                        self.report_truncation(
+                            self.end_of_buffer_offset - remaining as u64,
+                            remaining as u64,
+                            "truncated record header at the end of file",
+                        );
+                    }
                     return Err(ReaderError::EOF);
                 }
             }
@@ -268,25 +344,48 @@ impl Reader {
             let record_type = *header.last().unwrap();
             let data_length =
                 ((header[4] as usize & 0xff) | ((header[5] as usize & 0xff) << 8)) as usize;
-            let record_length = HEADER_SIZE + data_length;
+            let is_recyclable = record_type >= RecordType::RecyclableFull as u8
+                && record_type <= RecordType::RecyclableLast as u8;
+            let header_size = if is_recyclable {
+                self.recycled = true;
+                RECYCLABLE_HEADER_SIZE
+            } else {
+                HEADER_SIZE
+            };
+            let record_length = header_size + data_length;
+            // the offset of the current physical record
+            let offset = self.end_of_buffer_offset - self.buf_length as u64;
             // a record must be included in one block
             if record_length > self.buf_length {
                 let drop_size = self.buf_length;
                 self.clear_buf();
                 if !self.eof {
-                    self.report_drop(drop_size as u64, "bad record length");
+                    if self.recycled {
+                        return Err(self.stale());
+                    }
+                    self.report_drop(offset, drop_size as u64, "bad record length");
                     return Err(BadRecord);
                 }
                 // If the end of the file has been reached without reading |length| bytes
                 // of payload, assume the writer died in the middle of writing the record.
-                // Don't report a corruption.
+                // Don't report a corruption unless the absolute consistency is required.
+                self.report_truncation(
+                    offset,
+                    drop_size as u64,
+                    "truncated record at the end of file",
+                );
                 return Err(EOF);
             }
 
             // handling empty record generated by mmap
             if record_type == 0 && data_length == 0 {
                 self.clear_buf();
-                self.report_drop(self.buf.len() as u64, "empty length record");
+                if self.recycled {
+                    // the trailer of a block is padded with zero for the recyclable
+                    // header, which is longer than `HEADER_SIZE`
+                    continue;
+                }
+                self.report_drop(offset, self.buf.len() as u64, "empty length record");
                 return Err(BadRecord);
             }
 
@@ -298,7 +397,10 @@ impl Reader {
                 if expected != actual {
                     let drop_size = self.buf_length;
                     self.clear_buf();
-                    self.report_drop(drop_size as u64, "checksum mismatch");
+                    if self.recycled {
+                        return Err(self.stale());
+                    }
+                    self.report_drop(offset, drop_size as u64, "checksum mismatch");
                     return Err(BadRecord);
                 }
             }
@@ -313,50 +415,88 @@ impl Reader {
                 return Err(BadRecord);
             }
 
+            if record_type as usize > RecordType::RecyclableLast as usize {
+                if self.recycled {
+                    return Err(self.stale());
+                }
+                self.report_drop(
+                    offset,
+                    data_length as u64,
+                    format!("unknown record type {}", record_type).as_str(),
+                );
+                return Err(BadRecord);
+            }
+
+            if is_recyclable {
+                // the record of a previous log in the recycled file
+                if decode_fixed_32(&data[HEADER_SIZE..]) != self.log_number as u32 {
+                    return Err(self.stale());
+                }
+            } else if self.recycled && record_type != RecordType::Zero as u8 {
+                // a legacy record after the recyclable ones is stale as well
+                return Err(self.stale());
+            }
+
             // drop the head part
-            data.drain(0..HEADER_SIZE);
+            data.drain(0..header_size);
             return Ok(Record {
-                // TODO: avoid panic when we read a invalid record type
-                t: RecordType::from(record_type as usize),
+                t: RecordType::from(record_type as usize).to_legacy(),
+                offset,
                 data,
             });
         }
     }
 
-    // report record dropping to the `reporter`
-    fn report_drop(&mut self, bytes: u64, reason: &str) {
+    // report record dropping at `offset` to the `reporter`
+    fn report_drop(&mut self, offset: u64, bytes: u64, reason: &str) {
         if let Some(reporter) = self.reporter.as_mut() {
             // make sure the bytes not overflows 'the initial_offset'
             // and a special case is that we got a read error when we first read a block
             if self.end_of_buffer_offset == 0
                 || self.end_of_buffer_offset - bytes >= self.initial_offset
             {
-                reporter.corruption(bytes, reason);
+                reporter.corruption(offset, bytes, reason);
             }
         }
     }
 
+    // Reports the incomplete record at the end of the file, which is only a
+    // corruption in `WALRecoveryMode::AbsoluteConsistency`
+    fn report_truncation(&mut self, offset: u64, bytes: u64, reason: &str) {
+        self.tail_dropped = true;
+        if self.mode == WALRecoveryMode::AbsoluteConsistency {
+            self.report_drop(offset, bytes, reason)
+        }
+    }
+
+    // Stops reading the rest of the file, which is stale
+    fn stale(&mut self) -> ReaderError {
+        self.clear_buf();
+        self.eof = true;
+        Stale
+    }
+
     // clear `buf` and reset `buf_length`
     fn clear_buf(&mut self) {
-        self.buf = vec![0; BLOCK_SIZE];
+        self.buf = vec![0; self.block_size];
         self.buf_length = 0;
     }
 
     /// Skips all blocks that are completely before `initial_offset`
     /// Returns true on success. Handles reporting.
     fn skip_to_initial_block(&mut self) -> bool {
-        let offset_in_block = self.initial_offset % BLOCK_SIZE as u64;
+        let block_size = self.block_size as u64;
+        let offset_in_block = self.initial_offset % block_size;
         let mut block_start_location = self.initial_offset - offset_in_block;
 
         // skip to next block starting if we'd be in the trailer
-        if offset_in_block > BLOCK_SIZE as u64 - 6 {
-            block_start_location += BLOCK_SIZE as u64;
+        if offset_in_block > block_size - 6 {
+            block_start_location += block_size;
         }
         self.end_of_buffer_offset = block_start_location;
         if block_start_location > 0 {
-            let res = self.file.seek(SeekFrom::Start(block_start_location));
-            if res.is_err() {
-                self.report_drop(block_start_location, res.unwrap_err().description());
+            if let Err(e) = self.file.seek(SeekFrom::Start(block_start_location)) {
+                self.report_drop(0, block_start_location, e.to_string().as_str());
                 return false;
             }
         }
@@ -15,7 +15,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::record::{RecordType, BLOCK_SIZE, HEADER_SIZE};
+use crate::record::{RecordType, BLOCK_SIZE, HEADER_SIZE, RECYCLABLE_HEADER_SIZE};
 use crate::storage::File;
 use crate::util::coding::encode_fixed_32;
 use crate::util::crc32;
@@ -25,59 +25,81 @@ use crate::util::status::Result;
 /// Writer writes records to an underlying log `File`.
 pub struct Writer {
     dest: Box<dyn File>,
+    // The size of a log block
+    block_size: usize,
     //Current offset in block
     block_offset: usize,
+    // The log number written into the recyclable records
+    log_number: Option<u32>,
     // crc32c values for all supported record types.  These are
     // pre-computed to reduce the overhead of computing the crc of the
     // record type stored in the header.
-    crc_cache: [u32; (RecordType::Last as usize + 1) as usize],
+    crc_cache: [u32; RecordType::RecyclableLast as usize + 1],
 }
 
 impl Writer {
     pub fn new(dest: Box<dyn File>) -> Self {
-        let n = RecordType::Last as usize;
-        let mut cache = [0; RecordType::Last as usize + 1];
+        Self::with_config(dest, BLOCK_SIZE, None)
+    }
+
+    /// Creates a `Writer` splitting the records into the blocks of `block_size`.
+    /// If `log_number` is given, the records are written in the recyclable format
+    /// with the low 32 bits of it, so that the file is able to be recycled later.
+    pub fn with_config(dest: Box<dyn File>, block_size: usize, log_number: Option<u64>) -> Self {
+        let n = RecordType::RecyclableLast as usize;
+        let mut cache = [0; RecordType::RecyclableLast as usize + 1];
         for h in 1..=n {
             let v: [u8; 1] = [RecordType::from(h) as u8];
             cache[h as usize] = crc32::value(&v);
         }
         Self {
             dest,
+            block_size,
             block_offset: 0,
+            log_number: log_number.map(|n| n as u32),
             crc_cache: cache,
         }
     }
 
+    /// Sets the length of the existing data in the file, after which the records
+    /// are appended, so that the new records are aligned to the blocks as well
+    #[inline]
+    pub fn set_dest_length(&mut self, length: u64) {
+        self.block_offset = (length % self.block_size as u64) as usize;
+    }
+
     /// Appends a slice into the underlying log file
     pub fn add_record(&mut self, s: &Slice) -> Result<()> {
         let data = s.as_slice();
+        let header_size = self.header_size();
         let mut left = s.size();
         let mut begin = true; // indicate iff the record is a First or Middle record
         while {
             assert!(
-                BLOCK_SIZE >= self.block_offset,
-                "[record writer] the 'block_offset' {} overflows the max BLOCK_SIZE {}",
+                self.block_size >= self.block_offset,
+                "[record writer] the 'block_offset' {} overflows the block size {}",
                 self.block_offset,
-                BLOCK_SIZE,
+                self.block_size,
             );
-            let leftover = BLOCK_SIZE - self.block_offset;
+            let leftover = self.block_size - self.block_offset;
 
             // switch to a new block if the left size is not enough
             // for a record header
-            if leftover < HEADER_SIZE {
+            if leftover < header_size {
                 if leftover != 0 {
                     // fill the rest of the block with zero
-                    self.dest.write(&[0; 6][..leftover])?;
+                    self.dest
+                        .write(&[0; RECYCLABLE_HEADER_SIZE - 1][..leftover])?;
                 }
                 self.block_offset = 0; // use a new block
             };
             assert!(
-                BLOCK_SIZE >= self.block_offset + HEADER_SIZE,
+                self.block_size >= self.block_offset + header_size,
                 "[record writer] the left space of block {} is less than header size {}",
-                BLOCK_SIZE - self.block_offset,
-                HEADER_SIZE,
+                self.block_size - self.block_offset,
+                header_size,
             );
-            let space = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let space = self.block_size - self.block_offset - header_size;
             let to_write = if left < space { left } else { space };
             // indicates iff the data exhausts a record
             let end = to_write == left;
@@ -108,37 +130,52 @@ impl Writer {
         self.dest.flush()
     }
 
+    #[inline]
+    fn header_size(&self) -> usize {
+        if self.log_number.is_some() {
+            RECYCLABLE_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        }
+    }
+
     // create formatted bytes and write into the file
-    fn write(&mut self, rt: RecordType, data: &[u8]) -> Result<()> {
+    fn write(&mut self, mut rt: RecordType, data: &[u8]) -> Result<()> {
         let size = data.len();
+        let header_size = self.header_size();
         assert!(
             size <= 0xffff,
             "[record writer] the data length in a record must fit 2 bytes but got {}",
             size
         );
         assert!(
-            self.block_offset + HEADER_SIZE + size <= BLOCK_SIZE,
-            "[record writer] new record [{:?}] overflows the BLOCK_SIZE [{}]",
+            self.block_offset + header_size + size <= self.block_size,
+            "[record writer] new record [{:?}] overflows the block size [{}]",
             rt,
-            BLOCK_SIZE,
+            self.block_size,
         );
         // encode header
-        let mut buf: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
+        let mut buf: [u8; RECYCLABLE_HEADER_SIZE] = [0; RECYCLABLE_HEADER_SIZE];
         buf[4] = (size & 0xff) as u8; // data length
         buf[5] = (size >> 8) as u8;
+        if let Some(log_number) = self.log_number {
+            rt = rt.to_recyclable();
+            encode_fixed_32(&mut buf[HEADER_SIZE..], log_number);
+        }
         buf[6] = rt as u8; // record type
 
-        // encode crc
-        let mut crc = crc32::extend(self.crc_cache[rt as usize], data);
+        // encode crc of the record type, the log number and the data
+        let mut crc = crc32::extend(self.crc_cache[rt as usize], &buf[HEADER_SIZE..header_size]);
+        crc = crc32::extend(crc, data);
         crc = crc32::mask(crc);
         encode_fixed_32(&mut buf, crc);
 
         // write the header and the data
-        self.dest.write(&buf)?;
+        self.dest.write(&buf[..header_size])?;
         self.dest.write(data)?;
         self.dest.flush()?;
         // update block_offset
-        self.block_offset += HEADER_SIZE + size;
+        self.block_offset += header_size + size;
         Ok(())
     }
 }
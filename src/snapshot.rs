@@ -16,23 +16,80 @@
 // found in the LICENSE file.
 
 use std::collections::vec_deque::VecDeque;
+use std::ops::Deref;
 use std::sync::Arc;
 
 /// Abstract handle to particular state of a DB.
 /// A `Snapshot` is an immutable object and can therefore be safely
 /// accessed from multiple threads without any external synchronization.
+#[derive(Clone)]
 pub struct Snapshot {
     // The sequence number pointing to the view of db
     sequence_number: u64,
 }
 
 impl Snapshot {
+    /// Returns the sequence number of the view. The entries written with a sequence
+    /// number not larger than it are visible to the snapshot.
     #[inline]
-    pub fn sequence(&self) -> u64 {
+    pub fn sequence_number(&self) -> u64 {
         self.sequence_number
     }
 }
 
+/// A `SnapshotHandle` is a RAII guard of a `Snapshot` acquired from a DB.
+/// The snapshot is released when the handle is dropped or `release` is called,
+/// whichever comes first, so releasing a handle more than once is harmless.
+///
+/// A released snapshot no longer prevents the entries visible to it from being
+/// compacted away, so the reads by it after the release might see newer data.
+pub struct SnapshotHandle {
+    snapshot: Snapshot,
+    // The reference tracked by the `SnapshotList`
+    pinned: Option<Arc<Snapshot>>,
+}
+
+impl SnapshotHandle {
+    pub(crate) fn new(snapshot: Arc<Snapshot>) -> Self {
+        Self {
+            snapshot: (*snapshot).clone(),
+            pinned: Some(snapshot),
+        }
+    }
+
+    /// Returns the `Snapshot` which could be set in `ReadOptions`
+    #[inline]
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.clone()
+    }
+
+    /// Releases the snapshot. Does nothing if it's already released.
+    #[inline]
+    pub fn release(&mut self) {
+        self.pinned.take();
+    }
+
+    /// Returns whether the snapshot is released
+    #[inline]
+    pub fn is_released(&self) -> bool {
+        self.pinned.is_none()
+    }
+}
+
+impl Deref for SnapshotHandle {
+    type Target = Snapshot;
+
+    fn deref(&self) -> &Snapshot {
+        &self.snapshot
+    }
+}
+
+impl Drop for SnapshotHandle {
+    fn drop(&mut self) {
+        self.release()
+    }
+}
+
 /// Different from the C++ implementation,  a VecDequeue is handled for the SnapshotList because
 /// a safe double-linked circular list implementation in Rust is tough and not worth it.
 /// Although Rust provides a standard double linked list, use a array based containers are faster.
@@ -61,7 +118,6 @@ impl SnapshotList {
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub fn newest(&self) -> Arc<Snapshot> {
         assert!(!self.is_empty());
         self.snapshots.back().unwrap().clone()
@@ -99,7 +155,7 @@ impl SnapshotList {
 
 #[cfg(test)]
 mod tests {
-    use crate::snapshot::SnapshotList;
+    use crate::snapshot::{SnapshotHandle, SnapshotList};
 
     #[test]
     pub fn test_new_is_empty() {
@@ -127,9 +183,29 @@ mod tests {
         let mut s = SnapshotList::new();
         for i in [1, 1, 2, 3].iter() {
             let s = s.snapshot(*i);
-            assert_eq!(s.sequence(), *i);
+            assert_eq!(s.sequence_number(), *i);
         }
-        assert_eq!(1, s.oldest().sequence());
-        assert_eq!(3, s.newest().sequence());
+        assert_eq!(1, s.oldest().sequence_number());
+        assert_eq!(3, s.newest().sequence_number());
+    }
+
+    #[test]
+    pub fn test_release_snapshot_handle() {
+        fn assert_send<T: Send>(_: &T) {}
+        let mut s = SnapshotList::new();
+        let mut h1 = SnapshotHandle::new(s.snapshot(1));
+        let h2 = SnapshotHandle::new(s.snapshot(2));
+        assert_send(&h2);
+        h1.release();
+        assert!(h1.is_released());
+        // releasing twice is a no-op
+        h1.release();
+        assert_eq!(h1.sequence_number(), 1);
+        s.gc();
+        assert_eq!(2, s.oldest().sequence_number());
+        std::thread::spawn(move || drop(h2)).join().unwrap();
+        drop(h1);
+        s.gc();
+        assert!(s.is_empty());
     }
 }
@@ -15,14 +15,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use crate::iterator::Iterator;
+use crate::iterator::{EmptyIterator, Iterator};
 use crate::util::coding::{decode_fixed_32, put_fixed_32};
 use crate::util::comparator::Comparator;
+use crate::util::crc32::extend;
 use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
 use crate::util::varint::VarintU32;
 use std::cmp::{min, Ordering};
-use std::rc::Rc;
 use std::sync::Arc;
 
 /// `Block` is consist of one or more key/value entries and a block trailer.
@@ -42,9 +42,11 @@ use std::sync::Arc;
 ///
 #[derive(Clone, Debug)]
 pub struct Block {
-    data: Rc<Vec<u8>>,
+    data: Arc<Vec<u8>>,
     // offset in data of restart array
     restart_offset: u32,
+    // whether the checksum of the block has been verified when it's read from the file
+    checksum_verified: bool,
 }
 
 impl Block {
@@ -62,8 +64,9 @@ impl Block {
             // make sure the size is enough for restarts
             if restarts_len <= max_restarts_allowed {
                 return Ok(Self {
-                    data: Rc::new(data),
+                    data: Arc::new(data),
                     restart_offset: (size - (1 + restarts_len) * 4) as u32,
+                    checksum_verified: false,
                 });
             }
         };
@@ -73,9 +76,43 @@ impl Block {
         ))
     }
 
+    /// Creates a `Block` holding the raw `data` which is not in the block format,
+    /// such as the contents of a filter block, so that it could be stored in the
+    /// block cache. A raw block must never be iterated.
+    pub(crate) fn raw(data: Vec<u8>) -> Self {
+        Self {
+            data: Arc::new(data),
+            restart_offset: 0,
+            checksum_verified: false,
+        }
+    }
+
+    /// Marks whether the checksum of the block has been verified
+    #[inline]
+    pub(crate) fn with_checksum_verified(mut self, verified: bool) -> Self {
+        self.checksum_verified = verified;
+        self
+    }
+
+    /// Returns whether the checksum of the block has been verified when it's read
+    /// from the file
+    #[inline]
+    pub fn checksum_verified(&self) -> bool {
+        self.checksum_verified
+    }
+
+    /// Returns the contents of the block
+    #[inline]
+    pub(crate) fn data(&self) -> Arc<Vec<u8>> {
+        self.data.clone()
+    }
+
     /// Create a BlockIterator for current block.
     pub fn iter(&self, cmp: Arc<dyn Comparator>) -> Box<dyn Iterator> {
-        let num_restarts = Self::restarts_len(self.data.as_slice());
+        let num_restarts = self.num_restarts();
+        if num_restarts == 0 {
+            return Box::new(EmptyIterator::new());
+        }
         Box::new(BlockIterator::new(
             cmp,
             self.data.clone(),
@@ -84,6 +121,15 @@ impl Block {
         ))
     }
 
+    /// Returns the number of the restart points in the block
+    #[inline]
+    pub(crate) fn num_restarts(&self) -> u32 {
+        if self.data.len() < 4 {
+            return 0;
+        }
+        Self::restarts_len(self.data.as_slice())
+    }
+
     // decoded the restarts length from block data
     #[inline]
     fn restarts_len(data: &[u8]) -> u32 {
@@ -95,8 +141,9 @@ impl Block {
 impl Default for Block {
     fn default() -> Self {
         Self {
-            data: Rc::new(vec![]),
+            data: Arc::new(vec![]),
             restart_offset: 0,
+            checksum_verified: false,
         }
     }
 }
@@ -108,7 +155,7 @@ pub struct BlockIterator {
     err: Option<WickErr>,
     // underlying block data
     // should never be modified in iterator
-    data: Rc<Vec<u8>>,
+    data: Arc<Vec<u8>>,
     /*
       restarts
     */
@@ -130,16 +177,21 @@ pub struct BlockIterator {
 }
 
 impl BlockIterator {
-    pub fn new(cmp: Arc<Comparator>, data: Rc<Vec<u8>>, restarts: u32, restarts_len: u32) -> Self {
-        // should be 0
+    pub fn new(
+        cmp: Arc<dyn Comparator>,
+        data: Arc<Vec<u8>>,
+        restarts: u32,
+        restarts_len: u32,
+    ) -> Self {
+        // the iterator is invalid until it is positioned
         Self {
             cmp,
             err: None,
             data,
             restarts,
             restarts_len,
-            restart_index: 0,
-            current: 0,
+            restart_index: restarts_len,
+            current: restarts,
             shared: 0,
             not_shared: 0,
             value_len: 0,
@@ -151,7 +203,7 @@ impl BlockIterator {
     // return the offset in data just past the end of the current entry
     #[inline]
     fn next_entry_offset(&self) -> u32 {
-        self.key_offset + self.shared + self.not_shared + self.value_len
+        self.key_offset + self.not_shared + self.value_len
     }
 
     #[inline]
@@ -165,46 +217,64 @@ impl BlockIterator {
         self.current = self.get_restart_point(index);
     }
 
+    // Decodes the header of the entry at `offset`.
+    // Returns `(shared, not_shared, value_len, header_len)`
+    fn decode_entry_header(&self, offset: u32) -> Option<(u32, u32, u32, u32)> {
+        let src = &self.data[offset as usize..self.restarts as usize];
+        let mut cursor = src;
+        let shared = VarintU32::decode_from(&mut cursor)?;
+        let not_shared = VarintU32::decode_from(&mut cursor)?;
+        let value_len = VarintU32::decode_from(&mut cursor)?;
+        let n = (src.len() - cursor.len()) as u32;
+        if u64::from(offset) + u64::from(n) + u64::from(not_shared) + u64::from(value_len)
+            > u64::from(self.restarts)
+        {
+            return None;
+        }
+        Some((shared, not_shared, value_len, n))
+    }
+
     // decodes a block entry from `current`
     // mark as corrupted when the current entry tail overflows the starting offset of restarts
     fn parse_block_entry(&mut self) -> bool {
         let offset = self.current;
-        let src = &self.data[offset as usize..];
-        let (shared, n0) = VarintU32::common_read(src);
-        let (not_shared, n1) = VarintU32::common_read(&src[n0 as usize..]);
-        let (value_len, n2) = VarintU32::common_read(&src[(n1 + n0) as usize..]);
-        let n = (n0 + n1 + n2) as u32;
-        //
-        if offset + n + shared + not_shared + value_len > self.restarts {
-            self.corruption_err();
+        if offset >= self.restarts {
+            // No more entries to return. Mark as invalid.
+            self.current = self.restarts;
+            self.restart_index = self.restarts_len;
             return false;
         }
-        self.key_offset = self.current + n;
-        self.shared = shared;
-        self.not_shared = not_shared;
-        self.value_len = value_len;
-        let key_len = (shared + not_shared) as usize;
-        // update current key
-        self.key.resize(key_len, 0);
-        let delta = &src[self.key_offset as usize..self.key_offset as usize + key_len];
-        for i in shared as usize..self.key.len() {
-            self.key[i] = delta[i - shared as usize]
-        }
-        // update restart index
-        if shared == 0
-            && self.restart_index + 1 < self.restarts_len
-            && self.get_restart_point(self.restart_index + 1) == self.current
-        {
-            self.restart_index += 1
+        match self.decode_entry_header(offset) {
+            Some((shared, not_shared, value_len, n)) if shared as usize <= self.key.len() => {
+                self.key_offset = offset + n;
+                self.shared = shared;
+                self.not_shared = not_shared;
+                self.value_len = value_len;
+                // update current key
+                let delta =
+                    &self.data[self.key_offset as usize..(self.key_offset + not_shared) as usize];
+                self.key.truncate(shared as usize);
+                self.key.extend_from_slice(delta);
+                // update restart index
+                while self.restart_index + 1 < self.restarts_len
+                    && self.get_restart_point(self.restart_index + 1) < self.current
+                {
+                    self.restart_index += 1
+                }
+                true
+            }
+            _ => {
+                self.corruption_err();
+                false
+            }
         }
-        true
     }
 
     #[inline]
     fn corruption_err(&mut self) {
         self.err = Some(WickErr::new(Status::Corruption, Some("bad entry in block")));
         self.key.clear();
-        self.current = 0;
+        self.current = self.restarts;
         self.restart_index = self.restarts_len
     }
 
@@ -228,14 +298,15 @@ impl Iterator for BlockIterator {
 
     fn seek_to_first(&mut self) {
         self.seek_to_restart_point(0);
+        self.parse_block_entry();
     }
 
     fn seek_to_last(&mut self) {
-        // seek to the last
+        // seek to the last restart point and keep parsing to the last entry
         self.seek_to_restart_point(self.restarts_len - 1);
-        // keep parsing block
-        // TODO: the buffered key cost a lot waste here
-        while self.parse_block_entry() && self.next_entry_offset() < self.restarts {}
+        while self.parse_block_entry() && self.next_entry_offset() < self.restarts {
+            self.current = self.next_entry_offset();
+        }
     }
 
     // find the first entry in block with key>= target
@@ -246,26 +317,26 @@ impl Iterator for BlockIterator {
         while left < right {
             let mid = (left + right + 1) / 2;
             let region_offset = self.get_restart_point(mid);
-            let src = &self.data[region_offset as usize..];
-            let (shared, n0) = VarintU32::common_read(src);
-            let (not_shared, n1) = VarintU32::common_read(&src[n0 as usize..]);
-            let (_, n2) = VarintU32::common_read(&src[(n1 + n0) as usize..]);
-            if shared != 0 {
-                self.corruption_err();
-                return;
-            }
-            let key_offset = region_offset + (n0 + n1 + n2) as u32;
-            let key_len = (shared + not_shared) as usize;
-            let mid_key = &src[key_offset as usize..key_offset as usize + key_len];
-            match self.cmp.compare(&mid_key, target.as_slice()) {
-                Ordering::Less => left = mid,
-                _ => right = mid - 1,
+            match self.decode_entry_header(region_offset) {
+                Some((0, not_shared, _, n)) => {
+                    let key_offset = (region_offset + n) as usize;
+                    let mid_key = &self.data[key_offset..key_offset + not_shared as usize];
+                    match self.cmp.compare(mid_key, target.as_slice()) {
+                        Ordering::Less => left = mid,
+                        _ => right = mid - 1,
+                    }
+                }
+                // the key at a restart point should never share bytes
+                _ => {
+                    self.corruption_err();
+                    return;
+                }
             }
         }
 
         // linear search (with restart block) for first key >= target
         // if all the keys > target, we seek to the start
-        // if all the keys < target, we seek to the last
+        // if all the keys < target, the iterator becomes invalid
         self.seek_to_restart_point(left);
         loop {
             if !self.parse_block_entry() {
@@ -286,10 +357,25 @@ impl Iterator for BlockIterator {
         self.parse_block_entry();
     }
 
-    // seek to prev restart offset and scan backwards to a restart point before current
+    // Since the keys are prefix-compressed, the previous entry can't be decoded directly.
+    // Seek to the last restart point before the current entry and scan forwards
+    // until reaching the entry just before the current one.
     fn prev(&mut self) {
+        self.valid_or_panic();
         let original = self.current;
-        while self.get_restart_point(self.restart_index) >= original {}
+        while self.get_restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                // No more entries. Mark as invalid.
+                self.current = self.restarts;
+                self.restart_index = self.restarts_len;
+                return;
+            }
+            self.restart_index -= 1;
+        }
+        self.seek_to_restart_point(self.restart_index);
+        while self.parse_block_entry() && self.next_entry_offset() < original {
+            self.current = self.next_entry_offset();
+        }
     }
 
     fn key(&self) -> Slice {
@@ -333,6 +419,9 @@ pub struct BlockBuilder {
     counter: usize,
     finished: bool,
     last_key: Vec<u8>,
+    // the CRC-32C of the entries in `buffer`, which is updated as the entries
+    // are added so that the checksum of a large block is cheap at the end
+    crc: u32,
 }
 
 impl BlockBuilder {
@@ -350,6 +439,7 @@ impl BlockBuilder {
             counter: 0,
             restarts: vec![0; 1], //first restart point is at offset 0
             last_key: vec![],
+            crc: 0,
         }
     }
 
@@ -358,6 +448,17 @@ impl BlockBuilder {
         self.buffer.len() + self.restarts.len() * 4 + 4
     }
 
+    /// Returns the unmasked CRC-32C of the block data returned by `finish`, which
+    /// could be called before or after `finish`
+    pub fn checksum(&self) -> u32 {
+        let mut restarts = Vec::with_capacity(self.restarts.len() * 4 + 4);
+        for restart in self.restarts.iter() {
+            put_fixed_32(&mut restarts, *restart)
+        }
+        put_fixed_32(&mut restarts, self.restarts.len() as u32);
+        extend(self.crc, restarts.as_slice())
+    }
+
     /// Appends the block restarts metadata and returns the block data
     pub fn finish(&mut self) -> &[u8] {
         for restart in self.restarts.iter() {
@@ -403,6 +504,7 @@ impl BlockBuilder {
             self.counter = 0;
         }
         let non_shared = key.len() - shared;
+        let start = self.buffer.len();
 
         // | --- shared --- | --- non_shared --- | --- value length --- |
         VarintU32::put_varint(&mut self.buffer, shared as u32);
@@ -411,6 +513,7 @@ impl BlockBuilder {
         // append delta key and value
         self.buffer.extend_from_slice(&key[shared..]);
         self.buffer.extend_from_slice(value);
+        self.crc = extend(self.crc, &self.buffer[start..]);
         // update last_key
         self.last_key.clear();
         self.last_key.extend_from_slice(key);
@@ -424,6 +527,12 @@ impl BlockBuilder {
         self.buffer.is_empty()
     }
 
+    /// Returns the last key added since the last `reset()`
+    #[inline]
+    pub fn last_key(&self) -> &[u8] {
+        self.last_key.as_slice()
+    }
+
     /// Reset the current BlockBuilder if it is finished
     ///
     /// # Panic
@@ -441,5 +550,124 @@ impl BlockBuilder {
         self.counter = 0;
         self.restarts = vec![0; 1];
         self.last_key.clear();
+        self.crc = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sstable::block::{Block, BlockBuilder};
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::slice::Slice;
+    use crate::util::status::Status;
+    use std::sync::Arc;
+
+    fn new_test_block(keys: &[String], restart_interval: usize) -> Block {
+        let mut builder = BlockBuilder::new(restart_interval, Arc::new(BytewiseComparator::new()));
+        for key in keys.iter() {
+            builder.add(key.as_bytes(), key.as_bytes());
+        }
+        Block::new(Vec::from(builder.finish())).expect("block should be valid")
+    }
+
+    fn test_keys() -> Vec<String> {
+        (0..20).map(|i| format!("key{:03}", i * 2)).collect()
+    }
+
+    #[test]
+    fn test_block_iter_forward_and_reverse() {
+        let keys = test_keys();
+        for restart_interval in 1..5 {
+            let block = new_test_block(&keys, restart_interval);
+            let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+            assert!(!iter.valid());
+            iter.seek_to_first();
+            let mut forward = vec![];
+            while iter.valid() {
+                assert_eq!(iter.key(), iter.value());
+                forward.push(iter.key().as_str().to_owned());
+                iter.next();
+            }
+            assert_eq!(forward, keys);
+
+            iter.seek_to_last();
+            let mut backward = vec![];
+            while iter.valid() {
+                assert_eq!(iter.key(), iter.value());
+                backward.push(iter.key().as_str().to_owned());
+                iter.prev();
+            }
+            backward.reverse();
+            assert_eq!(backward, keys);
+        }
+    }
+
+    #[test]
+    fn test_block_iter_seek() {
+        let keys = test_keys();
+        let block = new_test_block(&keys, 3);
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        // exact match
+        iter.seek(&Slice::from("key010"));
+        assert_eq!(iter.key().as_str(), "key010");
+        // between two keys
+        iter.seek(&Slice::from("key011"));
+        assert_eq!(iter.key().as_str(), "key012");
+        iter.prev();
+        assert_eq!(iter.key().as_str(), "key010");
+        iter.next();
+        assert_eq!(iter.key().as_str(), "key012");
+        // before all the keys
+        iter.seek(&Slice::from("a"));
+        assert_eq!(iter.key().as_str(), "key000");
+        // after all the keys
+        iter.seek(&Slice::from("z"));
+        assert!(!iter.valid());
+
+        iter.seek_for_prev(&Slice::from("key010"));
+        assert_eq!(iter.key().as_str(), "key010");
+        iter.seek_for_prev(&Slice::from("key011"));
+        assert_eq!(iter.key().as_str(), "key010");
+        iter.seek_for_prev(&Slice::from("z"));
+        assert_eq!(iter.key().as_str(), "key038");
+        iter.seek_for_prev(&Slice::from("a"));
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_block_iter_corrupted_entry() {
+        let keys = test_keys();
+        let mut data = Vec::from(new_test_block(&keys, 3).data().as_slice());
+        // the first entry takes 15 bytes: the 3 one-byte lengths, the key and the value.
+        // Makes the second entry share more bytes than the first key.
+        data[15] = 100;
+        let block = Block::new(data).unwrap();
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek_to_first();
+        assert_eq!(iter.key().as_str(), "key000");
+        iter.next();
+        assert!(!iter.valid());
+        assert_eq!(iter.status().unwrap_err().status(), Status::Corruption);
+        // the error is reported only once
+        assert!(iter.status().is_ok());
+        // the entries after a good restart point are still readable
+        iter.seek(&Slice::from("key010"));
+        assert_eq!(iter.key().as_str(), "key010");
+        iter.seek(&Slice::from("key002"));
+        assert!(!iter.valid());
+        assert_eq!(iter.status().unwrap_err().status(), Status::Corruption);
+    }
+
+    #[test]
+    fn test_empty_block_iter() {
+        let block = new_test_block(&[], 16);
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+        iter.seek(&Slice::from("a"));
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
     }
 }
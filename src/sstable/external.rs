@@ -0,0 +1,184 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::db::format::{InternalKey, ValueType};
+use crate::options::Options;
+use crate::sstable::table::TableBuilder;
+use crate::storage::File;
+use crate::util::comparator::Comparator;
+use crate::util::slice::Slice;
+use crate::util::status::{Result, Status, WickErr};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// An `ExternalTableBuilder` builds a sstable outside of a DB from the user
+/// key/value pairs, which could be ingested into a DB later. The table is a
+/// valid table with the data blocks, the filter block, the index block and
+/// the footer built by the `filter_policy`, `block_size`, `compression` and so on
+/// in the given `Options`.
+///
+/// All the entries are stored with the sequence number 0 and the sequence
+/// numbers are assigned when the table is ingested.
+pub struct ExternalTableBuilder {
+    builder: TableBuilder,
+    ucmp: Arc<dyn Comparator>,
+    smallest_key: Vec<u8>,
+    largest_key: Vec<u8>,
+}
+
+impl ExternalTableBuilder {
+    /// Creates a `ExternalTableBuilder` writing the table into `file`
+    pub fn new(file: Box<dyn File>, options: Arc<Options>) -> Self {
+        let ucmp = options.comparator.clone();
+        let compression = options.compression;
+        Self {
+            builder: TableBuilder::new(file, compression, options),
+            ucmp,
+            smallest_key: vec![],
+            largest_key: vec![],
+        }
+    }
+
+    /// Adds a key/value pair to the table.
+    /// Returns an `InvalidArgument` error if the key is not greater than
+    /// any previously added key according to the comparator in `Options`.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.builder.num_entries() > 0
+            && self.ucmp.compare(key, self.largest_key.as_slice()) != Ordering::Greater
+        {
+            return Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("[external table builder] key is not greater than the last key"),
+            )
+            .with_context(format!("key {:?}, last key {:?}", key, self.largest_key)));
+        }
+        let ikey = InternalKey::new(&Slice::from(key), 0, ValueType::Value);
+        self.builder.add(ikey.data(), value)?;
+        if self.builder.num_entries() == 1 {
+            self.smallest_key = key.to_vec();
+        }
+        self.largest_key.clear();
+        self.largest_key.extend_from_slice(key);
+        Ok(())
+    }
+
+    /// Finishes building the table and syncs the file.
+    /// Returns an `InvalidArgument` error if no entry was added.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.builder.num_entries() == 0 {
+            return Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("[external table builder] can not build a table without entries"),
+            ));
+        }
+        self.builder.finish(true)
+    }
+
+    /// Returns the number of the key/value pairs added so far
+    #[inline]
+    pub fn num_entries(&self) -> usize {
+        self.builder.num_entries()
+    }
+
+    /// Returns the size of the file generated so far
+    #[inline]
+    pub fn file_size(&self) -> u64 {
+        self.builder.file_size()
+    }
+
+    /// Returns the smallest user key added so far
+    #[inline]
+    pub fn smallest_key(&self) -> &[u8] {
+        self.smallest_key.as_slice()
+    }
+
+    /// Returns the largest user key added so far
+    #[inline]
+    pub fn largest_key(&self) -> &[u8] {
+        self.largest_key.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::bloom::BloomFilter;
+    use crate::options::ReadOptions;
+    use crate::sstable::table::Table;
+    use crate::storage::mem::MemStorage;
+    use crate::storage::Storage;
+    use crate::util::comparator::{BytewiseComparator, ReverseComparator};
+
+    #[test]
+    fn test_external_table_builder() {
+        let options = Arc::new(Options {
+            block_size: 128,
+            filter_policy: Some(Arc::new(BloomFilter::new(10))),
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder =
+            ExternalTableBuilder::new(storage.create("external").unwrap(), options.clone());
+        assert_eq!(
+            builder.finish().unwrap_err().status(),
+            Status::InvalidArgument
+        );
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            builder.add(key.as_bytes(), b"value").unwrap();
+        }
+        // out of order or duplicated keys
+        for k in ["key050", "key099"].iter() {
+            assert_eq!(
+                builder.add(k.as_bytes(), b"value").unwrap_err().status(),
+                Status::InvalidArgument
+            );
+        }
+        assert_eq!(builder.num_entries(), 100);
+        assert_eq!(builder.smallest_key(), b"key000");
+        assert_eq!(builder.largest_key(), b"key099");
+        builder.finish().unwrap();
+
+        let file = storage.open("external").unwrap();
+        let size = file.len().unwrap();
+        assert_eq!(size, builder.file_size());
+        let table = Arc::new(Table::open(file, size, options).unwrap());
+        let mut iter = table.iter(ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..100 {
+            let parsed = iter.key().unwrap();
+            assert_eq!(parsed.user_key.as_str(), format!("key{:03}", i));
+            assert_eq!(parsed.seq, 0);
+            assert_eq!(parsed.value_type, ValueType::Value);
+            assert_eq!(iter.value(), b"value");
+            iter.next();
+        }
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_external_table_builder_with_comparator() {
+        let options = Arc::new(Options {
+            comparator: Arc::new(ReverseComparator::new(Arc::new(BytewiseComparator::new()))),
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder = ExternalTableBuilder::new(storage.create("external").unwrap(), options);
+        builder.add(b"b", b"").unwrap();
+        builder.add(b"a", b"").unwrap();
+        assert_eq!(
+            builder.add(b"c", b"").unwrap_err().status(),
+            Status::InvalidArgument
+        );
+    }
+}
@@ -18,17 +18,18 @@
 use crate::filter::FilterPolicy;
 use crate::util::coding::{decode_fixed_32, put_fixed_32};
 use crate::util::slice::Slice;
-use std::rc::Rc;
+use std::sync::Arc;
 
-const FILTER_BASE_LG: usize = 11;
-const FILTER_BASE: usize = 1 << FILTER_BASE_LG; // 2KiB
+/// The default log of the data range covered by a filter.
+/// Generates a filter for every 2KiB of data.
+pub const DEFAULT_FILTER_BASE_LG: usize = 11;
 const FILTER_META_LENGTH: usize = 5; // 4bytes filter offsets length + 1bytes base log
 
 /// A `FilterBlockBuilder` is used to construct all of the filters for a
 /// particular Table.  It generates a single string which is stored as
 /// a special block in the Table.
 pub struct FilterBlockBuilder {
-    policy: Rc<dyn FilterPolicy>,
+    policy: Arc<dyn FilterPolicy>,
     // key contents
     // reused by every block
     keys: Vec<Vec<u8>>,
@@ -37,18 +38,40 @@ pub struct FilterBlockBuilder {
     data: Vec<u8>,
     // the offset of every filter in the data
     filter_offsets: Vec<u32>,
+    // a filter is generated for every `1 << base_lg` bytes of data blocks
+    base_lg: usize,
 }
 
 impl FilterBlockBuilder {
-    pub fn new(policy: Rc<dyn FilterPolicy>) -> Self {
+    /// Creates a `FilterBlockBuilder` which generates a filter for every
+    /// `1 << base_lg` bytes of data blocks.
+    ///
+    /// # Panics
+    ///
+    /// * `base_lg` can not be encoded in a byte or is too large to shift
+    ///
+    pub fn new(policy: Arc<dyn FilterPolicy>, base_lg: usize) -> Self {
+        assert!(
+            base_lg < 64,
+            "[filter block builder] invalid filter base lg {}",
+            base_lg
+        );
         Self {
             policy,
+            base_lg,
             keys: vec![],
             filter_offsets: vec![],
             data: vec![],
         }
     }
 
+    /// Returns true if the whole keys should be added into the filters.
+    /// See `FilterPolicy::whole_key_filtering`.
+    #[inline]
+    pub fn whole_key_filtering(&self) -> bool {
+        self.policy.whole_key_filtering()
+    }
+
     /// Adds the given key into the builder
     pub fn add_key(&mut self, key: &Slice) {
         let key = Vec::from(key.as_slice());
@@ -59,8 +82,8 @@ impl FilterBlockBuilder {
     pub fn start_block(&mut self, block_offset: u64) {
         // calc the filter index for the given block offset
         // the filter with the index i filters the block data
-        // from i* FILTER_BASE ~ (i + 1) * FILTER_BASE
-        let filter_index = block_offset / FILTER_BASE as u64;
+        // from i * base ~ (i + 1) * base
+        let filter_index = block_offset >> self.base_lg;
         let filters_len = self.filter_offsets.len() as u64;
         assert!(
             filter_index >= filters_len,
@@ -87,7 +110,7 @@ impl FilterBlockBuilder {
         // append the 4bytes offset length
         put_fixed_32(&mut self.data, self.filter_offsets.len() as u32);
         // append the 1byte base lg
-        self.data.push(FILTER_BASE_LG as u8);
+        self.data.push(self.base_lg as u8);
         self.data.as_slice()
     }
 
@@ -96,7 +119,8 @@ impl FilterBlockBuilder {
         let num_keys = self.keys.len();
         if num_keys == 0 {
             // fast path if there are no keys
-            self.filter_offsets.push(self.data.len() as u32)
+            self.filter_offsets.push(self.data.len() as u32);
+            return;
         };
         let filter = self.policy.create_filter(self.keys.as_slice());
         self.filter_offsets.push(self.data.len() as u32);
@@ -107,21 +131,30 @@ impl FilterBlockBuilder {
 }
 
 pub struct FilterBlockReader {
-    policy: Rc<dyn FilterPolicy>,
-    // all filter block data without filter meta
-    // | ----- filter data ----- | ----- filter offsets ----|
+    policy: Arc<dyn FilterPolicy>,
+    // all filter block data with filter meta
+    // | ----- filter data ----- | ----- filter offsets ----| --- meta --- |
     //                                   num * 4 bytes
-    data: Vec<u8>,
+    data: Arc<Vec<u8>>,
+    // the length of the filter data and the filter offsets
+    len: usize,
     // the amount of filter data
     num: usize,
     base_lg: usize,
 }
 
 impl FilterBlockReader {
-    pub fn new(policy: Rc<dyn FilterPolicy>, mut filter_block: Vec<u8>) -> Self {
+    pub fn new(policy: Arc<dyn FilterPolicy>, filter_block: Vec<u8>) -> Self {
+        Self::from_shared(policy, Arc::new(filter_block))
+    }
+
+    /// Creates a `FilterBlockReader` on the filter block contents shared with others,
+    /// such as the ones held by the block cache
+    pub fn from_shared(policy: Arc<dyn FilterPolicy>, filter_block: Arc<Vec<u8>>) -> Self {
         let mut r = FilterBlockReader {
             policy,
-            data: vec![],
+            data: Arc::new(vec![]),
+            len: 0,
             num: 0,
             base_lg: 0,
         };
@@ -132,22 +165,28 @@ impl FilterBlockReader {
         r.num = decode_fixed_32(&filter_block[n - FILTER_META_LENGTH..n - 1]) as usize;
         // invalid filter offsets length
         if r.num * 4 + FILTER_META_LENGTH > n {
+            r.num = 0;
             return r;
         }
         r.base_lg = filter_block[n - 1] as usize;
-        filter_block.truncate(n - FILTER_META_LENGTH);
+        r.len = n - FILTER_META_LENGTH;
         r.data = filter_block;
         r
     }
 
+    /// Returns true if the whole keys are added into the filters.
+    /// See `FilterPolicy::whole_key_filtering`.
+    #[inline]
+    pub fn whole_key_filtering(&self) -> bool {
+        self.policy.whole_key_filtering()
+    }
+
     /// Returns iff the given key is probably contained in the given `block_offset` block
     pub fn key_may_match(&self, block_offset: u64, key: &Slice) -> bool {
-        let i = block_offset as usize >> self.base_lg; // a >> b == a / (1 << b)
+        let i = (block_offset >> self.base_lg) as usize; // a >> b == a / (1 << b)
         if i < self.num {
-            let (filter, offsets) = self
-                .data
-                .as_slice()
-                .split_at(self.data.len() - self.num * 4);
+            let data = &self.data[..self.len];
+            let (filter, offsets) = data.split_at(self.len - self.num * 4);
             let start = decode_fixed_32(&offsets[i * 4..i * 4 + 4]) as usize;
             let end = {
                 if i + 1 >= self.num {
@@ -157,7 +196,7 @@ impl FilterBlockReader {
                     decode_fixed_32(&offsets[i * 4 + 4..i * 4 + 8]) as usize
                 }
             };
-            let filter = &self.data[start..end];
+            let filter = &data[start..end];
             return self.policy.may_contain(filter, key);
         }
         // errors are treated as potential matches
@@ -169,6 +208,7 @@ impl FilterBlockReader {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filter::bloom::BloomFilter;
     use crate::filter::FilterPolicy;
     use crate::util::hash::hash;
 
@@ -202,17 +242,17 @@ mod tests {
     }
 
     fn new_test_builder() -> FilterBlockBuilder {
-        FilterBlockBuilder::new(Rc::new(TestHashFilter {}))
+        FilterBlockBuilder::new(Arc::new(TestHashFilter {}), DEFAULT_FILTER_BASE_LG)
     }
     fn new_test_reader(block: Vec<u8>) -> FilterBlockReader {
-        FilterBlockReader::new(Rc::new(TestHashFilter {}), block)
+        FilterBlockReader::new(Arc::new(TestHashFilter {}), block)
     }
 
     #[test]
     fn test_empty_builder() {
         let mut b = new_test_builder();
         let block = b.finish();
-        assert_eq!(&[0, 0, 0, 0, FILTER_BASE_LG as u8], block);
+        assert_eq!(&[0, 0, 0, 0, DEFAULT_FILTER_BASE_LG as u8], block);
         let r = new_test_reader(Vec::from(block));
         assert_eq!(r.key_may_match(0, &Slice::from("foo")), true);
         assert_eq!(r.key_may_match(10000, &Slice::from("foo")), true);
@@ -283,4 +323,57 @@ mod tests {
         assert_eq!(r.key_may_match(9000, &Slice::from("bar")), false);
         assert_eq!(r.key_may_match(9000, &Slice::from("hello")), true);
     }
+
+    #[test]
+    fn test_empty_filters_of_bloom_filter() {
+        // Unlike `TestHashFilter`, the bloom filter of no keys is not empty
+        let policy = Arc::new(BloomFilter::new(10));
+        let mut b = FilterBlockBuilder::new(policy.clone(), DEFAULT_FILTER_BASE_LG);
+        b.start_block(0);
+        b.add_key(&Slice::from("foo"));
+        // the filters in [2048, 8192) are empty
+        b.start_block(9000);
+        b.add_key(&Slice::from("bar"));
+        let r = FilterBlockReader::new(policy, Vec::from(b.finish()));
+        assert!(r.key_may_match(0, &Slice::from("foo")));
+        assert!(!r.key_may_match(0, &Slice::from("bar")));
+        // the filter of the block is not shifted by the empty filters
+        assert!(r.key_may_match(9000, &Slice::from("bar")));
+        assert!(!r.key_may_match(9000, &Slice::from("foo")));
+    }
+
+    #[test]
+    fn test_custom_base_lg() {
+        // Generates a filter for every 256 bytes
+        let base_lg = 8;
+        let mut b = FilterBlockBuilder::new(Arc::new(TestHashFilter {}), base_lg);
+        b.start_block(0);
+        b.add_key(&Slice::from("foo"));
+        b.start_block(300);
+        b.add_key(&Slice::from("bar"));
+        // the filters in [512, 1024) are empty
+        b.start_block(1100);
+        b.add_key(&Slice::from("box"));
+        let block = Vec::from(b.finish());
+        assert_eq!(*block.last().unwrap() as usize, base_lg);
+        let r = new_test_reader(block);
+        assert!(r.key_may_match(0, &Slice::from("foo")));
+        assert!(!r.key_may_match(0, &Slice::from("bar")));
+        assert!(r.key_may_match(300, &Slice::from("bar")));
+        assert!(!r.key_may_match(300, &Slice::from("foo")));
+        assert!(!r.key_may_match(600, &Slice::from("box")));
+        assert!(!r.key_may_match(800, &Slice::from("foo")));
+        assert!(r.key_may_match(1100, &Slice::from("box")));
+        assert!(!r.key_may_match(1100, &Slice::from("bar")));
+
+        // A reader always uses the base lg persisted in the block
+        let mut default_builder = new_test_builder();
+        default_builder.start_block(0);
+        default_builder.add_key(&Slice::from("foo"));
+        default_builder.start_block(300);
+        default_builder.add_key(&Slice::from("bar"));
+        let r = new_test_reader(Vec::from(default_builder.finish()));
+        assert!(r.key_may_match(300, &Slice::from("foo")));
+        assert!(r.key_may_match(0, &Slice::from("bar")));
+    }
 }
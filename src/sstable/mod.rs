@@ -28,6 +28,12 @@
 /// last key of the last block. Filter block is an optional block contains
 /// sequence of filter data generated by a filter generator.
 ///
+/// A table built with `IndexType::TwoLevel` splits the index entries into the
+/// index partitions, which are written between the metaindex block and the
+/// index block. The index block is the top-level index then: its keys are the
+/// last keys of the partitions and its values are the partition handles. Such
+/// a table is marked by the `index.two_level` key in the metaindex block.
+///
 /// ## Table data structure:
 ///
 /// ```text
@@ -52,6 +58,9 @@
 ///     The checksum is a CRC-32 computed using Castagnoli's polynomial. Compression
 ///     type also included in the checksum.
 ///
+///     Compression type tags: 0 = none, 1 = snappy, 2 = zstd, 3 = lz4. Any other tag is
+///     treated as a corruption when reading.
+///
 /// ```
 ///
 /// ## Table footer:
@@ -189,21 +198,27 @@
 /// # Meta block
 ///
 /// This meta block contains a bunch of stats. The key is the name of the statistic. The value contains the statistic.
-/// For the current implementation, the meta block only contains the filter meta data:
+/// For the current implementation, the meta block contains the filter meta data, the
+/// index type and the number of entries:
 ///
 /// ```text
 ///
-///     +-------------+---------------------+
-///     |     key     |        value        |
-///     +-------------+---------------------+
-///     | filter name | filter block handle |
-///     +-------------+---------------------+
+///     +-------------------+--------------------------+
+///     |        key        |          value           |
+///     +-------------------+--------------------------+
+///     | filter name       | filter block handle      |
+///     +-------------------+--------------------------+
+///     | index.two_level   | empty, only if two-level |
+///     +-------------------+--------------------------+
+///     | stats.num_entries | fixed64                  |
+///     +-------------------+--------------------------+
 ///
 /// ```
 ///
 /// NOTE: All fixed-length integer are little-endian.
 pub mod block;
-mod filter_block;
+pub mod external;
+pub mod filter_block;
 pub mod table;
 
 use crate::util::coding::{decode_fixed_64, put_fixed_64};
@@ -225,7 +240,7 @@ const FOOTER_ENCODED_LENGTH: usize = 2 * MAX_BLOCK_HANDLE_ENCODE_LENGTH + 8;
 
 /// `BlockHandle` is a pointer to the extent of a file that stores a data
 /// block or a meta block.
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct BlockHandle {
     offset: u64,
     // NOTICE: the block trailer size is not included
@@ -237,6 +252,18 @@ impl BlockHandle {
         Self { offset, size }
     }
 
+    /// Returns the offset of the block in the file
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the size of the block, excluding the block trailer
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
     #[inline]
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
@@ -279,6 +306,34 @@ impl BlockHandle {
             Err(WickErr::new(Status::Corruption, Some("bad block handle")))
         }
     }
+
+    /// Decodes a BlockHandle from bytes like `decode_from` and makes sure the
+    /// block with its trailer stays within a file of `file_len` bytes.
+    ///
+    /// # Error
+    ///
+    /// If varint decoding fails or the block goes beyond the end of the file,
+    /// return `Status::Corruption` with relative messages
+    pub fn decode_from_checked(src: &[u8], file_len: u64) -> Result<(Self, usize), WickErr> {
+        let (handle, n) = Self::decode_from(src)?;
+        handle.check_bound(file_len)?;
+        Ok((handle, n))
+    }
+
+    // Returns `Status::Corruption` if the block with its trailer goes beyond `file_len`
+    fn check_bound(&self, file_len: u64) -> Result<(), WickErr> {
+        match self
+            .offset
+            .checked_add(self.size)
+            .and_then(|end| end.checked_add(BLOCK_TRAILER_SIZE as u64))
+        {
+            Some(end) if end <= file_len => Ok(()),
+            _ => Err(WickErr::new(
+                Status::Corruption,
+                Some("block handle points beyond the end of file"),
+            )),
+        }
+    }
 }
 
 /// `Footer` encapsulates the fixed information stored at the tail
@@ -369,3 +424,32 @@ mod test_footer {
         assert_eq!(footer.meta_index_handle, BlockHandle::new(300, 100));
     }
 }
+
+#[cfg(test)]
+mod test_block_handle {
+    use crate::sstable::{BlockHandle, BLOCK_TRAILER_SIZE};
+    use crate::util::status::Status;
+
+    #[test]
+    fn test_decode_from_checked() {
+        let handle = BlockHandle::new(100, 50);
+        let encoded = handle.encoded();
+        let end = 100 + 50 + BLOCK_TRAILER_SIZE as u64;
+        let (decoded, n) = BlockHandle::decode_from_checked(&encoded, end)
+            .expect("handle within the file should be decoded");
+        assert_eq!(n, encoded.len());
+        assert_eq!(decoded.offset(), 100);
+        assert_eq!(decoded.size(), 50);
+        for &file_len in &[0, 100, end - 1] {
+            let e = BlockHandle::decode_from_checked(&encoded, file_len).unwrap_err();
+            assert_eq!(e.status(), Status::Corruption);
+        }
+    }
+
+    #[test]
+    fn test_decode_from_checked_overflow() {
+        let encoded = BlockHandle::new(u64::MAX - 1, 10).encoded();
+        let e = BlockHandle::decode_from_checked(&encoded, u64::MAX).unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+    }
+}
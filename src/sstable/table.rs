@@ -15,42 +15,107 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use crate::db::format::ParsedInternalKey;
-use crate::iterator::{ConcatenateIterator, DerivedIterFactory, Iterator};
-use crate::options::{CompressionType, Options, ReadOptions};
+use crate::cache::{Cache, HandleRef};
+use crate::db::format::{
+    extract_user_key, InternalKey, InternalKeyComparator, ParsedInternalKey, ValueType,
+    MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK,
+};
+use crate::db::range_del::RangeTombstone;
+use crate::filter::bloom::BloomFilter;
+use crate::filter::{builtin_policy, FilterPolicy};
+use crate::iterator::{ConcatenateIterator, DerivedIterFactory, EmptyIterator, Iterator};
+use crate::options::{
+    CompressionType, IndexType, Options, ReadOptions, MIN_CUSTOM_COMPRESSION_TAG,
+};
 use crate::sstable::block::{Block, BlockBuilder};
 use crate::sstable::filter_block::{FilterBlockBuilder, FilterBlockReader};
 use crate::sstable::{BlockHandle, Footer, BLOCK_TRAILER_SIZE, FOOTER_ENCODED_LENGTH};
 use crate::storage::File;
-use crate::util::coding::{decode_fixed_32, put_fixed_32, put_fixed_64};
-use crate::util::comparator::Comparator;
+use crate::util::coding::{decode_fixed_32, decode_fixed_64, put_fixed_32, put_fixed_64};
+use crate::util::comparator::{BytewiseComparator, Comparator, NoShorteningComparator};
 use crate::util::crc32::{extend, mask, unmask, value};
 use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
+use hashbrown::HashMap;
 use snap::max_compress_len;
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
 
+// The prefix of the keys of the stats in the meta block
+const STATS_PREFIX: &str = "stats.";
+// The key of the stat in the meta block recording the number of entries of the table
+const NUM_ENTRIES_STAT: &str = "stats.num_entries";
+// The key in the meta block marking a table with a two-level index. The tables
+// with a flat index have no such key.
+const TWO_LEVEL_INDEX_KEY: &str = "index.two_level";
+// The key in the meta block of the bloom filter over all the user keys of the table
+const TABLE_FILTER_KEY: &str = "table_filter";
+// The key in the meta block of the block holding all the range tombstones of the
+// table, whose keys are the internal keys of the tombstones and whose values are
+// the exclusive ends
+const RANGE_DEL_KEY: &str = "rangedel";
+
 /// A `Table` is a sorted map from strings to strings.  Tables are
 /// immutable and persistent.  A Table may be safely accessed from
 /// multiple threads without external synchronization.
 pub struct Table {
     options: Arc<Options>,
+    // the `InternalKeyComparator` made of the user comparator in `options`
+    cmp: Arc<dyn Comparator>,
     file: Box<dyn File>,
+    // the size of the table file
+    size: u64,
     cache_id: u64,
-    filter_reader: Option<FilterBlockReader>,
+    // The index block and the filter are held by the table unless
+    // `cache_index_and_filter_blocks` is set, in which case they are read
+    // through the block cache by `index_handle` and `filter_handle`
+    filter_reader: Option<Arc<FilterBlockReader>>,
+    filter_handle: Option<(BlockHandle, Arc<dyn FilterPolicy>)>,
     // None iff we fail to read meta block
     meta_block_handle: Option<BlockHandle>,
-    index_block: Block,
+    index_block: Option<Arc<Block>>,
+    index_handle: BlockHandle,
+    // For a two-level index, the index block is the top-level index whose values
+    // are the handles of the partitions
+    index_type: IndexType,
+    // The cache handles of the meta blocks pinned in the block cache, which are
+    // released when the table is dropped
+    pinned: Vec<HandleRef<Arc<Block>>>,
+    // The number of entries recorded in the meta block
+    num_entries: Option<u64>,
+    // The bloom filter over all the user keys, which is absent unless the table is
+    // built with `table_filter_bits_per_key`
+    table_filter: Option<Arc<Vec<u8>>>,
+    // The range tombstones in the table
+    range_tombstones: Vec<RangeTombstone>,
 }
 
+// The file is only read by `read_at` once the table is opened, and the pinned
+// handles are only released when the table is dropped.
+unsafe impl Send for Table {}
+unsafe impl Sync for Table {}
+
 // Common methods
 impl Table {
     /// Attempt to open the table that is stored in bytes `[0..size)`
     /// of `file`, and read the metadata entries necessary to allow
     /// retrieving data from the table.
     pub fn open(file: Box<dyn File>, size: u64, options: Arc<Options>) -> Result<Self> {
+        Self::open_with_pinning(file, size, options, false)
+    }
+
+    /// Opens the table like `open`. If `pin_meta_blocks` is true and the index block
+    /// and the filter block are put into the block cache, they're pinned in the cache
+    /// until the table is dropped.
+    pub(crate) fn open_with_pinning(
+        file: Box<dyn File>,
+        size: u64,
+        options: Arc<Options>,
+        pin_meta_blocks: bool,
+    ) -> Result<Self> {
         if size < FOOTER_ENCODED_LENGTH as u64 {
             return Err(WickErr::new(
                 Status::Corruption,
@@ -64,54 +129,86 @@ impl Table {
             size - FOOTER_ENCODED_LENGTH as u64,
         )?;
         let (footer, _) = Footer::decode_from(footer_space.as_slice())?;
+        // Make sure the handles from the footer stay within the file
+        let data_len = size - FOOTER_ENCODED_LENGTH as u64;
+        footer.meta_index_handle.check_bound(data_len)?;
+        footer.index_handle.check_bound(data_len)?;
         // Read the index block
-        let index_block_contents =
-            read_block(file.as_ref(), &footer.index_handle, options.paranoid_checks)?;
+        let index_block_contents = read_block(
+            file.as_ref(),
+            &footer.index_handle,
+            options.paranoid_checks,
+            &options,
+        )?;
         let index_block = Block::new(index_block_contents)?;
+        let cmp: Arc<dyn Comparator> =
+            Arc::new(InternalKeyComparator::new(options.comparator.clone()));
+        if options.paranoid_checks {
+            Self::check_index_order(&index_block, cmp.clone())?;
+        }
 
         let cache_id = if let Some(cache) = &options.block_cache {
             cache.new_id()
         } else {
             0
         };
+        let cache_meta_blocks = options.cache_index_and_filter_blocks;
+        let cache_meta_blocks = cache_meta_blocks && options.block_cache.is_some();
         let mut t = Self {
             options: options.clone(),
+            cmp,
             file,
+            size,
             cache_id,
             filter_reader: None,
+            filter_handle: None,
             meta_block_handle: None,
-            index_block,
+            index_block: None,
+            index_handle: footer.index_handle,
+            // the same as the table builder if the meta block is unreadable
+            index_type: options.index_type,
+            pinned: vec![],
+            num_entries: None,
+            table_filter: None,
+            range_tombstones: vec![],
         };
+        if cache_meta_blocks {
+            let handle = t.index_handle.clone();
+            t.insert_meta_block(&handle, index_block, pin_meta_blocks);
+        } else {
+            t.index_block = Some(Arc::new(index_block));
+        }
         // Read meta block
-        if footer.meta_index_handle.size > 0 && options.filter_policy.is_some() {
+        if footer.meta_index_handle.size() > 0 {
             // ignore the reading errors since meta info is not needed for operation
             if let Ok(meta_block_contents) = read_block(
                 t.file.as_ref(),
                 &footer.meta_index_handle,
                 options.paranoid_checks,
+                &options,
             ) {
                 if let Ok(meta_block) = Block::new(meta_block_contents) {
-                    let mut iter = meta_block.iter(options.comparator.clone());
-                    let filter_key = if let Some(fp) = &options.filter_policy {
-                        "filter.".to_owned() + fp.name()
+                    let mut iter = meta_block.iter(Arc::new(BytewiseComparator::new()));
+                    iter.seek(&Slice::from(TWO_LEVEL_INDEX_KEY));
+                    t.index_type = if iter.valid()
+                        && iter.key().as_slice() == TWO_LEVEL_INDEX_KEY.as_bytes()
+                    {
+                        IndexType::TwoLevel
                     } else {
-                        String::from("")
+                        IndexType::Flat
                     };
-                    // Read filter block
-                    iter.seek(&Slice::from(filter_key.as_bytes()));
-                    if iter.valid() && iter.key().as_str() == filter_key.as_str() {
-                        if let Ok((filter_handle, _)) =
-                            BlockHandle::decode_from(iter.value().as_slice())
-                        {
-                            if let Ok(filter_block) =
-                                read_block(t.file.as_ref(), &filter_handle, options.paranoid_checks)
-                            {
-                                t.filter_reader = Some(FilterBlockReader::new(
-                                    t.options.filter_policy.clone().unwrap(),
-                                    filter_block,
-                                ));
-                            }
-                        }
+                    // Read the stats, which are absent in the tables built by older versions
+                    iter.seek(&Slice::from(NUM_ENTRIES_STAT));
+                    if iter.valid()
+                        && iter.key().as_slice() == NUM_ENTRIES_STAT.as_bytes()
+                        && iter.value().size() == 8
+                    {
+                        t.num_entries = Some(decode_fixed_64(iter.value().as_slice()));
+                    }
+                    t.read_table_filter(iter.as_mut());
+                    t.read_range_tombstones(iter.as_mut())?;
+                    if let Some(policy) = options.filter_policy.clone() {
+                        t.read_filter(iter.as_mut(), policy, cache_meta_blocks, pin_meta_blocks);
                     }
                 }
             }
@@ -119,86 +216,374 @@ impl Table {
         Ok(t)
     }
 
+    // Reads the range tombstones found by the meta block iterator `iter`. Unlike the
+    // other meta blocks, the errors are returned since the keys deleted by the
+    // tombstones would come back if the tombstones were ignored.
+    fn read_range_tombstones(&mut self, iter: &mut dyn Iterator) -> Result<()> {
+        iter.seek(&Slice::from(RANGE_DEL_KEY));
+        if !iter.valid() || iter.key().as_slice() != RANGE_DEL_KEY.as_bytes() {
+            return Ok(());
+        }
+        let (handle, _) = BlockHandle::decode_from_checked(iter.value().as_slice(), self.size)?;
+        let block = read_block(
+            self.file.as_ref(),
+            &handle,
+            self.options.paranoid_checks,
+            &self.options,
+        )
+        .and_then(Block::new)?;
+        let mut iter = block.iter(self.cmp.clone());
+        iter.seek_to_first();
+        while iter.valid() {
+            match ParsedInternalKey::decode_from(iter.key()) {
+                Some(k) if k.value_type == ValueType::RangeDeletion => self.range_tombstones.push(
+                    RangeTombstone::new(k.user_key.as_slice(), iter.value().as_slice(), k.seq),
+                ),
+                _ => {
+                    return Err(WickErr::new(
+                        Status::Corruption,
+                        Some("bad entry in the range tombstone block"),
+                    )
+                    .with_context(format!("key {:?}", iter.key().as_slice())))
+                }
+            }
+            iter.next();
+        }
+        iter.status()
+    }
+
+    // Reads the filter block found by the meta block iterator `iter`
+    fn read_filter(
+        &mut self,
+        iter: &mut dyn Iterator,
+        policy: Arc<dyn FilterPolicy>,
+        cache_meta_blocks: bool,
+        pin_meta_blocks: bool,
+    ) {
+        let filter_key = "filter.".to_owned() + policy.name();
+        iter.seek(&Slice::from(filter_key.as_bytes()));
+        let policy = if iter.valid() && iter.key().as_str() == filter_key.as_str() {
+            Some(policy)
+        } else {
+            // The table may be built by another builtin filter
+            Self::find_builtin_filter(iter)
+        };
+        if let Some(policy) = policy {
+            if let Ok((filter_handle, _)) =
+                BlockHandle::decode_from_checked(iter.value().as_slice(), self.size)
+            {
+                if let Ok(filter_block) = read_block(
+                    self.file.as_ref(),
+                    &filter_handle,
+                    self.options.paranoid_checks,
+                    &self.options,
+                ) {
+                    if cache_meta_blocks {
+                        let block = Block::raw(filter_block);
+                        self.insert_meta_block(&filter_handle, block, pin_meta_blocks);
+                        self.filter_handle = Some((filter_handle, policy));
+                    } else {
+                        self.filter_reader =
+                            Some(Arc::new(FilterBlockReader::new(policy, filter_block)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Reads the whole-table filter found by the meta block iterator `iter`
+    fn read_table_filter(&mut self, iter: &mut dyn Iterator) {
+        iter.seek(&Slice::from(TABLE_FILTER_KEY));
+        if iter.valid() && iter.key().as_slice() == TABLE_FILTER_KEY.as_bytes() {
+            if let Ok((handle, _)) =
+                BlockHandle::decode_from_checked(iter.value().as_slice(), self.size)
+            {
+                if let Ok(filter) = read_block(
+                    self.file.as_ref(),
+                    &handle,
+                    self.options.paranoid_checks,
+                    &self.options,
+                ) {
+                    self.table_filter = Some(Arc::new(filter));
+                }
+            }
+        }
+    }
+
+    // Inserts the meta block read by `handle` into the block cache and pins it if
+    // `pin` is true
+    fn insert_meta_block(&mut self, handle: &BlockHandle, block: Block, pin: bool) {
+        let cache = self.options.block_cache.as_ref().unwrap();
+        let charge = block.data().len();
+        let h = cache.insert(self.cache_key(handle), Arc::new(block), charge, None);
+        if pin {
+            self.pinned.push(h);
+        } else {
+            cache.release(h);
+        }
+    }
+
+    // Returns the key of the block read by `handle` in the block cache
+    fn cache_key(&self, handle: &BlockHandle) -> Vec<u8> {
+        let mut cache_key_buffer = Vec::with_capacity(16);
+        put_fixed_64(&mut cache_key_buffer, self.cache_id);
+        put_fixed_64(&mut cache_key_buffer, handle.offset());
+        cache_key_buffer
+    }
+
+    // Returns the meta block read by `handle` from the block cache. The block is read
+    // from the file and inserted into the cache again if it has been evicted.
+    fn cached_meta_block(&self, handle: &BlockHandle, raw: bool) -> Result<Arc<Block>> {
+        let cache = self.options.block_cache.as_ref().unwrap();
+        let key = self.cache_key(handle);
+        if let Some(h) = cache.look_up(key.as_slice()) {
+            let block = h.get_value().unwrap().clone();
+            cache.release(h);
+            return Ok(block);
+        }
+        let data = read_block(
+            self.file.as_ref(),
+            handle,
+            self.options.paranoid_checks,
+            &self.options,
+        )?;
+        let charge = data.len();
+        let block = Arc::new(if raw {
+            Block::raw(data)
+        } else {
+            Block::new(data)?
+        });
+        let h = cache.insert(key, block.clone(), charge, None);
+        cache.release(h);
+        Ok(block)
+    }
+
+    // Returns the index block of the table
+    fn index_block(&self) -> Result<Arc<Block>> {
+        match &self.index_block {
+            Some(block) => Ok(block.clone()),
+            None => self.cached_meta_block(&self.index_handle, false),
+        }
+    }
+
+    // Reads the partition of a two-level index by the encoded handle `handle_val` in the
+    // top-level index. The partitions are cached in the block cache like the data blocks.
+    fn index_partition(
+        &self,
+        handle_val: &[u8],
+        options: Rc<ReadOptions>,
+    ) -> Result<Box<dyn Iterator>> {
+        let (handle, _) = BlockHandle::decode_from_checked(handle_val, self.size)?;
+        self.block_reader(handle, options)
+    }
+
+    // Returns an iterator over the index entries of the data blocks positioned at the
+    // first entry not less than `target`. Only the partition containing the entry is
+    // read for a two-level index.
+    fn seek_index(&self, target: &[u8], options: Rc<ReadOptions>) -> Result<Box<dyn Iterator>> {
+        let mut iter = self.index_block()?.iter(self.cmp.clone());
+        iter.seek(&Slice::from(target));
+        if self.index_type == IndexType::Flat || !iter.valid() {
+            return Ok(iter);
+        }
+        // the key of a partition in the top-level index is the last key in it
+        let mut partition = self.index_partition(iter.value().as_slice(), options)?;
+        partition.seek(&Slice::from(target));
+        Ok(partition)
+    }
+
+    // Returns the filter of the table. A filter failing to be read is ignored.
+    fn filter_reader(&self) -> Option<Arc<FilterBlockReader>> {
+        if let Some(filter) = &self.filter_reader {
+            return Some(filter.clone());
+        }
+        let (handle, policy) = self.filter_handle.as_ref()?;
+        let block = self.cached_meta_block(handle, true).ok()?;
+        Some(Arc::new(FilterBlockReader::from_shared(
+            policy.clone(),
+            block.data(),
+        )))
+    }
+
+    // Checks that the keys in the index block are strictly increasing, which is
+    // violated if the table is built by a different or broken comparator
+    fn check_index_order(index_block: &Block, cmp: Arc<dyn Comparator>) -> Result<()> {
+        let mut iter = index_block.iter(cmp.clone());
+        iter.seek_to_first();
+        let mut last_key: Option<Vec<u8>> = None;
+        while iter.valid() {
+            let key = iter.key();
+            if let Some(last) = &last_key {
+                if cmp.compare(key.as_slice(), last.as_slice()) != Ordering::Greater {
+                    return Err(WickErr::new(
+                        Status::Corruption,
+                        Some("index key is not greater than the previous key"),
+                    )
+                    .with_context(format!(
+                        "index key {:?}, previous key {:?}",
+                        InternalKey::decoded_from(key.as_slice()),
+                        InternalKey::decoded_from(last.as_slice()),
+                    )));
+                }
+            }
+            last_key = Some(Vec::from(key.as_slice()));
+            iter.next();
+        }
+        iter.status()
+    }
+
+    // Finds the first filter in the meta block written by a builtin filter policy
+    // and leaves `iter` at it
+    fn find_builtin_filter(iter: &mut dyn Iterator) -> Option<Arc<dyn FilterPolicy>> {
+        iter.seek(&Slice::from("filter."));
+        while iter.valid() {
+            let key = iter.key();
+            match key.as_str().strip_prefix("filter.") {
+                Some(name) => {
+                    if let Some(policy) = builtin_policy(name) {
+                        return Some(policy);
+                    }
+                }
+                None => break,
+            }
+            iter.next();
+        }
+        None
+    }
+
     /// Converts an BlockHandle into an iterator over the contents of the corresponding block.
     pub fn block_reader(
         &self,
         data_block_handle: BlockHandle,
         options: Rc<ReadOptions>,
     ) -> Result<Box<dyn Iterator>> {
-        let block = if let Some(cache) = &self.options.block_cache {
-            let mut cache_key_buffer = vec![0; 16];
-            put_fixed_64(&mut cache_key_buffer, self.cache_id);
-            put_fixed_64(&mut cache_key_buffer, data_block_handle.offset);
-            if let Some(cache_handle) = cache.look_up(&cache_key_buffer.as_slice()) {
-                let b = cache_handle.get_value().unwrap().clone();
-                cache.release(cache_handle);
-                b
+        let (block, handle) = self.read_data_block(&data_block_handle, options.as_ref(), None)?;
+        if let (Some(cache), Some(h)) = (&self.options.block_cache, handle) {
+            cache.release(h);
+        }
+        Ok(block.iter(self.cmp.clone()))
+    }
+
+    // Reads the data block from the block cache or the file, which is read through
+    // `readahead` if given.
+    // If the block is in the cache (or just inserted), the cache handle is returned as well
+    // and the caller must release it.
+    // The cached blocks are not verified again. But if `verify_checksums` is set and the
+    // cached block was read without verifying its checksum, the block is read from the
+    // file again and replaces the cached one once it's verified.
+    fn read_data_block(
+        &self,
+        data_block_handle: &BlockHandle,
+        options: &ReadOptions,
+        readahead: Option<&mut ReadaheadBuffer>,
+    ) -> Result<(Arc<Block>, Option<HandleRef<Arc<Block>>>)> {
+        let result = if let Some(cache) = &self.options.block_cache {
+            let cache_key_buffer = self.cache_key(data_block_handle);
+            let cached = Self::cached_data_block(cache.as_ref(), &cache_key_buffer, options);
+            if let Some((b, cache_handle)) = cached {
+                (b, Some(cache_handle))
             } else {
-                let data = read_block(
-                    self.file.as_ref(),
-                    &data_block_handle,
-                    options.verify_checksums,
-                )?;
-                let charge = data.len();
-                let new_block = Block::new(data)?;
-                let b = Arc::new(new_block);
+                let (b, charge) = self.load_data_block(data_block_handle, options, readahead)?;
                 if options.fill_cache {
                     // TODO: avoid clone
-                    cache.insert(cache_key_buffer, b.clone(), charge, None);
+                    let h = cache.insert(cache_key_buffer, b.clone(), charge, None);
+                    (b, Some(h))
+                } else {
+                    (b, None)
                 }
-                b
             }
         } else {
-            let data = read_block(
+            (
+                self.load_data_block(data_block_handle, options, readahead)?
+                    .0,
+                None,
+            )
+        };
+        Ok(result)
+    }
+
+    // Returns the data block in the block cache by `cache_key` with its cache handle.
+    // A cached block read without verifying its checksum is ignored if `verify_checksums`
+    // is set.
+    fn cached_data_block(
+        cache: &dyn Cache<Arc<Block>>,
+        cache_key: &[u8],
+        options: &ReadOptions,
+    ) -> Option<(Arc<Block>, HandleRef<Arc<Block>>)> {
+        let cache_handle = cache.look_up(cache_key)?;
+        let b = cache_handle.get_value().unwrap().clone();
+        if !options.verify_checksums || b.checksum_verified() {
+            Some((b, cache_handle))
+        } else {
+            cache.release(cache_handle);
+            None
+        }
+    }
+
+    // Reads the data block from the file through `readahead` if given without the
+    // block cache. Returns the block and its charge in the cache.
+    fn load_data_block(
+        &self,
+        data_block_handle: &BlockHandle,
+        options: &ReadOptions,
+        readahead: Option<&mut ReadaheadBuffer>,
+    ) -> Result<(Arc<Block>, usize)> {
+        let data = match readahead {
+            Some(readahead) => readahead.read_block(
                 self.file.as_ref(),
-                &data_block_handle,
+                self.size,
+                data_block_handle,
                 options.verify_checksums,
-            )?;
-            let b = Block::new(data)?;
-            Arc::new(b)
+                &self.options,
+            )?,
+            None => read_block(
+                self.file.as_ref(),
+                data_block_handle,
+                options.verify_checksums,
+                &self.options,
+            )?,
         };
-        Ok(block.iter(self.options.comparator.clone()))
+        let charge = data.len();
+        // a block failing the verification never reaches here
+        let b = Block::new(data)?.with_checksum_verified(options.verify_checksums);
+        Ok((Arc::new(b), charge))
     }
 
-    /// Gets the first entry with the key equal or greater than target, then calls the 'callback'
+    /// Seeks the first entry not less than the internal key `key`. Returns the value type,
+    /// the sequence number and the value of the entry if it has the same user key as `key`.
     pub fn internal_get(
         &self,
         options: Rc<ReadOptions>,
         key: &[u8],
-    ) -> Result<Option<ParsedInternalKey>> {
-        let mut index_iter = self.index_block.iter(self.options.comparator.clone());
+    ) -> Result<Option<(ValueType, u64, Vec<u8>)>> {
         // seek to the first 'last key' bigger than 'key'
-        index_iter.seek(&Slice::from(key));
+        let mut index_iter = self.seek_index(key, options.clone())?;
         if index_iter.valid() {
-            // It's called 'maybe_contained' not only because the filter policy may report the falsy result,
+            // The key may be contained not only because the filter policy may report the falsy result,
             // but also even if we've found a block with the last key bigger than the target
             // the key may not be contained if the block is the first block of the sstable.
-            let mut maybe_contained = true;
-
             let handle_val = index_iter.value();
-            // check the filter block
-            if let Some(filter) = &self.filter_reader {
-                if let Ok((handle, _)) = BlockHandle::decode_from(handle_val.as_slice()) {
-                    if !filter.key_may_match(handle.offset, &Slice::from(key)) {
-                        maybe_contained = false;
-                    }
-                }
-            }
-            if maybe_contained {
-                let (data_block_handle, _) = BlockHandle::decode_from(handle_val.as_slice())?;
+            let user_key = extract_user_key(key);
+            if self.filter_may_match(handle_val.as_slice(), &user_key) {
+                let (data_block_handle, _) =
+                    BlockHandle::decode_from_checked(handle_val.as_slice(), self.size)?;
                 let mut block_iter = self.block_reader(data_block_handle, options)?;
                 block_iter.seek(&Slice::from(key));
                 if block_iter.valid() {
-                    match ParsedInternalKey::decode_from(block_iter.value()) {
+                    match ParsedInternalKey::decode_from(block_iter.key()) {
                         None => return Err(WickErr::new(Status::Corruption, None)),
                         Some(parsed_key) => {
                             if self
                                 .options
                                 .comparator
-                                .compare(parsed_key.user_key.as_slice(), key)
+                                .compare(parsed_key.user_key.as_slice(), user_key.as_slice())
                                 == Ordering::Equal
                             {
-                                return Ok(Some(parsed_key));
+                                return Ok(Some((
+                                    parsed_key.value_type,
+                                    parsed_key.seq,
+                                    Vec::from(block_iter.value().as_slice()),
+                                )));
                             }
                         }
                     }
@@ -210,37 +595,228 @@ impl Table {
         Ok(None)
     }
 
+    /// Returns false if the table definitely contains no entry of the user key in the
+    /// internal key `key`. Only the index block and the filter block are consulted.
+    pub fn key_may_match(&self, key: &[u8]) -> bool {
+        let mut index_iter = match self.seek_index(key, Rc::new(ReadOptions::default())) {
+            Ok(iter) => iter,
+            Err(_) => return true,
+        };
+        if index_iter.valid() {
+            let handle_val = index_iter.value();
+            self.filter_may_match(handle_val.as_slice(), &extract_user_key(key))
+        } else {
+            // all the keys in the table are smaller than `key` unless the index block
+            // is broken
+            index_iter.status().is_err()
+        }
+    }
+
+    // Checks the filter of the data block whose encoded block handle is `handle_val`.
+    // Returns false if the `user_key` must be absent in the block.
+    fn filter_may_match(&self, handle_val: &[u8], user_key: &Slice) -> bool {
+        if let Some(filter) = self.filter_reader() {
+            if let Ok((handle, _)) = BlockHandle::decode_from(handle_val) {
+                if filter.whole_key_filtering() && !filter.key_may_match(handle.offset(), user_key)
+                {
+                    return false;
+                } else if let Some(extractor) = &self.options.prefix_extractor {
+                    // keys without the prefix in this block must be absent
+                    let ukey = user_key.as_slice();
+                    if extractor.in_domain(ukey)
+                        && !filter
+                            .key_may_match(handle.offset(), &Slice::from(extractor.transform(ukey)))
+                    {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Reads the whole table and validates the footer magic and the checksums of all
+    /// the blocks, bypassing the block cache. Returns the first error found.
+    pub fn verify_checksums(&self) -> Result<()> {
+        let mut footer_space = vec![0; FOOTER_ENCODED_LENGTH];
+        self.file.read_exact_at(
+            footer_space.as_mut_slice(),
+            self.size - FOOTER_ENCODED_LENGTH as u64,
+        )?;
+        let (footer, _) = Footer::decode_from(footer_space.as_slice())?;
+        let data_len = self.size - FOOTER_ENCODED_LENGTH as u64;
+        // the blocks referenced by the index block and the meta index block
+        for (handle, cmp, nested) in [
+            (
+                &footer.index_handle,
+                self.cmp.clone(),
+                self.index_type == IndexType::TwoLevel,
+            ),
+            (
+                &footer.meta_index_handle,
+                Arc::new(BytewiseComparator::new()) as Arc<dyn Comparator>,
+                false,
+            ),
+        ]
+        .iter()
+        {
+            if handle.size() == 0 {
+                continue;
+            }
+            handle.check_bound(data_len)?;
+            self.verify_block_handles(handle, cmp.clone(), *nested)?;
+        }
+        Ok(())
+    }
+
+    // Reads the block by `handle` and all the blocks referenced by its entries with
+    // the checksums verified. If `nested` is true, the referenced blocks are the
+    // partitions of a two-level index whose referenced blocks are verified as well.
+    fn verify_block_handles(
+        &self,
+        handle: &BlockHandle,
+        cmp: Arc<dyn Comparator>,
+        nested: bool,
+    ) -> Result<()> {
+        let block = Block::new(read_block(self.file.as_ref(), handle, true, &self.options)?)?;
+        let mut iter = block.iter(cmp.clone());
+        iter.seek_to_first();
+        while iter.valid() {
+            // the stats and the index type in the meta index block are not block handles
+            let key = iter.key();
+            if !key.starts_with(STATS_PREFIX.as_bytes())
+                && key.as_slice() != TWO_LEVEL_INDEX_KEY.as_bytes()
+            {
+                let (h, _) = BlockHandle::decode_from_checked(iter.value().as_slice(), self.size)?;
+                if nested {
+                    self.verify_block_handles(&h, cmp.clone(), false)?;
+                } else {
+                    read_block(self.file.as_ref(), &h, true, &self.options)?;
+                }
+            }
+            iter.next();
+        }
+        iter.status()
+    }
+
     /// Given a key, return an approximate byte offset in the file where
     /// the data for that key begins (or would begin if the key were
     /// present in the file).  The returned value is in terms of file
     /// bytes, and so includes effects like compression of the underlying data.
     /// E.g., the approximate offset of the last key in the table will
     /// be close to the file length.
-    /// Temporary only used in tests.
-    #[allow(dead_code)]
     pub(crate) fn approximate_offset_of(&self, key: &[u8]) -> u64 {
-        let mut index_iter = self.index_block.iter(self.options.comparator.clone());
-        index_iter.seek(&Slice::from(key));
-        if index_iter.valid() {
-            let val = index_iter.value();
-            if let Ok((h, _)) = BlockHandle::decode_from(val.as_slice()) {
-                return h.offset;
+        if let Ok(index_iter) = self.seek_index(key, Rc::new(ReadOptions::default())) {
+            if index_iter.valid() {
+                let val = index_iter.value();
+                if let Ok((h, _)) = BlockHandle::decode_from(val.as_slice()) {
+                    return h.offset();
+                }
             }
         }
         if let Some(meta) = &self.meta_block_handle {
-            return meta.offset;
+            return meta.offset();
         }
         0
     }
+
+    /// Returns the range tombstones in the table without reading the data blocks
+    #[inline]
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        self.range_tombstones.as_slice()
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if let Some(cache) = &self.options.block_cache {
+            for h in self.pinned.drain(..) {
+                cache.release(h);
+            }
+        }
+    }
 }
 
 pub struct TableIterFactory {
     table: Arc<Table>,
+    // The blocks touched during the lifetime of the table iterator when `pin_data` is set.
+    // The key is the offset of the block in the file and the cache handles are released
+    // when the factory (as well as the table iterator) is dropped.
+    pinned: RefCell<HashMap<u64, (Arc<Block>, Option<HandleRef<Arc<Block>>>)>>,
+    // Present if `ReadOptions::readahead_size` is set
+    readahead: RefCell<Option<ReadaheadBuffer>>,
+}
+
+impl TableIterFactory {
+    fn new(table: Arc<Table>, options: &ReadOptions) -> Self {
+        let readahead = if options.readahead_size > 0 {
+            Some(ReadaheadBuffer::new(options.readahead_size))
+        } else {
+            None
+        };
+        Self {
+            table,
+            pinned: RefCell::new(HashMap::new()),
+            readahead: RefCell::new(readahead),
+        }
+    }
+
+    // Reads the data block through the readahead buffer. The returned cache handle
+    // must be released by the caller.
+    fn read_block(
+        &self,
+        handle: &BlockHandle,
+        options: &ReadOptions,
+    ) -> Result<(Arc<Block>, Option<HandleRef<Arc<Block>>>)> {
+        let mut readahead = self.readahead.borrow_mut();
+        self.table
+            .read_data_block(handle, options, readahead.as_mut())
+    }
 }
+
 impl DerivedIterFactory for TableIterFactory {
     fn produce(&self, options: Rc<ReadOptions>, value: &Slice) -> Result<Box<dyn Iterator>> {
-        BlockHandle::decode_from(value.as_slice())
-            .and_then(|(handle, _)| self.table.block_reader(handle, options))
+        let (handle, _) = BlockHandle::decode_from_checked(value.as_slice(), self.table.size)?;
+        if !options.pin_data {
+            let (block, cache_handle) = self.read_block(&handle, options.as_ref())?;
+            if let (Some(cache), Some(h)) = (&self.table.options.block_cache, cache_handle) {
+                cache.release(h);
+            }
+            return Ok(block.iter(self.table.cmp.clone()));
+        }
+        let mut pinned = self.pinned.borrow_mut();
+        let block = match pinned.get(&handle.offset()) {
+            Some((block, _)) => block.clone(),
+            None => {
+                let (block, cache_handle) = self.read_block(&handle, options.as_ref())?;
+                pinned.insert(handle.offset(), (block.clone(), cache_handle));
+                block
+            }
+        };
+        Ok(block.iter(self.table.cmp.clone()))
+    }
+}
+
+impl Drop for TableIterFactory {
+    fn drop(&mut self) {
+        if let Some(cache) = &self.table.options.block_cache {
+            for (_, (_, handle)) in self.pinned.get_mut().drain() {
+                if let Some(h) = handle {
+                    cache.release(h);
+                }
+            }
+        }
+    }
+}
+
+// Reads the partitions of a two-level index by the entries of the top-level index
+struct IndexPartitionFactory {
+    table: Arc<Table>,
+}
+
+impl DerivedIterFactory for IndexPartitionFactory {
+    fn produce(&self, options: Rc<ReadOptions>, value: &Slice) -> Result<Box<dyn Iterator>> {
+        self.table.index_partition(value.as_slice(), options)
     }
 }
 
@@ -251,25 +827,295 @@ impl DerivedIterFactory for TableIterFactory {
 ///     key: internal key
 ///     value: value of user key
 pub fn new_table_iterator(table: Arc<Table>, options: Rc<ReadOptions>) -> Box<dyn Iterator> {
-    let cmp = table.options.comparator.clone();
-    let index_iter = table.index_block.iter(cmp);
-    let factory = Box::new(TableIterFactory { table });
+    let index_iter = match new_index_iterator(table.clone(), options.clone()) {
+        Ok(iter) => iter,
+        Err(e) => return Box::new(EmptyIterator::new_with_err(e)),
+    };
+    let factory = Box::new(TableIterFactory::new(table, options.as_ref()));
     Box::new(ConcatenateIterator::new(options, index_iter, factory))
 }
 
+// Returns an iterator over the index entries of all the data blocks in the table,
+// which goes through the partitions of a two-level index
+fn new_index_iterator(table: Arc<Table>, options: Rc<ReadOptions>) -> Result<Box<dyn Iterator>> {
+    let index_iter = table.index_block()?.iter(table.cmp.clone());
+    if table.index_type == IndexType::TwoLevel {
+        let factory = Box::new(IndexPartitionFactory {
+            table: table.clone(),
+        });
+        return Ok(Box::new(ConcatenateIterator::new(
+            options, index_iter, factory,
+        )));
+    }
+    Ok(index_iter)
+}
+
+impl Table {
+    /// Reads the data blocks overlapping the user keys `[start, end)` in order by the block
+    /// reader of the table iterators, and returns the number of the blocks read from the
+    /// file. The blocks are put into the block cache only if `options.fill_cache` is set.
+    /// The blocks already in the cache are not read again but still take `budget`.
+    /// Once the next block doesn't fit in the remaining `budget` bytes, stops and sets
+    /// `budget` to zero. Fails with a `Status::Aborted` error once `canceled` is set.
+    /// Does nothing without the block cache.
+    pub(crate) fn warm_cache(
+        self: Arc<Self>,
+        options: Rc<ReadOptions>,
+        (start, end): (&[u8], &[u8]),
+        budget: &mut usize,
+        canceled: Option<&AtomicBool>,
+    ) -> Result<usize> {
+        let cache = match &self.options.block_cache {
+            Some(cache) => cache.clone(),
+            None => return Ok(0),
+        };
+        let factory = TableIterFactory::new(self.clone(), options.as_ref());
+        // the blocks are put into the cache here once they are known to fit
+        let read_opt = ReadOptions {
+            verify_checksums: options.verify_checksums,
+            fill_cache: false,
+            ..Default::default()
+        };
+        let ucmp = self.options.comparator.clone();
+        let mut index_iter = new_index_iterator(self.clone(), options.clone())?;
+        let target = InternalKey::new(&Slice::from(start), MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+        index_iter.seek(&Slice::from(target.data()));
+        let mut loaded = 0;
+        while index_iter.valid() {
+            if canceled.is_some_and(|c| c.load(AtomicOrdering::Acquire)) {
+                return Err(WickErr::new(
+                    Status::Aborted,
+                    Some("warming the cache is canceled"),
+                ));
+            }
+            let (handle, _) =
+                BlockHandle::decode_from_checked(index_iter.value().as_slice(), self.size)?;
+            let cache_key = self.cache_key(&handle);
+            let charge = match Self::cached_data_block(cache.as_ref(), &cache_key, &options) {
+                Some((b, h)) => {
+                    cache.release(h);
+                    b.data().len()
+                }
+                // the size of a compressed block is no larger than its charge
+                None if handle.size() as usize > *budget => handle.size() as usize,
+                None => {
+                    let (b, _) = factory.read_block(&handle, &read_opt)?;
+                    let charge = b.data().len();
+                    if options.fill_cache && charge <= *budget {
+                        cache.release(cache.insert(cache_key, b, charge, None));
+                    }
+                    loaded += 1;
+                    charge
+                }
+            };
+            if charge > *budget {
+                *budget = 0;
+                break;
+            }
+            *budget -= charge;
+            // the index key is not less than all the keys in the block
+            let last = extract_user_key(index_iter.key().as_slice());
+            if ucmp.compare(last.as_slice(), end) != Ordering::Less {
+                break;
+            }
+            index_iter.next();
+        }
+        index_iter.status()?;
+        Ok(loaded)
+    }
+
+    /// Returns an iterator over all the entries in the table with the
+    /// internal keys decoded. This is useful for the offline tools which
+    /// inspect a single table file without opening a DB.
+    pub fn iter(self: Arc<Self>, options: ReadOptions) -> TableIterator {
+        TableIterator {
+            inner: new_table_iterator(self, Rc::new(options)),
+        }
+    }
+
+    /// Returns the number of the entries in the table recorded by the table builder.
+    /// Returns `None` if the table is built by an older version without this stat.
+    #[inline]
+    pub fn num_entries(&self) -> Option<u64> {
+        self.num_entries
+    }
+
+    /// Returns the bloom filter over all the user keys of the table, which could be
+    /// queried by a `BloomFilter`. Returns `None` if the table is built without it.
+    #[inline]
+    pub fn table_filter(&self) -> Option<Arc<Vec<u8>>> {
+        self.table_filter.clone()
+    }
+
+    /// Returns the number of the data blocks in the table, which is counted by the
+    /// index block without reading any data block
+    pub fn num_data_blocks(&self) -> Result<usize> {
+        let mut iter = self.index_block()?.iter(self.cmp.clone());
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            if self.index_type == IndexType::TwoLevel {
+                let options = Rc::new(ReadOptions::default());
+                let mut partition = self.index_partition(iter.value().as_slice(), options)?;
+                partition.seek_to_first();
+                while partition.valid() {
+                    count += 1;
+                    partition.next();
+                }
+                partition.status()?;
+            } else {
+                count += 1;
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(count)
+    }
+
+    /// Returns the smallest internal key in the table, or `None` for an empty table.
+    /// Only the first data block is read.
+    pub fn smallest_key(&self) -> Result<Option<InternalKey>> {
+        self.boundary_key(true)
+    }
+
+    /// Returns the largest internal key in the table, or `None` for an empty table.
+    /// Only the last data block is read.
+    pub fn largest_key(&self) -> Result<Option<InternalKey>> {
+        self.boundary_key(false)
+    }
+
+    // Returns the first key of the table if `first` is true, otherwise the last one.
+    // The keys in the index block are not used since they might be shortened.
+    fn boundary_key(&self, first: bool) -> Result<Option<InternalKey>> {
+        let mut index_iter = self.index_block()?.iter(self.cmp.clone());
+        if first {
+            index_iter.seek_to_first();
+        } else {
+            index_iter.seek_to_last();
+        }
+        let options = Rc::new(ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        });
+        if self.index_type == IndexType::TwoLevel && index_iter.valid() {
+            let partition = self.index_partition(index_iter.value().as_slice(), options.clone())?;
+            index_iter = partition;
+            if first {
+                index_iter.seek_to_first();
+            } else {
+                index_iter.seek_to_last();
+            }
+        }
+        if !index_iter.valid() {
+            index_iter.status()?;
+            return Ok(None);
+        }
+        let (handle, _) =
+            BlockHandle::decode_from_checked(index_iter.value().as_slice(), self.size)?;
+        let mut iter = self.block_reader(handle, options)?;
+        if first {
+            iter.seek_to_first();
+        } else {
+            iter.seek_to_last();
+        }
+        if iter.valid() {
+            return Ok(Some(InternalKey::decoded_from(iter.key().as_slice())));
+        }
+        iter.status()?;
+        Ok(None)
+    }
+}
+
+/// An iterator over a standalone `Table` which yields the entries with the
+/// user key, the sequence number and the value type decoded from the internal key.
+/// The entries are ordered by the user key and then by the decreasing sequence number.
+pub struct TableIterator {
+    inner: Box<dyn Iterator>,
+}
+
+impl TableIterator {
+    /// Returns true iff the iterator is positioned at an entry
+    #[inline]
+    pub fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    /// Positions at the first entry in the table
+    pub fn seek_to_first(&mut self) {
+        self.inner.seek_to_first()
+    }
+
+    /// Positions at the last entry in the table
+    pub fn seek_to_last(&mut self) {
+        self.inner.seek_to_last()
+    }
+
+    /// Positions at the newest entry of the first user key that is at or past `user_key`
+    pub fn seek(&mut self, user_key: &[u8]) {
+        let target = InternalKey::new(
+            &Slice::from(user_key),
+            MAX_KEY_SEQUENCE,
+            VALUE_TYPE_FOR_SEEK,
+        );
+        self.inner.seek(&Slice::from(target.data()))
+    }
+
+    /// Moves to the next entry.
+    /// REQUIRES: `valid()`
+    pub fn next(&mut self) {
+        self.inner.next()
+    }
+
+    /// Moves to the previous entry.
+    /// REQUIRES: `valid()`
+    pub fn prev(&mut self) {
+        self.inner.prev()
+    }
+
+    /// Returns the decoded internal key of the current entry. The user key
+    /// is valid only until the next modification of the iterator.
+    /// Returns a `Corruption` error if the internal key is malformed.
+    /// REQUIRES: `valid()`
+    pub fn key(&self) -> Result<ParsedInternalKey> {
+        ParsedInternalKey::decode_from(self.inner.key()).ok_or_else(|| {
+            WickErr::new(Status::Corruption, Some("malformed internal key in table"))
+        })
+    }
+
+    /// Returns the value of the current entry.
+    /// REQUIRES: `valid()`
+    pub fn value(&self) -> &[u8] {
+        // Safe because the values are owned by the data block iterator of the table
+        // iterator until it moves
+        unsafe { self.inner.value_slice() }
+    }
+
+    /// Returns the error occurred during the iteration, e.g. a `Corruption`
+    /// error for a block with a mismatched checksum
+    pub fn status(&mut self) -> Result<()> {
+        self.inner.status()
+    }
+}
+
 /// Temporarily stores the contents of the table it is
 /// building in .sst file but does not close the file. It is up to the
 /// caller to close the file after calling `Finish()`.
 pub struct TableBuilder {
     options: Arc<Options>,
     cmp: Arc<dyn Comparator>,
+    // the compression type for blocks in this table
+    compression: CompressionType,
     // underlying sst file
     file: Box<dyn File>,
     // the written data length
     // updated only after the pending_handle is stored in the index block
     offset: u64,
     data_block: BlockBuilder,
+    // the index block of a flat index, or the current partition of a two-level index
     index_block: BlockBuilder,
+    // the finished partitions of a two-level index with their last keys, which are
+    // written after all the data blocks
+    index_partitions: Vec<(Vec<u8>, Vec<u8>)>,
     // the last added key
     // can be used when adding a new entry into index block
     last_key: Vec<u8>,
@@ -277,6 +1123,10 @@ pub struct TableBuilder {
     num_entries: usize,
     closed: bool,
     filter_block: Option<FilterBlockBuilder>,
+    // the distinct user keys for the whole-table filter
+    table_filter_keys: Option<Vec<Vec<u8>>>,
+    // the internal keys and the values of the range tombstones added
+    range_tombstones: Vec<(Vec<u8>, Vec<u8>)>,
     // indicates iff we have to add a index to index_block
     //
     // We do not emit the index entry for a block until we have seen the
@@ -288,32 +1138,49 @@ pub struct TableBuilder {
 }
 
 impl TableBuilder {
-    pub fn new(file: Box<dyn File>, options: Arc<Options>) -> Self {
+    /// Creates a `TableBuilder` whose blocks are compressed by `compression`
+    pub fn new(file: Box<dyn File>, compression: CompressionType, options: Arc<Options>) -> Self {
         let opt = options.clone();
-        let db_builder =
-            BlockBuilder::new(options.block_restart_interval, options.comparator.clone());
-        let ib_builder =
-            BlockBuilder::new(options.block_restart_interval, options.comparator.clone());
+        let user_cmp: Arc<dyn Comparator> = if options.disable_key_shortening {
+            Arc::new(NoShorteningComparator::new(options.comparator.clone()))
+        } else {
+            options.comparator.clone()
+        };
+        let cmp: Arc<dyn Comparator> = Arc::new(InternalKeyComparator::new(user_cmp));
+        let db_builder = BlockBuilder::new(options.block_restart_interval, cmp.clone());
+        // Every index entry is a restart point so seeking the index for the data block
+        // of a key is a binary search on the restart array without any linear scan
+        let ib_builder = BlockBuilder::new(1, cmp.clone());
         let fb = {
-            if let Some(policy) = opt.filter_policy.clone() {
-                let mut f = FilterBlockBuilder::new(policy.clone());
-                f.start_block(0);
-                Some(f)
-            } else {
-                None
+            match opt.filter_policy.clone() {
+                // a prefix filter is useless without the prefix extractor
+                Some(policy) if policy.whole_key_filtering() || opt.prefix_extractor.is_some() => {
+                    let mut f = FilterBlockBuilder::new(policy.clone(), opt.filter_base_lg);
+                    f.start_block(0);
+                    Some(f)
+                }
+                _ => None,
             }
         };
         Self {
             options: opt,
             file,
-            cmp: options.comparator.clone(),
+            cmp,
+            compression,
             offset: 0,
             data_block: db_builder,
             index_block: ib_builder,
+            index_partitions: vec![],
             last_key: vec![],
             num_entries: 0,
             closed: false,
             filter_block: fb,
+            table_filter_keys: if options.table_filter_bits_per_key > 0 {
+                Some(vec![])
+            } else {
+                None
+            },
+            range_tombstones: vec![],
             pending_index_entry: false,
             pending_handle: BlockHandle::new(0, 0),
         }
@@ -330,18 +1197,46 @@ impl TableBuilder {
     ///
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
         self.assert_not_closed();
-        if self.num_entries > 0 {
-            assert_ne!(
-                self.cmp.compare(key, &self.last_key.as_slice()),
-                Ordering::Greater,
-                "[table builder] new key is inconsistent with the last key in sstable"
-            )
+        if self.num_entries > 0
+            && self.cmp.compare(key, self.last_key.as_slice()) != Ordering::Greater
+        {
+            let context = format!(
+                "new key {:?}, last key {:?}",
+                InternalKey::decoded_from(key),
+                InternalKey::decoded_from(self.last_key.as_slice()),
+            );
+            let msg = "[table builder] new key is not greater than the last key in sstable";
+            if self.options.paranoid_checks {
+                return Err(WickErr::new(Status::Corruption, Some(msg)).with_context(context));
+            }
+            panic!("{}: {}", msg, context);
         }
         // Check iff we need to create a new index entry
         self.maybe_append_index_block(Some(key));
         // Update filter block
         if let Some(fb) = self.filter_block.as_mut() {
-            fb.add_key(&Slice::from(key))
+            // the filter is built on the user keys
+            let user_key = extract_user_key(key);
+            if fb.whole_key_filtering() {
+                fb.add_key(&user_key);
+            }
+            if let Some(extractor) = &self.options.prefix_extractor {
+                if extractor.in_domain(user_key.as_slice()) {
+                    fb.add_key(&Slice::from(extractor.transform(user_key.as_slice())))
+                }
+            }
+        }
+        if let Some(keys) = self.table_filter_keys.as_mut() {
+            let user_key = extract_user_key(key);
+            if keys.last().map(|k| k.as_slice()) != Some(user_key.as_slice()) {
+                keys.push(Vec::from(user_key.as_slice()));
+            }
+        }
+        if key.len() >= 8
+            && decode_fixed_64(&key[key.len() - 8..]) & 0xff == ValueType::RangeDeletion as u64
+        {
+            self.range_tombstones
+                .push((Vec::from(key), Vec::from(value)));
         }
         // TODO: avoid the copy
         self.last_key.resize(key.len(), 0);
@@ -369,15 +1264,37 @@ impl TableBuilder {
         self.assert_not_closed();
         if !self.data_block.is_empty() {
             assert!(!self.pending_index_entry, "[table builder] the index for the previous data block should never remain when flushing current block data");
-            let data_block = self.data_block.finish();
-            let (compressed, compression) = compress_block(data_block, self.options.compression)?;
-            write_raw_block(
-                self.file.as_mut(),
-                compressed.as_slice(),
-                compression,
-                &mut self.pending_handle,
-                &mut self.offset,
-            )?;
+            match self.compression {
+                CompressionType::NoCompression | CompressionType::Unknown => {
+                    // The checksum of an uncompressed block is computed while the
+                    // entries are added so the block is written without another pass
+                    self.request_rate_limiter(
+                        self.data_block.current_size_estimate() + BLOCK_TRAILER_SIZE,
+                    );
+                    let crc = self.data_block.checksum();
+                    write_raw_block_with_crc(
+                        self.file.as_mut(),
+                        self.data_block.finish(),
+                        crc,
+                        CompressionType::NoCompression,
+                        &mut self.pending_handle,
+                        &mut self.offset,
+                    )?;
+                }
+                _ => {
+                    let data_block = self.data_block.finish();
+                    let (compressed, compression) =
+                        compress_block(data_block, self.compression, &self.options)?;
+                    self.request_rate_limiter(compressed.len() + BLOCK_TRAILER_SIZE);
+                    write_raw_block(
+                        self.file.as_mut(),
+                        compressed.as_slice(),
+                        compression,
+                        &mut self.pending_handle,
+                        &mut self.offset,
+                    )?;
+                }
+            }
             self.data_block.reset();
             self.pending_index_entry = true;
             if let Err(e) = self.file.flush() {
@@ -408,6 +1325,9 @@ impl TableBuilder {
         let mut has_filter_block = false;
         if let Some(fb) = &mut self.filter_block {
             let data = fb.finish();
+            if let Some(limiter) = &self.options.rate_limiter {
+                limiter.request(data.len() + BLOCK_TRAILER_SIZE);
+            }
             write_raw_block(
                 self.file.as_mut(),
                 data,
@@ -417,11 +1337,41 @@ impl TableBuilder {
             )?;
             has_filter_block = true;
         }
+        // write the whole-table filter
+        let mut table_filter_handle = None;
+        if let Some(keys) = self.table_filter_keys.take() {
+            let data = BloomFilter::new(self.options.table_filter_bits_per_key)
+                .create_filter(keys.as_slice());
+            self.request_rate_limiter(data.len() + BLOCK_TRAILER_SIZE);
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                self.file.as_mut(),
+                data.as_slice(),
+                CompressionType::NoCompression,
+                &mut handle,
+                &mut self.offset,
+            )?;
+            table_filter_handle = Some(handle);
+        }
+        // write the range tombstones
+        let mut range_del_handle = None;
+        if !self.range_tombstones.is_empty() {
+            let mut builder = BlockBuilder::new(1, self.cmp.clone());
+            for (key, value) in self.range_tombstones.iter() {
+                builder.add(key.as_slice(), value.as_slice());
+            }
+            let mut handle = BlockHandle::new(0, 0);
+            let block = Vec::from(builder.finish());
+            self.write_block(block.as_slice(), &mut handle)?;
+            range_del_handle = Some(handle);
+        }
 
         // write meta block
         let mut meta_block_handle = BlockHandle::new(0, 0);
-        let mut meta_block_builder =
-            BlockBuilder::new(self.options.block_restart_interval, self.cmp.clone());
+        let mut meta_block_builder = BlockBuilder::new(
+            self.options.block_restart_interval,
+            Arc::new(BytewiseComparator::new()),
+        );
         let meta_block = {
             if has_filter_block {
                 let filter_key = if let Some(fp) = &self.options.filter_policy {
@@ -434,15 +1384,33 @@ impl TableBuilder {
                     filter_block_handler.encoded().as_slice(),
                 );
             }
+            // the keys are sorted:
+            // "filter.*" < "index.*" < "rangedel" < "stats.*" < "table_filter"
+            if self.options.index_type == IndexType::TwoLevel {
+                meta_block_builder.add(TWO_LEVEL_INDEX_KEY.as_bytes(), &[]);
+            }
+            if let Some(handle) = &range_del_handle {
+                meta_block_builder.add(RANGE_DEL_KEY.as_bytes(), handle.encoded().as_slice());
+            }
+            let mut num_entries = vec![];
+            put_fixed_64(&mut num_entries, self.num_entries as u64);
+            meta_block_builder.add(NUM_ENTRIES_STAT.as_bytes(), num_entries.as_slice());
+            if let Some(handle) = &table_filter_handle {
+                meta_block_builder.add(TABLE_FILTER_KEY.as_bytes(), handle.encoded().as_slice());
+            }
             meta_block_builder.finish()
         };
         self.write_block(meta_block, &mut meta_block_handle)?;
 
         // Write index block
         self.maybe_append_index_block(None); // flush the last index first
+        if self.options.index_type == IndexType::TwoLevel {
+            self.write_index_partitions()?;
+        }
         let index_block = self.index_block.finish();
         let mut index_block_handle = BlockHandle::new(0, 0);
-        let (c_index_block, ct) = compress_block(index_block, self.options.compression)?;
+        let (c_index_block, ct) = compress_block(index_block, self.compression, &self.options)?;
+        self.request_rate_limiter(c_index_block.len() + BLOCK_TRAILER_SIZE);
         write_raw_block(
             self.file.as_mut(),
             c_index_block.as_slice(),
@@ -453,6 +1421,7 @@ impl TableBuilder {
         self.index_block.reset();
         // write footer
         let footer = Footer::new(meta_block_handle, index_block_handle).encoded();
+        self.request_rate_limiter(footer.len());
         self.file.write(footer.as_slice())?;
         self.offset += footer.len() as u64;
         if sync {
@@ -480,6 +1449,12 @@ impl TableBuilder {
         self.num_entries
     }
 
+    /// Returns true if any range tombstone is added so far.
+    #[inline]
+    pub fn has_range_tombstones(&self) -> bool {
+        !self.range_tombstones.is_empty()
+    }
+
     /// Returns size of the file generated so far. If invoked after a successful
     /// `Finish` call, returns the size of the final generated file.
     #[inline]
@@ -510,13 +1485,50 @@ impl TableBuilder {
             self.index_block
                 .add(s.as_slice(), handle_encoding.as_slice());
             self.pending_index_entry = false;
+            if self.options.index_type == IndexType::TwoLevel
+                && self.index_block.current_size_estimate() >= self.options.index_partition_size
+            {
+                self.cut_index_partition(s);
+            }
             return true;
         }
         false
     }
 
+    // Blocks until the configured rate limiter allows writing `bytes` bytes
+    #[inline]
+    fn request_rate_limiter(&self, bytes: usize) {
+        if let Some(limiter) = &self.options.rate_limiter {
+            limiter.request(bytes);
+        }
+    }
+
+    // Finishes the current partition of a two-level index whose last key is `last_key`
+    fn cut_index_partition(&mut self, last_key: Vec<u8>) {
+        let partition = Vec::from(self.index_block.finish());
+        self.index_block.reset();
+        self.index_partitions.push((last_key, partition));
+    }
+
+    // Writes all the partitions of a two-level index and leaves the top-level index
+    // pointing to the partitions in `index_block`
+    fn write_index_partitions(&mut self) -> Result<()> {
+        if !self.index_block.is_empty() {
+            let last_key = self.index_block.last_key().to_vec();
+            self.cut_index_partition(last_key);
+        }
+        for (last_key, partition) in std::mem::take(&mut self.index_partitions) {
+            let mut handle = BlockHandle::new(0, 0);
+            self.write_block(partition.as_slice(), &mut handle)?;
+            self.index_block
+                .add(last_key.as_slice(), handle.encoded().as_slice());
+        }
+        Ok(())
+    }
+
     fn write_block(&mut self, raw_block: &[u8], handle: &mut BlockHandle) -> Result<()> {
-        let (data, compression) = compress_block(raw_block, self.options.compression)?;
+        let (data, compression) = compress_block(raw_block, self.compression, &self.options)?;
+        self.request_rate_limiter(data.len() + BLOCK_TRAILER_SIZE);
         write_raw_block(
             self.file.as_mut(),
             &data,
@@ -533,6 +1545,7 @@ impl TableBuilder {
 fn compress_block(
     raw_block: &[u8],
     compression: CompressionType,
+    options: &Options,
 ) -> Result<(Vec<u8>, CompressionType)> {
     match compression {
         CompressionType::SnappyCompression => {
@@ -551,6 +1564,37 @@ fn compress_block(
             }
             Ok((buffer, CompressionType::SnappyCompression))
         }
+        CompressionType::ZstdCompression => {
+            // Use the frame format so that the decompressed size is recorded
+            match zstd::encode_all(raw_block, options.zstd_compression_level) {
+                Ok(compressed) => Ok((compressed, CompressionType::ZstdCompression)),
+                Err(e) => Err(WickErr::new_from_raw(
+                    Status::CompressionError,
+                    None,
+                    Box::new(e),
+                )),
+            }
+        }
+        CompressionType::Lz4Compression => Ok((
+            // The decompressed size is prepended for decoding
+            lz4_flex::compress_prepend_size(raw_block),
+            CompressionType::Lz4Compression,
+        )),
+        CompressionType::Custom(tag) => {
+            if tag < MIN_CUSTOM_COMPRESSION_TAG {
+                return Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some("custom compression tag is reserved for builtin compression types"),
+                ));
+            }
+            match options.compressors.get(&tag) {
+                Some(compressor) => Ok((compressor.compress(raw_block), compression)),
+                None => Err(WickErr::new(
+                    Status::InvalidArgument,
+                    Some("no compressor registered for the custom compression type"),
+                )),
+            }
+        }
         CompressionType::NoCompression | CompressionType::Unknown => {
             Ok((Vec::from(raw_block), CompressionType::NoCompression))
         }
@@ -564,6 +1608,18 @@ fn write_raw_block(
     compression: CompressionType,
     handle: &mut BlockHandle,
     offset: &mut u64,
+) -> Result<()> {
+    write_raw_block_with_crc(file, data, value(data), compression, handle, offset)
+}
+
+// Same as `write_raw_block` but uses the given unmasked CRC-32C `data_crc` of `data`
+fn write_raw_block_with_crc(
+    file: &mut dyn File,
+    data: &[u8],
+    data_crc: u32,
+    compression: CompressionType,
+    handle: &mut BlockHandle,
+    offset: &mut u64,
 ) -> Result<()> {
     // write block data
     file.write(data)?;
@@ -572,8 +1628,9 @@ fn write_raw_block(
     handle.set_size(data.len() as u64);
     // write trailer
     let mut trailer = vec![];
-    trailer.push(compression as u8);
-    let crc = mask(extend(value(data), &[compression as u8]));
+    let tag = u8::from(compression);
+    trailer.push(tag);
+    let crc = mask(extend(data_crc, &[tag]));
     put_fixed_32(&mut trailer, crc);
     assert_eq!(trailer.len(), BLOCK_TRAILER_SIZE);
     file.write(trailer.as_slice())?;
@@ -583,11 +1640,112 @@ fn write_raw_block(
 }
 
 /// Read the block identified from `file` according to the given `handle`.
-/// If the read data does not match the checksum, return a error marked as `Status::Corruption`
-pub fn read_block(file: &dyn File, handle: &BlockHandle, verify_checksum: bool) -> Result<Vec<u8>> {
-    let n = handle.size as usize;
-    let mut buffer = vec![0; n + BLOCK_TRAILER_SIZE];
-    file.read_exact_at(buffer.as_mut_slice(), handle.offset)?;
+/// If the read data does not match the checksum, return a error marked as `Status::Corruption`.
+/// Blocks compressed by a `CompressionType::Custom` are decompressed by the compressor
+/// registered in `options`.
+pub fn read_block(
+    file: &dyn File,
+    handle: &BlockHandle,
+    verify_checksum: bool,
+    options: &Options,
+) -> Result<Vec<u8>> {
+    read_block_contents(file, handle, verify_checksum, options).map_err(|e| {
+        e.with_context(format!(
+            "block at offset {} with size {}",
+            handle.offset(),
+            handle.size()
+        ))
+    })
+}
+
+/// A `ReadaheadBuffer` holds the `size` bytes of a table file following the last
+/// block read from the file, so that the sequential reads of the blocks become a
+/// few large reads of the file
+pub struct ReadaheadBuffer {
+    size: usize,
+    // the offset in the file of `buffer`
+    offset: u64,
+    buffer: Vec<u8>,
+}
+
+impl ReadaheadBuffer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            offset: 0,
+            buffer: vec![],
+        }
+    }
+
+    /// Like `read_block` but the block is taken from the buffer if possible. Otherwise
+    /// the buffer is refilled from the start of the block in the file which is
+    /// `file_size` long, or up to the end of the block if the block is before the
+    /// buffer so that a backward scan is served by the buffer as well.
+    pub fn read_block(
+        &mut self,
+        file: &dyn File,
+        file_size: u64,
+        handle: &BlockHandle,
+        verify_checksum: bool,
+        options: &Options,
+    ) -> Result<Vec<u8>> {
+        let n = handle.size() as usize + BLOCK_TRAILER_SIZE;
+        let offset = handle.offset();
+        if offset < self.offset || offset + n as u64 > self.offset + self.buffer.len() as u64 {
+            let start = if offset < self.offset {
+                (offset + n as u64)
+                    .saturating_sub(self.size as u64)
+                    .min(offset)
+            } else {
+                offset
+            };
+            let len = (self.size as u64).min(file_size.saturating_sub(start)) as usize;
+            self.buffer
+                .resize(len.max((offset - start) as usize + n), 0);
+            if let Err(e) = file.read_exact_at(self.buffer.as_mut_slice(), start) {
+                self.buffer.clear();
+                return Err(e.with_context(format!(
+                    "block at offset {} with size {}",
+                    offset,
+                    handle.size()
+                )));
+            }
+            self.offset = start;
+        }
+        let start = (offset - self.offset) as usize;
+        decode_block_contents(
+            Vec::from(&self.buffer[start..start + n]),
+            verify_checksum,
+            options,
+        )
+        .map_err(|e| {
+            e.with_context(format!(
+                "block at offset {} with size {}",
+                offset,
+                handle.size()
+            ))
+        })
+    }
+}
+
+fn read_block_contents(
+    file: &dyn File,
+    handle: &BlockHandle,
+    verify_checksum: bool,
+    options: &Options,
+) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; handle.size() as usize + BLOCK_TRAILER_SIZE];
+    file.read_exact_at(buffer.as_mut_slice(), handle.offset())?;
+    decode_block_contents(buffer, verify_checksum, options)
+}
+
+// Verifies and decompresses the block read into `buffer` with its trailer
+fn decode_block_contents(
+    mut buffer: Vec<u8>,
+    verify_checksum: bool,
+    options: &Options,
+) -> Result<Vec<u8>> {
+    let n = buffer.len() - BLOCK_TRAILER_SIZE;
     if verify_checksum {
         let crc = unmask(decode_fixed_32(&buffer.as_slice()[n + 1..]));
         // Compression type is included in CRC checksum
@@ -630,6 +1788,37 @@ pub fn read_block(file: &dyn File, handle: &BlockHandle, verify_checksum: bool)
                 }
                 decompressed
             }
+            CompressionType::ZstdCompression => match zstd::decode_all(&buffer.as_slice()[..n]) {
+                Ok(decompressed) => decompressed,
+                Err(e) => {
+                    return Err(WickErr::new_from_raw(
+                        Status::CompressionError,
+                        None,
+                        Box::new(e),
+                    ));
+                }
+            },
+            CompressionType::Lz4Compression => {
+                match lz4_flex::decompress_size_prepended(&buffer.as_slice()[..n]) {
+                    Ok(decompressed) => decompressed,
+                    Err(e) => {
+                        return Err(WickErr::new_from_raw(
+                            Status::CompressionError,
+                            None,
+                            Box::new(e),
+                        ));
+                    }
+                }
+            }
+            CompressionType::Custom(tag) => match options.compressors.get(&tag) {
+                Some(compressor) => compressor.decompress(&buffer.as_slice()[..n])?,
+                None => {
+                    return Err(WickErr::new(
+                        Status::Corruption,
+                        Some("no compressor registered for block compression type"),
+                    ))
+                }
+            },
             CompressionType::Unknown => {
                 return Err(WickErr::new(
                     Status::Corruption,
@@ -643,5 +1832,1469 @@ pub fn read_block(file: &dyn File, handle: &BlockHandle, verify_checksum: bool)
 
 #[cfg(test)]
 mod tests {
-    // TODO: add tests case after finishing the storage
+    use super::*;
+    use crate::cache::lru::SharedLRUCache;
+    use crate::cache::Cache;
+    use crate::filter::bloom::BloomFilter;
+    use crate::options::Compressor;
+    use crate::storage::mem::{InmemFile, MemStorage};
+    use crate::storage::Storage;
+    use crate::util::comparator::ReverseComparator;
+
+    fn raw_block_data() -> Vec<u8> {
+        let mut data = vec![];
+        for i in 0..1000u32 {
+            data.extend_from_slice(format!("key{:05}value{:05}", i, i % 7).as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_compress_block_round_trip() {
+        let raw = raw_block_data();
+        for &(compression, level) in &[
+            (CompressionType::NoCompression, 0),
+            (CompressionType::SnappyCompression, 0),
+            (CompressionType::ZstdCompression, 1),
+            (CompressionType::ZstdCompression, 3),
+            (CompressionType::ZstdCompression, 19),
+            (CompressionType::Lz4Compression, 0),
+        ] {
+            let options = Options {
+                zstd_compression_level: level,
+                ..Default::default()
+            };
+            let (compressed, ct) = compress_block(raw.as_slice(), compression, &options).unwrap();
+            assert_eq!(ct, compression);
+            if compression != CompressionType::NoCompression {
+                assert!(compressed.len() < raw.len());
+            }
+            let mut file = InmemFile::new("test");
+            let mut handle = BlockHandle::new(0, 0);
+            let mut offset = 0;
+            write_raw_block(
+                &mut file,
+                compressed.as_slice(),
+                ct,
+                &mut handle,
+                &mut offset,
+            )
+            .unwrap();
+            assert_eq!(offset, (compressed.len() + BLOCK_TRAILER_SIZE) as u64);
+            let decompressed = read_block(&file, &handle, true, &options).unwrap();
+            assert_eq!(decompressed, raw);
+        }
+    }
+
+    // Writes `data` with a trailer of the given type tag and a valid checksum
+    fn write_block_with_tag(data: &[u8], tag: u8) -> InmemFile {
+        let mut file = InmemFile::new("test");
+        file.write(data).unwrap();
+        let mut trailer = vec![tag];
+        put_fixed_32(&mut trailer, mask(extend(value(data), &[tag])));
+        file.write(trailer.as_slice()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_block_with_unknown_compression_type() {
+        let data = raw_block_data();
+        let options = Options::default();
+        let handle = BlockHandle::new(0, data.len() as u64);
+        // A reserved tag and a custom tag without any registered compressor
+        for &tag in &[MIN_CUSTOM_COMPRESSION_TAG - 1, 200u8] {
+            let file = write_block_with_tag(data.as_slice(), tag);
+            let e = read_block(&file, &handle, true, &options).unwrap_err();
+            assert_eq!(e.status(), Status::Corruption);
+        }
+    }
+
+    // Reverses the data and prepends a marker byte
+    struct ReverseCompressor;
+
+    impl Compressor for ReverseCompressor {
+        fn compress(&self, data: &[u8]) -> Vec<u8> {
+            let mut result = vec![0xab];
+            result.extend(data.iter().rev());
+            result
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            if data.first() != Some(&0xab) {
+                return Err(WickErr::new(Status::CompressionError, Some("bad marker")));
+            }
+            Ok(data[1..].iter().rev().cloned().collect())
+        }
+    }
+
+    #[test]
+    fn test_custom_compressor_round_trip() {
+        let raw = raw_block_data();
+        let tag = MIN_CUSTOM_COMPRESSION_TAG + 1;
+        let mut options = Options::default();
+        options
+            .compressors
+            .insert(tag, Arc::new(ReverseCompressor {}));
+        let (compressed, ct) =
+            compress_block(raw.as_slice(), CompressionType::Custom(tag), &options).unwrap();
+        assert_eq!(ct, CompressionType::Custom(tag));
+        let mut file = InmemFile::new("test");
+        let mut handle = BlockHandle::new(0, 0);
+        let mut offset = 0;
+        write_raw_block(
+            &mut file,
+            compressed.as_slice(),
+            ct,
+            &mut handle,
+            &mut offset,
+        )
+        .unwrap();
+        assert_eq!(file.get_pos_and_data().1[compressed.len()], tag);
+        assert_eq!(read_block(&file, &handle, true, &options).unwrap(), raw);
+
+        // Reading without the compressor registered fails cleanly
+        let e = read_block(&file, &handle, true, &Options::default()).unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+    }
+
+    #[test]
+    fn test_compress_block_with_invalid_custom_type() {
+        let options = Options::default();
+        let raw = raw_block_data();
+        for &tag in &[1u8, MIN_CUSTOM_COMPRESSION_TAG] {
+            let e =
+                compress_block(raw.as_slice(), CompressionType::Custom(tag), &options).unwrap_err();
+            assert_eq!(e.status(), Status::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_read_block_detects_corrupted_compression_type() {
+        let raw = raw_block_data();
+        let options = Options::default();
+        for &(compression, corrupted_type) in &[
+            (
+                CompressionType::ZstdCompression,
+                CompressionType::SnappyCompression,
+            ),
+            (
+                CompressionType::Lz4Compression,
+                CompressionType::NoCompression,
+            ),
+        ] {
+            let (compressed, ct) = compress_block(raw.as_slice(), compression, &options).unwrap();
+            let mut file = InmemFile::new("test");
+            let mut handle = BlockHandle::new(0, 0);
+            let mut offset = 0;
+            write_raw_block(
+                &mut file,
+                compressed.as_slice(),
+                ct,
+                &mut handle,
+                &mut offset,
+            )
+            .unwrap();
+            let mut contents = file.get_pos_and_data().1.to_vec();
+            contents[compressed.len()] = u8::from(corrupted_type);
+            let mut corrupted = InmemFile::new("corrupted");
+            corrupted.write(contents.as_slice()).unwrap();
+            let e = read_block(&corrupted, &handle, true, &options).unwrap_err();
+            assert_eq!(e.status(), Status::Corruption);
+        }
+    }
+
+    #[test]
+    fn test_open_table_with_handle_beyond_eof() {
+        let mut file = InmemFile::new("test");
+        file.write(&[0u8; 100]).unwrap();
+        // The index block handle points to the data beyond the end of file
+        let footer = Footer::new(BlockHandle::new(0, 0), BlockHandle::new(50, 100)).encoded();
+        file.write(footer.as_slice()).unwrap();
+        let size = file.len().unwrap();
+        match Table::open(Box::new(file), size, Arc::new(Options::default())) {
+            Ok(_) => panic!("opening a table with bad footer should fail"),
+            Err(e) => assert_eq!(e.status(), Status::Corruption),
+        }
+    }
+
+    #[test]
+    fn test_range_tombstones_block() {
+        let storage = MemStorage::default();
+        let options = Arc::new(Options {
+            index_type: IndexType::TwoLevel,
+            table_filter_bits_per_key: 10,
+            ..Default::default()
+        });
+        let file = storage.create("rangedel").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+        let entries = [
+            ("a", 5, ValueType::Value, "a"),
+            ("b", 9, ValueType::RangeDeletion, "d"),
+            ("b", 3, ValueType::Value, "b"),
+            ("c", 4, ValueType::RangeDeletion, "z"),
+            ("e", 1, ValueType::Deletion, ""),
+        ];
+        for (k, seq, t, v) in entries.iter() {
+            let ikey = InternalKey::new(&Slice::from(*k), *seq, *t);
+            builder.add(ikey.data(), v.as_bytes()).unwrap();
+        }
+        builder.finish(true).unwrap();
+        let file = storage.open("rangedel").unwrap();
+        let size = file.len().unwrap();
+        let table = Table::open(file, size, options.clone()).unwrap();
+        assert_eq!(
+            table.range_tombstones(),
+            &[
+                RangeTombstone::new(b"b", b"d", 9),
+                RangeTombstone::new(b"c", b"z", 4)
+            ][..]
+        );
+        // the other meta entries are still found
+        assert_eq!(table.index_type, IndexType::TwoLevel);
+        assert!(table.table_filter().is_some());
+        assert_eq!(table.num_entries(), Some(5));
+
+        // a corrupted range tombstone block fails the table to be opened
+        let mut contents = vec![];
+        storage
+            .open("rangedel")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        let mut entry = InternalKey::new(&Slice::from("c"), 4, ValueType::RangeDeletion)
+            .data()
+            .to_vec();
+        entry.push(b'z');
+        // the last one is in the range tombstone block written after the data blocks
+        let offset = contents
+            .windows(entry.len())
+            .rposition(|w| w == entry.as_slice())
+            .unwrap();
+        // flips the value type of the tombstone
+        contents[offset + 1] ^= 0xff;
+        let mut file = storage.create("corrupted").unwrap();
+        file.write(contents.as_slice()).unwrap();
+        let file = storage.open("corrupted").unwrap();
+        let e = Table::open(file, contents.len() as u64, options)
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::Corruption);
+    }
+
+    #[test]
+    fn test_incremental_block_checksum() {
+        use crate::util::comparator::BytewiseComparator;
+
+        let mut builder = BlockBuilder::new(4, Arc::new(BytewiseComparator::new()));
+        for round in 0..2 {
+            for i in 0..100 {
+                builder.add(format!("key{:03}{}", i, round).as_bytes(), b"value");
+            }
+            let crc = builder.checksum();
+            let data = Vec::from(builder.finish());
+            assert_eq!(crc, value(&data));
+            assert_eq!(builder.checksum(), crc);
+            // the trailers are identical to the ones computed from the whole block
+            let (mut f1, mut f2) = (InmemFile::new("f1"), InmemFile::new("f2"));
+            let (mut h1, mut h2) = (BlockHandle::new(0, 0), BlockHandle::new(0, 0));
+            let (mut o1, mut o2) = (0, 0);
+            let ct = CompressionType::NoCompression;
+            write_raw_block(&mut f1, &data, ct, &mut h1, &mut o1).unwrap();
+            write_raw_block_with_crc(&mut f2, &data, crc, ct, &mut h2, &mut o2).unwrap();
+            let (mut b1, mut b2) = (vec![], vec![]);
+            f1.read_all(&mut b1).unwrap();
+            f2.read_all(&mut b2).unwrap();
+            assert_eq!(b1, b2);
+            builder.reset();
+        }
+        // an empty block only contains the restarts
+        let crc = builder.checksum();
+        assert_eq!(crc, value(builder.finish()));
+    }
+
+    // Returns the internal key of a value of `key` at sequence 1
+    fn ikey(key: &str) -> Vec<u8> {
+        Vec::from(InternalKey::new(&Slice::from(key), 1, ValueType::Value).data())
+    }
+
+    // Builds a table with 100 entries in several data blocks using the given block cache
+    fn new_test_table(cache: Arc<dyn Cache<Arc<Block>>>) -> (Arc<Table>, Vec<String>) {
+        let options = Arc::new(Options {
+            block_size: 256,
+            block_cache: Some(cache),
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let file = storage.create("test_table").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i)).collect();
+        for k in keys.iter() {
+            builder
+                .add(ikey(k).as_slice(), b"value_value_value_value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+        let file = storage.open("test_table").unwrap();
+        let size = file.len().unwrap();
+        (Arc::new(Table::open(file, size, options).unwrap()), keys)
+    }
+
+    // A file recording the offsets read from it
+    struct CountingFile {
+        inner: Box<dyn File>,
+        reads: Rc<RefCell<Vec<u64>>>,
+    }
+
+    impl File for CountingFile {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> Result<()> {
+            self.inner.flush()
+        }
+        fn close(&mut self) -> Result<()> {
+            self.inner.close()
+        }
+        fn seek(&mut self, pos: std::io::SeekFrom) -> Result<u64> {
+            self.inner.seek(pos)
+        }
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.inner.read(buf)
+        }
+        fn read_all(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            self.inner.read_all(buf)
+        }
+        fn len(&self) -> Result<u64> {
+            self.inner.len()
+        }
+        fn lock(&self) -> Result<()> {
+            self.inner.lock()
+        }
+        fn unlock(&self) -> Result<()> {
+            self.inner.unlock()
+        }
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+            self.reads.borrow_mut().push(offset);
+            self.inner.read_at(buf, offset)
+        }
+    }
+
+    #[test]
+    fn test_cache_index_and_filter_blocks() {
+        let storage = MemStorage::default();
+        let build_options = Arc::new(Options {
+            block_size: 256,
+            filter_policy: Some(Arc::new(BloomFilter::new(10))),
+            ..Default::default()
+        });
+        let file = storage.create("test_table").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, build_options);
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i)).collect();
+        for k in keys.iter() {
+            builder
+                .add(ikey(k).as_slice(), b"value_value_value_value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+
+        // Opens the table and returns the number of the index block reads after
+        // reading all the keys
+        let index_reads = |cache: Arc<dyn Cache<Arc<Block>>>, pin: bool| {
+            let options = Arc::new(Options {
+                filter_policy: Some(Arc::new(BloomFilter::new(10))),
+                block_cache: Some(cache.clone()),
+                cache_index_and_filter_blocks: true,
+                ..Default::default()
+            });
+            let reads = Rc::new(RefCell::new(vec![]));
+            let file = CountingFile {
+                inner: storage.open("test_table").unwrap(),
+                reads: reads.clone(),
+            };
+            let size = file.len().unwrap();
+            let table =
+                Arc::new(Table::open_with_pinning(Box::new(file), size, options, pin).unwrap());
+            let index_offset = table.index_handle.offset();
+            let read_opt = Rc::new(ReadOptions::default());
+            let index_reads = || {
+                reads
+                    .borrow()
+                    .iter()
+                    .filter(|o| **o == index_offset)
+                    .count()
+            };
+            // the index block and the filter are loaded into the cache by the open
+            assert_eq!(index_reads(), 1);
+            assert!(table.index_block.is_none() && table.filter_reader.is_none());
+            // warm up
+            table
+                .internal_get(read_opt.clone(), ikey(&keys[0]).as_slice())
+                .unwrap()
+                .unwrap();
+            for k in keys.iter() {
+                let (_, _, v) = table
+                    .internal_get(read_opt.clone(), ikey(k).as_slice())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(v.as_slice(), b"value_value_value_value");
+                assert!(table.key_may_match(ikey(k).as_slice()));
+            }
+            // the missing keys are rejected by the cached filter
+            let data_reads = reads.borrow().len();
+            assert!(!table.key_may_match(ikey("key0005").as_slice()));
+            if pin {
+                assert_eq!(reads.borrow().len(), data_reads);
+            }
+            let res = index_reads();
+            // the pinned blocks are released with the table
+            drop(table);
+            cache.prune();
+            assert_eq!(cache.total_charge(), 0);
+            res
+        };
+        // no extra index block reads after the warm-up
+        assert_eq!(
+            index_reads(Arc::new(SharedLRUCache::new(1 << 20)), false),
+            1
+        );
+        // the unpinned index block is evicted by the data blocks in a tiny cache
+        let tiny_cache = || Arc::new(SharedLRUCache::with_config(1, 0, None));
+        assert!(index_reads(tiny_cache(), false) > 1);
+        assert_eq!(index_reads(tiny_cache(), true), 1);
+    }
+
+    #[test]
+    fn test_table_metadata() {
+        let options = Arc::new(Options {
+            block_size: 256,
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder = TableBuilder::new(
+            storage.create("test_table").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            builder.add(ikey(&k).as_slice(), b"value_value").unwrap();
+        }
+        builder.finish(true).unwrap();
+        let file = storage.open("test_table").unwrap();
+        let size = file.len().unwrap();
+        let table = Table::open(file, size, options.clone()).unwrap();
+        assert_eq!(table.num_entries(), Some(100));
+        let num_blocks = table.num_data_blocks().unwrap();
+        assert!(num_blocks > 1);
+        let smallest = table.smallest_key().unwrap().unwrap();
+        assert_eq!(smallest.user_key(), b"key000");
+        assert_eq!(smallest.parsed().unwrap().seq, 1);
+        let largest = table.largest_key().unwrap().unwrap();
+        assert_eq!(largest.user_key(), b"key099");
+
+        // a table without the stats in the meta block
+        let mut contents = vec![];
+        storage
+            .open("test_table")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        let footer_offset = contents.len() - FOOTER_ENCODED_LENGTH;
+        let (footer, _) = Footer::decode_from(&contents[footer_offset..]).unwrap();
+        contents.truncate(footer_offset);
+        contents.extend(Footer::new(BlockHandle::new(0, 0), footer.index_handle).encoded());
+        let mut file = storage.create("old_table").unwrap();
+        file.write(contents.as_slice()).unwrap();
+        let table = Table::open(file, size, options.clone()).unwrap();
+        assert_eq!(table.num_entries(), None);
+        assert_eq!(table.num_data_blocks().unwrap(), num_blocks);
+        assert_eq!(table.largest_key().unwrap().unwrap().user_key(), b"key099");
+
+        // an empty table
+        let mut builder = TableBuilder::new(
+            storage.create("empty").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        builder.finish(true).unwrap();
+        let file = storage.open("empty").unwrap();
+        let size = file.len().unwrap();
+        let table = Table::open(file, size, options).unwrap();
+        assert_eq!(table.num_entries(), Some(0));
+        assert_eq!(table.num_data_blocks().unwrap(), 0);
+        assert!(table.smallest_key().unwrap().is_none());
+        assert!(table.largest_key().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_table_iterator() {
+        let options = Arc::new(Options {
+            block_size: 256,
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder = TableBuilder::new(
+            storage.create("test_table").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        let mut expected = vec![];
+        for i in 0..100u64 {
+            let key = format!("key{:03}", i);
+            let t = match i % 3 {
+                0 => ValueType::Value,
+                1 => ValueType::Deletion,
+                _ => ValueType::Merge,
+            };
+            // two versions for every key
+            for seq in [i * 2 + 2, i * 2 + 1].iter() {
+                let ikey = InternalKey::new(&Slice::from(key.as_str()), *seq, t);
+                let value = format!("value{}", seq);
+                builder.add(ikey.data(), value.as_bytes()).unwrap();
+                expected.push((key.clone(), *seq, t, value));
+            }
+        }
+        builder.finish(true).unwrap();
+        let mut contents = vec![];
+        storage
+            .open("test_table")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        let file = storage.open("test_table").unwrap();
+        let size = file.len().unwrap();
+        let table = Arc::new(Table::open(file, size, options.clone()).unwrap());
+        let mut iter = table.iter(ReadOptions::default());
+        iter.seek_to_first();
+        for (key, seq, t, value) in expected.iter() {
+            assert!(iter.valid());
+            let parsed = iter.key().unwrap();
+            assert_eq!(parsed.user_key.as_str(), key.as_str());
+            assert_eq!(parsed.seq, *seq);
+            assert_eq!(parsed.value_type, *t);
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+        iter.status().unwrap();
+        iter.seek(b"key050");
+        let parsed = iter.key().unwrap();
+        assert_eq!(parsed.user_key.as_str(), "key050");
+        assert_eq!(parsed.seq, 102);
+        iter.seek(b"key0505");
+        assert_eq!(iter.key().unwrap().user_key.as_str(), "key051");
+        iter.seek(b"key100");
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        iter.prev();
+        assert_eq!(iter.key().unwrap().seq, 200);
+
+        // a truncated file
+        let mut file = storage.create("truncated").unwrap();
+        file.write(&contents[..contents.len() - 10]).unwrap();
+        let file = storage.open("truncated").unwrap();
+        let e = Table::open(file, contents.len() as u64 - 10, options.clone())
+            .err()
+            .unwrap();
+        assert_eq!(e.status(), Status::Corruption);
+
+        // a corrupted data block
+        let offset = contents.windows(6).position(|w| w == b"value2").unwrap();
+        contents[offset] ^= 0xff;
+        let mut file = storage.create("corrupted").unwrap();
+        file.write(contents.as_slice()).unwrap();
+        let file = storage.open("corrupted").unwrap();
+        let table = Arc::new(Table::open(file, contents.len() as u64, options).unwrap());
+        let mut iter = table.iter(ReadOptions::default());
+        iter.seek_to_first();
+        // the corrupted block is skipped
+        let mut count = 0;
+        while iter.valid() {
+            assert_ne!(iter.key().unwrap().user_key.as_str(), "key000");
+            iter.next();
+            count += 1;
+        }
+        assert!(count < expected.len());
+        assert_eq!(iter.status().unwrap_err().status(), Status::Corruption);
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let options = Arc::new(Options {
+            block_size: 256,
+            filter_policy: Some(Arc::new(BloomFilter::new(10))),
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder = TableBuilder::new(
+            storage.create("test_table").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        for i in 0..100 {
+            builder
+                .add(ikey(format!("key{:03}", i).as_str()).as_slice(), b"value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+        let mut contents = vec![];
+        storage
+            .open("test_table")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        let open = |contents: &[u8]| {
+            let mut file = storage.create("corrupted").unwrap();
+            file.write(contents).unwrap();
+            let file = storage.open("corrupted").unwrap();
+            Table::open(file, contents.len() as u64, options.clone()).unwrap()
+        };
+        open(contents.as_slice()).verify_checksums().unwrap();
+
+        // flips a byte in the value of the first entry
+        let offset = contents.windows(5).position(|w| w == b"value").unwrap();
+        contents[offset] ^= 0xff;
+        let table = open(contents.as_slice());
+        let e = table.verify_checksums().unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+        let key = ikey("key000");
+        let e = table
+            .internal_get(Rc::new(ReadOptions::default()), key.as_slice())
+            .unwrap_err();
+        assert_eq!(e.status(), Status::Corruption);
+        // the checksums are skipped for the trusted data
+        let read_opt = Rc::new(ReadOptions {
+            verify_checksums: false,
+            ..Default::default()
+        });
+        let (_, _, value) = table
+            .internal_get(read_opt, key.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_ne!(value.as_slice(), b"value");
+    }
+
+    #[test]
+    fn test_cached_block_checksum_verified() {
+        let cache = Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20));
+        let options = Arc::new(Options {
+            block_size: 256,
+            block_cache: Some(cache),
+            ..Default::default()
+        });
+        let storage = MemStorage::default();
+        let mut builder = TableBuilder::new(
+            storage.create("test_table").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        for i in 0..100 {
+            builder
+                .add(ikey(format!("key{:03}", i).as_str()).as_slice(), b"value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+        let mut contents = vec![];
+        storage
+            .open("test_table")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        let reads = Rc::new(RefCell::new(vec![]));
+        let open = |contents: &[u8]| {
+            let mut file = storage.create("test_table").unwrap();
+            file.write(contents).unwrap();
+            let file = CountingFile {
+                inner: storage.open("test_table").unwrap(),
+                reads: reads.clone(),
+            };
+            Table::open(Box::new(file), contents.len() as u64, options.clone()).unwrap()
+        };
+        let verified = Rc::new(ReadOptions::default());
+        let unverified = Rc::new(ReadOptions {
+            verify_checksums: false,
+            ..Default::default()
+        });
+        let get = |table: &Table, options: &Rc<ReadOptions>, key: &str| {
+            table
+                .internal_get(options.clone(), ikey(key).as_slice())
+                .map(|r| r.unwrap().2)
+        };
+        let block_reads = |offset: u64| reads.borrow().iter().filter(|o| **o == offset).count();
+
+        let table = open(contents.as_slice());
+        // a verified block is never verified again
+        assert_eq!(get(&table, &verified, "key000").unwrap(), b"value");
+        assert_eq!(get(&table, &verified, "key000").unwrap(), b"value");
+        assert_eq!(get(&table, &unverified, "key000").unwrap(), b"value");
+        assert_eq!(block_reads(0), 1);
+        // a block read without verification is read again to be verified once
+        let last_key = "key099";
+        assert_eq!(get(&table, &unverified, last_key).unwrap(), b"value");
+        let last_block = *reads.borrow().last().unwrap();
+        assert_eq!(block_reads(last_block), 1);
+        assert_eq!(get(&table, &verified, last_key).unwrap(), b"value");
+        assert_eq!(get(&table, &verified, last_key).unwrap(), b"value");
+        assert_eq!(block_reads(last_block), 2);
+
+        // flips a byte in the value of the first entry
+        let offset = contents.windows(5).position(|w| w == b"value").unwrap();
+        contents[offset] ^= 0xff;
+        reads.borrow_mut().clear();
+        let table = open(contents.as_slice());
+        assert_ne!(get(&table, &unverified, "key000").unwrap(), b"value");
+        // the corrupted block is never cached as a verified one
+        for _ in 0..2 {
+            let e = get(&table, &verified, "key000").unwrap_err();
+            assert_eq!(e.status(), Status::Corruption);
+        }
+        assert_eq!(block_reads(0), 3);
+        assert_ne!(get(&table, &unverified, "key000").unwrap(), b"value");
+        assert_eq!(block_reads(0), 3);
+    }
+
+    #[test]
+    fn test_warm_cache() {
+        let storage = MemStorage::default();
+        for &index_type in &[IndexType::Flat, IndexType::TwoLevel] {
+            let new_options = |cache: Arc<dyn Cache<Arc<Block>>>| {
+                Arc::new(Options {
+                    block_size: 256,
+                    index_type,
+                    index_partition_size: 128,
+                    block_cache: Some(cache),
+                    ..Default::default()
+                })
+            };
+            let cache: Arc<dyn Cache<Arc<Block>>> =
+                Arc::new(SharedLRUCache::with_config(1 << 20, 0, None));
+            let options = new_options(cache.clone());
+            let mut builder = TableBuilder::new(
+                storage.create("test_table").unwrap(),
+                CompressionType::NoCompression,
+                options.clone(),
+            );
+            for i in 0..200 {
+                let k = format!("key{:03}", i);
+                builder.add(ikey(&k).as_slice(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+            let reads = Rc::new(RefCell::new(vec![]));
+            let open = |options: Arc<Options>| {
+                let file = CountingFile {
+                    inner: storage.open("test_table").unwrap(),
+                    reads: reads.clone(),
+                };
+                let size = file.len().unwrap();
+                Arc::new(Table::open(Box::new(file), size, options).unwrap())
+            };
+            let read_opt = Rc::new(ReadOptions::default());
+            let get = |table: &Table, i: usize| {
+                let k = format!("key{:03}", i);
+                table
+                    .internal_get(read_opt.clone(), ikey(&k).as_slice())
+                    .unwrap()
+                    .unwrap()
+                    .2
+            };
+
+            let table = open(options.clone());
+            let mut budget = usize::MAX;
+            let loaded = table
+                .clone()
+                .warm_cache(read_opt.clone(), (b"key050", b"key100"), &mut budget, None)
+                .unwrap();
+            assert!(loaded > 1);
+            let warmed = usize::MAX - budget;
+            assert!(warmed > 0 && warmed <= cache.total_charge());
+            // the range is read without touching the file
+            reads.borrow_mut().clear();
+            for i in 50..100 {
+                assert_eq!(get(&table, i), format!("key{:03}", i).as_bytes());
+            }
+            assert!(reads.borrow().is_empty());
+            get(&table, 150);
+            assert!(!reads.borrow().is_empty());
+            // the cached blocks are not read again but still take the budget
+            let mut budget = usize::MAX;
+            let warm = |budget: &mut usize| {
+                table
+                    .clone()
+                    .warm_cache(read_opt.clone(), (b"key050", b"key100"), budget, None)
+                    .unwrap()
+            };
+            assert_eq!(warm(&mut budget), 0);
+            assert_eq!(usize::MAX - budget, warmed);
+            let mut budget = 0;
+            assert_eq!(warm(&mut budget), 0);
+
+            // stops once the next block exceeds the budget
+            let cache: Arc<dyn Cache<Arc<Block>>> =
+                Arc::new(SharedLRUCache::with_config(1 << 20, 0, None));
+            let table = open(new_options(cache.clone()));
+            let mut budget = 1000;
+            let partial = table
+                .clone()
+                .warm_cache(read_opt.clone(), (b"key050", b"key100"), &mut budget, None)
+                .unwrap();
+            assert!(partial > 0 && partial < loaded);
+            assert_eq!(budget, 0);
+            let mut budget = usize::MAX;
+            let rest = table
+                .warm_cache(read_opt.clone(), (b"key050", b"key100"), &mut budget, None)
+                .unwrap();
+            assert_eq!(partial + rest, loaded);
+
+            // the blocks are read but not cached without `fill_cache`
+            let cache: Arc<dyn Cache<Arc<Block>>> =
+                Arc::new(SharedLRUCache::with_config(1 << 20, 0, None));
+            let table = open(new_options(cache.clone()));
+            let no_fill = Rc::new(ReadOptions {
+                fill_cache: false,
+                ..Default::default()
+            });
+            let mut budget = usize::MAX;
+            let read = table
+                .clone()
+                .warm_cache(no_fill, (b"key050", b"key100"), &mut budget, None)
+                .unwrap();
+            assert_eq!(read, loaded);
+            reads.borrow_mut().clear();
+            get(&table, 50);
+            assert!(!reads.borrow().is_empty());
+            // stops once canceled
+            let canceled = AtomicBool::new(true);
+            let mut budget = usize::MAX;
+            let e = table
+                .clone()
+                .warm_cache(
+                    read_opt.clone(),
+                    (b"key050", b"key100"),
+                    &mut budget,
+                    Some(&canceled),
+                )
+                .unwrap_err();
+            assert_eq!(e.status(), Status::Aborted);
+            reads.borrow_mut().clear();
+            get(&table, 90);
+            assert!(!reads.borrow().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_paranoid_checks_key_order() {
+        let storage = MemStorage::default();
+        let options = Arc::new(Options {
+            paranoid_checks: true,
+            ..Default::default()
+        });
+        let mut builder = TableBuilder::new(
+            storage.create("out_of_order").unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        builder.add(ikey("b").as_slice(), b"").unwrap();
+        for k in ["a", "b"].iter() {
+            let e = builder.add(ikey(k).as_slice(), b"").unwrap_err();
+            assert_eq!(e.status(), Status::Corruption);
+            let context = e.context().unwrap();
+            assert!(
+                context.contains(&format!("{:?}", Slice::from(*k))),
+                "{}",
+                context
+            );
+            assert!(
+                context.contains(&format!("{:?}", Slice::from("b"))),
+                "{}",
+                context
+            );
+        }
+
+        // a table built by a reversed comparator has a descending index
+        let reverse = Arc::new(Options {
+            block_size: 64,
+            comparator: Arc::new(ReverseComparator::new(Arc::new(BytewiseComparator::new()))),
+            ..Default::default()
+        });
+        let mut builder = TableBuilder::new(
+            storage.create("reversed").unwrap(),
+            CompressionType::NoCompression,
+            reverse.clone(),
+        );
+        for i in (0..100).rev() {
+            builder
+                .add(ikey(format!("key{:03}", i).as_str()).as_slice(), b"value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+        let open = |options: Arc<Options>| {
+            let file = storage.open("reversed").unwrap();
+            let size = file.len().unwrap();
+            Table::open(file, size, options)
+        };
+        open(reverse).unwrap();
+        open(Arc::new(Options::default())).unwrap();
+        let e = open(options).err().unwrap();
+        assert_eq!(e.status(), Status::Corruption);
+    }
+
+    #[test]
+    fn test_custom_block_size_and_restart_interval() {
+        let keys: Vec<String> = (0..200).map(|i| format!("key{:04}", i * 2)).collect();
+        for &(block_size, restart_interval) in &[(64, 1), (256, 3), (4096, 7), (1 << 20, 16)] {
+            let options = Arc::new(Options {
+                block_size,
+                block_restart_interval: restart_interval,
+                ..Default::default()
+            });
+            let storage = MemStorage::default();
+            let file = storage.create("test_table").unwrap();
+            let mut builder =
+                TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+            for k in keys.iter() {
+                builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+            let file = storage.open("test_table").unwrap();
+            let size = file.len().unwrap();
+            let table = Arc::new(Table::open(file, size, options).unwrap());
+            // the index block restarts at every entry regardless of the interval
+            let index_block = table.index_block().unwrap();
+            let mut index_iter = index_block.iter(table.cmp.clone());
+            index_iter.seek_to_first();
+            let mut index_entries = 0;
+            while index_iter.valid() {
+                index_entries += 1;
+                index_iter.next();
+            }
+            assert_eq!(index_block.num_restarts(), index_entries);
+            let mut iter = new_table_iterator(table, Rc::new(ReadOptions::default()));
+            iter.seek_to_first();
+            for k in keys.iter() {
+                assert!(iter.valid());
+                assert_eq!(iter.key().as_slice(), ikey(k).as_slice());
+                assert_eq!(iter.value().as_slice(), k.as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for k in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(iter.value().as_slice(), k.as_bytes());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            // seeks to the keys around every restart point
+            for i in 0..keys.len() {
+                let target = format!("key{:04}", i * 2 + 1);
+                iter.seek(&Slice::from(ikey(target.as_str()).as_slice()));
+                if i + 1 < keys.len() {
+                    assert_eq!(iter.value().as_slice(), keys[i + 1].as_bytes());
+                } else {
+                    assert!(!iter.valid());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_disable_key_shortening() {
+        let keys: Vec<String> = (0..500)
+            .map(|i| format!("key{:04}{}", i * 2, "x".repeat(32)))
+            .collect();
+        let storage = MemStorage::default();
+        let mut index_sizes = vec![];
+        for &disable_key_shortening in &[false, true] {
+            let options = Arc::new(Options {
+                block_size: 256,
+                disable_key_shortening,
+                ..Default::default()
+            });
+            let file = storage.create("test_table").unwrap();
+            let mut builder =
+                TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+            for k in keys.iter() {
+                builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+            let file = storage.open("test_table").unwrap();
+            let size = file.len().unwrap();
+            let table = Arc::new(Table::open(file, size, options).unwrap());
+            index_sizes.push(table.index_handle.size());
+            // without the shortening every index entry is the last key of a data block
+            let (mut index_entries, mut full_keys) = (0, 0);
+            let mut index_iter = table.index_block().unwrap().iter(table.cmp.clone());
+            index_iter.seek_to_first();
+            while index_iter.valid() {
+                let index_key = index_iter.key();
+                let user_key =
+                    String::from_utf8(extract_user_key(index_key.as_slice()).as_slice().to_vec())
+                        .unwrap_or_default();
+                index_entries += 1;
+                if keys.contains(&user_key) {
+                    full_keys += 1;
+                }
+                index_iter.next();
+            }
+            if disable_key_shortening {
+                assert_eq!(full_keys, index_entries);
+            } else {
+                assert!(full_keys < index_entries / 2);
+            }
+            let mut iter = new_table_iterator(table, Rc::new(ReadOptions::default()));
+            iter.seek_to_first();
+            for k in keys.iter() {
+                assert_eq!(iter.value().as_slice(), k.as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            for i in 0..keys.len() {
+                let target = format!("key{:04}", i * 2 + 1);
+                iter.seek(&Slice::from(ikey(target.as_str()).as_slice()));
+                if i + 1 < keys.len() {
+                    assert_eq!(iter.value().as_slice(), keys[i + 1].as_bytes());
+                } else {
+                    assert!(!iter.valid());
+                }
+            }
+        }
+        assert!(index_sizes[0] < index_sizes[1], "{:?}", index_sizes);
+    }
+
+    #[test]
+    fn test_two_level_index() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{:04}", i * 2)).collect();
+        let storage = MemStorage::default();
+        for &(name, index_type) in &[
+            ("flat", IndexType::Flat),
+            ("two_level", IndexType::TwoLevel),
+        ] {
+            let options = Arc::new(Options {
+                block_size: 128,
+                index_type,
+                index_partition_size: 256,
+                filter_policy: Some(Arc::new(BloomFilter::new(10))),
+                ..Default::default()
+            });
+            let file = storage.create(name).unwrap();
+            let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options);
+            for k in keys.iter() {
+                builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+        }
+        // a table is read by the index type recorded in it regardless of the options
+        for &reader_type in &[IndexType::Flat, IndexType::TwoLevel] {
+            let open = |name: &str| {
+                let options = Arc::new(Options {
+                    index_type: reader_type,
+                    filter_policy: Some(Arc::new(BloomFilter::new(10))),
+                    ..Default::default()
+                });
+                let file = storage.open(name).unwrap();
+                let size = file.len().unwrap();
+                Arc::new(Table::open(file, size, options).unwrap())
+            };
+            let flat = open("flat");
+            let table = open("two_level");
+            assert_eq!(flat.index_type, IndexType::Flat);
+            assert_eq!(table.index_type, IndexType::TwoLevel);
+            let num_blocks = flat.num_data_blocks().unwrap();
+            let num_partitions = table.index_block().unwrap().num_restarts() as usize;
+            assert!(num_partitions > 1 && num_partitions < num_blocks / 5);
+            assert_eq!(table.num_data_blocks().unwrap(), num_blocks);
+            assert_eq!(table.smallest_key().unwrap(), flat.smallest_key().unwrap());
+            assert_eq!(table.largest_key().unwrap(), flat.largest_key().unwrap());
+            table.verify_checksums().unwrap();
+
+            let read_opt = Rc::new(ReadOptions::default());
+            for (i, k) in keys.iter().enumerate() {
+                let (_, _, v) = table
+                    .internal_get(read_opt.clone(), ikey(k).as_slice())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(v.as_slice(), k.as_bytes());
+                assert!(table.key_may_match(ikey(k).as_slice()));
+                // the data blocks are at the same offsets in both tables
+                assert_eq!(
+                    table.approximate_offset_of(ikey(k).as_slice()),
+                    flat.approximate_offset_of(ikey(k).as_slice())
+                );
+                let missing = format!("key{:04}", i * 2 + 1);
+                assert!(table
+                    .internal_get(read_opt.clone(), ikey(&missing).as_slice())
+                    .unwrap()
+                    .is_none());
+            }
+
+            let mut iter = new_table_iterator(table.clone(), read_opt.clone());
+            iter.seek_to_first();
+            for k in keys.iter() {
+                assert_eq!(iter.value().as_slice(), k.as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for k in keys.iter().rev() {
+                assert_eq!(iter.value().as_slice(), k.as_bytes());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            for i in 0..keys.len() {
+                let target = format!("key{:04}", i * 2 + 1);
+                iter.seek(&Slice::from(ikey(target.as_str()).as_slice()));
+                if i + 1 < keys.len() {
+                    assert_eq!(iter.value().as_slice(), keys[i + 1].as_bytes());
+                } else {
+                    assert!(!iter.valid());
+                }
+            }
+            iter.status().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_two_level_index_reads_one_partition() {
+        let storage = MemStorage::default();
+        let cache = Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20));
+        let options = Arc::new(Options {
+            block_size: 128,
+            index_type: IndexType::TwoLevel,
+            index_partition_size: 256,
+            block_cache: Some(cache),
+            ..Default::default()
+        });
+        let file = storage.create("test_table").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{:04}", i)).collect();
+        for k in keys.iter() {
+            builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+        }
+        builder.finish(true).unwrap();
+
+        let reads = Rc::new(RefCell::new(vec![]));
+        let file = CountingFile {
+            inner: storage.open("test_table").unwrap(),
+            reads: reads.clone(),
+        };
+        let size = file.len().unwrap();
+        let table = Table::open(Box::new(file), size, options).unwrap();
+        let mut partition_offsets = vec![];
+        let mut index_iter = table.index_block().unwrap().iter(table.cmp.clone());
+        index_iter.seek_to_first();
+        while index_iter.valid() {
+            let (h, _) = BlockHandle::decode_from(index_iter.value().as_slice()).unwrap();
+            partition_offsets.push(h.offset());
+            index_iter.next();
+        }
+        assert!(partition_offsets.len() > 1);
+        let partition_reads = || {
+            reads
+                .borrow()
+                .iter()
+                .filter(|o| partition_offsets.contains(o))
+                .count()
+        };
+        // no partition is read by the open
+        assert_eq!(partition_reads(), 0);
+        let read_opt = Rc::new(ReadOptions::default());
+        let (_, _, v) = table
+            .internal_get(read_opt.clone(), ikey(&keys[500]).as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!(v.as_slice(), keys[500].as_bytes());
+        assert_eq!(partition_reads(), 1);
+        // every partition is read only once through the block cache
+        for k in keys.iter() {
+            table
+                .internal_get(read_opt.clone(), ikey(k).as_slice())
+                .unwrap()
+                .unwrap();
+        }
+        assert_eq!(partition_reads(), partition_offsets.len());
+    }
+
+    #[test]
+    fn test_pin_data() {
+        let cache = Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20));
+        let (table, keys) = new_test_table(cache.clone());
+        let read_opt = Rc::new(ReadOptions {
+            pin_data: true,
+            ..Default::default()
+        });
+        let mut iter = new_table_iterator(table.clone(), read_opt);
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            assert_eq!(
+                extract_user_key(iter.key().as_slice()).as_str(),
+                keys[count]
+            );
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, keys.len());
+        let charge = cache.total_charge();
+        assert!(charge > 0);
+        // all the touched blocks are pinned so nothing can be pruned
+        cache.prune();
+        assert_eq!(charge, cache.total_charge());
+        // re-seeking backward reads the pinned blocks without looking up the cache
+        let looked_up = cache.stats().hits + cache.stats().misses;
+        iter.seek(&Slice::from(ikey("key010").as_slice()));
+        assert_eq!(extract_user_key(iter.key().as_slice()).as_str(), "key010");
+        iter.prev();
+        assert_eq!(extract_user_key(iter.key().as_slice()).as_str(), "key009");
+        assert_eq!(looked_up, cache.stats().hits + cache.stats().misses);
+
+        // dropping the iterator unpins the blocks so the capacity can be reclaimed
+        drop(iter);
+        cache.prune();
+        assert_eq!(0, cache.total_charge());
+    }
+
+    #[test]
+    fn test_mixed_filter_policies() {
+        use crate::filter::bloom::BloomFilter;
+        use crate::filter::xor::XorFilter;
+        let storage = MemStorage::default();
+        let policies: Vec<Arc<dyn FilterPolicy>> =
+            vec![Arc::new(BloomFilter::new(10)), Arc::new(XorFilter::new())];
+        let keys: Vec<String> = (0..10).map(|i| format!("key{}", i)).collect();
+        for (i, policy) in policies.iter().enumerate() {
+            let options = Arc::new(Options {
+                filter_policy: Some(policy.clone()),
+                ..Default::default()
+            });
+            let file = storage.create(format!("{}", i).as_str()).unwrap();
+            let mut builder =
+                TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+            for k in keys.iter() {
+                builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+        }
+        // every table is readable with either policy
+        for policy in policies.iter() {
+            let options = Arc::new(Options {
+                filter_policy: Some(policy.clone()),
+                ..Default::default()
+            });
+            for i in 0..policies.len() {
+                let file = storage.open(format!("{}", i).as_str()).unwrap();
+                let size = file.len().unwrap();
+                let table = Table::open(file, size, options.clone()).unwrap();
+                let filter = table.filter_reader.as_ref().unwrap();
+                for k in keys.iter() {
+                    assert!(filter.key_may_match(0, &Slice::from(k.as_str())));
+                }
+                assert!(!filter.key_may_match(0, &Slice::from("missing")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_filter() {
+        let storage = MemStorage::default();
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i / 2)).collect();
+        for (name, bits_per_key) in [("with_filter", 10), ("without_filter", 0)].iter() {
+            let options = Arc::new(Options {
+                table_filter_bits_per_key: *bits_per_key,
+                ..Default::default()
+            });
+            let file = storage.create(name).unwrap();
+            let mut builder =
+                TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+            for (seq, k) in keys.iter().enumerate() {
+                let ikey =
+                    InternalKey::new(&Slice::from(k.as_str()), 100 - seq as u64, ValueType::Value);
+                builder.add(ikey.data(), k.as_bytes()).unwrap();
+            }
+            builder.finish(true).unwrap();
+        }
+        let open = |name: &str| {
+            let file = storage.open(name).unwrap();
+            let size = file.len().unwrap();
+            Table::open(file, size, Arc::new(Options::default())).unwrap()
+        };
+        assert!(open("without_filter").table_filter().is_none());
+        let table = open("with_filter");
+        let filter = table.table_filter().unwrap();
+        let policy = BloomFilter::new(10);
+        for k in keys.iter() {
+            assert!(policy.may_contain(filter.as_slice(), &Slice::from(k.as_str())));
+        }
+        assert!(!policy.may_contain(filter.as_slice(), &Slice::from("missing")));
+        assert_eq!(table.num_entries(), Some(100));
+    }
+
+    #[test]
+    fn test_readahead() {
+        let storage = MemStorage::default();
+        let options = Arc::new(Options {
+            block_size: 256,
+            block_cache: Some(Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20))),
+            ..Default::default()
+        });
+        let file = storage.create("test_table").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options.clone());
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{:04}", i)).collect();
+        for k in keys.iter() {
+            builder
+                .add(ikey(k).as_slice(), b"value_value_value_value")
+                .unwrap();
+        }
+        builder.finish(true).unwrap();
+        let reads = Rc::new(RefCell::new(vec![]));
+        let file = CountingFile {
+            inner: storage.open("test_table").unwrap(),
+            reads: reads.clone(),
+        };
+        let size = file.len().unwrap();
+        let table = Arc::new(Table::open(Box::new(file), size, options.clone()).unwrap());
+        let blocks = table.num_data_blocks().unwrap();
+        assert!(blocks > 100);
+        // Returns the number of the file reads by scanning the table forward and backward
+        let scan = |readahead_size: usize| {
+            reads.borrow_mut().clear();
+            let read_opt = Rc::new(ReadOptions {
+                fill_cache: false,
+                readahead_size,
+                ..Default::default()
+            });
+            let mut iter = new_table_iterator(table.clone(), read_opt);
+            iter.seek_to_first();
+            for k in keys.iter() {
+                assert_eq!(iter.key().as_slice(), ikey(k).as_slice());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for k in keys.iter().rev() {
+                assert_eq!(iter.key().as_slice(), ikey(k).as_slice());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.status().unwrap();
+            let n = reads.borrow().len();
+            n
+        };
+        assert_eq!(scan(0), blocks * 2);
+        let n = scan(16 * 1024);
+        assert!(n < blocks / 10, "{} reads for {} blocks", n, blocks);
+        // a buffer smaller than a block reads the blocks one by one, except that the
+        // last block is still in the buffer when the backward scan starts
+        assert_eq!(scan(1), blocks * 2 - 1);
+        // the blocks are never cached
+        assert_eq!(options.block_cache.as_ref().unwrap().total_charge(), 0);
+    }
+
+    #[test]
+    fn test_prefix_bloom_filter() {
+        use crate::filter::bloom::BloomFilter;
+        use crate::util::prefix_extractor::FixedLengthPrefixExtractor;
+        let storage = MemStorage::default();
+        let extractor = Arc::new(FixedLengthPrefixExtractor::new(4));
+        let options = Arc::new(Options {
+            filter_policy: Some(Arc::new(BloomFilter::new_prefix(10))),
+            prefix_extractor: Some(extractor.clone()),
+            ..Default::default()
+        });
+        // 10 prefixes with 10 keys for each
+        let keys: Vec<String> = (0..100)
+            .map(|i| format!("p{:03}_{}", i / 10 * 10, i))
+            .collect();
+        let file = storage.create("prefix").unwrap();
+        let mut builder = TableBuilder::new(file, CompressionType::NoCompression, options);
+        for k in keys.iter() {
+            builder.add(ikey(k).as_slice(), k.as_bytes()).unwrap();
+        }
+        builder.finish(true).unwrap();
+
+        // the table is queried in the prefix mode even if a whole key filter is configured
+        let options = Arc::new(Options {
+            filter_policy: Some(Arc::new(BloomFilter::new(10))),
+            prefix_extractor: Some(extractor),
+            ..Default::default()
+        });
+        let file = storage.open("prefix").unwrap();
+        let size = file.len().unwrap();
+        let table = Table::open(file, size, options).unwrap();
+        let filter = table.filter_reader.as_ref().unwrap();
+        assert!(!filter.whole_key_filtering());
+        let read_opt = Rc::new(ReadOptions::default());
+        for k in keys.iter() {
+            let (_, _, v) = table
+                .internal_get(read_opt.clone(), ikey(k).as_slice())
+                .unwrap()
+                .unwrap();
+            assert_eq!(v.as_slice(), k.as_bytes());
+        }
+        for i in 0..10 {
+            let prefix = format!("p{:03}", i * 10);
+            assert!(filter.key_may_match(0, &Slice::from(prefix.as_str())));
+        }
+        // the keys whose prefix was never inserted are rejected
+        let rejected = (0..100)
+            .filter(|i| {
+                let prefix = format!("q{:03}", i);
+                !filter.key_may_match(0, &Slice::from(prefix.as_str()))
+            })
+            .count();
+        assert!(rejected >= 90, "{} rejected", rejected);
+        // the whole keys are not in the filter
+        let whole_keys = keys
+            .iter()
+            .filter(|k| filter.key_may_match(0, &Slice::from(k.as_str())))
+            .count();
+        assert!(whole_keys <= 10, "{} whole keys", whole_keys);
+    }
+
+    #[test]
+    fn test_blocks_are_not_pinned_by_default() {
+        let cache = Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20));
+        let (table, _) = new_test_table(cache.clone());
+        let mut iter = new_table_iterator(table, Rc::new(ReadOptions::default()));
+        iter.seek_to_first();
+        while iter.valid() {
+            iter.next();
+        }
+        assert!(cache.total_charge() > 0);
+        // the blocks are in the cache but not in use
+        cache.prune();
+        assert_eq!(0, cache.total_charge());
+    }
+
+    #[test]
+    fn test_scan_without_filling_cache() {
+        let cache = Arc::new(SharedLRUCache::<Arc<Block>>::new(1 << 20));
+        let (table, keys) = new_test_table(cache.clone());
+        let read_opt = Rc::new(ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        });
+        let scan = |read_opt: Rc<ReadOptions>| {
+            let mut iter = new_table_iterator(table.clone(), read_opt);
+            iter.seek_to_first();
+            let mut count = 0;
+            while iter.valid() {
+                count += 1;
+                iter.next();
+            }
+            assert_eq!(count, keys.len());
+        };
+        scan(read_opt.clone());
+        assert_eq!(0, cache.total_charge());
+        assert_eq!(0, cache.stats().insertions);
+        // the blocks read by a normal scan are cached
+        scan(Rc::new(ReadOptions::default()));
+        let charge = cache.total_charge();
+        assert!(charge > 0);
+        // the cached blocks are still used when `fill_cache` is false
+        cache.reset_stats();
+        scan(read_opt);
+        assert_eq!(charge, cache.total_charge());
+        assert_eq!(0, cache.stats().insertions);
+        assert_eq!(0, cache.stats().misses);
+    }
 }
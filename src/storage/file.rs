@@ -19,10 +19,12 @@ use crate::storage::{File, Storage};
 use crate::util::status::{Result, Status, WickErr};
 use fs2::FileExt;
 use std::fs::{
-    create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file, rename, File as SysFile,
-    OpenOptions,
+    create_dir_all, hard_link, read_dir, remove_dir, remove_dir_all, remove_file, rename,
+    File as SysFile, OpenOptions,
 };
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
 pub struct FileStorage;
@@ -48,6 +50,20 @@ impl Storage for FileStorage {
         }
     }
 
+    // Maps the file into memory. Falls back to a normal file if the file is empty
+    // or fails to be mapped.
+    #[cfg(unix)]
+    fn open_mmap(&self, name: &str) -> Result<Box<dyn File>> {
+        let file = match OpenOptions::new().read(true).open(name) {
+            Ok(f) => f,
+            Err(e) => return Err(WickErr::new_from_raw(Status::IOError, None, Box::new(e))),
+        };
+        match MmapFile::new(file) {
+            Ok(f) => Ok(Box::new(f)),
+            Err(file) => Ok(Box::new(file)),
+        }
+    }
+
     fn remove(&self, name: &str) -> Result<()> {
         let r = remove_file(name);
         w_io_result!(r)
@@ -100,6 +116,10 @@ impl Storage for FileStorage {
         }
         Ok(vec![])
     }
+
+    fn link(&self, src: &str, dst: &str) -> Result<()> {
+        w_io_result!(hard_link(src, dst))
+    }
 }
 
 impl File for SysFile {
@@ -157,6 +177,116 @@ impl File for SysFile {
         w_io_result!(r)
     }
 }
+
+/// A read only file whose contents are mapped into memory, so that the random reads
+/// by `read_at` don't need any syscall to read the data.
+#[cfg(unix)]
+pub struct MmapFile {
+    file: SysFile,
+    ptr: *mut u8,
+    // the length of the mapped region
+    len: usize,
+}
+
+#[cfg(unix)]
+impl MmapFile {
+    /// Maps the whole `file` into memory. Returns the `file` back if it's empty or can't be mapped.
+    pub fn new(file: SysFile) -> std::result::Result<Self, SysFile> {
+        let len = match file.metadata() {
+            Ok(m) if m.len() > 0 && m.len() <= usize::MAX as u64 => m.len() as usize,
+            _ => return Err(file),
+        };
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(file);
+        }
+        Ok(Self {
+            file,
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MmapFile {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl File for MmapFile {
+    fn write(&mut self, _buf: &[u8]) -> Result<usize> {
+        Err(WickErr::new(
+            Status::NotSupported,
+            Some("mmap file is read only"),
+        ))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        File::seek(&mut self.file, pos)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        File::read(&mut self.file, buf)
+    }
+
+    fn read_all(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        File::read_all(&mut self.file, buf)
+    }
+
+    fn len(&self) -> Result<u64> {
+        File::len(&self.file)
+    }
+
+    fn lock(&self) -> Result<()> {
+        File::lock(&self.file)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        File::unlock(&self.file)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        if offset >= self.len as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let n = buf.len().min(self.len - start);
+        // Accessing the pages beyond the end of a truncated file raises SIGBUS,
+        // so make sure the range to read is still in the file
+        if File::len(&self.file)? < (start + n) as u64 {
+            return Err(WickErr::new(
+                Status::IOError,
+                Some("mmap file is truncated after being mapped"),
+            ));
+        }
+        let data = unsafe { std::slice::from_raw_parts(self.ptr.add(start), n) };
+        buf[..n].copy_from_slice(data);
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,4 +318,56 @@ mod tests {
             .expect_err("failed to fill whole buffer");
         remove_file("test").expect("");
     }
+
+    #[test]
+    fn test_link() {
+        let (src, dst) = ("test_link_src", "test_link_dst");
+        let storage = FileStorage {};
+        let mut f = storage.create(src).unwrap();
+        f.write(b"hello").unwrap();
+        f.flush().unwrap();
+        storage.link(src, dst).unwrap();
+        assert!(storage.link(src, dst).is_err());
+        storage.remove(src).unwrap();
+        let mut buf = vec![];
+        storage.open(dst).unwrap().read_all(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+        storage.remove(dst).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap_read_at() {
+        let name = "test_mmap_read_at";
+        let mut f = SysFile::create(name).expect("");
+        f.write_all("hello world".as_bytes()).expect("");
+        f.sync_all().expect("");
+        let storage = FileStorage {};
+        let rf = storage.open_mmap(name).expect("");
+        let mut buffer = vec![0u8; 5];
+        assert_eq!(5, rf.read_at(&mut buffer, 6).expect(""));
+        assert_eq!(b"world", buffer.as_slice());
+        // reading beyond the end
+        assert_eq!(2, rf.read_at(&mut buffer, 9).expect(""));
+        assert_eq!(b"ld", &buffer[..2]);
+        assert_eq!(0, rf.read_at(&mut buffer, 11).expect(""));
+        rf.read_exact_at(&mut buffer, 0).expect("");
+        assert_eq!(b"hello", buffer.as_slice());
+
+        // truncated after being mapped
+        f.set_len(3).expect("");
+        let e = rf.read_at(&mut buffer, 0).unwrap_err();
+        assert_eq!(Status::IOError, e.status());
+        let mut small = vec![0u8; 2];
+        assert_eq!(2, rf.read_at(&mut small, 1).expect(""));
+        assert_eq!(b"el", small.as_slice());
+        drop(rf);
+        remove_file(name).expect("");
+
+        // empty files are not mapped
+        SysFile::create(name).expect("");
+        let rf = storage.open_mmap(name).expect("");
+        assert_eq!(0, rf.read_at(&mut buffer, 0).expect(""));
+        remove_file(name).expect("");
+    }
 }
@@ -15,30 +15,45 @@ use crate::storage::{File, Storage};
 use crate::util::status::{Result, Status, WickErr};
 use hashbrown::HashMap;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 
-/// An in memory file system based on a simple HashMap
+/// An in memory file system based on a simple HashMap from the file path to the file contents.
+///
+/// Just like a real file system, all the handles of a same file share the contents
+/// and the lock, but each of them has its own read position. Directories are implicit:
+/// a directory exists iff there are files in it.
 // TODO: maybe use a trie tree instead
 #[derive(Default, Clone)]
 pub struct MemStorage {
     inner: Arc<RwLock<HashMap<String, FileNode>>>,
+    // whether creating files fails, see `set_create_error`
+    create_error: Arc<AtomicBool>,
 }
 
 impl Storage for MemStorage {
     fn create(&self, name: &str) -> Result<Box<dyn File>> {
-        let file_node = FileNode::new(name);
-        self.inner
-            .write()
-            .unwrap()
-            .insert(String::from(name), file_node.clone());
+        self.check_create_error()?;
+        let mut files = self.inner.write().unwrap();
+        let file_node = match files.get(name) {
+            // truncate the existing file so that the opened handles see the change
+            Some(f) => {
+                f.inner.write().unwrap().truncate();
+                f.new_handle()
+            }
+            None => {
+                let f = FileNode::new(name);
+                files.insert(String::from(name), f.clone());
+                f
+            }
+        };
         Ok(Box::new(file_node))
     }
 
     fn open(&self, name: &str) -> Result<Box<dyn File>> {
         match self.inner.read().unwrap().get(name) {
-            Some(f) => Ok(Box::new(f.clone())),
+            Some(f) => Ok(Box::new(f.new_handle())),
             None => Err(WickErr::new(Status::IOError, Some("Not Found"))),
         }
     }
@@ -49,13 +64,20 @@ impl Storage for MemStorage {
         Ok(())
     }
 
-    // Should not be used
-    fn remove_dir(&self, _dir: &str, _recursively: bool) -> Result<()> {
+    fn remove_dir(&self, dir: &str, recursively: bool) -> Result<()> {
+        let mut files = self.inner.write().unwrap();
+        let dir = Path::new(dir);
+        if !recursively && files.keys().any(|name| Path::new(name).starts_with(dir)) {
+            return Err(WickErr::new(Status::IOError, Some("Directory not empty")));
+        }
+        files.retain(|name, _| !Path::new(name).starts_with(dir));
         Ok(())
     }
 
+    // Returns true for a file or a directory containing files
     fn exists(&self, name: &str) -> bool {
-        self.inner.read().unwrap().contains_key(name)
+        let files = self.inner.read().unwrap();
+        files.contains_key(name) || files.keys().any(|f| Path::new(f).starts_with(name))
     }
 
     fn rename(&self, old: &str, new: &str) -> Result<()> {
@@ -69,42 +91,127 @@ impl Storage for MemStorage {
         }
     }
 
+    fn reuse(&self, old: &str, new: &str) -> Result<Box<dyn File>> {
+        self.check_create_error()?;
+        self.rename(old, new)?;
+        let mut f = self.inner.read().unwrap().get(new).unwrap().new_handle();
+        f.write_pos = Some(0);
+        Ok(Box::new(f))
+    }
+
     // Should not be used
     fn mkdir_all(&self, _dir: &str) -> Result<()> {
         Ok(())
     }
 
-    // Just list all keys in HashMap
-    fn list(&self, _dir: &str) -> Result<Vec<PathBuf>> {
+    // Lists the files directly in `dir`
+    fn list(&self, dir: &str) -> Result<Vec<PathBuf>> {
+        let dir = Path::new(dir);
         let mut result = vec![];
         for (key, _) in self.inner.read().unwrap().iter() {
-            result.push(PathBuf::from(key.clone()))
+            let path = PathBuf::from(key.clone());
+            if path.parent() == Some(dir) {
+                result.push(path)
+            }
         }
         Ok(result)
     }
+
+    // The linked files share the same contents
+    fn link(&self, src: &str, dst: &str) -> Result<()> {
+        let mut files = self.inner.write().unwrap();
+        if files.contains_key(dst) {
+            return Err(WickErr::new(Status::IOError, Some("File exists")));
+        }
+        match files.get(src) {
+            Some(f) => {
+                let f = f.new_handle();
+                files.insert(dst.to_owned(), f);
+                Ok(())
+            }
+            None => Err(WickErr::new(Status::IOError, Some("Not Found"))),
+        }
+    }
 }
 
+impl MemStorage {
+    /// If `fail` is true, creating or reusing a file fails with a `Status::IOError`
+    /// from now on, which simulates a full disk. The opened files are still writable.
+    pub fn set_create_error(&self, fail: bool) {
+        self.create_error.store(fail, Ordering::Release)
+    }
+
+    fn check_create_error(&self) -> Result<()> {
+        if self.create_error.load(Ordering::Acquire) {
+            Err(WickErr::new(
+                Status::IOError,
+                Some("No space left on device (injected)"),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the number of the handles opened on the files in the storage and
+    /// not dropped yet. The handles of the removed files are not counted.
+    pub fn open_handles(&self) -> usize {
+        let files = self.inner.read().unwrap();
+        // the nodes in the map are not handles, and a linked file has several nodes
+        let mut nodes: HashMap<*const RwLock<InmemFile>, (usize, usize)> = HashMap::new();
+        for f in files.values() {
+            let e = nodes
+                .entry(Arc::as_ptr(&f.inner))
+                .or_insert((Arc::strong_count(&f.inner), 0));
+            e.1 += 1;
+        }
+        nodes.values().map(|(refs, nodes)| refs - nodes).sum()
+    }
+}
+
+/// A handle of a file in `MemStorage`
 #[derive(Clone)]
 pub struct FileNode {
     inner: Arc<RwLock<InmemFile>>,
+    // the read position of this handle
+    pos: u64,
+    // the position to overwrite for a reused file, or `None` to append
+    write_pos: Option<u64>,
 }
 
 impl FileNode {
     fn new(name: &str) -> Self {
         FileNode {
             inner: Arc::new(RwLock::new(InmemFile::new(name))),
+            pos: 0,
+            write_pos: None,
+        }
+    }
+
+    // Returns a new handle of the same file with the position at the start
+    fn new_handle(&self) -> Self {
+        FileNode {
+            inner: self.inner.clone(),
+            pos: 0,
+            write_pos: None,
         }
     }
 }
 
 impl File for FileNode {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        // TODO: as we acquire a mutable ref, the lock shouldn't be needed
-        self.inner.write().unwrap().write(buf)
+        match self.write_pos.as_mut() {
+            Some(pos) => {
+                let n = self.inner.write().unwrap().write_at(buf, *pos);
+                *pos += n as u64;
+                Ok(n)
+            }
+            None => self.inner.write().unwrap().write(buf),
+        }
     }
 
+    // All the written data is visible to other handles immediately so there is nothing to sync
     fn flush(&mut self) -> Result<()> {
-        self.inner.write().unwrap().flush()
+        Ok(())
     }
 
     fn close(&mut self) -> Result<()> {
@@ -112,15 +219,46 @@ impl File for FileNode {
     }
 
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.inner.write().unwrap().seek(pos)
+        let len = self.inner.read().unwrap().len()?;
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => (len as i64)
+                .checked_add(p)
+                .filter(|p| *p >= 0)
+                .map(|p| p as u64),
+            SeekFrom::Current(p) => (self.pos as i64)
+                .checked_add(p)
+                .filter(|p| *p >= 0)
+                .map(|p| p as u64),
+        };
+        match new_pos {
+            Some(p) => {
+                self.pos = p;
+                Ok(p)
+            }
+            None => Err(WickErr::new(
+                Status::IOError,
+                Some("invalid seek to a negative or overflowing position"),
+            )),
+        }
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.inner.write().unwrap().read(buf)
+        let file = self.inner.read().unwrap();
+        let data = file.contents.get_ref();
+        let start = (self.pos as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
     }
 
     fn read_all(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
-        self.inner.write().unwrap().read_all(buf)
+        let file = self.inner.read().unwrap();
+        let data = file.contents.get_ref();
+        buf.extend_from_slice(data);
+        self.pos = data.len() as u64;
+        Ok(data.len())
     }
 
     fn len(&self) -> Result<u64> {
@@ -166,6 +304,22 @@ impl InmemFile {
     pub fn get_pos_and_data(&self) -> (u64, &[u8]) {
         (self.contents.position(), self.contents.get_ref().as_slice())
     }
+
+    // Clears the contents. The lock is not changed.
+    fn truncate(&mut self) {
+        self.contents = Cursor::new(vec![]);
+    }
+
+    // Overwrites the contents at `offset` with `buf`, extending the file if needed
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> usize {
+        let data = self.contents.get_mut();
+        let (start, end) = (offset as usize, offset as usize + buf.len());
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        buf.len()
+    }
 }
 
 impl File for InmemFile {
@@ -229,7 +383,7 @@ impl File for InmemFile {
         } else {
             let inner = self.contents.get_ref();
             let length = inner.len() as u64;
-            if offset > length - 1 {
+            if offset >= length {
                 return Ok(0);
             }
             let exact = if buf.len() as u64 + offset > length {
@@ -251,6 +405,7 @@ mod tests {
     use crate::util::status::Status;
     use hashbrown::HashSet;
     use std::error::Error;
+    use std::io::SeekFrom;
 
     #[test]
     fn test_mem_file_read_write() {
@@ -375,4 +530,137 @@ mod tests {
             assert!(tmp_names.contains(name.to_str().unwrap()))
         }
     }
+
+    #[test]
+    fn test_memory_storage_shared_handles() {
+        let env = MemStorage::default();
+        let mut w = env.create("db/test").unwrap();
+        w.write(b"hello").unwrap();
+        // writes are visible to the subsequent opens and the opened handles
+        let mut r1 = env.open("db/test").unwrap();
+        w.write(b" world").unwrap();
+        let mut r2 = env.open("db/test").unwrap();
+        let mut buf = vec![0u8; 5];
+        assert_eq!(5, r1.read(&mut buf).unwrap());
+        assert_eq!(b"hello", buf.as_slice());
+        // each handle has its own position
+        assert_eq!(5, r2.read(&mut buf).unwrap());
+        assert_eq!(b"hello", buf.as_slice());
+        assert_eq!(5, r1.read(&mut buf).unwrap());
+        assert_eq!(b" worl", buf.as_slice());
+        assert_eq!(1, r1.read(&mut buf).unwrap());
+        assert_eq!(0, r1.read(&mut buf).unwrap());
+        assert_eq!(6, r2.seek(SeekFrom::End(-5)).unwrap());
+        assert_eq!(5, r2.read(&mut buf).unwrap());
+        assert_eq!(b"world", buf.as_slice());
+        assert!(r2.seek(SeekFrom::Current(-100)).is_err());
+
+        // the lock is shared by all the handles
+        w.lock().unwrap();
+        assert!(r1.lock().is_err());
+        let again = env.create("db/test").unwrap();
+        assert!(again.lock().is_err());
+        // `create` truncates the existing file
+        assert_eq!(0, r1.len().unwrap());
+        assert_eq!(0, r2.read_at(&mut buf, 0).unwrap());
+        w.unlock().unwrap();
+        assert!(again.lock().is_ok());
+    }
+
+    #[test]
+    fn test_memory_storage_dirs() {
+        let env = MemStorage::default();
+        for name in ["a/1", "a/2", "a/b/3", "c/4"].iter() {
+            env.create(name).unwrap();
+        }
+        let mut list: Vec<String> = env
+            .list("a")
+            .unwrap()
+            .iter()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        list.sort();
+        assert_eq!(list, vec!["a/1", "a/2"]);
+        assert!(env.list("d").unwrap().is_empty());
+
+        assert_eq!(
+            env.remove_dir("a", false).unwrap_err().status(),
+            Status::IOError
+        );
+        env.remove_dir("a", true).unwrap();
+        assert!(!env.exists("a/1"));
+        assert!(!env.exists("a/b/3"));
+        assert!(env.exists("c/4"));
+        assert!(env.exists("c"));
+        assert!(!env.exists("a"));
+        env.remove("c/4").unwrap();
+        env.remove_dir("c", false).unwrap();
+    }
+
+    #[test]
+    fn test_memory_storage_link() {
+        let env = MemStorage::default();
+        let mut f = env.create("a/1").unwrap();
+        f.write(b"hello").unwrap();
+        env.link("a/1", "b/1").unwrap();
+        assert!(env.link("a/1", "b/1").is_err());
+        assert!(env.link("a/2", "b/2").is_err());
+        // the linked file lives after the original one is removed
+        env.remove("a/1").unwrap();
+        let mut buf = vec![];
+        env.open("b/1").unwrap().read_all(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+    }
+
+    #[test]
+    fn test_memory_storage_reuse() {
+        let env = MemStorage::default();
+        let mut f = env.create("a/1").unwrap();
+        f.write(b"hello world").unwrap();
+        let mut f = env.reuse("a/1", "a/2").unwrap();
+        assert!(!env.exists("a/1"));
+        f.write(b"HELLO").unwrap();
+        f.write(b"-").unwrap();
+        let mut buf = vec![];
+        env.open("a/2").unwrap().read_all(&mut buf).unwrap();
+        assert_eq!(buf, b"HELLO-world");
+        f.write(b"WORLD!").unwrap();
+        buf.clear();
+        env.open("a/2").unwrap().read_all(&mut buf).unwrap();
+        assert_eq!(buf, b"HELLO-WORLD!");
+        assert!(env.reuse("a/1", "a/3").is_err());
+    }
+
+    #[test]
+    fn test_memory_storage_create_error() {
+        let env = MemStorage::default();
+        let mut f = env.create("a/1").unwrap();
+        env.clone().set_create_error(true);
+        for e in [env.create("a/2").err(), env.reuse("a/1", "a/3").err()].iter() {
+            assert_eq!(e.as_ref().unwrap().status(), Status::IOError);
+        }
+        assert!(!env.exists("a/2"));
+        // the opened files are still writable
+        f.write(b"abc").unwrap();
+        env.set_create_error(false);
+        env.create("a/2").unwrap();
+        env.reuse("a/1", "a/3").unwrap();
+    }
+
+    #[test]
+    fn test_memory_storage_open_handles() {
+        let env = MemStorage::default();
+        let f1 = env.create("a/1").unwrap();
+        let f2 = env.open("a/1").unwrap();
+        env.link("a/1", "a/2").unwrap();
+        let f3 = env.open("a/2").unwrap();
+        assert_eq!(env.open_handles(), 3);
+        drop(f1);
+        drop(f3);
+        assert_eq!(env.open_handles(), 1);
+        env.rename("a/2", "a/3").unwrap();
+        assert_eq!(env.open_handles(), 1);
+        drop(f2);
+        assert_eq!(env.open_handles(), 0);
+    }
 }
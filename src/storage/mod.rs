@@ -37,6 +37,13 @@ pub trait Storage: Send + Sync {
     /// Open a file for writing and reading
     fn open(&self, name: &str) -> Result<Box<dyn File>>;
 
+    /// Open a file only for random reads by `read_at`. The implementation may
+    /// map the file into memory to reduce the syscalls.
+    /// Falls back to `open` by default.
+    fn open_mmap(&self, name: &str) -> Result<Box<dyn File>> {
+        self.open(name)
+    }
+
     /// Delete the named file
     fn remove(&self, name: &str) -> Result<()>;
 
@@ -50,12 +57,35 @@ pub trait Storage: Send + Sync {
     /// `new` already exists.
     fn rename(&self, old: &str, new: &str) -> Result<()>;
 
+    /// Renames the file `old` to `new` and opens it for overwriting from the start.
+    /// The existing contents are kept until they're overwritten.
+    fn reuse(&self, old: &str, new: &str) -> Result<Box<dyn File>> {
+        self.rename(old, new)?;
+        self.open(new)
+    }
+
     /// Recursively create a directory and all of its parent components if they
     /// are missing.
     fn mkdir_all(&self, dir: &str) -> Result<()>;
 
     /// Returns a list of file names in given
     fn list(&self, dir: &str) -> Result<Vec<PathBuf>>;
+
+    /// Creates a hard link `dst` to the file `src`, which fails if `dst` already exists.
+    /// Falls back to copying the contents of `src` into a new file by default.
+    fn link(&self, src: &str, dst: &str) -> Result<()> {
+        if self.exists(dst) {
+            return Err(WickErr::new(Status::IOError, Some("File exists")));
+        }
+        let mut buf = vec![];
+        self.open(src)?.read_all(&mut buf)?;
+        let mut f = self.create(dst)?;
+        let mut written = 0;
+        while written < buf.len() {
+            written += f.write(&buf[written..])?;
+        }
+        f.flush()
+    }
 }
 
 /// A file abstraction for IO operations
@@ -15,10 +15,12 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::cache::lru::SharedLRUCache;
 use crate::cache::{Cache, HandleRef};
 use crate::db::filename::{generate_filename, FileType};
-use crate::db::format::ParsedInternalKey;
+use crate::db::format::{extract_user_key, ValueType};
+use crate::db::range_del::RangeTombstone;
+use crate::filter::bloom::BloomFilter;
+use crate::filter::FilterPolicy;
 use crate::iterator::{EmptyIterator, IterWithCleanup, Iterator};
 use crate::options::{Options, ReadOptions};
 use crate::sstable::table::{new_table_iterator, Table};
@@ -26,8 +28,11 @@ use crate::storage::Storage;
 use crate::util::slice::Slice;
 use crate::util::status::Result;
 use crate::util::varint::VarintU64;
+use crate::version::version_edit::FileMetaData;
+use std::collections::HashMap;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 
 /// A `TableCache` is the cache for the sst files and the sstable in them
 pub struct TableCache {
@@ -36,21 +41,58 @@ pub struct TableCache {
     options: Arc<Options>,
     // the key of cache is the file number
     cache: Arc<dyn Cache<Arc<Table>>>,
+    // The whole-table filters of the opened tables by the file numbers, which are
+    // kept after the tables are evicted from `cache` until the files are deleted
+    table_filters: Mutex<HashMap<u64, Arc<Vec<u8>>>>,
+    // reads the whole-table filters, whose hash counts are stored in the filters
+    table_filter_policy: BloomFilter,
+    // The range tombstones of the opened tables by the file numbers, which are kept
+    // like `table_filters`
+    range_tombstones: Mutex<HashMap<u64, Arc<Vec<RangeTombstone>>>>,
 }
 
 impl TableCache {
     pub fn new(db_name: String, options: Arc<Options>, size: usize) -> Self {
-        let cache = Arc::new(SharedLRUCache::<Arc<Table>>::new(size));
+        let cache = options.table_cache_eviction_policy.new_cache(size);
         Self {
             env: options.env.clone(),
             db_name,
             options,
             cache,
+            table_filters: Mutex::new(HashMap::new()),
+            table_filter_policy: BloomFilter::new(10),
+            range_tombstones: Mutex::new(HashMap::new()),
         }
     }
 
-    // Try to find the sst file from cache. If not found, try to find the file from storage and insert it into the cache
-    fn find_table(&self, file_number: u64, file_size: u64) -> Result<HandleRef<Arc<Table>>> {
+    // Returns false if the whole-table filter of the file tells the user key is
+    // definitely absent in it. Returns true if the filter is not loaded or the table
+    // has no such filter.
+    fn table_may_contain(&self, file_number: u64, user_key: &Slice) -> bool {
+        match self.table_filters.lock().unwrap().get(&file_number) {
+            Some(filter) => self
+                .table_filter_policy
+                .may_contain(filter.as_slice(), user_key),
+            None => true,
+        }
+    }
+
+    // Returns the context attached to the errors of reading the given file
+    fn err_context(&self, file_number: u64) -> String {
+        format!(
+            "table {}",
+            generate_filename(self.db_name.as_str(), FileType::Table, file_number)
+        )
+    }
+
+    // Try to find the sst file from cache. If not found, try to find the file from storage and insert it into the cache.
+    // The meta blocks of a table opened at level 0 are pinned if `pin_l0_filter_and_index_blocks_in_cache` is set.
+    fn find_table(
+        &self,
+        level: usize,
+        file_number: u64,
+        file_size: u64,
+    ) -> Result<HandleRef<Arc<Table>>> {
         let mut key = vec![];
         VarintU64::put_varint(&mut key, file_number);
         match self.cache.look_up(key.as_slice()) {
@@ -58,8 +100,25 @@ impl TableCache {
             None => {
                 let filename =
                     generate_filename(self.db_name.as_str(), FileType::Table, file_number);
-                let table_file = self.env.open(filename.as_str())?;
-                let table = Table::open(table_file, file_size, self.options.clone())?;
+                let table_file = if self.options.allow_mmap_reads {
+                    self.env.open_mmap(filename.as_str())
+                } else {
+                    self.env.open(filename.as_str())
+                };
+                let pin = level == 0 && self.options.pin_l0_filter_and_index_blocks_in_cache;
+                let table = table_file
+                    .and_then(|f| Table::open_with_pinning(f, file_size, self.options.clone(), pin))
+                    .map_err(|e| e.with_context(self.err_context(file_number)))?;
+                if let Some(filter) = table.table_filter() {
+                    self.table_filters
+                        .lock()
+                        .unwrap()
+                        .insert(file_number, filter);
+                }
+                self.range_tombstones
+                    .lock()
+                    .unwrap()
+                    .insert(file_number, Arc::new(table.range_tombstones().to_vec()));
                 Ok(self.cache.insert(key, Arc::new(table), 1, None))
             }
         }
@@ -70,27 +129,117 @@ impl TableCache {
         let mut key = vec![];
         VarintU64::put_varint(&mut key, file_number);
         self.cache.erase(key.as_slice());
+        self.table_filters.lock().unwrap().remove(&file_number);
+        self.range_tombstones.lock().unwrap().remove(&file_number);
     }
 
-    /// Returns the result of a seek to internal key `key` in specified file
+    /// Returns the range tombstones in the specified file. The file is opened only
+    /// if it has never been opened.
+    pub fn range_tombstones(
+        &self,
+        level: usize,
+        file_number: u64,
+        file_size: u64,
+    ) -> Result<Arc<Vec<RangeTombstone>>> {
+        if let Some(tombstones) = self.range_tombstones.lock().unwrap().get(&file_number) {
+            return Ok(tombstones.clone());
+        }
+        let handle = self.find_table(level, file_number, file_size)?;
+        let tombstones = Arc::new(handle.get_value().unwrap().range_tombstones().to_vec());
+        self.cache.release(handle);
+        Ok(tombstones)
+    }
+
+    /// Returns the value type, the sequence number and the value of the entry found
+    /// by a seek to internal key `key` in specified file at `level`. The file is not
+    /// opened if its whole-table filter tells the user key is absent.
     pub fn get(
         &self,
         options: Rc<ReadOptions>,
         key: &Slice,
+        level: usize,
         file_number: u64,
         file_size: u64,
-    ) -> Result<Option<ParsedInternalKey>> {
-        let handle = self.find_table(file_number, file_size)?;
+    ) -> Result<Option<(ValueType, u64, Vec<u8>)>> {
+        if !self.table_may_contain(file_number, &extract_user_key(key.as_slice())) {
+            return Ok(None);
+        }
+        let handle = self.find_table(level, file_number, file_size)?;
         // every value should be valid so unwrap is safe here
-        let parsed_key = handle
+        let res = handle
             .get_value()
             .unwrap()
-            .internal_get(options, key.as_slice())?;
+            .internal_get(options, key.as_slice())
+            .map_err(|e| e.with_context(self.err_context(file_number)));
         self.cache.release(handle);
-        Ok(parsed_key)
+        res
+    }
+
+    /// Returns false if the specified file definitely contains no entry of the user key
+    /// in the internal key `key` by its filter block. Returns true if the file can not
+    /// be opened.
+    pub fn key_may_match(
+        &self,
+        key: &[u8],
+        level: usize,
+        file_number: u64,
+        file_size: u64,
+    ) -> bool {
+        if !self.table_may_contain(file_number, &extract_user_key(key)) {
+            return false;
+        }
+        match self.find_table(level, file_number, file_size) {
+            Ok(h) => {
+                let res = h.get_value().unwrap().key_may_match(key);
+                self.cache.release(h);
+                res
+            }
+            Err(_) => true,
+        }
     }
 
-    /// Create an iterator for the specified `file_number` (the corresponding
+    /// Returns the approximate offset of the internal key `key` in the specified file.
+    /// Returns 0 if the file can not be opened.
+    pub fn approximate_offset_of(
+        &self,
+        level: usize,
+        file_number: u64,
+        file_size: u64,
+        key: &[u8],
+    ) -> u64 {
+        match self.find_table(level, file_number, file_size) {
+            Ok(h) => {
+                let offset = h.get_value().unwrap().approximate_offset_of(key);
+                self.cache.release(h);
+                offset
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Reads the data blocks of the specified file overlapping the user keys
+    /// `[start, end)` into the block cache. See `Table::warm_cache` for `budget`
+    /// and `canceled`. Returns the number of the blocks read from the file.
+    pub fn warm_cache(
+        &self,
+        options: Rc<ReadOptions>,
+        level: usize,
+        file: &FileMetaData,
+        range: (&[u8], &[u8]),
+        budget: &mut usize,
+        canceled: Option<&AtomicBool>,
+    ) -> Result<usize> {
+        let handle = self.find_table(level, file.number, file.file_size)?;
+        let res = handle
+            .get_value()
+            .unwrap()
+            .warm_cache(options, range, budget, canceled)
+            .map_err(|e| e.with_context(self.err_context(file.number)));
+        self.cache.release(handle);
+        res
+    }
+
+    /// Create an iterator for the specified `file_number` at `level` (the corresponding
     /// file length must be exactly `file_size` bytes).
     /// The table referenced by returning Iterator will be released after the Iterator is dropped.
     ///
@@ -100,13 +249,15 @@ impl TableCache {
     pub fn new_iter(
         &self,
         options: Rc<ReadOptions>,
+        level: usize,
         file_number: u64,
         file_size: u64,
     ) -> Box<dyn Iterator> {
-        match self.find_table(file_number, file_size) {
+        match self.find_table(level, file_number, file_size) {
             Ok(h) => {
                 let table = h.get_value().unwrap();
                 let mut iter = IterWithCleanup::new(new_table_iterator(table, options));
+                iter.set_err_context(self.err_context(file_number));
                 let cache = self.cache.clone();
                 iter.register_task(Box::new(move || cache.release(h.clone())));
                 Box::new(iter)
@@ -115,3 +266,80 @@ impl TableCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::format::{InternalKey, MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK};
+    use crate::options::CompressionType;
+    use crate::sstable::table::TableBuilder;
+    use crate::storage::mem::MemStorage;
+
+    #[test]
+    fn test_table_filter_skips_opening_table() {
+        let env: Arc<dyn Storage> = Arc::new(MemStorage::default());
+        let options = Arc::new(Options {
+            env: env.clone(),
+            table_filter_bits_per_key: 10,
+            ..Default::default()
+        });
+        let db_name = "test_table_filter_skips_opening_table";
+        let filename = generate_filename(db_name, FileType::Table, 1);
+        let mut builder = TableBuilder::new(
+            env.create(filename.as_str()).unwrap(),
+            CompressionType::NoCompression,
+            options.clone(),
+        );
+        for i in 0..100 {
+            let k = format!("key{:03}", i * 2);
+            let ikey = InternalKey::new(&Slice::from(k.as_str()), 1, ValueType::Value);
+            builder.add(ikey.data(), b"value").unwrap();
+        }
+        builder.finish(true).unwrap();
+        let file_size = builder.file_size();
+
+        let table_cache = TableCache::new(db_name.to_owned(), options, 10);
+        let get = |k: &str| {
+            let ikey = InternalKey::new(&Slice::from(k), MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+            table_cache.get(
+                Rc::new(ReadOptions::default()),
+                &Slice::from(ikey.data()),
+                1,
+                1,
+                file_size,
+            )
+        };
+        assert_eq!(get("key010").unwrap().unwrap().2, b"value".to_vec());
+        // the file can not be opened again but the filter is kept
+        let mut key = vec![];
+        VarintU64::put_varint(&mut key, 1);
+        table_cache.cache.erase(key.as_slice());
+        env.remove(filename.as_str()).unwrap();
+        assert!(get("key010").is_err());
+        let mut skipped = 0;
+        for i in 0..100 {
+            let k = format!("key{:03}", i * 2 + 1);
+            if table_cache.table_may_contain(1, &Slice::from(k.as_str())) {
+                assert!(get(k.as_str()).is_err());
+            } else {
+                skipped += 1;
+                assert!(get(k.as_str()).unwrap().is_none());
+                assert!(!table_cache.key_may_match(
+                    InternalKey::new(
+                        &Slice::from(k.as_str()),
+                        MAX_KEY_SEQUENCE,
+                        VALUE_TYPE_FOR_SEEK
+                    )
+                    .data(),
+                    1,
+                    1,
+                    file_size
+                ));
+            }
+        }
+        assert!(skipped > 90, "{} lookups skipped", skipped);
+        // the filter is dropped with the file
+        table_cache.evict(1);
+        assert!(table_cache.table_may_contain(1, &Slice::from("key001")));
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// The decision made by a `CompactionFilter` on an entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Keeps the entry unchanged
+    Keep,
+    /// Removes the entry as if the key was deleted
+    Remove,
+    /// Replaces the value of the entry with the given one
+    ChangeValue(Vec<u8>),
+}
+
+/// A `CompactionFilter` is consulted for every key/value pair written by
+/// `WriteBatch::put` when the pair is rewritten by a compaction, so the
+/// expired or garbage entries are able to be removed or modified in background.
+///
+/// The filter is only applied to the entries which are invisible to all the
+/// live snapshots, so the view of a `Snapshot` never changes. The merge
+/// operands, the deletions and the entries in the memtables are never filtered.
+///
+/// A `CompactionFilter` must be thread-safe since it might be invoked
+/// concurrently by the subcompactions.
+pub trait CompactionFilter: Send + Sync {
+    /// The name of the compaction filter
+    fn name(&self) -> &str;
+
+    /// Decides what to do with the entry `key`/`value` compacted from `level`
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> Decision;
+}
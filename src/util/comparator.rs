@@ -17,6 +17,7 @@
 
 use crate::util::byte::compare;
 use std::cmp::{min, Ordering};
+use std::sync::Arc;
 
 /// A Comparator object provides a total order across `Slice` that are
 /// used as keys in an sstable or a database.  A Comparator implementation
@@ -55,7 +56,7 @@ pub trait Comparator: Send + Sync {
     fn separator(&self, a: &[u8], b: &[u8]) -> Vec<u8>;
 
     /// Given a feasible key s, Successor returns feasible key k such that Compare(k,
-    /// a) >= 0.
+    /// s) >= 0.
     /// If the key is a run of \xff, returns itself
     // TODO: returns a &[u8] to avoid copy ?
     fn successor(&self, key: &[u8]) -> Vec<u8>;
@@ -120,9 +121,157 @@ impl Comparator for BytewiseComparator {
     }
 }
 
+/// A `ReverseComparator` inverts the order of the inner comparator so that
+/// the keys are stored in descending order.
+pub struct ReverseComparator {
+    inner: Arc<dyn Comparator>,
+    name: String,
+}
+
+impl ReverseComparator {
+    pub fn new(inner: Arc<dyn Comparator>) -> Self {
+        let name = format!("wickdb.ReverseComparator({})", inner.name());
+        ReverseComparator { inner, name }
+    }
+}
+
+impl Comparator for ReverseComparator {
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.inner.compare(b, a)
+    }
+
+    #[inline]
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    // The shortening of the inner comparator moves a key towards the
+    // opposite direction, so just keep the key unchanged
+    #[inline]
+    fn separator(&self, a: &[u8], _b: &[u8]) -> Vec<u8> {
+        Vec::from(a)
+    }
+
+    #[inline]
+    fn successor(&self, key: &[u8]) -> Vec<u8> {
+        Vec::from(key)
+    }
+}
+
+/// A `NoShorteningComparator` orders the keys exactly as the inner comparator but
+/// never shortens them: the separator is always the previous key and the successor
+/// is always the key itself. The index entries of the tables then carry the full
+/// last keys of the data blocks, which makes the index larger but easier to inspect.
+///
+/// It has the same name as the inner comparator since the order is unchanged, so a
+/// DB is openable with or without the wrapper.
+pub struct NoShorteningComparator {
+    inner: Arc<dyn Comparator>,
+}
+
+impl NoShorteningComparator {
+    pub fn new(inner: Arc<dyn Comparator>) -> Self {
+        NoShorteningComparator { inner }
+    }
+}
+
+impl Comparator for NoShorteningComparator {
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.inner.compare(a, b)
+    }
+
+    #[inline]
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    #[inline]
+    fn separator(&self, a: &[u8], _b: &[u8]) -> Vec<u8> {
+        Vec::from(a)
+    }
+
+    #[inline]
+    fn successor(&self, key: &[u8]) -> Vec<u8> {
+        Vec::from(key)
+    }
+}
+
+/// A `NumericComparator` orders the keys as unsigned big-endian integers, so the
+/// `u64` keys encoded by `u64::to_be_bytes` are sorted by their values.
+///
+/// The keys are not required to be in a same width: the leading zero bytes are
+/// insignificant to the value, and the keys with a same value are ordered by
+/// their lengths. E.g. `[2] < [0, 3] < [1, 0]` and `[1] < [0, 1]`.
+#[derive(Default)]
+pub struct NumericComparator {}
+
+impl NumericComparator {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // Strips the leading zero bytes
+    #[inline]
+    fn significant(key: &[u8]) -> &[u8] {
+        let zeros = key.iter().take_while(|b| **b == 0).count();
+        &key[zeros..]
+    }
+
+    // Returns the big-endian bytes of `key + 1` without leading zero bytes
+    fn increment(key: &[u8]) -> Vec<u8> {
+        let mut res = Vec::from(Self::significant(key));
+        for b in res.iter_mut().rev() {
+            if *b == 0xff {
+                *b = 0;
+            } else {
+                *b += 1;
+                return res;
+            }
+        }
+        res.insert(0, 1);
+        res
+    }
+}
+
+impl Comparator for NumericComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let (sa, sb) = (Self::significant(a), Self::significant(b));
+        sa.len()
+            .cmp(&sb.len())
+            .then_with(|| compare(sa, sb))
+            .then_with(|| a.len().cmp(&b.len()))
+    }
+
+    #[inline]
+    fn name(&self) -> &str {
+        "wickdb.NumericComparator"
+    }
+
+    // A shorter key of a same width is always numerically smaller, so the only way to
+    // shorten `a` is stripping its leading zero bytes and then adding one to it.
+    fn separator(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let k = Self::increment(a);
+        if k.len() < a.len() && self.compare(k.as_slice(), b) == Ordering::Less {
+            return k;
+        }
+        Vec::from(a)
+    }
+
+    fn successor(&self, key: &[u8]) -> Vec<u8> {
+        let k = Self::increment(key);
+        if k.len() < key.len() {
+            return k;
+        }
+        Vec::from(key)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::Rng;
 
     #[test]
     fn test_bytewise_comparator_separator() {
@@ -147,6 +296,16 @@ mod tests {
         let b: Vec<u8> = vec![48, 49, 50, 51];
         let res = c.separator(a.as_slice(), b.as_slice());
         assert_eq!(res, a);
+        // long common prefixes differing at the last byte
+        let tests: Vec<(&[u8], &[u8], &[u8])> = vec![
+            (b"abcdefgh\xfe", b"abcdefgh\xff", b"abcdefgh\xfe"),
+            (b"abcdefgh\xfd", b"abcdefgh\xff", b"abcdefgh\xfe"),
+            (b"abcdefgh\xff", b"abcdefgh\xff\x00", b"abcdefgh\xff"),
+            (b"abcdefg\xff\xff", b"abcdefh", b"abcdefg\xff\xff"),
+        ];
+        for (a, b, expect) in tests {
+            assert_eq!(c.separator(a, b), expect.to_vec());
+        }
     }
 
     #[test]
@@ -161,10 +320,150 @@ mod tests {
         let mut corner_tests = vec![
             (vec![0xff, 0xff, 1], vec![255u8, 255u8, 2]),
             (vec![0xff, 0xff, 0xff], vec![255u8, 255u8, 255u8]),
+            // trailing 0xff bytes are dropped with the incremented byte
+            (vec![b'a', 0xff, 0xff], vec![b'b']),
+            (vec![b'a', b'b', 0xff], vec![b'b']),
+            (vec![0xff, 0xfe, 0xff], vec![0xff, 0xff]),
+            (vec![0xfe, 0xff], vec![0xff]),
         ];
         for (input, expect) in corner_tests.drain(..) {
             let res = c.successor(input.as_slice());
             assert_eq!(res, expect)
         }
     }
+
+    // Generates a random key over a small alphabet with the boundary bytes so that
+    // the pairs often share long prefixes and differ at 0x00 or 0xff
+    fn random_key(rng: &mut impl Rng, prefix: &[u8]) -> Vec<u8> {
+        const ALPHABET: [u8; 6] = [0x00, 0x01, b'a', 0xfe, 0xff, 0xff];
+        let mut key = Vec::from(&prefix[..rng.gen_range(0, prefix.len() + 1)]);
+        for _ in 0..rng.gen_range(0, 5) {
+            key.push(ALPHABET[rng.gen_range(0, ALPHABET.len())]);
+        }
+        key
+    }
+
+    #[test]
+    fn test_bytewise_comparator_shortening_invariants() {
+        let c = BytewiseComparator::new();
+        let mut rng = rand::thread_rng();
+        for _ in 0..100_000 {
+            let prefix = random_key(&mut rng, &[0xff; 16]);
+            let (mut a, mut b) = (random_key(&mut rng, &prefix), random_key(&mut rng, &prefix));
+            match c.compare(&a, &b) {
+                Ordering::Equal => continue,
+                Ordering::Greater => std::mem::swap(&mut a, &mut b),
+                Ordering::Less => {}
+            }
+            let sep = c.separator(&a, &b);
+            assert_ne!(
+                c.compare(&a, &sep),
+                Ordering::Greater,
+                "{:?} {:?} {:?}",
+                a,
+                b,
+                sep
+            );
+            assert_eq!(
+                c.compare(&sep, &b),
+                Ordering::Less,
+                "{:?} {:?} {:?}",
+                a,
+                b,
+                sep
+            );
+            assert!(sep.len() <= a.len());
+            let succ = c.successor(&a);
+            assert_ne!(
+                c.compare(&a, &succ),
+                Ordering::Greater,
+                "{:?} {:?}",
+                a,
+                succ
+            );
+            assert!(succ.len() <= a.len());
+        }
+    }
+
+    #[test]
+    fn test_no_shortening_comparator() {
+        let c = NoShorteningComparator::new(Arc::new(BytewiseComparator::new()));
+        assert_eq!(c.name(), "leveldb.BytewiseComparator");
+        assert_eq!(c.compare(b"a", b"b"), Ordering::Less);
+        assert_eq!(c.separator(b"1111", b"13345"), b"1111".to_vec());
+        assert_eq!(c.successor(b"111"), b"111".to_vec());
+        assert_eq!(c.successor(b"a\xff\xff"), b"a\xff\xff".to_vec());
+    }
+
+    #[test]
+    fn test_numeric_comparator() {
+        let c = NumericComparator::new();
+        let mut keys: Vec<u64> = vec![0, 1, 255, 256, 1 << 32, u64::MAX - 1, 7, 65535];
+        let mut encoded: Vec<[u8; 8]> = keys.iter().map(|k| k.to_be_bytes()).collect();
+        keys.sort();
+        encoded.sort_by(|a, b| c.compare(a, b));
+        let decoded: Vec<u64> = encoded.iter().map(|k| u64::from_be_bytes(*k)).collect();
+        assert_eq!(decoded, keys);
+
+        // mixed-length keys
+        let tests: Vec<(&[u8], &[u8], Ordering)> = vec![
+            (b"", b"", Ordering::Equal),
+            (b"", b"\x00", Ordering::Less),
+            (b"\x02", b"\x00\x03", Ordering::Less),
+            (b"\x00\x03", b"\x01\x00", Ordering::Less),
+            (b"\x01", b"\x00\x01", Ordering::Less),
+            (b"\x00\x00\xff", b"\x01\x00", Ordering::Less),
+            (b"\x01\x00", b"\x01\x00", Ordering::Equal),
+            (b"\x00\x01\x00\x00", b"\xff", Ordering::Greater),
+        ];
+        for (a, b, expect) in tests {
+            assert_eq!(c.compare(a, b), expect, "{:?} vs {:?}", a, b);
+            assert_eq!(c.compare(b, a), expect.reverse(), "{:?} vs {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_numeric_comparator_shortening() {
+        let c = NumericComparator::new();
+        let sep = |a: u64, b: u64| c.separator(&a.to_be_bytes(), &b.to_be_bytes());
+        assert_eq!(sep(5, 9), vec![6]);
+        assert_eq!(sep(255, 1000), vec![1, 0]);
+        // `[6]` is shorter than the key of 6 in 8 bytes
+        assert_eq!(sep(5, 6), vec![6]);
+        // no key shorter than `a` between the two
+        assert_eq!(
+            sep(u64::MAX - 1, u64::MAX),
+            (u64::MAX - 1).to_be_bytes().to_vec()
+        );
+        let mut rng = rand::thread_rng();
+        for _ in 0..10000 {
+            let (a, b): (u64, u64) = (rng.gen_range(0, 1 << 20), rng.gen_range(0, 1 << 20));
+            let (a, b) = (a.min(b), a.max(b));
+            let (ea, eb) = (a.to_be_bytes(), b.to_be_bytes());
+            let s = c.separator(&ea, &eb);
+            assert_ne!(c.compare(&s, &ea), Ordering::Less);
+            if a < b {
+                assert_eq!(c.compare(&s, &eb), Ordering::Less);
+            }
+            assert_ne!(c.compare(&c.successor(&ea), &ea), Ordering::Less);
+        }
+        assert_eq!(c.successor(&255u64.to_be_bytes()), vec![1, 0]);
+        assert_eq!(c.successor(&[0xff, 0xff]), vec![0xff, 0xff]);
+        assert_eq!(c.successor(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_reverse_comparator() {
+        let c = ReverseComparator::new(Arc::new(BytewiseComparator::new()));
+        assert_eq!(
+            c.name(),
+            "wickdb.ReverseComparator(leveldb.BytewiseComparator)"
+        );
+        assert_eq!(c.compare(b"a", b"b"), Ordering::Greater);
+        assert_eq!(c.compare(b"b", b"a"), Ordering::Less);
+        assert_eq!(c.compare(b"a", b"a"), Ordering::Equal);
+        assert_eq!(c.compare(b"ab", b"a"), Ordering::Less);
+        assert_eq!(c.separator(b"b", b"a"), b"b".to_vec());
+        assert_eq!(c.successor(b"b"), b"b".to_vec());
+    }
 }
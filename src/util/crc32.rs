@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crc::crc32::{checksum_castagnoli, make_table, update};
+use crc::crc32::{make_table, update};
 
 const MASK_DELTA: u32 = 0xa282ead8;
 const CASTAGNOLI_POLY: u32 = 0x82f63b78;
@@ -22,13 +22,49 @@ lazy_static! {
 
 /// Returns a `u32` crc checksum for give data
 pub fn value(data: &[u8]) -> u32 {
-    checksum_castagnoli(data)
+    extend(0, data)
 }
 
+/// Returns the crc checksum of `A + data` where `crc` is the checksum of some string `A`.
+///
+/// The CRC-32C instructions of SSE4.2 are used if the CPU supports them, which is
+/// detected at runtime. Otherwise the table based implementation on the `crc` crate
+/// is used. Both produce the same Castagnoli CRC-32C.
 pub fn extend(crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            // Safe because the CPU supports SSE4.2
+            return unsafe { extend_sse42(crc, data) };
+        }
+    }
+    extend_portable(crc, data)
+}
+
+// The table based CRC-32C
+fn extend_portable(crc: u32, data: &[u8]) -> u32 {
     update(crc, &TABLE32, data)
 }
 
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn extend_sse42(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut state = u64::from(!crc);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        state = _mm_crc32_u64(state, u64::from_le_bytes(word));
+    }
+    let mut state = state as u32;
+    for b in chunks.remainder() {
+        state = _mm_crc32_u8(state, *b);
+    }
+    !state
+}
+
 /// Return a masked representation of crc.
 ///
 /// Motivation: it is problematic to compute the CRC of a string that
@@ -88,6 +124,18 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn test_accelerated_matches_portable() {
+        let data: Vec<u8> = (0..1024u32).map(|i| (i * 131 + i / 7) as u8).collect();
+        for start in 0..9 {
+            for end in [start, start + 1, start + 7, start + 8, 100, 1024].iter() {
+                let d = &data[start..*end];
+                assert_eq!(value(d), extend_portable(0, d));
+                assert_eq!(extend(0x1234_5678, d), extend_portable(0x1234_5678, d));
+            }
+        }
+    }
+
     #[test]
     pub fn test_mask_unmask() {
         let crc = value("foo".as_bytes());
@@ -0,0 +1,44 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// A `MergeOperator` specifies the semantics of a merge, which is a
+/// read-modify-write operation applied lazily: `WriteBatch::merge` only records
+/// an operand, and the operands are folded over the existing value of the key
+/// when the key is read or compacted.
+///
+/// A `MergeOperator` must be thread-safe since its methods might be invoked
+/// concurrently from multiple threads.
+pub trait MergeOperator: Send + Sync {
+    /// The name of the merge operator
+    fn name(&self) -> &str;
+
+    /// Applies the `operands` (from the oldest to the newest) on the `existing_value`
+    /// of `key`. `existing_value` is `None` if the key doesn't exist or has been deleted.
+    ///
+    /// Returns `None` if the operands can't be applied, which is reported as a corruption
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing_value: Option<&[u8]>,
+        operands: &[Vec<u8>],
+    ) -> Option<Vec<u8>>;
+
+    /// Combines two successive operands of `key` into one so that the operands
+    /// can be folded during compaction even if the existing value is unknown.
+    /// `left` is older than `right`.
+    ///
+    /// Returns `None` if the operands can't be combined, and then both of them are kept.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
@@ -13,11 +13,15 @@
 
 pub mod byte;
 pub mod coding;
+pub mod compaction_filter;
 pub mod comparator;
 pub mod crc32;
 #[macro_use]
 pub mod status;
 pub mod hash;
+pub mod merge_operator;
+pub mod prefix_extractor;
+pub mod rate_limiter;
 pub mod reporter;
 pub mod slice;
 pub mod varint;
@@ -0,0 +1,83 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// A `PrefixExtractor` extracts a prefix from a user key. Keys with the same prefix
+/// are considered to be in a same group that can be iterated together.
+///
+/// When a `PrefixExtractor` is configured in `Options`:
+///
+/// * An iterator positioned by `seek(target)` only yields the keys with the same
+///   prefix as `target`
+/// * The prefixes are also added into the filter blocks so that a point lookup
+///   for a key whose prefix was never inserted can be rejected by the filter
+///
+/// The prefix of a key must be consistent with the `Comparator`: all the keys with
+/// the same prefix must be adjacent in the key order.
+pub trait PrefixExtractor: Send + Sync {
+    /// The name of the extractor
+    fn name(&self) -> &str;
+
+    /// Returns the prefix of the given key.
+    ///
+    /// REQUIRES: `in_domain(key)`
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    /// Returns whether the key has a prefix. Keys out of the domain are
+    /// never restricted by the prefix.
+    fn in_domain(&self, _key: &[u8]) -> bool {
+        true
+    }
+}
+
+/// Extracts the first `len` bytes of a key as the prefix.
+/// Keys shorter than `len` are out of the domain.
+pub struct FixedLengthPrefixExtractor {
+    len: usize,
+}
+
+impl FixedLengthPrefixExtractor {
+    pub fn new(len: usize) -> Self {
+        Self { len }
+    }
+}
+
+impl PrefixExtractor for FixedLengthPrefixExtractor {
+    fn name(&self) -> &str {
+        "wickdb.FixedLengthPrefixExtractor"
+    }
+
+    #[inline]
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+
+    #[inline]
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_length_prefix_extractor() {
+        let e = FixedLengthPrefixExtractor::new(3);
+        assert!(!e.in_domain(b""));
+        assert!(!e.in_domain(b"ab"));
+        assert!(e.in_domain(b"abc"));
+        assert_eq!(e.transform(b"abc"), b"abc");
+        assert_eq!(e.transform(b"abcdef"), b"abc");
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2019 Fullstop000 <fullstop1005@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A `RateLimiter` controls the rate of the background I/O issued by
+/// flushes and compactions so that they don't hurt the foreground latency.
+/// Foreground writes (the WAL) are never throttled.
+pub trait RateLimiter: Send + Sync {
+    /// Requests for writing `bytes` bytes. Blocks until there are enough tokens.
+    fn request(&self, bytes: usize);
+
+    /// Returns the total bytes requested through this limiter so far
+    fn total_bytes_requested(&self) -> u64;
+}
+
+// Tokens are refilled every 100ms, so that a limiter allows at most
+// a burst of `bytes_per_sec / 10` bytes
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+struct Bucket {
+    // Negative if the limiter is in debt because of a request larger
+    // than the available tokens
+    available: i64,
+    last_refill: Instant,
+}
+
+/// A token bucket `RateLimiter` which allows `bytes_per_sec` bytes in a second
+pub struct GenericRateLimiter {
+    bytes_per_sec: u64,
+    burst: i64,
+    bucket: Mutex<Bucket>,
+    total_bytes_requested: AtomicU64,
+}
+
+impl GenericRateLimiter {
+    /// # Panics
+    ///
+    /// * If `bytes_per_sec` is zero
+    pub fn new(bytes_per_sec: u64) -> Self {
+        assert!(
+            bytes_per_sec > 0,
+            "[rate limiter] bytes_per_sec must be positive"
+        );
+        let burst = (bytes_per_sec * REFILL_PERIOD.as_millis() as u64 / 1000).max(1) as i64;
+        Self {
+            bytes_per_sec,
+            burst,
+            bucket: Mutex::new(Bucket {
+                available: burst,
+                last_refill: Instant::now(),
+            }),
+            total_bytes_requested: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RateLimiter for GenericRateLimiter {
+    fn request(&self, bytes: usize) {
+        self.total_bytes_requested
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refilled = now.duration_since(bucket.last_refill).as_micros() as u64
+                * self.bytes_per_sec
+                / 1_000_000;
+            if refilled > 0 {
+                bucket.available = (bucket.available + refilled as i64).min(self.burst);
+                bucket.last_refill = now;
+            }
+            bucket.available -= bytes as i64;
+            if bucket.available >= 0 {
+                return;
+            }
+            // Callers wait in the order of their requests since the debt is
+            // accumulated under the lock
+            Duration::from_micros(-bucket.available as u64 * 1_000_000 / self.bytes_per_sec)
+        };
+        thread::sleep(wait);
+    }
+
+    fn total_bytes_requested(&self) -> u64 {
+        self.total_bytes_requested.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generic_rate_limiter() {
+        // 10 KB per second with a burst of 1 KB
+        let limiter = GenericRateLimiter::new(10 * 1024);
+        let start = Instant::now();
+        for _ in 0..30 {
+            limiter.request(100);
+        }
+        // the first 1 KB is the burst and the rest takes 200ms at least
+        assert!(start.elapsed() >= Duration::from_millis(180));
+        assert_eq!(limiter.total_bytes_requested(), 3000);
+    }
+}
@@ -47,8 +47,12 @@ impl LogReporter {
 }
 
 impl Reporter for LogReporter {
-    fn corruption(&mut self, _bytes: u64, reason: &str) {
+    fn corruption(&mut self, offset: u64, bytes: u64, reason: &str) {
+        info!(
+            "log corruption at offset {}: dropping {} bytes; {}",
+            offset, bytes, reason
+        );
         self.inner.borrow_mut().ok = false;
-        self.inner.borrow_mut().reason = reason.to_owned();
+        self.inner.borrow_mut().reason = format!("{} at offset {}", reason, offset);
     }
 }
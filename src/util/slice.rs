@@ -76,9 +76,17 @@ impl Slice {
         self.data
     }
 
+    /// Compares the referenced data bytewise, which is the same order as the
+    /// `BytewiseComparator`. An empty Slice is less than any other Slice.
     #[inline]
     pub fn compare(&self, other: &Slice) -> Ordering {
-        compare(self.as_slice(), other.as_slice())
+        compare(self.bytes(), other.bytes())
+    }
+
+    /// Returns true if the referenced data starts with `prefix`
+    #[inline]
+    pub fn starts_with(&self, prefix: &[u8]) -> bool {
+        self.bytes().starts_with(prefix)
     }
 
     #[inline]
@@ -95,6 +103,16 @@ impl Slice {
             unsafe { ::std::str::from_utf8_unchecked(self.as_slice()) }
         }
     }
+
+    // Same as `as_slice` but returns an empty `&[u8]` for an invalid Slice
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        if self.data.is_null() {
+            &[]
+        } else {
+            self.as_slice()
+        }
+    }
 }
 
 impl Default for Slice {
@@ -115,6 +133,18 @@ impl PartialEq for Slice {
     }
 }
 
+impl PartialOrd for Slice {
+    fn partial_cmp(&self, other: &Slice) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Slice {
+    fn cmp(&self, other: &Slice) -> Ordering {
+        self.compare(other)
+    }
+}
+
 impl Index<usize> for Slice {
     type Output = u8;
 
@@ -132,7 +162,7 @@ impl Index<usize> for Slice {
 
 impl Hash for Slice {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let hash = hash(self.as_slice(), 0xbc9f1d34);
+        let hash = hash(self.bytes(), 0xbc9f1d34);
         state.write_u32(hash);
         state.finish();
     }
@@ -158,3 +188,52 @@ impl<'a> From<&'a str> for Slice {
         Slice::new(s.as_ptr(), s.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::{BytewiseComparator, Comparator};
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::BTreeMap;
+
+    fn hash_of(s: &Slice) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        s.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_slice_ord() {
+        let keys: Vec<&[u8]> = vec![b"", b"\x00", b"a", b"a\x00", b"ab", b"b", b"\xff"];
+        let cmp = BytewiseComparator::new();
+        for a in keys.iter() {
+            for b in keys.iter() {
+                assert_eq!(Slice::from(*a).cmp(&Slice::from(*b)), cmp.compare(a, b));
+            }
+        }
+        assert_eq!(Slice::default().cmp(&Slice::from("")), Ordering::Equal);
+        assert!(Slice::default() < Slice::from("a"));
+
+        let mut map = BTreeMap::new();
+        for k in keys.iter().rev() {
+            map.insert(Slice::from(*k), k.len());
+        }
+        let sorted: Vec<&[u8]> = map.keys().map(|k| k.bytes()).collect();
+        assert_eq!(sorted, keys);
+    }
+
+    #[test]
+    fn test_slice_hash_and_starts_with() {
+        let v = b"prefix_key".to_vec();
+        let s = Slice::from(&v);
+        assert_eq!(hash_of(&s), hash_of(&Slice::from("prefix_key")));
+        assert_ne!(hash_of(&s), hash_of(&Slice::from("prefix")));
+        assert_eq!(hash_of(&Slice::default()), hash_of(&Slice::from("")));
+        assert!(s.starts_with(b"prefix"));
+        assert!(s.starts_with(b""));
+        assert!(!s.starts_with(b"key"));
+        assert!(!s.starts_with(b"prefix_key_"));
+        assert!(Slice::default().starts_with(b""));
+        assert!(!Slice::default().starts_with(b"p"));
+    }
+}
@@ -29,6 +29,7 @@ pub enum Status {
     InvalidArgument,
     CompressionError,
     IOError,
+    Aborted,
 
     Unexpected,
     Default, // used for default
@@ -43,6 +44,7 @@ impl Status {
             Status::InvalidArgument => "InvalidArgumentError",
             Status::CompressionError => "CompressionError",
             Status::IOError => "IOError",
+            Status::Aborted => "AbortedError",
             Status::Unexpected => "UnexpectedError",
             _ => "",
         }
@@ -55,11 +57,18 @@ pub struct WickErr {
     t: Status,
     msg: Option<&'static str>,
     raw: Option<Rc<Box<dyn Error>>>,
+    // where the error occurs, like the file and the offset being read
+    context: Option<String>,
 }
 
 impl WickErr {
     pub fn new(t: Status, msg: Option<&'static str>) -> Self {
-        Self { t, msg, raw: None }
+        Self {
+            t,
+            msg,
+            raw: None,
+            context: None,
+        }
     }
 
     pub fn new_from_raw(t: Status, msg: Option<&'static str>, raw: Box<dyn Error>) -> Self {
@@ -67,9 +76,20 @@ impl WickErr {
             t,
             msg,
             raw: Some(Rc::new(raw)),
+            context: None,
         }
     }
 
+    /// Attaches where the error occurs to the error. The contexts attached by the
+    /// callers are prepended, so the outermost one comes first.
+    pub fn with_context(mut self, context: String) -> Self {
+        self.context = Some(match self.context.take() {
+            Some(inner) => format!("{}: {}", context, inner),
+            None => context,
+        });
+        self
+    }
+
     #[inline]
     pub fn take_raw(&mut self) -> Option<Rc<Box<dyn Error>>> {
         mem::replace(&mut self.raw, None)
@@ -79,6 +99,17 @@ impl WickErr {
     pub fn status(&self) -> Status {
         self.t.clone()
     }
+
+    #[inline]
+    pub fn msg(&self) -> Option<&'static str> {
+        self.msg
+    }
+
+    /// Returns where the error occurs if known
+    #[inline]
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
 }
 
 unsafe impl Send for WickErr {}
@@ -103,6 +134,7 @@ impl Clone for WickErr {
             t: self.t.clone(),
             msg: self.msg,
             raw: self.raw.clone(),
+            context: self.context.clone(),
         }
     }
 }
@@ -113,41 +145,28 @@ impl Default for WickErr {
             t: Status::Default,
             msg: None,
             raw: None,
+            context: None,
         }
     }
 }
 
 impl Display for WickErr {
     fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
-        match self.msg {
-            Some(m) => match &self.raw {
-                Some(e) => {
-                    return write!(
-                        f,
-                        "WickDB error [{}] : {} , raw : {}",
-                        self.t.as_str(),
-                        m,
-                        e.description()
-                    );
-                }
-                None => {
-                    return write!(f, "WickDB error [{}] : {}", self.t.as_str(), m);
-                }
-            },
-            None => match &self.raw {
-                Some(e) => {
-                    return write!(
-                        f,
-                        "WickDB error [{}] : {}",
-                        self.t.as_str(),
-                        e.description()
-                    );
-                }
-                None => {
-                    return write!(f, "WickDB error [{}]", self.t.as_str());
-                }
-            },
+        write!(f, "WickDB error [{}]", self.t.as_str())?;
+        if let Some(m) = self.msg {
+            write!(f, " : {}", m)?;
+        }
+        if let Some(e) = &self.raw {
+            if self.msg.is_some() {
+                write!(f, " , raw : {}", e)?;
+            } else {
+                write!(f, " : {}", e)?;
+            }
         }
+        if let Some(context) = &self.context {
+            write!(f, " , context : {}", context)?;
+        }
+        Ok(())
     }
 }
 
@@ -161,4 +180,39 @@ impl ::std::error::Error for WickErr {
             },
         }
     }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.raw.as_ref().map(|e| e.as_ref().as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_error_source_and_context() {
+        let e = WickErr::new(Status::Corruption, Some("bad block"));
+        assert!(e.source().is_none());
+        assert_eq!(e.to_string(), "WickDB error [CorruptionError] : bad block");
+        let e = e
+            .with_context("block at offset 10".to_owned())
+            .with_context("table 000001.sst".to_owned());
+        assert_eq!(e.context(), Some("table 000001.sst: block at offset 10"));
+        assert_eq!(e.clone().context(), e.context());
+        assert_eq!(
+            e.to_string(),
+            "WickDB error [CorruptionError] : bad block , context : table 000001.sst: block at offset 10"
+        );
+
+        let raw = io::Error::new(io::ErrorKind::UnexpectedEof, "short read");
+        let e = WickErr::new_from_raw(Status::IOError, None, Box::new(raw));
+        let source = e.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(e.to_string(), "WickDB error [IOError] : short read");
+    }
 }
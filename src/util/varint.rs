@@ -62,6 +62,16 @@ macro_rules! impl_varint {
                 None
             }
 
+            /// Decodes a uint from the front of `cursor` and advances `cursor` past the
+            /// decoded bytes. If an error or overflow occurred, returns `None` and
+            /// `cursor` is left unchanged.
+            #[inline]
+            pub fn decode_from(cursor: &mut &[u8]) -> Option<$uint> {
+                let (n, i) = Self::read(cursor)?;
+                *cursor = &cursor[i..];
+                Some(n)
+            }
+
             /// Append `n` as varint bytes into the dst.
             /// Returns the bytes written.
             pub fn put_varint(dst: &mut Vec<u8>, mut n: $uint) -> usize {
@@ -146,6 +156,61 @@ macro_rules! impl_varint {
 impl_varint!(VarintU32, u32);
 impl_varint!(VarintU64, u64);
 
+/// Zigzag encoded signed varints, which map the signed integers to the unsigned ones
+/// so that the numbers with a small absolute value have a short encoding:
+/// 0 => 0, -1 => 1, 1 => 2, -2 => 3, ...
+///
+/// The max encoded lengths are the same as the unsigned ones,
+/// `MAX_VARINT_LEN_U32` and `MAX_VARINT_LEN_U64`.
+pub struct VarintI32 {}
+pub struct VarintI64 {}
+
+macro_rules! impl_signed_varint {
+    ($type:ty, $int: ty, $unsigned_type: ty, $uint: ty) => {
+        impl $type {
+            #[inline]
+            fn zigzag_encode(n: $int) -> $uint {
+                ((n << 1) ^ (n >> (<$int>::BITS - 1))) as $uint
+            }
+
+            #[inline]
+            fn zigzag_decode(n: $uint) -> $int {
+                ((n >> 1) as $int) ^ -((n & 1) as $int)
+            }
+
+            /// Encodes an int into given vec and returns the number of bytes written.
+            ///
+            /// # Panic
+            ///
+            /// Panic when `dst` length is not enough
+            pub fn write(dst: &mut [u8], n: $int) -> usize {
+                <$unsigned_type>::write(dst, Self::zigzag_encode(n))
+            }
+
+            /// Decodes an int(32 or 64) from given bytes and returns that value and the
+            /// number of bytes read ( > 0).
+            /// If an error or overflow occurred, returns `None`
+            pub fn read(src: &[u8]) -> Option<($int, usize)> {
+                <$unsigned_type>::read(src).map(|(n, i)| (Self::zigzag_decode(n), i))
+            }
+
+            /// Append `n` as varint bytes into the dst.
+            /// Returns the bytes written.
+            pub fn put_varint(dst: &mut Vec<u8>, n: $int) -> usize {
+                <$unsigned_type>::put_varint(dst, Self::zigzag_encode(n))
+            }
+
+            /// Decodes an int from the give slice , and advance the given slice
+            pub fn drain_read(src: &mut Slice) -> Option<$int> {
+                <$unsigned_type>::drain_read(src).map(Self::zigzag_decode)
+            }
+        }
+    };
+}
+
+impl_signed_varint!(VarintI32, i32, VarintU32, u32);
+impl_signed_varint!(VarintI64, i64, VarintU64, u64);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +300,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_signed_varint() {
+        let tests = vec![
+            (0i64, vec![0]),
+            (-1, vec![1]),
+            (1, vec![2]),
+            (-64, vec![0b111_1111]),
+            (64, vec![0b1000_0000, 0b1]),
+            (
+                i64::MAX,
+                vec![0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x1],
+            ),
+            (
+                i64::MIN,
+                vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x1],
+            ),
+        ];
+        for (n, expect) in tests {
+            let mut buf = vec![];
+            assert_eq!(VarintI64::put_varint(&mut buf, n), expect.len());
+            assert_eq!(buf, expect);
+            let mut dst = [0; MAX_VARINT_LEN_U64];
+            assert_eq!(VarintI64::write(&mut dst, n), expect.len());
+            assert_eq!(&dst[..expect.len()], expect.as_slice());
+            assert_eq!(VarintI64::read(&buf), Some((n, expect.len())));
+            // truncated
+            assert_eq!(VarintI64::read(&buf[..buf.len() - 1]), None);
+        }
+        for n in [0i32, -1, 1, i32::MIN, i32::MAX, -12345, 12345].iter() {
+            let mut buf = vec![];
+            let written = VarintI32::put_varint(&mut buf, *n);
+            assert!(written <= MAX_VARINT_LEN_U32);
+            assert_eq!(VarintI32::read(&buf), Some((*n, written)));
+            assert_eq!(VarintI32::read(&buf[..written - 1]), None);
+            let mut s = Slice::from(buf.as_slice());
+            assert_eq!(VarintI32::drain_read(&mut s), Some(*n));
+            assert_eq!(s.size(), 0);
+        }
+    }
+
+    #[test]
+    fn test_decode_from() {
+        let mut buf = vec![];
+        let mut numbers = vec![];
+        for i in 0..10000 {
+            // values of all the encoded lengths
+            let r = rand::random::<u64>() >> (i % 64);
+            VarintU64::put_varint(&mut buf, r);
+            numbers.push(r);
+        }
+        let mut cursor = buf.as_slice();
+        let mut start = 0;
+        for n in numbers.iter() {
+            let (expect, len) = VarintU64::read(&buf[start..]).unwrap();
+            start += len;
+            assert_eq!(expect, *n);
+            assert_eq!(VarintU64::decode_from(&mut cursor), Some(expect));
+            assert_eq!(cursor.len(), buf.len() - start);
+        }
+        assert!(cursor.is_empty());
+        assert_eq!(VarintU64::decode_from(&mut cursor), None);
+
+        // a truncated or overflowed varint leaves the cursor unchanged
+        let truncated = [0b1000_0001, 0b1000_0001];
+        let mut cursor = &truncated[..];
+        assert_eq!(VarintU32::decode_from(&mut cursor), None);
+        assert_eq!(cursor.len(), 2);
+        let overflowed = [0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let mut cursor = &overflowed[..];
+        assert_eq!(VarintU32::decode_from(&mut cursor), None);
+        assert_eq!(cursor.len(), 6);
+    }
+
     #[test]
     fn test_put_and_get_prefixed_slice() {
         let mut encoded = vec![];
@@ -15,12 +15,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use crate::compaction::{pick_sorted_runs, SortedRun};
 use crate::db::format::{
-    InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey, ValueType,
+    InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey, ValueType, MAX_KEY_SEQUENCE,
     VALUE_TYPE_FOR_SEEK,
 };
+use crate::db::range_del::max_covering_tombstone_seq;
 use crate::iterator::Iterator;
-use crate::options::{Options, ReadOptions};
+use crate::options::{CompactionStyle, Options, ReadOptions};
 use crate::table_cache::TableCache;
 use crate::util::coding::put_fixed_64;
 use crate::util::comparator::Comparator;
@@ -95,6 +97,13 @@ impl SeekStats {
     }
 }
 
+// A sstable at `level` searched by a point lookup
+struct TableLookup<'a> {
+    table_cache: &'a TableCache,
+    level: usize,
+    file: &'a FileMetaData,
+}
+
 impl Version {
     pub fn new(options: Arc<Options>, icmp: Arc<InternalKeyComparator>) -> Self {
         let max_levels = options.max_levels as usize;
@@ -114,64 +123,187 @@ impl Version {
     }
 
     /// Search the value by the given key in sstables level by level
+    /// Searches the value of `key` from the newest level to the oldest.
+    /// The merge operands found before the value are pushed into `merge_operands`
+    /// from the newest to the oldest.
+    /// If `newest_seq` is `None`, it's set to the sequence number of the newest entry found.
     pub fn get(
         &self,
-        options: ReadOptions,
-        key: LookupKey,
+        opt: Rc<ReadOptions>,
+        key: &LookupKey,
         table_cache: Arc<TableCache>,
-    ) -> Result<(Option<Slice>, SeekStats)> {
-        let opt = Rc::new(options);
+        merge_operands: &mut Vec<Vec<u8>>,
+        newest_seq: &mut Option<u64>,
+    ) -> Result<(Option<Vec<u8>>, SeekStats)> {
         let ikey = key.internal_key();
         let ukey = key.user_key();
         let ucmp = self.icmp.user_comparator.as_ref();
-        let mut files_to_seek = vec![];
         let mut seek_stats = SeekStats::new();
-        for (level, files) in self.files.iter().enumerate() {
-            if files.is_empty() {
-                continue;
-            }
-            if level == 0 {
-                // Level-0 files may overlap each other. Find all files that
-                // overlap user_key and process them in order from newest to oldest because
-                // the last level-0 file always has the newest entries.
-                for f in files.iter().rev() {
-                    if ucmp.compare(ukey.as_slice(), f.largest.data()) != CmpOrdering::Greater
-                        && ucmp.compare(ukey.as_slice(), f.smallest.data()) != CmpOrdering::Less
-                    {
-                        files_to_seek.push(f.clone());
+        // The file read last time, which is charged for the seek if another
+        // file has to be read
+        let mut last_file_read: Option<(usize, Arc<FileMetaData>)> = None;
+        // The largest sequence number of the range tombstones covering the key in the
+        // files searched so far. The entries older than it are deleted.
+        let mut tombstone_seq: Option<u64> = None;
+        'search: for level in 0..self.files.len() {
+            let files_to_seek = self.files_to_seek(level, key);
+            for file in files_to_seek.iter() {
+                if let Some((last_level, last_file)) = last_file_read.take() {
+                    if seek_stats.seek_file.is_none() {
+                        // Only the first file read is charged
+                        seek_stats.seek_file_level = Some(last_level);
+                        seek_stats.seek_file = Some(last_file);
                     }
-                    files_to_seek.sort_by(|a, b| b.number.cmp(&a.number))
                 }
-            } else {
-                let index = Self::find_file(self.icmp.clone(), self.files[level].as_slice(), &ikey);
-                if index >= files.len() {
-                    // TODO: maybe '==' is enough ?
-                    // we reach the end but not found a file matches
-                } else {
-                    let target = files[index].clone();
-                    // if what we found is just the first file, it could still not includes the target
-                    if ucmp.compare(ukey.as_slice(), target.smallest.data()) != CmpOrdering::Less {
-                        files_to_seek = vec![target];
+                last_file_read = Some((level, file.clone()));
+                if file.has_range_tombstones {
+                    let tombstones =
+                        table_cache.range_tombstones(level, file.number, file.file_size)?;
+                    tombstone_seq = tombstone_seq.max(max_covering_tombstone_seq(
+                        tombstones.as_slice(),
+                        ucmp,
+                        ukey.as_slice(),
+                        key.sequence(),
+                    ));
+                }
+                match table_cache.get(opt.clone(), &ikey, level, file.number, file.file_size)? {
+                    None => continue, // keep searching
+                    // The tombstone starting at the key is handled as the one covering it
+                    Some((value_type, seq, value))
+                        if value_type != ValueType::RangeDeletion
+                            && !matches!(tombstone_seq, Some(t) if seq <= t) =>
+                    {
+                        if newest_seq.is_none() {
+                            *newest_seq = Some(seq);
+                        }
+                        match value_type {
+                            ValueType::Value => return Ok((Some(value), seek_stats)),
+                            ValueType::Deletion => return Ok((None, seek_stats)),
+                            ValueType::Merge => {
+                                let table = TableLookup {
+                                    table_cache: &table_cache,
+                                    level,
+                                    file,
+                                };
+                                if let Some(base) = self.collect_merge_operands(
+                                    opt.clone(),
+                                    &ikey,
+                                    ukey.as_slice(),
+                                    &table,
+                                    tombstone_seq,
+                                    merge_operands,
+                                )? {
+                                    return Ok((base, seek_stats));
+                                }
+                            }
+                            _ => {}
+                        }
                     }
+                    // The entry is deleted by a newer range tombstone
+                    Some(_) => break 'search,
                 }
             }
+        }
+        if let Some(seq) = tombstone_seq {
+            // The key is deleted by the range tombstone
+            if newest_seq.is_none() {
+                *newest_seq = Some(seq);
+            }
+        }
+        Ok((None, seek_stats))
+    }
 
-            for file in files_to_seek.iter() {
-                seek_stats.seek_file_level = Some(level);
-                seek_stats.seek_file = Some(file.clone());
-                match table_cache.get(opt.clone(), &ikey, file.number, file.file_size)? {
-                    None => continue, // keep searching
-                    Some(parsed_key) => match parsed_key.value_type {
+    /// Returns false if all the sstables which might contain `key` report that the
+    /// key is absent by their filters. No data block is read.
+    pub fn key_may_exist(&self, key: &LookupKey, table_cache: Arc<TableCache>) -> bool {
+        let ikey = key.internal_key();
+        (0..self.files.len()).any(|level| {
+            self.files_to_seek(level, key)
+                .iter()
+                .any(|f| table_cache.key_may_match(ikey.as_slice(), level, f.number, f.file_size))
+        })
+    }
+
+    // Returns the files at `level` whose key range contains the user key of `key`,
+    // from the newest to the oldest.
+    fn files_to_seek(&self, level: usize, key: &LookupKey) -> Vec<Arc<FileMetaData>> {
+        let files = &self.files[level];
+        let ukey = key.user_key();
+        let ucmp = self.icmp.user_comparator.as_ref();
+        let mut files_to_seek = vec![];
+        if files.is_empty() {
+            return files_to_seek;
+        }
+        if level == 0 {
+            // Level-0 files may overlap each other. Find all files that
+            // overlap user_key and process them in order from newest to oldest because
+            // the last level-0 file always has the newest entries.
+            for f in files.iter() {
+                if ucmp.compare(ukey.as_slice(), f.largest.user_key()) != CmpOrdering::Greater
+                    && ucmp.compare(ukey.as_slice(), f.smallest.user_key()) != CmpOrdering::Less
+                {
+                    files_to_seek.push(f.clone());
+                }
+            }
+            files_to_seek.sort_by_key(|f| std::cmp::Reverse(f.number));
+        } else {
+            let index = Self::find_file(self.icmp.clone(), files.as_slice(), &key.internal_key());
+            if index < files.len() {
+                let target = files[index].clone();
+                // if what we found is just the first file, it could still not includes the target
+                if ucmp.compare(ukey.as_slice(), target.smallest.user_key()) != CmpOrdering::Less {
+                    files_to_seek.push(target);
+                }
+            }
+        }
+        files_to_seek
+    }
+
+    // Walks the entries of `ukey` not newer than `ikey` in the given table and pushes the
+    // merge operands into `operands`. Returns `Some(base)` once a value or a deletion is
+    // found, otherwise the older files should be searched. The entries older than
+    // `tombstone_seq` are deleted by a range tombstone.
+    fn collect_merge_operands(
+        &self,
+        options: Rc<ReadOptions>,
+        ikey: &Slice,
+        ukey: &[u8],
+        table: &TableLookup,
+        tombstone_seq: Option<u64>,
+        operands: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<Option<Vec<u8>>>> {
+        let ucmp = self.icmp.user_comparator.as_ref();
+        let mut iter = table.table_cache.new_iter(
+            options,
+            table.level,
+            table.file.number,
+            table.file.file_size,
+        );
+        iter.seek(ikey);
+        while iter.valid() {
+            match ParsedInternalKey::decode_from(iter.key()) {
+                Some(pkey)
+                    if ucmp.compare(pkey.user_key.as_slice(), ukey) == CmpOrdering::Equal =>
+                {
+                    if tombstone_seq.is_some_and(|t| pkey.seq < t) {
+                        return Ok(Some(None));
+                    }
+                    match pkey.value_type {
+                        ValueType::Merge => operands.push(Vec::from(iter.value().as_slice())),
                         ValueType::Value => {
-                            return Ok((Some(parsed_key.user_key.clone()), seek_stats))
+                            return Ok(Some(Some(Vec::from(iter.value().as_slice()))))
                         }
-                        ValueType::Deletion => return Ok((None, seek_stats)),
-                        _ => {}
-                    },
+                        // A range tombstone starting at `ukey` deletes the older entries too
+                        ValueType::Deletion | ValueType::RangeDeletion => return Ok(Some(None)),
+                        ValueType::Unknown => {}
+                    }
                 }
+                _ => break,
             }
+            iter.next();
         }
-        Ok((None, seek_stats))
+        iter.status()?;
+        Ok(None)
     }
 
     /// Update seek stats for a sstable file. If it runs out of `allow_seek`,
@@ -212,7 +344,7 @@ impl Version {
         let mut left = 0;
         let mut right = files.len();
         while left < right {
-            let mid = left + right / 2;
+            let mid = (left + right) / 2;
             let f = &files[mid];
             if icmp.compare(f.largest.data(), ikey.as_slice()) == CmpOrdering::Less {
                 // Key at "mid.largest" is < "target".  Therefore all
@@ -235,15 +367,22 @@ impl Version {
         largest_ukey: &Slice,
     ) -> usize {
         let mut level = 0;
+        if self.options.compaction_style == CompactionStyle::Universal {
+            // Every flushed memtable is a new sorted run in level 0
+            return level;
+        }
+        if self.options.flush_to_lowest_level {
+            return self.pick_level_for_external_file(smallest_ukey, largest_ukey);
+        }
         if !self.overlap_in_level(level, smallest_ukey, largest_ukey) {
             // No overlapping in level 0
             // we might directly push files to next level if there is no overlap in next level
-            let smallest_ikey = Rc::new(InternalKey::new(
+            let smallest_ikey = Arc::new(InternalKey::new(
                 smallest_ukey,
-                u64::max_value(),
+                MAX_KEY_SEQUENCE,
                 VALUE_TYPE_FOR_SEEK,
             ));
-            let largest_ikey = Rc::new(InternalKey::new(largest_ukey, 0, ValueType::Deletion));
+            let largest_ikey = Arc::new(InternalKey::new(largest_ukey, 0, ValueType::Deletion));
             while level < self.options.max_mem_compact_level {
                 if self.overlap_in_level(level + 1, smallest_ukey, largest_ukey) {
                     break;
@@ -256,7 +395,7 @@ impl Version {
                         Some(largest_ikey.clone()),
                     );
                     if VersionSet::total_file_size(&overlaps)
-                        > self.options.max_grandparent_overlap_bytes()
+                        > self.options.max_grandparent_overlap_bytes(level + 1)
                     {
                         break;
                     }
@@ -267,13 +406,43 @@ impl Version {
         level
     }
 
+    /// Returns the level at which we should place an ingested file that covers
+    /// the range `[smallest_ukey,largest_ukey]` with a sequence number newer than
+    /// all the existing keys. That's the deepest level such that neither the level
+    /// nor the upper levels overlap the range, since the keys are searched from the
+    /// upper levels to the deeper ones.
+    pub fn pick_level_for_external_file(
+        &self,
+        smallest_ukey: &Slice,
+        largest_ukey: &Slice,
+    ) -> usize {
+        let mut level = 0;
+        for l in 0..self.options.max_levels as usize {
+            if self.overlap_in_level(l, smallest_ukey, largest_ukey) {
+                break;
+            }
+            level = l;
+        }
+        level
+    }
+
     // Calculate the compaction score of the version
     // The level with highest score will be marked as compaction needed.
     pub fn finalize(&mut self) {
+        if self.options.compaction_style == CompactionStyle::Universal {
+            let runs = self.sorted_runs();
+            self.compaction_score = if pick_sorted_runs(&runs, &self.options).is_some() {
+                1.0
+            } else {
+                0.0
+            };
+            return;
+        }
+        let max_bytes = self.max_bytes_for_levels();
         // pre-computed best level for next compaction
         let mut best_level = 0;
         let mut best_score = 0.0;
-        for level in 0..self.options.max_levels as usize {
+        for (level, max_bytes) in max_bytes.iter().enumerate() {
             let score = {
                 if level == 0 {
                     // We treat level-0 specially by bounding the number of files
@@ -287,10 +456,11 @@ impl Version {
                     // file size is small (perhaps because of a small write-buffer
                     // setting, or very high compression ratios, or lots of
                     // overwrites/deletions)
-                    self.files[level].len() as f64 / self.options.l0_compaction_threshold as f64
+                    self.files[level].len() as f64
+                        / self.options.level0_file_num_compaction_trigger as f64
                 } else {
                     let level_bytes = VersionSet::total_file_size(self.files[level].as_ref());
-                    level_bytes as f64 / self.options.max_bytes_for_level(level) as f64
+                    level_bytes as f64 / *max_bytes as f64
                 }
             };
             if score > best_score {
@@ -302,6 +472,72 @@ impl Version {
         self.compaction_score = best_score as f32;
     }
 
+    // Returns the maximum bytes of every level. See `Options::level_compaction_dynamic_level_bytes`.
+    fn max_bytes_for_levels(&self) -> Vec<u64> {
+        let mut max_bytes: Vec<u64> = (0..self.options.max_levels as usize)
+            .map(|level| self.options.max_bytes_for_level(level))
+            .collect();
+        if !self.options.level_compaction_dynamic_level_bytes {
+            return max_bytes;
+        }
+        let bottommost = (1..self.options.max_levels as usize)
+            .rev()
+            .find(|level| !self.files[*level].is_empty());
+        if let Some(bottommost) = bottommost {
+            let mut target = VersionSet::total_file_size(self.files[bottommost].as_ref());
+            for level in (1..bottommost).rev() {
+                target /= self.options.max_bytes_for_level_multiplier;
+                max_bytes[level] = target.max(self.options.max_bytes_for_level_base);
+            }
+        }
+        max_bytes
+    }
+
+    /// Returns the sorted runs of the universal compaction from the newest to the oldest
+    pub fn sorted_runs(&self) -> Vec<SortedRun> {
+        let mut level0 = self.files[0].clone();
+        level0.sort_by_key(|f| std::cmp::Reverse(f.number));
+        let mut runs: Vec<SortedRun> = level0
+            .into_iter()
+            .map(|f| SortedRun {
+                level: 0,
+                size: f.file_size,
+                files: vec![f],
+            })
+            .collect();
+        for (level, files) in self.files.iter().enumerate().skip(1) {
+            if !files.is_empty() {
+                runs.push(SortedRun {
+                    level,
+                    files: files.clone(),
+                    size: VersionSet::total_file_size(files),
+                })
+            }
+        }
+        runs
+    }
+
+    /// Returns the oldest file with the level which is created more than `ttl`
+    /// seconds before `now`. The files with the unknown creation time are ignored.
+    pub fn oldest_expired_file(&self, ttl: u64, now: u64) -> Option<(usize, Arc<FileMetaData>)> {
+        let mut oldest: Option<(usize, Arc<FileMetaData>)> = None;
+        for (level, files) in self.files.iter().enumerate() {
+            for f in files.iter() {
+                if f.creation_time == 0 || f.creation_time.saturating_add(ttl) > now {
+                    continue;
+                }
+                let older = match &oldest {
+                    Some((_, o)) => f.creation_time < o.creation_time,
+                    None => true,
+                };
+                if older {
+                    oldest = Some((level, f.clone()));
+                }
+            }
+        }
+        oldest
+    }
+
     /// Returns `icmp`
     #[inline]
     pub fn get_comparator(&self) -> Arc<InternalKeyComparator> {
@@ -324,6 +560,31 @@ impl Version {
         self.files[level].as_slice()
     }
 
+    /// Returns the approximate offset of the internal key `ikey` in the data of
+    /// this version, i.e. the total size of the data before `ikey` in all the levels.
+    pub fn approximate_offset_of(&self, ikey: &[u8], table_cache: &TableCache) -> u64 {
+        let mut result = 0;
+        for (level, files) in self.files.iter().enumerate() {
+            for f in files.iter() {
+                if self.icmp.compare(f.largest.data(), ikey) != CmpOrdering::Greater {
+                    // Entire file is before `ikey`
+                    result += f.file_size;
+                } else if self.icmp.compare(f.smallest.data(), ikey) == CmpOrdering::Greater {
+                    // Entire file is after `ikey`
+                    if level > 0 {
+                        // Files other than level 0 are sorted by the smallest key so
+                        // no further files in this level will contain data for `ikey`
+                        break;
+                    }
+                } else {
+                    // `ikey` is within the range of this table
+                    result += table_cache.approximate_offset_of(level, f.number, f.file_size, ikey);
+                }
+            }
+        }
+        result
+    }
+
     /// Call `func(level, file)` for every file that overlaps `user_key` in
     /// order from newest to oldest.  If an invocation of func returns
     /// false, makes no more calls.
@@ -414,11 +675,16 @@ impl Version {
         false
     }
 
-    // Returns true iff some file in the specified level overlaps
-    // some part of `[smallest_ukey,largest_ukey]`.
-    // `smallest_ukey` is empty represents a key smaller than all the DB's keys.
-    // `largest_ukey` is empty represents a key largest than all the DB's keys.
-    fn overlap_in_level(&self, level: usize, smallest_ukey: &Slice, largest_ukey: &Slice) -> bool {
+    /// Returns true iff some file in the specified level overlaps
+    /// some part of `[smallest_ukey,largest_ukey]`.
+    /// `smallest_ukey` is empty represents a key smaller than all the DB's keys.
+    /// `largest_ukey` is empty represents a key largest than all the DB's keys.
+    pub fn overlap_in_level(
+        &self,
+        level: usize,
+        smallest_ukey: &Slice,
+        largest_ukey: &Slice,
+    ) -> bool {
         if level == 0 {
             // need to check against all files in level 0
             for file in self.files[0].iter() {
@@ -436,7 +702,7 @@ impl Version {
         let index = {
             if !smallest_ukey.is_empty() {
                 let smallest_ikey =
-                    InternalKey::new(smallest_ukey, u64::max_value(), VALUE_TYPE_FOR_SEEK);
+                    InternalKey::new(smallest_ukey, MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
                 Self::find_file(
                     self.icmp.clone(),
                     &self.files[level],
@@ -482,8 +748,8 @@ impl Version {
     fn get_overlapping_inputs(
         &self,
         level: usize,
-        begin: Option<Rc<InternalKey>>,
-        end: Option<Rc<InternalKey>>,
+        begin: Option<Arc<InternalKey>>,
+        end: Option<Arc<InternalKey>>,
     ) -> Vec<Arc<FileMetaData>> {
         // TODO: the implementation treating level 0 files is somewhat tricky ( since we use unsafe pointer ).
         //       Consider separate this into two single functions: one for level 0, one for level > 0
@@ -562,6 +828,16 @@ impl LevelFileNumIterator {
     fn valid_or_panic(&self) {
         assert!(self.valid(), "[level file num iterator] out of bounds")
     }
+
+    // Encodes the number and the size of the current file into `value_buf`
+    fn fill_value_buf(&mut self) {
+        self.value_buf.clear();
+        if self.valid() {
+            let file = &self.files[self.index];
+            put_fixed_64(&mut self.value_buf, file.number);
+            put_fixed_64(&mut self.value_buf, file.file_size);
+        }
+    }
 }
 
 impl Iterator for LevelFileNumIterator {
@@ -571,6 +847,7 @@ impl Iterator for LevelFileNumIterator {
 
     fn seek_to_first(&mut self) {
         self.index = 0;
+        self.fill_value_buf();
     }
 
     fn seek_to_last(&mut self) {
@@ -579,20 +856,18 @@ impl Iterator for LevelFileNumIterator {
         } else {
             self.index = self.files.len() - 1;
         }
+        self.fill_value_buf();
     }
 
     fn seek(&mut self, target: &Slice) {
-        let index = Version::find_file(self.icmp.clone(), self.files.as_slice(), target);
-        self.index = index;
-        let file = &self.files[index];
-        // fill the buf
-        put_fixed_64(&mut self.value_buf, file.number);
-        put_fixed_64(&mut self.value_buf, file.file_size);
+        self.index = Version::find_file(self.icmp.clone(), self.files.as_slice(), target);
+        self.fill_value_buf();
     }
 
     fn next(&mut self) {
         self.valid_or_panic();
         self.index += 1;
+        self.fill_value_buf();
     }
 
     fn prev(&mut self) {
@@ -603,6 +878,7 @@ impl Iterator for LevelFileNumIterator {
         } else {
             self.index -= 1;
         }
+        self.fill_value_buf();
     }
 
     // make sure the underlying data's lifetime is longer than returning Slice
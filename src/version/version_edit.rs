@@ -20,14 +20,16 @@ use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
 use crate::util::varint::{VarintU32, VarintU64};
 use crate::version::version_edit::Tag::{
-    CompactPointer, Comparator, DeletedFile, LastSequence, LogNumber, NewFile, NextFileNumber,
-    PrevLogNumber, Unknown,
+    CompactPointer, Comparator, DeletedFile, LastSequence, LogBlockSize, LogNumber, NewFile,
+    NewFile2, NewFile3, NextFileNumber, PrevLogNumber, Unknown,
 };
 use hashbrown::HashSet;
 use std::fmt::{Debug, Formatter};
 use std::mem;
 use std::rc::Rc;
 use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Tags for the VersionEdit disk format.
 // Tag 8 is no longer used.
@@ -41,6 +43,12 @@ enum Tag {
     NewFile = 7,
     // 8 was used for large value refs
     PrevLogNumber = 9,
+    // A new file with its creation time
+    NewFile2 = 10,
+    // The block size of the logs
+    LogBlockSize = 11,
+    // A `NewFile2` of a table without range tombstones
+    NewFile3 = 12,
     Unknown, // unknown tag
 }
 
@@ -55,6 +63,9 @@ impl From<u32> for Tag {
             6 => Tag::DeletedFile,
             7 => Tag::NewFile,
             9 => Tag::PrevLogNumber,
+            10 => Tag::NewFile2,
+            11 => Tag::LogBlockSize,
+            12 => Tag::NewFile3,
             _ => Tag::Unknown,
         }
     }
@@ -77,9 +88,22 @@ pub struct FileMetaData {
     // the file number
     pub number: u64,
     // Smallest internal key served by table
-    pub smallest: Rc<InternalKey>,
+    pub smallest: Arc<InternalKey>,
     // Largest internal key served by table
-    pub largest: Rc<InternalKey>,
+    pub largest: Arc<InternalKey>,
+    // The time when the file was created in seconds since the UNIX epoch.
+    // 0 means unknown, which is the case for the files added by an old manifest.
+    pub creation_time: u64,
+    // Whether the table might have range tombstones. It's true for the files added
+    // by an old manifest since it's unknown.
+    pub has_range_tombstones: bool,
+}
+
+/// Returns the current time in seconds since the UNIX epoch
+pub fn unix_time_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
 }
 
 impl Default for FileMetaData {
@@ -88,8 +112,10 @@ impl Default for FileMetaData {
             allowed_seeks: AtomicUsize::new(0),
             file_size: 0,
             number: 0,
-            smallest: Rc::new(InternalKey::default()),
-            largest: Rc::new(InternalKey::default()),
+            smallest: Arc::new(InternalKey::default()),
+            largest: Arc::new(InternalKey::default()),
+            creation_time: 0,
+            has_range_tombstones: true,
         }
     }
 }
@@ -100,6 +126,8 @@ pub struct VersionEdit {
     max_levels: u8,
     // comparator name
     pub comparator_name: Option<String>,
+    // the block size of the logs, which is only recorded if it's not the default
+    pub log_block_size: Option<u64>,
     // file number of .log
     pub log_number: Option<u64>,
     pub prev_log_number: Option<u64>,
@@ -108,7 +136,7 @@ pub struct VersionEdit {
     pub last_sequence: Option<u64>,
 
     // (level, InternalKey)
-    pub compaction_pointers: Vec<(usize, Rc<InternalKey>)>,
+    pub compaction_pointers: Vec<(usize, Arc<InternalKey>)>,
     // (level, file_number)
     pub deleted_files: HashSet<(usize, u64)>,
     // (level, FileMetaData)
@@ -120,6 +148,7 @@ impl VersionEdit {
         Self {
             max_levels,
             comparator_name: None,
+            log_block_size: None,
             log_number: None,
             prev_log_number: None,
             next_file_number: None,
@@ -134,6 +163,7 @@ impl VersionEdit {
     #[inline]
     pub fn clear(&mut self) {
         self.comparator_name = None;
+        self.log_block_size = None;
         self.log_number = None;
         self.prev_log_number = None;
         self.next_file_number = None;
@@ -143,23 +173,18 @@ impl VersionEdit {
         // compaction pointers are not cleared here
     }
 
-    /// Add the specified file at the specified number
-    pub fn add_file(
-        &mut self,
-        level: usize,
-        file_number: u64,
-        file_size: u64,
-        smallest: Rc<InternalKey>,
-        largest: Rc<InternalKey>,
-    ) {
+    /// Add the specified file at the specified level
+    pub fn add_file(&mut self, level: usize, file: &FileMetaData) {
         self.new_files.push((
             level,
             Rc::new(FileMetaData {
                 allowed_seeks: AtomicUsize::new(0),
-                file_size,
-                number: file_number,
-                smallest,
-                largest,
+                file_size: file.file_size,
+                number: file.number,
+                smallest: file.smallest.clone(),
+                largest: file.largest.clone(),
+                creation_time: file.creation_time,
+                has_range_tombstones: file.has_range_tombstones,
             }),
         ))
     }
@@ -173,7 +198,7 @@ impl VersionEdit {
     #[inline]
     #[allow(dead_code)]
     pub fn add_compaction_pointer(&mut self, level: usize, key: InternalKey) {
-        self.compaction_pointers.push((level, Rc::new(key)))
+        self.compaction_pointers.push((level, Arc::new(key)))
     }
 
     #[inline]
@@ -181,6 +206,11 @@ impl VersionEdit {
         mem::replace::<Option<String>>(&mut self.comparator_name, Some(name));
     }
 
+    #[inline]
+    pub fn set_log_block_size(&mut self, block_size: u64) {
+        self.log_block_size = Some(block_size);
+    }
+
     #[inline]
     pub fn set_log_number(&mut self, log_num: u64) {
         mem::replace::<Option<u64>>(&mut self.log_number, Some(log_num));
@@ -207,6 +237,10 @@ impl VersionEdit {
             VarintU32::put_varint(dst, Comparator as u32);
             VarintU32::put_varint_prefixed_slice(dst, cmp_name.as_bytes());
         }
+        if let Some(block_size) = &self.log_block_size {
+            VarintU32::put_varint(dst, LogBlockSize as u32);
+            VarintU64::put_varint(dst, *block_size);
+        }
         if let Some(log_number) = &self.log_number {
             VarintU32::put_varint(dst, LogNumber as u32);
             VarintU64::put_varint(dst, *log_number);
@@ -238,12 +272,24 @@ impl VersionEdit {
         }
 
         for (level, file_meta) in self.new_files.iter() {
-            VarintU32::put_varint(dst, NewFile as u32);
+            // The files which might have range tombstones are kept readable by the old
+            // versions, and so are the files without the creation time
+            let tag = if !file_meta.has_range_tombstones {
+                NewFile3
+            } else if file_meta.creation_time == 0 {
+                NewFile
+            } else {
+                NewFile2
+            } as u32;
+            VarintU32::put_varint(dst, tag);
             VarintU32::put_varint(dst, *level as u32);
             VarintU64::put_varint(dst, file_meta.number);
             VarintU64::put_varint(dst, file_meta.file_size);
             VarintU32::put_varint_prefixed_slice(dst, file_meta.smallest.data());
             VarintU32::put_varint_prefixed_slice(dst, file_meta.largest.data());
+            if tag != NewFile as u32 {
+                VarintU64::put_varint(dst, file_meta.creation_time);
+            }
         }
     }
 
@@ -264,6 +310,14 @@ impl VersionEdit {
                             break;
                         }
                     }
+                    LogBlockSize => {
+                        if let Some(block_size) = VarintU64::drain_read(&mut s) {
+                            self.log_block_size = Some(block_size);
+                        } else {
+                            msg.push_str("log block size");
+                            break;
+                        }
+                    }
                     LogNumber => {
                         // decode log number
                         if let Some(log_num) = VarintU64::drain_read(&mut s) {
@@ -296,7 +350,7 @@ impl VersionEdit {
                         if let Some(level) = get_level(self.max_levels, &mut s) {
                             if let Some(key) = get_internal_key(&mut s) {
                                 self.compaction_pointers
-                                    .push((level as usize, Rc::new(key)));
+                                    .push((level as usize, Arc::new(key)));
                                 continue;
                             }
                         }
@@ -313,23 +367,34 @@ impl VersionEdit {
                         msg.push_str("deleted file");
                         break;
                     }
-                    NewFile => {
+                    NewFile | NewFile2 | NewFile3 => {
+                        let with_creation_time = tag != NewFile as u32;
                         if let Some(level) = get_level(self.max_levels, &mut s) {
                             if let Some(number) = VarintU64::drain_read(&mut s) {
                                 if let Some(file_size) = VarintU64::drain_read(&mut s) {
                                     if let Some(smallest) = get_internal_key(&mut s) {
                                         if let Some(largest) = get_internal_key(&mut s) {
-                                            self.new_files.push((
-                                                level as usize,
-                                                Rc::new(FileMetaData {
-                                                    allowed_seeks: AtomicUsize::new(0),
-                                                    file_size,
-                                                    number,
-                                                    smallest: Rc::new(smallest),
-                                                    largest: Rc::new(largest),
-                                                }),
-                                            ));
-                                            continue;
+                                            let creation_time = if with_creation_time {
+                                                VarintU64::drain_read(&mut s)
+                                            } else {
+                                                Some(0)
+                                            };
+                                            if let Some(creation_time) = creation_time {
+                                                self.new_files.push((
+                                                    level as usize,
+                                                    Rc::new(FileMetaData {
+                                                        allowed_seeks: AtomicUsize::new(0),
+                                                        file_size,
+                                                        number,
+                                                        smallest: Arc::new(smallest),
+                                                        largest: Arc::new(largest),
+                                                        creation_time,
+                                                        has_range_tombstones: tag
+                                                            != NewFile3 as u32,
+                                                    }),
+                                                ));
+                                                continue;
+                                            }
                                         }
                                     }
                                 }
@@ -374,6 +439,9 @@ impl Debug for VersionEdit {
         if let Some(comparator) = &self.comparator_name {
             write!(f, "\n  Comparator: {}", comparator)?;
         }
+        if let Some(block_size) = &self.log_block_size {
+            write!(f, "\n  LogBlockSize: {}", block_size)?;
+        }
         if let Some(log_number) = &self.log_number {
             write!(f, "\n  LogNumber: {}", log_number)?;
         }
@@ -427,8 +495,8 @@ fn get_level(max_levels: u8, src: &mut Slice) -> Option<u32> {
 mod tests {
     use crate::db::format::{InternalKey, ValueType};
     use crate::util::slice::Slice;
-    use crate::version::version_edit::VersionEdit;
-    use std::rc::Rc;
+    use crate::version::version_edit::{FileMetaData, VersionEdit};
+    use std::sync::Arc;
 
     fn assert_encode_decode(edit: &VersionEdit) {
         let mut encoded = vec![];
@@ -448,18 +516,23 @@ mod tests {
             assert_encode_decode(&edit);
             edit.add_file(
                 3,
-                k_big + 300 + i,
-                k_big + 400 + i,
-                Rc::new(InternalKey::new(
-                    &Slice::from("foo"),
-                    k_big + 500 + i,
-                    ValueType::Value,
-                )),
-                Rc::new(InternalKey::new(
-                    &Slice::from("zoo"),
-                    k_big + 700 + i,
-                    ValueType::Deletion,
-                )),
+                &FileMetaData {
+                    number: k_big + 300 + i,
+                    file_size: k_big + 400 + i,
+                    smallest: Arc::new(InternalKey::new(
+                        &Slice::from("foo"),
+                        k_big + 500 + i,
+                        ValueType::Value,
+                    )),
+                    largest: Arc::new(InternalKey::new(
+                        &Slice::from("zoo"),
+                        k_big + 700 + i,
+                        ValueType::Deletion,
+                    )),
+                    creation_time: i * 1000,
+                    has_range_tombstones: i % 2 == 0,
+                    ..Default::default()
+                },
             );
             edit.delete_file(4, k_big + 700 + i);
             edit.add_compaction_pointer(
@@ -468,6 +541,7 @@ mod tests {
             );
         }
         edit.set_comparator_name("foo".to_owned());
+        edit.set_log_block_size(1 << 16);
         edit.set_log_number(k_big + 100);
         edit.set_next_file(k_big + 200);
         edit.set_last_sequence(k_big + 1000);
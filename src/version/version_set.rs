@@ -15,23 +15,27 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::compaction::{Compaction, CompactionStats, ManualCompaction};
+use crate::compaction::{
+    pick_sorted_runs, Compaction, CompactionInputsRelation, CompactionStats, ManualCompaction,
+};
 use crate::db::build_table;
 use crate::db::filename::{generate_filename, parse_filename, update_current, FileType};
-use crate::db::format::{InternalKey, InternalKeyComparator};
+use crate::db::format::{extract_user_key, InternalKey, InternalKeyComparator};
+use crate::db::range_del::RangeTombstone;
 use crate::iterator::{ConcatenateIterator, DerivedIterFactory, EmptyIterator, Iterator};
-use crate::options::Options;
+use crate::options::{CompactionStyle, Options, WALRecoveryMode};
 use crate::record::reader::Reader;
 use crate::record::writer::Writer;
+use crate::record::BLOCK_SIZE;
 use crate::snapshot::{Snapshot, SnapshotList};
 use crate::sstable::table::TableBuilder;
 use crate::table_cache::TableCache;
 use crate::util::coding::decode_fixed_64;
-use crate::util::comparator::{BytewiseComparator, Comparator};
+use crate::util::comparator::Comparator;
 use crate::util::reporter::LogReporter;
 use crate::util::slice::Slice;
 use crate::util::status::{Result, Status, WickErr};
-use crate::version::version_edit::{FileMetaData, VersionEdit};
+use crate::version::version_edit::{unix_time_secs, FileMetaData, VersionEdit};
 use crate::version::{LevelFileNumIterator, Version, FILE_META_LENGTH};
 use crate::ReadOptions;
 use hashbrown::HashSet;
@@ -39,7 +43,7 @@ use std::cmp::Ordering as CmpOrdering;
 use std::collections::vec_deque::VecDeque;
 use std::path::MAIN_SEPARATOR;
 use std::rc::Rc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -54,11 +58,11 @@ struct LevelState {
 pub struct VersionBuilder {
     // file changes for every level
     levels: Vec<LevelState>,
-    base: Version,
+    base: Arc<Version>,
 }
 
 impl VersionBuilder {
-    pub fn new(base: Version) -> Self {
+    pub fn new(base: Arc<Version>) -> Self {
         let max_levels = base.options.max_levels as usize;
         let mut levels = Vec::with_capacity(max_levels);
         for _ in 0..max_levels {
@@ -112,22 +116,26 @@ impl VersionBuilder {
     /// Apply all the changes on the base Version and produce a new Version based on it
     /// same as `save_to` in C++ implementation
     pub fn apply_to_new(&mut self) -> Version {
-        // TODO: config this to the option
-        let icmp = Arc::new(InternalKeyComparator::new(Arc::new(
-            BytewiseComparator::new(),
-        )));
+        let icmp = self.base.icmp.clone();
         let mut v = Version::new(self.base.options.clone(), icmp.clone());
-        for (level, (mut base_files, delta)) in self
-            .base
-            .files
-            .drain(..)
-            .zip(self.levels.drain(..))
-            .enumerate()
-        {
-            for file in base_files.drain(..) {
+        for (level, delta) in self.levels.drain(..).enumerate() {
+            for file in self.base.files[level].iter() {
                 // filter the deleted files
                 if !delta.deleted_files.contains(&file.number) {
-                    v.files[level].push(file)
+                    v.files[level].push(file.clone())
+                }
+            }
+            for file in delta.added_files.iter() {
+                if !delta.deleted_files.contains(&file.number) {
+                    v.files[level].push(Arc::new(FileMetaData {
+                        allowed_seeks: AtomicUsize::new(file.allowed_seeks.load(Ordering::Acquire)),
+                        file_size: file.file_size,
+                        number: file.number,
+                        smallest: Arc::new(file.smallest.as_ref().clone()),
+                        largest: Arc::new(file.largest.as_ref().clone()),
+                        creation_time: file.creation_time,
+                        has_range_tombstones: file.has_range_tombstones,
+                    }))
                 }
             }
             if level == 0 {
@@ -158,10 +166,18 @@ pub struct VersionSet {
     pub compaction_stats: Vec<CompactionStats>,
     // Set of table files to protect from deletion because they are part of ongoing compaction
     pub pending_outputs: HashSet<u64>,
+    // The input files of the running compaction, which must not be removed from the
+    // current version by others until the compaction is installed
+    pub compacting_files: HashSet<u64>,
     // iff should schedule a manual compaction, temporarily just for test
     pub manual_compaction: Option<ManualCompaction>,
     // WAL writer
     pub record_writer: Option<Writer>,
+    // The obsolete log files kept for being overwritten by the new logs
+    pub recycled_logs: VecDeque<u64>,
+    // The number of the first log written in the recyclable format by this DB.
+    // Only the logs since it are recycled.
+    pub min_recyclable_log: u64,
 
     // db path
     db_name: String,
@@ -183,7 +199,7 @@ pub struct VersionSet {
     versions: VecDeque<Arc<Version>>,
 
     // Indicates that every level's compaction progress of last compaction.
-    compaction_pointer: Vec<Rc<InternalKey>>,
+    compaction_pointer: Vec<Arc<InternalKey>>,
 }
 
 unsafe impl Send for VersionSet {}
@@ -198,9 +214,12 @@ impl VersionSet {
             snapshots: SnapshotList::new(),
             compaction_stats,
             pending_outputs: HashSet::new(),
+            compacting_files: HashSet::new(),
             manual_compaction: None,
             db_name,
             record_writer: None,
+            recycled_logs: VecDeque::new(),
+            min_recyclable_log: u64::MAX,
             options: options.clone(),
             icmp: Arc::new(InternalKeyComparator::new(options.comparator.clone())),
             next_file_number: 0,
@@ -210,7 +229,9 @@ impl VersionSet {
             manifest_file_number: 0,
             manifest_writer: None,
             versions: VecDeque::new(),
-            compaction_pointer: vec![],
+            compaction_pointer: (0..options.max_levels)
+                .map(|_| Arc::new(InternalKey::default()))
+                .collect(),
         }
     }
     /// Returns the number of files in a certain level
@@ -241,11 +262,14 @@ impl VersionSet {
     /// Whether the current version needs to be compacted
     #[inline]
     pub fn needs_compaction(&self) -> bool {
-        if self.manual_compaction.is_some() {
+        if self.manual_compaction.as_ref().is_some_and(|m| !m.done) {
             true
         } else {
             let current = self.current();
-            current.compaction_score > 1.0 || current.file_to_compact.read().unwrap().is_some()
+            current.compaction_score >= 1.0
+                || (self.options.compaction_style == CompactionStyle::Level
+                    && (current.file_to_compact.read().unwrap().is_some()
+                        || self.expired_file(&current).is_some()))
         }
     }
 
@@ -309,19 +333,19 @@ impl VersionSet {
         let mut res = vec![];
         // Merge all level zero files together since they may overlap
         for file in version.files[0].iter() {
-            res.push(table_cache.new_iter(read_opt.clone(), file.number, file.file_size));
+            res.push(table_cache.new_iter(read_opt.clone(), 0, file.number, file.file_size));
         }
 
         // For levels > 0, we can use a concatenating iterator that sequentially
         // walks through the non-overlapping files in the level, opening them
         // lazily
-        for files in version.files.iter().skip(1) {
+        for (level, files) in version.files.iter().enumerate().skip(1) {
             if !files.is_empty() {
                 let level_file_iter = LevelFileNumIterator::new(
                     Arc::new(InternalKeyComparator::new(self.options.comparator.clone())),
                     files.clone(),
                 );
-                let factory = FileIterFactory::new(table_cache.clone());
+                let factory = FileIterFactory::new(table_cache.clone(), level);
                 let iter = ConcatenateIterator::new(
                     read_opt.clone(),
                     Box::new(level_file_iter),
@@ -359,10 +383,9 @@ impl VersionSet {
         let mut record = vec![];
         edit.encode_to(&mut record);
 
-        let mut v = Version::new(self.options.clone(), self.icmp.clone());
-        let mut builder = VersionBuilder::new(v);
+        let mut builder = VersionBuilder::new(self.current());
         builder.accumulate(&edit, self);
-        v = builder.apply_to_new();
+        let mut v = builder.apply_to_new();
         v.finalize();
 
         // cleanup all the old versions
@@ -434,18 +457,28 @@ impl VersionSet {
 
     /// Return a compaction object for compacting the range `[begin,end]` in
     /// the specified level.  Returns `None` if there is nothing in that
-    /// level that overlaps the specified range
+    /// level that overlaps the specified range. If `in_place` is true, all the
+    /// overlapping files are rewritten in the level in one shot.
     pub fn compact_range(
         &mut self,
         level: usize,
-        begin: Option<Rc<InternalKey>>,
-        end: Option<Rc<InternalKey>>,
+        begin: Option<Arc<InternalKey>>,
+        end: Option<Arc<InternalKey>>,
+        in_place: bool,
     ) -> Option<Compaction> {
         let version = self.current();
         let mut overlapping_inputs = version.get_overlapping_inputs(level, begin, end);
         if overlapping_inputs.is_empty() {
             return None;
         }
+        if in_place {
+            // The range is not split since the outputs overlap the rest of it
+            let mut c = Compaction::new(self.options.clone(), level);
+            c.output_level = level;
+            c.input_version = Some(version.clone());
+            c.inputs[0] = overlapping_inputs;
+            return Some(self.add_boundary_inputs(c));
+        }
         // Avoid compacting too much in one shot in case the range is large.
         // But we cannot do this for level-0 since level-0 files can overlap
         // and we must not pick one file and drop another older file if the
@@ -454,7 +487,7 @@ impl VersionSet {
             let mut total = 0;
             for (i, file) in overlapping_inputs.iter().enumerate() {
                 total += file.file_size;
-                if total >= version.options.max_file_size {
+                if total >= version.options.max_file_size_for_level(level) {
                     overlapping_inputs.truncate(i + 1);
                     break;
                 }
@@ -471,6 +504,9 @@ impl VersionSet {
     /// Otherwise returns compaction object that
     /// describes the compaction.
     pub fn pick_compaction(&mut self) -> Option<Compaction> {
+        if self.options.compaction_style == CompactionStyle::Universal {
+            return self.pick_universal_compaction();
+        }
         let current = self.current();
         let size_compaction = current.compaction_score >= 1.0;
         let mut file_to_compact = Arc::new(FileMetaData::default());
@@ -507,8 +543,8 @@ impl VersionSet {
                     }
                 }
                 if compaction.inputs[0].is_empty() {
-                    if let Some(file) = current.files[0].first() {
-                        // Wrap-around to the beginning of the key spac
+                    if let Some(file) = current.files[level].first() {
+                        // Wrap-around to the beginning of the key space
                         compaction.inputs[0].push(file.clone())
                     }
                 }
@@ -518,6 +554,17 @@ impl VersionSet {
                 let mut compaction = Compaction::new(self.options.clone(), level);
                 compaction.inputs[0].push(file_to_compact);
                 compaction
+            } else if let Some((level, file)) = self.expired_file(&current) {
+                let mut compaction = Compaction::new(self.options.clone(), level);
+                compaction.inputs[0].push(file);
+                compaction.expired = true;
+                if level + 1 == self.options.max_levels as usize {
+                    // The files in the last level are rewritten in place
+                    compaction.output_level = level;
+                    compaction.input_version = Some(current.clone());
+                    return Some(self.add_boundary_inputs(compaction));
+                }
+                compaction
             } else {
                 return None;
             }
@@ -537,65 +584,152 @@ impl VersionSet {
         Some(self.setup_other_inputs(compaction))
     }
 
+    // Returns the oldest file in `v` older than `Options::ttl`
+    fn expired_file(&self, v: &Version) -> Option<(usize, Arc<FileMetaData>)> {
+        if self.options.ttl == 0 {
+            return None;
+        }
+        v.oldest_expired_file(self.options.ttl, unix_time_secs())
+    }
+
+    // Picks the sorted runs to be merged by the universal compaction. The output
+    // is placed in the deepest level above the next older run, so the levels are
+    // still sorted from the newest to the oldest.
+    fn pick_universal_compaction(&mut self) -> Option<Compaction> {
+        let current = self.current();
+        let runs = current.sorted_runs();
+        let picked = pick_sorted_runs(&runs, &self.options)?;
+        let output_level = match runs.get(picked.end) {
+            Some(next) => next.level.max(1) - 1,
+            None => self.options.max_levels as usize - 1,
+        };
+        let mut compaction = Compaction::new(self.options.clone(), runs[picked.start].level);
+        compaction.output_level = output_level;
+        for run in runs[picked].iter() {
+            if run.level == compaction.level {
+                compaction.inputs[CompactionInputsRelation::Source as usize]
+                    .extend(run.files.iter().cloned());
+            } else {
+                compaction.other_inputs.push((run.level, run.files.clone()));
+            }
+        }
+        compaction.input_version = Some(current);
+        Some(compaction)
+    }
+
     /// Persistent given memtable into a single level0 file.
+    /// The file might be pushed to a deeper level if it does not create overlap.
+    /// `range_tombstones` are all the range tombstones in the memtable.
     pub fn write_level0_files<'a>(
         &mut self,
         db_name: &str,
         table_cache: Arc<TableCache>,
         mem_iter: Box<dyn Iterator + 'a>,
+        range_tombstones: &[RangeTombstone],
         edit: &mut VersionEdit,
     ) -> Result<()> {
         let base = self.current();
         let now = SystemTime::now();
-        let mut meta = FileMetaData::default();
-        meta.number = self.inc_next_file_number();
-        info!("Level-0 table #{} : started", meta.number);
-        let build_result = build_table(
+        let mut meta = FileMetaData {
+            number: self.inc_next_file_number(),
+            ..Default::default()
+        };
+        let (level, build_result) = Self::build_level0_file(
             self.options.clone(),
             db_name,
             table_cache,
+            &base,
             mem_iter,
+            range_tombstones,
             &mut meta,
         );
+        self.record_level0_file(
+            level,
+            &meta,
+            now.elapsed().unwrap().as_micros() as u64,
+            build_result.is_ok(),
+            edit,
+        );
+        build_result
+    }
+
+    /// Builds the table `meta.number` with the entries in the memtable and returns the
+    /// level picked for the table based on the `base` version. This doesn't access the
+    /// `VersionSet` so that it's able to run without holding the lock.
+    /// `range_tombstones` are all the range tombstones in the memtable.
+    pub fn build_level0_file<'a>(
+        options: Arc<Options>,
+        db_name: &str,
+        table_cache: Arc<TableCache>,
+        base: &Version,
+        mut mem_iter: Box<dyn Iterator + 'a>,
+        range_tombstones: &[RangeTombstone],
+        meta: &mut FileMetaData,
+    ) -> (usize, Result<()>) {
+        // Pick the output level ahead so that the table is built with the compression of that level
+        let mut level = 0;
+        mem_iter.seek_to_first();
+        if mem_iter.valid() {
+            let smallest_ukey = extract_user_key(mem_iter.key().as_slice());
+            mem_iter.seek_to_last();
+            let mut largest_ukey = extract_user_key(mem_iter.key().as_slice());
+            // The level must not overlap the keys deleted by the range tombstones either
+            for t in range_tombstones.iter() {
+                if options
+                    .comparator
+                    .compare(t.end.as_slice(), largest_ukey.as_slice())
+                    == CmpOrdering::Greater
+                {
+                    largest_ukey = Slice::from(t.end.as_slice());
+                }
+            }
+            level = base.pick_level_for_memtable_output(&smallest_ukey, &largest_ukey);
+        }
+        info!("Level-0 table #{} : started", meta.number);
+        let compression = options.compression_for_level(level);
+        let build_result = build_table(options, db_name, table_cache, mem_iter, meta, compression);
         info!(
             "Level-0 table #{} : {} bytes [{:?}]",
             meta.number, meta.file_size, &build_result
         );
-        let mut level = 0;
+        (level, build_result)
+    }
 
+    /// Adds the table built by `build_level0_file` at `level` into `edit` if it's
+    /// built successfully, and updates the compaction stats of the level
+    pub fn record_level0_file(
+        &mut self,
+        level: usize,
+        meta: &FileMetaData,
+        micros: u64,
+        built: bool,
+        edit: &mut VersionEdit,
+    ) {
         // If `file_size` is zero, the file has been deleted and
         // should not be added to the manifest
-        if build_result.is_ok() && meta.file_size > 0 {
-            let smallest_ukey = Slice::from(meta.smallest.user_key());
-            let largest_ukey = Slice::from(meta.largest.user_key());
-            level = base.pick_level_for_memtable_output(&smallest_ukey, &largest_ukey);
-            edit.add_file(
-                level,
-                meta.number,
-                meta.file_size,
-                meta.smallest.clone(),
-                meta.largest.clone(),
-            );
+        if built && meta.file_size > 0 {
+            edit.add_file(level, meta);
         }
-        self.compaction_stats[level].accumulate(
-            now.elapsed().unwrap().as_micros() as u64,
-            0,
-            meta.file_size,
-        );
-        build_result
+        self.compaction_stats[level].accumulate(micros, 0, meta.file_size);
     }
 
-    /// Add all living files in all versions into the `pending_outputs` to
-    /// prevent them to be deleted
-    #[inline]
-    pub fn lock_live_files(&mut self) {
-        for version in self.versions.iter() {
+    /// Returns the numbers of the files in `pending_outputs` and all the living
+    /// versions, which must not be deleted
+    pub fn live_files(&self) -> HashSet<u64> {
+        let mut live = self.pending_outputs.clone();
+        // The old versions not referenced anymore are skipped, even if they are not
+        // cleaned up yet
+        for (i, version) in self.versions.iter().enumerate() {
+            if i > 0 && Arc::strong_count(version) == 1 {
+                continue;
+            }
             for files in version.files.iter() {
                 for f in files.iter() {
-                    self.pending_outputs.insert(f.number);
+                    live.insert(f.number);
                 }
             }
         }
+        live
     }
 
     /// Calculate the total size of given files
@@ -609,11 +743,19 @@ impl VersionSet {
         assert!(compact.builder.is_none());
         let file_number = self.inc_next_file_number();
         self.pending_outputs.insert(file_number);
-        let mut output = FileMetaData::default();
-        output.number = file_number;
+        let output = FileMetaData {
+            number: file_number,
+            creation_time: unix_time_secs(),
+            ..Default::default()
+        };
         let file_name = generate_filename(self.db_name.as_str(), FileType::Table, file_number);
         let file = self.options.env.create(file_name.as_str())?;
-        compact.builder = Some(TableBuilder::new(file, self.options.clone()));
+        compact.builder = Some(TableBuilder::new(
+            file,
+            self.options.compression_for_level(compact.output_level),
+            self.options.clone(),
+        ));
+        compact.outputs.push(output);
         Ok(())
     }
 
@@ -651,10 +793,20 @@ impl VersionSet {
             }
         };
         let file_length = current_manifest.len();
-        let mut builder =
-            VersionBuilder::new(Version::new(self.options.clone(), self.icmp.clone()));
+        let mut builder = VersionBuilder::new(Arc::new(Version::new(
+            self.options.clone(),
+            self.icmp.clone(),
+        )));
         let reporter = LogReporter::new();
-        let mut reader = Reader::new(current_manifest, Some(Box::new(reporter.clone())), true, 0);
+        let mut reader = Reader::new(
+            current_manifest,
+            Some(Box::new(reporter.clone())),
+            true,
+            0,
+            WALRecoveryMode::TolerateCorruptedTailRecords,
+            BLOCK_SIZE,
+            0,
+        );
         let mut buf = vec![];
 
         let mut next_file_number = 0;
@@ -665,6 +817,8 @@ impl VersionSet {
         let mut has_prev_log_number = false;
         let mut last_sequence = 0;
         let mut has_last_sequence = false;
+        // the logs are written in the default block size unless it's recorded
+        let mut log_block_size = BLOCK_SIZE as u64;
         while reader.read_record(&mut buf) {
             if let Err(e) = reporter.result() {
                 return Err(e);
@@ -676,12 +830,15 @@ impl VersionSet {
                     return Err(WickErr::new(
                         Status::InvalidArgument,
                         Some(Box::leak(
-                            (cmp_name.clone() + " does not match existing compactor")
+                            (cmp_name.clone() + " does not match existing comparator")
                                 .into_boxed_str(),
                         )),
                     ));
                 }
             }
+            if let Some(n) = edit.log_block_size {
+                log_block_size = n;
+            }
             builder.accumulate(&edit, self);
             if let Some(n) = edit.next_file_number {
                 next_file_number = n;
@@ -719,6 +876,16 @@ impl VersionSet {
                 Some("no last-sequence-number entry in manifest"),
             ));
         }
+        if log_block_size != self.options.log_block_size as u64 {
+            return Err(WickErr::new(
+                Status::InvalidArgument,
+                Some("log_block_size does not match the existing log block size"),
+            )
+            .with_context(format!(
+                "log_block_size {}, existing log block size {}",
+                self.options.log_block_size, log_block_size
+            )));
+        }
 
         if !has_prev_log_number {
             prev_log_number = 0;
@@ -738,6 +905,23 @@ impl VersionSet {
         Ok(!self.should_reuse_manifest(&file_name, file_length))
     }
 
+    /// Re-reads the MANIFEST pointed by CURRENT, which might be updated by another
+    /// process, and installs the version it describes as the current version.
+    /// The old versions are kept alive as long as they are referenced.
+    /// The last sequence never goes backward.
+    pub fn catch_up(&mut self) -> Result<()> {
+        let last_sequence = self.last_sequence;
+        self.recover()?;
+        if self.last_sequence < last_sequence {
+            self.last_sequence = last_sequence;
+        }
+        // cleanup all the old versions except the new current one
+        let current = self.versions.pop_front().unwrap();
+        self.gc();
+        self.versions.push_front(current);
+        Ok(())
+    }
+
     /// Forward to `num + 1` as the next file number
     pub fn mark_file_number_used(&mut self, num: u64) {
         if self.next_file_number <= num {
@@ -750,12 +934,15 @@ impl VersionSet {
         self.versions.retain(|v| Arc::strong_count(v) > 1)
     }
 
-    // Create snapshot of current version and persistent to manifest file.
-    // Only be called when initializing a new db
-    fn write_snapshot(&self, writer: &mut Writer) -> Result<()> {
+    /// Returns a `VersionEdit` with the comparator name, the compaction pointers
+    /// and all the files in `version`, which is the first record of a new MANIFEST
+    pub fn snapshot_edit(&self, version: &Version) -> VersionEdit {
         let mut edit = VersionEdit::new(self.options.max_levels);
         // Save metadata
         edit.set_comparator_name(String::from(self.icmp.user_comparator.name()));
+        if self.options.log_block_size != BLOCK_SIZE {
+            edit.set_log_block_size(self.options.log_block_size as u64);
+        }
         // Save compaction pointers
         for level in 0..self.options.max_levels as usize {
             if !self.compaction_pointer[level].is_empty() {
@@ -766,17 +953,17 @@ impl VersionSet {
 
         // Save files
         for level in 0..self.options.max_levels as usize {
-            for file in self.current().files[level].iter() {
-                edit.add_file(
-                    level,
-                    file.number,
-                    file.file_size,
-                    file.smallest.clone(),
-                    file.largest.clone(),
-                );
+            for file in version.files[level].iter() {
+                edit.add_file(level, file);
             }
         }
+        edit
+    }
 
+    // Create snapshot of current version and persistent to manifest file.
+    // Only be called when initializing a new db
+    fn write_snapshot(&self, writer: &mut Writer) -> Result<()> {
+        let edit = self.snapshot_edit(&self.current());
         let mut record = vec![];
         edit.encode_to(&mut record);
         writer.add_record(&Slice::from(record.as_slice()))?;
@@ -790,18 +977,18 @@ impl VersionSet {
     fn setup_other_inputs(&mut self, c: Compaction) -> Compaction {
         let mut c = self.add_boundary_inputs(c);
         let current = &self.current();
-        // re-calculate the range
-        let (smallest, mut largest) = c.base_range(&self.icmp);
-        c.inputs[0] = current.get_overlapping_inputs(
+        let (smallest, mut largest) = self.key_range(c.inputs[0].as_slice());
+        c.inputs[1] = current.get_overlapping_inputs(
             c.level + 1,
             Some(smallest.clone()),
             Some(largest.clone()),
         );
+        self.add_boundary_inputs_for_compact_files(c.level + 1, &mut c.inputs[1]);
         let (mut all_smallest, mut all_largest) = c.total_range(&self.icmp);
 
         // See if we can grow the number of inputs in "level" without
         // changing the number of "level+1" files we pick up.
-        if !c.inputs[0].is_empty() {
+        if !c.inputs[1].is_empty() {
             // re-count the L(n) inputs
             // We fill the compaction 'holes' left by `add_boundary_inputs` here
             let mut expanded0 = current.get_overlapping_inputs(
@@ -816,15 +1003,16 @@ impl VersionSet {
             let inputs1_size = Self::total_file_size(c.inputs[1].as_slice());
             if expanded0.len() > c.inputs[0].len()
                 && inputs1_size + expanded0_size
-                    <= self.options.expanded_compaction_byte_size_limit()
+                    <= self.options.expanded_compaction_byte_size_limit(c.level)
             {
-                let (new_smallest, new_largest) = c.base_range(&self.icmp);
+                let (new_smallest, new_largest) = self.key_range(expanded0.as_slice());
                 // TODO: use a more sufficient way to checking expanding in L(n+1) ?
-                let expanded1 = current.get_overlapping_inputs(
+                let mut expanded1 = current.get_overlapping_inputs(
                     c.level + 1,
                     Some(new_smallest.clone()),
                     Some(new_largest.clone()),
                 );
+                self.add_boundary_inputs_for_compact_files(c.level + 1, &mut expanded1);
                 // the L(n+1) compacting files shouldn't be expanded
                 if expanded1.len() == c.inputs[1].len() {
                     let expanded1_size = Self::total_file_size(expanded1.as_slice());
@@ -865,6 +1053,21 @@ impl VersionSet {
         c
     }
 
+    // Returns the smallest and the largest key of the given non-empty files
+    fn key_range(&self, files: &[Arc<FileMetaData>]) -> (Arc<InternalKey>, Arc<InternalKey>) {
+        let mut smallest = files[0].smallest.clone();
+        let mut largest = files[0].largest.clone();
+        for f in files.iter().skip(1) {
+            if self.icmp.compare(f.smallest.data(), smallest.data()) == CmpOrdering::Less {
+                smallest = f.smallest.clone();
+            }
+            if self.icmp.compare(f.largest.data(), largest.data()) == CmpOrdering::Greater {
+                largest = f.largest.clone();
+            }
+        }
+        (smallest, largest)
+    }
+
     // A helper of 'add_boundary_input_for_compact_files' for Compaction
     fn add_boundary_inputs(&self, mut c: Compaction) -> Compaction {
         self.add_boundary_inputs_for_compact_files(c.level, &mut c.inputs[0]);
@@ -919,8 +1122,8 @@ impl VersionSet {
             {
                 match &smallest_boundary_file {
                     None => smallest_boundary_file = Some(f.clone()),
-                    Some(f) => {
-                        if self.icmp.compare(f.smallest.data(), f.smallest.data())
+                    Some(b) => {
+                        if self.icmp.compare(f.smallest.data(), b.smallest.data())
                             == CmpOrdering::Less
                         {
                             smallest_boundary_file = Some(f.clone());
@@ -944,13 +1147,14 @@ impl VersionSet {
             match file_size {
                 Ok(len) => {
                     // Make new compacted MANIFEST if old one is too big
-                    if len > self.options.max_file_size {
+                    if len > self.options.target_file_size_base {
                         return false;
                     }
                     match self.options.env.open(manifest_file) {
                         Ok(f) => {
                             info!("Reusing MANIFEST {}", manifest_file);
-                            let writer = Writer::new(f);
+                            let mut writer = Writer::new(f);
+                            writer.set_dest_length(len);
                             self.manifest_writer = Some(writer);
                             self.manifest_file_number = file_number;
                             true
@@ -971,17 +1175,19 @@ impl VersionSet {
 
 pub struct FileIterFactory {
     table_cache: Arc<TableCache>,
+    // the level of the files
+    level: usize,
 }
 
 impl FileIterFactory {
-    pub fn new(table_cache: Arc<TableCache>) -> Self {
-        Self { table_cache }
+    pub fn new(table_cache: Arc<TableCache>, level: usize) -> Self {
+        Self { table_cache, level }
     }
 }
 
 impl DerivedIterFactory for FileIterFactory {
     fn produce(&self, options: Rc<ReadOptions>, value: &Slice) -> Result<Box<dyn Iterator>> {
-        if value.size() != 2 * FILE_META_LENGTH {
+        if value.size() != FILE_META_LENGTH {
             Ok(Box::new(EmptyIterator::new_with_err(WickErr::new(
                 Status::Corruption,
                 Some("file reader invoked with unexpected value"),
@@ -989,7 +1195,9 @@ impl DerivedIterFactory for FileIterFactory {
         } else {
             let file_number = decode_fixed_64(value.as_slice());
             let file_size = decode_fixed_64(&value.as_slice()[8..]);
-            Ok(self.table_cache.new_iter(options, file_number, file_size))
+            Ok(self
+                .table_cache
+                .new_iter(options, self.level, file_number, file_size))
         }
     }
 }